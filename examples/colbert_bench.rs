@@ -0,0 +1,75 @@
+//! Times quantized `MaxSim`, with float and int8 queries, at each SIMD level
+//! this CPU supports.
+//!
+//! Run with `cargo run --release --example colbert_bench [candidates]`.
+
+use std::{
+   hint::black_box,
+   time::{Duration, Instant},
+};
+
+use ndarray::Array2;
+use smgrep::search::colbert::{
+   MaxSimScratch, QuantizedQuery, SimdLevel, max_sim_int8_with, max_sim_quantized_with,
+};
+
+/// Default `colbert_dim`.
+const DIM: usize = 96;
+const QUERY_TOKENS: usize = 32;
+const DOC_TOKENS: usize = 180;
+const ROUNDS: usize = 20;
+
+fn main() {
+   let candidates: usize = std::env::args()
+      .nth(1)
+      .and_then(|n| n.parse().ok())
+      .unwrap_or(500);
+
+   let query = Array2::from_shape_fn((QUERY_TOKENS, DIM), |(t, d)| {
+      ((t * 31 + d * 7) % 97) as f32 / 97.0 - 0.5
+   });
+   let docs: Vec<Vec<u8>> = (0..candidates)
+      .map(|i| {
+         (0..DOC_TOKENS * DIM)
+            .map(|j| ((i * 131 + j * 37) % 255) as u8)
+            .collect()
+      })
+      .collect();
+
+   println!(
+      "{candidates} candidates of {DOC_TOKENS} tokens, {QUERY_TOKENS} query tokens, dim {DIM}"
+   );
+   println!("detected: {}", SimdLevel::detect().as_str());
+
+   let int8_query = QuantizedQuery::new(&query);
+   let mut scratch = MaxSimScratch::default();
+   for level in SimdLevel::ALL.into_iter().filter(|l| l.is_supported()) {
+      let float = time(|| {
+         for doc in &docs {
+            black_box(max_sim_quantized_with(level, black_box(&query), doc, 0.01, DIM));
+         }
+      });
+      let int8 = time(|| {
+         for doc in &docs {
+            black_box(max_sim_int8_with(level, black_box(&int8_query), doc, 0.01, &mut scratch));
+         }
+      });
+      for (kind, per_rerank) in [("f32", float), ("int8", int8)] {
+         println!(
+            "{:>9} {kind:>4}: {:>8.2} ms per rerank, {:>6} ns per candidate",
+            level.as_str(),
+            per_rerank.as_secs_f64() * 1000.0,
+            per_rerank.as_nanos() / candidates.max(1) as u128
+         );
+      }
+   }
+}
+
+/// Average time of one call to `rerank` over [`ROUNDS`] calls.
+fn time(mut rerank: impl FnMut()) -> Duration {
+   let started = Instant::now();
+   for _ in 0..ROUNDS {
+      rerank();
+   }
+   started.elapsed() / ROUNDS as u32
+}
@@ -18,6 +18,10 @@ fn main() {
          num_lines:  4,
          chunk_type: Some(ChunkType::Function),
          is_anchor:  Some(false),
+         breakdown:  None,
+         bridges:    None,
+         language:   None,
+         linked:     Vec::new(),
       },
       SearchResult {
          path:       "src/handlers/login.rs".into(),
@@ -31,6 +35,10 @@ fn main() {
          num_lines:  5,
          chunk_type: Some(ChunkType::Function),
          is_anchor:  Some(false),
+         breakdown:  None,
+         bridges:    None,
+         language:   None,
+         linked:     Vec::new(),
       },
       SearchResult {
          path:       "tests/auth_test.rs".into(),
@@ -45,6 +53,10 @@ fn main() {
          num_lines:  6,
          chunk_type: Some(ChunkType::Function),
          is_anchor:  Some(false),
+         breakdown:  None,
+         bridges:    None,
+         language:   None,
+         linked:     Vec::new(),
       },
    ];
 
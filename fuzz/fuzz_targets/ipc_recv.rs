@@ -0,0 +1,29 @@
+//! Feeds arbitrary bytes to the daemon's request decoder.
+//!
+//! Run with `cargo +nightly fuzz run ipc_recv` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smgrep::{
+   Error,
+   ipc::{Response, SocketBuffer},
+};
+
+fuzz_target!(|data: &[u8]| {
+   futures::executor::block_on(async {
+      let mut reader = data;
+      let mut buffer = SocketBuffer::new();
+      // Keep reading after malformed frames, as the daemon does.
+      while !reader.is_empty() {
+         match buffer.recv_request(&mut reader).await {
+            Ok(request) => assert!(request.validate().is_ok()),
+            Err(Error::Ipc(e)) if e.is_malformed_frame() => {},
+            Err(_) => break,
+         }
+      }
+
+      let mut reader = data;
+      let _ = buffer.recv::<_, Response>(&mut reader).await;
+   });
+});
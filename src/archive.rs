@@ -0,0 +1,366 @@
+//! Portable index archives.
+//!
+//! Bundles a store's `LanceDB` table, its metadata JSON and a manifest
+//! describing the models used into a single tar file, so an index built once
+//! (e.g. in CI) can be downloaded and imported instead of re-embedded locally.
+
+use std::{
+   fs::{self, File},
+   io::{BufReader, BufWriter, Read, Write},
+   path::{Component, Path, PathBuf},
+   time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+   Result, config,
+   error::ArchiveError,
+   index_lock::IndexLock,
+   meta::{self, MetaStore, ModelSignature},
+   version,
+};
+
+/// Current archive layout version.
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+/// Default file extension for exported archives.
+pub const ARCHIVE_EXTENSION: &str = "smgrep";
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const META_ENTRY: &str = "meta.json";
+const DATA_PREFIX: &str = "data";
+
+/// Describes the contents of an exported archive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveManifest {
+   pub format_version: u32,
+   pub store_id:       String,
+   pub smgrep_version: String,
+   pub model:          ModelSignature,
+   pub created_at:     u64,
+}
+
+#[derive(Deserialize)]
+struct MetaModel {
+   #[serde(default)]
+   model: Option<ModelSignature>,
+}
+
+/// Writes `store_id` to a tar archive at `output`.
+///
+/// The manifest is always the first entry so importers can validate it before
+/// touching any table data.
+pub fn export_store(store_id: &str, output: &Path) -> Result<ArchiveManifest> {
+   let data_path = config::data_dir().join(store_id);
+   if !data_path.is_dir() || !meta::exists(store_id) {
+      return Err(ArchiveError::StoreNotFound(store_id.to_string()).into());
+   }
+
+   let _lock = IndexLock::acquire_shared(store_id)?;
+
+   let meta_bytes = MetaStore::load(store_id)?.to_json()?;
+   let model = serde_json::from_slice::<MetaModel>(&meta_bytes)?
+      .model
+      .unwrap_or_else(ModelSignature::current);
+
+   let manifest = ArchiveManifest {
+      format_version: ARCHIVE_FORMAT_VERSION,
+      store_id: store_id.to_string(),
+      smgrep_version: version::VERSION.to_string(),
+      model,
+      created_at: SystemTime::now()
+         .duration_since(UNIX_EPOCH)
+         .map_or(0, |d| d.as_secs()),
+   };
+
+   let tmp_path = output.with_extension("tmp");
+   let result = (|| -> Result<()> {
+      let mut builder = tar::Builder::new(BufWriter::new(File::create(&tmp_path)?));
+      builder.follow_symlinks(false);
+
+      append_bytes(&mut builder, MANIFEST_ENTRY, &serde_json::to_vec_pretty(&manifest)?)?;
+      append_bytes(&mut builder, META_ENTRY, &meta_bytes)?;
+      builder.append_dir_all(DATA_PREFIX, &data_path)?;
+
+      builder.into_inner()?.flush()?;
+      Ok(())
+   })();
+
+   if let Err(e) = result {
+      let _ = fs::remove_file(&tmp_path);
+      return Err(e);
+   }
+
+   fs::rename(&tmp_path, output)?;
+   Ok(manifest)
+}
+
+fn append_bytes<W: Write>(builder: &mut tar::Builder<W>, name: &str, data: &[u8]) -> Result<()> {
+   let mut header = tar::Header::new_gnu();
+   header.set_size(data.len() as u64);
+   header.set_mode(0o644);
+   header.set_cksum();
+   builder.append_data(&mut header, name, data)?;
+   Ok(())
+}
+
+/// Reads only the manifest of an archive.
+pub fn read_manifest(archive: &Path) -> Result<ArchiveManifest> {
+   let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+   let mut entries = tar.entries()?;
+   let Some(entry) = entries.next() else {
+      return Err(ArchiveError::MissingManifest.into());
+   };
+   parse_manifest(entry?)
+}
+
+fn parse_manifest<R: Read>(mut entry: tar::Entry<'_, R>) -> Result<ArchiveManifest> {
+   if entry.path()?.as_ref() != Path::new(MANIFEST_ENTRY) {
+      return Err(ArchiveError::MissingManifest.into());
+   }
+
+   let mut buf = Vec::new();
+   entry.read_to_end(&mut buf)?;
+   let manifest: ArchiveManifest = serde_json::from_slice(&buf)?;
+
+   if manifest.format_version > ARCHIVE_FORMAT_VERSION {
+      return Err(ArchiveError::UnsupportedVersion(manifest.format_version).into());
+   }
+   Ok(manifest)
+}
+
+/// Options controlling [`import_archive`].
+#[derive(Debug, Default, Clone)]
+pub struct ImportOptions {
+   /// Store ID to import into; defaults to the ID recorded in the manifest.
+   pub store_id: Option<String>,
+   /// Replace an existing store and ignore model mismatches.
+   pub force:    bool,
+}
+
+/// Unpacks an archive created by [`export_store`] into the local data and
+/// metadata directories.
+///
+/// Table data is extracted into a staging directory first and only moved into
+/// place once the whole archive has been read.
+pub fn import_archive(archive: &Path, opts: &ImportOptions) -> Result<ArchiveManifest> {
+   let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+   let mut entries = tar.entries()?;
+
+   let Some(first) = entries.next() else {
+      return Err(ArchiveError::MissingManifest.into());
+   };
+   let manifest = parse_manifest(first?)?;
+
+   let current = ModelSignature::current();
+   if !opts.force && manifest.model != current {
+      return Err(
+         ArchiveError::ModelMismatch {
+            archive: format!("{}/{}", manifest.model.dense_model, manifest.model.colbert_model),
+            current: format!("{}/{}", current.dense_model, current.colbert_model),
+         }
+         .into(),
+      );
+   }
+
+   let store_id = opts.store_id.as_deref().unwrap_or(&manifest.store_id);
+   if !is_plain_store_id(store_id) {
+      return Err(ArchiveError::InvalidStoreId(store_id.to_string()).into());
+   }
+   let data_dir = config::data_dir();
+   let data_path = data_dir.join(store_id);
+   if data_path.exists() && !opts.force {
+      return Err(ArchiveError::StoreExists(store_id.to_string()).into());
+   }
+
+   fs::create_dir_all(data_dir)?;
+   let _lock = IndexLock::acquire(store_id)?;
+
+   let staging = data_dir.join(format!(".import-{store_id}"));
+   if staging.exists() {
+      fs::remove_dir_all(&staging)?;
+   }
+   fs::create_dir_all(&staging)?;
+
+   let result = (|| -> Result<Option<Vec<u8>>> {
+      let mut meta = None;
+      for entry in entries {
+         let mut entry = entry?;
+         let path = entry.path()?.into_owned();
+
+         if path == Path::new(META_ENTRY) {
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            meta = Some(buf);
+            continue;
+         }
+
+         let rel = path
+            .strip_prefix(DATA_PREFIX)
+            .ok()
+            .and_then(sanitize_relative)
+            .ok_or_else(|| ArchiveError::InvalidEntry(path.clone()))?;
+         if rel.as_os_str().is_empty() {
+            continue;
+         }
+
+         let kind = entry.header().entry_type();
+         if !kind.is_file() && !kind.is_dir() {
+            return Err(ArchiveError::InvalidEntry(path).into());
+         }
+
+         let dest = staging.join(&rel);
+         if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+         }
+         entry.unpack(&dest)?;
+      }
+      Ok(meta)
+   })();
+
+   let meta = match result {
+      Ok(Some(meta)) => meta,
+      Ok(None) => {
+         let _ = fs::remove_dir_all(&staging);
+         return Err(ArchiveError::MissingMeta.into());
+      },
+      Err(e) => {
+         let _ = fs::remove_dir_all(&staging);
+         return Err(e);
+      },
+   };
+
+   if data_path.exists() {
+      fs::remove_dir_all(&data_path)?;
+   }
+   fs::rename(&staging, &data_path)?;
+
+   // Metadata in another format is converted from the JSON on the next load
+   meta::delete_files(store_id)?;
+   fs::create_dir_all(config::meta_dir())?;
+   fs::write(meta::json_path(store_id), meta)?;
+
+   Ok(manifest)
+}
+
+/// Whether `store_id` names a single entry of the data and metadata
+/// directories, as it comes from an untrusted manifest or the command line.
+fn is_plain_store_id(store_id: &str) -> bool {
+   let mut components = Path::new(store_id).components();
+   !store_id.contains(['/', '\\'])
+      && matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+/// Rejects absolute paths and `..` components so entries cannot escape the
+/// staging directory.
+fn sanitize_relative(path: &Path) -> Option<PathBuf> {
+   let mut out = PathBuf::new();
+   for component in path.components() {
+      match component {
+         Component::Normal(c) => out.push(c),
+         Component::CurDir => {},
+         _ => return None,
+      }
+   }
+   Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+   use tempfile::TempDir;
+
+   use super::*;
+
+   #[test]
+   fn sanitize_relative_rejects_escapes() {
+      assert_eq!(sanitize_relative(Path::new("a/./b")), Some(PathBuf::from("a/b")));
+      assert_eq!(sanitize_relative(Path::new("../etc/passwd")), None);
+      assert_eq!(sanitize_relative(Path::new("/etc/passwd")), None);
+   }
+
+   #[test]
+   fn rejects_store_ids_outside_data_dir() {
+      let dir = TempDir::new().unwrap();
+      let path = dir.path().join("evil.smgrep");
+
+      let manifest = ArchiveManifest {
+         format_version: ARCHIVE_FORMAT_VERSION,
+         store_id:       "../escape".to_string(),
+         smgrep_version: "0.0.0".to_string(),
+         model:          ModelSignature::current(),
+         created_at:     0,
+      };
+      let mut builder = tar::Builder::new(File::create(&path).unwrap());
+      append_bytes(&mut builder, MANIFEST_ENTRY, &serde_json::to_vec(&manifest).unwrap()).unwrap();
+      append_bytes(&mut builder, META_ENTRY, b"{}").unwrap();
+      builder.finish().unwrap();
+      drop(builder);
+
+      let force = ImportOptions { store_id: None, force: true };
+      assert!(matches!(
+         import_archive(&path, &force),
+         Err(crate::Error::Archive(ArchiveError::InvalidStoreId(id))) if id == "../escape"
+      ));
+      for id in ["/tmp/escape", "a/b", "a\\b", "..", ".", ""] {
+         let opts = ImportOptions { store_id: Some(id.to_string()), force: true };
+         assert!(
+            matches!(
+               import_archive(&path, &opts),
+               Err(crate::Error::Archive(ArchiveError::InvalidStoreId(_)))
+            ),
+            "{id}"
+         );
+      }
+      assert!(is_plain_store_id("can1357-smgrep@feature-x"));
+   }
+
+   #[test]
+   fn manifest_must_come_first() {
+      let dir = TempDir::new().unwrap();
+      let path = dir.path().join("bad.smgrep");
+
+      let mut builder = tar::Builder::new(File::create(&path).unwrap());
+      append_bytes(&mut builder, META_ENTRY, b"{}").unwrap();
+      builder.finish().unwrap();
+      drop(builder);
+
+      assert!(matches!(
+         read_manifest(&path),
+         Err(crate::Error::Archive(ArchiveError::MissingManifest))
+      ));
+   }
+
+   #[test]
+   fn rejects_newer_format_version() {
+      let dir = TempDir::new().unwrap();
+      let path = dir.path().join("future.smgrep");
+
+      let manifest = ArchiveManifest {
+         format_version: ARCHIVE_FORMAT_VERSION + 1,
+         store_id:       "s".to_string(),
+         smgrep_version: "0.0.0".to_string(),
+         model:          ModelSignature {
+            dense_model:     "d".to_string(),
+            colbert_model:   "c".to_string(),
+            dense_dim:       1,
+            colbert_dim:     1,
+            dense_pooling:   None,
+            colbert_markers: false,
+            window_pooling:  None,
+            truncate_dim:    None,
+            embed_template:  None,
+         },
+         created_at:     0,
+      };
+      let mut builder = tar::Builder::new(File::create(&path).unwrap());
+      append_bytes(&mut builder, MANIFEST_ENTRY, &serde_json::to_vec(&manifest).unwrap()).unwrap();
+      builder.finish().unwrap();
+      drop(builder);
+
+      assert!(matches!(
+         read_manifest(&path),
+         Err(crate::Error::Archive(ArchiveError::UnsupportedVersion(v))) if v == ARCHIVE_FORMAT_VERSION + 1
+      ));
+   }
+}
@@ -14,13 +14,14 @@ use crate::{
 
 /// Creates an anchor chunk containing file metadata and context.
 ///
-/// Extracts top-level comments, imports, exports, and preamble to provide
-/// context for code search. Returns a special chunk marked as an anchor.
+/// Extracts top-level comments, imports, exports, module declarations and
+/// preamble to provide context for code search. Rust, Python and Go files get
+/// their own extractors (crate docs and docstrings count as top comments);
+/// others are read as JavaScript or TypeScript. Returns a special chunk marked
+/// as an anchor.
 pub fn create_anchor_chunk(content: &Str, path: &Path) -> Chunk {
    let lines: Vec<&str> = content.as_str().lines().collect();
-   let top_comments = extract_top_comments(&lines);
-   let imports = extract_imports(&lines);
-   let exports = extract_exports(&lines);
+   let Outline { top_comments, imports, exports, modules } = outline(path, &lines);
 
    let mut preamble = Vec::new();
    let mut non_blank = 0;
@@ -50,6 +51,10 @@ pub fn create_anchor_chunk(content: &Str, path: &Path) -> Chunk {
       write!(anchor_text, "\n\nExports: {}", exports.join(", ")).unwrap();
    }
 
+   if !modules.is_empty() {
+      write!(anchor_text, "\n\nModules: {}", modules.join(", ")).unwrap();
+   }
+
    if !top_comments.is_empty() {
       write!(anchor_text, "\n\nTop comments:\n{}", top_comments.join("\n")).unwrap();
    }
@@ -71,6 +76,78 @@ pub fn create_anchor_chunk(content: &Str, path: &Path) -> Chunk {
    chunk
 }
 
+/// Imports, exports and modules listed in the text of an anchor chunk.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AnchorSummary<'a> {
+   pub imports: Vec<&'a str>,
+   pub exports: Vec<&'a str>,
+   pub modules: Vec<&'a str>,
+}
+
+impl<'a> AnchorSummary<'a> {
+   /// Reads the summary back from text written by [`create_anchor_chunk`].
+   pub fn parse(text: &'a str) -> Self {
+      // Only the header is searched, so that file contents quoted in the
+      // comments and preamble are not mistaken for it.
+      let header = ["\n\nTop comments:", "\n\nPreamble:", "\n\n---"]
+         .iter()
+         .filter_map(|marker| text.find(marker))
+         .min()
+         .map_or(text, |end| &text[..end]);
+      let list = |label: &str| -> Vec<&'a str> {
+         header
+            .lines()
+            .find_map(|line| line.strip_prefix(label))
+            .map(|items| items.split(", ").collect())
+            .unwrap_or_default()
+      };
+      Self { imports: list("Imports: "), exports: list("Exports: "), modules: list("Modules: ") }
+   }
+}
+
+/// What an anchor lists about a file besides its preamble.
+pub(super) struct Outline {
+   pub top_comments: Vec<String>,
+   pub imports:      Vec<String>,
+   pub exports:      Vec<String>,
+   /// Submodules the file declares; Rust only.
+   pub modules:      Vec<String>,
+}
+
+pub(super) fn outline(path: &Path, lines: &[&str]) -> Outline {
+   let ext = path
+      .extension()
+      .and_then(|e| e.to_str())
+      .unwrap_or_default();
+   let (imports, exports, modules) = match ext {
+      "rs" => rust_outline(lines),
+      "py" | "pyi" => python_outline(lines),
+      "go" => go_outline(lines),
+      _ => (extract_imports(lines), extract_exports(lines), Vec::new()),
+   };
+   let top_comments = match ext {
+      "rs" => Some(extract_rust_docs(lines)).filter(|docs| !docs.is_empty()),
+      "py" | "pyi" => extract_docstring(lines).map(|doc| {
+         let mut comments = extract_top_comments(lines);
+         comments.extend(doc);
+         comments
+      }),
+      _ => None,
+   };
+   Outline {
+      top_comments: top_comments.unwrap_or_else(|| extract_top_comments(lines)),
+      imports:      sorted(imports),
+      exports:      sorted(exports),
+      modules:      sorted(modules),
+   }
+}
+
+fn sorted(mut items: Vec<String>) -> Vec<String> {
+   items.sort();
+   items.dedup();
+   items
+}
+
 fn extract_top_comments(lines: &[&str]) -> Vec<String> {
    let mut comments = Vec::new();
    let mut in_block = false;
@@ -118,14 +195,6 @@ fn extract_top_comments(lines: &[&str]) -> Vec<String> {
    comments
 }
 
-macro_rules! static_regex {
-   ($($name:ident = $regex:expr),* $(,)?) => {
-      $(
-         pub(crate) static $name: LazyLock<Regex> = LazyLock::new(|| Regex::new($regex).unwrap());
-      )*
-   };
-}
-
 static_regex! {
    IMPORT_FROM_REGEX    = r#"from\s+["']([^"']+)["']"#,
    IMPORT_REGEX         = r#"^import\s+["']([^"']+)["']"#,
@@ -134,6 +203,155 @@ static_regex! {
    EXPORT_REGEX         = r"^export\s+(?:default\s+)?(class|function|const|let|var|interface|type|enum)\s+([A-Za-z0-9_$]+)",
    EXPORT_BRACE_REGEX   = r"^export\s+\{([^}]+)\}",
    CONST_EXPORT_REGEX   = r"(?:^|\n)\s*(?:export\s+)?const\s+[A-Z0-9_]+\s*=",
+   RUST_USE_REGEX       = r"^(?:pub(?:\([^)]*\))?\s+)?use\s+([^;{\s]+?)(?:::\{|;|\s|$)",
+   RUST_PUB_ITEM_REGEX  = r#"^pub\s+(?:(?:async|const|unsafe|extern\s+"[^"]*")\s+)*(?:fn|struct|enum|trait|type|const|static(?:\s+mut)?|union|mod)\s+([A-Za-z_][A-Za-z0-9_]*)"#,
+   RUST_MOD_REGEX       = r"^(?:pub(?:\([^)]*\))?\s+)?mod\s+([A-Za-z_][A-Za-z0-9_]*)\s*;",
+   RUST_MACRO_REGEX     = r"^macro_rules!\s*([A-Za-z_][A-Za-z0-9_]*)",
+   PY_IMPORT_REGEX      = r"^import\s+(.+)",
+   PY_FROM_REGEX        = r"^from\s+([A-Za-z0-9_.]+)\s+import\b",
+   PY_DEF_REGEX         = r"^(?:async\s+def|def|class)\s+([A-Za-z][A-Za-z0-9_]*)",
+   GO_IMPORT_REGEX      = r#"^(?:import\s+)?(?:[A-Za-z0-9_.]+\s+)?"([^"]+)""#,
+   GO_FUNC_REGEX        = r"^func\s+(?:\([^)]*\)\s*)?([A-Z][A-Za-z0-9_]*)",
+   GO_DECL_REGEX        = r"^(?:type|var|const)\s+([A-Z][A-Za-z0-9_]*)",
+   GO_BLOCK_ITEM_REGEX  = r"^\s+([A-Z][A-Za-z0-9_]*)\b",
+}
+
+/// Crate or module docs (`//!`) at the top of a Rust file, past any inner
+/// attributes or license comments before them.
+fn extract_rust_docs(lines: &[&str]) -> Vec<String> {
+   let mut docs = Vec::new();
+   for line in lines {
+      let trimmed = line.trim();
+      if trimmed.starts_with("//!") {
+         docs.push(line.to_string());
+      } else if !(trimmed.is_empty() || trimmed.starts_with("//") || trimmed.starts_with("#![")) {
+         break;
+      }
+   }
+   docs
+}
+
+/// Imports, public items and declared submodules of a Rust file. Only
+/// unindented lines count, so methods and items of inline modules are left
+/// out.
+fn rust_outline(lines: &[&str]) -> (Vec<String>, Vec<String>, Vec<String>) {
+   let mut imports = Vec::new();
+   let mut exports = Vec::new();
+   let mut modules = Vec::new();
+   let mut macro_export = false;
+
+   for line in lines {
+      if macro_export && let Some(caps) = RUST_MACRO_REGEX.captures(line) {
+         exports.push(caps[1].to_string());
+      }
+      // Holds across further attributes, up to the item they annotate.
+      macro_export = line.starts_with("#[macro_export") || (macro_export && line.starts_with("#["));
+
+      if let Some(caps) = RUST_USE_REGEX.captures(line) {
+         imports.push(caps[1].trim_end_matches("::*").to_string());
+      }
+      if let Some(caps) = RUST_MOD_REGEX.captures(line) {
+         modules.push(caps[1].to_string());
+      } else if let Some(caps) = RUST_PUB_ITEM_REGEX.captures(line) {
+         exports.push(caps[1].to_string());
+      }
+   }
+
+   (imports, exports, modules)
+}
+
+/// Leading docstring of a Python module.
+fn extract_docstring(lines: &[&str]) -> Option<Vec<String>> {
+   let mut rest = lines.iter().skip_while(|line| {
+      let trimmed = line.trim();
+      trimmed.is_empty() || trimmed.starts_with('#')
+   });
+   let first = rest.next()?;
+   let opening = first.trim().trim_start_matches(['r', 'R', 'u', 'U']);
+   let quote = ["\"\"\"", "'''"]
+      .into_iter()
+      .find(|q| opening.starts_with(q))?;
+
+   let mut doc = vec![first.to_string()];
+   if !opening[quote.len()..].contains(quote) {
+      for line in rest {
+         doc.push(line.to_string());
+         if line.contains(quote) {
+            break;
+         }
+      }
+   }
+   Some(doc)
+}
+
+/// Imports and public top-level definitions of a Python module; names
+/// starting with an underscore are private by convention.
+fn python_outline(lines: &[&str]) -> (Vec<String>, Vec<String>, Vec<String>) {
+   let mut imports = Vec::new();
+   let mut exports = Vec::new();
+
+   for line in lines {
+      let trimmed = line.trim();
+      if let Some(caps) = PY_FROM_REGEX.captures(trimmed) {
+         imports.push(caps[1].to_string());
+      } else if let Some(caps) = PY_IMPORT_REGEX.captures(trimmed) {
+         imports.extend(
+            caps[1]
+               .split(',')
+               .filter_map(|module| module.split_whitespace().next())
+               .map(str::to_string),
+         );
+      }
+      if let Some(caps) = PY_DEF_REGEX.captures(line) {
+         exports.push(caps[1].to_string());
+      }
+   }
+
+   (imports, exports, Vec::new())
+}
+
+/// Imported packages and exported (capitalized) top-level names of a Go
+/// file, including those in `import (...)`, `const (...)`, `var (...)` and
+/// `type (...)` groups.
+fn go_outline(lines: &[&str]) -> (Vec<String>, Vec<String>, Vec<String>) {
+   let mut imports = Vec::new();
+   let mut exports = Vec::new();
+   let mut import_group = false;
+   let mut decl_group = false;
+
+   for line in lines {
+      let trimmed = line.trim();
+      if import_group || decl_group {
+         if trimmed.starts_with(')') {
+            import_group = false;
+            decl_group = false;
+         } else if import_group {
+            if let Some(caps) = GO_IMPORT_REGEX.captures(trimmed) {
+               imports.push(caps[1].to_string());
+            }
+         } else if let Some(caps) = GO_BLOCK_ITEM_REGEX.captures(line) {
+            exports.push(caps[1].to_string());
+         }
+         continue;
+      }
+
+      if trimmed == "import (" {
+         import_group = true;
+      } else if matches!(trimmed, "const (" | "var (" | "type (") {
+         decl_group = true;
+      } else if trimmed.starts_with("import ") {
+         if let Some(caps) = GO_IMPORT_REGEX.captures(trimmed) {
+            imports.push(caps[1].to_string());
+         }
+      } else if let Some(caps) = GO_FUNC_REGEX
+         .captures(line)
+         .or_else(|| GO_DECL_REGEX.captures(line))
+      {
+         exports.push(caps[1].to_string());
+      }
+   }
+
+   (imports, exports, Vec::new())
 }
 
 fn extract_imports(lines: &[&str]) -> Vec<String> {
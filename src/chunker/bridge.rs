@@ -0,0 +1,272 @@
+//! Symbol names shared across FFI boundaries.
+//!
+//! A binding layer and the code on the other side of it rarely use the same
+//! vocabulary, so they seldom rank together. Each chunk records the names it
+//! exports or imports across a language boundary as bridge keys, such as
+//! `c:deflate` for a `#[no_mangle]` function and the C code calling it, or
+//! `jni:Inflater.inflate` for a `Java_..._Inflater_inflate` implementation
+//! and the Java `native` method it backs. Search links results that share a
+//! key but live on different sides.
+
+use std::{collections::HashSet, path::Path, sync::LazyLock};
+
+use regex::{Captures, Regex};
+
+use crate::{Str, grammar::GrammarManager};
+
+/// Upper bound on the keys recorded for one chunk.
+const MAX_KEYS: usize = 32;
+
+/// Words that look like C function names to [`C_FN_REGEX`] but are not.
+const C_KEYWORDS: &[&str] = &[
+   "if", "for", "while", "switch", "return", "sizeof", "typedef", "else", "do", "case", "defined",
+];
+
+/// Language family on one side of an FFI boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+   Rust,
+   C,
+   Jvm,
+   Js,
+   Python,
+}
+
+/// Returns the FFI side of a file, or `None` if its language has no bridges.
+pub fn side(path: &Path) -> Option<Side> {
+   let ext = path.extension()?.to_str()?;
+   match GrammarManager::extension_to_language(ext)? {
+      "rust" => Some(Side::Rust),
+      "c" | "cpp" => Some(Side::C),
+      "java" | "kotlin" => Some(Side::Jvm),
+      "javascript" | "typescript" | "tsx" => Some(Side::Js),
+      "python" => Some(Side::Python),
+      _ => None,
+   }
+}
+
+static_regex! {
+   NO_MANGLE_REGEX   = r#"#\[(?:unsafe\()?no_mangle\)?\](?:\s*#\[[^\]]*\])*\s*(?:pub(?:\([^)]*\))?\s+)?(?:unsafe\s+)?(?:extern\s+(?:"[^"]*"\s+)?)?fn\s+([A-Za-z_]\w*)"#,
+   EXPORT_NAME_REGEX = r#"#\[(?:unsafe\()?export_name\s*=\s*"([^"]+)""#,
+   EXTERN_BLOCK_REGEX = r#"(#\[wasm_bindgen[^\]]*\]\s*)?(?:unsafe\s+)?extern\s+"(?:C|C-unwind|system)"\s*\{"#,
+   EXTERN_FN_REGEX   = r"(?:#\[wasm_bindgen\(([^\]]*)\)\]\s*)?(?:pub(?:\([^)]*\))?\s+)?(?:safe\s+|unsafe\s+)?fn\s+([A-Za-z_]\w*)",
+   WASM_FN_REGEX     = r"#\[wasm_bindgen(?:\(([^\]]*)\))?\]\s*(?:pub(?:\([^)]*\))?\s+)?(?:async\s+)?fn\s+([A-Za-z_]\w*)",
+   JS_NAME_REGEX     = r#"js_name\s*=\s*"?([A-Za-z_$][\w$]*)"?"#,
+   JNI_RUST_REGEX    = r"\bfn\s+Java_(\w+)",
+   JNI_C_REGEX       = r"\bJava_(\w+)\s*\(",
+   C_FN_REGEX        = r#"(?m)^(?:(?:[A-Za-z_]\w*|"C")[\s*&]+)+?([A-Za-z_]\w*)\s*\("#,
+   JAVA_NATIVE_REGEX = r"\bnative\b[^;{(=]*?\b([A-Za-z_]\w*)\s*\(",
+   KOTLIN_EXTERNAL_REGEX = r"\bexternal\s+fun\s+([A-Za-z_]\w*)",
+   JS_FN_REGEX       = r"(?m)^\s*(?:export\s+)?(?:default\s+)?(?:async\s+)?function\s*\*?\s*([A-Za-z_$][\w$]*)",
+   JS_ARROW_REGEX    = r"(?m)^\s*(?:export\s+)?(?:const|let|var)\s+([A-Za-z_$][\w$]*)\s*=\s*(?:async\s+)?(?:function\b|\([^)]*\)\s*=>|[A-Za-z_$][\w$]*\s*=>)",
+   WASM_IMPORT_REGEX = r#"import\s*\{([^}]*)\}\s*from\s*["']([^"']*)["']"#,
+   CTYPES_REGEX      = r"\.([A-Za-z_]\w*)\.(?:argtypes|restype)\b",
+}
+
+/// Collects the bridge keys of a chunk, encoded for storage.
+///
+/// Returns `None` when the chunk has none.
+pub fn collect(path: &Path, content: &str) -> Option<Str> {
+   encode(&symbols(path, content))
+}
+
+/// Encodes keys as one space-delimited string, padded so that every key,
+/// including the first and last, can be matched as ` key `.
+pub fn encode<S: AsRef<str>>(keys: &[S]) -> Option<Str> {
+   let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+   (!keys.is_empty()).then(|| format!(" {} ", keys.join(" ")).into())
+}
+
+/// Splits a value produced by [`encode`] back into keys.
+pub fn keys(stored: &str) -> impl Iterator<Item = &str> {
+   stored.split_whitespace()
+}
+
+/// SQL `LIKE` pattern matching stored values that contain `key`.
+///
+/// `_` in keys acts as a wildcard, so callers confirm matches with [`keys`].
+pub fn like_pattern(key: &str) -> String {
+   format!("% {key} %")
+}
+
+/// Extracts the bridge keys a chunk of `path` exports or imports.
+pub fn symbols(path: &Path, content: &str) -> Vec<String> {
+   let mut keys = Vec::new();
+   match side(path) {
+      Some(Side::Rust) => rust_symbols(content, &mut keys),
+      Some(Side::C) => c_symbols(content, &mut keys),
+      Some(Side::Jvm) => jvm_symbols(path, content, &mut keys),
+      Some(Side::Js) => js_symbols(content, &mut keys),
+      Some(Side::Python) => {
+         for caps in CTYPES_REGEX.captures_iter(content) {
+            keys.push(format!("c:{}", &caps[1]));
+         }
+      },
+      None => {},
+   }
+
+   let mut seen = HashSet::new();
+   keys.retain(|k| seen.insert(k.clone()));
+   keys.truncate(MAX_KEYS);
+   keys
+}
+
+fn rust_symbols(content: &str, keys: &mut Vec<String>) {
+   for caps in NO_MANGLE_REGEX.captures_iter(content) {
+      // JNI entry points are keyed by their Java method instead.
+      if !caps[1].starts_with("Java_") {
+         keys.push(format!("c:{}", &caps[1]));
+      }
+   }
+   for caps in EXPORT_NAME_REGEX.captures_iter(content) {
+      keys.push(format!("c:{}", &caps[1]));
+   }
+
+   for block in EXTERN_BLOCK_REGEX.captures_iter(content) {
+      let prefix = if block.get(1).is_some() { "js" } else { "c" };
+      let body_start = block.get(0).unwrap().end();
+      let body = &content[body_start..];
+      let body = &body[..body.find('}').unwrap_or(body.len())];
+      for caps in EXTERN_FN_REGEX.captures_iter(body) {
+         keys.push(format!("{prefix}:{}", js_name(&caps).unwrap_or(&caps[2])));
+      }
+   }
+
+   for caps in WASM_FN_REGEX.captures_iter(content) {
+      keys.push(format!("js:{}", js_name(&caps).unwrap_or(&caps[2])));
+   }
+
+   for caps in JNI_RUST_REGEX.captures_iter(content) {
+      keys.extend(jni_key(&caps[1]));
+   }
+}
+
+fn c_symbols(content: &str, keys: &mut Vec<String>) {
+   for caps in JNI_C_REGEX.captures_iter(content) {
+      keys.extend(jni_key(&caps[1]));
+   }
+
+   for caps in C_FN_REGEX.captures_iter(content) {
+      let name = &caps[1];
+      let line = caps.get(0).unwrap().as_str();
+      if C_KEYWORDS.contains(&name) || name.starts_with("Java_") || line.starts_with("typedef") {
+         continue;
+      }
+      keys.push(format!("c:{name}"));
+   }
+}
+
+/// JNI resolves `native` methods by class and method name; the package is
+/// left out so keys match regardless of how much of it a binding spells out.
+fn jvm_symbols(path: &Path, content: &str, keys: &mut Vec<String>) {
+   let Some(class) = path.file_stem().and_then(|s| s.to_str()) else {
+      return;
+   };
+   let natives = JAVA_NATIVE_REGEX
+      .captures_iter(content)
+      .chain(KOTLIN_EXTERNAL_REGEX.captures_iter(content));
+   for caps in natives {
+      keys.push(format!("jni:{class}.{}", &caps[1]));
+   }
+}
+
+fn js_symbols(content: &str, keys: &mut Vec<String>) {
+   for caps in JS_FN_REGEX
+      .captures_iter(content)
+      .chain(JS_ARROW_REGEX.captures_iter(content))
+   {
+      keys.push(format!("js:{}", &caps[1]));
+   }
+
+   // Functions imported from a wasm-bindgen package are implemented in Rust.
+   for caps in WASM_IMPORT_REGEX.captures_iter(content) {
+      let source = &caps[2];
+      if !source.contains("wasm") && !source.contains("pkg") {
+         continue;
+      }
+      for name in caps[1].split(',') {
+         let name = name.split_whitespace().next().unwrap_or_default();
+         if !name.is_empty() {
+            keys.push(format!("js:{name}"));
+         }
+      }
+   }
+}
+
+/// Returns the `js_name` override from a `#[wasm_bindgen(...)]` capture.
+fn js_name<'a>(caps: &Captures<'a>) -> Option<&'a str> {
+   let args = caps.get(1)?.as_str();
+   JS_NAME_REGEX
+      .captures(args)
+      .and_then(|c| c.get(1))
+      .map(|m| m.as_str())
+}
+
+/// Turns the mangled part of a `Java_<package>_<Class>_<method>` symbol into
+/// a `jni:Class.method` key.
+///
+/// Overload signatures after `__` are dropped and `_1` unescapes to `_`.
+fn jni_key(mangled: &str) -> Option<String> {
+   let mangled = mangled.split("__").next()?;
+   let mut parts = mangled
+      .replace("_1", "\u{1}")
+      .split('_')
+      .map(|p| p.replace('\u{1}', "_"))
+      .collect::<Vec<_>>();
+   let method = parts.pop()?;
+   let class = parts.pop()?;
+   (!class.is_empty() && !method.is_empty()).then(|| format!("jni:{class}.{method}"))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn rust_exports_match_c_callers() {
+      let rust = symbols(
+         Path::new("src/ffi.rs"),
+         "#[unsafe(no_mangle)]\npub extern \"C\" fn zs_deflate(buf: *const u8) -> i32 { 0 \
+          }\n\nunsafe extern \"C\" {\n    fn zs_log(msg: *const c_char);\n}",
+      );
+      assert_eq!(rust, ["c:zs_deflate", "c:zs_log"]);
+
+      let c = symbols(
+         Path::new("native/compress.c"),
+         "static int compress(const char *buf) {\n   if (buf == NULL) return -1;\n   return \
+          zs_deflate(buf);\n}\n\nvoid zs_log(const char *msg) {\n   puts(msg);\n}",
+      );
+      assert_eq!(c, ["c:compress", "c:zs_log"]);
+   }
+
+   #[test]
+   fn jni_and_wasm_bindings() {
+      let rust = symbols(
+         Path::new("src/jni.rs"),
+         "#[unsafe(no_mangle)]\npub extern \"system\" fn \
+          Java_com_example_Inflater_inflate_1all(env: JNIEnv) {}\n\n#[wasm_bindgen(js_name = \
+          \"parseConfig\")]\npub fn parse_config(src: &str) {}",
+      );
+      assert_eq!(rust, ["js:parseConfig", "jni:Inflater.inflate_all"]);
+
+      let java = symbols(
+         Path::new("src/main/java/com/example/Inflater.java"),
+         "private static native int inflate_all(byte[] input);",
+      );
+      assert_eq!(java, ["jni:Inflater.inflate_all"]);
+
+      let js = symbols(
+         Path::new("web/app.ts"),
+         "import { parseConfig } from \"../pkg/core_wasm\";\nexport function load() {}",
+      );
+      assert_eq!(js, ["js:load", "js:parseConfig"]);
+   }
+
+   #[test]
+   fn stored_keys_round_trip() {
+      let stored = collect(Path::new("lib.py"), "lib.zs_deflate.argtypes = [c_char_p]").unwrap();
+      assert_eq!(stored.as_str(), " c:zs_deflate ");
+      assert_eq!(keys(&stored).collect::<Vec<_>>(), ["c:zs_deflate"]);
+      assert!(collect(Path::new("README.md"), "fn main() {}").is_none());
+   }
+}
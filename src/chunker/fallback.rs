@@ -0,0 +1,53 @@
+//! Line-based chunking for files without a usable grammar.
+
+use std::{path::Path, slice};
+
+use super::{Chunker, MAX_CHARS, split};
+use crate::{
+   Str,
+   error::Result,
+   types::{Chunk, ChunkType},
+};
+
+/// Splits any text into overlapping windows of lines, cutting at blank lines
+/// and statement boundaries where possible. Handles every file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FallbackChunker;
+
+impl FallbackChunker {
+   pub(super) fn simple_chunk(content: &Str, path: &Path) -> Vec<Chunk> {
+      let lines: Vec<&str> = content.lines().collect();
+      let mut chunks = Vec::new();
+      let context: Str = format!("File: {}", path.display()).into();
+      let stack = slice::from_ref(&context);
+
+      let mut i = 0;
+      while i < lines.len() {
+         let end = split::window_end(&lines, i);
+
+         let (start_byte, end_byte) = split::line_range_to_byte_range(content, i, end);
+         let sub_content = content.slice(start_byte..end_byte);
+
+         if sub_content.len() <= MAX_CHARS {
+            chunks.push(Chunk::new(sub_content, i, end, ChunkType::Block, stack));
+         } else {
+            let split_chunks = split::split_content_by_chars(&sub_content, i, stack);
+            chunks.extend(split_chunks);
+         }
+
+         if end == lines.len() {
+            break;
+         }
+         i = split::window_next_start(&lines, i, end);
+      }
+
+      chunks
+   }
+}
+
+#[async_trait::async_trait]
+impl Chunker for FallbackChunker {
+   async fn try_chunk(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>> {
+      Ok(Some(split::limit_size(Self::simple_chunk(content, path))))
+   }
+}
@@ -1,23 +1,35 @@
 //! Code chunking with tree-sitter language-aware parsing.
 //!
 //! Splits source files into semantic chunks for code search and analysis.
-//! Uses tree-sitter grammars when available, falls back to line-based
-//! splitting.
+//! Chunkers implement [`Chunker`] and compose through [`ChainChunker`]; the
+//! default chain uses tree-sitter grammars when available and falls back to
+//! line-based splitting.
+
+/// Declares lazily compiled regexes shared by the chunker modules.
+macro_rules! static_regex {
+   ($($name:ident = $regex:expr),* $(,)?) => {
+      $(
+         pub(crate) static $name: LazyLock<Regex> = LazyLock::new(|| Regex::new($regex).unwrap());
+      )*
+   };
+}
 
 pub mod anchor;
+pub mod bridge;
+mod fallback;
+pub mod module;
+mod split;
+pub mod stream;
+mod tokens;
+mod treesitter;
 
-use std::{borrow::Cow, path::Path, slice, sync::Arc};
+use std::{path::Path, sync::Arc};
 
-use memchr::memchr_iter;
-use tree_sitter::Language;
+pub use fallback::FallbackChunker;
+pub use tokens::TokenLimit;
+pub use treesitter::TreeSitterChunker;
 
-use crate::{
-   Str,
-   chunker::anchor::CONST_EXPORT_REGEX,
-   error::{ChunkerError, Result},
-   grammar::GrammarManager,
-   types::{Chunk, ChunkType},
-};
+use crate::{Str, config, error::Result, format, types::Chunk};
 
 /// Maximum number of lines per chunk.
 pub const MAX_LINES: usize = 75;
@@ -38,505 +50,120 @@ pub const STRIDE_CHARS: usize = MAX_CHARS - OVERLAP_CHARS;
 /// Number of lines to advance between chunks (`MAX_LINES` - `OVERLAP_LINES`).
 pub const STRIDE_LINES: usize = MAX_LINES - OVERLAP_LINES;
 
-/// Splits source code into semantic chunks using tree-sitter grammars.
-///
-/// Extracts definitions (functions, classes, types) from parsed syntax trees
-/// and splits them into manageable chunks for code search.
-#[derive(Clone, Debug, Default)]
-#[repr(transparent)]
-pub struct Chunker(Arc<GrammarManager>);
+/// How far back from a hard window end to look for a cleaner split point.
+const BOUNDARY_LOOKBACK_LINES: usize = MAX_LINES / 4;
 
-impl Chunker {
-   async fn get_language(&self, path: &Path) -> Result<Option<Language>> {
-      self.0.get_language_for_path(path).await.inspect_err(|e| {
-         tracing::warn!("failed to load language for {}: {}", path.display(), e);
-      })
+/// Text to embed for `chunk` of `path` following `embed_template`, or `None`
+/// when that is just the chunk's content.
+pub fn embed_text(path: &Path, chunk: &Chunk) -> Option<Str> {
+   let template = &config::get().embed_template;
+   if template == "{content}" {
+      return None;
    }
+   let path = path.display().to_string();
+   Some(format::render_embed_template(template, &chunk.context[..], &path, &chunk.content).into())
+}
 
-   fn line_range_to_byte_range(
-      content: &str,
-      start_line: usize,
-      end_line: usize,
-   ) -> (usize, usize) {
-      let bytes = content.as_bytes();
-      let mut start_byte = 0;
-      let mut end_byte = bytes.len();
-      let mut current_line = 0;
-
-      for idx in memchr_iter(b'\n', bytes) {
-         current_line += 1;
-         if current_line == end_line {
-            end_byte = idx;
-            break;
-         }
-         if current_line == start_line {
-            start_byte = idx + 1;
-         }
-      }
+/// Splits a file into chunks.
+///
+/// Implementations return chunks within [`MAX_LINES`] and [`MAX_CHARS`].
+#[async_trait::async_trait]
+pub trait Chunker: Send + Sync {
+   /// Chunks `content`, or returns `None` if this chunker does not handle
+   /// `path` so the next chunker in a [`ChainChunker`] can.
+   async fn try_chunk(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>>;
 
-      (start_byte, end_byte)
+   /// Chunks `content`, producing no chunks if this chunker declines it.
+   async fn chunk(&self, content: &Str, path: &Path) -> Result<Vec<Chunk>> {
+      Ok(self.try_chunk(content, path).await?.unwrap_or_default())
    }
 
-   fn simple_chunk(content: &Str, path: &Path) -> Vec<Chunk> {
-      let lines: Vec<&str> = content.lines().collect();
-      let mut chunks = Vec::new();
-      let stride = (MAX_LINES - OVERLAP_LINES).max(1);
-      let context: Str = format!("File: {}", path.display()).into();
-      let stack = slice::from_ref(&context);
-
-      let mut i = 0;
-      while i < lines.len() {
-         let end = (i + MAX_LINES).min(lines.len());
-         let sub_lines = &lines[i..end];
-
-         if sub_lines.is_empty() {
-            break;
-         }
-
-         let (start_byte, end_byte) = Self::line_range_to_byte_range(content, i, end);
-         let sub_content = content.slice(start_byte..end_byte);
-
-         if sub_content.len() <= MAX_CHARS {
-            chunks.push(Chunk::new(sub_content, i, end, ChunkType::Block, stack));
-         } else {
-            let split_chunks = Self::split_content_by_chars(&sub_content, i, stack);
-            chunks.extend(split_chunks);
-         }
-         i += stride;
-      }
-
+   /// Applies the limits of this chunker's output to chunks made elsewhere,
+   /// such as those streamed from large files.
+   async fn limit(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
       chunks
    }
+}
 
-   async fn chunk_with_tree_sitter(
-      &self,
-      content: &Str,
-      path: &Path,
-   ) -> Result<Option<Vec<Chunk>>> {
-      let Some(language) = self.get_language(path).await? else {
-         return Ok(None);
-      };
-
-      let (mut parser, store) = self.0.create_parser_with_store()?;
-      parser
-         .set_wasm_store(store)
-         .map_err(ChunkerError::SetWasmStore)?;
-
-      parser
-         .set_language(&language)
-         .map_err(ChunkerError::SetLanguage)?;
-
-      let tree = parser
-         .parse(content.as_str(), None)
-         .ok_or(ChunkerError::ParseFailed)?;
-
-      let root = tree.root_node();
-      let file_context: Str = format!("File: {}", path.display()).into();
-
-      let mut chunks = Vec::new();
-      let mut block_chunks = Vec::new();
-      let mut cursor_index = 0;
-      let mut cursor_row = 0;
-      let mut saw_definition = false;
-
-      let mut cursor = root.walk();
-      for child in root.named_children(&mut cursor) {
-         Self::visit_node(
-            &child,
-            content,
-            slice::from_ref(&file_context),
-            &mut chunks,
-            &mut saw_definition,
-         );
-
-         let effective = Self::unwrap_export(&child);
-         let is_definition = Self::is_definition_node(&effective, content.as_str());
-
-         if is_definition {
-            if child.start_byte() > cursor_index {
-               let gap_text = content.slice(cursor_index..child.start_byte());
-               if !gap_text.trim().is_empty() {
-                  block_chunks.push(Chunk::new(
-                     gap_text,
-                     cursor_row,
-                     child.start_position().row,
-                     ChunkType::Block,
-                     slice::from_ref(&file_context),
-                  ));
-               }
-            }
-
-            cursor_index = child.end_byte();
-            cursor_row = child.end_position().row;
-         }
-      }
-
-      if cursor_index < content.len() {
-         let tail_text = content.slice(cursor_index..);
-         if !tail_text.trim().is_empty() {
-            block_chunks.push(Chunk::new(
-               tail_text,
-               cursor_row,
-               root.end_position().row,
-               ChunkType::Block,
-               &[file_context],
-            ));
-         }
-      }
-
-      if !saw_definition {
-         return Ok(Some(Vec::new()));
-      }
-
-      let mut combined = block_chunks;
-      combined.extend(chunks);
-      combined.sort_by(|a, b| {
-         a.start_line
-            .cmp(&b.start_line)
-            .then(a.end_line.cmp(&b.end_line))
-      });
-
-      Ok(Some(combined))
+#[async_trait::async_trait]
+impl<T: Chunker + ?Sized> Chunker for Arc<T> {
+   async fn try_chunk(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>> {
+      (**self).try_chunk(content, path).await
    }
 
-   fn visit_node(
-      node: &tree_sitter::Node,
-      content: &Str,
-      stack: &[Str],
-      chunks: &mut Vec<Chunk>,
-      saw_definition: &mut bool,
-   ) {
-      let effective = Self::unwrap_export(node);
-      let is_definition = Self::is_definition_node(&effective, content.as_str());
-      let mut stack = Cow::Borrowed(stack);
-
-      if is_definition {
-         *saw_definition = true;
-         let label = Self::label_for_node(&effective, content.as_str());
-         if let Some(label) = label {
-            stack.to_mut().push(label.into());
-         }
-
-         let node_text = content.slice(effective.start_byte()..effective.end_byte());
-         chunks.push(Chunk::new(
-            node_text,
-            effective.start_position().row,
-            effective.end_position().row,
-            Self::classify_node(&effective),
-            stack.as_ref(),
-         ));
-      }
-
-      let mut cursor = effective.walk();
-      for child in effective.named_children(&mut cursor) {
-         Self::visit_node(&child, content, &stack, chunks, saw_definition);
-      }
+   async fn limit(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
+      (**self).limit(chunks).await
    }
+}
 
-   fn unwrap_export<'a>(node: &'a tree_sitter::Node) -> tree_sitter::Node<'a> {
-      if node.kind() == "export_statement" && node.named_child_count() > 0 {
-         return node.named_child(0).unwrap();
-      }
-      *node
-   }
+/// Tries each chunker in turn and uses the first that handles a file.
+///
+/// A chunker that fails is logged and skipped. The default chain is
+/// [`TreeSitterChunker`] followed by [`FallbackChunker`]; custom chunkers
+/// (markdown, notebooks) go in front with [`Self::with_first`]. The default
+/// chain also splits chunks to the dense model's [`TokenLimit`].
+#[derive(Clone)]
+pub struct ChainChunker {
+   chunkers:    Vec<Arc<dyn Chunker>>,
+   token_limit: Option<Arc<TokenLimit>>,
+}
 
-   fn is_definition_node(node: &tree_sitter::Node, content: &str) -> bool {
-      let kind = node.kind();
-      matches!(
-         kind,
-         // JavaScript/TypeScript/Go/C/C++/Java
-         "function_declaration"
-            | "function_definition"
-            | "method_definition"
-            | "method_declaration"
-            | "class_declaration"
-            | "class_definition"
-            | "interface_declaration"
-            | "type_alias_declaration"
-            | "type_declaration"
-            // Rust
-            | "function_item"
-            | "impl_item"
-            | "struct_item"
-            | "enum_item"
-            | "trait_item"
-            | "mod_item"
-            | "type_item"
-            | "const_item"
-            | "static_item"
-            // Python
-            | "function_def"
-            | "class_def"
-            | "async_function_def"
-      ) || Self::is_top_level_value_def(node, content)
+impl ChainChunker {
+   pub fn new(chunkers: Vec<Arc<dyn Chunker>>) -> Self {
+      Self { chunkers, token_limit: None }
    }
 
-   fn is_top_level_value_def(node: &tree_sitter::Node, content: &str) -> bool {
-      let kind = node.kind();
-      if kind != "lexical_declaration" && kind != "variable_declaration" {
-         return false;
-      }
-
-      if let Some(parent) = node.parent() {
-         let parent_kind = parent.kind();
-         if !matches!(parent_kind, "program" | "module" | "source_file" | "class_body") {
-            return false;
-         }
-      }
-
-      let text = &content[node.start_byte()..node.end_byte()];
-
-      if text.contains("=>") {
-         return true;
-      }
-      if text.contains("function ") {
-         return true;
-      }
-      if text.contains("class ") {
-         return true;
-      }
-
-      if CONST_EXPORT_REGEX.is_match(text) {
-         return true;
-      }
-
-      false
+   /// Tries `chunker` before the chunkers already in the chain.
+   pub fn with_first(mut self, chunker: impl Chunker + 'static) -> Self {
+      self.chunkers.insert(0, Arc::new(chunker));
+      self
    }
 
-   fn classify_node(node: &tree_sitter::Node) -> ChunkType {
-      let kind = node.kind();
-      if kind.contains("class") {
-         ChunkType::Class
-      } else if kind.contains("interface") {
-         ChunkType::Interface
-      } else if kind.contains("type_alias") || kind.contains("type_declaration") {
-         ChunkType::TypeAlias
-      } else {
-         ChunkType::Other
-      }
+   /// Splits the chain's chunks to `token_limit`.
+   pub fn with_token_limit(mut self, token_limit: TokenLimit) -> Self {
+      self.token_limit = Some(Arc::new(token_limit));
+      self
    }
+}
 
-   fn get_node_name<'a>(node: &tree_sitter::Node, content: &'a str) -> Option<&'a str> {
-      if let Some(name_node) = node.child_by_field_name("name") {
-         return Some(&content[name_node.start_byte()..name_node.end_byte()]);
-      }
-
-      if let Some(property_node) = node.child_by_field_name("property") {
-         return Some(&content[property_node.start_byte()..property_node.end_byte()]);
-      }
-
-      if let Some(identifier_node) = node.child_by_field_name("identifier") {
-         return Some(&content[identifier_node.start_byte()..identifier_node.end_byte()]);
-      }
-
-      let mut cursor = node.walk();
-      for child in node.named_children(&mut cursor) {
-         let child_kind = child.kind();
-         if matches!(
-            child_kind,
-            "identifier" | "property_identifier" | "type_identifier" | "field_identifier"
-         ) {
-            return Some(&content[child.start_byte()..child.end_byte()]);
-         }
-
-         if child_kind == "variable_declarator"
-            && let Some(name) = Self::get_node_name(&child, content)
-         {
-            return Some(name);
-         }
-      }
-
-      None
+impl Default for ChainChunker {
+   fn default() -> Self {
+      Self::new(vec![Arc::new(TreeSitterChunker::default()), Arc::new(FallbackChunker)])
+         .with_token_limit(TokenLimit::new())
    }
+}
 
-   fn label_for_node(node: &tree_sitter::Node, content: &str) -> Option<String> {
-      let name = Self::get_node_name(node, content);
-      let kind = node.kind();
-
-      let (prefix, default) = if kind.contains("class") {
-         ("Class: ", "<anonymous class>")
-      } else if kind.contains("method") {
-         ("Method: ", "<anonymous method>")
-      } else if kind.contains("interface") {
-         ("Interface: ", "<anonymous interface>")
-      } else if kind.contains("type_alias") || kind.contains("type_declaration") {
-         ("Type: ", "<anonymous type>")
-      } else if kind.contains("function") || Self::is_top_level_value_def(node, content) {
-         ("Function: ", "<anonymous function>")
-      } else {
-         return name.map(|n| format!("Symbol: {n}"));
-      };
-      Some(format!("{prefix}{}", name.unwrap_or(default)))
+impl std::fmt::Debug for ChainChunker {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      f.debug_tuple("ChainChunker")
+         .field(&self.chunkers.len())
+         .finish()
    }
+}
 
-   fn split_if_too_big(chunk: Chunk) -> Vec<Chunk> {
-      let char_count = chunk.content.len();
-      let lines: Vec<&str> = chunk.content.lines().collect();
-      let line_count = lines.len();
-
-      if line_count <= MAX_LINES && char_count <= MAX_CHARS {
-         return vec![chunk];
-      }
-
-      if char_count > MAX_CHARS && line_count <= MAX_LINES {
-         return Self::split_by_chars(chunk);
-      }
-
-      let mut sub_chunks = Vec::new();
-      let stride = (MAX_LINES - OVERLAP_LINES).max(1);
-      let header = Self::extract_header_line(&chunk.content);
-
-      let mut i = 0;
-      while i < lines.len() {
-         let end = (i + MAX_LINES).min(lines.len());
-         let sub_lines = &lines[i..end];
-
-         if sub_lines.len() < 3 && i > 0 {
-            i += stride;
-            continue;
-         }
-
-         let (start_byte, end_byte) = Self::line_range_to_byte_range(&chunk.content, i, end);
-         let mut content = chunk.content.slice(start_byte..end_byte);
-
-         if let Some(h) = header
-            && i > 0
-            && chunk.chunk_type != Some(ChunkType::Block)
-         {
-            content = Str::from_string(format!("{h}\n{content}"));
+#[async_trait::async_trait]
+impl Chunker for ChainChunker {
+   async fn try_chunk(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>> {
+      for chunker in &self.chunkers {
+         match chunker.try_chunk(content, path).await {
+            Ok(Some(chunks)) => return Ok(Some(self.limit(chunks).await)),
+            Ok(None) => {},
+            Err(e) => {
+               tracing::warn!(
+                  error = %e,
+                  path = %path.display(),
+                  "chunker failed, trying the next one"
+               );
+            },
          }
-
-         sub_chunks.push(Chunk::new(
-            content,
-            chunk.start_line + i,
-            chunk.start_line + end,
-            chunk.chunk_type.unwrap_or(ChunkType::Other),
-            &chunk.context,
-         ));
-
-         i += stride;
       }
-
-      sub_chunks
-         .into_iter()
-         .flat_map(|sc| {
-            if sc.content.len() > MAX_CHARS {
-               Self::split_by_chars(sc)
-            } else {
-               vec![sc]
-            }
-         })
-         .collect()
+      Ok(None)
    }
 
-   fn split_by_chars_impl(
-      content: &Str,
-      start_line: usize,
-      chunk_type: ChunkType,
-      context: &[Str],
-   ) -> Vec<Chunk> {
-      let mut chunks = Vec::new();
-      let mut iter = content.as_str();
-      let mut ln = start_line;
-      loop {
-         iter = iter.trim_start();
-         if iter.is_empty() {
-            break;
-         }
-         let lim = iter.floor_char_boundary(MAX_CHARS);
-         let (pre, post) = iter.split_at(lim);
-         iter = post;
-         let trimmed = pre.trim_end();
-         if trimmed.is_empty() {
-            continue;
-         }
-         let lines = trimmed.lines().count();
-         chunks.push(Chunk::new(content.slice_ref(trimmed), ln, ln + lines, chunk_type, context));
-         ln += lines;
+   async fn limit(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
+      match &self.token_limit {
+         Some(token_limit) => token_limit.apply(chunks).await,
+         None => chunks,
       }
-      chunks
-   }
-
-   fn split_content_by_chars(input: &Str, start_line: usize, context: &[Str]) -> Vec<Chunk> {
-      Self::split_by_chars_impl(input, start_line, ChunkType::Block, context)
-   }
-
-   fn split_by_chars(chunk: Chunk) -> Vec<Chunk> {
-      Self::split_by_chars_impl(
-         &chunk.content,
-         chunk.start_line,
-         chunk.chunk_type.unwrap_or(ChunkType::Other),
-         &chunk.context,
-      )
-   }
-
-   fn extract_header_line(text: &str) -> Option<&str> {
-      text.lines().map(str::trim).find(|s| !s.is_empty())
-   }
-
-   /// Splits source code into semantic chunks.
-   ///
-   /// Attempts tree-sitter parsing first, falls back to line-based chunking if
-   /// parsing fails. Ensures all chunks satisfy [`MAX_LINES`] and
-   /// [`MAX_CHARS`] constraints.
-   pub async fn chunk(&self, content: &Str, path: &Path) -> Result<Vec<Chunk>> {
-      let raw_chunks = match self.chunk_with_tree_sitter(content, path).await {
-         Ok(Some(c)) => c,
-         Ok(None) => Self::simple_chunk(content, path),
-         Err(e) => {
-            tracing::warn!(
-               error = %e,
-               path = %path.display(),
-               "tree-sitter chunk failed, falling back to simple chunk"
-            );
-            Self::simple_chunk(content, path)
-         },
-      };
-
-      let chunks: Vec<Chunk> = raw_chunks
-         .into_iter()
-         .flat_map(Self::split_if_too_big)
-         .collect();
-
-      Ok(chunks)
-   }
-}
-
-#[cfg(test)]
-mod tests {
-   use std::fmt::Write;
-
-   use super::*;
-
-   #[test]
-   fn split_by_chars_preserves_chunk_type() {
-      let content = Str::from_string("a".repeat(MAX_CHARS + 10));
-      let chunk = Chunk::new(content, 0, 1, ChunkType::Function, &[]);
-
-      let pieces = Chunker::split_by_chars(chunk);
-
-      assert!(!pieces.is_empty());
-      assert!(
-         pieces
-            .iter()
-            .all(|c| c.chunk_type == Some(ChunkType::Function))
-      );
-   }
-
-   #[test]
-   fn split_if_too_big_keeps_trailing_lines() {
-      let content = Str::from_string((0..131).fold(String::new(), |mut s, i| {
-         let _ = writeln!(s, "line {i}");
-         s
-      }));
-      let chunk = Chunk::new(content, 0, 131, ChunkType::Block, &[]);
-
-      let sub_chunks = Chunker::split_if_too_big(chunk);
-
-      assert!(
-         sub_chunks
-            .iter()
-            .any(|c| c.content.as_str().contains("line 130"))
-      );
    }
 }
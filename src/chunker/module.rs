@@ -0,0 +1,90 @@
+//! Module summary chunks for directories.
+//!
+//! Summarizes the files directly inside a directory (their names, exports and
+//! opening comments) into one synthetic chunk, so that questions about where
+//! a feature lives can be answered with a directory rather than whichever
+//! function happens to mention it.
+
+use std::{fmt::Write, path::Path};
+
+use super::anchor::{Outline, outline};
+use crate::{
+   Str,
+   types::{Chunk, ChunkType},
+};
+
+/// Exports listed in a summary before the rest are counted.
+const MAX_EXPORTS: usize = 40;
+/// Files whose opening comment is quoted in a summary.
+const MAX_DESCRIBED: usize = 20;
+/// Characters quoted from each file's opening comment.
+const MAX_DESCRIPTION_CHARS: usize = 120;
+
+/// Creates the module summary chunk of `dir` from the `(path, content)` of
+/// the files directly inside it.
+pub fn create_module_chunk(dir: &Path, files: &[(&Path, &str)]) -> Chunk {
+   let mut names = Vec::with_capacity(files.len());
+   let mut exports = Vec::new();
+   let mut descriptions = Vec::new();
+
+   for (path, content) in files {
+      let name = path
+         .file_name()
+         .map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+      let lines: Vec<&str> = content.lines().collect();
+      let Outline { top_comments, exports: file_exports, modules, .. } = outline(path, &lines);
+      exports.extend(file_exports);
+      exports.extend(modules);
+      if descriptions.len() < MAX_DESCRIBED
+         && let Some(description) = describe(&top_comments)
+      {
+         descriptions.push(format!("{name}: {description}"));
+      }
+      names.push(name);
+   }
+   names.sort();
+   exports.sort();
+   exports.dedup();
+
+   let mut text = String::new();
+   write!(text, "Directory: {}", dir.display()).unwrap();
+   write!(text, "\n\nFiles: {}", names.join(", ")).unwrap();
+   if !exports.is_empty() {
+      let shown = exports[..exports.len().min(MAX_EXPORTS)].join(", ");
+      match exports.len().saturating_sub(MAX_EXPORTS) {
+         0 => write!(text, "\n\nExports: {shown}").unwrap(),
+         rest => write!(text, "\n\nExports: {shown} (+{rest} more)").unwrap(),
+      }
+   }
+   if !descriptions.is_empty() {
+      descriptions.sort();
+      write!(text, "\n\nAbout:\n{}", descriptions.join("\n")).unwrap();
+   }
+   text.push_str("\n\n---\n\n(module)");
+
+   Chunk::new(Str::from_string(text), 0, 0, ChunkType::Module, &[format!(
+      "Directory: {}",
+      dir.display()
+   )
+   .into()])
+}
+
+/// The first line of a file's opening comment with its comment markers
+/// stripped, shortened to [`MAX_DESCRIPTION_CHARS`].
+fn describe(comments: &[String]) -> Option<String> {
+   let line = comments
+      .iter()
+      .filter(|line| !line.trim_start().starts_with("#!"))
+      .map(|line| {
+         line
+            .trim()
+            .trim_start_matches(['/', '*', '!', '#', '"', '\''])
+            .trim_end_matches(['*', '/', '"', '\''])
+            .trim()
+      })
+      .find(|line| !line.is_empty())?;
+   Some(match line.char_indices().nth(MAX_DESCRIPTION_CHARS) {
+      Some((end, _)) => format!("{}...", &line[..end]),
+      None => line.to_string(),
+   })
+}
@@ -0,0 +1,428 @@
+//! Size limits for chunks.
+//!
+//! Splits oversized chunks into windows of at most [`MAX_LINES`] lines and
+//! [`MAX_CHARS`] characters, preferring blank lines and statement boundaries
+//! over cutting mid-block, and chunks over a token budget in halves until
+//! every piece fits.
+
+use std::cmp::Reverse;
+
+use memchr::memchr_iter;
+
+use super::{BOUNDARY_LOOKBACK_LINES, MAX_CHARS, MAX_LINES, OVERLAP_LINES};
+use crate::{
+   Str,
+   types::{Chunk, ChunkType},
+};
+
+/// Splits every chunk that exceeds [`MAX_LINES`] or [`MAX_CHARS`].
+pub(super) fn limit_size(chunks: Vec<Chunk>) -> Vec<Chunk> {
+   chunks.into_iter().flat_map(split_if_too_big).collect()
+}
+
+pub(super) fn line_range_to_byte_range(
+   content: &str,
+   start_line: usize,
+   end_line: usize,
+) -> (usize, usize) {
+   let bytes = content.as_bytes();
+   let mut start_byte = 0;
+   let mut end_byte = bytes.len();
+   let mut current_line = 0;
+
+   for idx in memchr_iter(b'\n', bytes) {
+      current_line += 1;
+      if current_line == end_line {
+         end_byte = idx;
+         break;
+      }
+      if current_line == start_line {
+         start_byte = idx + 1;
+      }
+   }
+
+   (start_byte, end_byte)
+}
+
+/// Ranks how cleanly a chunk can end after `line`.
+///
+/// Blank lines rank highest, then lines that only close blocks, then
+/// statement terminators. Anything else scores zero.
+fn boundary_score(line: &str) -> u8 {
+   let trimmed = line.trim();
+   if trimmed.is_empty() {
+      3
+   } else if trimmed.starts_with('}')
+      && trimmed
+         .bytes()
+         .all(|b| matches!(b, b'}' | b')' | b']' | b';' | b','))
+   {
+      2
+   } else {
+      u8::from(trimmed.ends_with([';', '}']))
+   }
+}
+
+/// Whether `line` continues the previous statement (`} else {`, `.map(..)`,
+/// `&& cond`) and would read badly as the first line of a chunk.
+fn is_continuation(line: &str) -> bool {
+   let trimmed = line.trim_start();
+   trimmed.starts_with(['}', ')', ']', '.', '?'])
+      || ["else", "catch", "finally", "except", "elif", "&&", "||"]
+         .iter()
+         .any(|kw| trimmed.starts_with(kw))
+}
+
+/// Scores a split between `prev` and `next`, where `next` becomes the
+/// first line of the following chunk.
+fn split_score(prev: &str, next: Option<&str>) -> u8 {
+   if next.is_some_and(is_continuation) {
+      0
+   } else {
+      boundary_score(prev)
+   }
+}
+
+/// Picks the exclusive end line for a window starting at `start`.
+///
+/// Looks back up to [`BOUNDARY_LOOKBACK_LINES`] from the hard
+/// [`MAX_LINES`] limit for the best statement or blank-line boundary,
+/// preferring later lines on ties.
+pub(super) fn window_end(lines: &[&str], start: usize) -> usize {
+   let hard_end = (start + MAX_LINES).min(lines.len());
+   if hard_end == lines.len() {
+      return hard_end;
+   }
+
+   let floor = hard_end
+      .saturating_sub(BOUNDARY_LOOKBACK_LINES)
+      .max(start + 1);
+   let mut best = (0, hard_end);
+   for end in (floor..=hard_end).rev() {
+      let score = split_score(lines[end - 1], lines.get(end).copied());
+      if score > best.0 {
+         best = (score, end);
+      }
+   }
+   best.1
+}
+
+/// Picks where the window after `start..end` begins, keeping roughly
+/// [`OVERLAP_LINES`] of overlap but starting on a clean line if one exists.
+pub(super) fn window_next_start(lines: &[&str], start: usize, end: usize) -> usize {
+   let lo = end.saturating_sub(OVERLAP_LINES).max(start + 1);
+   (lo..end)
+      .find(|&k| split_score(lines[k - 1], Some(lines[k])) > 0)
+      .unwrap_or(lo)
+}
+
+/// Returns the byte offset at which to cut `text` so the head fits in
+/// `limit` bytes.
+///
+/// Prefers line breaks in the last quarter of the window, ranked by
+/// [`split_score`], over cutting mid-line.
+fn char_split_point(text: &str, limit: usize) -> usize {
+   if text.len() <= limit {
+      return text.len();
+   }
+
+   let lim = text.floor_char_boundary(limit);
+   let floor = lim - lim / 4;
+   let head = &text[..lim];
+
+   let mut best: Option<(u8, usize)> = None;
+   let mut line_start = 0;
+   for nl in memchr_iter(b'\n', head.as_bytes()) {
+      let cut = nl + 1;
+      if cut >= floor {
+         let score = split_score(&head[line_start..nl], text[cut..].lines().next());
+         if best.is_none_or(|(s, _)| score >= s) {
+            best = Some((score, cut));
+         }
+      }
+      line_start = cut;
+   }
+
+   best.map_or(lim, |(_, cut)| cut)
+}
+
+fn split_if_too_big(chunk: Chunk) -> Vec<Chunk> {
+   let char_count = chunk.content.len();
+   let lines: Vec<&str> = chunk.content.lines().collect();
+   let line_count = lines.len();
+
+   if line_count <= MAX_LINES && char_count <= MAX_CHARS {
+      return vec![chunk];
+   }
+
+   if char_count > MAX_CHARS && line_count <= MAX_LINES {
+      return split_by_chars(chunk);
+   }
+
+   let mut sub_chunks = Vec::new();
+   let header = extract_header_line(&chunk.content);
+
+   let mut i = 0;
+   while i < lines.len() {
+      let end = window_end(&lines, i);
+
+      let (start_byte, end_byte) = line_range_to_byte_range(&chunk.content, i, end);
+      let mut content = chunk.content.slice(start_byte..end_byte);
+
+      if let Some(h) = header
+         && i > 0
+         && chunk.chunk_type != Some(ChunkType::Block)
+      {
+         content = Str::from_string(format!("{h}\n{content}"));
+      }
+
+      sub_chunks.push(Chunk::new(
+         content,
+         chunk.start_line + i,
+         chunk.start_line + end,
+         chunk.chunk_type.unwrap_or(ChunkType::Other),
+         &chunk.context,
+      ));
+
+      if end == lines.len() {
+         break;
+      }
+      i = window_next_start(&lines, i, end);
+   }
+
+   sub_chunks
+      .into_iter()
+      .flat_map(|sc| {
+         if sc.content.len() > MAX_CHARS {
+            split_by_chars(sc)
+         } else {
+            vec![sc]
+         }
+      })
+      .collect()
+}
+
+fn split_by_chars_impl(
+   content: &Str,
+   start_line: usize,
+   chunk_type: ChunkType,
+   context: &[Str],
+) -> Vec<Chunk> {
+   let mut chunks = Vec::new();
+   let mut iter = content.as_str();
+   let mut ln = start_line;
+   loop {
+      iter = iter.trim_start();
+      if iter.is_empty() {
+         break;
+      }
+      let lim = char_split_point(iter, MAX_CHARS);
+      let (pre, post) = iter.split_at(lim);
+      iter = post;
+      let trimmed = pre.trim_end();
+      if trimmed.is_empty() {
+         continue;
+      }
+      let lines = trimmed.lines().count();
+      chunks.push(Chunk::new(content.slice_ref(trimmed), ln, ln + lines, chunk_type, context));
+      ln += lines;
+   }
+   chunks
+}
+
+pub(super) fn split_content_by_chars(
+   input: &Str,
+   start_line: usize,
+   context: &[Str],
+) -> Vec<Chunk> {
+   split_by_chars_impl(input, start_line, ChunkType::Block, context)
+}
+
+fn split_by_chars(chunk: Chunk) -> Vec<Chunk> {
+   split_by_chars_impl(
+      &chunk.content,
+      chunk.start_line,
+      chunk.chunk_type.unwrap_or(ChunkType::Other),
+      &chunk.context,
+   )
+}
+
+/// Splits every chunk that `count` finds longer than `max_tokens` tokens.
+pub(super) fn limit_tokens(
+   chunks: Vec<Chunk>,
+   max_tokens: usize,
+   count: &impl Fn(&str) -> usize,
+) -> Vec<Chunk> {
+   chunks
+      .into_iter()
+      .flat_map(|chunk| split_to_tokens(chunk, max_tokens, count))
+      .collect()
+}
+
+/// Halves `chunk` until every piece fits in `max_tokens`, cutting between
+/// lines at the cleanest boundary near the middle, or mid-line at whitespace
+/// when a single line is too long.
+fn split_to_tokens(chunk: Chunk, max_tokens: usize, count: &impl Fn(&str) -> usize) -> Vec<Chunk> {
+   if count(&chunk.content) <= max_tokens {
+      return vec![chunk];
+   }
+
+   let chunk_type = chunk.chunk_type.unwrap_or(ChunkType::Other);
+   let lines: Vec<&str> = chunk.content.lines().collect();
+   let halves = if lines.len() > 1 {
+      let mid = lines.len() / 2;
+      let cut = (lines.len() / 4).max(1)..=(lines.len() * 3 / 4).max(1);
+      let at = cut
+         .max_by_key(|&k| {
+            (split_score(lines[k - 1], lines.get(k).copied()), Reverse(mid.abs_diff(k)))
+         })
+         .unwrap_or(mid);
+      let (_, head_end) = line_range_to_byte_range(&chunk.content, 0, at);
+      let tail_start = (head_end + 1).min(chunk.content.len());
+      [
+         (chunk.content.slice(..head_end), chunk.start_line, chunk.start_line + at),
+         (chunk.content.slice(tail_start..), chunk.start_line + at, chunk.end_line),
+      ]
+   } else {
+      let text = chunk.content.as_str();
+      let mid = text.floor_char_boundary(text.len() / 2);
+      let at = text[..mid]
+         .rfind(char::is_whitespace)
+         .filter(|&i| i > 0)
+         .unwrap_or(mid);
+      if at == 0 {
+         return vec![chunk];
+      }
+      [
+         (chunk.content.slice(..at), chunk.start_line, chunk.end_line),
+         (chunk.content.slice(at..), chunk.start_line, chunk.end_line),
+      ]
+   };
+
+   halves
+      .into_iter()
+      .filter(|(content, ..)| !content.trim().is_empty())
+      .flat_map(|(content, start, end)| {
+         let piece = Chunk::new(content, start, end, chunk_type, &chunk.context);
+         split_to_tokens(piece, max_tokens, count)
+      })
+      .collect()
+}
+
+fn extract_header_line(text: &str) -> Option<&str> {
+   text.lines().map(str::trim).find(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+   use std::fmt::Write;
+
+   use super::*;
+
+   #[test]
+   fn split_by_chars_preserves_chunk_type() {
+      let content = Str::from_string("a".repeat(MAX_CHARS + 10));
+      let chunk = Chunk::new(content, 0, 1, ChunkType::Function, &[]);
+
+      let pieces = split_by_chars(chunk);
+
+      assert!(!pieces.is_empty());
+      assert!(
+         pieces
+            .iter()
+            .all(|c| c.chunk_type == Some(ChunkType::Function))
+      );
+   }
+
+   #[test]
+   fn split_if_too_big_keeps_trailing_lines() {
+      let content = Str::from_string((0..131).fold(String::new(), |mut s, i| {
+         let _ = writeln!(s, "line {i}");
+         s
+      }));
+      let chunk = Chunk::new(content, 0, 131, ChunkType::Block, &[]);
+
+      let sub_chunks = split_if_too_big(chunk);
+
+      assert!(
+         sub_chunks
+            .iter()
+            .any(|c| c.content.as_str().contains("line 130"))
+      );
+   }
+
+   #[test]
+   fn split_if_too_big_prefers_blank_line_boundaries() {
+      let mut text = String::new();
+      for f in 0..12 {
+         let _ = writeln!(text, "fn f{f}() {{");
+         for i in 0..10 {
+            let _ = writeln!(text, "    let x{i} = {i};");
+         }
+         text.push_str("}\n\n");
+      }
+      let line_count = text.lines().count();
+      let chunk = Chunk::new(Str::from_string(text), 0, line_count, ChunkType::Block, &[]);
+
+      let sub_chunks = split_if_too_big(chunk);
+
+      assert!(sub_chunks.len() > 1);
+      for sc in &sub_chunks {
+         let last = sc.content.as_str().lines().last().unwrap();
+         assert!(last.trim().is_empty() || last.trim() == "}", "chunk ended mid-block: {last:?}");
+      }
+   }
+
+   #[test]
+   fn limit_tokens_keeps_every_line_within_budget() {
+      let text = (0..40).fold(String::new(), |mut s, i| {
+         let _ = writeln!(s, "let value_{i} = compute({i});");
+         s
+      });
+      let words = |t: &str| t.split_whitespace().count();
+      let chunk = Chunk::new(Str::from_string(text), 10, 50, ChunkType::Function, &[]);
+
+      let pieces = limit_tokens(vec![chunk], 30, &words);
+
+      assert!(pieces.len() > 1);
+      assert!(pieces.iter().all(|p| words(&p.content) <= 30));
+      let joined: Vec<&str> = pieces
+         .iter()
+         .flat_map(|p| p.content.as_str().lines())
+         .collect();
+      assert_eq!(joined.len(), 40);
+      assert_eq!(joined[39], "let value_39 = compute(39);");
+      assert_eq!(pieces[0].start_line, 10);
+      assert_eq!(pieces.last().unwrap().end_line, 50);
+   }
+
+   #[test]
+   fn limit_tokens_splits_long_lines_at_whitespace() {
+      let text = "word ".repeat(100);
+      let words = |t: &str| t.split_whitespace().count();
+      let chunk = Chunk::new(Str::from_string(text), 0, 1, ChunkType::Other, &[]);
+
+      let pieces = limit_tokens(vec![chunk], 16, &words);
+
+      assert!(pieces.iter().all(|p| words(&p.content) <= 16));
+      assert_eq!(pieces.iter().map(|p| words(&p.content)).sum::<usize>(), 100);
+   }
+
+   #[test]
+   fn split_by_chars_avoids_continuation_starts() {
+      let mut text = String::new();
+      while text.len() < MAX_CHARS * 3 {
+         text.push_str("if cond {\n    do_something_long_enough();\n} else {\n    other();\n}\n");
+      }
+      let chunk = Chunk::new(Str::from_string(text), 0, 0, ChunkType::Function, &[]);
+
+      let pieces = split_by_chars(chunk);
+
+      assert!(pieces.len() > 1);
+      for piece in &pieces {
+         let first = piece.content.as_str().lines().next().unwrap();
+         assert!(!is_continuation(first), "chunk starts mid-statement: {first:?}");
+         assert!(piece.content.len() <= MAX_CHARS);
+      }
+   }
+}
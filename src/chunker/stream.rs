@@ -0,0 +1,163 @@
+//! Chunking of large files a segment of lines at a time.
+//!
+//! Files over `stream_chunk_threshold_kb` are never loaded whole: they skip
+//! tree-sitter, which needs the full source, and are split by
+//! [`FallbackChunker`] one segment at a time, so memory stays bounded by the
+//! segment size and the chunks kept.
+
+use std::{
+   fs::File,
+   io::{self, BufRead, BufReader, Read},
+   path::Path,
+};
+
+use encoding_rs::Encoding;
+
+use super::{FallbackChunker, OVERLAP_LINES, split};
+use crate::{Str, types::Chunk};
+
+/// Lines read into memory at once.
+const SEGMENT_LINES: usize = 4096;
+
+/// Bytes read into memory at once; longer lines are cut at this length.
+const SEGMENT_BYTES: usize = 1024 * 1024;
+
+/// Bytes kept from the start of the file for its anchor chunk.
+const HEAD_BYTES: usize = 64 * 1024;
+
+/// Chunks of a file read from disk.
+#[derive(Debug)]
+pub struct StreamedChunks {
+   pub chunks:    Vec<Chunk>,
+   /// Start of the file, for its anchor chunk.
+   pub head:      Str,
+   /// Whether chunking stopped at `max_chunks` before the end of the file.
+   pub truncated: bool,
+}
+
+/// Chunks the file at `path` by lines, reading it a segment at a time and
+/// decoding it from `encoding`, which must be ASCII-compatible. Stops after
+/// `max_chunks` chunks, or reads the whole file when it is 0.
+pub fn chunk_file(
+   path: &Path,
+   encoding: &'static Encoding,
+   max_chunks: usize,
+) -> io::Result<StreamedChunks> {
+   chunk_reader(BufReader::new(File::open(path)?), path, encoding, max_chunks)
+}
+
+fn chunk_reader(
+   mut reader: impl BufRead,
+   path: &Path,
+   encoding: &'static Encoding,
+   max_chunks: usize,
+) -> io::Result<StreamedChunks> {
+   let mut chunks = Vec::new();
+   let mut head = String::new();
+   let mut segment: Vec<String> = Vec::new();
+   let mut segment_start = 0;
+   let mut line = Vec::new();
+
+   loop {
+      // Consecutive segments share `OVERLAP_LINES` lines, like the windows
+      // within one segment.
+      let mut segment_bytes: usize = segment.iter().map(String::len).sum();
+      let mut fresh = 0;
+      let mut eof = false;
+      while segment.len() < SEGMENT_LINES && segment_bytes < SEGMENT_BYTES {
+         line.clear();
+         if (&mut reader)
+            .take(SEGMENT_BYTES as u64)
+            .read_until(b'\n', &mut line)?
+            == 0
+         {
+            eof = true;
+            break;
+         }
+         let (text, _) = encoding.decode_with_bom_removal(&line);
+         let text = text.trim_end_matches(['\n', '\r']);
+         if head.len() < HEAD_BYTES {
+            head.push_str(text);
+            head.push('\n');
+         }
+         segment_bytes += text.len();
+         segment.push(text.to_owned());
+         fresh += 1;
+      }
+      if fresh == 0 {
+         return Ok(StreamedChunks { chunks, head: head.into(), truncated: false });
+      }
+
+      let content = Str::from_string(segment.join("\n"));
+      let pieces = split::limit_size(FallbackChunker::simple_chunk(&content, path));
+      for mut chunk in pieces {
+         if max_chunks > 0 && chunks.len() == max_chunks {
+            return Ok(StreamedChunks { chunks, head: head.into(), truncated: true });
+         }
+         // Copied so the chunk does not keep the whole segment alive.
+         chunk.content = Str::copy_from_str(chunk.content.as_str());
+         chunk.start_line += segment_start;
+         chunk.end_line += segment_start;
+         chunks.push(chunk);
+      }
+      if eof {
+         return Ok(StreamedChunks { chunks, head: head.into(), truncated: false });
+      }
+
+      let carried = segment.len() - OVERLAP_LINES.min(segment.len());
+      segment.drain(..carried);
+      segment_start += carried;
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::fmt::Write;
+
+   use encoding_rs::UTF_8;
+
+   use super::*;
+
+   fn numbered_lines(count: usize) -> String {
+      (0..count).fold(String::new(), |mut text, i| {
+         writeln!(text, "line {i}").unwrap();
+         text
+      })
+   }
+
+   #[test]
+   fn chunks_span_segments_with_file_line_numbers() {
+      let text = numbered_lines(SEGMENT_LINES * 2 + 100);
+      let path = Path::new("dump.sql");
+
+      let streamed = chunk_reader(text.as_bytes(), path, UTF_8, 0).unwrap();
+
+      assert!(!streamed.truncated);
+      assert!(streamed.head.as_str().starts_with("line 0\n"));
+      let last = streamed.chunks.last().unwrap();
+      assert!(
+         last
+            .content
+            .as_str()
+            .ends_with(&format!("line {}", SEGMENT_LINES * 2 + 99))
+      );
+      assert_eq!(last.end_line, SEGMENT_LINES * 2 + 100);
+      for chunk in &streamed.chunks {
+         let first = chunk.content.as_str().lines().next().unwrap();
+         assert_eq!(first, format!("line {}", chunk.start_line));
+      }
+   }
+
+   #[test]
+   fn stops_at_chunk_limit() {
+      let text = numbered_lines(10_000);
+
+      let streamed = chunk_reader(text.as_bytes(), Path::new("app.log"), UTF_8, 5).unwrap();
+      assert_eq!(streamed.chunks.len(), 5);
+      assert!(streamed.truncated);
+
+      let streamed = chunk_reader("a\nb\n".as_bytes(), Path::new("app.log"), UTF_8, 1).unwrap();
+      assert_eq!(streamed.chunks.len(), 1);
+      assert!(!streamed.truncated);
+   }
+}
@@ -0,0 +1,79 @@
+//! Token budget for chunks.
+//!
+//! The dense model only reads its first `dense_max_length` tokens, so a chunk
+//! within [`MAX_CHARS`](super::MAX_CHARS) can still lose its tail to
+//! truncation. [`TokenLimit`] counts tokens with the dense model's tokenizer
+//! and splits chunks that would be cut, so every line reaches an embedding.
+
+use tokenizers::Tokenizer;
+use tokio::sync::OnceCell;
+
+use super::split;
+use crate::{
+   config::{self, LongChunks},
+   embed::CandleEmbedder,
+   types::Chunk,
+};
+
+/// Tokens the tokenizer adds around every input (`[CLS]` and `[SEP]`).
+const SPECIAL_TOKENS: usize = 2;
+
+/// Splits chunks longer than the dense model reads, using its tokenizer,
+/// which is loaded on first use.
+#[derive(Debug, Default)]
+pub struct TokenLimit {
+   tokenizer: OnceCell<Option<Tokenizer>>,
+}
+
+impl TokenLimit {
+   pub fn new() -> Self {
+      Self::default()
+   }
+
+   /// The dense model's tokenizer, or `None` if it cannot be loaded, in
+   /// which case chunks are left to the character limit.
+   async fn tokenizer(&self) -> Option<&Tokenizer> {
+      self
+         .tokenizer
+         .get_or_init(|| async {
+            let model = &config::get().dense_model;
+            let path = CandleEmbedder::download_model(model)
+               .await
+               .inspect_err(|e| tracing::debug!("no tokenizer to limit chunks with: {e}"))
+               .ok()?;
+            let mut tokenizer = Tokenizer::from_file(path.join("tokenizer.json"))
+               .inspect_err(|e| tracing::debug!("failed to load the {model} tokenizer: {e}"))
+               .ok()?;
+            // Count every token, not just those the tokenizer would keep
+            tokenizer.with_truncation(None).ok()?;
+            Some(tokenizer)
+         })
+         .await
+         .as_ref()
+   }
+
+   /// Splits the chunks the dense model would truncate, unless
+   /// `long_chunks = "windows"` has the embedder read them whole.
+   pub async fn apply(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
+      if config::get().long_chunks == LongChunks::Windows {
+         return chunks;
+      }
+      let Some(tokenizer) = self.tokenizer().await else {
+         return chunks;
+      };
+      let max_tokens = config::get()
+         .dense_max_length
+         .saturating_sub(SPECIAL_TOKENS)
+         .max(1);
+      let count = |text: &str| {
+         // Every token covers at least one byte, so short text always fits
+         if text.len() <= max_tokens {
+            return text.len();
+         }
+         tokenizer
+            .encode_fast(text, false)
+            .map_or(0, |encoding| encoding.len())
+      };
+      split::limit_tokens(chunks, max_tokens, &count)
+   }
+}
@@ -0,0 +1,302 @@
+//! Tree-sitter based chunking.
+
+use std::{borrow::Cow, path::Path, slice, sync::Arc};
+
+use tree_sitter::Language;
+
+use super::{Chunker, split};
+use crate::{
+   Str,
+   chunker::anchor::CONST_EXPORT_REGEX,
+   error::{ChunkerError, Result},
+   grammar::GrammarManager,
+   types::{Chunk, ChunkType},
+};
+
+/// Splits source code into semantic chunks using tree-sitter grammars.
+///
+/// Extracts definitions (functions, classes, types) from parsed syntax trees
+/// and splits them into manageable chunks for code search. Declines files
+/// without a grammar so a [`ChainChunker`](super::ChainChunker) can hand
+/// them to the next chunker.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct TreeSitterChunker(Arc<GrammarManager>);
+
+impl TreeSitterChunker {
+   async fn get_language(&self, path: &Path, content: &str) -> Result<Option<Language>> {
+      self
+         .0
+         .get_language_for_path(path, content)
+         .await
+         .inspect_err(|e| {
+            tracing::warn!("failed to load language for {}: {}", path.display(), e);
+         })
+   }
+
+   /// Parses `content` and extracts its definitions, returning `None` when
+   /// no grammar is available for `path`.
+   async fn parse(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>> {
+      let Some(language) = self.get_language(path, content.as_str()).await? else {
+         return Ok(None);
+      };
+
+      let (mut parser, store) = self.0.create_parser_with_store()?;
+      parser
+         .set_wasm_store(store)
+         .map_err(ChunkerError::SetWasmStore)?;
+
+      parser
+         .set_language(&language)
+         .map_err(ChunkerError::SetLanguage)?;
+
+      let tree = parser
+         .parse(content.as_str(), None)
+         .ok_or(ChunkerError::ParseFailed)?;
+
+      let root = tree.root_node();
+      let file_context: Str = format!("File: {}", path.display()).into();
+
+      let mut chunks = Vec::new();
+      let mut block_chunks = Vec::new();
+      let mut cursor_index = 0;
+      let mut cursor_row = 0;
+      let mut saw_definition = false;
+
+      let mut cursor = root.walk();
+      for child in root.named_children(&mut cursor) {
+         Self::visit_node(
+            &child,
+            content,
+            slice::from_ref(&file_context),
+            &mut chunks,
+            &mut saw_definition,
+         );
+
+         let effective = Self::unwrap_export(&child);
+         let is_definition = Self::is_definition_node(&effective, content.as_str());
+
+         if is_definition {
+            if child.start_byte() > cursor_index {
+               let gap_text = content.slice(cursor_index..child.start_byte());
+               if !gap_text.trim().is_empty() {
+                  block_chunks.push(Chunk::new(
+                     gap_text,
+                     cursor_row,
+                     child.start_position().row,
+                     ChunkType::Block,
+                     slice::from_ref(&file_context),
+                  ));
+               }
+            }
+
+            cursor_index = child.end_byte();
+            cursor_row = child.end_position().row;
+         }
+      }
+
+      if cursor_index < content.len() {
+         let tail_text = content.slice(cursor_index..);
+         if !tail_text.trim().is_empty() {
+            block_chunks.push(Chunk::new(
+               tail_text,
+               cursor_row,
+               root.end_position().row,
+               ChunkType::Block,
+               &[file_context],
+            ));
+         }
+      }
+
+      if !saw_definition {
+         return Ok(Some(Vec::new()));
+      }
+
+      let mut combined = block_chunks;
+      combined.extend(chunks);
+      combined.sort_by(|a, b| {
+         a.start_line
+            .cmp(&b.start_line)
+            .then(a.end_line.cmp(&b.end_line))
+      });
+
+      Ok(Some(combined))
+   }
+
+   fn visit_node(
+      node: &tree_sitter::Node,
+      content: &Str,
+      stack: &[Str],
+      chunks: &mut Vec<Chunk>,
+      saw_definition: &mut bool,
+   ) {
+      let effective = Self::unwrap_export(node);
+      let is_definition = Self::is_definition_node(&effective, content.as_str());
+      let mut stack = Cow::Borrowed(stack);
+
+      if is_definition {
+         *saw_definition = true;
+         let label = Self::label_for_node(&effective, content.as_str());
+         if let Some(label) = label {
+            stack.to_mut().push(label.into());
+         }
+
+         let node_text = content.slice(effective.start_byte()..effective.end_byte());
+         chunks.push(Chunk::new(
+            node_text,
+            effective.start_position().row,
+            effective.end_position().row,
+            Self::classify_node(&effective),
+            stack.as_ref(),
+         ));
+      }
+
+      let mut cursor = effective.walk();
+      for child in effective.named_children(&mut cursor) {
+         Self::visit_node(&child, content, &stack, chunks, saw_definition);
+      }
+   }
+
+   fn unwrap_export<'a>(node: &'a tree_sitter::Node) -> tree_sitter::Node<'a> {
+      if node.kind() == "export_statement" && node.named_child_count() > 0 {
+         return node.named_child(0).unwrap();
+      }
+      *node
+   }
+
+   fn is_definition_node(node: &tree_sitter::Node, content: &str) -> bool {
+      let kind = node.kind();
+      matches!(
+         kind,
+         // JavaScript/TypeScript/Go/C/C++/Java
+         "function_declaration"
+            | "function_definition"
+            | "method_definition"
+            | "method_declaration"
+            | "class_declaration"
+            | "class_definition"
+            | "interface_declaration"
+            | "type_alias_declaration"
+            | "type_declaration"
+            // Rust
+            | "function_item"
+            | "impl_item"
+            | "struct_item"
+            | "enum_item"
+            | "trait_item"
+            | "mod_item"
+            | "type_item"
+            | "const_item"
+            | "static_item"
+            // Python
+            | "function_def"
+            | "class_def"
+            | "async_function_def"
+      ) || Self::is_top_level_value_def(node, content)
+   }
+
+   fn is_top_level_value_def(node: &tree_sitter::Node, content: &str) -> bool {
+      let kind = node.kind();
+      if kind != "lexical_declaration" && kind != "variable_declaration" {
+         return false;
+      }
+
+      if let Some(parent) = node.parent() {
+         let parent_kind = parent.kind();
+         if !matches!(parent_kind, "program" | "module" | "source_file" | "class_body") {
+            return false;
+         }
+      }
+
+      let text = &content[node.start_byte()..node.end_byte()];
+
+      if text.contains("=>") {
+         return true;
+      }
+      if text.contains("function ") {
+         return true;
+      }
+      if text.contains("class ") {
+         return true;
+      }
+
+      if CONST_EXPORT_REGEX.is_match(text) {
+         return true;
+      }
+
+      false
+   }
+
+   fn classify_node(node: &tree_sitter::Node) -> ChunkType {
+      let kind = node.kind();
+      if kind.contains("class") {
+         ChunkType::Class
+      } else if kind.contains("interface") {
+         ChunkType::Interface
+      } else if kind.contains("type_alias") || kind.contains("type_declaration") {
+         ChunkType::TypeAlias
+      } else {
+         ChunkType::Other
+      }
+   }
+
+   fn get_node_name<'a>(node: &tree_sitter::Node, content: &'a str) -> Option<&'a str> {
+      if let Some(name_node) = node.child_by_field_name("name") {
+         return Some(&content[name_node.start_byte()..name_node.end_byte()]);
+      }
+
+      if let Some(property_node) = node.child_by_field_name("property") {
+         return Some(&content[property_node.start_byte()..property_node.end_byte()]);
+      }
+
+      if let Some(identifier_node) = node.child_by_field_name("identifier") {
+         return Some(&content[identifier_node.start_byte()..identifier_node.end_byte()]);
+      }
+
+      let mut cursor = node.walk();
+      for child in node.named_children(&mut cursor) {
+         let child_kind = child.kind();
+         if matches!(
+            child_kind,
+            "identifier" | "property_identifier" | "type_identifier" | "field_identifier"
+         ) {
+            return Some(&content[child.start_byte()..child.end_byte()]);
+         }
+
+         if child_kind == "variable_declarator"
+            && let Some(name) = Self::get_node_name(&child, content)
+         {
+            return Some(name);
+         }
+      }
+
+      None
+   }
+
+   fn label_for_node(node: &tree_sitter::Node, content: &str) -> Option<String> {
+      let name = Self::get_node_name(node, content);
+      let kind = node.kind();
+
+      let (prefix, default) = if kind.contains("class") {
+         ("Class: ", "<anonymous class>")
+      } else if kind.contains("method") {
+         ("Method: ", "<anonymous method>")
+      } else if kind.contains("interface") {
+         ("Interface: ", "<anonymous interface>")
+      } else if kind.contains("type_alias") || kind.contains("type_declaration") {
+         ("Type: ", "<anonymous type>")
+      } else if kind.contains("function") || Self::is_top_level_value_def(node, content) {
+         ("Function: ", "<anonymous function>")
+      } else {
+         return name.map(|n| format!("Symbol: {n}"));
+      };
+      Some(format!("{prefix}{}", name.unwrap_or(default)))
+   }
+}
+
+#[async_trait::async_trait]
+impl Chunker for TreeSitterChunker {
+   async fn try_chunk(&self, content: &Str, path: &Path) -> Result<Option<Vec<Chunk>>> {
+      Ok(self.parse(content, path).await?.map(split::limit_size))
+   }
+}
@@ -0,0 +1,307 @@
+//! Performance benchmark command.
+//!
+//! Times each stage of indexing on a sample of the files under a directory,
+//! and search latency on its existing index, printing a report that can be
+//! compared across builds and machines to catch performance regressions.
+
+use std::{
+   fs,
+   path::{Path, PathBuf},
+   sync::Arc,
+   time::{Duration, Instant},
+};
+
+use console::style;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+   Result, Str,
+   chunker::{ChainChunker, Chunker},
+   config,
+   file::{self, FileSystem, LocalFileSystem},
+   git,
+   meta::{FileHash, MetaStore},
+   search::{Retrieval, SearchEngine, SearchFilter},
+   store::Store,
+   types::{Chunk, VectorRecord},
+   util,
+};
+
+/// Queries timed against the index, cycled through when more are asked for.
+const QUERIES: &[&str] = &[
+   "error handling",
+   "parse the configuration file",
+   "open a database connection",
+   "retry a failed request",
+   "read a file into memory",
+   "spawn a background task",
+   "validate user input",
+   "serialize to json",
+   "cache lookup and eviction",
+   "parse command line arguments",
+];
+/// Results fetched per timed query, as for a default `smgrep search`.
+const SEARCH_LIMIT: usize = 10;
+
+/// Command-line options for the bench command.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+   /// Files chunked, embedded and inserted.
+   pub files:   usize,
+   /// Searches timed in each mode.
+   pub queries: usize,
+   pub json:    bool,
+}
+
+/// Items processed by a stage and the time it took.
+#[derive(Debug, Serialize)]
+struct Throughput {
+   items:   usize,
+   secs:    f64,
+   per_sec: f64,
+}
+
+impl Throughput {
+   fn new(items: usize, elapsed: Duration) -> Self {
+      let secs = elapsed.as_secs_f64();
+      Self { items, secs, per_sec: if secs > 0.0 { items as f64 / secs } else { 0.0 } }
+   }
+}
+
+/// Latency of a series of searches.
+#[derive(Debug, Serialize)]
+struct Latency {
+   queries: usize,
+   p50_ms:  f64,
+   p95_ms:  f64,
+}
+
+impl Latency {
+   fn new(mut samples: Vec<Duration>) -> Self {
+      samples.sort_unstable();
+      Self {
+         queries: samples.len(),
+         p50_ms:  percentile(&samples, 0.50).as_secs_f64() * 1000.0,
+         p95_ms:  percentile(&samples, 0.95).as_secs_f64() * 1000.0,
+      }
+   }
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+   store_id:      String,
+   device:        String,
+   files:         usize,
+   bytes:         u64,
+   /// Chunks made per second.
+   chunking:      Throughput,
+   /// Chunks embedded per second.
+   embedding:     Throughput,
+   /// Chunks written to the store per second.
+   insert:        Throughput,
+   /// `None` when the directory has not been indexed.
+   search:        Option<Latency>,
+   search_rerank: Option<Latency>,
+}
+
+/// Executes the bench command.
+pub async fn execute(
+   path: Option<PathBuf>,
+   options: BenchOptions,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = path.map_or_else(std::env::current_dir, Ok)?;
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&root), Ok)?;
+   MetaStore::load(&resolved_store_id)?.ensure_model_matches(&resolved_store_id)?;
+
+   let files: Vec<(PathBuf, Str)> = LocalFileSystem::new()
+      .get_files(&root)?
+      .filter_map(|path| {
+         let bytes = fs::read(&path).ok()?;
+         Some((path, file::encoding::decode(&bytes, None).0))
+      })
+      .take(options.files)
+      .collect();
+   let bytes = files.iter().map(|(_, content)| content.len() as u64).sum();
+   if !options.json {
+      eprintln!("{}", style(format!("Benchmarking {} files...", files.len())).dim());
+   }
+
+   let chunker = ChainChunker::default();
+   let started = Instant::now();
+   let mut chunks: Vec<(Arc<PathBuf>, Chunk)> = Vec::new();
+   for (path, content) in &files {
+      let path = Arc::new(path.clone());
+      for chunk in chunker.chunk(content, &path).await? {
+         chunks.push((Arc::clone(&path), chunk));
+      }
+   }
+   let chunking = Throughput::new(chunks.len(), started.elapsed());
+
+   let (embedder, store) = super::open_backends()?;
+
+   // Loads the models, which is not part of the throughput
+   embedder
+      .compute_hybrid(&[Str::from_static("warmup")])
+      .await?;
+   let texts: Vec<Str> = chunks
+      .iter()
+      .map(|(_, chunk)| chunk.content.clone())
+      .collect();
+   let started = Instant::now();
+   let mut embeddings = Vec::with_capacity(texts.len());
+   for batch in texts.chunks(config::get().batch_size().max(1)) {
+      embeddings.extend(embedder.compute_hybrid(batch).await?);
+   }
+   let embedding = Throughput::new(embeddings.len(), started.elapsed());
+
+   let records: Vec<VectorRecord> = chunks
+      .into_iter()
+      .zip(embeddings)
+      .enumerate()
+      .map(|(i, ((path, chunk), embedding))| VectorRecord {
+         id: format!("{}:{i}", path.display()),
+         hash: FileHash::default(),
+         chunk_hash: FileHash::sum(chunk.content.as_bytes()),
+         path,
+         content: chunk.content,
+         start_line: chunk.start_line as u32,
+         end_line: chunk.end_line as u32,
+         chunk_index: Some(i as u32),
+         is_anchor: Some(false),
+         chunk_type: chunk.chunk_type,
+         context_prev: None,
+         context_next: None,
+         vector: embedding.dense,
+         colbert: embedding.colbert,
+         colbert_scale: embedding.colbert_scale,
+         indexed_at: Some(util::unix_now()),
+         crate_name: None,
+         bridges: None,
+         language: None,
+      })
+      .collect();
+
+   // Inserts go to a scratch store so the real index is left untouched
+   let scratch_id = format!("{resolved_store_id}-bench");
+   let count = records.len();
+   let started = Instant::now();
+   let inserted = store.insert_batch(&scratch_id, records).await;
+   let insert = Throughput::new(count, started.elapsed());
+   store.delete_store(&scratch_id).await?;
+   inserted?;
+
+   let (search, search_rerank) = if store.is_empty(&resolved_store_id).await? {
+      (None, None)
+   } else {
+      let engine = SearchEngine::new(store, Arc::clone(&embedder));
+      let cancel = util::cancel_on_ctrl_c();
+      let time = async |retrieval| {
+         time_searches(&engine, &resolved_store_id, retrieval, options.queries, &cancel).await
+      };
+      (
+         Some(time(Retrieval::default()).await?),
+         Some(time(Retrieval { rerank: true, ..Retrieval::default() }).await?),
+      )
+   };
+
+   let report = BenchReport {
+      store_id: resolved_store_id,
+      device: embedder.status().device,
+      files: files.len(),
+      bytes,
+      chunking,
+      embedding,
+      insert,
+      search,
+      search_rerank,
+   };
+   if options.json {
+      println!("{}", serde_json::to_string(&report)?);
+   } else {
+      print_report(&report, &root);
+   }
+   Ok(())
+}
+
+/// Times `count` searches of [`QUERIES`] after an untimed one.
+async fn time_searches(
+   engine: &SearchEngine,
+   store_id: &str,
+   retrieval: Retrieval,
+   count: usize,
+   cancel: &CancellationToken,
+) -> Result<Latency> {
+   let search = |query: &'static str| {
+      engine.search(store_id, query, SEARCH_LIMIT, 1, SearchFilter::default(), retrieval, cancel)
+   };
+   search(QUERIES[0]).await?;
+   let mut samples = Vec::with_capacity(count);
+   for query in QUERIES.iter().cycle().take(count) {
+      let started = Instant::now();
+      search(query).await?;
+      samples.push(started.elapsed());
+   }
+   Ok(Latency::new(samples))
+}
+
+/// The `p` quantile of ascending `samples`, by nearest rank.
+fn percentile(samples: &[Duration], p: f64) -> Duration {
+   if samples.is_empty() {
+      return Duration::ZERO;
+   }
+   let rank = (p * samples.len() as f64).ceil() as usize;
+   samples[rank.clamp(1, samples.len()) - 1]
+}
+
+fn print_report(report: &BenchReport, root: &Path) {
+   println!(
+      "\n{} {}\n",
+      style(format!("Benchmark: {}", root.display())).bold(),
+      style(format!("({}, {})", report.store_id, report.device)).dim()
+   );
+   let print_throughput = |stage: &str, stage_throughput: &Throughput| {
+      println!(
+         "   {stage:<15} {:>8.1} chunks/s   {}",
+         stage_throughput.per_sec,
+         style(format!("({} chunks in {:.2}s)", stage_throughput.items, stage_throughput.secs))
+            .dim()
+      );
+   };
+   print_throughput("Chunking", &report.chunking);
+   print_throughput("Embedding", &report.embedding);
+   print_throughput("Insert", &report.insert);
+   println!(
+      "   {:<15} {}",
+      "",
+      style(format!("{} files, {:.1} MB", report.files, report.bytes as f64 / 1e6)).dim()
+   );
+
+   let print_latency = |stage: &str, latency: Option<&Latency>| match latency {
+      Some(latency) => println!(
+         "   {stage:<15} p50 {:>7.1}ms   p95 {:>7.1}ms   {}",
+         latency.p50_ms,
+         latency.p95_ms,
+         style(format!("({} queries)", latency.queries)).dim()
+      ),
+      None => println!("   {stage:<15} {}", style("skipped: not indexed").dim()),
+   };
+   print_latency("Search", report.search.as_ref());
+   print_latency("Search+rerank", report.search_rerank.as_ref());
+   println!();
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn percentiles_by_nearest_rank() {
+      let samples: Vec<Duration> = (1..=20).map(Duration::from_millis).collect();
+      assert_eq!(percentile(&samples, 0.50), Duration::from_millis(10));
+      assert_eq!(percentile(&samples, 0.95), Duration::from_millis(19));
+      assert_eq!(percentile(&samples[..1], 0.95), Duration::from_millis(1));
+      assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+   }
+}
@@ -0,0 +1,314 @@
+//! Semantic lint gate for CI pipelines.
+//!
+//! Compares the code added since a base revision against the index and a
+//! policy file, exiting non-zero when new code closely duplicates existing
+//! code or matches a policy query.
+
+use std::{
+   fs,
+   path::{Path, PathBuf},
+};
+
+use console::style;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+   Result, Str,
+   error::Error,
+   git::{self, AddedHunk},
+   meta::MetaStore,
+   store::{SearchParams, Store},
+};
+
+/// Policy file picked up from the repository root when `--policy` is not
+/// given.
+pub const POLICY_FILE: &str = ".smgrep-policy.toml";
+
+/// Indexed matches fetched per hunk when looking for clones.
+const CLONE_CANDIDATES: usize = 5;
+
+/// Queries that new code must not match, read from a TOML policy file.
+#[derive(Debug, Default, Deserialize)]
+struct Policy {
+   #[serde(default, rename = "rule")]
+   rules: Vec<PolicyRule>,
+}
+
+/// A single `[[rule]]` entry of a policy file.
+#[derive(Debug, Deserialize)]
+struct PolicyRule {
+   name:      String,
+   query:     String,
+   /// Similarity at or above which added code violates the rule.
+   #[serde(default = "default_rule_threshold")]
+   threshold: f32,
+   /// Explanation printed alongside violations.
+   message:   Option<String>,
+}
+
+const fn default_rule_threshold() -> f32 {
+   0.6
+}
+
+impl Policy {
+   fn load(path: &Path) -> Result<Self> {
+      let text = fs::read_to_string(path)?;
+      toml::from_str(&text)
+         .map_err(|e| Error::InvalidPolicy { path: path.to_path_buf(), reason: e.to_string() })
+   }
+}
+
+/// Added code that failed a check.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Finding {
+   /// Closely duplicates code already in the index.
+   Clone {
+      path:         PathBuf,
+      start_line:   u32,
+      end_line:     u32,
+      similar_path: PathBuf,
+      similar_line: u32,
+      score:        f32,
+   },
+   /// Matches a policy rule's query.
+   Policy {
+      path:       PathBuf,
+      start_line: u32,
+      end_line:   u32,
+      rule:       String,
+      #[serde(skip_serializing_if = "Option::is_none")]
+      message:    Option<String>,
+      score:      f32,
+   },
+}
+
+/// Command-line options for the check command.
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+   /// Revision the working tree is compared against.
+   pub base:      String,
+   /// Similarity at or above which added code counts as a clone; `None`
+   /// disables the clone gate.
+   pub threshold: Option<f32>,
+   /// Policy file; defaults to [`POLICY_FILE`] at the repository root.
+   pub policy:    Option<PathBuf>,
+   /// Hunks with fewer non-blank lines are ignored.
+   pub min_lines: usize,
+   pub json:      bool,
+}
+
+/// Executes the check command, exiting with status 1 when any finding is
+/// reported.
+pub async fn execute(
+   path: Option<PathBuf>,
+   options: CheckOptions,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = path.map_or_else(std::env::current_dir, Ok)?;
+   let repo_root = git::get_repo_root(&root).unwrap_or_else(|| root.clone());
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&root), Ok)?;
+
+   let policy_path = options.policy.clone().or_else(|| {
+      let default = repo_root.join(POLICY_FILE);
+      default.is_file().then_some(default)
+   });
+   let policy = policy_path
+      .as_deref()
+      .map(Policy::load)
+      .transpose()?
+      .unwrap_or_default();
+
+   let hunks: Vec<AddedHunk> = git::added_hunks(&root, &options.base)?
+      .into_iter()
+      .filter(|h| h.content.lines().filter(|l| !l.trim().is_empty()).count() >= options.min_lines)
+      .collect();
+
+   let mut findings = Vec::new();
+   if !hunks.is_empty() && (options.threshold.is_some() || !policy.rules.is_empty()) {
+      MetaStore::load(&resolved_store_id)?.ensure_model_matches(&resolved_store_id)?;
+
+      let (embedder, store) = super::open_backends()?;
+
+      let texts: Vec<Str> = hunks.iter().map(|h| Str::from(h.content.clone())).collect();
+      let embeddings = embedder.compute_hybrid(&texts).await?;
+      let vectors: Vec<&[f32]> = embeddings.iter().map(|e| e.dense.as_slice()).collect();
+
+      if let Some(threshold) = options.threshold {
+         findings
+            .extend(find_clones(&*store, &resolved_store_id, &hunks, &vectors, threshold).await?);
+      }
+      for rule in &policy.rules {
+         let query = embedder.encode_query(&rule.query).await?;
+         findings.extend(match_rule(rule, &query.dense, &hunks, &vectors));
+      }
+   }
+
+   for finding in &mut findings {
+      let (Finding::Clone { path, .. } | Finding::Policy { path, .. }) = finding;
+      if let Ok(rel) = path.strip_prefix(&repo_root) {
+         *path = rel.to_path_buf();
+      }
+      if let Finding::Clone { similar_path, .. } = finding
+         && let Ok(rel) = similar_path.strip_prefix(&repo_root)
+      {
+         *similar_path = rel.to_path_buf();
+      }
+   }
+
+   if options.json {
+      println!("{}", serde_json::to_string(&findings)?);
+   } else {
+      print_findings(&findings, hunks.len(), &options.base);
+   }
+
+   if !findings.is_empty() {
+      std::process::exit(1);
+   }
+   Ok(())
+}
+
+/// Finds hunks whose nearest indexed neighbour, other than the hunk itself,
+/// scores at or above `threshold`.
+async fn find_clones(
+   store: &dyn Store,
+   store_id: &str,
+   hunks: &[AddedHunk],
+   vectors: &[&[f32]],
+   threshold: f32,
+) -> Result<Vec<Finding>> {
+   let no_colbert = Array2::zeros((0, 0));
+   let mut findings = Vec::new();
+
+   for (hunk, vector) in hunks.iter().zip(vectors) {
+      let response = store
+         .search(SearchParams {
+            store_id,
+            query_text: &hunk.content,
+            query_vector: vector,
+            query_colbert: &no_colbert,
+            limit: CLONE_CANDIDATES,
+            path_filter: None,
+            path_globs: None,
+            indexed_after: None,
+            crate_name: None,
+            rerank: false,
+            rerank_depth: None,
+            nprobes: None,
+            exact: false,
+            cancel: None,
+         })
+         .await?;
+
+      // The index may already contain the new code; skip matches against it.
+      let nearest = response.results.into_iter().find(|r| {
+         let end_line = r.start_line + r.num_lines;
+         !(r.path == hunk.path && r.start_line <= hunk.end_line && hunk.start_line <= end_line)
+            && !r.is_anchor.unwrap_or(false)
+      });
+
+      if let Some(nearest) = nearest
+         && nearest.score >= threshold
+      {
+         findings.push(Finding::Clone {
+            path:         hunk.path.clone(),
+            start_line:   hunk.start_line,
+            end_line:     hunk.end_line,
+            similar_path: nearest.path,
+            similar_line: nearest.start_line,
+            score:        nearest.score,
+         });
+      }
+   }
+
+   Ok(findings)
+}
+
+/// Reports every hunk whose similarity to the rule's query reaches its
+/// threshold.
+fn match_rule(
+   rule: &PolicyRule,
+   query: &[f32],
+   hunks: &[AddedHunk],
+   vectors: &[&[f32]],
+) -> Vec<Finding> {
+   hunks
+      .iter()
+      .zip(vectors)
+      .filter_map(|(hunk, vector)| {
+         let score: f32 = query.iter().zip(*vector).map(|(a, b)| a * b).sum();
+         (score >= rule.threshold).then(|| Finding::Policy {
+            path: hunk.path.clone(),
+            start_line: hunk.start_line,
+            end_line: hunk.end_line,
+            rule: rule.name.clone(),
+            message: rule.message.clone(),
+            score,
+         })
+      })
+      .collect()
+}
+
+/// Prints findings in human-readable form.
+fn print_findings(findings: &[Finding], checked: usize, base: &str) {
+   if findings.is_empty() {
+      println!("{} {checked} added hunk(s) since {base} passed", style("✓").green());
+      return;
+   }
+
+   for finding in findings {
+      match finding {
+         Finding::Clone { path, start_line, end_line, similar_path, similar_line, score } => {
+            println!(
+               "{} {}:{start_line}-{end_line} duplicates {}:{similar_line} {}",
+               style("clone").red().bold(),
+               style(path.display()).green(),
+               style(similar_path.display()).green(),
+               style(format!("(score: {score:.3})")).dim()
+            );
+         },
+         Finding::Policy { path, start_line, end_line, rule, message, score } => {
+            println!(
+               "{} {}:{start_line}-{end_line} matches rule {} {}",
+               style("policy").red().bold(),
+               style(path.display()).green(),
+               style(rule).bold(),
+               style(format!("(score: {score:.3})")).dim()
+            );
+            if let Some(message) = message {
+               println!("  {message}");
+            }
+         },
+      }
+   }
+   println!("\n{} finding(s) in {checked} added hunk(s) since {base}", findings.len());
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parses_policy_rules() {
+      let policy: Policy = toml::from_str(
+         r#"
+            [[rule]]
+            name = "no-raw-sql"
+            query = "building SQL queries with string formatting"
+            threshold = 0.7
+            message = "use the query builder"
+
+            [[rule]]
+            name = "no-unwrap-in-handlers"
+            query = "unwrap inside a request handler"
+         "#,
+      )
+      .unwrap();
+
+      assert_eq!(policy.rules.len(), 2);
+      assert_eq!(policy.rules[0].threshold, 0.7);
+      assert_eq!(policy.rules[1].threshold, default_rule_threshold());
+      assert_eq!(policy.rules[1].message, None);
+   }
+}
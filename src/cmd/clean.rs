@@ -3,9 +3,11 @@
 //! Removes both lance data and metadata for a store, ensuring a clean slate for
 //! re-indexing.
 
+use std::collections::BTreeSet;
+
 use console::style;
 
-use crate::{Result, config, git};
+use crate::{Result, config, git, index_lock::IndexLock, meta};
 
 pub fn execute(store_id: Option<String>, all: bool) -> Result<()> {
    if all {
@@ -26,11 +28,10 @@ pub fn execute(store_id: Option<String>, all: bool) -> Result<()> {
 }
 
 fn clean_store(store_id: &str) -> Result<()> {
-   // Delete metadata file
-   let meta_path = config::meta_dir().join(format!("{store_id}.json"));
-   if meta_path.exists() {
-      std::fs::remove_file(&meta_path)?;
-   }
+   let _lock = IndexLock::acquire(store_id)?;
+
+   // Delete metadata files
+   meta::delete_files(store_id)?;
 
    // Delete entire lance database directory (not just drop_table which leaves
    // fragments)
@@ -50,18 +51,23 @@ fn clean_all() -> Result<()> {
 
    // Clean stores found in meta directory
    if meta_dir.exists() {
+      let mut store_ids = BTreeSet::new();
       for entry in std::fs::read_dir(meta_dir)? {
          let entry = entry?;
          let path = entry.path();
-         if path.extension().is_some_and(|e| e == "json")
+         if path
+            .extension()
+            .is_some_and(|e| e == "json" || e == "sqlite")
             && let Some(stem) = path.file_stem()
          {
-            let store_id = stem.to_string_lossy();
-            println!("{}", style(format!("Cleaning: {store_id}")).dim());
-            clean_store(&store_id)?;
-            cleaned += 1;
+            store_ids.insert(stem.to_string_lossy().into_owned());
          }
       }
+      for store_id in store_ids {
+         println!("{}", style(format!("Cleaning: {store_id}")).dim());
+         clean_store(&store_id)?;
+         cleaned += 1;
+      }
    }
 
    // Also clean any orphaned data directories (no meta file)
@@ -73,8 +79,7 @@ fn clean_all() -> Result<()> {
             && let Some(name) = path.file_name()
          {
             let store_id = name.to_string_lossy();
-            let meta_path = meta_dir.join(format!("{store_id}.json"));
-            if !meta_path.exists() {
+            if !meta::exists(&store_id) {
                println!("{}", style(format!("Cleaning orphaned: {store_id}")).dim());
                let _ = std::fs::remove_dir_all(&path);
                cleaned += 1;
@@ -72,7 +72,7 @@ async fn wait_for_daemon(store_id: &str) -> Result<usock::Stream> {
 
 /// Attempts to connect to an existing daemon and verify version compatibility
 /// via handshake.
-async fn try_connect_existing(store_id: &str) -> Result<Option<usock::Stream>> {
+pub async fn try_connect_existing(store_id: &str) -> Result<Option<usock::Stream>> {
    match usock::Stream::connect(store_id).await {
       Ok(mut stream) => {
          if matches!(handshake(&mut stream).await, Ok(true)) {
@@ -104,10 +104,14 @@ pub async fn force_shutdown(existing: Option<usock::Stream>, store_id: &str) ->
    let mut buffer = SocketBuffer::new();
 
    if let Some(mut stream) = existing {
-      let _ = buffer.send(&mut stream, &Request::Shutdown).await;
+      let _ = buffer
+         .send(&mut stream, &Request::Shutdown { force: true })
+         .await;
       let _ = buffer.recv::<_, Response>(&mut stream).await;
    } else if let Ok(mut stream) = usock::Stream::connect(store_id).await {
-      let _ = buffer.send(&mut stream, &Request::Shutdown).await;
+      let _ = buffer
+         .send(&mut stream, &Request::Shutdown { force: true })
+         .await;
       let _ = buffer.recv::<_, Response>(&mut stream).await;
    }
 
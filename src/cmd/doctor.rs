@@ -8,8 +8,9 @@ use std::path::Path;
 use console::style;
 
 use crate::{
-   Result, config,
-   grammar::{GRAMMAR_URLS, GrammarManager},
+   Result, config, disk,
+   embed::{CandleEmbedder, candle::device_label},
+   grammar::{GRAMMAR_URLS, GrammarManager, builtin},
    util::{format_size, get_dir_size},
 };
 
@@ -66,10 +67,11 @@ pub fn execute() -> Result<()> {
       let available = gm.available_languages();
       let missing = gm.missing_languages();
 
-      for (lang, _) in GRAMMAR_URLS {
+      for (lang, ..) in GRAMMAR_URLS {
          let exists = available.clone().any(|l| &l == lang);
+         let builtin = builtin::LANGUAGES.contains(lang);
 
-         let symbol = if exists {
+         let symbol = if exists || builtin {
             style("✓").green()
          } else {
             style("○").yellow()
@@ -77,6 +79,8 @@ pub fn execute() -> Result<()> {
 
          let status = if exists {
             "installed".to_string()
+         } else if builtin {
+            "built in, will download on first use".to_string()
          } else {
             "will download on first use".to_string()
          };
@@ -104,6 +108,38 @@ pub fn execute() -> Result<()> {
    {
       println!("\n{} {}", style("Data directory size:").dim(), style(format_size(size)).cyan());
    }
+   if let Some(free) = disk::available_space(data) {
+      let reserve = cfg.min_free_space_mb * 1024 * 1024;
+      let free = if free < reserve {
+         style(format_size(free)).red()
+      } else {
+         style(format_size(free)).cyan()
+      };
+      println!("{} {free}", style("Free disk space:").dim());
+   }
+
+   let device = if cfg.disable_gpu {
+      "cpu (disable_gpu)".to_string()
+   } else {
+      device_label(&CandleEmbedder::select_device())
+   };
+   let compiled: Vec<&str> = [
+      ("cuda", candle_core::utils::cuda_is_available()),
+      ("metal", candle_core::utils::metal_is_available()),
+   ]
+   .into_iter()
+   .filter_map(|(name, available)| available.then_some(name))
+   .collect();
+   println!(
+      "\n{} {device} (device = {}; built with {})",
+      style("Device:").dim(),
+      cfg.device.as_str(),
+      if compiled.is_empty() {
+         "CPU only".to_string()
+      } else {
+         compiled.join(", ")
+      }
+   );
 
    println!(
       "\n{} {} {} | Rust: {}",
@@ -0,0 +1,290 @@
+//! Duplicate code detection command.
+//!
+//! Joins the store's chunks against each other by their dense embeddings and
+//! reports clusters of near-identical chunks, which are likely copy-pasted
+//! code. Candidate pairs come from random-hyperplane hashing, so the chunks
+//! are never compared all against all.
+
+use std::{
+   collections::HashMap,
+   path::{Path, PathBuf},
+};
+
+use console::style;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{
+   Result, git,
+   store::{self, ChunkVector, Store},
+};
+
+/// Hash tables chunks are bucketed in; a pair is compared when it shares a
+/// bucket in any of them.
+const TABLES: usize = 12;
+/// Hyperplanes, and so signature bits, per table.
+const BITS: usize = 12;
+/// Chunks each chunk is compared with in an oversized bucket.
+const MAX_BUCKET_SPAN: usize = 64;
+/// Seed of the hyperplanes, fixed so that runs agree.
+const SEED: u64 = 0x5347_5245_5044_5550;
+
+/// A chunk in a duplicate cluster.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Location {
+   path:       PathBuf,
+   start_line: u32,
+   end_line:   u32,
+}
+
+/// Chunks that are near-identical to each other.
+#[derive(Debug, Serialize)]
+struct Cluster {
+   /// Lowest similarity among the pairs that joined the cluster.
+   similarity: f32,
+   chunks:     Vec<Location>,
+}
+
+/// Executes the dupes command, printing clusters of chunks whose embeddings
+/// are at least `threshold` similar.
+pub async fn execute(
+   path: Option<PathBuf>,
+   threshold: f32,
+   max: usize,
+   json: bool,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = path.map_or_else(std::env::current_dir, Ok)?;
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&root), Ok)?;
+   let base = root.canonicalize().unwrap_or(root);
+
+   let store = store::open()?;
+   let mut chunks = store.chunk_vectors(&resolved_store_id).await?;
+   chunks.retain(|c| c.path.starts_with(&base));
+   let mut clusters = tokio::task::spawn_blocking(move || find_clusters(&chunks, threshold))
+      .await
+      .map_err(std::io::Error::other)?;
+   clusters.truncate(max);
+   for location in clusters.iter_mut().flat_map(|c| &mut c.chunks) {
+      location.path = relative(&location.path, &base);
+   }
+
+   if json {
+      println!("{}", serde_json::to_string(&clusters)?);
+      return Ok(());
+   }
+   if clusters.is_empty() {
+      println!("No duplicates found at similarity {threshold}");
+      return Ok(());
+   }
+
+   for (i, cluster) in clusters.iter().enumerate() {
+      println!(
+         "{} {} {}",
+         style(format!("{})", i + 1)).bold().cyan(),
+         style(format!("{} similar chunks", cluster.chunks.len())).bold(),
+         style(format!("(similarity ≥ {:.3})", cluster.similarity)).dim()
+      );
+      for chunk in &cluster.chunks {
+         println!(
+            "   {}:{}-{}",
+            style(chunk.path.display()).green(),
+            chunk.start_line + 1,
+            chunk.end_line + 1
+         );
+      }
+   }
+   Ok(())
+}
+
+/// `path` relative to `base` when it lies under it.
+fn relative(path: &Path, base: &Path) -> PathBuf {
+   path.strip_prefix(base).unwrap_or(path).to_path_buf()
+}
+
+/// Groups chunks joined by pairs at least `threshold` similar, largest and
+/// closest clusters first.
+fn find_clusters(chunks: &[ChunkVector], threshold: f32) -> Vec<Cluster> {
+   let Some(dim) = chunks.first().map(|c| c.vector.len()) else {
+      return Vec::new();
+   };
+   let unit: Vec<Vec<f32>> = chunks.iter().map(|c| normalized(&c.vector)).collect();
+   let planes = hyperplanes(dim);
+
+   let mut parent: Vec<usize> = (0..chunks.len()).collect();
+   // Lowest pair similarity in each cluster, by its root
+   let mut lowest: HashMap<usize, f32> = HashMap::new();
+   let pairs: Vec<(usize, usize, f32)> = planes
+      .par_chunks(BITS)
+      .flat_map_iter(|table| similar_pairs(chunks, &unit, table, threshold))
+      .collect();
+
+   for (a, b, similarity) in pairs {
+      let (root_a, root_b) = (find(&mut parent, a), find(&mut parent, b));
+      let low = [lowest.remove(&root_a), lowest.remove(&root_b)]
+         .into_iter()
+         .flatten()
+         .fold(similarity, f32::min);
+      parent[root_b] = root_a;
+      lowest.insert(root_a, low);
+   }
+
+   let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+   for i in 0..chunks.len() {
+      let root = find(&mut parent, i);
+      members.entry(root).or_default().push(i);
+   }
+   let mut clusters: Vec<Cluster> = members
+      .into_iter()
+      .filter(|(_, members)| members.len() > 1)
+      .map(|(root, members)| {
+         let mut chunks: Vec<Location> = members
+            .into_iter()
+            .map(|i| Location {
+               path:       chunks[i].path.clone(),
+               start_line: chunks[i].start_line,
+               end_line:   chunks[i].end_line,
+            })
+            .collect();
+         chunks.sort_by(|a, b| (&a.path, a.start_line).cmp(&(&b.path, b.start_line)));
+         Cluster { similarity: lowest.get(&root).copied().unwrap_or(1.0), chunks }
+      })
+      .collect();
+   clusters.sort_by(|a, b| {
+      b.chunks
+         .len()
+         .cmp(&a.chunks.len())
+         .then(b.similarity.total_cmp(&a.similarity))
+         .then_with(|| a.chunks[0].path.cmp(&b.chunks[0].path))
+   });
+   clusters
+}
+
+/// Pairs of chunks that share a bucket of the table hashed by `planes` and
+/// are at least `threshold` similar.
+fn similar_pairs(
+   chunks: &[ChunkVector],
+   unit: &[Vec<f32>],
+   planes: &[Vec<f32>],
+   threshold: f32,
+) -> Vec<(usize, usize, f32)> {
+   let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+   for (i, vector) in unit.iter().enumerate() {
+      buckets
+         .entry(signature(vector, planes))
+         .or_default()
+         .push(i);
+   }
+   let mut pairs = Vec::new();
+   for bucket in buckets.values() {
+      for (n, &a) in bucket.iter().enumerate() {
+         for &b in bucket.iter().skip(n + 1).take(MAX_BUCKET_SPAN) {
+            let similarity = dot(&unit[a], &unit[b]);
+            if similarity >= threshold && !overlaps(&chunks[a], &chunks[b]) {
+               pairs.push((a, b, similarity));
+            }
+         }
+      }
+   }
+   pairs
+}
+
+/// Root of `i` in the union-find forest, compressing the path to it.
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+   while parent[i] != i {
+      parent[i] = parent[parent[i]];
+      i = parent[i];
+   }
+   i
+}
+
+/// Whether two chunks cover the same lines of one file, as the pieces of a
+/// split chunk can.
+fn overlaps(a: &ChunkVector, b: &ChunkVector) -> bool {
+   a.path == b.path && a.start_line.max(b.start_line) <= a.end_line.min(b.end_line)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+   a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+   let norm = dot(vector, vector).sqrt();
+   if norm == 0.0 {
+      return vector.to_vec();
+   }
+   vector.iter().map(|x| x / norm).collect()
+}
+
+/// Bits telling which side of each hyperplane `vector` lies on.
+fn signature(vector: &[f32], planes: &[Vec<f32>]) -> u64 {
+   planes
+      .iter()
+      .enumerate()
+      .filter(|(_, plane)| dot(vector, plane) >= 0.0)
+      .fold(0, |bits, (i, _)| bits | (1 << i))
+}
+
+/// `TABLES * BITS` pseudo-random hyperplanes through the origin.
+fn hyperplanes(dim: usize) -> Vec<Vec<f32>> {
+   let mut state = SEED;
+   let mut next = move || {
+      // splitmix64
+      state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+      let mut z = state;
+      z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+      z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+      z ^ (z >> 31)
+   };
+   (0..TABLES * BITS)
+      .map(|_| {
+         (0..dim)
+            .map(|_| (next() >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+            .collect()
+      })
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn chunk(path: &str, start_line: u32, vector: &[f32]) -> ChunkVector {
+      ChunkVector {
+         path: PathBuf::from(path),
+         start_line,
+         end_line: start_line + 9,
+         vector: vector.to_vec(),
+      }
+   }
+
+   #[test]
+   fn clusters_near_identical_chunks() {
+      let chunks = [
+         chunk("a.rs", 0, &[1.0, 0.0, 0.0, 0.0]),
+         chunk("b.rs", 20, &[0.99, 0.01, 0.0, 0.0]),
+         chunk("c.rs", 5, &[2.0, 0.0, 0.0, 0.01]),
+         chunk("d.rs", 0, &[0.0, 1.0, 0.0, 0.0]),
+         chunk("e.rs", 0, &[0.0, 0.0, 1.0, 0.0]),
+      ];
+      let clusters = find_clusters(&chunks, 0.95);
+
+      assert_eq!(clusters.len(), 1);
+      let paths: Vec<&Path> = clusters[0]
+         .chunks
+         .iter()
+         .map(|c| c.path.as_path())
+         .collect();
+      assert_eq!(paths, [Path::new("a.rs"), Path::new("b.rs"), Path::new("c.rs")]);
+      assert!(clusters[0].similarity >= 0.95);
+   }
+
+   #[test]
+   fn overlapping_pieces_of_one_chunk_are_not_duplicates() {
+      let chunks = [chunk("a.rs", 0, &[1.0, 0.0]), chunk("a.rs", 5, &[1.0, 0.0])];
+      assert!(find_clusters(&chunks, 0.95).is_empty());
+
+      let apart = [chunk("a.rs", 0, &[1.0, 0.0]), chunk("a.rs", 40, &[1.0, 0.0])];
+      assert_eq!(find_clusters(&apart, 0.95).len(), 1);
+   }
+}
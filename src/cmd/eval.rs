@@ -0,0 +1,133 @@
+//! Retrieval evaluation command.
+//!
+//! Runs every query of an eval suite through the same sync and search
+//! pipeline as `smgrep search` and reports recall@k and MRR; see
+//! [`crate::eval`].
+
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+use crate::{
+   Result,
+   chunker::ChainChunker,
+   config,
+   eval::{self, EvalReport, QueryScore},
+   file::LocalFileSystem,
+   git,
+   meta::{MetaStore, SyncActor},
+   reranker::RerankMode,
+   search::{Retrieval, SearchEngine, SearchFilter},
+   summarize,
+   sync::SyncEngine,
+   util,
+};
+
+/// Command-line options for the eval command.
+#[derive(Debug, Clone)]
+pub struct EvalOptions {
+   /// Cutoffs recall is reported at; results are fetched to the largest.
+   pub ks:        Vec<usize>,
+   pub per_file:  usize,
+   pub no_rerank: bool,
+   pub rerank:    RerankMode,
+   pub json:      bool,
+}
+
+/// Executes the eval command, searching for every query of `suite` within
+/// `path` and printing the scores.
+pub async fn execute(
+   suite: PathBuf,
+   path: Option<PathBuf>,
+   options: EvalOptions,
+   store_id: Option<String>,
+) -> Result<()> {
+   let cases = eval::load_suite(&suite)?;
+   let root = path.map_or_else(std::env::current_dir, Ok)?;
+   let root = root.canonicalize().unwrap_or(root);
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&root), Ok)?;
+   MetaStore::load(&resolved_store_id)?.ensure_model_matches(&resolved_store_id)?;
+
+   let mut ks: Vec<usize> = options.ks.into_iter().filter(|&k| k > 0).collect();
+   ks.sort_unstable();
+   ks.dedup();
+   let limit = ks.last().copied().unwrap_or(10);
+   let retrieval = Retrieval {
+      rerank: !options.no_rerank && !config::get().fast_mode,
+      cross: options.rerank == RerankMode::Cross,
+      ..Retrieval::default()
+   };
+
+   let (embedder, store) = super::open_backends()?;
+
+   let cancel = util::cancel_on_ctrl_c();
+   let sync_engine = SyncEngine::new(
+      LocalFileSystem::new(),
+      ChainChunker::default(),
+      embedder.clone(),
+      store.clone(),
+   )
+   .with_summarizer(summarize::from_config());
+   sync_engine
+      .initial_sync(&resolved_store_id, &root, false, SyncActor::Search, &mut (), &cancel)
+      .await?;
+
+   let engine = SearchEngine::new(store, embedder);
+   let queries: Vec<String> = cases.iter().map(|case| case.query.clone()).collect();
+   let filter = SearchFilter { path: Some(&root), ..SearchFilter::default() };
+   let responses = engine
+      .search_batch(
+         &resolved_store_id,
+         &queries,
+         limit,
+         options.per_file,
+         filter,
+         retrieval,
+         &cancel,
+      )
+      .await?;
+   let scores = cases
+      .iter()
+      .zip(responses)
+      .map(|(case, response)| match response {
+         Ok(response) => QueryScore::new(case, &response.results, &root),
+         Err(e) => QueryScore::failed(case, e.to_string()),
+      })
+      .collect();
+   let report = EvalReport::new(scores, &ks);
+
+   if options.json {
+      println!("{}", serde_json::to_string(&report)?);
+      return Ok(());
+   }
+   print_report(&report, &suite, limit);
+   Ok(())
+}
+
+fn print_report(report: &EvalReport, suite: &Path, limit: usize) {
+   println!(
+      "\n{} {}\n",
+      style(format!("Eval: {}", suite.display())).bold(),
+      style(format!("({} queries)", report.queries.len())).dim()
+   );
+   for recall in &report.recall {
+      println!("   {:<10} {:.3}", format!("recall@{}", recall.k), recall.recall);
+   }
+   println!("   {:<10} {:.3}", "MRR", report.mrr);
+
+   let misses: Vec<&QueryScore> = report
+      .queries
+      .iter()
+      .filter(|query| query.first_hit().is_none())
+      .collect();
+   if !misses.is_empty() {
+      println!("\n{}", style(format!("Not found in the top {limit}:")).bold());
+      for query in misses {
+         match &query.error {
+            Some(error) => println!("   {} {}", query.query, style(format!("({error})")).red()),
+            None => println!("   {}", query.query),
+         }
+      }
+   }
+   println!();
+}
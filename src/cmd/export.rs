@@ -0,0 +1,44 @@
+//! Store export command.
+//!
+//! Packs a store's table data, metadata and a model manifest into a single
+//! archive that can be imported on another machine.
+
+use std::path::PathBuf;
+
+use console::style;
+
+use crate::{Result, archive, git, util::format_size};
+
+/// Executes the export command, writing `store_id` (default: the current
+/// directory's store) to `output` (default: `<store_id>.smgrep`).
+pub fn execute(store_id: Option<String>, output: Option<PathBuf>) -> Result<()> {
+   let store_id = if let Some(id) = store_id {
+      id
+   } else {
+      let cwd = std::env::current_dir()?;
+      git::resolve_store_id(&cwd)?
+   };
+
+   let output =
+      output.unwrap_or_else(|| PathBuf::from(format!("{store_id}.{}", archive::ARCHIVE_EXTENSION)));
+
+   let manifest = archive::export_store(&store_id, &output)?;
+   let size = std::fs::metadata(&output)?.len();
+
+   println!(
+      "{}",
+      style(format!("Exported {store_id} to {} ({})", output.display(), format_size(size))).green()
+   );
+   println!(
+      "{}",
+      style(format!(
+         "Models: {} ({}d), {} ({}d)",
+         manifest.model.dense_model,
+         manifest.model.dense_dim,
+         manifest.model.colbert_model,
+         manifest.model.colbert_dim
+      ))
+      .dim()
+   );
+   Ok(())
+}
@@ -0,0 +1,112 @@
+//! File-level search command.
+//!
+//! Ranks whole files against a query by their anchor chunks, which summarize
+//! each file's imports, exports and opening lines, to answer questions like
+//! "which files handle configuration?".
+
+use std::path::{Path, PathBuf};
+
+use console::style;
+use serde::Serialize;
+
+use crate::{
+   Result, chunker::anchor::AnchorSummary, git, meta::MetaStore, search::SearchEngine, util,
+};
+
+/// Imports, exports or modules listed per file before the rest are counted.
+const MAX_LISTED: usize = 8;
+
+/// A file ranked by its anchor chunk.
+#[derive(Debug, Serialize)]
+struct FileResult {
+   path:    PathBuf,
+   score:   f32,
+   imports: Vec<String>,
+   exports: Vec<String>,
+   modules: Vec<String>,
+}
+
+/// Executes the files command, printing the files whose anchors best match
+/// `query`.
+pub async fn execute(
+   query: String,
+   path: Option<PathBuf>,
+   max: usize,
+   json: bool,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = std::env::current_dir()?;
+   let search_path = path.as_deref().unwrap_or(&root);
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(search_path), Ok)?;
+   MetaStore::load(&resolved_store_id)?.ensure_model_matches(&resolved_store_id)?;
+
+   let (embedder, store) = super::open_backends()?;
+
+   // Only an explicit path narrows the search; otherwise the whole store is
+   // ranked, as for `smgrep search`.
+   let filter = path
+      .as_deref()
+      .map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf()));
+   let engine = SearchEngine::new(store, embedder);
+   let cancel = util::cancel_on_ctrl_c();
+   let anchors = engine
+      .search_files(&resolved_store_id, &query, max, filter.as_deref(), &cancel)
+      .await?;
+
+   let base = filter.as_deref().unwrap_or(&root);
+   let results: Vec<FileResult> = anchors
+      .into_iter()
+      .map(|anchor| {
+         let summary = AnchorSummary::parse(anchor.content.as_str());
+         FileResult {
+            path:    relative(&anchor.path, base),
+            score:   anchor.score,
+            imports: summary.imports.iter().map(ToString::to_string).collect(),
+            exports: summary.exports.iter().map(ToString::to_string).collect(),
+            modules: summary.modules.iter().map(ToString::to_string).collect(),
+         }
+      })
+      .collect();
+
+   if json {
+      println!("{}", serde_json::to_string(&results)?);
+      return Ok(());
+   }
+   if results.is_empty() {
+      println!("No files found for '{query}'");
+      return Ok(());
+   }
+
+   println!("\n{}\n", style(format!("Files for: {query}")).bold());
+   for (i, result) in results.iter().enumerate() {
+      println!(
+         "{}{} {}",
+         style(format!("{}) ", i + 1)).bold().cyan(),
+         style(result.path.display()).green(),
+         style(format!("(score: {:.3})", result.score)).dim()
+      );
+      let lists =
+         [("imports", &result.imports), ("exports", &result.exports), ("modules", &result.modules)];
+      for (label, items) in lists {
+         if !items.is_empty() {
+            println!("   {} {}", style(format!("{label}:")).dim(), list(items));
+         }
+      }
+   }
+   println!();
+   Ok(())
+}
+
+/// `path` relative to `base` when it lies under it.
+fn relative(path: &Path, base: &Path) -> PathBuf {
+   path.strip_prefix(base).unwrap_or(path).to_path_buf()
+}
+
+/// Joins up to [`MAX_LISTED`] items, counting the rest.
+fn list(items: &[String]) -> String {
+   let shown = items[..items.len().min(MAX_LISTED)].join(", ");
+   match items.len().saturating_sub(MAX_LISTED) {
+      0 => shown,
+      rest => format!("{shown} (+{rest} more)"),
+   }
+}
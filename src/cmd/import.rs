@@ -0,0 +1,31 @@
+//! Store import command.
+//!
+//! Unpacks an archive produced by `smgrep export` into the local data
+//! directory so the index can be searched without re-embedding.
+
+use std::path::PathBuf;
+
+use console::style;
+
+use crate::{
+   Result,
+   archive::{self, ImportOptions},
+};
+
+/// Executes the import command.
+pub fn execute(archive_path: PathBuf, store_id: Option<String>, force: bool) -> Result<()> {
+   let manifest =
+      archive::import_archive(&archive_path, &ImportOptions { store_id: store_id.clone(), force })?;
+   let store_id = store_id.unwrap_or(manifest.store_id);
+
+   println!("{}", style(format!("Imported store: {store_id}")).green());
+   println!(
+      "{}",
+      style(format!(
+         "Built by smgrep {} with {} / {}",
+         manifest.smgrep_version, manifest.model.dense_model, manifest.model.colbert_model
+      ))
+      .dim()
+   );
+   Ok(())
+}
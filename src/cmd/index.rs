@@ -1,7 +1,9 @@
 //! Index creation and management command.
 //!
 //! Scans source files, chunks them, computes embeddings, and stores them in the
-//! vector database. Supports dry-run mode and index reset operations.
+//! vector database. Supports dry-run mode and index reset operations. When a
+//! daemon is serving the store, it does the indexing instead and this command
+//! shows its progress.
 
 use std::{
    path::{Path, PathBuf},
@@ -10,18 +12,25 @@ use std::{
 
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
+use tokio_util::sync::CancellationToken;
 use walkdir::WalkDir;
 
 use crate::{
    Result,
-   chunker::Chunker,
+   chunker::ChainChunker,
+   cmd::daemon,
    embed::Embedder,
+   error::Error,
    file::LocalFileSystem,
    git,
    index_lock::IndexLock,
-   meta::MetaStore,
-   store::{LanceStore, Store},
+   ipc::{Request, Response, SocketBuffer},
+   meta::{MetaStore, SyncActor},
+   store::{self, Store},
+   summarize,
    sync::{SyncEngine, SyncProgressCallback},
+   types::SyncProgress,
+   usock, util,
 };
 #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
 use crate::embed::candle::CandleEmbedder;
@@ -33,6 +42,7 @@ pub async fn execute(
    path: Option<PathBuf>,
    dry_run: bool,
    reset: bool,
+   paranoid: bool,
    store_id: Option<String>,
 ) -> Result<()> {
    let root = std::env::current_dir()?;
@@ -72,12 +82,44 @@ pub async fn execute(
    pb.set_message("...");
    pb.set_prefix("Indexing: ");
 
-   let result = index_files(&index_path, &resolved_store_id, &mut |u| {
+   // Ctrl-C stops indexing at the next batch; finished batches are kept.
+   let cancel = util::cancel_on_ctrl_c();
+
+   // The daemon syncs by modification time, so a paranoid sync runs here
+   if !reset && !paranoid && usock::socket_path(&resolved_store_id).exists() {
+      match sync_via_daemon(&resolved_store_id, &mut pb, &cancel).await? {
+         Some(progress) => {
+            pb.finish_with_message(format!(
+               "Indexing complete: {} files indexed",
+               progress.indexed
+            ));
+            println!("\n{}", style("Index updated by the running daemon").green().bold());
+            println!("Store ID: {}", style(&resolved_store_id).cyan());
+            println!("Files indexed: {}", progress.indexed);
+            return Ok(());
+         },
+         None if cancel.is_cancelled() => {
+            pb.abandon_with_message("Stopped following; the daemon keeps indexing");
+            return Err(Error::Cancelled);
+         },
+         None => {},
+      }
+   }
+
+   let result = match index_files(&index_path, &resolved_store_id, paranoid, &cancel, &mut |u| {
       pb.progress(u);
       spinner.tick();
       pb.tick();
    })
-   .await?;
+   .await
+   {
+      Ok(result) => result,
+      Err(Error::Cancelled) => {
+         pb.abandon_with_message("Indexing cancelled; progress saved, run again to resume");
+         return Err(Error::Cancelled);
+      },
+      Err(e) => return Err(e),
+   };
 
    pb.finish_with_message(format!("Indexing complete: {} files indexed", result.indexed));
 
@@ -86,15 +128,64 @@ pub async fn execute(
    println!("Path: {}", style(index_path.display()).dim());
    println!("Files indexed: {}", result.indexed);
    println!("Total chunks: {}", style(result.total_chunks.to_string()).bold());
+   if result.unindexable > 0 {
+      println!(
+         "Skipped: {} binary, minified or oversized files",
+         style(result.unindexable).yellow()
+      );
+   }
+   if result.failed > 0 {
+      println!(
+         "Failed: {} files could not be embedded; run again to retry them",
+         style(result.failed).red()
+      );
+   }
 
    Ok(())
 }
 
+/// Has the daemon serving `store_id` sync its root and follows the progress
+/// until the sync ends. Returns the final progress, or `None` when no daemon
+/// with a matching version answers or `cancel` fires first.
+async fn sync_via_daemon(
+   store_id: &str,
+   pb: &mut ProgressBar,
+   cancel: &CancellationToken,
+) -> Result<Option<SyncProgress>> {
+   let Some(mut stream) = daemon::try_connect_existing(store_id).await? else {
+      return Ok(None);
+   };
+
+   let mut buffer = SocketBuffer::new();
+   buffer.send(&mut stream, &Request::Sync).await?;
+   match buffer.recv(&mut stream).await? {
+      Response::Sync { .. } => {},
+      Response::Error { message } => return Err(Error::Server { op: "sync", reason: message }),
+      _ => return Err(Error::UnexpectedResponse("sync")),
+   }
+
+   buffer.send(&mut stream, &Request::Progress).await?;
+   loop {
+      let response = tokio::select! {
+         response = buffer.recv::<_, Response>(&mut stream) => response?,
+         () = cancel.cancelled() => return Ok(None),
+      };
+      match response {
+         Response::Progress { progress, done: true } => return Ok(Some(progress)),
+         Response::Progress { progress, done: false } => pb.progress(progress),
+         Response::Error { message } => {
+            return Err(Error::Server { op: "sync", reason: message });
+         },
+         _ => return Err(Error::UnexpectedResponse("sync progress")),
+      }
+   }
+}
+
 /// Deletes an existing store and its associated metadata.
 async fn delete_store(store_id: &str, index_path: &Path) -> Result<()> {
    let _lock = IndexLock::acquire(store_id)?;
 
-   let store = LanceStore::new()?;
+   let store = store::open()?;
 
    store.delete_store(store_id).await?;
 
@@ -132,12 +223,18 @@ fn scan_files(path: &Path) -> usize {
 struct IndexResult {
    indexed:      usize,
    total_chunks: usize,
+   /// Files left out as binary, minified or too large.
+   unindexable:  usize,
+   /// Files whose chunks could not be embedded.
+   failed:       usize,
 }
 
 /// Performs the actual file indexing using the sync engine.
 async fn index_files(
    path: &Path,
    store_id: &str,
+   paranoid: bool,
+   cancel: &CancellationToken,
    callback: &mut dyn SyncProgressCallback,
 ) -> Result<IndexResult> {
    let file_system = LocalFileSystem::new();
@@ -147,13 +244,20 @@ async fn index_files(
    let embedder: Arc<dyn Embedder> = Arc::new(CandleEmbedder::new()?);
    #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
    let embedder: Arc<dyn Embedder> = Arc::new(EmbedWorker::new()?);
-   let store: Arc<dyn Store> = Arc::new(LanceStore::new()?);
+   let store = store::open()?;
 
-   let sync_engine = SyncEngine::new(file_system, Chunker::default(), embedder, store);
+   let sync_engine = SyncEngine::new(file_system, ChainChunker::default(), embedder, store)
+      .with_summarizer(summarize::from_config())
+      .with_paranoid(paranoid);
 
    let result = sync_engine
-      .initial_sync(store_id, path, false, callback)
+      .initial_sync(store_id, path, false, SyncActor::Index, callback, cancel)
       .await?;
 
-   Ok(IndexResult { indexed: result.indexed, total_chunks: result.indexed })
+   Ok(IndexResult {
+      indexed:      result.indexed,
+      total_chunks: result.indexed,
+      unindexable:  result.binary + result.minified + result.too_large,
+      failed:       result.failed,
+   })
 }
@@ -1,14 +1,17 @@
 //! List all vector stores command.
 //!
 //! Displays information about all existing stores including their size and
-//! modification time.
+//! modification time, or the files indexed in a single store.
 
-use std::{fs, time::SystemTime};
+use std::{
+   fs,
+   time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use console::style;
 
 use crate::{
-   Result, config,
+   Result, config, git, store,
    util::{format_size, get_dir_size},
 };
 
@@ -79,6 +82,46 @@ pub fn execute() -> Result<()> {
    Ok(())
 }
 
+/// Lists the files indexed in `store_id` (default: the current directory's
+/// store), most recently indexed first.
+pub async fn execute_files(store_id: Option<String>) -> Result<()> {
+   let store_id = if let Some(id) = store_id {
+      id
+   } else {
+      let cwd = std::env::current_dir()?;
+      git::resolve_store_id(&cwd)?
+   };
+
+   let mut files = store::open()?.list_files(&store_id).await?;
+   if files.is_empty() {
+      println!("No files indexed in {store_id}.");
+      return Ok(());
+   }
+
+   files.sort_by(|a, b| {
+      b.indexed_at
+         .cmp(&a.indexed_at)
+         .then_with(|| a.path.cmp(&b.path))
+   });
+
+   println!(
+      "\n{} {}",
+      style(format!("{} file(s) in {store_id}:", files.len())).bold(),
+      style("(by index time)").dim()
+   );
+   println!();
+
+   for file in files {
+      let when = file.indexed_at.map_or_else(
+         || "unknown".to_string(),
+         |ts| format_time_ago(UNIX_EPOCH + Duration::from_secs(ts)),
+      );
+      println!("  {}  {}", file.path.display(), style(when).dim());
+   }
+
+   Ok(())
+}
+
 /// Information about a store on disk.
 struct StoreInfo {
    name:     String,
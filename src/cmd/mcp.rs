@@ -15,9 +15,11 @@ use tokio::io::{AsyncBufReadExt, BufReader};
 use crate::{
    Result,
    cmd::daemon,
+   config,
    error::Error,
    git,
    ipc::{Request, Response, SocketBuffer},
+   search::testfile::TestFilter,
    usock,
 };
 
@@ -80,7 +82,17 @@ impl DaemonConn {
          query: query.to_string(),
          limit,
          path: Some(self.cwd.clone()),
-         rerank: true,
+         rerank: !config::get().fast_mode,
+         exact: false,
+         keyword: false,
+         indexed_after: None,
+         crate_name: None,
+         tests: TestFilter::All,
+         include: vec![],
+         exclude: vec![],
+         rerank_depth: None,
+         cross_rerank: false,
+         request_id: None,
       };
 
       self.buffer.send(&mut self.stream, &request).await?;
@@ -95,11 +107,24 @@ impl DaemonConn {
                for line in r.content.lines().take(10) {
                   writeln!(output, "  {line}").unwrap();
                }
+               for linked in &r.linked {
+                  writeln!(
+                     output,
+                     "  linked via {}: {}:{}",
+                     linked.symbol,
+                     linked.path.display(),
+                     linked.start_line
+                  )
+                  .unwrap();
+               }
                output.push('\n');
             }
             if output.is_empty() {
                output = format!("No results found for '{query}'");
             }
+            if let Some(progress) = search_response.progress {
+               output.insert_str(0, &format!("Note: {progress} — results may be incomplete.\n\n"));
+            }
             Ok(output)
          },
          Response::Error { message } => Err(Error::Server { op: "search", reason: message }),
@@ -183,7 +208,7 @@ async fn handle_request(
                "properties": {
                   "query": {
                      "type": "string",
-                     "description": "Natural language query describing what you're looking for"
+                     "description": "Natural language query describing what you're looking for. Supports \"exact phrase\", -term to exclude, and path:fragment / -path:fragment to filter by path."
                   },
                   "limit": {
                      "type": "integer",
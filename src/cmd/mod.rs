@@ -3,16 +3,53 @@
 //! This module contains all subcommand implementations for the smgrep CLI tool.
 //! Each module corresponds to a specific command available to users.
 
+pub mod bench;
+pub mod check;
 pub mod claude_install;
 pub mod clean;
 pub mod daemon;
 pub mod doctor;
+pub mod dupes;
+pub mod eval;
+pub mod export;
+pub mod files;
+pub mod import;
 pub mod index;
 pub mod list;
 pub mod mcp;
+pub mod models;
 pub mod search;
 pub mod serve;
 pub mod setup;
+pub mod similar;
+pub mod stats;
 pub mod status;
 pub mod stop;
 pub mod stop_all;
+pub mod unload;
+pub mod verify;
+pub mod warmup;
+
+use std::sync::Arc;
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+use crate::embed::candle::CandleEmbedder;
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+use crate::embed::worker::EmbedWorker;
+use crate::{
+   Result,
+   embed::Embedder,
+   store::{self, Store},
+};
+
+/// Opens the embedder and store for a command that runs the models in
+/// process rather than through the daemon.
+pub fn open_backends() -> Result<(Arc<dyn Embedder>, Arc<dyn Store>)> {
+   // EmbedWorker's parallel workers cause hangs on Metal. Use CandleEmbedder
+   // directly, as huggingface/text-embeddings-inference runs single-threaded.
+   #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+   let embedder: Arc<dyn Embedder> = Arc::new(CandleEmbedder::new()?);
+   #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+   let embedder: Arc<dyn Embedder> = Arc::new(EmbedWorker::new()?);
+   Ok((embedder, store::open()?))
+}
@@ -0,0 +1,115 @@
+//! Model selection and listing.
+//!
+//! `--model` binds a store to a dense model in the model registry, and
+//! `smgrep models` lists the registered models with the stores bound to them.
+
+use std::{env, path::Path};
+
+use console::style;
+use hf_hub::Cache;
+
+use crate::{
+   Result,
+   cmd::daemon,
+   config,
+   embed::{
+      CandleEmbedder,
+      candle::hub_repo,
+      registry::{self, ModelKind, ModelRegistry},
+   },
+   git, usock,
+};
+
+/// Makes `model_id` the dense model for this process and, when `bind` is
+/// set, binds the store (`store_id`, or the store for `path` or the current
+/// directory) to it. A daemon serving the store with another model is shut
+/// down so that the next one starts with `model_id`.
+pub async fn select(
+   model_id: String,
+   bind: bool,
+   store_id: Option<&str>,
+   path: Option<&Path>,
+) -> Result<()> {
+   let dim = registry::resolve_dense_dim(&model_id).await?;
+   // Resolving the store reads the configuration, which takes the override
+   // only if it is set first
+   config::set_model_override(model_id.clone(), dim);
+   if bind {
+      let store_id = match store_id {
+         Some(id) => id.to_string(),
+         None => match path {
+            Some(path) => git::resolve_store_id(path)?,
+            None => git::resolve_store_id(&env::current_dir()?)?,
+         },
+      };
+      if ModelRegistry::bind(&store_id, &model_id)?
+         && usock::list_running_servers().contains(&store_id)
+      {
+         daemon::force_shutdown(None, &store_id).await?;
+      }
+   }
+   Ok(())
+}
+
+/// Lists the registered models, whether each is installed, and the stores
+/// bound to each dense model.
+pub fn execute() -> Result<()> {
+   let registry = ModelRegistry::load();
+   if registry.models.is_empty() {
+      println!(
+         "{}",
+         style("No models registered yet. Run `smgrep setup` to fetch the defaults.").dim()
+      );
+      return Ok(());
+   }
+
+   let cfg = config::get();
+   println!("{}", style("Registered models:").bold());
+   println!();
+
+   for (model_id, model) in &registry.models {
+      let kind = match model.kind {
+         ModelKind::Dense => "dense",
+         ModelKind::Colbert => "colbert",
+      };
+      let installed = CandleEmbedder::installed_model_dir(model_id).is_some()
+         || Cache::default()
+            .repo(hub_repo(model_id))
+            .get("model.safetensors")
+            .is_some();
+      let dot = if installed {
+         style("●").green()
+      } else {
+         style("○").dim()
+      };
+      let current = if model_id == &cfg.dense_model || model_id == &cfg.colbert_model {
+         " (current)"
+      } else {
+         ""
+      };
+
+      println!(
+         "  {dot} {model_id}{} {}",
+         style(current).cyan(),
+         style(match model.pooling {
+            Some(pooling) => format!("{kind}, {} dims, {} pooling", model.dim, pooling.as_str()),
+            None => format!("{kind}, {} dims", model.dim),
+         })
+         .dim()
+      );
+
+      let stores: Vec<&str> = registry
+         .bindings
+         .iter()
+         .filter(|(_, bound)| *bound == model_id)
+         .map(|(store_id, _)| store_id.as_str())
+         .collect();
+      if !stores.is_empty() {
+         println!("      {} {}", style("Stores:").dim(), stores.join(", "));
+      }
+   }
+
+   println!();
+   println!("{}", style("To use another dense model: smgrep --model <id> index").dim());
+   Ok(())
+}
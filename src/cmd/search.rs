@@ -5,67 +5,231 @@
 //! options.
 
 use std::{
+   collections::HashMap,
+   fmt::Write as _,
+   fs, io,
    path::{Path, PathBuf},
    sync::Arc,
-   time::Duration,
+   time::{Duration, Instant},
 };
 
 use console::style;
+use futures::stream::{self, StreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
 use tokio::time;
 
 use crate::{
    Result,
-   chunker::Chunker,
+   chunker::ChainChunker,
    cmd::daemon,
+   config,
    error::Error,
-   file::LocalFileSystem,
+   file::{self, LocalFileSystem},
    git,
+   hooks::{self, Hook},
    ipc::{self, Request, Response},
-   search::SearchEngine,
-   store::LanceStore,
+   meta::{MetaStore, SyncActor},
+   reranker::RerankMode,
+   search::{
+      self, Retrieval, SearchEngine, SearchFilter,
+      glob::PathGlobs,
+      hydrate::{self, Drift},
+      testfile::TestFilter,
+   },
+   store, summarize,
    sync::SyncEngine,
-   usock,
+   types::{
+      self, IndexProgress, QueryRoute, ScoreBreakdown, SearchStage, SearchStatus, StageTiming,
+      VectorSearchMode,
+   },
+   usage, usock, util,
+   workspace::CargoWorkspace,
 };
 #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
 use crate::embed::candle::CandleEmbedder;
 #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
 use crate::embed::worker::EmbedWorker;
 
+/// Lines of each neighbouring chunk shown by `--show-context`.
+const CONTEXT_PREVIEW_LINES: usize = 3;
+
 /// A single search result with metadata and content.
 #[derive(Debug, Serialize, Deserialize)]
 struct SearchResult {
-   path:       PathBuf,
-   score:      f32,
-   content:    String,
+   path:         PathBuf,
+   score:        f32,
+   content:      String,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   chunk_type:   Option<String>,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   start_line:   Option<usize>,
    #[serde(skip_serializing_if = "Option::is_none")]
-   chunk_type: Option<String>,
+   end_line:     Option<usize>,
    #[serde(skip_serializing_if = "Option::is_none")]
-   start_line: Option<usize>,
+   is_anchor:    Option<bool>,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   language:     Option<String>,
+   /// Chunk before this one in its file.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   context_prev: Option<String>,
+   /// Chunk after this one in its file.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   context_next: Option<String>,
    #[serde(skip_serializing_if = "Option::is_none")]
-   end_line:   Option<usize>,
+   drift:        Option<Drift>,
+   /// Indexed content of a drifted result, when requested.
    #[serde(skip_serializing_if = "Option::is_none")]
-   is_anchor:  Option<bool>,
+   stored:       Option<String>,
+   /// Component scores behind `score`, when requested.
+   #[serde(skip_serializing_if = "Option::is_none")]
+   breakdown:    Option<ScoreBreakdown>,
+   /// Last commit to change the file, when requested.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   blame:        Option<Blame>,
+   /// Code across an FFI boundary sharing a bridge key with this result.
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   linked:       Vec<Linked>,
+}
+
+/// Author and date of the last commit to change a result's file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Blame {
+   author: String,
+   email:  String,
+   /// Author date, in RFC 3339.
+   date:   String,
+   commit: String,
 }
 
-/// JSON output format for search results.
-#[derive(Debug, Serialize)]
+impl From<git::LastCommit> for Blame {
+   fn from(commit: git::LastCommit) -> Self {
+      Self {
+         author: commit.author,
+         email:  commit.email,
+         date:   chrono::DateTime::from_timestamp(commit.time, 0)
+            .unwrap_or_default()
+            .to_rfc3339(),
+         commit: commit.id,
+      }
+   }
+}
+
+/// Location of a chunk linked to a result through a bridge key.
+#[derive(Debug, Serialize, Deserialize)]
+struct Linked {
+   symbol:     String,
+   path:       PathBuf,
+   start_line: usize,
+   end_line:   usize,
+}
+
+impl From<types::LinkedResult> for Linked {
+   fn from(linked: types::LinkedResult) -> Self {
+      Self {
+         symbol:     linked.symbol.into_string(),
+         path:       linked.path,
+         start_line: linked.start_line as usize,
+         end_line:   (linked.start_line + linked.num_lines) as usize,
+      }
+   }
+}
+
+/// JSON output format for search results, also exchanged with the
+/// `post_search` hook.
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct JsonOutput {
-   results: Vec<SearchResult>,
+   results:  Vec<SearchResult>,
+   /// How dense candidates were retrieved; absent when no search ran.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   mode:     Option<VectorSearchMode>,
+   /// How the query was routed; absent when no search ran.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   route:    Option<QueryRoute>,
+   /// Whether the index was complete or still being built; absent when no
+   /// search ran.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   status:   Option<SearchStatus>,
+   /// Set when a daemon answered while still indexing, so results are
+   /// partial.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   progress: Option<IndexProgress>,
+   /// Time spent in each search stage, in order.
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   stages:   Vec<StageTiming>,
+}
+
+impl JsonOutput {
+   fn new(results: Vec<SearchResult>, response: &types::SearchResponse) -> Self {
+      Self {
+         results,
+         mode: Some(response.mode),
+         route: Some(response.route),
+         status: Some(response.status),
+         progress: response.progress,
+         stages: response.stages.clone(),
+      }
+   }
+}
+
+/// Input of the `post_search` hook: the JSON output plus the query. Also a
+/// line of `--batch --json` output.
+#[derive(Serialize)]
+struct PostSearchPayload<'a> {
+   query:  &'a str,
+   #[serde(flatten)]
+   output: &'a JsonOutput,
+}
+
+/// A line of `--batch --json` output for a query whose search failed.
+#[derive(Serialize)]
+struct BatchError<'a> {
+   query: &'a str,
+   error: String,
 }
 
 /// Command-line options for search behavior.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone)]
 pub struct SearchOptions {
-   pub content:   bool,
-   pub compact:   bool,
-   pub scores:    bool,
-   pub sync:      bool,
-   pub dry_run:   bool,
-   pub json:      bool,
-   pub no_rerank: bool,
-   pub plain:     bool,
+   pub content:       bool,
+   pub compact:       bool,
+   pub scores:        bool,
+   pub sync:          bool,
+   pub dry_run:       bool,
+   pub json:          bool,
+   pub no_rerank:     bool,
+   pub plain:         bool,
+   /// Scan every vector instead of using the approximate index.
+   pub exact:         bool,
+   /// Query only the full-text index, without embedding the query.
+   pub keyword:       bool,
+   /// Candidates to rescore with `ColBERT`, overriding `rerank_depth`.
+   pub rerank_depth:  Option<usize>,
+   /// Which stages rescore candidates.
+   pub rerank:        RerankMode,
+   pub migrate:       bool,
+   /// Only match files indexed within this long ago.
+   pub changed_since: Option<Duration>,
+   /// Re-read result ranges from the working tree and flag drift.
+   pub fresh:         bool,
+   /// Keep the indexed content of drifted results alongside the current one.
+   pub show_stored:   bool,
+   /// Preview the chunks around each result.
+   pub show_context:  bool,
+   /// Only match chunks from this Cargo workspace member.
+   pub crate_name:    Option<String>,
+   /// Only match files accepted by one of these globs.
+   pub include:       Vec<String>,
+   /// Skip files matching any of these globs.
+   pub exclude:       Vec<String>,
+   /// Show how each result's score was derived.
+   pub explain:       bool,
+   /// Annotate results with the last commit to change their file.
+   pub blame:         bool,
+   /// Only keep results whose last commit is by a matching author.
+   pub author:        Option<String>,
+   /// Keep only test code, or drop it.
+   pub tests:         TestFilter,
 }
 
 /// Options for formatting search results in human-readable output.
@@ -75,6 +239,95 @@ struct FormatOptions {
    compact: bool,
    scores:  bool,
    plain:   bool,
+   explain: bool,
+   context: bool,
+}
+
+impl FormatOptions {
+   const fn from_search(options: &SearchOptions) -> Self {
+      Self {
+         content: options.content,
+         compact: options.compact,
+         scores:  options.scores,
+         plain:   options.plain,
+         explain: options.explain,
+         context: options.show_context,
+      }
+   }
+}
+
+/// Where and how a search looks, resolved from its command-line options.
+struct SearchScope {
+   root:          PathBuf,
+   search_path:   PathBuf,
+   store_id:      String,
+   /// Results shown per query.
+   limit:         usize,
+   /// Results fetched per query, more than `limit` when some are dropped
+   /// after the search.
+   max:           usize,
+   retrieval:     Retrieval,
+   indexed_after: Option<u64>,
+   globs:         PathGlobs,
+}
+
+impl SearchScope {
+   fn resolve(
+      path: Option<PathBuf>,
+      max: usize,
+      options: &SearchOptions,
+      store_id: Option<String>,
+   ) -> Result<Self> {
+      let root = std::env::current_dir()?;
+      // Results by other authors are dropped after the search, so more are
+      // fetched to fill `max`.
+      let limit = max;
+      let max = if options.author.is_some() {
+         max * search::FILTER_OVERFETCH
+      } else {
+         max
+      };
+      let search_path = path.unwrap_or_else(|| root.clone());
+
+      let store_id = store_id.map_or_else(|| git::resolve_store_id(&search_path), Ok)?;
+      let retrieval = Retrieval {
+         rerank:       !options.no_rerank && !config::get().fast_mode,
+         exact:        options.exact,
+         keyword:      options.keyword,
+         rerank_depth: options.rerank_depth,
+         cross:        options.rerank == RerankMode::Cross,
+      };
+      let indexed_after = options
+         .changed_since
+         .map(|age| util::unix_now().saturating_sub(age.as_secs()));
+
+      if let Some(name) = options.crate_name.as_deref() {
+         let workspace = CargoWorkspace::discover(&search_path);
+         if !workspace.contains(name) {
+            return Err(Error::UnknownCrate {
+               name:      name.to_string(),
+               available: if workspace.is_empty() {
+                  "none".to_string()
+               } else {
+                  workspace.names().join(", ")
+               },
+            });
+         }
+      }
+      let globs = PathGlobs::new(&search_path, &options.include, &options.exclude)?;
+      Ok(Self { root, search_path, store_id, limit, max, retrieval, indexed_after, globs })
+   }
+
+   fn filter<'a>(&'a self, options: &'a SearchOptions) -> SearchFilter<'a> {
+      SearchFilter {
+         path:          None,
+         globs:         (!self.globs.is_empty()).then_some(&self.globs),
+         indexed_after: self.indexed_after,
+         crate_name:    options.crate_name.as_deref(),
+         tests:         options.tests,
+         tests_root:    Some(&self.search_path),
+      }
+   }
 }
 
 /// Executes a semantic code search.
@@ -86,35 +339,44 @@ pub async fn execute(
    options: SearchOptions,
    store_id: Option<String>,
 ) -> Result<()> {
-   let root = std::env::current_dir()?;
-   let search_path = path.unwrap_or_else(|| root.clone());
+   let started = Instant::now();
+   let scope = SearchScope::resolve(path, max, &options, store_id)?;
+   let filter = scope.filter(&options);
+   let search_path = scope.search_path.as_path();
+   let resolved_store_id = scope.store_id.as_str();
 
-   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&search_path), Ok)?;
+   // Re-indexing happens in this process, behind the back of a daemon that
+   // would keep answering from the old embeddings
+   if options.migrate
+      && daemon::try_connect_existing(resolved_store_id)
+         .await?
+         .is_some()
+   {
+      return Err(Error::DaemonRunning(resolved_store_id.to_string()));
+   }
 
-   if let Some(results) =
-      try_daemon_search(&query, max, !options.no_rerank, &search_path, &resolved_store_id).await?
+   if let Some(output) =
+      try_daemon_search(&query, scope.max, scope.retrieval, filter, search_path, resolved_store_id)
+         .await?
    {
+      usage::record_search(&query, started.elapsed(), true);
+      let output = search_submodules(output, &query, &scope, per_file, filter).await?;
+      let output = refine_output(output, &query, &scope, &options).await?;
       if options.json {
-         println!("{}", serde_json::to_string(&JsonOutput { results })?);
+         println!("{}", serde_json::to_string(&output)?);
       } else {
-         let format_opts = FormatOptions {
-            content: options.content,
-            compact: options.compact,
-            scores:  options.scores,
-            plain:   options.plain,
-         };
-         format_results(&results, &query, &root, format_opts);
+         format_results(&output, &query, &scope.root, FormatOptions::from_search(&options));
       }
       return Ok(());
    }
 
    if options.dry_run {
       if options.json {
-         println!("{}", serde_json::to_string(&JsonOutput { results: vec![] })?);
+         println!("{}", serde_json::to_string(&JsonOutput::default())?);
       } else {
          println!("Dry run: would search for '{query}' in {}", search_path.display());
          println!("Store ID: {resolved_store_id}");
-         println!("Max results: {max}");
+         println!("Max results: {}", scope.limit);
       }
       return Ok(());
    }
@@ -134,13 +396,27 @@ pub async fn execute(
       spinner.finish_with_message("Sync complete");
    }
 
-   let results =
-      perform_search(&query, &search_path, &resolved_store_id, max, per_file, !options.no_rerank)
-         .await?;
+   if !options.migrate && !options.keyword {
+      MetaStore::load(resolved_store_id)?.ensure_model_matches(resolved_store_id)?;
+   }
 
-   if results.is_empty() {
+   let output = perform_search(
+      &query,
+      search_path,
+      resolved_store_id,
+      scope.max,
+      per_file,
+      filter,
+      scope.retrieval,
+   )
+   .await?;
+   usage::record_search(&query, started.elapsed(), false);
+   let output = search_submodules(output, &query, &scope, per_file, filter).await?;
+   let output = refine_output(output, &query, &scope, &options).await?;
+
+   if output.results.is_empty() {
       if options.json {
-         println!("{}", serde_json::to_string(&JsonOutput { results: vec![] })?);
+         println!("{}", serde_json::to_string(&output)?);
       } else {
          println!("No results found for '{query}'");
          if !options.sync {
@@ -151,52 +427,262 @@ pub async fn execute(
    }
 
    if options.json {
-      println!("{}", serde_json::to_string(&JsonOutput { results })?);
+      println!("{}", serde_json::to_string(&output)?);
    } else {
-      let format_opts = FormatOptions {
-         content: options.content,
-         compact: options.compact,
-         scores:  options.scores,
-         plain:   options.plain,
-      };
-      format_results(&results, &query, &root, format_opts);
+      format_results(&output, &query, &scope.root, FormatOptions::from_search(&options));
    }
 
    Ok(())
 }
 
+/// Runs one search per non-empty line of `batch`, or of stdin when it is
+/// `-`. With `--json`, prints each query's results as one line of JSON, with
+/// the query under `query` and a failed query's error under `error`.
+///
+/// A daemon already serving the store answers the queries concurrently.
+/// Otherwise none is started: the directory is synced once in-process, then
+/// the queries are embedded in one batch and searched concurrently.
+pub async fn execute_batch(
+   batch: PathBuf,
+   path: Option<PathBuf>,
+   max: usize,
+   per_file: usize,
+   options: SearchOptions,
+   store_id: Option<String>,
+) -> Result<()> {
+   let text = if batch.as_os_str() == "-" {
+      io::read_to_string(io::stdin())?
+   } else {
+      fs::read_to_string(&batch)?
+   };
+   let queries: Vec<String> = text
+      .lines()
+      .map(str::trim)
+      .filter(|query| !query.is_empty())
+      .map(String::from)
+      .collect();
+   if queries.is_empty() {
+      return Ok(());
+   }
+
+   let started = Instant::now();
+   let scope = &SearchScope::resolve(path, max, &options, store_id)?;
+   let filter = scope.filter(&options);
+   let via_daemon = daemon::try_connect_existing(&scope.store_id)
+      .await?
+      .is_some();
+   if options.migrate && via_daemon {
+      return Err(Error::DaemonRunning(scope.store_id.clone()));
+   }
+
+   let outputs: Vec<Result<JsonOutput>> = if via_daemon {
+      stream::iter(&queries)
+         .map(|query| async move {
+            let stream = daemon::try_connect_existing(&scope.store_id)
+               .await?
+               .ok_or_else(|| Error::Server {
+                  op:     "search",
+                  reason: "daemon stopped".to_string(),
+               })?;
+            send_search_request(
+               stream,
+               query,
+               scope.max,
+               scope.retrieval,
+               filter,
+               &scope.search_path,
+            )
+            .await
+         })
+         .buffered(search::BATCH_CONCURRENCY)
+         .collect()
+         .await
+   } else {
+      if !options.migrate && !options.keyword {
+         MetaStore::load(&scope.store_id)?.ensure_model_matches(&scope.store_id)?;
+      }
+      perform_batch_search(&queries, scope, per_file, filter).await?
+   };
+   let latency = started.elapsed() / queries.len() as u32;
+
+   for (query, output) in queries.iter().zip(outputs) {
+      usage::record_search(query, latency, via_daemon);
+      let output = match output {
+         Ok(output) => refine_output(output, query, scope, &options).await,
+         Err(e) => Err(e),
+      };
+      match output {
+         Ok(output) if options.json => {
+            println!("{}", serde_json::to_string(&PostSearchPayload { query, output: &output })?);
+         },
+         Ok(output) => {
+            format_results(&output, query, &scope.root, FormatOptions::from_search(&options));
+         },
+         Err(e) if options.json => {
+            println!("{}", serde_json::to_string(&BatchError { query, error: e.to_string() })?);
+         },
+         Err(e) => eprintln!("Search for '{query}' failed: {e}"),
+      }
+   }
+   Ok(())
+}
+
+/// Applies the options that act on finished results, keeps the first
+/// `limit`, then runs the `post_search` hook.
+async fn refine_output(
+   mut output: JsonOutput,
+   query: &str,
+   scope: &SearchScope,
+   options: &SearchOptions,
+) -> Result<JsonOutput> {
+   if !options.explain {
+      strip_breakdowns(&mut output.results);
+   }
+   if options.fresh {
+      hydrate_results(&mut output.results, &scope.search_path, options.show_stored);
+   }
+   if options.blame || options.author.is_some() {
+      blame_results(&mut output.results, &scope.search_path, options.author.as_deref())?;
+   }
+   output.results.truncate(scope.limit);
+   post_search(query, &scope.search_path, &scope.store_id, output).await
+}
+
+/// Runs the `post_search` hook, if configured. When the hook prints a JSON
+/// document on stdout, it replaces the results.
+async fn post_search(
+   query: &str,
+   root: &Path,
+   store_id: &str,
+   output: JsonOutput,
+) -> Result<JsonOutput> {
+   let payload = PostSearchPayload { query, output: &output };
+   let Some(stdout) = hooks::run(Hook::PostSearch, root, store_id, &payload).await? else {
+      return Ok(output);
+   };
+   if stdout.trim_ascii().is_empty() {
+      return Ok(output);
+   }
+   serde_json::from_slice(&stdout).map_err(|e| Error::Hook {
+      hook:   Hook::PostSearch.name(),
+      reason: format!("invalid JSON output: {e}"),
+   })
+}
+
 /// Attempts to execute the search via a running daemon, returning None if
-/// unavailable.
+/// unavailable. Time the daemon did not account for is reported as the
+/// queued stage.
 async fn try_daemon_search(
    query: &str,
    max: usize,
-   rerank: bool,
+   retrieval: Retrieval,
+   filter: SearchFilter<'_>,
    path: &Path,
    store_id: &str,
-) -> Result<Option<Vec<SearchResult>>> {
+) -> Result<Option<JsonOutput>> {
+   let started = Instant::now();
    let Ok(stream) = daemon::connect_matching_daemon(path, store_id).await else {
       return Ok(None);
    };
 
-   send_search_request(stream, query, max, rerank, path)
-      .await
-      .map(Some)
+   let mut output = send_search_request(stream, query, max, retrieval, filter, path).await?;
+   let round_trip = StageTiming::since(SearchStage::Queued, started);
+   let accounted: u64 = output.stages.iter().map(|s| s.ms).sum();
+   let queued = StageTiming { ms: round_trip.ms.saturating_sub(accounted), ..round_trip };
+   output.stages.insert(0, queued);
+   Ok(Some(output))
+}
+
+/// Adds the results of the checked-out submodules under the searched
+/// directory, each searched in its own store, keeping the best `max` of them
+/// all. A submodule that cannot be searched is left out.
+async fn search_submodules(
+   mut output: JsonOutput,
+   query: &str,
+   scope: &SearchScope,
+   per_file: usize,
+   filter: SearchFilter<'_>,
+) -> Result<JsonOutput> {
+   if !config::get().submodule_stores {
+      return Ok(output);
+   }
+   let roots = git::submodule_roots(&scope.search_path);
+   if roots.is_empty() {
+      return Ok(output);
+   }
+
+   // Globs are relative to the searched directory, not each submodule
+   let globs = filter.globs.map(PathGlobs::anchored).transpose()?;
+   let filter = SearchFilter { globs: globs.as_ref(), ..filter };
+   for root in roots {
+      let found = async {
+         let store_id = git::resolve_store_id(&root)?;
+         match try_daemon_search(query, scope.max, scope.retrieval, filter, &root, &store_id)
+            .await?
+         {
+            Some(found) => Ok(found),
+            None => {
+               perform_search(query, &root, &store_id, scope.max, per_file, filter, scope.retrieval)
+                  .await
+            },
+         }
+      };
+      let found = match found.await {
+         Ok(found) => found,
+         Err(e) => {
+            tracing::warn!("failed to search submodule {}: {e}", root.display());
+            continue;
+         },
+      };
+
+      let prefix = root.strip_prefix(&scope.search_path).unwrap_or(&root);
+      output
+         .results
+         .extend(found.results.into_iter().map(|mut result| {
+            result.path = prefix.join(&result.path);
+            for linked in &mut result.linked {
+               linked.path = prefix.join(&linked.path);
+            }
+            result
+         }));
+   }
+   output.results.sort_by(|a, b| b.score.total_cmp(&a.score));
+   output.results.truncate(scope.max);
+   Ok(output)
 }
 
 /// Sends a search request to a daemon over the given stream and returns
-/// results.
+/// its results, with the mode, route, index progress and stage timings it
+/// reported.
 async fn send_search_request(
    mut stream: usock::Stream,
    query: &str,
    max: usize,
-   rerank: bool,
+   retrieval: Retrieval,
+   filter: SearchFilter<'_>,
    path: &Path,
-) -> Result<Vec<SearchResult>> {
+) -> Result<JsonOutput> {
    let request = Request::Search {
-      query: query.to_string(),
-      limit: max,
-      path: Some(path.to_path_buf()),
-      rerank,
+      query:         query.to_string(),
+      limit:         max,
+      path:          Some(path.to_path_buf()),
+      rerank:        retrieval.rerank,
+      exact:         retrieval.exact,
+      keyword:       retrieval.keyword,
+      indexed_after: filter.indexed_after,
+      crate_name:    filter.crate_name.map(str::to_string),
+      tests:         filter.tests,
+      include:       filter
+         .globs
+         .map(PathGlobs::include_globs)
+         .unwrap_or_default(),
+      exclude:       filter
+         .globs
+         .map(PathGlobs::exclude_globs)
+         .unwrap_or_default(),
+      rerank_depth:  retrieval.rerank_depth,
+      cross_rerank:  retrieval.cross,
+      request_id:    None,
    };
 
    let mut buffer = ipc::SocketBuffer::new();
@@ -204,21 +690,28 @@ async fn send_search_request(
    let response: Response = buffer.recv(&mut stream).await?;
 
    match response {
-      Response::Search(search_response) => {
-         let results = search_response
-            .results
+      Response::Search(mut search_response) => {
+         let results = std::mem::take(&mut search_response.results)
             .into_iter()
             .map(|r| SearchResult {
-               path:       r.path,
-               score:      r.score,
-               content:    r.content.into_string(),
-               chunk_type: r.chunk_type.map(|ct| ct.as_lowercase_str().to_string()),
-               start_line: Some(r.start_line as usize),
-               end_line:   Some((r.start_line + r.num_lines) as usize),
-               is_anchor:  r.is_anchor,
+               path:         r.path,
+               score:        r.score,
+               content:      r.content.into_string(),
+               chunk_type:   r.chunk_type.map(|ct| ct.as_lowercase_str().to_string()),
+               start_line:   Some(r.start_line as usize),
+               end_line:     Some((r.start_line + r.num_lines) as usize),
+               is_anchor:    r.is_anchor,
+               language:     r.language.map(String::from),
+               context_prev: r.context_prev.map(String::from),
+               context_next: r.context_next.map(String::from),
+               drift:        None,
+               stored:       None,
+               breakdown:    r.breakdown,
+               blame:        None,
+               linked:       r.linked.into_iter().map(Linked::from).collect(),
             })
             .collect();
-         Ok(results)
+         Ok(JsonOutput::new(results, &search_response))
       },
       Response::Error { message } => Err(Error::Server { op: "search", reason: message }),
       _ => Err(Error::UnexpectedResponse("search")),
@@ -233,9 +726,19 @@ async fn perform_search(
    store_id: &str,
    max: usize,
    per_file: usize,
-   rerank: bool,
-) -> Result<Vec<SearchResult>> {
-   let store = Arc::new(LanceStore::new()?);
+   filter: SearchFilter<'_>,
+   retrieval: Retrieval,
+) -> Result<JsonOutput> {
+   let store = store::open()?;
+
+   // Keyword searches need no model, so they skip loading one along with the
+   // sync that would require it and search whatever is already indexed.
+   if retrieval.keyword {
+      let mut response =
+         search::keyword_search(&*store, store_id, query, max, per_file, filter).await?;
+      let results = relative_results(std::mem::take(&mut response.results), path);
+      return Ok(JsonOutput::new(results, &response));
+   }
 
    // EmbedWorker's parallel workers cause hangs on Metal. Use CandleEmbedder directly.
    // This matches the single-threaded pattern used by huggingface/text-embeddings-inference.
@@ -245,52 +748,197 @@ async fn perform_search(
    let embedder = Arc::new(EmbedWorker::new()?);
 
    let file_system = LocalFileSystem::new();
-   let chunker = Chunker::default();
-   let sync_engine = SyncEngine::new(file_system, chunker, embedder.clone(), store.clone());
+   let chunker = ChainChunker::default();
+   let sync_engine = SyncEngine::new(file_system, chunker, embedder.clone(), store.clone())
+      .with_summarizer(summarize::from_config());
+   let cancel = util::cancel_on_ctrl_c();
+
+   sync_engine
+      .initial_sync(store_id, path, false, SyncActor::Search, &mut (), &cancel)
+      .await?;
+
+   let engine = SearchEngine::new(store, embedder);
+   let mut response = engine
+      .search(store_id, query, max, per_file, filter, retrieval, &cancel)
+      .await?;
+
+   let results = relative_results(std::mem::take(&mut response.results), path);
+   Ok(JsonOutput::new(results, &response))
+}
+
+/// Searches for every query in-process after a single sync, embedding them
+/// in one batch; see [`SearchEngine::search_batch`].
+async fn perform_batch_search(
+   queries: &[String],
+   scope: &SearchScope,
+   per_file: usize,
+   filter: SearchFilter<'_>,
+) -> Result<Vec<Result<JsonOutput>>> {
+   let store = store::open()?;
+   let cancel = util::cancel_on_ctrl_c();
+   let path = scope.search_path.as_path();
+   let to_output = |response: Result<types::SearchResponse>| {
+      response.map(|mut response| {
+         let results = relative_results(std::mem::take(&mut response.results), path);
+         JsonOutput::new(results, &response)
+      })
+   };
+
+   if scope.retrieval.keyword {
+      let responses = stream::iter(queries)
+         .map(|query| {
+            search::keyword_search(&*store, &scope.store_id, query, scope.max, per_file, filter)
+         })
+         .buffered(search::BATCH_CONCURRENCY)
+         .collect::<Vec<_>>()
+         .await;
+      return Ok(responses.into_iter().map(to_output).collect());
+   }
+
+   // EmbedWorker's parallel workers cause hangs on Metal. Use CandleEmbedder
+   // directly.
+   #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+   let embedder = Arc::new(CandleEmbedder::new()?);
+   #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+   let embedder = Arc::new(EmbedWorker::new()?);
 
+   let sync_engine = SyncEngine::new(
+      LocalFileSystem::new(),
+      ChainChunker::default(),
+      embedder.clone(),
+      store.clone(),
+   )
+   .with_summarizer(summarize::from_config());
    sync_engine
-      .initial_sync(store_id, path, false, &mut ())
+      .initial_sync(&scope.store_id, path, false, SyncActor::Search, &mut (), &cancel)
       .await?;
 
    let engine = SearchEngine::new(store, embedder);
-   let response = engine
-      .search(store_id, query, max, per_file, None, rerank)
+   let responses = engine
+      .search_batch(&scope.store_id, queries, scope.max, per_file, filter, scope.retrieval, &cancel)
       .await?;
+   Ok(responses.into_iter().map(to_output).collect())
+}
+
+/// Converts engine results to output results with paths relative to `root`.
+fn relative_results(results: Vec<types::SearchResult>, root: &Path) -> Vec<SearchResult> {
+   let root_str = root.to_string_lossy().into_owned();
 
-   let root_str = path.to_string_lossy().into_owned();
+   let relative = |path: &Path| -> PathBuf {
+      path
+         .strip_prefix(&root_str)
+         .unwrap_or(path)
+         .to_string_lossy()
+         .trim_start_matches('/')
+         .into()
+   };
 
-   let results = response
-      .results
+   results
       .into_iter()
       .map(|r| {
-         let rel_path = r
-            .path
-            .strip_prefix(&root_str)
-            .unwrap_or(&r.path)
-            .to_string_lossy()
-            .trim_start_matches('/')
-            .into();
+         let linked = r
+            .linked
+            .into_iter()
+            .map(|l| Linked { path: relative(&l.path), ..Linked::from(l) })
+            .collect();
 
          SearchResult {
-            path:       rel_path,
-            score:      r.score,
-            content:    r.content.into_string(),
+            path: relative(&r.path),
+            score: r.score,
+            content: r.content.into_string(),
             chunk_type: r.chunk_type.map(|ct| ct.as_lowercase_str().to_string()),
             start_line: Some(r.start_line as usize),
-            end_line:   Some((r.start_line + r.num_lines) as usize),
-            is_anchor:  r.is_anchor,
+            end_line: Some((r.start_line + r.num_lines) as usize),
+            is_anchor: r.is_anchor,
+            language: r.language.map(String::from),
+            context_prev: r.context_prev.map(String::from),
+            context_next: r.context_next.map(String::from),
+            drift: None,
+            stored: None,
+            breakdown: r.breakdown,
+            blame: None,
+            linked,
          }
       })
-      .collect();
+      .collect()
+}
+
+/// Replaces each result's content with the current lines from disk and records
+/// whether they drifted from the indexed version.
+///
+/// Relative result paths are resolved against `base`. Module summaries stand
+/// for a directory rather than lines of a file, and are left as stored.
+fn hydrate_results(results: &mut [SearchResult], base: &Path, show_stored: bool) {
+   let mut files: HashMap<PathBuf, Option<String>> = HashMap::new();
+
+   for result in results {
+      let Some(start_line) = result.start_line else {
+         continue;
+      };
+      if result.chunk_type == Some(types::ChunkType::Module) {
+         continue;
+      }
+      let file = files.entry(result.path.clone()).or_insert_with(|| {
+         fs::read(base.join(&result.path))
+            .ok()
+            .map(|bytes| file::encoding::decode(&bytes, None).0.into_string())
+      });
+
+      let hydrated = hydrate::hydrate(file.as_deref(), start_line, &result.content);
+      result.drift = Some(hydrated.drift);
+      if let Some(current) = hydrated.content
+         && hydrated.drift.is_drifted()
+      {
+         let stored = std::mem::replace(&mut result.content, current);
+         if show_stored {
+            result.stored = Some(stored);
+         }
+      }
+   }
+}
+
+/// Attaches the last commit to change each result's file. With `author`,
+/// drops results whose commit author matches it by neither name nor email,
+/// case-insensitively.
+fn blame_results(results: &mut Vec<SearchResult>, base: &Path, author: Option<&str>) -> Result<()> {
+   let mut paths: Vec<PathBuf> = results.iter().map(|r| base.join(&r.path)).collect();
+   paths.sort();
+   paths.dedup();
+   let commits = git::last_commits(base, &paths)?;
+
+   for result in results.iter_mut() {
+      result.blame = commits
+         .get(&base.join(&result.path))
+         .cloned()
+         .map(Blame::from);
+   }
 
-   Ok(results)
+   if let Some(author) = author {
+      let author = author.to_lowercase();
+      results.retain(|r| {
+         r.blame.as_ref().is_some_and(|b| {
+            b.author.to_lowercase().contains(&author) || b.email.to_lowercase().contains(&author)
+         })
+      });
+   }
+   Ok(())
 }
 
 /// Formats and prints search results in human-readable form.
-fn format_results(results: &[SearchResult], query: &str, root: &Path, options: FormatOptions) {
-   const MAX_PREVIEW_LINES: usize = 12;
+fn format_results(output: &JsonOutput, query: &str, root: &Path, options: FormatOptions) {
+   let results = &output.results;
+   let max_preview_lines = config::get().preview_lines;
+
+   let banner = output
+      .progress
+      .map(|progress| format!("{progress} — results may be incomplete"));
 
    if options.compact {
+      // Compact output is a list of paths for other tools, so the banner
+      // goes to stderr.
+      if let Some(banner) = banner {
+         eprintln!("{banner}");
+      }
       for result in results {
          println!("{}", result.path.display());
       }
@@ -299,11 +947,33 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
 
    if options.plain {
       println!("\nSearch results for: {query}");
-      println!("Root: {}\n", root.display());
+      println!("Root: {}", root.display());
    } else {
       println!("\n{}", style(format!("Search results for: {query}")).bold());
-      println!("{}", style(format!("Root: {}\n", root.display())).dim());
+      println!("{}", style(format!("Root: {}", root.display())).dim());
    }
+   if let Some(banner) = banner {
+      if options.plain {
+         println!("{banner}");
+      } else {
+         println!("{}", style(banner).yellow());
+      }
+   }
+   if options.explain {
+      let route = output
+         .route
+         .map(|r| format!("Route: {}", route_description(r)));
+      let stages =
+         (!output.stages.is_empty()).then(|| format!("Stages: {}", stages_line(&output.stages)));
+      for line in route.into_iter().chain(stages) {
+         if options.plain {
+            println!("{line}");
+         } else {
+            println!("{}", style(line).dim());
+         }
+      }
+   }
+   println!();
 
    let display_results: Vec<_> = results
       .iter()
@@ -314,13 +984,18 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
       let start_line = result.start_line.unwrap_or(1);
       let lines: Vec<&str> = result.content.lines().collect();
       let total_lines = lines.len();
-      let show_all = options.content || total_lines <= MAX_PREVIEW_LINES;
+      let show_all = options.content || total_lines <= max_preview_lines;
       let display_lines = if show_all {
          total_lines
       } else {
-         MAX_PREVIEW_LINES
+         max_preview_lines
       };
       let line_num_width = format!("{}", start_line + display_lines).len();
+      let (before, after) = if options.context {
+         context_previews(result)
+      } else {
+         (Vec::new(), Vec::new())
+      };
 
       if options.plain {
          print!("{}) {}:{}", i + 1, result.path.display(), start_line);
@@ -328,9 +1003,22 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
          if options.scores {
             print!(" (score: {:.3})", result.score);
          }
+         if let Some(marker) = drift_marker(result.drift) {
+            print!(" [{marker}]");
+         }
+         if let Some(blame) = &result.blame {
+            print!(" ({})", blame_label(blame));
+         }
 
          println!();
 
+         if options.explain
+            && let Some(breakdown) = &result.breakdown
+         {
+            println!("{:>line_num_width$}   {}", "", explain_line(breakdown, result.score));
+         }
+
+         print_context(&before, line_num_width, true);
          for (j, line) in lines.iter().take(display_lines).enumerate() {
             let line_num = start_line + j;
             println!("{line_num:>line_num_width$} | {line}");
@@ -340,6 +1028,7 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
             let remaining = total_lines - display_lines;
             println!("{:>width$} | ... (+{} more lines)", "", remaining, width = line_num_width);
          }
+         print_context(&after, line_num_width, true);
       } else {
          print!("{}", style(format!("{}) ", i + 1)).bold().cyan());
          print!("{}:{}", style(result.path.display()).green(), start_line);
@@ -347,9 +1036,26 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
          if options.scores {
             print!(" {}", style(format!("(score: {:.3})", result.score)).dim());
          }
+         if let Some(marker) = drift_marker(result.drift) {
+            print!(" {}", style(format!("[{marker}]")).yellow());
+         }
+         if let Some(blame) = &result.blame {
+            print!(" {}", style(format!("({})", blame_label(blame))).magenta());
+         }
 
          println!();
 
+         if options.explain
+            && let Some(breakdown) = &result.breakdown
+         {
+            println!(
+               "{:>line_num_width$}   {}",
+               "",
+               style(explain_line(breakdown, result.score)).dim()
+            );
+         }
+
+         print_context(&before, line_num_width, false);
          for (j, line) in lines.iter().take(display_lines).enumerate() {
             let line_num = start_line + j;
             println!(
@@ -371,8 +1077,199 @@ fn format_results(results: &[SearchResult], query: &str, root: &Path, options: F
                width = line_num_width
             );
          }
+         print_context(&after, line_num_width, false);
+      }
+
+      if let Some(stored) = &result.stored {
+         let header = "indexed version:";
+         if options.plain {
+            println!("{:>width$} | {header}", "", width = line_num_width);
+         } else {
+            println!(
+               "{:>width$} {} {}",
+               "",
+               style("|").dim(),
+               style(header).dim(),
+               width = line_num_width
+            );
+         }
+         for (j, line) in stored.lines().enumerate() {
+            let line_num = start_line + j;
+            if options.plain {
+               println!("{line_num:>line_num_width$} - {line}");
+            } else {
+               println!(
+                  "{:>width$} {} {}",
+                  style(line_num).dim(),
+                  style("-").red(),
+                  style(line).dim(),
+                  width = line_num_width
+               );
+            }
+         }
+      }
+
+      for linked in &result.linked {
+         if options.plain {
+            println!(
+               "{:>width$}   linked via {}: {}:{}",
+               "",
+               linked.symbol,
+               linked.path.display(),
+               linked.start_line,
+               width = line_num_width
+            );
+         } else {
+            println!(
+               "{:>width$}   {} {}:{}",
+               "",
+               style(format!("linked via {}:", linked.symbol)).dim(),
+               style(linked.path.display()).green(),
+               linked.start_line,
+               width = line_num_width
+            );
+         }
       }
 
       println!();
    }
 }
+
+/// Lines of the neighbouring chunks shown around a result by
+/// `--show-context`: the end of the previous chunk and the start of the next,
+/// without the lines they share with the result.
+fn context_previews(result: &SearchResult) -> (Vec<&str>, Vec<&str>) {
+   let lines: Vec<&str> = result.content.lines().collect();
+
+   let before = result
+      .context_prev
+      .as_deref()
+      .map_or_else(Vec::new, |prev| {
+         let prev: Vec<&str> = prev.lines().collect();
+         let end = prev.len() - overlap(&prev, &lines);
+         prev[end.saturating_sub(CONTEXT_PREVIEW_LINES)..end].to_vec()
+      });
+   let after = result
+      .context_next
+      .as_deref()
+      .map_or_else(Vec::new, |next| {
+         let next: Vec<&str> = next.lines().collect();
+         let start = overlap(&lines, &next);
+         next[start..]
+            .iter()
+            .take(CONTEXT_PREVIEW_LINES)
+            .copied()
+            .collect()
+      });
+   (before, after)
+}
+
+/// Number of lines that end `first` and also start `second`, as when
+/// sliding-window chunks overlap.
+fn overlap(first: &[&str], second: &[&str]) -> usize {
+   (1..=first.len().min(second.len()))
+      .rev()
+      .find(|&n| first[first.len() - n..] == second[..n])
+      .unwrap_or(0)
+}
+
+/// Prints lines of a neighbouring chunk, dimmed and without line numbers
+/// since chunks need not be contiguous.
+fn print_context(lines: &[&str], line_num_width: usize, plain: bool) {
+   for line in lines {
+      if plain {
+         println!("{:>line_num_width$} : {line}", "");
+      } else {
+         println!("{:>line_num_width$} {} {}", "", style(":").dim(), style(line).dim());
+      }
+   }
+}
+
+/// Drops score breakdowns so they are only reported with `--explain`.
+fn strip_breakdowns(results: &mut [SearchResult]) {
+   for result in results {
+      result.breakdown = None;
+   }
+}
+
+/// Renders the component scores of a result, followed by any boosts applied
+/// after fusion and the cross-encoder score, e.g.
+/// `dense 0.812 · fts 0.500 · colbert - → 0.734 · structure ×1.25 = 0.918`.
+fn explain_line(breakdown: &ScoreBreakdown, score: f32) -> String {
+   let component =
+      |score: Option<f32>| score.map_or_else(|| "-".to_string(), |s| format!("{s:.3}"));
+   let mut line = format!(
+      "dense {} · fts {} · colbert {} → {:.3}",
+      component(breakdown.dense),
+      component(breakdown.fts),
+      component(breakdown.colbert),
+      breakdown.fused
+   );
+
+   let boosts = [
+      ("structure", breakdown.structure),
+      ("file-type", breakdown.file_type),
+      ("anchor", breakdown.anchor),
+      ("symbol", breakdown.symbol),
+   ];
+   let mut boosted = false;
+   for (name, multiplier) in boosts {
+      if let Some(multiplier) = multiplier {
+         write!(line, " · {name} ×{multiplier:.2}").unwrap();
+         boosted = true;
+      }
+   }
+   if boosted {
+      write!(line, " = {score:.3}").unwrap();
+   }
+   if let Some(cross) = breakdown.cross {
+      write!(line, " · cross {cross:.3}").unwrap();
+   }
+   line
+}
+
+/// Stage timings as `embedding 12ms, retrieval 30ms, ...`.
+fn stages_line(stages: &[StageTiming]) -> String {
+   stages
+      .iter()
+      .map(|s| format!("{} {}ms", s.stage.as_str(), s.ms))
+      .collect::<Vec<_>>()
+      .join(", ")
+}
+
+/// Explains how a query was routed, for `--explain`.
+const fn route_description(route: QueryRoute) -> &'static str {
+   match route {
+      QueryRoute::Semantic => "semantic",
+      QueryRoute::Path => "path (only files whose path ends with the query)",
+      QueryRoute::Symbol => "symbol (chunks mentioning the symbol boosted)",
+   }
+}
+
+/// Author and day of a result's last commit.
+fn blame_label(blame: &Blame) -> String {
+   let day = blame.date.get(..10).unwrap_or(&blame.date);
+   format!("{}, {day}", blame.author)
+}
+
+/// Label shown next to results whose code no longer matches the index.
+const fn drift_marker(drift: Option<Drift>) -> Option<&'static str> {
+   match drift {
+      Some(Drift::Changed) => Some("changed since indexing"),
+      Some(Drift::Missing) => Some("file missing"),
+      Some(Drift::Fresh) | None => None,
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn overlap_finds_shared_lines() {
+      assert_eq!(overlap(&["a", "b", "c"], &["b", "c", "d"]), 2);
+      assert_eq!(overlap(&["a", "b"], &["c"]), 0);
+      assert_eq!(overlap(&["a", "a"], &["a", "a", "b"]), 2);
+      assert_eq!(overlap(&[], &["a"]), 0);
+   }
+}
@@ -2,54 +2,142 @@
 //!
 //! Starts a background server that maintains an index, watches for file
 //! changes, and responds to search requests over Unix domain sockets.
-//! Automatically shuts down after a period of inactivity.
+//! Frees its models after a period of inactivity and shuts down after a
+//! longer one.
 
 use std::{
+   collections::{BTreeSet, HashMap, HashSet, VecDeque},
    path::{Path, PathBuf},
    sync::{
       Arc,
-      atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering},
+      atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
    },
-   time::{Duration, Instant},
+   time::{Duration, Instant, SystemTime},
 };
 
 use console::style;
 use futures::stream::{self, StreamExt};
 use parking_lot::Mutex;
-use tokio::{signal, sync::watch, time};
+use tokio::{
+   io::{self, WriteHalf},
+   signal,
+   sync::{mpsc, watch},
+   time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use crate::{
-   Result, Str,
-   chunker::Chunker,
-   config,
-   embed::Embedder,
-   file::{FileSystem, FileWatcher, IgnorePatterns, LocalFileSystem, WatchAction},
+   Error, Result, Str,
+   chunker::{ChainChunker, Chunker, bridge, embed_text},
+   config, docs,
+   embed::{self, Embedder},
+   file::{self, FileSystem, FileWatcher, IgnorePatterns, LocalFileSystem, WatchAction},
    git,
+   grammar::GrammarManager,
    index_lock::IndexLock,
    ipc::{self, Request, Response, ServerStatus},
-   meta::{FileHash, MetaStore},
-   store::{LanceStore, SearchParams, Store},
-   types::{PreparedChunk, SearchResponse, SearchResult, SearchStatus, VectorRecord},
-   usock, version,
+   meta::{FileHash, MetaStore, SyncActor, SyncRun},
+   reranker::CrossEncoder,
+   search::{self, Retrieval, SearchFilter, glob::PathGlobs, links, route, testfile::TestFilter},
+   store::{KeywordParams, SearchParams, Store},
+   summarize::{self, Summarizer, SummaryCache},
+   sync::{self, BaseStore},
+   types::{
+      ChunkType, IndexProgress, LinkedResult, PreparedChunk, QueryRoute, SearchResponse,
+      SearchResult, SearchStage, SearchStatus, StageTiming, SyncProgress, VectorRecord,
+   },
+   usock, util, version,
+   workspace::CargoWorkspace,
 };
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-use crate::embed::candle::CandleEmbedder;
-#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
-use crate::embed::worker::EmbedWorker;
+
+/// Query run by [`Server::preload`]; its results are thrown away.
+const PRELOAD_QUERY: &str = "warm up";
+
+/// Requests a client may have read ahead of the one being answered.
+const MAX_QUEUED_REQUESTS: usize = 16;
+
+/// A request read from a client, or the error a malformed frame is answered
+/// with.
+type ClientRequest = Result<Request, String>;
+
+type ClientWriter = WriteHalf<usock::Stream>;
+
+/// Recent search results, tagged with the write generation they were read at.
+type SearchCache = moka::future::Cache<SearchQuery, (u64, Arc<SearchResponse>)>;
+
+/// The parameters of a `Request::Search` that decide its results, which also
+/// key the search cache.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SearchQuery {
+   query:         String,
+   limit:         usize,
+   path:          Option<PathBuf>,
+   retrieval:     Retrieval,
+   indexed_after: Option<u64>,
+   crate_name:    Option<String>,
+   tests:         TestFilter,
+   include:       Vec<String>,
+   exclude:       Vec<String>,
+}
+
+/// How far the daemon has been asked to stop. Requests only ever escalate it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StopMode {
+   Running,
+   /// Finish the files being indexed and save their metadata, then exit.
+   Graceful,
+   /// Exit without waiting for indexing.
+   Force,
+}
 
 /// The main server state managing indexing, search, and file watching.
 struct Server {
-   store:         Arc<dyn Store>,
-   embedder:      Arc<dyn Embedder>,
-   chunker:       Chunker,
-   meta_store:    Mutex<MetaStore>,
-   store_id:      String,
-   root:          PathBuf,
-   indexing:      AtomicBool,
-   progress:      AtomicU8,
-   launch_time:   Instant,
-   last_activity: AtomicU64,
-   shutdown:      watch::Sender<bool>,
+   store:          Arc<dyn Store>,
+   embedder:       Arc<dyn Embedder>,
+   cross_encoder:  CrossEncoder,
+   chunker:        ChainChunker,
+   summarizer:     Option<Arc<dyn Summarizer>>,
+   summaries:      Mutex<SummaryCache>,
+   meta_store:     Mutex<MetaStore>,
+   store_id:       String,
+   /// Store whose embeddings a branch or worktree store reuses.
+   base_store:     Option<BaseStore>,
+   /// Whether `store_id` is that of the checked-out branch.
+   tracks_branch:  bool,
+   root:           PathBuf,
+   workspace:      CargoWorkspace,
+   indexing:       AtomicBool,
+   progress:       AtomicU8,
+   /// Files the running sync has gone through, out of `sync_total`.
+   sync_done:      AtomicUsize,
+   sync_total:     AtomicUsize,
+   /// [`Server::clock`] when the running sync started.
+   sync_started:   AtomicU64,
+   /// Progress of the running or last sync, followed by `Request::Progress`.
+   sync_progress:  watch::Sender<SyncProgress>,
+   launch_time:    Instant,
+   last_activity:  AtomicU64,
+   /// [`Server::clock`] when indexing last embedded a file.
+   last_embed:     AtomicU64,
+   /// Rows inserted or files deleted since the store was last optimized.
+   pending_writes: AtomicU64,
+   /// Bumped on every write to the store, so that cached results read
+   /// before it are never served.
+   generation:     AtomicU64,
+   /// `None` when `search_cache_entries` is 0.
+   search_cache:   Option<SearchCache>,
+   shutdown:       watch::Sender<StopMode>,
+   /// Set once a stop is requested; no new files start indexing after it.
+   draining:       AtomicBool,
+   /// Syncs and watcher updates, awaited by a graceful stop.
+   tasks:          TaskTracker,
+   /// Fired when the daemon exits so that in-flight indexing and searches
+   /// stop early. Each search runs under a child token of its own.
+   cancel:         CancellationToken,
+   /// Running searches by client request id, for `Request::Cancel`, tagged
+   /// with a sequence number from `search_seq`.
+   searches:       Mutex<HashMap<u64, (u64, CancellationToken)>>,
+   search_seq:     AtomicU64,
 }
 
 impl Server {
@@ -69,6 +157,140 @@ impl Server {
          .saturating_sub(self.last_activity.load(Ordering::Relaxed));
       Duration::from_millis(timestamp)
    }
+
+   /// Time since a client request or indexing last needed the models.
+   fn models_idle_duration(&self) -> Duration {
+      let last_used = self
+         .last_activity
+         .load(Ordering::Relaxed)
+         .max(self.last_embed.load(Ordering::Relaxed));
+      Duration::from_millis(self.clock().saturating_sub(last_used))
+   }
+
+   /// Frees the embedding and cross-encoder models, returning whether either
+   /// was loaded. Both load again when next used.
+   fn unload_models(&self) -> bool {
+      let embedder = self.embedder.unload();
+      let cross_encoder = self.cross_encoder.unload();
+      embedder || cross_encoder
+   }
+
+   fn record_writes(&self, count: usize) {
+      self
+         .pending_writes
+         .fetch_add(count as u64, Ordering::Relaxed);
+      self.generation.fetch_add(1, Ordering::Release);
+      if let Some(cache) = &self.search_cache {
+         cache.invalidate_all();
+      }
+   }
+
+   /// Results of an identical search since the last write to the store.
+   async fn cached_search(&self, search: &SearchQuery) -> Option<Response> {
+      let (generation, response) = self.search_cache.as_ref()?.get(search).await?;
+      (generation == self.generation.load(Ordering::Acquire))
+         .then(|| Response::Search(SearchResponse::clone(&response)))
+   }
+
+   /// Caches the results of a search that started at `generation`, unless the
+   /// store has been written since or the index was still being built.
+   async fn cache_search(&self, search: SearchQuery, generation: u64, response: &Response) {
+      let (Some(cache), Response::Search(results)) = (&self.search_cache, response) else {
+         return;
+      };
+      if results.status == SearchStatus::Ready
+         && generation == self.generation.load(Ordering::Acquire)
+      {
+         cache
+            .insert(search, (generation, Arc::new(results.clone())))
+            .await;
+      }
+   }
+
+   /// Optimizes the store once `threshold` writes have accumulated and no
+   /// client has made a request for `idle`.
+   async fn compact_if_idle(&self, threshold: u64, idle: Duration) {
+      let pending = self.pending_writes.load(Ordering::Relaxed);
+      if pending < threshold || self.indexing.load(Ordering::Relaxed) || self.idle_duration() < idle
+      {
+         return;
+      }
+
+      let lock = match IndexLock::acquire(&self.store_id) {
+         Ok(lock) => lock,
+         Err(e) => {
+            tracing::warn!("Skipping compaction, failed to acquire index lock: {e}");
+            return;
+         },
+      };
+
+      let started = Instant::now();
+      let compacted = match self.store.optimize(&self.store_id).await {
+         Ok(()) => {
+            self.pending_writes.fetch_sub(pending, Ordering::Relaxed);
+            tracing::info!(
+               "Compacted store after {pending} writes in {:.1}s",
+               started.elapsed().as_secs_f64()
+            );
+            true
+         },
+         Err(e) => {
+            tracing::warn!("Background compaction failed: {e}");
+            false
+         },
+      };
+      drop(lock);
+
+      // Compaction rewrites the files and indexes that were warm.
+      if compacted {
+         self.warm_up().await;
+      }
+   }
+
+   /// Loads the store into memory so the first search after startup or
+   /// compaction does not pay for cold reads, if `warm_up` is enabled.
+   async fn warm_up(&self) {
+      if !config::get().warm_up {
+         return;
+      }
+      let started = Instant::now();
+      match self.store.warm_up(&self.store_id).await {
+         Ok(()) => {
+            tracing::info!("Warmed up store in {:.1}s", started.elapsed().as_secs_f64());
+         },
+         Err(e) => tracing::warn!("Store warm-up failed: {e}"),
+      }
+   }
+
+   /// Loads the embedding models and the store, then runs a throwaway search
+   /// through both, so that the first real search pays for neither.
+   async fn preload(&self) -> Result<()> {
+      let query = self.embedder.encode_query(PRELOAD_QUERY).await?;
+      if self.store.is_empty(&self.store_id).await? {
+         return Ok(());
+      }
+      self.store.warm_up(&self.store_id).await?;
+      self
+         .store
+         .search(SearchParams {
+            store_id:      &self.store_id,
+            query_text:    PRELOAD_QUERY,
+            query_vector:  &query.dense,
+            query_colbert: &query.colbert,
+            limit:         1,
+            path_filter:   None,
+            path_globs:    None,
+            indexed_after: None,
+            crate_name:    None,
+            rerank:        true,
+            rerank_depth:  None,
+            nprobes:       None,
+            exact:         false,
+            cancel:        Some(&self.cancel),
+         })
+         .await?;
+      Ok(())
+   }
 }
 
 /// Executes the serve command, starting a long-running daemon server.
@@ -76,6 +298,9 @@ pub async fn execute(path: Option<PathBuf>, store_id: Option<String>) -> Result<
    let root = std::env::current_dir()?;
    let serve_path = path.unwrap_or_else(|| root.clone());
 
+   // Stores named after the checked-out branch change with it
+   let tracks_branch = store_id.is_none()
+      && (config::get().branch_stores || git::main_worktree(&serve_path).is_some());
    let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&serve_path), Ok)?;
 
    let listener = match usock::Listener::bind(&resolved_store_id).await {
@@ -92,19 +317,14 @@ pub async fn execute(path: Option<PathBuf>, store_id: Option<String>) -> Result<
    println!("Path: {}", style(serve_path.display()).dim());
    println!("Store ID: {}", style(&resolved_store_id).cyan());
 
-   let store: Arc<dyn Store> = Arc::new(LanceStore::new()?);
-   // EmbedWorker's parallel workers cause hangs on Metal. Use CandleEmbedder directly.
-   // This matches the single-threaded pattern used by huggingface/text-embeddings-inference.
-   #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-   let embedder: Arc<dyn Embedder> = Arc::new(CandleEmbedder::new()?);
-   #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
-   let embedder: Arc<dyn Embedder> = Arc::new(EmbedWorker::new()?);
+   let (embedder, store) = super::open_backends()?;
 
    if !embedder.is_ready() {
       println!("{}", style("Waiting for embedder to initialize...").yellow());
       time::sleep(Duration::from_millis(500)).await;
    }
 
+   let lock = IndexLock::acquire(&resolved_store_id)?;
    let mut meta_store = MetaStore::load(&resolved_store_id)?;
    let model_changed = meta_store.model_mismatch();
 
@@ -113,171 +333,450 @@ pub async fn execute(path: Option<PathBuf>, store_id: Option<String>) -> Result<
       meta_store.reset_for_model_change();
       meta_store.save()?;
    }
+   if meta_store.needs_rebuild() {
+      meta_store.rebuild_from(store.get_file_hashes(&resolved_store_id).await?);
+      meta_store.save()?;
+   }
+   sync::adopt_root(&store, &resolved_store_id, &mut meta_store, &serve_path).await?;
+   sync::share_base(&resolved_store_id, &mut meta_store)?;
+   drop(lock);
 
    let is_empty = store.is_empty(&resolved_store_id).await?;
-   let needs_initial_index = is_empty || model_changed;
+   let rehash = meta_store.hash_mismatch();
+   // Branches may have been switched while no daemon served the store, and
+   // the files a store shares may have changed in its base
+   let checked_out = tracks_branch && meta_store.head() != git::head_commit(&serve_path).as_deref();
+   let needs_initial_index =
+      is_empty || model_changed || rehash || checked_out || meta_store.base_moved();
+   // A store sharing its base's rows reads their embeddings already
+   let base_store = if meta_store.base().is_none() {
+      BaseStore::resolve(&resolved_store_id, &serve_path)
+   } else {
+      None
+   };
 
-   let (shutdown_tx, shutdown_rx) = watch::channel(false);
+   let (shutdown_tx, mut shutdown_rx) = watch::channel(StopMode::Running);
 
    let server = Arc::new(Server {
       store,
       embedder,
-      chunker: Chunker::default(),
+      cross_encoder: CrossEncoder::new(),
+      chunker: ChainChunker::default(),
+      summarizer: summarize::from_config(),
+      summaries: Mutex::new(SummaryCache::load(&resolved_store_id)),
       meta_store: Mutex::new(meta_store),
+      base_store,
+      tracks_branch,
       store_id: resolved_store_id,
+      workspace: CargoWorkspace::discover(&serve_path),
       root: serve_path,
-      indexing: AtomicBool::new(needs_initial_index),
+      indexing: AtomicBool::new(false),
       progress: AtomicU8::new(0),
+      sync_done: AtomicUsize::new(0),
+      sync_total: AtomicUsize::new(0),
+      sync_started: AtomicU64::new(0),
+      sync_progress: watch::Sender::new(SyncProgress {
+         processed:    0,
+         indexed:      0,
+         total:        0,
+         current_file: None,
+      }),
       last_activity: AtomicU64::new(0),
+      last_embed: AtomicU64::new(0),
+      pending_writes: AtomicU64::new(0),
+      generation: AtomicU64::new(0),
+      search_cache: (config::get().search_cache_entries > 0).then(|| {
+         SearchCache::builder()
+            .max_capacity(config::get().search_cache_entries)
+            .build()
+      }),
       launch_time: Instant::now(),
-      shutdown: shutdown_tx.clone(),
+      shutdown: shutdown_tx,
+      draining: AtomicBool::new(false),
+      tasks: TaskTracker::new(),
+      cancel: CancellationToken::new(),
+      searches: Mutex::new(HashMap::new()),
+      search_seq: AtomicU64::new(0),
    });
 
    if needs_initial_index {
       let reason = if model_changed {
          "Embedding models changed; rebuilding index..."
+      } else if rehash && !is_empty {
+         "File hash algorithm changed; re-hashing files..."
+      } else if !is_empty {
+         "Syncing changes since the last checkout..."
       } else {
          "Store empty, performing initial index..."
       };
 
       println!("{}", style(reason).yellow());
-      let server_clone = Arc::clone(&server);
+      server.start_sync();
+   } else if config::get().preload_models {
+      let preload_server = Arc::clone(&server);
       tokio::spawn(async move {
-         if let Err(e) = server_clone.initial_sync().await {
-            tracing::error!("Initial sync failed: {}", e);
+         let started = Instant::now();
+         match preload_server.preload().await {
+            Ok(()) => {
+               tracing::info!("Preloaded models in {:.1}s", started.elapsed().as_secs_f64());
+            },
+            Err(e) => tracing::warn!("Model preload failed: {e}"),
          }
       });
+   } else {
+      let warm_server = Arc::clone(&server);
+      tokio::spawn(async move { warm_server.warm_up().await });
    }
 
    let _watcher = server.start_watcher()?;
 
    let idle_server = Arc::clone(&server);
-   let idle_shutdown = shutdown_tx.clone();
    let cfg = config::get();
-   let idle_timeout = Duration::from_secs(cfg.idle_timeout_secs);
+   let idle_timeout =
+      (cfg.idle_timeout_secs > 0).then(|| Duration::from_secs(cfg.idle_timeout_secs));
+   let unload_after =
+      (cfg.model_unload_secs > 0).then(|| Duration::from_secs(cfg.model_unload_secs));
    let idle_check_interval = Duration::from_secs(cfg.idle_check_interval_secs);
    tokio::spawn(async move {
       loop {
          time::sleep(idle_check_interval).await;
-         if idle_server.idle_duration() > idle_timeout {
+         if idle_timeout.is_some_and(|timeout| idle_server.idle_duration() > timeout) {
             println!("{}", style("Idle timeout reached, shutting down...").yellow());
-            let _ = idle_shutdown.send(true);
+            idle_server.request_stop(StopMode::Graceful);
             break;
          }
+         if unload_after.is_some_and(|after| idle_server.models_idle_duration() > after)
+            && !idle_server.indexing.load(Ordering::Relaxed)
+            && idle_server.unload_models()
+         {
+            tracing::info!("Unloaded models after {}s idle", cfg.model_unload_secs);
+         }
       }
    });
 
+   if cfg.compact_after_writes > 0 {
+      let compact_server = Arc::clone(&server);
+      let compact_threshold = cfg.compact_after_writes;
+      let compact_idle = Duration::from_secs(cfg.compact_idle_secs);
+      tokio::spawn(async move {
+         loop {
+            time::sleep(idle_check_interval).await;
+            compact_server
+               .compact_if_idle(compact_threshold, compact_idle)
+               .await;
+         }
+      });
+   }
+
    println!("\n{}", style("Server listening").green());
    println!("{}", style("Press Ctrl+C to stop").dim());
 
+   // Clients are still served while a graceful stop waits for indexing, so
+   // that they can search or force the stop.
    let accept_server = Arc::clone(&server);
-   let mut accept_shutdown = shutdown_rx.clone();
    let accept_handle = tokio::spawn(async move {
       loop {
-         tokio::select! {
-            result = listener.accept() => {
-               match result {
-                  Ok(stream) => {
-                     let client_server = Arc::clone(&accept_server);
-                     tokio::spawn(async move { client_server.handle_client(stream).await });
-                  }
-                  Err(e) => {
-                     tracing::error!("Accept error: {}", e);
-                  }
-               }
-            }
-            _ = accept_shutdown.changed() => {
-               if *accept_shutdown.borrow() {
-                  break;
-               }
-            }
+         match listener.accept().await {
+            Ok(stream) => {
+               let client_server = Arc::clone(&accept_server);
+               tokio::spawn(async move { client_server.handle_client(stream).await });
+            },
+            Err(e) => {
+               tracing::error!("Accept error: {}", e);
+            },
          }
       }
    });
 
    tokio::select! {
-      _ = signal::ctrl_c() => {
-         println!("\n{}", style("Shutting down...").yellow());
-         let _ = shutdown_tx.send(true);
+      _ = signal::ctrl_c() => server.request_stop(StopMode::Graceful),
+      _ = shutdown_rx.wait_for(|mode| *mode != StopMode::Running) => {}
+   }
+
+   if *shutdown_rx.borrow() == StopMode::Graceful {
+      println!(
+         "\n{}",
+         style("Shutting down after indexing in progress, Ctrl+C again to stop now...").yellow()
+      );
+      tokio::select! {
+         () = server.drain() => {}
+         _ = signal::ctrl_c() => server.request_stop(StopMode::Force),
+         _ = shutdown_rx.wait_for(|mode| *mode == StopMode::Force) => {}
       }
-      () = async {
-         let mut rx = shutdown_rx.clone();
-         loop {
-            rx.changed().await.ok();
-            if *rx.borrow() {
-               break;
-            }
-         }
-      } => {}
    }
 
+   server.cancel.cancel();
+   // Saving unlocked could overwrite meta written by a concurrent `index`.
+   match IndexLock::acquire(&server.store_id) {
+      Ok(_lock) => {
+         if let Err(e) = server.meta_store.lock().save() {
+            tracing::error!("Failed to save meta on shutdown: {}", e);
+         }
+      },
+      Err(e) => {
+         tracing::error!("Skipping meta save on shutdown, failed to acquire index lock: {e}")
+      },
+   }
+   // Dropping the listener removes the socket.
    accept_handle.abort();
+   let _ = accept_handle.await;
 
    println!("{}", style("Server stopped").green());
    Ok(())
 }
 
 impl Server {
-   async fn handle_client(self: &Arc<Self>, mut stream: usock::Stream) {
+   async fn handle_client(self: &Arc<Self>, stream: usock::Stream) {
       self.touch();
 
+      let (mut reader, mut writer) = io::split(stream);
+      let (request_tx, mut requests) = mpsc::channel(MAX_QUEUED_REQUESTS);
+      // Requests are read on their own task so that a cancel or a hang-up is
+      // noticed while a search runs.
+      let reader_task = tokio::spawn(async move {
+         let mut buffer = ipc::SocketBuffer::new();
+         loop {
+            let request = match buffer.recv_request(&mut reader).await {
+               Ok(req) => Ok(req),
+               Err(Error::Ipc(e)) if e.is_malformed_frame() => {
+                  tracing::debug!("Rejected client request: {}", e);
+                  Err(e.to_string())
+               },
+               Err(e) => {
+                  tracing::debug!("Client read error: {}", e);
+                  break;
+               },
+            };
+            if request_tx.send(request).await.is_err() {
+               break;
+            }
+         }
+      });
+
       let mut buffer = ipc::SocketBuffer::new();
-      let mut shutting_down = false;
+      let mut queued = VecDeque::new();
+      let mut stop = None;
 
       loop {
-         let request: Request = match buffer.recv(&mut stream).await {
-            Ok(req) => req,
-            Err(e) => {
-               if e.to_string().contains("failed to read length") {
+         let request = if let Some(request) = queued.pop_front() {
+            request
+         } else if let Some(request) = requests.recv().await {
+            request
+         } else {
+            break;
+         };
+
+         self.touch();
+
+         let request = match request {
+            Ok(request) => request,
+            Err(message) => {
+               if let Err(e) = buffer.send(&mut writer, &Response::Error { message }).await {
+                  tracing::debug!("Client write error: {}", e);
                   break;
                }
-               tracing::debug!("Client read error: {}", e);
-               break;
+               continue;
             },
          };
 
-         self.touch();
-
          let response = match request {
             Request::Hello { .. } => Response::Hello { git_hash: version::GIT_HASH.to_string() },
-            Request::Search { query, limit, path, rerank } => {
-               self.handle_search(query, limit, path, rerank).await
-            },
-            Request::Health => Response::Health {
-               status: ServerStatus {
-                  indexing: self.indexing.load(Ordering::Relaxed),
-                  progress: self.progress.load(Ordering::Relaxed),
-                  files:    0,
-               },
+            Request::Search {
+               query,
+               limit,
+               path,
+               rerank,
+               exact,
+               keyword,
+               indexed_after,
+               crate_name,
+               tests,
+               include,
+               exclude,
+               rerank_depth,
+               cross_rerank,
+               request_id,
+            } => {
+               let search = SearchQuery {
+                  query,
+                  limit,
+                  path,
+                  retrieval: Retrieval {
+                     rerank,
+                     exact,
+                     keyword,
+                     rerank_depth,
+                     cross: cross_rerank,
+                  },
+                  indexed_after,
+                  crate_name,
+                  tests,
+                  include,
+                  exclude,
+               };
+               if let Some(response) = self.cached_search(&search).await {
+                  response
+               } else {
+                  let generation = self.generation.load(Ordering::Acquire);
+                  let cancel = self.cancel.child_token();
+                  let supervised = self.supervise_search(
+                     self.handle_search(&search, &cancel),
+                     &cancel,
+                     request_id,
+                     &mut writer,
+                     &mut requests,
+                     &mut queued,
+                  );
+                  let Some(response) = supervised.await else {
+                     break;
+                  };
+                  self.cache_search(search, generation, &response).await;
+                  response
+               }
             },
-            Request::Shutdown => {
-               shutting_down = true;
+            Request::Health => Response::Health { status: self.status().await },
+            Request::Shutdown { force } => {
+               stop = Some(if force {
+                  StopMode::Force
+               } else {
+                  StopMode::Graceful
+               });
                Response::Shutdown { success: true }
             },
+            Request::History => Response::History {
+               runs: self.meta_store.lock().history().iter().cloned().collect(),
+            },
+            Request::Unload => Response::Unload { unloaded: self.unload_models() },
+            Request::WarmUp => {
+               let started = Instant::now();
+               match self.preload().await {
+                  Ok(()) => Response::WarmUp { ms: started.elapsed().as_millis() as u64 },
+                  Err(e) => Response::Error { message: format!("warm-up failed: {e}") },
+               }
+            },
+            Request::Sync => Response::Sync { started: self.start_sync() },
+            Request::Progress => {
+               if let Err(e) = self.stream_progress(&mut buffer, &mut writer).await {
+                  tracing::debug!("Client write error: {}", e);
+                  break;
+               }
+               continue;
+            },
+            Request::Cancel { request_id } => {
+               Response::Cancel { found: self.cancel_search(request_id) }
+            },
          };
 
-         if let Err(e) = buffer.send(&mut stream, &response).await {
+         if let Err(e) = buffer.send(&mut writer, &response).await {
             tracing::debug!("Client write error: {}", e);
             break;
          }
 
-         if shutting_down {
-            let _ = self.shutdown.send(true);
+         if let Some(mode) = stop {
+            self.request_stop(mode);
             break;
          }
       }
+
+      reader_task.abort();
    }
 
-   async fn handle_search(
+   /// Runs `search` while reading the client's next requests, so that it
+   /// stops early on a [`Request::Cancel`] for its `request_id`, from this or
+   /// any other connection, or once the client hangs up. Other requests read
+   /// meanwhile wait in `queued`. Returns `None` if the client hung up.
+   async fn supervise_search(
       &self,
-      query: String,
-      limit: usize,
-      path: Option<PathBuf>,
-      rerank: bool,
-   ) -> Response {
+      search: impl Future<Output = Response>,
+      cancel: &CancellationToken,
+      request_id: Option<u64>,
+      writer: &mut ClientWriter,
+      requests: &mut mpsc::Receiver<ClientRequest>,
+      queued: &mut VecDeque<ClientRequest>,
+   ) -> Option<Response> {
+      let registration = request_id.map(|id| {
+         let seq = self.search_seq.fetch_add(1, Ordering::Relaxed);
+         self.searches.lock().insert(id, (seq, cancel.clone()));
+         (id, seq)
+      });
+
+      let mut buffer = ipc::SocketBuffer::new();
+      let mut hung_up = false;
+      tokio::pin!(search);
+      let response = loop {
+         tokio::select! {
+            response = &mut search => break response,
+            request = requests.recv(), if !hung_up && queued.len() < MAX_QUEUED_REQUESTS => {
+               match request {
+                  Some(Ok(Request::Cancel { request_id })) => {
+                     let found = self.cancel_search(request_id);
+                     if buffer.send(writer, &Response::Cancel { found }).await.is_err() {
+                        hung_up = true;
+                        cancel.cancel();
+                     }
+                  },
+                  Some(request) => queued.push_back(request),
+                  None => {
+                     hung_up = true;
+                     cancel.cancel();
+                  },
+               }
+            },
+         }
+      };
+
+      // A later search may have taken over the id; its registration stays.
+      if let Some((id, seq)) = registration {
+         let mut searches = self.searches.lock();
+         if searches.get(&id).is_some_and(|(owner, _)| *owner == seq) {
+            searches.remove(&id);
+         }
+      }
+
+      if hung_up {
+         tracing::debug!("Client hung up, search cancelled");
+         return None;
+      }
+      let cancelled = cancel.is_cancelled() && !self.cancel.is_cancelled();
+      Some(match response {
+         Response::Error { .. } if cancelled => {
+            Response::Error { message: "search cancelled".to_string() }
+         },
+         response => response,
+      })
+   }
+
+   /// Cancels the running search registered under `request_id`, returning
+   /// whether there was one.
+   fn cancel_search(&self, request_id: u64) -> bool {
+      self
+         .searches
+         .lock()
+         .get(&request_id)
+         .inspect(|(_, cancel)| cancel.cancel())
+         .is_some()
+   }
+
+   async fn handle_search(&self, search: &SearchQuery, cancel: &CancellationToken) -> Response {
+      let SearchQuery {
+         query,
+         limit,
+         path,
+         retrieval,
+         indexed_after,
+         crate_name,
+         tests,
+         include,
+         exclude,
+      } = search;
+      let (limit, retrieval, indexed_after) = (*limit, *retrieval, *indexed_after);
       if query.is_empty() {
          return Response::Error { message: "query is required".to_string() };
       }
+      let parsed = match search::parse_query(query) {
+         Ok(parsed) => parsed,
+         Err(e) => return Response::Error { message: e.to_string() },
+      };
 
       let search_path = path.as_ref().map(|p| {
          if p.is_absolute() {
@@ -286,131 +785,514 @@ impl Server {
             self.root.join(p)
          }
       });
-
-      let query_emb = match self.embedder.encode_query(&query).await {
-         Ok(emb) => emb,
-         Err(e) => return Response::Error { message: format!("embedding failed: {e}") },
+      let globs =
+         match PathGlobs::new(search_path.as_deref().unwrap_or(&self.root), include, exclude) {
+            Ok(globs) => globs,
+            Err(e) => return Response::Error { message: e.to_string() },
+         };
+      let filter = SearchFilter {
+         globs: Some(&globs),
+         tests: *tests,
+         tests_root: Some(search_path.as_deref().unwrap_or(&self.root)),
+         ..SearchFilter::default()
+      };
+      // The cross-encoder picks the final results from among its depth.
+      let kept = limit.max(retrieval.cross_depth());
+      let fetch = if filter.drops_results(&parsed) {
+         kept * search::FILTER_OVERFETCH
+      } else {
+         kept
       };
 
-      let search_result = self
-         .store
-         .search(SearchParams {
-            store_id: &self.store_id,
-            query_text: &query,
-            query_vector: &query_emb.dense,
-            query_colbert: &query_emb.colbert,
-            limit,
-            path_filter: search_path.as_deref(),
-            rerank,
+      let mut route = QueryRoute::Semantic;
+      let search_result = if retrieval.keyword {
+         let started = Instant::now();
+         let query_store = async |limit: usize| {
+            self
+               .store
+               .keyword_search(KeywordParams {
+                  store_id: &self.store_id,
+                  query_text: &parsed.text,
+                  limit,
+                  path_filter: search_path.as_deref(),
+                  path_globs: Some(&globs),
+                  indexed_after,
+                  crate_name: crate_name.as_deref(),
+               })
+               .await
+         };
+         search::fetch_matching(&parsed, filter, fetch, kept, query_store)
+            .await
+            .map(|mut response| {
+               response
+                  .stages
+                  .push(StageTiming::since(SearchStage::Retrieval, started));
+               response
+            })
+      } else {
+         let embed_started = Instant::now();
+         let query_emb =
+            match util::cancellable(cancel, self.embedder.encode_query(&parsed.text)).await {
+               Ok(emb) => emb,
+               Err(e) => return Response::Error { message: format!("embedding failed: {e}") },
+            };
+         let embedding = StageTiming::since(SearchStage::Embedding, embed_started);
+
+         let retrieve = async |globs: &PathGlobs| -> Result<SearchResponse> {
+            let query_store = async |limit: usize| {
+               let search = self.store.search(SearchParams {
+                  store_id: &self.store_id,
+                  query_text: &parsed.text,
+                  query_vector: &query_emb.dense,
+                  query_colbert: &query_emb.colbert,
+                  limit,
+                  path_filter: search_path.as_deref(),
+                  path_globs: Some(globs),
+                  indexed_after,
+                  crate_name: crate_name.as_deref(),
+                  rerank: retrieval.rerank,
+                  rerank_depth: retrieval.rerank_depth,
+                  nprobes: None,
+                  exact: retrieval.exact,
+                  cancel: Some(cancel),
+               });
+               util::cancellable(cancel, search).await
+            };
+            let filter = SearchFilter { globs: Some(globs), ..filter };
+            search::fetch_matching(&parsed, filter, fetch, kept, query_store).await
+         };
+
+         let retrieval_started = Instant::now();
+         route = route::classify(&parsed.text);
+         let path_globs = match route {
+            QueryRoute::Path if globs.is_empty() => route::path_globs(&parsed.text).ok(),
+            _ => None,
+         };
+         let routed = match &path_globs {
+            Some(path_globs) => match retrieve(path_globs).await {
+               Ok(response) if response.results.is_empty() => None,
+               result => Some(result),
+            },
+            None => None,
+         };
+         if route == QueryRoute::Path && routed.is_none() {
+            route = QueryRoute::Semantic;
+         }
+         let result = match routed {
+            Some(result) => result,
+            None => retrieve(&globs).await,
+         };
+         result.map(|mut response| {
+            search::record_stages(
+               &mut response,
+               embedding,
+               StageTiming::since(SearchStage::Retrieval, retrieval_started),
+            );
+            if route == QueryRoute::Symbol {
+               route::apply_symbol_boost(&mut response.results, route::symbol_name(&parsed.text));
+            }
+            response
          })
-         .await;
+      };
 
       match search_result {
-         Ok(response) => {
+         Ok(mut response) => {
+            search::retain_matching(&mut response.results, &parsed, filter);
+            if retrieval.cross {
+               let started = Instant::now();
+               let rerank = self.cross_encoder.rerank(
+                  &parsed.text,
+                  &mut response.results,
+                  retrieval.cross_depth(),
+               );
+               if let Err(e) = util::cancellable(cancel, rerank).await {
+                  return Response::Error { message: format!("cross-encoder failed: {e}") };
+               }
+               response
+                  .stages
+                  .push(StageTiming::since(SearchStage::Cross, started));
+            }
+            response.results.truncate(limit);
+            links::link_bridges(&*self.store, &self.store_id, &mut response.results).await;
+
+            let relative = |path: PathBuf| {
+               path
+                  .strip_prefix(&self.root)
+                  .map(PathBuf::from)
+                  .unwrap_or(path)
+            };
             let results = response
                .results
                .into_iter()
-               .map(|r| {
-                  let rel_path = r
-                     .path
-                     .strip_prefix(&self.root)
-                     .map(PathBuf::from)
-                     .unwrap_or(r.path);
-
-                  SearchResult {
-                     path:       rel_path,
-                     content:    r.content,
-                     score:      r.score,
-                     start_line: r.start_line,
-                     num_lines:  r.num_lines,
-                     chunk_type: r.chunk_type,
-                     is_anchor:  r.is_anchor,
-                  }
+               .map(|r| SearchResult {
+                  path:         relative(r.path),
+                  content:      r.content,
+                  score:        r.score,
+                  start_line:   r.start_line,
+                  num_lines:    r.num_lines,
+                  chunk_type:   r.chunk_type,
+                  is_anchor:    r.is_anchor,
+                  breakdown:    r.breakdown,
+                  bridges:      r.bridges,
+                  language:     r.language,
+                  context_prev: r.context_prev,
+                  context_next: r.context_next,
+                  linked:       r
+                     .linked
+                     .into_iter()
+                     .map(|l| LinkedResult { path: relative(l.path), ..l })
+                     .collect(),
                })
                .collect();
 
-            let is_indexing = self.indexing.load(Ordering::Relaxed);
-            let progress_val = self.progress.load(Ordering::Relaxed);
+            let progress = self.index_progress();
 
             Response::Search(SearchResponse {
                results,
-               status: if is_indexing {
+               status: if progress.is_some() {
                   SearchStatus::Indexing
                } else {
                   SearchStatus::Ready
                },
-               progress: if is_indexing {
-                  Some(progress_val)
-               } else {
-                  None
-               },
+               progress,
+               mode: response.mode,
+               route,
+               stages: response.stages,
             })
          },
          Err(e) => Response::Error { message: format!("search failed: {e}") },
       }
    }
 
-   async fn initial_sync(self: &Arc<Self>) -> Result<()> {
+   /// Diagnostics reported by `Request::Health`.
+   async fn status(&self) -> ServerStatus {
+      let embedder = self.embedder.status();
+      let rows = match self.store.get_info(&self.store_id).await {
+         Ok(info) => info.row_count,
+         Err(e) => {
+            tracing::debug!("Failed to read store info: {}", e);
+            0
+         },
+      };
+      let (files, last_sync) = {
+         let meta = self.meta_store.lock();
+         (meta.all_paths().count(), meta.history().back().map(|run| run.finished_at))
+      };
+
+      ServerStatus {
+         indexing: self.indexing.load(Ordering::Relaxed),
+         progress: self.progress.load(Ordering::Relaxed),
+         files,
+         rows,
+         models_loaded: embedder.models_loaded,
+         device: embedder.device,
+         last_sync,
+         memory_bytes: util::resident_memory(),
+         queue_depth: embedder.queue_depth,
+      }
+   }
+
+   /// Asks the daemon to stop, unless it was already asked to stop in a way
+   /// at least as forceful.
+   fn request_stop(&self, mode: StopMode) {
+      self.shutdown.send_if_modified(|current| {
+         let escalated = mode > *current;
+         if escalated {
+            *current = mode;
+         }
+         escalated
+      });
+   }
+
+   /// Whether a checkout moved the root to another branch's store, whose
+   /// changes must not be written to this one.
+   fn switched_branch(&self) -> bool {
+      self.tracks_branch
+         && git::resolve_store_id(&self.root).is_ok_and(|store_id| store_id != self.store_id)
+   }
+
+   /// Whether indexing should stop before starting another file.
+   fn stopping(&self) -> bool {
+      self.draining.load(Ordering::Acquire) || self.cancel.is_cancelled()
+   }
+
+   /// Stops new files from being indexed and waits for those in progress to
+   /// be written, along with their metadata, and for their index locks to be
+   /// released.
+   async fn drain(&self) {
+      self.draining.store(true, Ordering::Release);
+      self.tasks.close();
+      self.tasks.wait().await;
+   }
+
+   /// Syncs the root in the background unless a sync is already running,
+   /// returning whether this call started one.
+   fn start_sync(self: &Arc<Self>) -> bool {
+      if self.stopping() || self.indexing.swap(true, Ordering::AcqRel) {
+         return false;
+      }
+      self.sync_done.store(0, Ordering::Relaxed);
+      self.progress.store(0, Ordering::Relaxed);
+      self.sync_progress.send_modify(|p| {
+         p.processed = 0;
+         p.indexed = 0;
+         p.current_file = None;
+      });
+
+      let server = Arc::clone(self);
+      self.tasks.spawn(async move {
+         if let Err(e) = server.sync().await {
+            tracing::error!("Sync failed: {}", e);
+         }
+         server.indexing.store(false, Ordering::Release);
+         server.progress.store(100, Ordering::Relaxed);
+         // Wakes the clients following progress so they see the sync end.
+         server.sync_progress.send_modify(|_| {});
+         if !server.stopping() {
+            server.warm_up().await;
+         }
+      });
+      true
+   }
+
+   /// Sends the progress of the running sync each time it changes, ending
+   /// with a frame marked `done` once no sync is running.
+   async fn stream_progress(
+      &self,
+      buffer: &mut ipc::SocketBuffer,
+      writer: &mut ClientWriter,
+   ) -> Result<()> {
+      let mut updates = self.sync_progress.subscribe();
+      loop {
+         let done = !self.indexing.load(Ordering::Acquire);
+         let progress = updates.borrow_and_update().clone();
+         buffer
+            .send(writer, &Response::Progress { progress, done })
+            .await?;
+         if done || updates.changed().await.is_err() {
+            return Ok(());
+         }
+         // A long sync followed by a client is not idle time.
+         self.touch();
+      }
+   }
+
+   /// Indexes every file under the root whose content changed since it was
+   /// last indexed, recording the run in the sync history.
+   async fn sync(self: &Arc<Self>) -> Result<()> {
       let _lock = IndexLock::acquire(&self.store_id)?;
+      let started = SystemTime::now();
 
       let fs = LocalFileSystem::new();
-      let files: Vec<PathBuf> = fs.get_files(&self.root)?.collect();
+      let external_docs = docs::collect(&fs, &self.root, &config::get().docs_sources).await;
+      let files: Vec<PathBuf> = fs
+         .get_files(&self.root)?
+         .filter(|p| !external_docs.iter().any(|d| d.path == *p))
+         .collect();
 
-      let total = files.len();
+      // Files deleted while no daemon watched them lose their rows, and stop
+      // being shared from the base store
+      let found: HashSet<&Path> = files
+         .iter()
+         .chain(external_docs.iter().map(|doc| &doc.path))
+         .map(PathBuf::as_path)
+         .collect();
+      let deleted: Vec<PathBuf> = self
+         .meta_store
+         .lock()
+         .all_paths()
+         .filter(|path| path.starts_with(&self.root) && !found.contains(path.as_path()))
+         .cloned()
+         .collect();
+      if !deleted.is_empty() {
+         self.store.delete_files(&self.store_id, &deleted).await?;
+         self.record_writes(deleted.len());
+         let mut meta = self.meta_store.lock();
+         for path in &deleted {
+            meta.remove(path);
+         }
+         meta.save()?;
+      }
+
+      let total = files.len() + external_docs.len();
+      self.sync_started.store(self.clock(), Ordering::Relaxed);
+      self.sync_total.store(total, Ordering::Relaxed);
+      self.sync_progress.send_modify(|p| p.total = total);
       if total == 0 {
-         self.indexing.store(false, Ordering::Relaxed);
-         self.progress.store(100, Ordering::Relaxed);
-         tracing::info!("Initial sync complete: 0/0 files indexed");
-         return Ok(());
+         tracing::info!("Sync complete: 0/0 files indexed");
+         return self.record_sync(SyncRun {
+            deleted: deleted.len(),
+            ..SyncRun::new(SyncActor::Daemon, started)
+         });
       }
 
+      let dirs = sync::module_dirs(&self.root, &files);
       let results: Vec<_> = stream::iter(files.into_iter().enumerate())
          .map(|(i, file_path)| {
             let server = Arc::clone(self);
             async move {
-               let result = server.process_file(&file_path).await;
+               let result = if server.stopping() {
+                  Err(Error::Cancelled)
+               } else {
+                  server.process_file(&file_path).await
+               };
+               server.advance_sync(&file_path, result.is_ok());
                (i, file_path, result)
             }
          })
          .buffer_unordered(8)
          .collect()
          .await;
+      if self.stopping() {
+         return Err(Error::Cancelled);
+      }
 
       let mut indexed = 0;
-      for (completed, (_i, file_path, result)) in results.into_iter().enumerate() {
+      let mut errors = 0;
+      let mut last_error = None;
+      for (_i, file_path, result) in results {
          match result {
             Ok(()) => indexed += 1,
-            Err(e) => tracing::warn!("Failed to index {}: {}", file_path.display(), e),
+            Err(e) => {
+               tracing::warn!("Failed to index {}: {}", file_path.display(), e);
+               errors += 1;
+               last_error = Some(format!("{}: {e}", file_path.display()));
+            },
          }
+      }
 
-         let pct = ((completed + 1) * 100 / total).min(100) as u8;
-         self.progress.store(pct, Ordering::Relaxed);
+      for doc in external_docs {
+         if self.stopping() {
+            return Err(Error::Cancelled);
+         }
+         let Some(content) = doc.content else {
+            continue;
+         };
+         let result = self.index_content(&doc.path, &content, true).await;
+         self.advance_sync(&doc.path, result.is_ok());
+         match result {
+            Ok(()) => indexed += 1,
+            Err(e) => {
+               tracing::warn!("Failed to index {}: {}", doc.path.display(), e);
+               errors += 1;
+               last_error = Some(format!("{}: {e}", doc.path.display()));
+            },
+         }
       }
 
-      self.indexing.store(false, Ordering::Relaxed);
-      self.progress.store(100, Ordering::Relaxed);
+      self.refresh_modules(&dirs).await;
 
-      tracing::info!("Initial sync complete: {}/{} files indexed", indexed, total);
-      Ok(())
+      tracing::info!("Sync complete: {}/{} files indexed", indexed, total);
+      {
+         let mut meta = self.meta_store.lock();
+         meta.record_file_hash();
+         meta.set_head(git::head_commit(&self.root));
+      }
+      self.record_sync(SyncRun {
+         processed: total,
+         indexed,
+         errors,
+         deleted: deleted.len(),
+         last_error,
+         ..SyncRun::new(SyncActor::Daemon, started)
+      })
+   }
+
+   /// Counts `file` as done in the running sync and updates the progress.
+   fn advance_sync(&self, file: &Path, indexed: bool) {
+      let done = self.sync_done.fetch_add(1, Ordering::Relaxed) + 1;
+      let total = self.sync_total.load(Ordering::Relaxed).max(1);
+      self
+         .progress
+         .store((done * 100 / total).min(100) as u8, Ordering::Relaxed);
+      self.sync_progress.send_modify(|p| {
+         p.processed = done;
+         p.indexed += usize::from(indexed);
+         p.current_file = Some(file.to_string_lossy().into_owned().into());
+      });
+   }
+
+   /// Progress of the running sync, with an ETA extrapolated from the files
+   /// done so far. `None` once indexing is over.
+   fn index_progress(&self) -> Option<IndexProgress> {
+      if !self.indexing.load(Ordering::Relaxed) {
+         return None;
+      }
+      let done = self.sync_done.load(Ordering::Relaxed);
+      let total = self.sync_total.load(Ordering::Relaxed);
+      let elapsed_ms = self
+         .clock()
+         .saturating_sub(self.sync_started.load(Ordering::Relaxed));
+      let eta_secs = (done > 0 && total >= done)
+         .then(|| elapsed_ms * (total - done) as u64 / done as u64 / 1000);
+
+      Some(IndexProgress {
+         percent: self.progress.load(Ordering::Relaxed),
+         files_done: done,
+         files_total: total,
+         eta_secs,
+      })
+   }
+
+   /// Adds a finished run to the store's sync history.
+   fn record_sync(&self, run: SyncRun) -> Result<()> {
+      let mut meta = self.meta_store.lock();
+      meta.record_sync(run);
+      meta.save()
+   }
+
+   /// Rewrites the module summaries of `dirs` from the files indexed now.
+   async fn refresh_modules(&self, dirs: &BTreeSet<PathBuf>) {
+      if !config::get().module_summaries || dirs.is_empty() {
+         return;
+      }
+      let indexed: Vec<PathBuf> = self.meta_store.lock().all_paths().cloned().collect();
+      let refreshed = sync::refresh_module_chunks(
+         &*self.embedder,
+         &*self.store,
+         &self.store_id,
+         dirs,
+         &indexed,
+         &self.workspace,
+      )
+      .await;
+      match refreshed {
+         Ok(written) => self.record_writes(written),
+         Err(e) => tracing::warn!("Failed to refresh module summaries: {e}"),
+      }
    }
 
    async fn process_file(&self, file_path: &Path) -> Result<()> {
+      if let Some(reason) = file::check_size(file_path) {
+         tracing::debug!("not indexing {}: {reason:?}", file_path.display());
+         return Ok(());
+      }
       let content = tokio::fs::read(file_path).await?;
+      if let Some(reason) = file::check_content(&content) {
+         tracing::debug!("not indexing {}: {reason:?}", file_path.display());
+         return Ok(());
+      }
+      self.index_content(file_path, &content, false).await
+   }
 
+   /// Indexes `content` as the current version of `file_path`, tagging its
+   /// chunks as docs when it comes from an external docs source.
+   async fn index_content(&self, file_path: &Path, content: &[u8], is_doc: bool) -> Result<()> {
       if content.is_empty() {
          return Ok(());
       }
-      let content_str = Str::from_utf8_lossy(&content);
+      let hint = self
+         .meta_store
+         .lock()
+         .get_encoding(file_path)
+         .map(str::to_string);
+      let (content_str, encoding) = file::encoding::decode(content, hint.as_deref());
 
-      let hash = FileHash::sum(&content);
+      let hash = FileHash::digest(config::get().file_hash, content);
 
-      {
-         let meta = self.meta_store.lock();
-         if let Some(existing_hash) = meta.get_hash(file_path)
-            && existing_hash == hash
-         {
-            return Ok(());
-         }
+      let existing_hash = self.meta_store.lock().get_hash(file_path);
+      if existing_hash == Some(hash) {
+         return Ok(());
       }
 
       let chunks = self.chunker.chunk(&content_str, file_path).await?;
@@ -419,7 +1301,8 @@ impl Server {
       }
 
       let path_arc = std::sync::Arc::new(file_path.to_path_buf());
-      let prepared: Vec<PreparedChunk> = chunks
+      let language = GrammarManager::language_for(file_path, &content_str).map(Str::from);
+      let mut prepared: Vec<PreparedChunk> = chunks
          .iter()
          .enumerate()
          .map(|(i, chunk)| {
@@ -442,16 +1325,50 @@ impl Server {
                end_line: chunk.end_line as u32,
                chunk_index: Some(i as u32),
                is_anchor: chunk.is_anchor,
-               chunk_type: chunk.chunk_type,
+               chunk_type: if is_doc {
+                  Some(ChunkType::Doc)
+               } else {
+                  chunk.chunk_type
+               },
                context_prev,
                context_next,
+               bridges: bridge::collect(file_path, &chunk.content),
+               language: language.clone(),
+               embed_text: embed_text(file_path, chunk),
             }
          })
          .collect();
 
-      let texts: Vec<Str> = prepared.iter().map(|c| c.content.clone()).collect();
-      let embeddings = self.embedder.compute_hybrid(&texts).await?;
+      if let Some(summarizer) = &self.summarizer {
+         summarize::summarize_chunks(&**summarizer, &self.summaries, &mut prepared).await;
+         self.summaries.lock().save()?;
+      }
+
+      // Chunks that survived the edit keep their stored embeddings; only new
+      // or modified ones are embedded.
+      let file_paths = [file_path.to_path_buf()];
+      let mut reuse = if existing_hash.is_some() {
+         self
+            .store
+            .get_chunk_embeddings(&self.store_id, &file_paths)
+            .await?
+      } else {
+         HashMap::new()
+      };
+      if let Some(base) = &self.base_store {
+         reuse.extend(base.embeddings(&*self.store, &file_paths).await);
+      }
+
+      let texts: Vec<Str> = prepared.iter().map(|c| c.text_to_embed().clone()).collect();
+      self.last_embed.fetch_max(self.clock(), Ordering::Relaxed);
+      let (embeddings, computed) = util::cancellable(
+         &self.cancel,
+         embed::compute_hybrid_reusing(&self.embedder, &texts, &reuse),
+      )
+      .await?;
+      tracing::debug!("embedded {computed} of {} chunks for {}", texts.len(), file_path.display());
 
+      let indexed_at = util::unix_now();
       let records: Vec<VectorRecord> = prepared
          .into_iter()
          .zip(embeddings)
@@ -459,6 +1376,9 @@ impl Server {
             id:            prep.id,
             path:          prep.path,
             hash:          prep.hash,
+            chunk_hash:    FileHash::sum(
+               prep.embed_text.as_ref().unwrap_or(&prep.content).as_bytes(),
+            ),
             content:       prep.content,
             start_line:    prep.start_line,
             end_line:      prep.end_line,
@@ -470,14 +1390,30 @@ impl Server {
             vector:        emb.dense,
             colbert:       emb.colbert,
             colbert_scale: emb.colbert_scale,
+            indexed_at:    Some(indexed_at),
+            crate_name:    self.workspace.crate_for(file_path),
+            bridges:       prep.bridges,
+            language:      prep.language,
          })
          .collect();
 
-      self.store.insert_batch(&self.store_id, records).await?;
+      // Only chunks that changed are written; the rest stay in place.
+      let written = if existing_hash.is_some() {
+         self
+            .store
+            .replace_files(&self.store_id, &file_paths, records)
+            .await?
+      } else {
+         let written = records.len();
+         self.store.insert_batch(&self.store_id, records).await?;
+         written
+      };
+      self.record_writes(written);
 
       {
          let mut meta = self.meta_store.lock();
          meta.set_hash(file_path, hash);
+         meta.set_encoding(file_path, file::encoding::label(encoding));
       }
       self.meta_store.lock().save()?;
 
@@ -488,8 +1424,16 @@ impl Server {
       let ignore_patterns = IgnorePatterns::new(&self.root);
       let server = Arc::clone(self);
       let watcher = FileWatcher::new(self.root.clone(), ignore_patterns, move |changes| {
+         if server.stopping() {
+            return;
+         }
+         if server.switched_branch() {
+            println!("{}", style("Branch switched, shutting down...").yellow());
+            server.request_stop(StopMode::Graceful);
+            return;
+         }
          let server = Arc::clone(&server);
-         tokio::spawn(async move {
+         server.tasks.clone().spawn(async move {
             let _lock = match IndexLock::acquire(&server.store_id) {
                Ok(lock) => lock,
                Err(e) => {
@@ -498,14 +1442,16 @@ impl Server {
                },
             };
 
+            let dirs = sync::module_dirs(&server.root, changes.iter().map(|(path, _)| path));
             let results: Vec<_> = stream::iter(changes)
                .map(|(path, action)| {
                   let server = Arc::clone(&server);
                   async move {
                      let result = match action {
                         WatchAction::Delete => {
-                           if let Err(e) = server.store.delete_file(&server.store_id, &path).await {
-                              tracing::error!("Failed to delete file from store: {}", e);
+                           match server.store.delete_file(&server.store_id, &path).await {
+                              Ok(()) => server.record_writes(1),
+                              Err(e) => tracing::error!("Failed to delete file from store: {}", e),
                            }
                            {
                               let mut meta = server.meta_store.lock();
@@ -538,6 +1484,7 @@ impl Server {
                   }
                }
             }
+            server.refresh_modules(&dirs).await;
          });
       })?;
 
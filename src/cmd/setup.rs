@@ -1,24 +1,45 @@
 //! Initial setup command.
 //!
 //! Downloads required models and grammars, creating necessary directories
-//! for first-time use of smgrep.
+//! for first-time use of smgrep. Model files are checked against the
+//! checksums the Hub publishes and grammars against the ones pinned in
+//! [`GRAMMAR_URLS`], and fetched again on a mismatch. Setup ends by embedding
+//! a sample string, and fails if anything along the way did.
+//!
+//! Machines without internet access can install from a bundle instead, see
+//! [`install_bundle`].
 
 use std::{
-   fs,
-   path::{Path, PathBuf},
+   fs::{self, File},
+   io::BufReader,
+   path::{Component, Path, PathBuf},
    time::Duration,
 };
 
 use console::style;
+use hf_hub::api::tokio::Api;
 use indicatif::{ProgressBar, ProgressStyle};
 
 use crate::{
-   Result, config,
-   grammar::{GRAMMAR_URLS, GrammarManager},
+   Error, Result, config,
+   embed::{
+      CandleEmbedder, Embedder,
+      candle::{MODEL_FILES, hub_offline, hub_repo},
+   },
+   grammar::{GRAMMAR_URLS, GrammarManager, GrammarSource},
+   meta::FileHash,
 };
 
-/// Executes the setup command to download models and grammars.
-pub async fn execute() -> Result<()> {
+/// Text embedded by the self-test.
+const SELF_TEST_TEXT: &str = "fn main() { println!(\"hello, world\"); }";
+
+/// Directories that bundle entries must lie in, found in an archive within
+/// its first two path components.
+const BUNDLE_DIRS: [&str; 2] = ["models", "grammars"];
+
+/// Executes the setup command to download models and grammars, or to install
+/// them from `from_dir` without going online.
+pub async fn execute(from_dir: Option<PathBuf>) -> Result<()> {
    println!("{}\n", style("smgrep Setup").bold());
 
    let models = config::model_dir();
@@ -34,12 +55,36 @@ pub async fn execute() -> Result<()> {
    check_dir("Grammars", grammars);
    println!();
 
+   if let Some(source) = &from_dir {
+      println!("{}", style(format!("Installing from {}...", source.display())).bold());
+      let installed = install_bundle(source)?;
+      if installed == 0 {
+         return Err(Error::Setup(format!("no models or grammars found in {}", source.display())));
+      }
+      println!("{} Installed {installed} files", style("✓").green());
+      println!();
+   }
+   let offline = from_dir.is_some() || hub_offline();
+
    println!("{}", style("Downloading models...").bold());
-   download_models(models).await?;
+   let mut failed = download_models().await?;
    println!();
 
    println!("{}", style("Downloading grammars...").bold());
-   download_grammars(grammars).await?;
+   failed += download_grammars(grammars, offline).await?;
+
+   if failed > 0 {
+      return Err(Error::Setup(format!("{failed} downloads failed or did not verify")));
+   }
+   println!();
+
+   println!("{}", style("Testing embeddings...").bold());
+   let spinner = spinner(format!("Embedding a sample with {}...", config::get().dense_model));
+   if let Err(e) = self_test().await {
+      spinner.finish_with_message(format!("{} Self-test failed", style("✗").red()));
+      return Err(e);
+   }
+   spinner.finish_with_message(format!("{} Embeddings work", style("✓").green()));
 
    println!("\n{}", style("Setup Complete!").green().bold());
    println!("\n{}", style("You can now run:").dim());
@@ -66,36 +111,37 @@ fn check_dir(name: &str, path: &Path) {
    println!("{} {}: {}", symbol, name, style(path.display()).dim());
 }
 
-/// Downloads embedding models from Hugging Face.
-async fn download_models(models_dir: &Path) -> Result<()> {
-   let cfg = config::get();
-   let models = [&cfg.dense_model, &cfg.colbert_model];
-   for model_id in models {
-      let model_path = models_dir.join(model_id.replace('/', "--"));
-
-      if model_path.exists() {
-         println!("{} Model: {}", style("✓").green(), style(model_id).dim());
-         continue;
-      }
+fn spinner(message: String) -> ProgressBar {
+   let spinner = ProgressBar::new_spinner();
+   spinner.set_style(
+      ProgressStyle::default_spinner()
+         .template("{spinner:.green} {msg}")
+         .unwrap(),
+   );
+   spinner.enable_steady_tick(Duration::from_millis(100));
+   spinner.set_message(message);
+   spinner
+}
 
-      let spinner = ProgressBar::new_spinner();
-      spinner.set_style(
-         ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-      );
-      spinner.enable_steady_tick(Duration::from_millis(100));
-      spinner.set_message(format!("Downloading {model_id}..."));
+/// Downloads and verifies the embedding models from Hugging Face, returning
+/// how many failed.
+async fn download_models() -> Result<usize> {
+   let cfg = config::get();
+   let api = Api::new()?;
+   let mut failed = 0;
+   for model_id in [&cfg.dense_model, &cfg.colbert_model] {
+      let spinner = spinner(format!("Downloading {model_id}..."));
 
-      match download_model_from_hf(model_id, &model_path).await {
+      match download_model(&api, model_id).await {
          Ok(()) => {
             spinner.finish_with_message(format!(
-               "{} Downloaded: {}",
+               "{} Model: {}",
                style("✓").green(),
                style(model_id).dim()
             ));
          },
          Err(e) => {
+            failed += 1;
             spinner.finish_with_message(format!(
                "{} Failed: {} - {}",
                style("✗").red(),
@@ -106,70 +152,225 @@ async fn download_models(models_dir: &Path) -> Result<()> {
       }
    }
 
+   Ok(failed)
+}
+
+/// Fetches each file of a model at its configured revision, or takes it from
+/// the Hub cache, and verifies it, downloading it again if it does not match.
+/// Offline models are only verified, and models installed from a bundle,
+/// which carries no checksums, are taken as they are.
+async fn download_model(api: &Api, model_id: &str) -> Result<()> {
+   if CandleEmbedder::installed_model_dir(model_id).is_some() {
+      return Ok(());
+   }
+   if hub_offline() {
+      let dir = CandleEmbedder::download_model(model_id).await?;
+      for file in MODEL_FILES {
+         verify_hub_file(&dir.join(file), &format!("{model_id}/{file}"))?;
+      }
+      return Ok(());
+   }
+
+   let repo = api.repo(hub_repo(model_id));
+   for file in MODEL_FILES {
+      let artifact = format!("{model_id}/{file}");
+      if verify_hub_file(&repo.get(file).await?, &artifact).is_err() {
+         verify_hub_file(&repo.download(file).await?, &artifact)?;
+      }
+   }
    Ok(())
 }
 
-/// Downloads tree-sitter grammar files for supported languages.
-async fn download_grammars(grammars_dir: &Path) -> Result<()> {
+/// Downloads and verifies tree-sitter grammars for supported languages,
+/// returning how many failed. `offline` only verifies those already
+/// installed.
+async fn download_grammars(grammars_dir: &Path, offline: bool) -> Result<usize> {
    let grammar_manager = GrammarManager::with_auto_download(false)?;
+   let mut failed = 0;
 
-   for pair @ (lang, _url) in GRAMMAR_URLS {
+   for source @ (lang, ..) in GRAMMAR_URLS {
       let grammar_path = grammars_dir.join(format!("tree-sitter-{lang}.wasm"));
-
-      if grammar_path.exists() {
-         println!("{} Grammar: {}", style("✓").green(), style(lang).dim());
+      if offline && !grammar_path.exists() {
+         println!(
+            "{} Grammar: {} {}",
+            style("-").dim(),
+            style(lang).dim(),
+            style("(not installed)").dim()
+         );
          continue;
       }
+      let spinner = spinner(format!("Downloading {lang} grammar..."));
 
-      let spinner = ProgressBar::new_spinner();
-      spinner.set_style(
-         ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-      );
-      spinner.enable_steady_tick(Duration::from_millis(100));
-      spinner.set_message(format!("Downloading {lang} grammar..."));
-
-      match grammar_manager.download_grammar(*pair).await {
-         Ok(_) => {
+      match download_grammar(&grammar_manager, *source, &grammar_path, offline).await {
+         Ok(()) => {
             spinner.finish_with_message(format!(
-               "{} Downloaded: {}",
+               "{} Grammar: {}",
                style("✓").green(),
                style(lang).dim()
             ));
          },
          Err(e) => {
+            failed += 1;
             spinner.finish_with_message(format!("{} Failed: {} - {}", style("✗").red(), lang, e));
          },
       }
    }
 
+   Ok(failed)
+}
+
+/// Downloads a grammar unless the one on disk matches its pinned checksum.
+/// Offline, the one on disk is only verified.
+async fn download_grammar(
+   grammar_manager: &GrammarManager,
+   source: GrammarSource,
+   path: &Path,
+   offline: bool,
+) -> Result<()> {
+   if offline {
+      return GrammarManager::verify(source, &fs::read(path)?);
+   }
+   grammar_manager.download_grammar(source).await?;
    Ok(())
 }
 
-/// Downloads a specific model from Hugging Face Hub to the destination
-/// directory.
-async fn download_model_from_hf(model_id: &str, dest: &PathBuf) -> Result<()> {
-   fs::create_dir_all(dest)?;
-
-   let api = hf_hub::api::tokio::Api::new()?;
-   let repo = api.model(model_id.to_string());
-
-   let files_to_download =
-      vec!["config.json", "tokenizer.json", "tokenizer_config.json", "model.onnx"];
-
-   for file in files_to_download {
-      match repo.get(file).await {
-         Ok(path) => {
-            let dest_file = dest.join(file);
-            if let Some(parent) = dest_file.parent() {
-               fs::create_dir_all(parent)?;
-            }
-            fs::copy(path, dest_file)?;
-         },
-         Err(_e) => {},
+/// Checks a file in the Hub cache against the checksum the Hub published for
+/// it, which names the blob the file links to: the SHA-256 of large files and
+/// the git object id of small ones.
+fn verify_hub_file(path: &Path, artifact: &str) -> Result<()> {
+   let blob = fs::canonicalize(path)?;
+   let expected = blob
+      .file_name()
+      .and_then(|name| name.to_str())
+      .unwrap_or_default()
+      .to_ascii_lowercase();
+   let actual = match expected.len() {
+      64 => FileHash::sum_reader(File::open(&blob)?)?.to_string(),
+      40 => git2::Oid::hash_file(git2::ObjectType::Blob, &blob)?.to_string(),
+      _ => return Err(Error::Setup(format!("{artifact} is not a Hugging Face Hub download"))),
+   };
+   if actual != expected {
+      return Err(Error::ChecksumMismatch { artifact: artifact.to_string(), expected, actual });
+   }
+   Ok(())
+}
+
+/// Copies models and grammars out of an offline bundle, returning how many
+/// files it installed.
+///
+/// A bundle is a directory or an uncompressed tar archive laid out like
+/// `~/.smgrep`: `models/<org>--<name>/` holding each model's
+/// `config.json`, `tokenizer.json` and `model.safetensors`, and
+/// `grammars/tree-sitter-<lang>.wasm`. An archive may wrap both in one
+/// top-level directory. Anything else in the bundle is ignored.
+fn install_bundle(source: &Path) -> Result<usize> {
+   let mut installed = 0;
+   if source.is_dir() {
+      let mut files = Vec::new();
+      collect_files(source, Path::new(""), &mut files)?;
+      for relative in files {
+         if let Some(dest) = bundle_destination(&relative) {
+            fs::create_dir_all(dest.parent().unwrap_or(&dest))?;
+            fs::copy(source.join(&relative), &dest)?;
+            installed += 1;
+         }
+      }
+   } else {
+      let mut archive = tar::Archive::new(BufReader::new(File::open(source)?));
+      for entry in archive.entries()? {
+         let mut entry = entry?;
+         if !entry.header().entry_type().is_file() {
+            continue;
+         }
+         let path = entry.path()?.into_owned();
+         if let Some(dest) = bundle_destination(&path) {
+            fs::create_dir_all(dest.parent().unwrap_or(&dest))?;
+            entry.unpack(&dest)?;
+            installed += 1;
+         }
+      }
+   }
+   Ok(installed)
+}
+
+/// Lists the files under `dir` down to the depth a bundle uses, relative to
+/// the bundle root.
+fn collect_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+   for entry in fs::read_dir(dir)? {
+      let entry = entry?;
+      let relative = relative.join(entry.file_name());
+      let file_type = entry.file_type()?;
+      if file_type.is_dir() && relative.components().count() < 3 {
+         collect_files(&entry.path(), &relative, files)?;
+      } else if file_type.is_file() {
+         files.push(relative);
       }
    }
+   Ok(())
+}
 
+/// Where a bundle entry at `relative` is installed, or `None` if it is not
+/// a model file or grammar.
+fn bundle_destination(relative: &Path) -> Option<PathBuf> {
+   let parts = relative
+      .components()
+      .map(|c| match c {
+         Component::Normal(part) => part.to_str(),
+         _ => None,
+      })
+      .collect::<Option<Vec<_>>>()?;
+   let start = parts
+      .iter()
+      .take(2)
+      .position(|part| BUNDLE_DIRS.contains(part))?;
+   match &parts[start..] {
+      ["models", model, file] if MODEL_FILES.contains(file) => {
+         Some(config::model_dir().join(model).join(file))
+      },
+      ["grammars", file] if file.starts_with("tree-sitter-") && file.ends_with(".wasm") => {
+         Some(config::grammar_dir().join(file))
+      },
+      _ => None,
+   }
+}
+
+/// Loads the configured models and embeds a sample, checking that the
+/// result has the configured size and holds only finite values.
+async fn self_test() -> Result<()> {
+   let cfg = config::get();
+   let embedding = CandleEmbedder::new()?.encode_query(SELF_TEST_TEXT).await?;
+   if embedding.dense.len() != cfg.dense_vector_dim() {
+      return Err(Error::Setup(format!(
+         "self-test embedding has {} dimensions, expected {}",
+         embedding.dense.len(),
+         cfg.dense_vector_dim()
+      )));
+   }
+   if !embedding.dense.iter().all(|x| x.is_finite()) {
+      return Err(Error::Setup("self-test embedding contains NaN or infinite values".to_string()));
+   }
    Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn maps_bundle_entries_into_smgrep_dirs() {
+      let dest = |path: &str| bundle_destination(Path::new(path));
+      let model = config::model_dir()
+         .join("org--model")
+         .join("model.safetensors");
+      let grammar = config::grammar_dir().join("tree-sitter-rust.wasm");
+
+      assert_eq!(dest("models/org--model/model.safetensors"), Some(model.clone()));
+      assert_eq!(dest("bundle/models/org--model/model.safetensors"), Some(model));
+      assert_eq!(dest("grammars/tree-sitter-rust.wasm"), Some(grammar));
+      assert_eq!(dest("models/org--model/README.md"), None);
+      assert_eq!(dest("grammars/notes.txt"), None);
+      assert_eq!(dest("a/b/models/org--model/config.json"), None);
+      assert_eq!(dest("../models/org--model/config.json"), None);
+      assert_eq!(dest("models/../grammars/tree-sitter-rust.wasm"), None);
+   }
+}
@@ -0,0 +1,259 @@
+//! Similar code search command.
+//!
+//! Embeds a region of a file and searches the index for the chunks closest to
+//! it, to find the other places a pattern is implemented. The region is
+//! embedded through `embed_template` like the indexed chunks, with the
+//! context stack of the chunk that encloses it.
+
+use std::{
+   fs,
+   path::{Path, PathBuf},
+};
+
+use console::style;
+use ndarray::Array2;
+use serde::Serialize;
+
+use crate::{
+   Result, Str,
+   chunker::{self, ChainChunker, Chunker},
+   config,
+   error::Error,
+   git,
+   meta::MetaStore,
+   store::{SearchParams, Store},
+   types::{Chunk, ChunkType, ContextVec},
+};
+
+/// Matches fetched beyond `max` to make up for those dropped as the region
+/// itself.
+const EXTRA_CANDIDATES: usize = 10;
+
+/// Lines of a file given as `<path>:<start>-<end>` or `<path>:<line>`, 1-based
+/// and inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeRange {
+   pub path:       PathBuf,
+   pub start_line: u32,
+   pub end_line:   u32,
+}
+
+impl CodeRange {
+   /// Parses a `<path>:<start>-<end>` or `<path>:<line>` argument.
+   pub fn parse(s: &str) -> std::result::Result<Self, String> {
+      let invalid = || format!("invalid range `{s}`: expected <path>:<start>-<end>");
+      let (path, lines) = s.rsplit_once(':').ok_or_else(invalid)?;
+      let (start, end) = lines.split_once('-').unwrap_or((lines, lines));
+      let start_line: u32 = start.trim().parse().map_err(|_| invalid())?;
+      let end_line: u32 = end.trim().parse().map_err(|_| invalid())?;
+      if path.is_empty() || start_line == 0 || end_line < start_line {
+         return Err(invalid());
+      }
+      Ok(Self { path: PathBuf::from(path), start_line, end_line })
+   }
+
+   /// Whether the chunk starting at 0-based `start_line` and spanning
+   /// `num_lines` overlaps these lines of `path`.
+   fn overlaps(&self, path: &Path, start_line: u32, num_lines: u32) -> bool {
+      path == self.path
+         && start_line < self.end_line
+         && self.start_line <= start_line + num_lines + 1
+   }
+}
+
+/// A chunk similar to the region.
+#[derive(Debug, Serialize)]
+struct SimilarResult {
+   path:       PathBuf,
+   /// First line, 1-based.
+   start_line: u32,
+   /// Last line, 1-based and inclusive.
+   end_line:   u32,
+   score:      f32,
+   content:    Str,
+}
+
+/// Executes the similar command, printing the indexed chunks closest to the
+/// lines of `range`.
+pub async fn execute(
+   range: CodeRange,
+   path: Option<PathBuf>,
+   max: usize,
+   json: bool,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = std::env::current_dir()?;
+   let search_path = path.as_deref().unwrap_or(&root);
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(search_path), Ok)?;
+   MetaStore::load(&resolved_store_id)?.ensure_model_matches(&resolved_store_id)?;
+
+   let range = CodeRange { path: range.path.canonicalize()?, ..range };
+   let source = fs::read_to_string(&range.path)?;
+   let text = source
+      .lines()
+      .skip(range.start_line as usize - 1)
+      .take((range.end_line - range.start_line) as usize + 1)
+      .collect::<Vec<_>>()
+      .join("\n");
+   if text.trim().is_empty() {
+      return Err(Error::EmptyRange {
+         path:  range.path,
+         start: range.start_line,
+         end:   range.end_line,
+      });
+   }
+
+   let (embedder, store) = super::open_backends()?;
+
+   let first_line = range.start_line as usize - 1;
+   let mut region = Chunk::new(
+      Str::from(text.clone()),
+      first_line,
+      range.end_line as usize - 1,
+      ChunkType::Block,
+      &[],
+   );
+   // The context stack only matters to a template that embeds it
+   if config::get().embed_template != "{content}" {
+      let chunks = ChainChunker::default()
+         .chunk(&Str::from(source), &range.path)
+         .await?;
+      region.context = enclosing_context(chunks, first_line);
+   }
+   let embed = chunker::embed_text(&range.path, &region).unwrap_or(region.content);
+   let embeddings = embedder.compute_hybrid(&[embed]).await?;
+   let Some(embedding) = embeddings.first() else {
+      return Ok(());
+   };
+
+   // Only an explicit path narrows the search; otherwise the whole store is
+   // searched, as for `smgrep search`.
+   let filter = path
+      .as_deref()
+      .map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf()));
+   let no_colbert = Array2::zeros((0, 0));
+   let response = store
+      .search(SearchParams {
+         store_id:      &resolved_store_id,
+         query_text:    &text,
+         query_vector:  &embedding.dense,
+         query_colbert: &no_colbert,
+         limit:         max + EXTRA_CANDIDATES,
+         path_filter:   filter.as_deref(),
+         path_globs:    None,
+         indexed_after: None,
+         crate_name:    None,
+         rerank:        false,
+         rerank_depth:  None,
+         nprobes:       None,
+         exact:         false,
+         cancel:        None,
+      })
+      .await?;
+
+   let base = filter.as_deref().unwrap_or(&root);
+   let results: Vec<SimilarResult> = response
+      .results
+      .into_iter()
+      .filter(|r| {
+         !r.is_anchor.unwrap_or(false)
+            && r.chunk_type != Some(ChunkType::Module)
+            && !range.overlaps(&r.path, r.start_line, r.num_lines)
+      })
+      .take(max)
+      .map(|r| SimilarResult {
+         path:       relative(&r.path, base),
+         start_line: r.start_line + 1,
+         end_line:   r.start_line + r.num_lines,
+         score:      r.score,
+         content:    r.content,
+      })
+      .collect();
+
+   if json {
+      println!("{}", serde_json::to_string(&results)?);
+      return Ok(());
+   }
+   let target =
+      format!("{}:{}-{}", relative(&range.path, base).display(), range.start_line, range.end_line);
+   if results.is_empty() {
+      println!("No code similar to {target} found");
+      return Ok(());
+   }
+
+   println!("\n{}\n", style(format!("Similar to: {target}")).bold());
+   for (i, result) in results.iter().enumerate() {
+      println!(
+         "{}{}:{}-{} {}",
+         style(format!("{}) ", i + 1)).bold().cyan(),
+         style(result.path.display()).green(),
+         result.start_line,
+         result.end_line,
+         style(format!("(score: {:.3})", result.score)).dim()
+      );
+   }
+   println!();
+   Ok(())
+}
+
+/// Context stack of the innermost of `chunks` that holds 0-based `line`.
+fn enclosing_context(chunks: Vec<Chunk>, line: usize) -> ContextVec {
+   chunks
+      .into_iter()
+      .filter(|c| !c.is_anchor.unwrap_or(false) && c.start_line <= line && line <= c.end_line)
+      .min_by_key(|c| c.end_line - c.start_line)
+      .map(|c| c.context)
+      .unwrap_or_default()
+}
+
+/// `path` relative to `base` when it lies under it.
+fn relative(path: &Path, base: &Path) -> PathBuf {
+   path.strip_prefix(base).unwrap_or(path).to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parses_ranges() {
+      let range = CodeRange::parse("src/lib.rs:10-24").unwrap();
+      assert_eq!(range.path, PathBuf::from("src/lib.rs"));
+      assert_eq!((range.start_line, range.end_line), (10, 24));
+
+      let line = CodeRange::parse("C:/repo/main.rs:7").unwrap();
+      assert_eq!(line.path, PathBuf::from("C:/repo/main.rs"));
+      assert_eq!((line.start_line, line.end_line), (7, 7));
+
+      for invalid in ["src/lib.rs", "src/lib.rs:0-3", "src/lib.rs:9-3", ":1-2", "a.rs:x-2"] {
+         assert!(CodeRange::parse(invalid).is_err(), "{invalid}");
+      }
+   }
+
+   #[test]
+   fn excludes_chunks_overlapping_the_range() {
+      let range = CodeRange::parse("a.rs:10-20").unwrap();
+      assert!(range.overlaps(Path::new("a.rs"), 5, 5));
+      assert!(range.overlaps(Path::new("a.rs"), 0, 9));
+      assert!(range.overlaps(Path::new("a.rs"), 19, 10));
+      assert!(!range.overlaps(Path::new("a.rs"), 20, 10));
+      assert!(!range.overlaps(Path::new("a.rs"), 0, 8));
+      assert!(!range.overlaps(Path::new("b.rs"), 12, 4));
+   }
+
+   #[test]
+   fn takes_context_of_innermost_chunk() {
+      let chunk = |start, end, context: &[&'static str]| {
+         let context: Vec<Str> = context.iter().copied().map(Str::from_static).collect();
+         Chunk::new(Str::from_static(""), start, end, ChunkType::Function, &context)
+      };
+      let chunks = || {
+         let anchor = Chunk { is_anchor: Some(true), ..chunk(0, 5, &["anchor"]) };
+         vec![chunk(0, 40, &["impl Parser"]), chunk(10, 20, &["impl Parser", "fn parse"]), anchor]
+      };
+      assert_eq!(&enclosing_context(chunks(), 12)[..], ["impl Parser", "fn parse"]);
+      assert_eq!(&enclosing_context(chunks(), 30)[..], ["impl Parser"]);
+      assert_eq!(&enclosing_context(chunks(), 2)[..], ["impl Parser"]);
+      assert!(enclosing_context(chunks(), 50).is_empty());
+   }
+}
@@ -0,0 +1,180 @@
+//! Local statistics command.
+//!
+//! Shows the search usage recorded on this machine when `usage_stats` is
+//! enabled, the sync history kept in a store's metadata, or the space a
+//! store's dense vectors take.
+
+use chrono::{DateTime, Local};
+use console::style;
+
+use crate::{Result, config, git, meta::MetaStore, store, usage::UsageStats, util::format_size};
+
+/// Days of per-day query counts shown.
+const RECENT_DAYS: usize = 7;
+
+/// Query terms shown.
+const TOP_TERMS: usize = 10;
+
+/// Executes the stats command, showing or clearing usage statistics.
+pub fn execute(reset: bool, json: bool) -> Result<()> {
+   if reset {
+      UsageStats::reset()?;
+      println!("Usage statistics cleared.");
+      return Ok(());
+   }
+
+   let stats = UsageStats::load();
+   if json {
+      println!("{}", serde_json::to_string(&stats)?);
+      return Ok(());
+   }
+
+   if stats.searches() == 0 {
+      println!("No usage recorded yet.");
+      if !config::get().usage_stats {
+         println!(
+            "\nSet {} in {} to start recording. Statistics never leave this machine.",
+            style("usage_stats = true").green(),
+            config::config_file_path().display()
+         );
+      }
+      return Ok(());
+   }
+
+   let days = stats.queries_per_day.len() as u64;
+   println!(
+      "\n{} {}",
+      style("Search usage").bold(),
+      style(format!("(in {})", config::usage_stats_path().display())).dim()
+   );
+   println!();
+   println!(
+      "  Searches:        {} over {days} day(s), {} per day",
+      stats.searches(),
+      stats.searches() / days
+   );
+   if let Some(rate) = stats.cache_hit_rate() {
+      println!("  Daemon hit rate: {:.0}%", rate * 100.0);
+   }
+   if let Some(latency) = stats.average_latency() {
+      println!("  Avg latency:     {}ms", latency.as_millis());
+   }
+
+   println!("\n  {}", style("Recent days").bold());
+   for (day, count) in stats.queries_per_day.iter().rev().take(RECENT_DAYS) {
+      println!("    {day}  {count}");
+   }
+
+   let terms = stats.top_terms(TOP_TERMS);
+   if !terms.is_empty() {
+      println!("\n  {}", style("Top terms").bold());
+      let width = terms.iter().map(|(t, _)| t.len()).max().unwrap_or(0);
+      for (term, count) in terms {
+         println!("    {term:<width$}  {count}");
+      }
+   }
+   println!();
+
+   if !config::get().usage_stats {
+      println!("{}", style("Recording is currently disabled (usage_stats = false).").dim());
+   }
+   Ok(())
+}
+
+/// Executes `stats --history`, listing the recorded sync runs of a store,
+/// newest first.
+pub fn execute_history(store_id: Option<String>, json: bool) -> Result<()> {
+   let store_id = if let Some(id) = store_id {
+      id
+   } else {
+      let cwd = std::env::current_dir()?;
+      git::resolve_store_id(&cwd)?
+   };
+
+   let meta_store = MetaStore::load(&store_id)?;
+   let runs = meta_store.history();
+   if json {
+      println!("{}", serde_json::to_string(runs)?);
+      return Ok(());
+   }
+
+   if runs.is_empty() {
+      println!("No syncs recorded for {store_id}.");
+      return Ok(());
+   }
+
+   println!("\n{}", style(format!("Sync history of {store_id}")).bold());
+   println!();
+   println!(
+      "  {:<16}  {:<7}  {:>9}  {:>9}  {:>7}  {:>7}  {:>6}",
+      "Started", "Actor", "Duration", "Processed", "Indexed", "Deleted", "Errors"
+   );
+   for run in runs.iter().rev() {
+      let started = DateTime::from_timestamp(run.started_at.cast_signed(), 0)
+         .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+         .unwrap_or_default();
+      let errors = if run.errors > 0 {
+         style(run.errors.to_string()).red()
+      } else {
+         style(run.errors.to_string())
+      };
+      println!(
+         "  {started:<16}  {:<7}  {:>8.1}s  {:>9}  {:>7}  {:>7}  {errors:>6}",
+         run.actor.to_string(),
+         run.duration_ms as f64 / 1000.0,
+         run.processed,
+         run.indexed,
+         run.deleted,
+      );
+   }
+
+   if let Some(error) = runs.iter().rev().find_map(|r| r.last_error.as_deref()) {
+      println!("\n  {} {error}", style("Last error:").dim());
+   }
+   println!();
+   Ok(())
+}
+
+/// Executes `stats --index`, showing how much space the dense vectors of a
+/// store take and what `quantize_dense` saves.
+pub async fn execute_index(store_id: Option<String>, json: bool) -> Result<()> {
+   let store_id = if let Some(id) = store_id {
+      id
+   } else {
+      let cwd = std::env::current_dir()?;
+      git::resolve_store_id(&cwd)?
+   };
+
+   let info = store::open()?.get_info(&store_id).await?;
+   let dense_bytes = info.dense_bytes();
+   let saved = info.dense_f32_bytes().saturating_sub(dense_bytes);
+   if json {
+      println!(
+         "{}",
+         serde_json::json!({
+            "store_id": info.store_id,
+            "rows": info.row_count,
+            "dense_dim": info.dense_dim,
+            "dense_quantized": info.dense_quantized,
+            "dense_bytes": dense_bytes,
+            "dense_saved_bytes": saved,
+         })
+      );
+      return Ok(());
+   }
+
+   println!("\n{}", style(format!("Index of {store_id}")).bold());
+   println!();
+   println!("  Chunks:        {}", info.row_count);
+   println!(
+      "  Dense vectors: {} ({} dims, {})",
+      format_size(dense_bytes),
+      info.dense_dim,
+      if info.dense_quantized { "int8" } else { "f32" }
+   );
+   if info.dense_quantized {
+      println!("  Saved:         {} by quantize_dense", format_size(saved));
+   }
+   println!();
+   Ok(())
+}
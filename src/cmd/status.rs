@@ -1,20 +1,56 @@
 //! Server status command.
 //!
-//! Displays the status of all running smgrep daemon servers.
+//! Displays the status of all running smgrep daemon servers, along with each
+//! one's models, store size, last sync and memory use.
 
+use chrono::{DateTime, Local};
 use console::style;
+use serde::Serialize;
 
 use crate::{
    Result,
-   ipc::{self, Request, Response},
-   usock,
+   ipc::{self, Request, Response, ServerStatus},
+   usock, util,
 };
 
+/// How a daemon answered the health check.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Reachability {
+   Ok,
+   /// Accepted the connection but did not take the request.
+   Unresponsive,
+   /// Answered with something other than its health.
+   Unknown,
+   /// Its socket is left over from a daemon that is gone.
+   Stale,
+}
+
+/// One daemon as reported by `status --json`.
+#[derive(Debug, Serialize)]
+struct ServerReport {
+   store_id: String,
+   state:    Reachability,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   status:   Option<ServerStatus>,
+}
+
 /// Executes the status command to show running servers.
-pub async fn execute() -> Result<()> {
+pub async fn execute(json: bool) -> Result<()> {
    let servers = usock::list_running_servers();
 
-   if servers.is_empty() {
+   let mut buffer = ipc::SocketBuffer::new();
+   let mut reports = Vec::with_capacity(servers.len());
+   for store_id in servers {
+      reports.push(query(&mut buffer, store_id).await);
+   }
+
+   if json {
+      println!("{}", serde_json::to_string(&reports)?);
+      return Ok(());
+   }
+
+   if reports.is_empty() {
       println!("{}", style("No servers running").dim());
       return Ok(());
    }
@@ -22,39 +58,66 @@ pub async fn execute() -> Result<()> {
    println!("{}", style("Running servers:").bold());
    println!();
 
-   let mut buffer = ipc::SocketBuffer::new();
-   for store_id in servers {
-      match usock::Stream::connect(&store_id).await {
-         Ok(mut stream) => {
-            if buffer.send(&mut stream, &Request::Health).await.is_err() {
-               println!("  {} {} {}", style("●").yellow(), store_id, style("(unresponsive)").dim());
-               continue;
-            }
-
-            match buffer.recv(&mut stream).await {
-               Ok(Response::Health { status }) => {
-                  let state = if status.indexing {
-                     format!("indexing {}%", status.progress)
-                  } else {
-                     "ready".to_string()
-                  };
-                  println!(
-                     "  {} {} {}",
-                     style("●").green(),
-                     store_id,
-                     style(format!("({state})")).dim()
-                  );
-               },
-               _ => {
-                  println!("  {} {} {}", style("●").yellow(), store_id, style("(unknown)").dim());
-               },
-            }
-         },
-         Err(_) => {
-            println!("  {} {} {}", style("●").red(), store_id, style("(stale)").dim());
-         },
-      }
+   for report in &reports {
+      print_report(report);
    }
 
    Ok(())
 }
+
+/// Asks the daemon serving `store_id` for its health.
+async fn query(buffer: &mut ipc::SocketBuffer, store_id: String) -> ServerReport {
+   let Ok(mut stream) = usock::Stream::connect(&store_id).await else {
+      return ServerReport { store_id, state: Reachability::Stale, status: None };
+   };
+   if buffer.send(&mut stream, &Request::Health).await.is_err() {
+      return ServerReport { store_id, state: Reachability::Unresponsive, status: None };
+   }
+   match buffer.recv(&mut stream).await {
+      Ok(Response::Health { status }) => {
+         ServerReport { store_id, state: Reachability::Ok, status: Some(status) }
+      },
+      _ => ServerReport { store_id, state: Reachability::Unknown, status: None },
+   }
+}
+
+fn print_report(report: &ServerReport) {
+   let Some(status) = &report.status else {
+      let (dot, label) = match report.state {
+         Reachability::Stale => (style("●").red(), "stale"),
+         Reachability::Unresponsive => (style("●").yellow(), "unresponsive"),
+         Reachability::Ok | Reachability::Unknown => (style("●").yellow(), "unknown"),
+      };
+      println!("  {dot} {} {}", report.store_id, style(format!("({label})")).dim());
+      return;
+   };
+
+   let state = if status.indexing {
+      format!("indexing {}%", status.progress)
+   } else {
+      "ready".to_string()
+   };
+   println!("  {} {} {}", style("●").green(), report.store_id, style(format!("({state})")).dim());
+
+   let models = if status.models_loaded {
+      format!("loaded on {}", status.device)
+   } else {
+      format!("not loaded ({})", status.device)
+   };
+   let last_sync = status
+      .last_sync
+      .and_then(|secs| DateTime::from_timestamp(secs.cast_signed(), 0))
+      .map_or_else(
+         || "never".to_string(),
+         |t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+      );
+   let memory = status
+      .memory_bytes
+      .map_or_else(|| "unknown".to_string(), util::format_size);
+
+   println!("      {} {models}", style("Models:   ").dim());
+   println!("      {} {} chunks in {} files", style("Store:    ").dim(), status.rows, status.files);
+   println!("      {} {last_sync}", style("Last sync:").dim());
+   println!("      {} {memory}", style("Memory:   ").dim());
+   println!("      {} {} batches", style("Queue:    ").dim(), status.queue_depth);
+}
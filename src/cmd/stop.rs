@@ -2,9 +2,10 @@
 //!
 //! Gracefully shuts down a running daemon server for the current project.
 
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, time::Duration};
 
 use console::style;
+use tokio::time;
 
 use crate::{
    Result, git,
@@ -12,8 +13,14 @@ use crate::{
    usock,
 };
 
-/// Executes the stop command to shut down a server.
-pub async fn execute(path: Option<PathBuf>) -> Result<()> {
+/// How long to wait for a daemon to finish indexing and remove its socket.
+const STOP_WAIT: Duration = Duration::from_secs(30);
+/// Delay between checks for the socket.
+const POLL_DELAY: Duration = Duration::from_millis(100);
+
+/// Executes the stop command to shut down a server, without letting it
+/// finish indexing if `force` is set.
+pub async fn execute(path: Option<PathBuf>, force: bool) -> Result<()> {
    let root = env::current_dir()?;
    let target_path = path.unwrap_or(root);
 
@@ -27,11 +34,20 @@ pub async fn execute(path: Option<PathBuf>) -> Result<()> {
    let mut buffer = ipc::SocketBuffer::new();
 
    if let Ok(mut stream) = usock::Stream::connect(&store_id).await {
-      buffer.send(&mut stream, &Request::Shutdown).await?;
+      buffer
+         .send(&mut stream, &Request::Shutdown { force })
+         .await?;
 
       match buffer.recv(&mut stream).await {
          Ok(Response::Shutdown { success: true }) => {
-            println!("{}", style("Server stopped").green());
+            if wait_for_exit(&store_id).await {
+               println!("{}", style("Server stopped").green());
+            } else {
+               println!(
+                  "{}",
+                  style("Server is still finishing indexing; use --force to stop it now").yellow()
+               );
+            }
          },
          Ok(_) => {
             println!("{}", style("Unexpected response from server").yellow());
@@ -47,3 +63,17 @@ pub async fn execute(path: Option<PathBuf>) -> Result<()> {
 
    Ok(())
 }
+
+/// Waits up to [`STOP_WAIT`] for the daemon to remove its socket, returning
+/// whether it did.
+async fn wait_for_exit(store_id: &str) -> bool {
+   let socket = usock::socket_path(store_id);
+   let deadline = time::Instant::now() + STOP_WAIT;
+   while socket.exists() {
+      if time::Instant::now() >= deadline {
+         return false;
+      }
+      time::sleep(POLL_DELAY).await;
+   }
+   true
+}
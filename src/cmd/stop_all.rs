@@ -10,8 +10,9 @@ use crate::{
    usock,
 };
 
-/// Executes the stop-all command to shut down all running servers.
-pub async fn execute() -> Result<()> {
+/// Executes the stop-all command to shut down all running servers, without
+/// letting them finish indexing if `force` is set.
+pub async fn execute(force: bool) -> Result<()> {
    let servers = usock::list_running_servers();
 
    if servers.is_empty() {
@@ -25,7 +26,7 @@ pub async fn execute() -> Result<()> {
    for store_id in servers {
       if let Ok(mut stream) = usock::Stream::connect(&store_id).await {
          let mut buffer = ipc::SocketBuffer::new();
-         if let Err(e) = buffer.send(&mut stream, &Request::Shutdown).await {
+         if let Err(e) = buffer.send(&mut stream, &Request::Shutdown { force }).await {
             tracing::debug!("Failed to send shutdown to {}: {}", store_id, e);
             failed += 1;
             continue;
@@ -0,0 +1,53 @@
+//! Unload models command.
+//!
+//! Asks the daemon for the current project to free its models while it keeps
+//! serving. The next search loads them again.
+
+use std::{env, path::PathBuf};
+
+use console::style;
+
+use crate::{
+   Result, git,
+   ipc::{self, Request, Response},
+   usock,
+};
+
+/// Executes the unload command against a running server.
+pub async fn execute(path: Option<PathBuf>) -> Result<()> {
+   let root = env::current_dir()?;
+   let target_path = path.unwrap_or(root);
+
+   let store_id = git::resolve_store_id(&target_path)?;
+
+   if !usock::socket_path(&store_id).exists() {
+      println!("{}", style("No server running for this project").yellow());
+      return Ok(());
+   }
+
+   let Ok(mut stream) = usock::Stream::connect(&store_id).await else {
+      usock::remove_socket(&store_id);
+      println!("{}", style("Removed stale socket").yellow());
+      return Ok(());
+   };
+
+   let mut buffer = ipc::SocketBuffer::new();
+   buffer.send(&mut stream, &Request::Unload).await?;
+
+   match buffer.recv(&mut stream).await? {
+      Response::Unload { unloaded: true } => {
+         println!("{}", style("Models unloaded").green());
+      },
+      Response::Unload { unloaded: false } => {
+         println!("{}", style("No models were loaded").dim());
+      },
+      Response::Error { message } => {
+         println!("{}", style(format!("Server error: {message}")).red());
+      },
+      _ => {
+         println!("{}", style("Unexpected response from server").yellow());
+      },
+   }
+
+   Ok(())
+}
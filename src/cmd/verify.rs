@@ -0,0 +1,289 @@
+//! Index integrity verification command.
+//!
+//! Cross-checks a store's rows against its metadata and the files on disk,
+//! reporting the files whose index has drifted from them, and with
+//! `--repair` drops those files from both so the next sync indexes them
+//! afresh.
+
+use std::{
+   collections::{BTreeMap, HashMap},
+   path::{Path, PathBuf},
+};
+
+use console::style;
+use serde::Serialize;
+
+use crate::{
+   Result,
+   cmd::daemon,
+   config,
+   error::Error,
+   git,
+   index_lock::IndexLock,
+   meta::{FileHash, MetaStore},
+   store::{self, Store},
+};
+
+/// Paths listed per kind of issue in the text report.
+const MAX_LISTED: usize = 10;
+
+/// Way a file's index disagrees with its metadata or the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum IssueKind {
+   /// Rows for a file or directory no longer on disk.
+   Orphaned,
+   /// Rows whose dense vectors do not have the configured dimensions.
+   Dimension,
+   /// Rows from another version of the file than the metadata records.
+   HashMismatch,
+   /// Rows for a file the metadata does not track.
+   Untracked,
+   /// Metadata for a file that has no rows.
+   Dangling,
+}
+
+impl IssueKind {
+   const fn describe(self) -> &'static str {
+      match self {
+         Self::Orphaned => "chunks of files missing on disk",
+         Self::Dimension => "chunks with vectors of the wrong dimension",
+         Self::HashMismatch => "files whose chunks do not match the recorded hash",
+         Self::Untracked => "files with chunks but no metadata",
+         Self::Dangling => "metadata entries without chunks",
+      }
+   }
+}
+
+#[derive(Debug, Serialize)]
+struct Issue {
+   kind: IssueKind,
+   path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyReport {
+   store_id: String,
+   /// Files the metadata tracks.
+   files:    usize,
+   /// Files and directories with rows in the store.
+   stored:   usize,
+   issues:   Vec<Issue>,
+   repaired: bool,
+}
+
+/// What is known about each file, gathered from the metadata, the store and
+/// the disk.
+#[derive(Default)]
+struct Snapshot {
+   meta:       HashMap<PathBuf, FileHash>,
+   stored:     HashMap<PathBuf, FileHash>,
+   /// Dense dimensions of each file's first row that has the wrong ones.
+   wrong_dims: HashMap<PathBuf, usize>,
+}
+
+impl Snapshot {
+   /// The issue with each file that has one, worst first when a file has
+   /// several. `on_disk` tells whether a stored path still exists.
+   fn issues(&self, on_disk: impl Fn(&Path) -> bool) -> Vec<Issue> {
+      let mut issues = Vec::new();
+      for (path, stored_hash) in &self.stored {
+         let kind = if !on_disk(path) {
+            IssueKind::Orphaned
+         } else if self.wrong_dims.contains_key(path) {
+            IssueKind::Dimension
+         } else {
+            match self.meta.get(path) {
+               Some(hash) if hash != stored_hash => IssueKind::HashMismatch,
+               Some(_) => continue,
+               // Module summaries are stored under their directory
+               None if path.is_dir() => continue,
+               None => IssueKind::Untracked,
+            }
+         };
+         issues.push(Issue { kind, path: path.clone() });
+      }
+      issues.extend(
+         self
+            .meta
+            .keys()
+            .filter(|path| !self.stored.contains_key(*path))
+            .map(|path| Issue { kind: IssueKind::Dangling, path: path.clone() }),
+      );
+      issues.sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+      issues
+   }
+}
+
+/// Whether a stored path is on disk; documents fetched over HTTP are stored
+/// under their URL and always count as present.
+fn on_disk(path: &Path) -> bool {
+   path.to_string_lossy().contains("://") || path.exists()
+}
+
+/// Executes the verify command, exiting with status 1 when issues are found
+/// and not repaired.
+pub async fn execute(
+   path: Option<PathBuf>,
+   repair: bool,
+   json: bool,
+   store_id: Option<String>,
+) -> Result<()> {
+   let root = path.map_or_else(std::env::current_dir, Ok)?;
+   let resolved_store_id = store_id.map_or_else(|| git::resolve_store_id(&root), Ok)?;
+
+   // A daemon keeps its own copy of the metadata and would write it back
+   if repair
+      && daemon::try_connect_existing(&resolved_store_id)
+         .await?
+         .is_some()
+   {
+      return Err(Error::DaemonRunning(resolved_store_id));
+   }
+   let lock = if repair {
+      IndexLock::acquire(&resolved_store_id)?
+   } else {
+      IndexLock::acquire_shared(&resolved_store_id)?
+   };
+
+   let store = store::open()?;
+   let mut meta_store = MetaStore::load(&resolved_store_id)?;
+   let expected_dim = config::get().dense_vector_dim();
+   let mut snapshot = Snapshot {
+      meta: meta_store
+         .all_paths()
+         .filter_map(|path| Some((path.clone(), meta_store.get_hash(path)?)))
+         .collect(),
+      stored: store.get_file_hashes(&resolved_store_id).await?,
+      ..Snapshot::default()
+   };
+   for chunk in store.chunk_vectors(&resolved_store_id).await? {
+      if chunk.vector.len() != expected_dim {
+         snapshot
+            .wrong_dims
+            .entry(chunk.path)
+            .or_insert(chunk.vector.len());
+      }
+   }
+   let issues = snapshot.issues(on_disk);
+
+   let repaired = repair && !issues.is_empty();
+   if repaired {
+      let paths: Vec<PathBuf> = issues.iter().map(|issue| issue.path.clone()).collect();
+      store.delete_files(&resolved_store_id, &paths).await?;
+      for path in &paths {
+         meta_store.remove(path);
+      }
+      meta_store.save()?;
+   }
+   drop(lock);
+
+   let report = VerifyReport {
+      store_id: resolved_store_id,
+      files: snapshot.meta.len(),
+      stored: snapshot.stored.len(),
+      issues,
+      repaired,
+   };
+   if json {
+      println!("{}", serde_json::to_string(&report)?);
+   } else {
+      print_report(&report, expected_dim, &snapshot.wrong_dims);
+   }
+
+   if !report.issues.is_empty() && !report.repaired {
+      std::process::exit(1);
+   }
+   Ok(())
+}
+
+fn print_report(report: &VerifyReport, expected_dim: usize, wrong_dims: &HashMap<PathBuf, usize>) {
+   println!(
+      "\n{} {}\n",
+      style(format!("Verified {}", report.store_id)).bold(),
+      style(format!("({} files tracked, {} stored)", report.files, report.stored)).dim()
+   );
+   if report.issues.is_empty() {
+      println!("{}\n", style("No issues found").green());
+      return;
+   }
+
+   let mut by_kind: BTreeMap<IssueKind, Vec<&Path>> = BTreeMap::new();
+   for issue in &report.issues {
+      by_kind.entry(issue.kind).or_default().push(&issue.path);
+   }
+   for (kind, paths) in by_kind {
+      println!("{} {}", style(paths.len()).bold().yellow(), kind.describe());
+      for path in paths.iter().take(MAX_LISTED) {
+         match wrong_dims
+            .get(*path)
+            .filter(|_| kind == IssueKind::Dimension)
+         {
+            Some(dim) => println!(
+               "   {} {}",
+               path.display(),
+               style(format!("({dim} dims, expected {expected_dim})")).dim()
+            ),
+            None => println!("   {}", path.display()),
+         }
+      }
+      if paths.len() > MAX_LISTED {
+         println!("   {}", style(format!("...and {} more", paths.len() - MAX_LISTED)).dim());
+      }
+   }
+
+   if report.repaired {
+      println!(
+         "\n{}",
+         style(format!(
+            "Removed {} files from the index; the next sync indexes them again",
+            report.issues.len()
+         ))
+         .green()
+      );
+   } else {
+      println!("\nRun {} to fix them", style("smgrep verify --repair").cyan());
+   }
+   println!();
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn classifies_each_file_once() {
+      let hash = |s: &str| FileHash::sum(s);
+      let path = |s: &str| PathBuf::from(s);
+      let snapshot = Snapshot {
+         meta:       HashMap::from([
+            (path("/repo/ok.rs"), hash("ok")),
+            (path("/repo/edited.rs"), hash("new")),
+            (path("/repo/gone.rs"), hash("gone")),
+            (path("/repo/unstored.rs"), hash("unstored")),
+            (path("/repo/wide.rs"), hash("wide")),
+         ]),
+         stored:     HashMap::from([
+            (path("/repo/ok.rs"), hash("ok")),
+            (path("/repo/edited.rs"), hash("old")),
+            (path("/repo/gone.rs"), hash("other")),
+            (path("/repo/stray.rs"), hash("stray")),
+            (path("/repo/wide.rs"), hash("wide")),
+         ]),
+         wrong_dims: HashMap::from([(path("/repo/wide.rs"), 768)]),
+      };
+
+      let issues: Vec<(IssueKind, PathBuf)> = snapshot
+         .issues(|p| p != Path::new("/repo/gone.rs"))
+         .into_iter()
+         .map(|issue| (issue.kind, issue.path))
+         .collect();
+      assert_eq!(issues, [
+         (IssueKind::Orphaned, path("/repo/gone.rs")),
+         (IssueKind::Dimension, path("/repo/wide.rs")),
+         (IssueKind::HashMismatch, path("/repo/edited.rs")),
+         (IssueKind::Untracked, path("/repo/stray.rs")),
+         (IssueKind::Dangling, path("/repo/unstored.rs")),
+      ]);
+   }
+}
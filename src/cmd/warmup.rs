@@ -0,0 +1,45 @@
+//! Warm-up command.
+//!
+//! Has the daemon for the current project load its models and index and run
+//! a throwaway search, starting the daemon if needed, so that the first real
+//! search does not pay for the cold start.
+
+use std::{env, path::PathBuf, time::Duration};
+
+use console::style;
+
+use crate::{
+   Result,
+   cmd::daemon,
+   git,
+   ipc::{self, Request, Response},
+};
+
+/// Executes the warmup command, spawning a server if none is running.
+pub async fn execute(path: Option<PathBuf>) -> Result<()> {
+   let root = env::current_dir()?;
+   let target_path = path.unwrap_or(root);
+
+   let store_id = git::resolve_store_id(&target_path)?;
+   let mut stream = daemon::connect_matching_daemon(&target_path, &store_id).await?;
+
+   println!("{}", style("Warming up...").dim());
+
+   let mut buffer = ipc::SocketBuffer::new();
+   buffer.send(&mut stream, &Request::WarmUp).await?;
+
+   match buffer.recv(&mut stream).await? {
+      Response::WarmUp { ms } => {
+         let elapsed = Duration::from_millis(ms).as_secs_f64();
+         println!("{}", style(format!("Warmed up in {elapsed:.1}s")).green());
+      },
+      Response::Error { message } => {
+         println!("{}", style(format!("Server error: {message}")).red());
+      },
+      _ => {
+         println!("{}", style("Unexpected response from server").yellow());
+      },
+   }
+
+   Ok(())
+}
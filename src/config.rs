@@ -1,6 +1,7 @@
 //! Configuration management for model settings, performance tuning, and paths.
 
 use std::{
+   collections::BTreeMap,
    fs,
    path::{Path, PathBuf},
    sync::OnceLock,
@@ -14,61 +15,622 @@ use figment::{
 use serde::{Deserialize, Serialize};
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
+static PROFILE_OVERRIDE: OnceLock<Profile> = OnceLock::new();
+static MODEL_OVERRIDE: OnceLock<(String, usize)> = OnceLock::new();
+static STORE_TARGET: OnceLock<StoreTarget> = OnceLock::new();
+
+/// Name of the optional per-repository config file at the repository root.
+pub const REPO_CONFIG_FILE: &str = ".smgrep.toml";
+
+/// Settings a repository's [`REPO_CONFIG_FILE`] may set.
+///
+/// The file comes with the checkout, so it only chooses how its code is
+/// chunked and searched; hooks, URLs, credentials, models and store backends
+/// are left to the user's own config.
+pub const REPO_CONFIG_KEYS: &[&str] = &[
+   "embed_template",
+   "query_prefix",
+   "long_chunks",
+   "window_pooling",
+   "window_overlap",
+   "docs_sources",
+   "language_overrides",
+   "max_file_size_kb",
+   "minified_line_length",
+   "stream_chunk_threshold_kb",
+   "max_chunks_per_file",
+   "profile",
+   "rerank_depth",
+   "anchor_weight",
+   "preview_lines",
+   "cross_encoder_depth",
+   "fusion",
+   "fusion_dense_weight",
+   "fusion_fts_weight",
+   "fusion_colbert_weight",
+   "fusion_rrf_k",
+   "structure_boosts",
+   "path_boosts",
+   "vector_nprobes",
+   "binary_prefilter_min_chunks",
+   "binary_prefilter_oversample",
+];
+
+/// Named presets bundling related search and indexing settings.
+///
+/// `Balanced` leaves every setting as configured; the other profiles change
+/// the defaults of the knobs they control, which the config files and
+/// environment still override.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+   /// Skip `ColBERT` reranking and favor throughput.
+   Fast,
+   /// Use the configured settings unchanged.
+   #[default]
+   Balanced,
+   /// Rerank deeper and lean harder on file-level signals.
+   Quality,
+}
+
+/// Values a [`Profile`] gives the settings it controls.
+#[derive(Serialize)]
+struct Preset {
+   fast_mode:          bool,
+   rerank_depth:       usize,
+   preview_lines:      usize,
+   anchor_weight:      f32,
+   default_batch_size: usize,
+   max_batch_size:     usize,
+}
+
+impl Profile {
+   /// Settings this profile controls, layered under the config files and
+   /// environment.
+   const fn preset(self) -> Option<Preset> {
+      match self {
+         Self::Fast => Some(Preset {
+            fast_mode:          true,
+            rerank_depth:       20,
+            preview_lines:      8,
+            anchor_weight:      0.0,
+            default_batch_size: 64,
+            max_batch_size:     128,
+         }),
+         Self::Balanced => None,
+         Self::Quality => Some(Preset {
+            fast_mode:          false,
+            rerank_depth:       150,
+            preview_lines:      20,
+            anchor_weight:      0.15,
+            default_batch_size: 32,
+            max_batch_size:     64,
+         }),
+      }
+   }
+}
+
+/// Vector store implementation used for indexes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+   /// `LanceDB` table directory, with stores of up to
+   /// `flat_store_max_chunks` rows kept in a flat file instead.
+   #[default]
+   Lance,
+   /// Single flat file per store, searched by brute force in memory.
+   Flat,
+   /// Single `SQLite` file with sqlite-vec and FTS5 (requires the `sqlite`
+   /// feature).
+   Sqlite,
+   /// Remote Qdrant server at `qdrant_url`.
+   Qdrant,
+   /// Postgres database with pgvector at `postgres_url` (requires the
+   /// `postgres` feature).
+   Postgres,
+}
+
+impl StoreBackend {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Lance => "lance",
+         Self::Flat => "flat",
+         Self::Sqlite => "sqlite",
+         Self::Qdrant => "qdrant",
+         Self::Postgres => "postgres",
+      }
+   }
+}
+
+/// Format of the per-store file metadata kept for incremental indexing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetaBackend {
+   /// JSON, moving to `SQLite` for good once a store tracks more than
+   /// [`SQLITE_META_MIN_FILES`](crate::meta::SQLITE_META_MIN_FILES) files,
+   /// if the `sqlite` feature is built in.
+   #[default]
+   Auto,
+   /// A JSON file rewritten whole on every save.
+   Json,
+   /// A `SQLite` database written incrementally (requires the `sqlite`
+   /// feature).
+   Sqlite,
+}
+
+/// Hash of a file's contents used to tell whether it changed since it was
+/// indexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileHashAlgorithm {
+   #[default]
+   Sha256,
+   /// Several times faster than SHA-256 on large files, using SIMD.
+   Blake3,
+}
+
+impl FileHashAlgorithm {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Sha256 => "sha256",
+         Self::Blake3 => "blake3",
+      }
+   }
+}
+
+/// Score multiplier for results whose path matches any of `globs`.
+///
+/// Globs match the full path and ignore case, so most should start with
+/// `**/`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathBoost {
+   pub globs:      Vec<String>,
+   pub multiplier: f32,
+}
+
+impl PathBoost {
+   fn new(globs: &[&str], multiplier: f32) -> Self {
+      Self { globs: globs.iter().map(ToString::to_string).collect(), multiplier }
+   }
+}
+
+/// Approximate nearest-neighbor index built over dense vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexType {
+   /// IVF partitions with product-quantized vectors; smallest and fastest to
+   /// build.
+   #[default]
+   IvfPq,
+   /// IVF partitions with an HNSW graph over product-quantized vectors.
+   IvfHnswPq,
+   /// IVF partitions with an HNSW graph over 8-bit scalar-quantized vectors;
+   /// higher recall at 4x less compression than PQ.
+   IvfHnswSq,
+}
+
+/// Distance used to compare dense vectors in the ANN index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorMetric {
+   #[default]
+   L2,
+   Cosine,
+   Dot,
+}
+
+impl VectorMetric {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::L2 => "l2",
+         Self::Cosine => "cosine",
+         Self::Dot => "dot",
+      }
+   }
+}
+
+/// How the dense model's token embeddings are reduced to one vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pooling {
+   /// Embedding of the first (`[CLS]`) token.
+   Cls,
+   /// Average of the embeddings of all tokens, ignoring padding.
+   Mean,
+   /// Embedding of the last token, for decoder-style models.
+   LastToken,
+}
+
+impl Pooling {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Cls => "cls",
+         Self::Mean => "mean",
+         Self::LastToken => "last-token",
+      }
+   }
+}
+
+/// What happens to chunks longer than the dense model reads
+/// (`dense_max_length` tokens).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LongChunks {
+   /// Split them into chunks that fit.
+   #[default]
+   Split,
+   /// Keep them whole and embed overlapping token windows, pooled into one
+   /// vector with `window_pooling`.
+   Windows,
+}
+
+/// How the dense embeddings of a long chunk's windows are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowPooling {
+   #[default]
+   Mean,
+   Max,
+}
+
+impl WindowPooling {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Mean => "mean",
+         Self::Max => "max",
+      }
+   }
+}
+
+/// Device the models run on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComputeDevice {
+   /// Metal or CUDA, whichever this build supports and finds, else the CPU.
+   #[default]
+   Auto,
+   Cpu,
+   Cuda,
+   Metal,
+}
+
+impl ComputeDevice {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Auto => "auto",
+         Self::Cpu => "cpu",
+         Self::Cuda => "cuda",
+         Self::Metal => "metal",
+      }
+   }
+}
+
+/// Floating-point precision the models run in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelDtype {
+   #[default]
+   F32,
+   /// Half precision on GPUs; F32 on the CPU.
+   F16,
+   /// Bfloat16 on GPUs; F32 on the CPU.
+   Bf16,
+}
+
+/// How the dense, full-text and `ColBERT` scores of a candidate are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FusionMethod {
+   /// Weighted sum of the component scores.
+   #[default]
+   Weighted,
+   /// Weighted reciprocal rank fusion over each component's ranking.
+   Rrf,
+}
 
 /// Application configuration loaded from config file and environment variables
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
-   pub dense_model:   String,
-   pub colbert_model: String,
-   pub dense_dim:     usize,
-   pub colbert_dim:   usize,
-
-   pub query_prefix:       String,
-   pub dense_max_length:   usize,
-   pub colbert_max_length: usize,
-   pub default_batch_size: usize,
-   pub max_batch_size:     usize,
-   pub max_threads:        usize,
+   pub dense_model:        String,
+   pub colbert_model:      String,
+   /// Hub revision (commit, tag or branch) of a model by id; unlisted models
+   /// follow `main`.
+   pub model_revisions:    BTreeMap<String, String>,
+   pub dense_dim:          usize,
+   pub colbert_dim:        usize,
+   /// Leading dimensions of the dense embeddings to keep, for models trained
+   /// with Matryoshka representation learning; by default all of them.
+   pub dense_truncate_dim: Option<usize>,
+   /// Pooling of the dense model's token embeddings; by default the one in
+   /// the model's sentence-transformers config, else the architecture's.
+   pub dense_pooling:      Option<Pooling>,
+   pub long_chunks:        LongChunks,
+   pub window_pooling:     WindowPooling,
+   /// Tokens shared by consecutive windows of a long chunk.
+   pub window_overlap:     usize,
+
+   pub query_prefix:         String,
+   /// Text embedded for each chunk, with `{path}`, `{context}` (the chunk's
+   /// enclosing file, classes and functions) and `{content}` filled in.
+   pub embed_template:       String,
+   pub dense_max_length:     usize,
+   pub colbert_max_length:   usize,
+   /// Mark `ColBERT` queries and documents with the checkpoint's `[Q]`/`[D]`
+   /// tokens and pad queries with `[MASK]` to `colbert_query_length`.
+   pub colbert_markers:      bool,
+   pub colbert_query_length: usize,
+   pub default_batch_size:   usize,
+   pub max_batch_size:       usize,
+   /// Tokens per embedding batch to start from; 0 sizes it from
+   /// `default_batch_size` and, on the CPU, the memory available.
+   pub batch_tokens:         usize,
+   /// Tokens an embedding batch may grow to; 0 picks a limit for the device.
+   pub max_batch_tokens:     usize,
+   pub max_threads:          usize,
+
+   pub follow_symlinks:   bool,
+   /// External documentation indexed into the docs channel: paths relative
+   /// to the repository root (which may point outside it) or http(s) URLs.
+   pub docs_sources:      Vec<String>,
+   pub store_backend:     StoreBackend,
+   pub qdrant_url:        String,
+   pub qdrant_api_key:    Option<String>,
+   pub postgres_url:      String,
+   /// PEM file with an extra CA to trust for Postgres TLS connections.
+   pub postgres_ca_cert:  Option<PathBuf>,
+   pub meta_backend:      MetaBackend,
+   /// Changing it re-hashes every file on the next sync, which rewrites the
+   /// index without re-embedding it.
+   pub file_hash:         FileHashAlgorithm,
+   /// Seconds to wait for a store locked by another process, as by a
+   /// running sync, before giving up; 0 waits indefinitely.
+   pub lock_timeout_secs: u64,
+   /// Give each git branch but the default one a store of its own,
+   /// `<store>@<branch>`, that reuses the default branch's embeddings.
+   pub branch_stores:     bool,
+   /// Search checked-out git submodules along with their repository, each
+   /// indexed in a store of its own.
+   pub submodule_stores:  bool,
+
+   /// Files larger than this many KiB are not indexed.
+   pub max_file_size_kb:     u64,
+   /// Files whose lines average more than this many bytes are treated as
+   /// minified and not indexed; 0 disables.
+   pub minified_line_length: usize,
+
+   /// Files over this many KiB are chunked by lines while being read, rather
+   /// than loaded whole and parsed.
+   pub stream_chunk_threshold_kb: u64,
+   /// Chunks indexed per file; the rest of a longer file is left out with a
+   /// warning. 0 disables the limit.
+   pub max_chunks_per_file:       usize,
+   /// Index a summary of each directory's files as a `module` chunk.
+   pub module_summaries:          bool,
+
+   /// OpenAI-compatible chat completions endpoint that writes a one-sentence
+   /// summary of each definition to embed with it; unset disables summaries.
+   pub summarizer_url:     Option<String>,
+   pub summarizer_model:   String,
+   pub summarizer_api_key: Option<String>,
+
+   /// Language for files by extension (`svelte = "html"`), over the built-in
+   /// mapping. Listed extensions are indexed even when unknown otherwise.
+   pub language_overrides: BTreeMap<String, String>,
+
+   /// Rows up to which a `lance` store is kept in a flat file; 0 always uses
+   /// Lance.
+   pub flat_store_max_chunks: usize,
+
+   pub profile:       Profile,
+   pub rerank_depth:  usize,
+   pub anchor_weight: f32,
+   pub preview_lines: usize,
+
+   /// Cross-encoder run over the top results with `--rerank cross`.
+   pub cross_encoder_model:      String,
+   /// Results rescored by the cross-encoder.
+   pub cross_encoder_depth:      usize,
+   /// Tokens of query and chunk the cross-encoder reads together.
+   pub cross_encoder_max_length: usize,
+
+   pub fusion:                FusionMethod,
+   pub fusion_dense_weight:   f32,
+   pub fusion_fts_weight:     f32,
+   pub fusion_colbert_weight: f32,
+   /// Rank offset `k` for [`FusionMethod::Rrf`].
+   pub fusion_rrf_k:          f32,
+
+   /// Score multipliers by chunk type (`function`, `class`, `interface`,
+   /// `method`, `typealias`, `block`, `other`, `doc`).
+   pub structure_boosts: BTreeMap<String, f32>,
+   /// Score multipliers by path; each rule with a matching glob applies.
+   pub path_boosts:      Vec<PathBoost>,
+
+   pub vector_index:                VectorIndexType,
+   /// IVF partition count (nlist); `None` sizes it from the row count.
+   pub vector_partitions:           Option<u32>,
+   /// IVF partitions searched per query (nprobe).
+   pub vector_nprobes:              usize,
+   /// Distance between dense vectors; `None` uses [`Self::vector_metric`].
+   pub vector_metric:               Option<VectorMetric>,
+   /// Stores dense vectors in the lance backend as `i8` with a scale per
+   /// vector, a quarter of the `f32` size. Such tables are compared by cosine
+   /// distance, the default `vector_metric` with this set.
+   pub quantize_dense:              bool,
+   /// Chunks from which a lance store without an ANN index is first searched
+   /// by hamming distance over binary codes of the dense vectors; 0 always
+   /// scans the full vectors.
+   pub binary_prefilter_min_chunks: usize,
+   /// Candidates the binary prefilter keeps per candidate requested, which
+   /// are then ranked by their full vectors.
+   pub binary_prefilter_oversample: usize,
 
    pub port:                     u16,
+   /// Seconds without client requests before the daemon exits; 0 keeps it
+   /// running.
    pub idle_timeout_secs:        u64,
+   /// Seconds without client requests before the daemon frees its models;
+   /// 0 keeps them loaded.
+   pub model_unload_secs:        u64,
    pub idle_check_interval_secs: u64,
    pub worker_timeout_ms:        u64,
+   /// Times a batch lost to a crashed or hung embedding worker is retried.
+   pub embed_retries:            u32,
+   /// Rows written by the daemon before it compacts the store; 0 disables.
+   pub compact_after_writes:     u64,
+   /// Seconds without client requests before pending compaction runs.
+   pub compact_idle_secs:        u64,
+   /// Load the store's indexes and hot columns into memory when the daemon
+   /// starts and after compaction.
+   pub warm_up:                  bool,
+   /// Load the embedding models when the daemon starts instead of on the
+   /// first search.
+   pub preload_models:           bool,
+   /// Lock stores of up to this many MiB in memory after warm-up; 0
+   /// disables.
+   pub lock_index_max_mb:        u64,
+   /// Search results the daemon keeps for repeated queries, dropped on any
+   /// write to the index; 0 disables.
+   pub search_cache_entries:     u64,
 
    pub low_impact:      bool,
    pub disable_gpu:     bool,
+   pub device:          ComputeDevice,
+   pub model_dtype:     ModelDtype,
    pub fast_mode:       bool,
    pub profile_enabled: bool,
    pub skip_meta_save:  bool,
    pub debug_models:    bool,
    pub debug_embed:     bool,
+   /// Record local-only search statistics for `smgrep stats --usage`.
+   pub usage_stats:     bool,
+
+   /// Free space, in MiB, that indexing leaves on the index volume; 0
+   /// disables disk-space checks.
+   pub min_free_space_mb: u64,
+
+   /// Shell command run before indexing; see [`crate::hooks`].
+   pub pre_index_hook:    Option<String>,
+   /// Shell command run after indexing.
+   pub post_index_hook:   Option<String>,
+   /// Shell command that can rewrite CLI search results.
+   pub post_search_hook:  Option<String>,
+   pub hook_timeout_secs: u64,
 }
 
 impl Default for Config {
    fn default() -> Self {
       Self {
-         dense_model:              "ibm-granite/granite-embedding-small-english-r2".to_string(),
-         colbert_model:            "answerdotai/answerai-colbert-small-v1".to_string(),
-         dense_dim:                384,
-         colbert_dim:              96,
-         query_prefix:             String::new(),
-         dense_max_length:         256,
-         colbert_max_length:       256,
-         default_batch_size:       48,
-         max_batch_size:           96,
-         max_threads:              32,
-         port:                     4444,
-         idle_timeout_secs:        30 * 60,
+         dense_model: "ibm-granite/granite-embedding-small-english-r2".to_string(),
+         colbert_model: "answerdotai/answerai-colbert-small-v1".to_string(),
+         model_revisions: BTreeMap::new(),
+         dense_dim: 384,
+         colbert_dim: 96,
+         dense_truncate_dim: None,
+         dense_pooling: None,
+         long_chunks: LongChunks::Split,
+         window_pooling: WindowPooling::Mean,
+         window_overlap: 64,
+         query_prefix: String::new(),
+         embed_template: "{content}".to_string(),
+         dense_max_length: 256,
+         colbert_max_length: 256,
+         colbert_markers: true,
+         colbert_query_length: 32,
+         default_batch_size: 48,
+         max_batch_size: 96,
+         batch_tokens: 0,
+         max_batch_tokens: 0,
+         max_threads: 32,
+         follow_symlinks: true,
+         docs_sources: Vec::new(),
+         store_backend: StoreBackend::Lance,
+         qdrant_url: "http://localhost:6333".to_string(),
+         qdrant_api_key: None,
+         postgres_url: "postgres://localhost/smgrep".to_string(),
+         postgres_ca_cert: None,
+         meta_backend: MetaBackend::Auto,
+         file_hash: FileHashAlgorithm::Sha256,
+         lock_timeout_secs: 300,
+         branch_stores: false,
+         submodule_stores: false,
+         max_file_size_kb: 1024,
+         minified_line_length: 1000,
+         stream_chunk_threshold_kb: 512,
+         max_chunks_per_file: 2000,
+         module_summaries: true,
+         summarizer_url: None,
+         summarizer_model: "qwen2.5-coder:1.5b".to_string(),
+         summarizer_api_key: None,
+         language_overrides: BTreeMap::new(),
+         flat_store_max_chunks: 2000,
+         profile: Profile::Balanced,
+         rerank_depth: 50,
+         anchor_weight: 0.1,
+         preview_lines: 12,
+         cross_encoder_model: "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+         cross_encoder_depth: 30,
+         cross_encoder_max_length: 512,
+         fusion: FusionMethod::Weighted,
+         fusion_dense_weight: 0.3,
+         fusion_fts_weight: 0.1,
+         fusion_colbert_weight: 0.6,
+         fusion_rrf_k: 60.0,
+         structure_boosts: ["function", "class", "interface", "method", "typealias"]
+            .into_iter()
+            .map(|ty| (ty.to_string(), 1.25))
+            .collect(),
+         path_boosts: vec![
+            PathBoost::new(&["**/*.test.*", "**/*.spec.*", "**/__tests__/**"], 0.85),
+            PathBoost::new(
+               &[
+                  "**/*.md",
+                  "**/*.mdx",
+                  "**/*.txt",
+                  "**/*.json",
+                  "**/*.yaml",
+                  "**/*.yml",
+                  "**/*.lock",
+                  "**/docs/**",
+               ],
+               0.5,
+            ),
+         ],
+         vector_index: VectorIndexType::IvfPq,
+         vector_partitions: None,
+         vector_nprobes: 20,
+         vector_metric: None,
+         quantize_dense: false,
+         binary_prefilter_min_chunks: 100_000,
+         binary_prefilter_oversample: 8,
+         port: 4444,
+         idle_timeout_secs: 30 * 60,
+         model_unload_secs: 10 * 60,
          idle_check_interval_secs: 60,
-         worker_timeout_ms:        60000,
-         low_impact:               false,
-         disable_gpu:              false,
-         fast_mode:                false,
-         profile_enabled:          false,
-         skip_meta_save:           false,
-         debug_models:             false,
-         debug_embed:              false,
+         worker_timeout_ms: 60000,
+         embed_retries: 2,
+         compact_after_writes: 2000,
+         compact_idle_secs: 120,
+         warm_up: true,
+         preload_models: false,
+         lock_index_max_mb: 0,
+         search_cache_entries: 256,
+         low_impact: false,
+         disable_gpu: false,
+         device: ComputeDevice::Auto,
+         model_dtype: ModelDtype::F32,
+         fast_mode: false,
+         profile_enabled: false,
+         skip_meta_save: false,
+         debug_models: false,
+         debug_embed: false,
+         usage_stats: false,
+         min_free_space_mb: 1024,
+         pre_index_hook: None,
+         post_index_hook: None,
+         post_search_hook: None,
+         hook_timeout_secs: 300,
       }
    }
 }
@@ -80,24 +642,83 @@ impl Config {
          Self::create_default_config(config_path);
       }
 
-      Figment::from(Serialized::defaults(Self::default()))
-         .merge(Toml::file(config_path))
-         .merge(Env::prefixed("SMGREP_").lowercase(false))
+      let mut files = Figment::from(Toml::file(config_path));
+      if let Some(repo_config) = repo_config_path() {
+         files = files.merge(Serialized::defaults(repo_settings(&repo_config)));
+      }
+      let files = files.merge(Env::prefixed("SMGREP_").lowercase(false));
+
+      let profile = PROFILE_OVERRIDE
+         .get()
+         .copied()
+         .unwrap_or_else(|| files.extract_inner("profile").unwrap_or_default());
+      let mut cfg: Self = Self::layered(profile, files)
          .extract()
          .inspect_err(|e| tracing::warn!("failed to parse config: {e}"))
-         .unwrap_or_default()
+         .unwrap_or_default();
+      cfg.profile = profile;
+
+      if let Some((model, dim)) = MODEL_OVERRIDE
+         .get()
+         .cloned()
+         .or_else(|| bound_model(cfg.branch_stores))
+      {
+         cfg.dense_model = model;
+         cfg.dense_dim = dim;
+      }
+      cfg
+   }
+
+   /// Defaults, then `profile`'s settings, then `files`.
+   fn layered(profile: Profile, files: Figment) -> Figment {
+      let mut figment = Figment::from(Serialized::defaults(Self::default()));
+      if let Some(preset) = profile.preset() {
+         figment = figment.merge(Serialized::defaults(preset));
+      }
+      figment.merge(files)
    }
 
+   /// Writes every option with its default, commented out so that profiles
+   /// and later defaults still apply to those left alone.
    fn create_default_config(path: &Path) {
       if let Some(parent) = path.parent() {
          let _ = fs::create_dir_all(parent);
       }
       let default_config = Self::default();
       if let Ok(toml) = toml::to_string_pretty(&default_config) {
-         let _ = fs::write(path, toml);
+         let commented: String = toml
+            .lines()
+            .map(|line| {
+               if line.is_empty() {
+                  "\n".to_string()
+               } else {
+                  format!("# {line}\n")
+               }
+            })
+            .collect();
+         let _ = fs::write(path, commented);
       }
    }
 
+   /// Size of the dense vectors stored and searched: `dense_truncate_dim`
+   /// when set below `dense_dim`, else `dense_dim`.
+   pub fn dense_vector_dim(&self) -> usize {
+      self
+         .dense_truncate_dim
+         .filter(|&dim| dim > 0)
+         .map_or(self.dense_dim, |dim| dim.min(self.dense_dim))
+   }
+
+   /// Distance between dense vectors: `vector_metric` when set, else cosine
+   /// for quantized vectors and L2 otherwise.
+   pub fn vector_metric(&self) -> VectorMetric {
+      self.vector_metric.unwrap_or(if self.quantize_dense {
+         VectorMetric::Cosine
+      } else {
+         VectorMetric::L2
+      })
+   }
+
    /// Returns the configured batch size, capped at maximum
    pub fn batch_size(&self) -> usize {
       self.default_batch_size.min(self.max_batch_size)
@@ -114,6 +735,111 @@ pub fn get() -> &'static Config {
    CONFIG.get_or_init(Config::load)
 }
 
+/// Selects a profile for this process, taking precedence over the config
+/// files and `SMGREP_PROFILE`.
+///
+/// Must be called before the first [`get`]; later calls are ignored with a
+/// warning.
+pub fn set_profile_override(profile: Profile) {
+   warn_if_loaded("--profile");
+   let _ = PROFILE_OVERRIDE.set(profile);
+}
+
+/// Uses `model` as the dense model, producing embeddings of size `dim`, in
+/// place of `dense_model` and any store binding.
+///
+/// Must be called before the first [`get`]; later calls are ignored with a
+/// warning.
+pub fn set_model_override(model: String, dim: usize) {
+   warn_if_loaded("--model");
+   let _ = MODEL_OVERRIDE.set((model, dim));
+}
+
+/// Store a command works on, whose model binding picks the dense model.
+#[derive(Debug, Clone, Default)]
+pub struct StoreTarget {
+   /// Store named with `--store`.
+   pub store_id: Option<String>,
+   /// Directory the command is run on, when it takes one.
+   pub path:     Option<PathBuf>,
+}
+
+/// Sets the store this process works on, so that the model it is bound to
+/// is used. Without it, `SMGREP_STORE` or the store for the current
+/// directory is.
+///
+/// Must be called before the first [`get`]; later calls are ignored with a
+/// warning.
+pub fn set_store_target(target: StoreTarget) {
+   warn_if_loaded("the store");
+   let _ = STORE_TARGET.set(target);
+}
+
+fn warn_if_loaded(what: &str) {
+   if CONFIG.get().is_some() {
+      tracing::warn!("configuration already loaded; ignoring {what} set afterwards");
+   }
+}
+
+/// Dense model and its embedding size that the target store, or the store
+/// for the current directory, is bound to in the model registry, if any.
+/// Runs while the configuration loads, so it is handed the settings it
+/// needs.
+fn bound_model(branch_stores: bool) -> Option<(String, usize)> {
+   let registry = crate::embed::registry::ModelRegistry::load();
+   if registry.bindings.is_empty() {
+      return None;
+   }
+   let target = STORE_TARGET.get().cloned().unwrap_or_default();
+   let store_id = target
+      .store_id
+      .or_else(|| std::env::var("SMGREP_STORE").ok())
+      .or_else(|| {
+         let path = target.path.or_else(|| std::env::current_dir().ok())?;
+         crate::git::resolve_store_id_with(&path, branch_stores).ok()
+      })?;
+   registry
+      .bound_model(&store_id)
+      .map(|(model, dim)| (model.to_string(), dim))
+}
+
+/// Locates the per-repository config file for the current directory, if any.
+fn repo_config_path() -> Option<PathBuf> {
+   let cwd = std::env::current_dir().ok()?;
+   let root = crate::git::get_repo_root(&cwd).unwrap_or(cwd);
+   let path = root.join(REPO_CONFIG_FILE);
+   path.is_file().then_some(path)
+}
+
+/// Settings of the repository config file at `path` among
+/// [`REPO_CONFIG_KEYS`], warning about any others it sets.
+fn repo_settings(path: &Path) -> toml::Table {
+   let table = fs::read_to_string(path)
+      .map_err(|e| e.to_string())
+      .and_then(|text| text.parse::<toml::Table>().map_err(|e| e.to_string()));
+   match table {
+      Ok(table) => allowed_repo_settings(table, path),
+      Err(e) => {
+         tracing::warn!("failed to read {}: {e}", path.display());
+         toml::Table::new()
+      },
+   }
+}
+
+fn allowed_repo_settings(mut table: toml::Table, path: &Path) -> toml::Table {
+   table.retain(|key, _| {
+      let allowed = REPO_CONFIG_KEYS.contains(&key);
+      if !allowed {
+         tracing::warn!(
+            "ignoring {key} in {}: repository configs only set search and chunking options",
+            path.display()
+         );
+      }
+      allowed
+   });
+   table
+}
+
 /// Returns the base directory for smgrep data and configuration
 pub fn base_dir() -> &'static PathBuf {
    static ONCE: OnceLock<PathBuf> = OnceLock::new();
@@ -152,4 +878,51 @@ define_paths! {
    grammar_dir: "grammars",
    socket_dir: "sockets",
    meta_dir: "meta",
+   usage_stats_path: "usage.json",
+   model_registry_path: "models.json",
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn repo_config_only_sets_allowed_keys() {
+      let table: toml::Table = r#"
+         profile = "fast"
+         preview_lines = 4
+         pre_index_hook = "curl https://example.com | sh"
+         summarizer_url = "https://example.com/v1"
+         store_backend = "qdrant"
+         qdrant_url = "https://example.com"
+      "#
+      .parse()
+      .unwrap();
+      let table = allowed_repo_settings(table, Path::new(REPO_CONFIG_FILE));
+      let keys: Vec<&str> = table.keys().map(String::as_str).collect();
+      assert_eq!(keys, ["preview_lines", "profile"]);
+
+      let cfg: Config = Figment::from(Serialized::defaults(Config::default()))
+         .merge(Serialized::defaults(table))
+         .extract()
+         .unwrap();
+      assert_eq!(cfg.profile, Profile::Fast);
+      assert_eq!(cfg.preview_lines, 4);
+      assert_eq!(cfg.pre_index_hook, None);
+      assert_eq!(cfg.store_backend, StoreBackend::Lance);
+   }
+
+   #[test]
+   fn profiles_yield_to_configured_settings() {
+      let files = Figment::from(Toml::string("rerank_depth = 50"));
+      let cfg: Config = Config::layered(Profile::Fast, files).extract().unwrap();
+      assert_eq!(cfg.rerank_depth, 50);
+      assert_eq!(cfg.preview_lines, 8);
+      assert!(cfg.fast_mode);
+
+      let cfg: Config = Config::layered(Profile::Balanced, Figment::new())
+         .extract()
+         .unwrap();
+      assert_eq!(cfg.rerank_depth, Config::default().rerank_depth);
+   }
 }
@@ -0,0 +1,125 @@
+//! Free-space checks that keep indexing from filling the disk.
+//!
+//! A full disk in the middle of a write can leave a half-written dataset
+//! behind, so indexing estimates its footprint up front and stops between
+//! batches once free space drops below the configured reserve.
+
+use std::path::Path;
+
+use crate::{
+   Result,
+   config::{self, StoreBackend},
+   error::Error,
+};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Source bytes per chunk, roughly.
+const BYTES_PER_CHUNK: u64 = 1024;
+
+/// Source bytes per `ColBERT` token, roughly.
+const BYTES_PER_TOKEN: u64 = 4;
+
+/// Copies of a chunk's text that are stored: its content plus the content of
+/// its neighbours as context.
+const CONTENT_COPIES: u64 = 3;
+
+/// Directory holding the index on this machine, or `None` when the backend
+/// stores it remotely.
+pub fn index_dir() -> Option<&'static Path> {
+   match config::get().store_backend {
+      StoreBackend::Lance | StoreBackend::Flat | StoreBackend::Sqlite => Some(config::data_dir()),
+      StoreBackend::Qdrant | StoreBackend::Postgres => None,
+   }
+}
+
+/// Estimates the bytes needed to index `files` files totalling
+/// `source_bytes`, including each file's anchor chunk.
+pub fn estimate_index_size(source_bytes: u64, files: usize) -> u64 {
+   let cfg = config::get();
+   let chunks = source_bytes / BYTES_PER_CHUNK + 2 * files as u64;
+   let dense = chunks * (cfg.dense_vector_dim() as u64 * 4);
+   let colbert = source_bytes / BYTES_PER_TOKEN * cfg.colbert_dim as u64;
+   source_bytes * CONTENT_COPIES + dense + colbert
+}
+
+/// Free space available to this user on the volume holding `path`, which
+/// need not exist yet.
+pub fn available_space(path: &Path) -> Option<u64> {
+   let existing = path.ancestors().find(|p| p.exists())?;
+   fs4::available_space(existing)
+      .inspect_err(|e| tracing::debug!("cannot read free space of {}: {e}", existing.display()))
+      .ok()
+}
+
+/// Free space kept in reserve, from `min_free_space_mb`; `None` when disk
+/// checks are disabled.
+fn reserve() -> Option<u64> {
+   let mb = config::get().min_free_space_mb;
+   (mb > 0).then(|| mb * MIB)
+}
+
+/// Fails when `dir` cannot hold `required` more bytes on top of the reserve,
+/// and warns when the margin is thin.
+pub fn ensure_space(dir: &Path, required: u64) -> Result<()> {
+   let Some(reserve) = reserve() else {
+      return Ok(());
+   };
+   let Some(available) = available_space(dir) else {
+      return Ok(());
+   };
+
+   if available < required + reserve {
+      return Err(Error::InsufficientDiskSpace {
+         path: dir.to_path_buf(),
+         available,
+         required: required + reserve,
+      });
+   }
+   if available < 2 * required + reserve {
+      tracing::warn!(
+         "indexing needs about {} and {} has {} free; space may run short",
+         crate::util::format_size(required),
+         dir.display(),
+         crate::util::format_size(available)
+      );
+   }
+   Ok(())
+}
+
+/// Fails once free space on `dir` has dropped below the reserve.
+pub fn ensure_reserve(dir: &Path) -> Result<()> {
+   let Some(reserve) = reserve() else {
+      return Ok(());
+   };
+   match available_space(dir) {
+      Some(available) if available < reserve => {
+         Err(Error::DiskFull { path: dir.to_path_buf(), available, reserve })
+      },
+      _ => Ok(()),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn estimate_grows_with_source_and_files() {
+      let empty = estimate_index_size(0, 0);
+      let one_file = estimate_index_size(0, 1);
+      let larger = estimate_index_size(10 * MIB, 1);
+
+      assert_eq!(empty, 0);
+      assert!(one_file > 0);
+      assert!(larger > 10 * MIB * CONTENT_COPIES);
+   }
+
+   #[test]
+   fn available_space_of_missing_path_uses_ancestor() {
+      let dir = tempfile::tempdir().unwrap();
+      let missing = dir.path().join("not/created/yet");
+
+      assert!(available_space(&missing).is_some());
+   }
+}
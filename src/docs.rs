@@ -0,0 +1,153 @@
+//! External documentation sources.
+//!
+//! Pulls documentation that lives outside the indexed tree — a docs
+//! submodule, an ADR directory elsewhere on disk, an `OpenAPI` spec served
+//! over HTTP — into the store during sync. Chunks from these sources are
+//! tagged [`ChunkType::Doc`](crate::types::ChunkType::Doc) so they are
+//! searched alongside the repository's own docs.
+
+use std::{
+   path::{Path, PathBuf},
+   time::Duration,
+};
+
+use crate::file::FileSystem;
+
+/// Timeout for fetching a remote source.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest document accepted from any source.
+const MAX_DOC_SIZE: usize = 4 * 1024 * 1024;
+
+/// A configured documentation source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocSource {
+   /// File or directory on the local file system.
+   Local(PathBuf),
+   /// Document fetched over http(s).
+   Remote(String),
+}
+
+impl DocSource {
+   /// Parses a `docs_sources` entry, resolving relative paths against `root`.
+   pub fn parse(entry: &str, root: &Path) -> Self {
+      if entry.starts_with("http://") || entry.starts_with("https://") {
+         Self::Remote(entry.to_string())
+      } else {
+         Self::Local(root.join(entry))
+      }
+   }
+}
+
+/// A document gathered from a [`DocSource`].
+#[derive(Debug, Clone)]
+pub struct ExternalDoc {
+   /// Path the document is stored under; remote documents use their URL.
+   pub path:    PathBuf,
+   /// Document contents, or `None` if it could not be read this time. Rows
+   /// stored for unavailable documents are kept rather than deleted.
+   pub content: Option<Vec<u8>>,
+}
+
+/// Gathers the documents for every entry in `entries`.
+///
+/// Directories are walked with `file_system`, so they honor the same ignore
+/// rules as the repository. Failures are logged and never abort the sync.
+pub async fn collect<F: FileSystem + Sync + ?Sized>(
+   file_system: &F,
+   root: &Path,
+   entries: &[String],
+) -> Vec<ExternalDoc> {
+   let mut docs = Vec::new();
+   let client = reqwest::Client::builder()
+      .timeout(FETCH_TIMEOUT)
+      .build()
+      .unwrap_or_default();
+
+   for entry in entries {
+      let source = match DocSource::parse(entry, root) {
+         DocSource::Local(path) => DocSource::Local(path.canonicalize().unwrap_or(path)),
+         remote @ DocSource::Remote(_) => remote,
+      };
+      match source {
+         DocSource::Local(path) if path.is_dir() => {
+            let files: Vec<PathBuf> = match file_system.get_files(&path) {
+               Ok(files) => files.collect(),
+               Err(e) => {
+                  tracing::warn!("failed to list docs in {}: {e}", path.display());
+                  continue;
+               },
+            };
+            for file in files {
+               let content = read_local(&file).await;
+               docs.push(ExternalDoc { path: file, content });
+            }
+         },
+         DocSource::Local(path) => {
+            let content = read_local(&path).await;
+            docs.push(ExternalDoc { path, content });
+         },
+         DocSource::Remote(url) => {
+            let content = fetch(&client, &url).await;
+            docs.push(ExternalDoc { path: PathBuf::from(url), content });
+         },
+      }
+   }
+
+   docs.sort_by(|a, b| a.path.cmp(&b.path));
+   docs.dedup_by(|a, b| a.path == b.path);
+   docs
+}
+
+async fn read_local(path: &Path) -> Option<Vec<u8>> {
+   match tokio::fs::read(path).await {
+      Ok(content) if content.len() <= MAX_DOC_SIZE => Some(content),
+      Ok(_) => {
+         tracing::warn!("skipping oversized doc {}", path.display());
+         None
+      },
+      Err(e) => {
+         tracing::warn!("failed to read doc {}: {e}", path.display());
+         None
+      },
+   }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Option<Vec<u8>> {
+   let body = async {
+      client
+         .get(url)
+         .send()
+         .await?
+         .error_for_status()?
+         .bytes()
+         .await
+   };
+   match body.await {
+      Ok(body) if body.len() <= MAX_DOC_SIZE => Some(body.to_vec()),
+      Ok(_) => {
+         tracing::warn!("skipping oversized doc {url}");
+         None
+      },
+      Err(e) => {
+         tracing::warn!("failed to fetch doc {url}: {e}");
+         None
+      },
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parses_sources() {
+      let root = Path::new("/repo");
+      assert_eq!(
+         DocSource::parse("https://example.com/openapi.yaml", root),
+         DocSource::Remote("https://example.com/openapi.yaml".to_string())
+      );
+      assert_eq!(DocSource::parse("../adr", root), DocSource::Local(PathBuf::from("/repo/../adr")));
+      assert_eq!(DocSource::parse("/srv/docs", root), DocSource::Local(PathBuf::from("/srv/docs")));
+   }
+}
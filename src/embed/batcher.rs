@@ -0,0 +1,139 @@
+//! Adaptive sizing of embedding batches.
+//!
+//! Batches are sized in tokens rather than texts, since a forward pass costs
+//! memory in proportion to the texts times their padded length. The budget
+//! starts from `batch_tokens`, or from the memory available when that is 0,
+//! halves whenever a batch runs out of memory, and grows back by a quarter
+//! after a run of batches that fit, up to `max_batch_tokens`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{config, util};
+
+/// Smallest budget in tokens.
+const MIN_TOKENS: usize = 512;
+
+/// Batches that must fit in a row before the budget grows.
+const GROW_AFTER: usize = 8;
+
+/// Rough memory a token takes during a forward pass of a small encoder,
+/// counting activations and attention scores.
+const BYTES_PER_TOKEN: u64 = 64 * 1024;
+
+/// The CPU budget may use a quarter of the available memory.
+const MEMORY_SHARE: u64 = 4;
+
+/// Multiple of `max_batch_size` full-length texts a GPU budget may grow to.
+const GPU_GROWTH: usize = 8;
+
+/// Token budget for the embedding batches of one embedder.
+#[derive(Debug)]
+pub struct TokenBatcher {
+   budget: AtomicUsize,
+   max:    usize,
+   streak: AtomicUsize,
+}
+
+impl TokenBatcher {
+   pub fn new(initial: usize, max: usize) -> Self {
+      let max = max.max(MIN_TOKENS);
+      Self {
+         budget: AtomicUsize::new(initial.clamp(MIN_TOKENS, max)),
+         max,
+         streak: AtomicUsize::new(0),
+      }
+   }
+
+   /// Budget from `batch_tokens` and `max_batch_tokens`. When they are 0 the
+   /// budget starts at `default_batch_size` full-length texts and may grow to
+   /// [`GPU_GROWTH`] times `max_batch_size` of them; `on_cpu` caps both by
+   /// the memory available instead, since running out of it aborts the
+   /// process rather than failing the batch.
+   pub fn from_config(on_cpu: bool) -> Self {
+      let cfg = config::get();
+      let seq_len = cfg.dense_max_length.max(cfg.colbert_max_length);
+      let memory_cap = on_cpu.then(util::available_memory).flatten().map(|bytes| {
+         usize::try_from(bytes / MEMORY_SHARE / BYTES_PER_TOKEN).unwrap_or(usize::MAX)
+      });
+
+      let max = if cfg.max_batch_tokens > 0 {
+         cfg.max_batch_tokens
+      } else {
+         memory_cap.unwrap_or(cfg.max_batch_size * seq_len * GPU_GROWTH)
+      };
+      let initial = if cfg.batch_tokens > 0 {
+         cfg.batch_tokens
+      } else {
+         (cfg.batch_size() * seq_len).min(max)
+      };
+      Self::new(initial, max)
+   }
+
+   /// Current budget in tokens.
+   pub fn budget(&self) -> usize {
+      self.budget.load(Ordering::Relaxed)
+   }
+
+   /// Texts to put in a batch whose texts are padded to `seq_len` tokens.
+   pub fn batch_len(&self, seq_len: usize) -> usize {
+      (self.budget() / seq_len.max(1)).max(1)
+   }
+
+   /// Notes a batch that fit, growing the budget after [`GROW_AFTER`] in a
+   /// row.
+   pub fn record_success(&self) {
+      if self.streak.fetch_add(1, Ordering::Relaxed) + 1 < GROW_AFTER {
+         return;
+      }
+      self.streak.store(0, Ordering::Relaxed);
+      let budget = self.budget();
+      let grown = (budget + budget / 4).min(self.max);
+      if grown > budget {
+         self.budget.store(grown, Ordering::Relaxed);
+         tracing::debug!("embedding batches fit, raising the budget to {grown} tokens");
+      }
+   }
+
+   /// Notes a batch that ran out of memory, halving the budget. Returns
+   /// whether the batch should be retried, which it should not once a
+   /// single text is over budget.
+   pub fn record_oom(&self, batch_len: usize) -> bool {
+      self.streak.store(0, Ordering::Relaxed);
+      if batch_len <= 1 {
+         return false;
+      }
+      let budget = self.budget();
+      let halved = (budget / 2).max(MIN_TOKENS);
+      self.budget.store(halved, Ordering::Relaxed);
+      tracing::warn!(
+         "out of memory, reducing the embedding batch budget: {budget} -> {halved} tokens"
+      );
+      true
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn shrinks_on_oom_and_grows_back_to_the_cap() {
+      let batcher = TokenBatcher::new(8192, 10_000);
+      assert_eq!(batcher.batch_len(256), 32);
+
+      assert!(batcher.record_oom(32));
+      assert_eq!(batcher.budget(), 4096);
+      assert!(!batcher.record_oom(1));
+      assert_eq!(batcher.budget(), 4096);
+
+      for _ in 0..GROW_AFTER {
+         batcher.record_success();
+      }
+      assert_eq!(batcher.budget(), 5120);
+      for _ in 0..GROW_AFTER * 10 {
+         batcher.record_success();
+      }
+      assert_eq!(batcher.budget(), 10_000);
+      assert_eq!(batcher.batch_len(20_000), 1);
+   }
+}
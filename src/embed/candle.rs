@@ -5,31 +5,98 @@
 
 use std::{
    fmt, fs, io,
-   path::PathBuf,
-   sync::{
-      OnceLock,
-      atomic::{AtomicUsize, Ordering},
-   },
+   path::{Path, PathBuf},
+   sync::Arc,
 };
 
-use candle_core::{DType, Device, Module, Tensor};
+use candle_core::{DType, Device, DeviceLocation, Module, Tensor};
 use candle_nn::{Linear, VarBuilder};
 use candle_transformers::models::{
    bert::{BertModel, Config as BertConfig},
    modernbert::{Config as ModernBertConfig, ModernBert},
 };
-use hf_hub::{Repo, RepoType, api::tokio::Api};
+use hf_hub::{Cache, Repo, RepoType, api::tokio::Api};
 use ndarray::Array2;
-use tokenizers::Tokenizer;
+use parking_lot::RwLock;
+use tokenizers::{Tokenizer, TruncationParams};
 use tokio::sync::Mutex;
 
 use crate::{
-   Str, config,
-   embed::{Embedder, HybridEmbedding, QueryEmbedding},
+   Str,
+   config::{self, ComputeDevice, LongChunks, ModelDtype, Pooling, WindowPooling},
+   embed::{
+      Embedder, EmbedderStatus, HybridEmbedding, QueryEmbedding,
+      batcher::TokenBatcher,
+      registry::{self, ModelKind, ModelRegistry},
+   },
    error::Result,
 };
 
-const MIN_BATCH_SIZE: usize = 1;
+/// Files fetched from the Hugging Face Hub for each model.
+pub(crate) const MODEL_FILES: [&str; 3] = ["config.json", "tokenizer.json", "model.safetensors"];
+
+/// Pooling config of sentence-transformers models, relative to the model.
+const POOLING_CONFIG: &str = "1_Pooling/config.json";
+
+/// Name of `device`, such as `cpu` or `cuda:0`.
+pub fn device_label(device: &Device) -> String {
+   match device.location() {
+      DeviceLocation::Cpu => "cpu".to_string(),
+      DeviceLocation::Cuda { gpu_id } => format!("cuda:{gpu_id}"),
+      DeviceLocation::Metal { gpu_id } => format!("metal:{gpu_id}"),
+   }
+}
+
+/// Combines the dense embeddings of a text's windows into one unit vector.
+fn pool_windows(windows: &[Vec<f32>], pooling: WindowPooling) -> Vec<f32> {
+   let mut pooled = windows[0].clone();
+   for window in &windows[1..] {
+      for (acc, &x) in pooled.iter_mut().zip(window) {
+         match pooling {
+            WindowPooling::Mean => *acc += x,
+            WindowPooling::Max => *acc = acc.max(x),
+         }
+      }
+   }
+   CandleEmbedder::normalize_l2(&mut pooled);
+   pooled
+}
+
+/// Keeps the leading `dense_truncate_dim` dimensions of a dense embedding,
+/// normalized again. Models trained with Matryoshka representation learning
+/// front-load their embeddings so that such a prefix still ranks well.
+fn truncate_dense(dense: &mut Vec<f32>) {
+   let dim = config::get().dense_vector_dim();
+   if dense.len() > dim {
+      dense.truncate(dim);
+      CandleEmbedder::normalize_l2(dense);
+   }
+}
+
+/// Adds a loaded model to the model registry.
+fn record_model(model_id: &str, kind: ModelKind, dim: usize, pooling: Option<Pooling>) {
+   if let Err(e) = ModelRegistry::record(model_id, kind, dim, pooling) {
+      tracing::debug!("failed to record {model_id} in the model registry: {e}");
+   }
+}
+
+/// Whether `HF_HUB_OFFLINE` forbids contacting the Hugging Face Hub.
+pub(crate) fn hub_offline() -> bool {
+   std::env::var("HF_HUB_OFFLINE").is_ok_and(|value| {
+      matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+   })
+}
+
+/// The Hub repository of `model_id`, at the revision `model_revisions` pins
+/// it to, if any.
+pub(crate) fn hub_repo(model_id: &str) -> Repo {
+   match config::get().model_revisions.get(model_id) {
+      Some(revision) => {
+         Repo::with_revision(model_id.to_string(), RepoType::Model, revision.clone())
+      },
+      None => Repo::model(model_id.to_string()),
+   }
+}
 
 #[derive(Debug)]
 pub struct Models(DenseModelState, ColbertModelState);
@@ -37,19 +104,21 @@ pub struct Models(DenseModelState, ColbertModelState);
 /// Candle-based embedder with GPU support and adaptive batching
 ///
 /// Manages both dense and `ColBERT` models with lazy initialization
-/// and automatic batch size reduction on OOM errors.
+/// and automatic batch size reduction on OOM errors. The models can be
+/// unloaded and are loaded again by the next call that needs them.
 #[derive(Debug)]
 pub struct CandleEmbedder {
-   models:              OnceLock<Models>,
-   init_lock:           Mutex<()>,
-   device:              Device,
-   adaptive_batch_size: AtomicUsize,
+   models:    RwLock<Option<Arc<Models>>>,
+   init_lock: Mutex<()>,
+   device:    Device,
+   batcher:   TokenBatcher,
 }
 
 /// Model backend trait supporting BERT and `ModernBERT` architectures
 trait DenseModelBackend: Send + Sync + fmt::Debug {
    fn forward(&self, input_ids: &Tensor, attention_mask: &Tensor) -> candle_core::Result<Tensor>;
-   fn uses_mean_pooling(&self) -> bool;
+   /// Pooling the architecture is usually trained with.
+   fn default_pooling(&self) -> Pooling;
 }
 
 struct BertBackend {
@@ -73,8 +142,8 @@ impl DenseModelBackend for BertBackend {
          .forward(input_ids, &token_type_ids, Some(attention_mask))
    }
 
-   fn uses_mean_pooling(&self) -> bool {
-      false
+   fn default_pooling(&self) -> Pooling {
+      Pooling::Cls
    }
 }
 
@@ -96,8 +165,8 @@ impl DenseModelBackend for ModernBertBackend {
       self.model.forward(input_ids, attention_mask)
    }
 
-   fn uses_mean_pooling(&self) -> bool {
-      true
+   fn default_pooling(&self) -> Pooling {
+      Pooling::Mean
    }
 }
 
@@ -105,6 +174,10 @@ struct DenseModelState {
    name:      &'static str,
    model:     Box<dyn DenseModelBackend>,
    tokenizer: Tokenizer,
+   pooling:   Pooling,
+   /// Tokenizer cutting long texts into overlapping windows, with
+   /// `long_chunks = "windows"`.
+   windows:   Option<Tokenizer>,
 }
 
 impl fmt::Debug for DenseModelState {
@@ -113,6 +186,8 @@ impl fmt::Debug for DenseModelState {
          .field("name", &self.name)
          .field("model", &self.model)
          .field("tokenizer", &self.tokenizer)
+         .field("pooling", &self.pooling)
+         .field("windows", &self.windows.is_some())
          .finish()
    }
 }
@@ -120,8 +195,68 @@ impl fmt::Debug for DenseModelState {
 struct ColbertModelState {
    name:       &'static str,
    bert:       BertModel,
-   projection: Linear,
+   /// Linear head down to `colbert_dim`; `None` for checkpoints whose hidden
+   /// states already have that size.
+   projection: Option<Linear>,
    tokenizer:  Tokenizer,
+   markers:    Option<ColbertMarkers>,
+}
+
+impl ColbertModelState {
+   fn project(&self, embeddings: Tensor) -> Result<Tensor> {
+      match &self.projection {
+         Some(projection) => Ok(projection
+            .forward(&embeddings)
+            .map_err(EmbeddingError::Projection)?),
+         None => Ok(embeddings),
+      }
+   }
+}
+
+/// Token IDs `ColBERT` marks its inputs with: a query (`[Q]`) or document
+/// (`[D]`) marker after `[CLS]`, and `[MASK]` padding that lets short queries
+/// expand into extra query embeddings.
+#[derive(Debug, Clone, Copy)]
+struct ColbertMarkers {
+   query:    u32,
+   document: u32,
+   mask:     u32,
+}
+
+impl ColbertMarkers {
+   /// Markers of a BERT-vocabulary checkpoint, which reuses `[unused0]` and
+   /// `[unused1]` as `[Q]` and `[D]`.
+   fn from_tokenizer(tokenizer: &Tokenizer) -> Option<Self> {
+      Some(Self {
+         query:    tokenizer.token_to_id("[unused0]")?,
+         document: tokenizer.token_to_id("[unused1]")?,
+         mask:     tokenizer.token_to_id("[MASK]")?,
+      })
+   }
+
+   /// Marks a tokenized document. Adds one token, which the tokenization
+   /// must leave room for.
+   fn document(self, tokens: (Vec<u32>, Vec<u32>)) -> (Vec<u32>, Vec<u32>) {
+      Self::insert(tokens, self.document)
+   }
+
+   /// Marks a tokenized query and pads it with unattended `[MASK]` tokens to
+   /// `min_len`.
+   fn query(self, tokens: (Vec<u32>, Vec<u32>), min_len: usize) -> (Vec<u32>, Vec<u32>) {
+      let (mut ids, mut mask) = Self::insert(tokens, self.query);
+      let pad = min_len.saturating_sub(ids.len());
+      ids.extend(std::iter::repeat_n(self.mask, pad));
+      mask.extend(std::iter::repeat_n(0, pad));
+      (ids, mask)
+   }
+
+   /// Inserts `marker` after `[CLS]`.
+   fn insert((mut ids, mut mask): (Vec<u32>, Vec<u32>), marker: u32) -> (Vec<u32>, Vec<u32>) {
+      let at = ids.len().min(1);
+      ids.insert(at, marker);
+      mask.insert(at, 1);
+      (ids, mask)
+   }
 }
 
 impl fmt::Debug for ColbertModelState {
@@ -131,6 +266,7 @@ impl fmt::Debug for ColbertModelState {
          .field("device", &self.bert.device)
          .field("projection", &self.projection)
          .field("tokenizer", &self.tokenizer)
+         .field("markers", &self.markers)
          .finish()
    }
 }
@@ -159,6 +295,9 @@ pub enum EmbeddingError {
    #[error("failed to load projection: {0}")]
    LoadProjection(#[source] candle_core::Error),
 
+   #[error("failed to load cross-encoder head: {0}")]
+   LoadClassifier(#[source] candle_core::Error),
+
    #[error("failed to create model cache: {0}")]
    CreateModelCache(#[from] io::Error),
 
@@ -174,6 +313,24 @@ pub enum EmbeddingError {
    #[error("invalid model path")]
    InvalidModelPath,
 
+   #[error(
+      "model {0} is not installed and HF_HUB_OFFLINE is set. Run 'smgrep setup --from-dir' to \
+       install it from a local copy."
+   )]
+   ModelNotInstalled(String),
+
+   #[error(
+      "model {model} produces {found}-dimensional embeddings, but dense_dim is {expected}. Set \
+       dense_dim to match or select the model with --model."
+   )]
+   DimensionMismatch { model: String, found: usize, expected: usize },
+
+   #[error(
+      "{model} has no ColBERT projection head and its {hidden}-dimensional hidden states do not \
+       match colbert_dim = {dim}. Set colbert_model to a ColBERT checkpoint."
+   )]
+   NotColbert { model: String, hidden: usize, dim: usize },
+
    #[error("dense model not loaded")]
    DenseModelNotLoaded,
 
@@ -221,6 +378,15 @@ pub enum EmbeddingError {
 
    #[error("work cancelled")]
    WorkCancelled,
+
+   #[error("embedding worker did not answer within {0} ms")]
+   WorkerTimeout(u64),
+
+   #[error("embedding worker panicked: {0}")]
+   WorkerPanicked(String),
+
+   #[error("embedder returned {got} embeddings for {expected} texts")]
+   CountMismatch { expected: usize, got: usize },
 }
 
 fn is_oom_error(err: &str) -> bool {
@@ -232,9 +398,15 @@ fn is_oom_error(err: &str) -> bool {
       || err.contains("alloc")
 }
 
-const fn optimal_dtype(_device: &Device) -> DType {
-   // BF16/F16 on some CUDA setups can yield NaNs; F32 is stable across devices.
-   DType::F32
+/// Precision to run models in on `device`: `model_dtype` on GPUs, F32 on
+/// the CPU, where half precision is slower.
+pub(crate) fn optimal_dtype(device: &Device) -> DType {
+   match config::get().model_dtype {
+      ModelDtype::F16 if !device.is_cpu() => DType::F16,
+      ModelDtype::Bf16 if !device.is_cpu() => DType::BF16,
+      // F32 is the default as BF16/F16 on some CUDA setups can yield NaNs
+      _ => DType::F32,
+   }
 }
 
 impl CandleEmbedder {
@@ -247,70 +419,66 @@ impl CandleEmbedder {
          Self::select_device()
       };
 
-      let initial_batch = cfg.batch_size();
-
       Ok(Self {
-         models: OnceLock::new(),
+         models: RwLock::new(None),
          init_lock: Mutex::new(()),
+         batcher: TokenBatcher::from_config(device.is_cpu()),
          device,
-         adaptive_batch_size: AtomicUsize::new(initial_batch),
       })
    }
 
-   fn select_device() -> Device {
-      #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-      {
-         if let Ok(device) = Device::new_metal(0) {
-            return device;
-         }
-      }
-      #[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
-      {
-         if let Ok(device) = Device::cuda_if_available(0) {
-            if device.is_cuda() {
-               return device;
-            }
+   /// Picks the device set by `device` (`SMGREP_DEVICE`), falling back to
+   /// the CPU when this build or machine lacks it. Builds for Apple Silicon
+   /// include Metal and builds for other targets CUDA; the `metal` and
+   /// `cuda` features add either elsewhere.
+   pub(crate) fn select_device() -> Device {
+      let choice = config::get().device;
+      let device = match choice {
+         ComputeDevice::Cpu => Ok(Device::Cpu),
+         ComputeDevice::Cuda => Device::new_cuda(0),
+         ComputeDevice::Metal => Device::new_metal(0),
+         ComputeDevice::Auto => Device::new_metal(0).or_else(|_| Device::new_cuda(0)),
+      };
+      device.unwrap_or_else(|e| {
+         if choice != ComputeDevice::Auto {
+            tracing::warn!("{} device unavailable, using the CPU: {e}", choice.as_str());
          }
-      }
-      Device::Cpu
-   }
-
-   /// Returns the current adaptive batch size
-   pub fn current_batch_size(&self) -> usize {
-      self.adaptive_batch_size.load(Ordering::Relaxed)
+         Device::Cpu
+      })
    }
 
-   fn reduce_batch_size(&self) -> usize {
-      let current = self.adaptive_batch_size.load(Ordering::Relaxed);
-      let new_size = (current / 2).max(MIN_BATCH_SIZE);
-      self.adaptive_batch_size.store(new_size, Ordering::Relaxed);
-      tracing::warn!("OOM detected, reducing batch size: {} -> {}", current, new_size);
-      new_size
+   /// Name of the device the models run on, such as `cpu` or `cuda:0`
+   pub fn device_name(&self) -> String {
+      device_label(&self.device)
    }
 
    #[inline(always)]
-   async fn models(&self) -> Result<&Models> {
-      if self.models.get().is_some() {
-         return Ok(self.models.get().unwrap());
+   async fn models(&self) -> Result<Arc<Models>> {
+      if let Some(models) = self.models.read().as_ref() {
+         return Ok(Arc::clone(models));
       }
       self.init_models_cold().await
    }
 
    #[cold]
-   async fn init_models_cold(&self) -> Result<&Models> {
+   async fn init_models_cold(&self) -> Result<Arc<Models>> {
       let _guard = self.init_lock.lock().await;
-      if self.models.get().is_some() {
-         return Ok(self.models.get().unwrap());
+      if let Some(models) = self.models.read().as_ref() {
+         return Ok(Arc::clone(models));
       }
 
       let dense = Self::load_dense(&self.device).await?;
       let colbert = Self::load_colbert(&self.device).await?;
 
-      self
-         .models
-         .set(Models(dense, colbert))
-         .expect("should be exclusive under self.init_lock");
-      Ok(self.models.get().unwrap())
+      let models = Arc::new(Models(dense, colbert));
+      *self.models.write() = Some(Arc::clone(&models));
+      Ok(models)
+   }
+
+   /// Drops the loaded models, returning whether any were loaded. Embeddings
+   /// already in progress keep their models until they finish.
+   pub fn unload(&self) -> bool {
+      self.models.write().take().is_some()
    }
 
    async fn load_dense(device: &Device) -> Result<DenseModelState> {
@@ -323,6 +491,19 @@ impl CandleEmbedder {
          tracing::info!("loading dense model from {:?} with {:?}", model_path, dtype);
       }
 
+      let dim = registry::hidden_size(&model_path.join("config.json"))?;
+      record_model(&cfg.dense_model, ModelKind::Dense, dim, None);
+      if dim != cfg.dense_dim {
+         return Err(
+            EmbeddingError::DimensionMismatch {
+               model:    cfg.dense_model.clone(),
+               found:    dim,
+               expected: cfg.dense_dim,
+            }
+            .into(),
+         );
+      }
+
       let tokenizer = Tokenizer::from_file(model_path.join("tokenizer.json"))
          .map_err(EmbeddingError::LoadTokenizer)?;
 
@@ -365,11 +546,44 @@ impl CandleEmbedder {
          Box::new(BertBackend { model, device: device.clone() })
       };
 
+      let registered = ModelRegistry::load()
+         .models
+         .get(&cfg.dense_model)
+         .and_then(|m| m.pooling);
+      let model_pooling = match registered {
+         Some(pooling) => pooling,
+         None => {
+            let pooling = Self::sentence_transformers_pooling(&cfg.dense_model, &model_path)
+               .await
+               .unwrap_or_else(|| model.default_pooling());
+            record_model(&cfg.dense_model, ModelKind::Dense, dim, Some(pooling));
+            pooling
+         },
+      };
+      let pooling = cfg.dense_pooling.unwrap_or(model_pooling);
+
       if cfg.debug_models {
-         tracing::info!("dense model loaded");
+         tracing::info!("dense model loaded with {} pooling", pooling.as_str());
       }
 
-      Ok(DenseModelState { name: cfg.dense_model.as_str(), model, tokenizer })
+      let windows = if cfg.long_chunks == LongChunks::Windows {
+         let mut windows = tokenizer.clone();
+         windows
+            .with_truncation(Some(TruncationParams {
+               max_length: cfg.dense_max_length,
+               // Must stay below the window's length without special tokens
+               stride: cfg
+                  .window_overlap
+                  .min(cfg.dense_max_length.saturating_sub(2) / 2),
+               ..Default::default()
+            }))
+            .map_err(EmbeddingError::LoadTokenizer)?;
+         Some(windows)
+      } else {
+         None
+      };
+
+      Ok(DenseModelState { name: cfg.dense_model.as_str(), model, tokenizer, pooling, windows })
    }
 
    async fn load_colbert(device: &Device) -> Result<ColbertModelState> {
@@ -397,34 +611,120 @@ impl CandleEmbedder {
       };
 
       let bert = BertModel::load(vb.clone(), &config).map_err(EmbeddingError::LoadColbertModel)?;
+      record_model(&cfg.colbert_model, ModelKind::Colbert, cfg.colbert_dim, None);
+
+      let projection = if vb.contains_tensor("linear.weight") {
+         Some(
+            candle_nn::linear_no_bias(config.hidden_size, cfg.colbert_dim, vb.pp("linear"))
+               .map_err(EmbeddingError::LoadProjection)?,
+         )
+      } else if config.hidden_size == cfg.colbert_dim {
+         tracing::warn!(
+            "{} has no ColBERT projection head; using its hidden states",
+            cfg.colbert_model
+         );
+         None
+      } else {
+         return Err(
+            EmbeddingError::NotColbert {
+               model:  cfg.colbert_model.clone(),
+               hidden: config.hidden_size,
+               dim:    cfg.colbert_dim,
+            }
+            .into(),
+         );
+      };
 
-      let projection =
-         candle_nn::linear_no_bias(config.hidden_size, cfg.colbert_dim, vb.pp("linear"))
-            .map_err(EmbeddingError::LoadProjection)?;
+      let markers = if cfg.colbert_markers {
+         let markers = ColbertMarkers::from_tokenizer(&tokenizer);
+         if markers.is_none() {
+            tracing::warn!(
+               "{} has no [Q]/[D] marker tokens; not marking inputs",
+               cfg.colbert_model
+            );
+         }
+         markers
+      } else {
+         None
+      };
 
       if cfg.debug_models {
          tracing::info!(
-            "colbert model loaded (hidden={}, proj={})",
+            "colbert model loaded (hidden={}, proj={}, markers={})",
             config.hidden_size,
-            cfg.colbert_dim
+            cfg.colbert_dim,
+            markers.is_some()
          );
       }
 
-      Ok(ColbertModelState { name: cfg.colbert_model.as_str(), bert, projection, tokenizer })
+      Ok(ColbertModelState {
+         name: cfg.colbert_model.as_str(),
+         bert,
+         projection,
+         tokenizer,
+         markers,
+      })
    }
 
-   async fn download_model(model_id: &str) -> Result<PathBuf> {
+   /// Pooling in the model's sentence-transformers config, fetched from the
+   /// Hub unless it is next to the model's files or the Hub is offline.
+   async fn sentence_transformers_pooling(model_id: &str, model_path: &Path) -> Option<Pooling> {
+      let local = model_path.join(POOLING_CONFIG);
+      let path = if local.is_file() {
+         local
+      } else if hub_offline() {
+         return None;
+      } else {
+         Api::new()
+            .ok()?
+            .repo(hub_repo(model_id))
+            .get(POOLING_CONFIG)
+            .await
+            .inspect_err(|e| tracing::debug!("no pooling config for {model_id}: {e}"))
+            .ok()?
+      };
+      registry::sentence_transformers_pooling(&fs::read_to_string(path).ok()?)
+   }
+
+   /// Directory `smgrep setup --from-dir` installed a model into, if every
+   /// file of the model is there.
+   pub(crate) fn installed_model_dir(model_id: &str) -> Option<PathBuf> {
+      let dir = config::model_dir().join(model_id.replace('/', "--"));
+      MODEL_FILES
+         .iter()
+         .all(|file| dir.join(file).is_file())
+         .then_some(dir)
+   }
+
+   /// Finds a model's files, in order: installed by `smgrep setup
+   /// --from-dir`, in the Hugging Face cache, or downloaded from the Hub.
+   /// With `HF_HUB_OFFLINE` set, the Hub is never contacted.
+   pub(crate) async fn download_model(model_id: &str) -> Result<PathBuf> {
+      if let Some(dir) = Self::installed_model_dir(model_id) {
+         return Ok(dir);
+      }
+
+      if hub_offline() {
+         let repo = Cache::default().repo(hub_repo(model_id));
+         let cached = MODEL_FILES
+            .iter()
+            .map(|file| repo.get(file))
+            .collect::<Option<Vec<_>>>()
+            .and_then(|paths| paths[0].parent().map(Path::to_path_buf));
+         return cached
+            .ok_or_else(|| EmbeddingError::ModelNotInstalled(model_id.to_string()).into());
+      }
+
       let cache_dir = config::model_dir();
       fs::create_dir_all(cache_dir).map_err(EmbeddingError::CreateModelCache)?;
 
       let api = Api::new().map_err(EmbeddingError::InitHfHub)?;
 
-      let repo = api.repo(Repo::new(model_id.to_string(), RepoType::Model));
+      let repo = api.repo(hub_repo(model_id));
 
-      let model_files = ["config.json", "tokenizer.json", "model.safetensors"];
       let mut paths = Vec::new();
 
-      for filename in &model_files {
+      for filename in &MODEL_FILES {
          let path = repo
             .get(filename)
             .await
@@ -458,13 +758,15 @@ impl CandleEmbedder {
    }
 
    async fn tokenize_dense(&self, text: &str) -> Result<(Vec<u32>, Vec<u32>)> {
-      let Models(dense, _) = self.models().await?;
+      let models = self.models().await?;
+      let Models(dense, _) = &*models;
       let max_len = config::get().dense_max_length;
       Self::tokenize_impl(&dense.tokenizer, text, max_len)
    }
 
    async fn tokenize_dense_batch(&self, texts: &[Str]) -> Result<Vec<(Vec<u32>, Vec<u32>)>> {
-      let Models(dense, _) = self.models().await?;
+      let models = self.models().await?;
+      let Models(dense, _) = &*models;
       let max_len = config::get().dense_max_length;
       texts
          .iter()
@@ -472,22 +774,44 @@ impl CandleEmbedder {
          .collect()
    }
 
-   async fn tokenize_colbert(&self, text: &str) -> Result<(Vec<u32>, Vec<u32>)> {
-      let Models(_, colbert) = self.models().await?;
-      let max_len = config::get().colbert_max_length;
-      Self::tokenize_impl(&colbert.tokenizer, text, max_len)
+   async fn tokenize_colbert_query(&self, text: &str) -> Result<(Vec<u32>, Vec<u32>)> {
+      let models = self.models().await?;
+      let Models(_, colbert) = &*models;
+      let cfg = config::get();
+      match colbert.markers {
+         Some(markers) => {
+            let tokens = Self::tokenize_impl(
+               &colbert.tokenizer,
+               text,
+               cfg.colbert_max_length.saturating_sub(1),
+            )?;
+            Ok(markers.query(tokens, cfg.colbert_query_length))
+         },
+         None => Self::tokenize_impl(&colbert.tokenizer, text, cfg.colbert_max_length),
+      }
    }
 
    async fn tokenize_colbert_batch(&self, texts: &[Str]) -> Result<Vec<(Vec<u32>, Vec<u32>)>> {
-      let Models(_, colbert) = self.models().await?;
+      let models = self.models().await?;
+      let Models(_, colbert) = &*models;
       let max_len = config::get().colbert_max_length;
       texts
          .iter()
-         .map(|text| Self::tokenize_impl(&colbert.tokenizer, text.as_str(), max_len))
+         .map(|text| match colbert.markers {
+            Some(markers) => {
+               let tokens = Self::tokenize_impl(
+                  &colbert.tokenizer,
+                  text.as_str(),
+                  max_len.saturating_sub(1),
+               )?;
+               Ok(markers.document(tokens))
+            },
+            None => Self::tokenize_impl(&colbert.tokenizer, text.as_str(), max_len),
+         })
          .collect()
    }
 
-   fn normalize_l2(embeddings: &mut [f32]) {
+   pub(crate) fn normalize_l2(embeddings: &mut [f32]) {
       let norm: f32 = embeddings.iter().map(|x| x * x).sum::<f32>().sqrt();
       if norm > 0.0 {
          for x in embeddings.iter_mut() {
@@ -504,7 +828,7 @@ impl CandleEmbedder {
       }
    }
 
-   fn quantize_embeddings(tokens: &Array2<f32>) -> (Vec<u8>, f64) {
+   pub(crate) fn quantize_embeddings(tokens: &Array2<f32>) -> (Vec<u8>, f64) {
       if tokens.is_empty() {
          return (Vec::new(), 1.0);
       }
@@ -557,6 +881,49 @@ impl CandleEmbedder {
       buckets
    }
 
+   /// Reduces token embeddings `[batch, seq_len, dim]` to one embedding per
+   /// sequence `[batch, dim]`. Sequences are padded on the right.
+   fn pool(embeddings: &Tensor, attention_mask: &Tensor, pooling: Pooling) -> Result<Tensor> {
+      let pooled = match pooling {
+         Pooling::Cls => embeddings
+            .narrow(1, 0, 1)
+            .and_then(|t| t.squeeze(1))
+            .map_err(EmbeddingError::ExtractCls)?,
+         Pooling::Mean => {
+            // Average over non-padding tokens
+            let mask = attention_mask
+               .to_dtype(embeddings.dtype())
+               .map_err(EmbeddingError::DtypeConversion)?
+               .unsqueeze(2)
+               .map_err(EmbeddingError::Unsqueeze)?;
+            let sum = embeddings
+               .broadcast_mul(&mask)
+               .and_then(|t| t.sum(1))
+               .map_err(EmbeddingError::ForwardPass)?;
+            let count = mask
+               .sum(1)
+               .and_then(|t| t.clamp(1.0, f64::MAX))
+               .map_err(EmbeddingError::ForwardPass)?;
+            sum.broadcast_div(&count)
+               .map_err(EmbeddingError::ForwardPass)?
+         },
+         Pooling::LastToken => {
+            let lengths: Vec<u32> = attention_mask
+               .sum(1)
+               .and_then(|t| t.to_vec1())
+               .map_err(EmbeddingError::ConvertToVec)?;
+            let last = lengths
+               .iter()
+               .enumerate()
+               .map(|(i, &len)| embeddings.get(i)?.get(len.saturating_sub(1) as usize))
+               .collect::<candle_core::Result<Vec<_>>>()
+               .map_err(EmbeddingError::GetBatch)?;
+            Tensor::stack(&last, 0).map_err(EmbeddingError::ForwardPass)?
+         },
+      };
+      Ok(pooled)
+   }
+
    async fn compute_dense_embedding(&self, text: &str) -> Result<Vec<f32>> {
       let (token_ids, attention_mask) = self.tokenize_dense(text).await?;
 
@@ -570,38 +937,17 @@ impl CandleEmbedder {
          .unsqueeze(0)
          .map_err(EmbeddingError::Unsqueeze)?;
 
-      let Models(dense, _) = self.models().await?;
+      let models = self.models().await?;
+      let Models(dense, _) = &*models;
 
       let embeddings = dense
          .model
          .forward(&token_ids_tensor, &attention_mask_tensor)
          .map_err(EmbeddingError::ForwardPass)?;
 
-      let pooled = if dense.model.uses_mean_pooling() {
-         // Mean pooling: average over non-padding tokens
-         let batch_emb = embeddings.get(0).map_err(EmbeddingError::GetBatch)?;
-         let mask_f32 = attention_mask_tensor
-            .get(0)
-            .map_err(EmbeddingError::GetBatch)?
-            .to_dtype(DType::F32)
-            .map_err(EmbeddingError::DtypeConversion)?
-            .unsqueeze(1)
-            .map_err(EmbeddingError::Unsqueeze)?;
-         let masked = batch_emb
-            .broadcast_mul(&mask_f32)
-            .map_err(EmbeddingError::ForwardPass)?;
-         let sum = masked.sum(0).map_err(EmbeddingError::ForwardPass)?;
-         let count = mask_f32.sum_all().map_err(EmbeddingError::ForwardPass)?;
-         sum.broadcast_div(&count)
-            .map_err(EmbeddingError::ForwardPass)?
-      } else {
-         // CLS pooling: take first token
-         embeddings
-            .get(0)
-            .map_err(EmbeddingError::GetBatch)?
-            .get(0)
-            .map_err(EmbeddingError::ExtractCls)?
-      };
+      let pooled = Self::pool(&embeddings, &attention_mask_tensor, dense.pooling)?
+         .get(0)
+         .map_err(EmbeddingError::GetBatch)?;
 
       let mut dense_vec: Vec<f32> = pooled
          .to_dtype(DType::F32)
@@ -611,6 +957,7 @@ impl CandleEmbedder {
          .to_vec1()
          .map_err(EmbeddingError::ConvertToVec)?;
 
+      Self::sanitize(&mut dense_vec);
       Self::normalize_l2(&mut dense_vec);
       Ok(dense_vec)
    }
@@ -652,7 +999,8 @@ impl CandleEmbedder {
          .reshape(&[batch_size, max_len])
          .map_err(EmbeddingError::Reshape)?;
 
-      let Models(dense, _) = self.models().await?;
+      let models = self.models().await?;
+      let Models(dense, _) = &*models;
 
       let embeddings = dense
          .model
@@ -660,34 +1008,7 @@ impl CandleEmbedder {
          .map_err(EmbeddingError::ForwardPass)?;
 
       let dim = config::get().dense_dim;
-      let pooled = if dense.model.uses_mean_pooling() {
-         // Mean pooling: average over non-padding tokens for each batch item
-         // embeddings: [batch, seq_len, dim]
-         // attention_mask: [batch, seq_len]
-         let mask_f32 = attention_mask_tensor
-            .to_dtype(DType::F32)
-            .map_err(EmbeddingError::DtypeConversion)?
-            .unsqueeze(2)
-            .map_err(EmbeddingError::Unsqueeze)?;
-         let masked = embeddings
-            .broadcast_mul(&mask_f32)
-            .map_err(EmbeddingError::ForwardPass)?;
-         let sum = masked.sum(1).map_err(EmbeddingError::ForwardPass)?;
-         let count = mask_f32
-            .sum(1)
-            .map_err(EmbeddingError::ForwardPass)?
-            .clamp(1.0, f64::MAX)
-            .map_err(EmbeddingError::ForwardPass)?;
-         sum.broadcast_div(&count)
-            .map_err(EmbeddingError::ForwardPass)?
-      } else {
-         // CLS pooling: take first token from each sequence
-         embeddings
-            .narrow(1, 0, 1)
-            .map_err(EmbeddingError::ExtractCls)?
-            .squeeze(1)
-            .map_err(EmbeddingError::ExtractCls)?
-      };
+      let pooled = Self::pool(&embeddings, &attention_mask_tensor, dense.pooling)?;
 
       let mut flat: Vec<f32> = pooled
          .to_dtype(DType::F32)
@@ -709,7 +1030,7 @@ impl CandleEmbedder {
    }
 
    async fn compute_colbert_embedding(&self, text: &str) -> Result<Array2<f32>> {
-      let (token_ids, attention_mask) = self.tokenize_colbert(text).await?;
+      let (token_ids, attention_mask) = self.tokenize_colbert_query(text).await?;
       let seq_len = token_ids.len();
 
       let token_ids_tensor = Tensor::new(&token_ids[..], &self.device)
@@ -727,16 +1048,14 @@ impl CandleEmbedder {
          .zeros_like()
          .map_err(EmbeddingError::CreateMask)?;
 
-      let Models(_, colbert) = self.models().await?;
+      let models = self.models().await?;
+      let Models(_, colbert) = &*models;
 
       let embeddings = colbert
          .bert
          .forward(&token_ids_tensor, &token_type_ids, Some(&attention_mask_tensor))
          .map_err(EmbeddingError::ForwardPass)?;
-      let projected = colbert
-         .projection
-         .forward(&embeddings)
-         .map_err(EmbeddingError::Projection)?;
+      let projected = colbert.project(embeddings)?;
 
       let dim = config::get().colbert_dim;
 
@@ -801,17 +1120,15 @@ impl CandleEmbedder {
          .zeros_like()
          .map_err(EmbeddingError::CreateMask)?;
 
-      let Models(_, colbert) = self.models().await?;
+      let models = self.models().await?;
+      let Models(_, colbert) = &*models;
 
       let embeddings = colbert
          .bert
          .forward(&token_ids_tensor, &token_type_ids, Some(&attention_mask_tensor))
          .map_err(EmbeddingError::ForwardPass)?;
 
-      let projected = colbert
-         .projection
-         .forward(&embeddings)
-         .map_err(EmbeddingError::Projection)?;
+      let projected = colbert.project(embeddings)?;
 
       let projected_f32 = projected
          .to_dtype(DType::F32)
@@ -833,6 +1150,7 @@ impl CandleEmbedder {
          let end = base + seq_len * dim;
          let mut data = flat[base..end].to_vec();
          for chunk in data.chunks_mut(dim) {
+            Self::sanitize(chunk);
             Self::normalize_l2(chunk);
          }
          results.push(Array2::from_shape_vec((seq_len, dim), data).expect("shape matches data"));
@@ -858,13 +1176,17 @@ impl CandleEmbedder {
       let buckets = Self::bucket_by_length(&combined_lengths, 32);
 
       let mut results = vec![None; texts.len()];
-      let mut current_batch_size = self.adaptive_batch_size.load(Ordering::Relaxed);
 
       for bucket_indices in &buckets {
+         let seq_len = bucket_indices
+            .iter()
+            .map(|&idx| combined_lengths[idx])
+            .max()
+            .unwrap_or(1);
          let mut offset = 0;
 
          while offset < bucket_indices.len() {
-            let end = (offset + current_batch_size).min(bucket_indices.len());
+            let end = (offset + self.batcher.batch_len(seq_len)).min(bucket_indices.len());
             let batch_indices = &bucket_indices[offset..end];
 
             match self
@@ -878,13 +1200,12 @@ impl CandleEmbedder {
                      let (colbert, colbert_scale) = Self::quantize_embeddings(colbert_tokens);
                      results[orig_idx] = Some(HybridEmbedding { dense, colbert, colbert_scale });
                   }
+                  self.batcher.record_success();
                   offset = end;
                },
                Err(e) => {
-                  let err_str = e.to_string();
-                  if is_oom_error(&err_str) && current_batch_size > MIN_BATCH_SIZE {
-                     current_batch_size = self.reduce_batch_size();
-                  } else {
+                  if !(is_oom_error(&e.to_string()) && self.batcher.record_oom(batch_indices.len()))
+                  {
                      return Err(e);
                   }
                },
@@ -892,10 +1213,70 @@ impl CandleEmbedder {
          }
       }
 
-      Ok(results
+      let mut results: Vec<HybridEmbedding> = results
          .into_iter()
          .map(|r| r.expect("all indices processed"))
-         .collect())
+         .collect();
+      self.embed_windows(texts, &mut results).await?;
+      for result in &mut results {
+         truncate_dense(&mut result.dense);
+      }
+      Ok(results)
+   }
+
+   /// With `long_chunks = "windows"`, replaces the dense embedding of each
+   /// text longer than `dense_max_length` tokens with the pooled embeddings
+   /// of overlapping windows covering all of it.
+   async fn embed_windows(&self, texts: &[Str], results: &mut [HybridEmbedding]) -> Result<()> {
+      let models = self.models().await?;
+      let Models(dense, _) = &*models;
+      let Some(windower) = &dense.windows else {
+         return Ok(());
+      };
+
+      let mut windows = Vec::new();
+      let mut spans = Vec::new();
+      for (idx, text) in texts.iter().enumerate() {
+         let encoding = windower
+            .encode(text.as_str(), true)
+            .map_err(EmbeddingError::from)?;
+         if encoding.get_overflowing().is_empty() {
+            continue;
+         }
+         let start = windows.len();
+         for window in std::iter::once(&encoding).chain(encoding.get_overflowing()) {
+            windows.push((window.get_ids().to_vec(), window.get_attention_mask().to_vec()));
+         }
+         spans.push((idx, start..windows.len()));
+      }
+      if windows.is_empty() {
+         return Ok(());
+      }
+
+      let indices: Vec<usize> = (0..windows.len()).collect();
+      let seq_len = windows.iter().map(|(ids, _)| ids.len()).max().unwrap_or(1);
+      let mut embedded: Vec<Vec<f32>> = Vec::with_capacity(windows.len());
+      while embedded.len() < indices.len() {
+         let end = (embedded.len() + self.batcher.batch_len(seq_len)).min(indices.len());
+         let batch = &indices[embedded.len()..end];
+         match self
+            .compute_dense_embeddings_batch_inner(batch, &windows)
+            .await
+         {
+            Ok(matrix) => {
+               embedded.extend(matrix.rows().into_iter().map(|row| row.to_vec()));
+               self.batcher.record_success();
+            },
+            Err(e) if is_oom_error(&e.to_string()) && self.batcher.record_oom(batch.len()) => {},
+            Err(e) => return Err(e),
+         }
+      }
+
+      let pooling = config::get().window_pooling;
+      for (idx, span) in spans {
+         results[idx].dense = pool_windows(&embedded[span], pooling);
+      }
+      Ok(())
    }
 
    async fn try_compute_batch_indexed(
@@ -932,7 +1313,8 @@ impl Embedder for CandleEmbedder {
          tracing::info!("encoding query: {:?}", text);
       }
 
-      let dense = self.compute_dense_embedding(&query_text).await?;
+      let mut dense = self.compute_dense_embedding(&query_text).await?;
+      truncate_dense(&mut dense);
       let colbert = self.compute_colbert_embedding(&query_text).await?;
 
       if cfg.debug_embed {
@@ -946,8 +1328,60 @@ impl Embedder for CandleEmbedder {
       Ok(QueryEmbedding { dense, colbert })
    }
 
+   async fn encode_queries(&self, texts: &[&str]) -> Result<Vec<QueryEmbedding>> {
+      let prefix = &config::get().query_prefix;
+      let mut dense_tokenized = Vec::with_capacity(texts.len());
+      let mut colbert_tokenized = Vec::with_capacity(texts.len());
+      for text in texts {
+         let query_text = format!("{prefix}{text}");
+         dense_tokenized.push(self.tokenize_dense(&query_text).await?);
+         colbert_tokenized.push(self.tokenize_colbert_query(&query_text).await?);
+      }
+
+      let indices: Vec<usize> = (0..texts.len()).collect();
+      let seq_len = dense_tokenized
+         .iter()
+         .zip(&colbert_tokenized)
+         .map(|((dense_ids, _), (colbert_ids, _))| dense_ids.len().max(colbert_ids.len()))
+         .max()
+         .unwrap_or(1);
+      let mut embeddings = Vec::with_capacity(texts.len());
+      while embeddings.len() < indices.len() {
+         let end = (embeddings.len() + self.batcher.batch_len(seq_len)).min(indices.len());
+         let batch = &indices[embeddings.len()..end];
+         match self
+            .try_compute_batch_indexed(batch, &dense_tokenized, &colbert_tokenized)
+            .await
+         {
+            Ok((dense_matrix, colbert_embeddings)) => {
+               for (row, colbert) in dense_matrix.rows().into_iter().zip(colbert_embeddings) {
+                  let mut dense = row.to_vec();
+                  truncate_dense(&mut dense);
+                  embeddings.push(QueryEmbedding { dense, colbert });
+               }
+               self.batcher.record_success();
+            },
+            Err(e) if is_oom_error(&e.to_string()) && self.batcher.record_oom(batch.len()) => {},
+            Err(e) => return Err(e),
+         }
+      }
+      Ok(embeddings)
+   }
+
    fn is_ready(&self) -> bool {
-      self.models.get().is_some()
+      self.models.read().is_some()
+   }
+
+   fn unload(&self) -> bool {
+      Self::unload(self)
+   }
+
+   fn status(&self) -> EmbedderStatus {
+      EmbedderStatus {
+         models_loaded: self.is_ready(),
+         device:        self.device_name(),
+         queue_depth:   0,
+      }
    }
 }
 
@@ -956,3 +1390,36 @@ impl Default for CandleEmbedder {
       Self::new().expect("failed to create CandleEmbedder")
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const MARKERS: ColbertMarkers = ColbertMarkers { query: 1, document: 2, mask: 103 };
+
+   #[test]
+   fn marks_colbert_inputs_after_cls() {
+      let tokens = || (vec![101, 7, 8, 102], vec![1, 1, 1, 1]);
+
+      assert_eq!(MARKERS.document(tokens()), (vec![101, 2, 7, 8, 102], vec![1, 1, 1, 1, 1]));
+      assert_eq!(
+         MARKERS.query(tokens(), 7),
+         (vec![101, 1, 7, 8, 102, 103, 103], vec![1, 1, 1, 1, 1, 0, 0])
+      );
+      assert_eq!(MARKERS.query(tokens(), 3).0.len(), 5);
+   }
+
+   #[test]
+   fn pools_window_embeddings_into_a_unit_vector() {
+      let windows = [vec![1.0, 0.0], vec![0.0, 1.0], vec![0.6, 0.8]];
+
+      let mean = pool_windows(&windows, WindowPooling::Mean);
+      let norm = 1.6f32.hypot(1.8);
+      assert!((mean[0] - 1.6 / norm).abs() < 1e-6 && (mean[1] - 1.8 / norm).abs() < 1e-6);
+
+      let max = pool_windows(&windows, WindowPooling::Max);
+      assert!(
+         (max[0] - 1.0 / 2f32.sqrt()).abs() < 1e-6 && (max[1] - 1.0 / 2f32.sqrt()).abs() < 1e-6
+      );
+   }
+}
@@ -0,0 +1,177 @@
+//! Deterministic embedder for tests.
+//!
+//! [`MockEmbedder`] hashes the words of a text into its vectors instead of
+//! running a model, so sync and search can be exercised without downloading
+//! one. Texts sharing words get similar dense vectors and matching `ColBERT`
+//! tokens, which keeps search results meaningful. Enabled by the
+//! `mock-embedder` feature.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ndarray::Array2;
+
+use crate::{
+   Str, config,
+   embed::{CandleEmbedder, Embedder, EmbedderStatus, HybridEmbedding, QueryEmbedding},
+   error::Result,
+};
+
+/// Words of a text given a `ColBERT` row, like a model's token limit.
+const MAX_WORDS: usize = 512;
+
+/// Embedder producing hash-based vectors, identical across runs and
+/// platforms.
+#[derive(Debug)]
+pub struct MockEmbedder {
+   dense_dim:   usize,
+   colbert_dim: usize,
+   embedded:    AtomicUsize,
+}
+
+impl MockEmbedder {
+   pub const fn new(dense_dim: usize, colbert_dim: usize) -> Self {
+      Self { dense_dim, colbert_dim, embedded: AtomicUsize::new(0) }
+   }
+
+   /// Texts embedded so far by [`Embedder::compute_hybrid`].
+   pub fn embedded(&self) -> usize {
+      self.embedded.load(Ordering::Relaxed)
+   }
+
+   /// Dense vector of `text`: its words hashed into signed buckets,
+   /// normalized.
+   pub fn dense(&self, text: &str) -> Vec<f32> {
+      let mut dense = vec![0.0; self.dense_dim];
+      for word in words(text) {
+         add_feature(&mut dense, fnv1a(word.as_bytes()));
+      }
+      CandleEmbedder::normalize_l2(&mut dense);
+      dense
+   }
+
+   /// `ColBERT` matrix of `text`, a row per word up to [`MAX_WORDS`].
+   pub fn colbert(&self, text: &str) -> Array2<f32> {
+      let words: Vec<String> = words(text).take(MAX_WORDS).collect();
+      let mut matrix = Array2::zeros((words.len(), self.colbert_dim));
+      for (mut row, word) in matrix.rows_mut().into_iter().zip(&words) {
+         let hash = fnv1a(word.as_bytes());
+         let row = row.as_slice_mut().expect("rows are contiguous");
+         // Two features so that distinct words rarely collide completely
+         add_feature(row, hash);
+         add_feature(row, hash.rotate_left(32));
+         CandleEmbedder::normalize_l2(row);
+      }
+      matrix
+   }
+}
+
+impl Default for MockEmbedder {
+   /// Embedder with the configured dimensions, matching the store.
+   fn default() -> Self {
+      let cfg = config::get();
+      Self::new(cfg.dense_vector_dim(), cfg.colbert_dim)
+   }
+}
+
+#[async_trait::async_trait]
+impl Embedder for MockEmbedder {
+   async fn compute_hybrid(&self, texts: &[Str]) -> Result<Vec<HybridEmbedding>> {
+      self.embedded.fetch_add(texts.len(), Ordering::Relaxed);
+      Ok(texts
+         .iter()
+         .map(|text| {
+            let (colbert, colbert_scale) =
+               CandleEmbedder::quantize_embeddings(&self.colbert(text.as_str()));
+            HybridEmbedding { dense: self.dense(text.as_str()), colbert, colbert_scale }
+         })
+         .collect())
+   }
+
+   async fn encode_query(&self, text: &str) -> Result<QueryEmbedding> {
+      Ok(QueryEmbedding { dense: self.dense(text), colbert: self.colbert(text) })
+   }
+
+   fn is_ready(&self) -> bool {
+      true
+   }
+
+   fn unload(&self) -> bool {
+      false
+   }
+
+   fn status(&self) -> EmbedderStatus {
+      EmbedderStatus { models_loaded: true, device: "mock".to_string(), queue_depth: 0 }
+   }
+}
+
+/// Lowercased words (runs of alphanumerics) of a text, so that
+/// `snake_case` identifiers match the words they are made of.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+   text
+      .split(|c: char| !c.is_alphanumeric())
+      .filter(|word| !word.is_empty())
+      .map(str::to_lowercase)
+}
+
+/// Adds ±1 to the bucket of `vector` picked by `hash`.
+fn add_feature(vector: &mut [f32], hash: u64) {
+   if vector.is_empty() {
+      return;
+   }
+   let bucket = (hash % vector.len() as u64) as usize;
+   vector[bucket] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+}
+
+/// 64-bit FNV-1a, which unlike the standard hasher is stable across Rust
+/// releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+   bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+      (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn cosine(a: &[f32], b: &[f32]) -> f32 {
+      a.iter().zip(b).map(|(x, y)| x * y).sum()
+   }
+
+   #[tokio::test]
+   async fn embeds_deterministically_by_shared_words() {
+      let embedder = MockEmbedder::new(64, 16);
+      let texts: Vec<Str> = vec!["fn parse_config(path: &Path)".into(), "".into()];
+      let first = embedder.compute_hybrid(&texts).await.unwrap();
+      let again = MockEmbedder::new(64, 16)
+         .compute_hybrid(&texts)
+         .await
+         .unwrap();
+      assert_eq!(first[0].dense, again[0].dense);
+      assert_eq!(first[0].colbert, again[0].colbert);
+      assert_eq!(first[0].colbert.len(), 5 * 16);
+      assert!(first[1].dense.iter().all(|&x| x == 0.0));
+      assert!(first[1].colbert.is_empty());
+      assert_eq!(embedder.embedded(), 2);
+
+      let query = embedder.encode_query("parse config").await.unwrap();
+      let related = embedder.dense("let cfg = parse_config(&path)?;");
+      let unrelated = embedder.dense("struct Renderer { width: u32 }");
+      assert!(cosine(&query.dense, &first[0].dense) > cosine(&query.dense, &unrelated));
+      assert!(cosine(&first[0].dense, &related) > cosine(&first[0].dense, &unrelated));
+      assert_eq!(query.colbert.dim(), (2, 16));
+
+      let batch = embedder
+         .encode_queries(&["parse config", "render"])
+         .await
+         .unwrap();
+      assert_eq!(batch.len(), 2);
+      assert_eq!(batch[0].dense, query.dense);
+   }
+
+   #[test]
+   fn hashes_stably() {
+      assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+      assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+   }
+}
@@ -3,16 +3,20 @@
 //! Provides hybrid embedding functionality combining dense and `ColBERT` sparse
 //! vectors for improved retrieval accuracy.
 
+pub mod batcher;
 pub mod candle;
+#[cfg(any(test, feature = "mock-embedder"))]
+pub mod mock;
+pub mod registry;
 pub mod worker;
 
-use std::sync::Arc;
+use std::{collections::HashMap, hash::BuildHasher, sync::Arc};
 
 pub use candle::CandleEmbedder;
 use ndarray::Array2;
 pub use worker::EmbedWorker;
 
-use crate::{Str, error::Result};
+use crate::{Str, error::Result, meta::FileHash};
 
 /// Hybrid embedding representation combining dense and sparse vectors
 ///
@@ -37,6 +41,17 @@ pub struct QueryEmbedding {
    pub colbert: Array2<f32>,
 }
 
+/// State of an embedder, as reported by the daemon's health check
+#[derive(Debug, Clone)]
+pub struct EmbedderStatus {
+   /// Whether the models are in memory rather than waiting for first use
+   pub models_loaded: bool,
+   /// Device the models run on, such as `cpu` or `cuda:0`
+   pub device:        String,
+   /// Embedding batches waiting for a worker
+   pub queue_depth:   usize,
+}
+
 /// Text embedding trait for generating hybrid embeddings
 #[async_trait::async_trait]
 pub trait Embedder: Send + Sync {
@@ -44,8 +59,66 @@ pub trait Embedder: Send + Sync {
    async fn compute_hybrid(&self, texts: &[Str]) -> Result<Vec<HybridEmbedding>>;
    /// Encodes a query with optional prefix
    async fn encode_query(&self, text: &str) -> Result<QueryEmbedding>;
+   /// Encodes several queries, in order; embedders that can run them as one
+   /// batch override this
+   async fn encode_queries(&self, texts: &[&str]) -> Result<Vec<QueryEmbedding>> {
+      let mut embeddings = Vec::with_capacity(texts.len());
+      for text in texts {
+         embeddings.push(self.encode_query(text).await?);
+      }
+      Ok(embeddings)
+   }
    /// Returns whether the embedder models are loaded and ready
    fn is_ready(&self) -> bool;
+   /// Frees the loaded models until the next call needs them, returning
+   /// whether any were loaded
+   fn unload(&self) -> bool;
+   /// Reports model, device and queue state for diagnostics
+   fn status(&self) -> EmbedderStatus;
+}
+
+/// Embeds `texts`, taking embeddings from `reuse` for any text whose content
+/// hash it contains and computing only the rest.
+///
+/// Returns the embeddings in input order along with how many were computed.
+pub async fn compute_hybrid_reusing<E: Embedder + ?Sized, S: BuildHasher + Sync>(
+   embedder: &E,
+   texts: &[Str],
+   reuse: &HashMap<FileHash, HybridEmbedding, S>,
+) -> Result<(Vec<HybridEmbedding>, usize)> {
+   let reused: Vec<Option<HybridEmbedding>> = texts
+      .iter()
+      .map(|t| reuse.get(&FileHash::sum(t.as_bytes())).cloned())
+      .collect();
+   let missing: Vec<Str> = texts
+      .iter()
+      .zip(&reused)
+      .filter(|(_, r)| r.is_none())
+      .map(|(t, _)| t.clone())
+      .collect();
+
+   let computed = if missing.is_empty() {
+      Vec::new()
+   } else {
+      embedder.compute_hybrid(&missing).await?
+   };
+   if computed.len() != missing.len() {
+      return Err(
+         candle::EmbeddingError::CountMismatch {
+            expected: missing.len(),
+            got:      computed.len(),
+         }
+         .into(),
+      );
+   }
+
+   let computed_count = computed.len();
+   let mut computed = computed.into_iter();
+   let embeddings = reused
+      .into_iter()
+      .filter_map(|r| r.or_else(|| computed.next()))
+      .collect();
+   Ok((embeddings, computed_count))
 }
 
 #[async_trait::async_trait]
@@ -58,7 +131,80 @@ impl<T: Embedder + ?Sized> Embedder for Arc<T> {
       (**self).encode_query(text).await
    }
 
+   async fn encode_queries(&self, texts: &[&str]) -> Result<Vec<QueryEmbedding>> {
+      (**self).encode_queries(texts).await
+   }
+
    fn is_ready(&self) -> bool {
       (**self).is_ready()
    }
+
+   fn unload(&self) -> bool {
+      (**self).unload()
+   }
+
+   fn status(&self) -> EmbedderStatus {
+      (**self).status()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   /// Embeds each text and query as its length, counting how many texts it
+   /// saw.
+   struct LenEmbedder(std::sync::atomic::AtomicUsize);
+
+   #[async_trait::async_trait]
+   impl Embedder for LenEmbedder {
+      async fn compute_hybrid(&self, texts: &[Str]) -> Result<Vec<HybridEmbedding>> {
+         self
+            .0
+            .fetch_add(texts.len(), std::sync::atomic::Ordering::Relaxed);
+         Ok(texts
+            .iter()
+            .map(|t| HybridEmbedding {
+               dense:         vec![t.len() as f32],
+               colbert:       Vec::new(),
+               colbert_scale: 1.0,
+            })
+            .collect())
+      }
+
+      async fn encode_query(&self, text: &str) -> Result<QueryEmbedding> {
+         Ok(QueryEmbedding { dense: vec![text.len() as f32], colbert: Array2::zeros((0, 0)) })
+      }
+
+      fn is_ready(&self) -> bool {
+         true
+      }
+
+      fn unload(&self) -> bool {
+         false
+      }
+
+      fn status(&self) -> EmbedderStatus {
+         EmbedderStatus { models_loaded: true, device: "cpu".to_string(), queue_depth: 0 }
+      }
+   }
+
+   #[tokio::test]
+   async fn reuses_embeddings_of_unchanged_texts() {
+      let stored =
+         HybridEmbedding { dense: vec![-1.0], colbert: vec![7], colbert_scale: 0.5 };
+      let reuse = HashMap::from([(FileHash::sum(b"fn a() {}"), stored)]);
+      let texts: Vec<Str> = vec!["fn b() {}\n".into(), "fn a() {}".into()];
+
+      let embedder = LenEmbedder(Default::default());
+      let (embeddings, computed) = compute_hybrid_reusing(&embedder, &texts, &reuse)
+         .await
+         .unwrap();
+
+      assert_eq!(computed, 1);
+      assert_eq!(embedder.0.into_inner(), 1);
+      assert_eq!(embeddings[0].dense, vec![10.0]);
+      assert_eq!(embeddings[1].dense, vec![-1.0]);
+      assert_eq!(embeddings[1].colbert, vec![7]);
+   }
 }
@@ -0,0 +1,229 @@
+//! Local registry of embedding models and the stores bound to them.
+//!
+//! Every model smgrep has fetched or loaded is recorded in `models.json`
+//! with the size of its embeddings, so that a model picked with `--model`
+//! can be used without knowing its dimensions up front. A store indexed with
+//! `--model` is bound to that model, and later commands and daemons for the
+//! store use it instead of the configured `dense_model`, so stores with
+//! different models can be used side by side.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use hf_hub::{Cache, api::tokio::Api};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+   Result,
+   config::{self, Pooling},
+   embed::candle::{CandleEmbedder, EmbeddingError, hub_offline, hub_repo},
+   util,
+};
+
+/// What a model is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelKind {
+   Dense,
+   Colbert,
+}
+
+/// A model smgrep has fetched or loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredModel {
+   pub kind:        ModelKind,
+   /// Size of the embeddings the model produces.
+   pub dim:         usize,
+   /// Unix timestamp (seconds) at which the model was first recorded.
+   pub recorded_at: u64,
+   /// Pooling the dense model was trained with, once it has been loaded.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub pooling:     Option<Pooling>,
+}
+
+/// Contents of `models.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ModelRegistry {
+   #[serde(default)]
+   pub models:   BTreeMap<String, RegisteredModel>,
+   /// Dense model by store ID, for stores indexed with `--model`.
+   #[serde(default)]
+   pub bindings: BTreeMap<String, String>,
+}
+
+impl ModelRegistry {
+   /// Loads the registry, starting empty if it is missing or unreadable.
+   pub fn load() -> Self {
+      fs::read(config::model_registry_path())
+         .ok()
+         .and_then(|data| {
+            serde_json::from_slice(&data)
+               .inspect_err(|e| tracing::warn!("ignoring unreadable model registry: {e}"))
+               .ok()
+         })
+         .unwrap_or_default()
+   }
+
+   /// Writes the registry, replacing it atomically.
+   pub fn save(&self) -> Result<()> {
+      let path = config::model_registry_path();
+      if let Some(parent) = path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+      let tmp = path.with_extension("json.tmp");
+      fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+      fs::rename(&tmp, path)?;
+      Ok(())
+   }
+
+   /// Dense model the store is bound to, with its embedding size, if the
+   /// model is registered.
+   pub fn bound_model(&self, store_id: &str) -> Option<(&str, usize)> {
+      let model = self.bindings.get(store_id)?;
+      let dim = self.models.get(model)?.dim;
+      Some((model, dim))
+   }
+
+   /// Records a model, keeping a known pooling when `pooling` is `None` and
+   /// the registry file as is if nothing changed.
+   pub fn record(
+      model_id: &str,
+      kind: ModelKind,
+      dim: usize,
+      pooling: Option<Pooling>,
+   ) -> Result<()> {
+      let mut registry = Self::load();
+      let known = registry.models.get(model_id);
+      let pooling = pooling.or_else(|| known.and_then(|m| m.pooling));
+      if known.is_some_and(|m| m.kind == kind && m.dim == dim && m.pooling == pooling) {
+         return Ok(());
+      }
+      registry
+         .models
+         .insert(model_id.to_string(), RegisteredModel {
+            kind,
+            dim,
+            recorded_at: util::unix_now(),
+            pooling,
+         });
+      registry.save()
+   }
+
+   /// Binds a store to a dense model, returning whether it was bound to
+   /// another one or none.
+   pub fn bind(store_id: &str, model_id: &str) -> Result<bool> {
+      let mut registry = Self::load();
+      if registry.bindings.get(store_id).map(String::as_str) == Some(model_id) {
+         return Ok(false);
+      }
+      registry
+         .bindings
+         .insert(store_id.to_string(), model_id.to_string());
+      registry.save()?;
+      Ok(true)
+   }
+}
+
+/// Size of the embeddings of the dense model `model_id`, from the registry,
+/// or else from its `config.json`, fetched from the Hub if no local copy has
+/// one. Records the model.
+pub async fn resolve_dense_dim(model_id: &str) -> Result<usize> {
+   if let Some(model) = ModelRegistry::load().models.get(model_id) {
+      return Ok(model.dim);
+   }
+
+   let config_path = if let Some(dir) = CandleEmbedder::installed_model_dir(model_id) {
+      dir.join("config.json")
+   } else if let Some(path) = Cache::default().repo(hub_repo(model_id)).get("config.json") {
+      path
+   } else if hub_offline() {
+      return Err(EmbeddingError::ModelNotInstalled(model_id.to_string()).into());
+   } else {
+      Api::new()
+         .map_err(EmbeddingError::InitHfHub)?
+         .repo(hub_repo(model_id))
+         .get("config.json")
+         .await
+         .map_err(|e| EmbeddingError::DownloadModel {
+            file:   "config.json".to_string(),
+            model:  model_id.to_string(),
+            reason: e.to_string(),
+         })?
+   };
+
+   let dim = hidden_size(&config_path)?;
+   ModelRegistry::record(model_id, ModelKind::Dense, dim, None)?;
+   Ok(dim)
+}
+
+/// `hidden_size` of a model's `config.json`, the size of its embeddings.
+pub fn hidden_size(config_path: &Path) -> Result<usize> {
+   #[derive(Deserialize)]
+   struct ModelConfig {
+      hidden_size: usize,
+   }
+
+   let content = fs::read_to_string(config_path).map_err(EmbeddingError::ReadConfig)?;
+   let config: ModelConfig = serde_json::from_str(&content).map_err(EmbeddingError::ParseConfig)?;
+   Ok(config.hidden_size)
+}
+
+/// Pooling set in a sentence-transformers pooling config
+/// (`1_Pooling/config.json`), if it names one smgrep supports.
+pub fn sentence_transformers_pooling(config: &str) -> Option<Pooling> {
+   #[derive(Deserialize)]
+   struct PoolingConfig {
+      #[serde(default)]
+      pooling_mode_cls_token:   bool,
+      #[serde(default)]
+      pooling_mode_mean_tokens: bool,
+      #[serde(default)]
+      pooling_mode_lasttoken:   bool,
+   }
+
+   let config: PoolingConfig = serde_json::from_str(config).ok()?;
+   if config.pooling_mode_cls_token {
+      Some(Pooling::Cls)
+   } else if config.pooling_mode_mean_tokens {
+      Some(Pooling::Mean)
+   } else if config.pooling_mode_lasttoken {
+      Some(Pooling::LastToken)
+   } else {
+      None
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn binding_needs_a_registered_model() {
+      let mut registry = ModelRegistry::default();
+      registry
+         .bindings
+         .insert("org-repo".to_string(), "org/model".to_string());
+      assert_eq!(registry.bound_model("org-repo"), None);
+
+      registry
+         .models
+         .insert("org/model".to_string(), RegisteredModel {
+            kind:        ModelKind::Dense,
+            dim:         768,
+            recorded_at: 0,
+            pooling:     None,
+         });
+      assert_eq!(registry.bound_model("org-repo"), Some(("org/model", 768)));
+      assert_eq!(registry.bound_model("other"), None);
+   }
+
+   #[test]
+   fn reads_sentence_transformers_pooling() {
+      let mean = r#"{"word_embedding_dimension": 384, "pooling_mode_cls_token": false,
+         "pooling_mode_mean_tokens": true, "pooling_mode_max_tokens": false}"#;
+      assert_eq!(sentence_transformers_pooling(mean), Some(Pooling::Mean));
+      let last = r#"{"pooling_mode_lasttoken": true}"#;
+      assert_eq!(sentence_transformers_pooling(last), Some(Pooling::LastToken));
+      let max = r#"{"pooling_mode_max_tokens": true}"#;
+      assert_eq!(sentence_transformers_pooling(max), None);
+   }
+}
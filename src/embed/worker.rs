@@ -1,9 +1,14 @@
 //! Multi-threaded embedding worker pool with timeout management
 //!
 //! Provides concurrent embedding processing with automatic worker lifecycle
-//! management and timeout-based cleanup.
+//! management and timeout-based cleanup. Workers that idled out or crashed
+//! are started again on demand, and a batch that a worker panicked on or that
+//! took longer than `worker_timeout_ms` is retried up to `embed_retries`
+//! times on a restarted pool.
 
 use std::{
+   any::Any,
+   panic::AssertUnwindSafe,
    sync::{
       Arc,
       atomic::{AtomicU64, Ordering},
@@ -11,7 +16,8 @@ use std::{
    time::Duration,
 };
 
-use futures::{StreamExt, stream::FuturesUnordered};
+use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
+use parking_lot::Mutex;
 use smallvec::SmallVec;
 use tokio::{
    sync::oneshot,
@@ -22,8 +28,11 @@ use tokio_util::sync::CancellationToken;
 
 use crate::{
    Str, config,
-   embed::{CandleEmbedder, Embedder, HybridEmbedding, QueryEmbedding, candle::EmbeddingError},
-   error::Result,
+   embed::{
+      CandleEmbedder, Embedder, EmbedderStatus, HybridEmbedding, QueryEmbedding,
+      candle::EmbeddingError,
+   },
+   error::{Error, Result},
 };
 
 struct WorkerMessage {
@@ -45,15 +54,26 @@ impl RelativeClock {
    }
 }
 
+/// Running worker tasks, replaced as a whole when a worker hangs.
+struct Pool {
+   workers:      Vec<JoinHandle<()>>,
+   cancel_token: CancellationToken,
+}
+
 /// Multi-threaded embedding worker pool
 ///
 /// Distributes embedding work across multiple async workers with automatic
-/// timeout management and graceful shutdown. Workers idle out after inactivity.
+/// timeout management and graceful shutdown. Workers idle out after inactivity
+/// and are started again by the next request.
 pub struct EmbedWorker {
-   workers:      Option<Vec<JoinHandle<()>>>,
+   pool:         Mutex<Pool>,
    sender:       flume::Sender<WorkerMessage>,
-   cancel_token: CancellationToken,
+   receiver:     flume::Receiver<WorkerMessage>,
    batch_size:   usize,
+   timeout:      Duration,
+   retries:      u32,
+   clock:        RelativeClock,
+   last_message: Arc<AtomicU64>,
    embedder:     Arc<CandleEmbedder>,
 }
 
@@ -62,86 +82,182 @@ impl EmbedWorker {
    pub fn new() -> Result<Self> {
       let cfg = config::get();
       let num_threads = cfg.default_threads();
-      let batch_sz = cfg.batch_size();
-      let timeout = Duration::from_millis(cfg.worker_timeout_ms);
       let embedder = Arc::new(CandleEmbedder::new()?);
 
-      let (tx, rx) = flume::bounded(num_threads * 2);
+      let (sender, receiver) = flume::bounded(num_threads * 2);
 
-      let mut workers = Vec::with_capacity(num_threads);
+      let worker = Self {
+         pool: Mutex::new(Pool {
+            workers:      Vec::with_capacity(num_threads),
+            cancel_token: CancellationToken::new(),
+         }),
+         sender,
+         receiver,
+         batch_size: cfg.batch_size(),
+         timeout: Duration::from_millis(cfg.worker_timeout_ms),
+         retries: cfg.embed_retries,
+         clock: RelativeClock::new(),
+         last_message: Arc::new(AtomicU64::new(0)),
+         embedder,
+      };
+      {
+         let mut pool = worker.pool.lock();
+         for worker_id in 0..num_threads {
+            let handle = worker.spawn(worker_id, &pool.cancel_token);
+            pool.workers.push(handle);
+         }
+      }
+      Ok(worker)
+   }
+
+   fn spawn(&self, worker_id: usize, cancel_token: &CancellationToken) -> JoinHandle<()> {
+      let timeout = self.timeout;
+      let timeout_ms = self.timeout.as_millis() as u64;
+      let cancel_token = cancel_token.clone();
+      let last_message = self.last_message.clone();
+      let t0 = self.clock;
+      let embedder = self.embedder.clone();
+      let mut rx = self.receiver.clone().into_stream();
 
-      let t0 = RelativeClock::new();
-      let cancel_token = CancellationToken::new();
-      let last_message = Arc::new(AtomicU64::new(0));
+      tokio::spawn(async move {
+         let mut timer = tokio::time::interval(timeout);
+         timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+         tracing::debug!(worker_id, "embedding worker started");
 
-      for worker_id in 0..num_threads {
-         let timeout_ms = cfg.worker_timeout_ms;
-         let cancel_token = cancel_token.clone();
-         let last_message = last_message.clone();
-         let embedder = embedder.clone();
-         let mut rx = rx.clone().into_stream();
-         let handle = tokio::spawn(async move {
-            let mut timer = tokio::time::interval(timeout);
-            timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
-            tracing::debug!(worker_id, "embedding worker started");
+         let mut ops = FuturesUnordered::new();
 
-            let mut ops = FuturesUnordered::new();
+         loop {
+            // if we have more than 10 operations, wait for one to complete
+            if ops.len() > 10 {
+               ops.next().await;
+               continue;
+            }
 
-            loop {
-               // if we have more than 10 operations, wait for one to complete
-               if ops.len() > 10 {
-                  ops.next().await;
+            // Receive a message from the channel
+            let msg: WorkerMessage = tokio::select! {
+               // An empty set is always ready, which would spin the worker
+               _ = ops.next(), if !ops.is_empty() => {
+                  // An operation completed
                   continue;
                }
-
-               // Receive a message from the channel
-               let msg: WorkerMessage = tokio::select! {
-                  _ = ops.next() => {
-                     // An operation completed
-                     continue;
-                  }
-                  Some(msg) = rx.next() => {
-                     // Update the last message time
-                     last_message.fetch_max(t0.time(), Ordering::Release);
-                     msg
-                  }
-                  _ = timer.tick() => {
-                     // Check if the timeout has been exceeded
-                     let now = t0.time();
-                     let last_msg = last_message.load(Ordering::Acquire);
-                     if now - last_msg > timeout_ms {
-                        // Cancel the token if the timeout has been exceeded
-                        cancel_token.cancel();
-                     }
-                     continue;
-                  }
-                  () = cancel_token.cancelled() => {
-                     // Cancel the token if the worker has been cancelled
-                     tracing::debug!(worker_id, "embedding worker cancelled");
-                     break;
-                  }
-                  else => {
-                     // The channel has been disconnected, shut down the worker
-                     tracing::error!(worker_id, "channel disconnected, shutting down");
+               Some(msg) = rx.next() => {
+                  // Update the last message time
+                  last_message.fetch_max(t0.time(), Ordering::Release);
+                  msg
+               }
+               _ = timer.tick() => {
+                  // Idle out once no message arrived within the timeout and
+                  // nothing is in flight
+                  let now = t0.time();
+                  let last_msg = last_message.load(Ordering::Acquire);
+                  if ops.is_empty() && now.saturating_sub(last_msg) > timeout_ms {
+                     tracing::debug!(worker_id, "embedding worker idle");
                      break;
                   }
-               };
-
-               // Compute the embeddings
-               let embedder = embedder.clone();
-               ops.push(async move {
-                  let result = embedder.compute_hybrid(&msg.chunk).await;
-                  _ = msg.tx.send(result);
-               });
-            }
+                  continue;
+               }
+               () = cancel_token.cancelled() => {
+                  tracing::debug!(worker_id, "embedding worker cancelled");
+                  break;
+               }
+               else => {
+                  // The channel has been disconnected, shut down the worker
+                  tracing::error!(worker_id, "channel disconnected, shutting down");
+                  break;
+               }
+            };
 
-            tracing::debug!(worker_id, "worker shut down");
-         });
+            // Compute the embeddings, answering with an error if the
+            // embedder panics so the worker keeps running
+            let embedder = embedder.clone();
+            ops.push(async move {
+               let result = AssertUnwindSafe(embedder.compute_hybrid(&msg.chunk))
+                  .catch_unwind()
+                  .await
+                  .unwrap_or_else(|panic| {
+                     tracing::error!(worker_id, "embedding worker panicked");
+                     Err(EmbeddingError::WorkerPanicked(panic_message(panic.as_ref())).into())
+                  });
+               _ = msg.tx.send(result);
+            });
+         }
 
-         workers.push(handle);
+         tracing::debug!(worker_id, "worker shut down");
+      })
+   }
+
+   /// Starts workers again in place of those that idled out or died.
+   fn ensure_running(&self) {
+      // Workers started now must not idle out before taking the request
+      self
+         .last_message
+         .fetch_max(self.clock.time(), Ordering::Release);
+      let mut pool = self.pool.lock();
+      let pool = &mut *pool;
+      for (worker_id, handle) in pool.workers.iter_mut().enumerate() {
+         if handle.is_finished() {
+            *handle = self.spawn(worker_id, &pool.cancel_token);
+         }
+      }
+   }
+
+   /// Replaces every worker after one hung. A worker stuck inside the model
+   /// cannot be interrupted and keeps its thread until it returns, but no
+   /// longer takes work.
+   fn restart(&self) {
+      let mut pool = self.pool.lock();
+      let pool = &mut *pool;
+      tracing::warn!("embedding workers unresponsive, restarting {}", pool.workers.len());
+      self
+         .last_message
+         .fetch_max(self.clock.time(), Ordering::Release);
+      pool.cancel_token.cancel();
+      pool.cancel_token = CancellationToken::new();
+      for (worker_id, handle) in pool.workers.iter_mut().enumerate() {
+         handle.abort();
+         *handle = self.spawn(worker_id, &pool.cancel_token);
       }
+   }
 
-      Ok(Self { workers: Some(workers), sender: tx, batch_size: batch_sz, cancel_token, embedder })
+   async fn submit(
+      &self,
+      chunk: &[Str],
+   ) -> Result<oneshot::Receiver<Result<Vec<HybridEmbedding>>>> {
+      let (tx, rx) = oneshot::channel();
+      self
+         .sender
+         .send_async(WorkerMessage { chunk: chunk.iter().cloned().collect(), tx })
+         .await
+         .map_err(|_| EmbeddingError::WorkerClosed)?;
+      Ok(rx)
+   }
+
+   /// Waits for the embeddings of `chunk`, resubmitting it after a worker
+   /// crashed or hung on it.
+   async fn receive(
+      &self,
+      chunk: &[Str],
+      mut rx: oneshot::Receiver<Result<Vec<HybridEmbedding>>>,
+   ) -> Result<Vec<HybridEmbedding>> {
+      let mut attempt = 0;
+      loop {
+         let err = match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(Err(Error::Embedding(err @ EmbeddingError::WorkerPanicked(_))))) => err,
+            Ok(Ok(result)) => return result,
+            Ok(Err(_)) => EmbeddingError::WorkCancelled,
+            Err(_) => {
+               self.restart();
+               EmbeddingError::WorkerTimeout(self.timeout.as_millis() as u64)
+            },
+         };
+         if attempt >= self.retries {
+            return Err(err.into());
+         }
+         attempt += 1;
+         tracing::warn!("embedding batch failed: {err}; retrying ({attempt}/{})", self.retries);
+         self.ensure_running();
+         rx = self.submit(chunk).await?;
+      }
    }
 
    /// Computes hybrid embeddings using the worker pool
@@ -150,29 +266,33 @@ impl EmbedWorker {
          return Ok(Vec::new());
       }
 
-      let rxs: Vec<oneshot::Receiver<_>> = texts
-         .chunks(self.batch_size)
-         .map(|chunk| {
-            let (tx, rx) = oneshot::channel();
-            self
-               .sender
-               .send(WorkerMessage { chunk: chunk.iter().cloned().collect(), tx })
-               .map_err(|_| EmbeddingError::WorkerClosed)?;
-            Ok(rx)
-         })
-         .collect::<Result<Vec<_>>>()?;
+      self.ensure_running();
+      let mut rxs = Vec::with_capacity(texts.len().div_ceil(self.batch_size));
+      for chunk in texts.chunks(self.batch_size) {
+         rxs.push(self.submit(chunk).await?);
+      }
 
       let mut messages = Vec::with_capacity(texts.len());
-      for rx in rxs {
-         messages.extend(rx.await.map_err(|_| EmbeddingError::WorkCancelled)??);
+      for (chunk, rx) in texts.chunks(self.batch_size).zip(rxs) {
+         messages.extend(self.receive(chunk, rx).await?);
       }
       Ok(messages)
    }
 }
 
+/// Message of a panic payload, which is a `&str` or `String` for panics
+/// raised with a message.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+   payload
+      .downcast_ref::<&str>()
+      .map(|s| (*s).to_string())
+      .or_else(|| payload.downcast_ref::<String>().cloned())
+      .unwrap_or_else(|| "unknown panic".to_string())
+}
+
 impl Drop for EmbedWorker {
    fn drop(&mut self) {
-      self.cancel_token.cancel();
+      self.pool.get_mut().cancel_token.cancel();
    }
 }
 
@@ -186,8 +306,20 @@ impl Embedder for EmbedWorker {
       self.embedder.encode_query(text).await
    }
 
+   async fn encode_queries(&self, texts: &[&str]) -> Result<Vec<QueryEmbedding>> {
+      self.embedder.encode_queries(texts).await
+   }
+
    fn is_ready(&self) -> bool {
-      self.workers.is_some()
+      !self.pool.lock().cancel_token.is_cancelled()
+   }
+
+   fn unload(&self) -> bool {
+      self.embedder.unload()
+   }
+
+   fn status(&self) -> EmbedderStatus {
+      EmbedderStatus { queue_depth: self.sender.len(), ..self.embedder.status() }
    }
 }
 
@@ -208,4 +340,12 @@ mod tests {
       assert!(result.is_ok());
       assert_eq!(result.unwrap().len(), 0);
    }
+
+   #[test]
+   fn reads_panic_messages() {
+      let payload = std::panic::catch_unwind(|| panic!("index {} out of range", 3)).unwrap_err();
+      assert_eq!(panic_message(payload.as_ref()), "index 3 out of range");
+      let payload = std::panic::catch_unwind(|| std::panic::panic_any(7_u8)).unwrap_err();
+      assert_eq!(panic_message(payload.as_ref()), "unknown panic");
+   }
 }
@@ -3,7 +3,17 @@ use std::{io, path::PathBuf, sync::Arc};
 use thiserror::Error;
 use tree_sitter::{LanguageError, WasmError};
 
-use crate::{embed::candle::EmbeddingError, store::lance::StoreError, usock::SocketError};
+#[cfg(feature = "sqlite")]
+use crate::meta::sqlite::MetaDbError;
+#[cfg(feature = "postgres")]
+use crate::store::postgres::PostgresStoreError;
+#[cfg(feature = "sqlite")]
+use crate::store::sqlite::SqliteStoreError;
+use crate::{
+   embed::candle::EmbeddingError,
+   store::{flat::FlatStoreError, lance::StoreError, qdrant::QdrantStoreError},
+   usock::SocketError,
+};
 /// Main error type for the smgrep application.
 ///
 /// This enum represents all possible errors that can occur throughout the
@@ -24,6 +34,33 @@ pub enum Error {
    #[error("store error: {0}")]
    Store(#[from] StoreError),
 
+   /// Error occurred in the flat-file store backend.
+   #[error("flat store error: {0}")]
+   Flat(#[from] FlatStoreError),
+
+   /// Error occurred in the `SQLite` store backend.
+   #[cfg(feature = "sqlite")]
+   #[error("sqlite store error: {0}")]
+   Sqlite(#[from] SqliteStoreError),
+
+   /// Error occurred in the `SQLite` metadata backend.
+   #[cfg(feature = "sqlite")]
+   #[error("metadata error: {0}")]
+   MetaDb(#[from] MetaDbError),
+
+   /// Error occurred in the Postgres store backend.
+   #[cfg(feature = "postgres")]
+   #[error("postgres store error: {0}")]
+   Postgres(#[from] PostgresStoreError),
+
+   /// Error occurred in the Qdrant store backend.
+   #[error("qdrant store error: {0}")]
+   Qdrant(#[from] QdrantStoreError),
+
+   /// Configured store backend was not compiled into this binary.
+   #[error("store backend `{0}` is not available in this build; rebuild with `--features {0}`")]
+   BackendUnavailable(&'static str),
+
    /// Error occurred during embedding generation or processing.
    #[error("embedding error: {0}")]
    Embedding(#[from] EmbeddingError),
@@ -64,6 +101,94 @@ pub enum Error {
    #[error("socket error: {0}")]
    Socket(#[from] SocketError),
 
+   /// Metadata file was written by a newer, incompatible version.
+   #[error(
+      "metadata for {store_id} uses format v{found}, but this smgrep supports up to v{supported}; \
+       upgrade smgrep or run `smgrep clean`"
+   )]
+   MetaVersionTooNew { store_id: String, found: u32, supported: u32 },
+
+   /// Store was indexed with different embedding models than configured.
+   #[error(
+      "store {store_id} was indexed with {indexed}, but the current config uses {current}; rerun \
+       with --migrate to re-index"
+   )]
+   ModelMismatch { store_id: String, indexed: String, current: String },
+
+   /// The configured store backend has no full-text index to search.
+   #[error(
+      "keyword search is not supported by the configured store backend; use lance, sqlite or \
+       postgres"
+   )]
+   KeywordSearchUnavailable,
+
+   /// Not enough free disk space to index without dipping into the reserve.
+   #[error(
+      "not enough disk space to index: {path} has {available} free but about {required} is \
+       needed; free some space or lower `min_free_space_mb`",
+      path = path.display(),
+      available = crate::util::format_size(*available),
+      required = crate::util::format_size(*required)
+   )]
+   InsufficientDiskSpace { path: PathBuf, available: u64, required: u64 },
+
+   /// Free disk space fell below the reserve while indexing.
+   #[error(
+      "free space on {path} dropped to {available}, below the {reserve} reserve; indexing \
+       stopped after the last complete batch, free some space and rerun to resume",
+      path = path.display(),
+      available = crate::util::format_size(*available),
+      reserve = crate::util::format_size(*reserve)
+   )]
+   DiskFull { path: PathBuf, available: u64, reserve: u64 },
+
+   /// The operation was cancelled before it finished.
+   #[error("operation cancelled")]
+   Cancelled,
+
+   /// A query consisted only of operators such as `-term` or `path:`.
+   #[error("query has no search terms besides operators")]
+   EmptyQuery,
+
+   /// An `--include` or `--exclude` glob could not be compiled.
+   #[error("invalid glob `{glob}`: {reason}")]
+   InvalidGlob { glob: String, reason: String },
+
+   /// `--crate` named a package that is not a member of the workspace.
+   #[error("`{name}` is not a crate in this workspace (available: {available})")]
+   UnknownCrate { name: String, available: String },
+
+   /// `smgrep similar` was given lines with no code, or past the end of the
+   /// file.
+   #[error("no code in lines {start}-{end} of {path}", path = path.display())]
+   EmptyRange { path: PathBuf, start: u32, end: u32 },
+
+   /// A `smgrep check` policy file could not be parsed.
+   #[error("invalid policy file {path}: {reason}", path = path.display())]
+   InvalidPolicy { path: PathBuf, reason: String },
+
+   /// A `smgrep eval` suite could not be parsed.
+   #[error("invalid eval suite {path}: {reason}", path = path.display())]
+   InvalidEvalSuite { path: PathBuf, reason: String },
+
+   /// A user hook command failed or timed out.
+   #[error("{hook} hook failed: {reason}")]
+   Hook { hook: &'static str, reason: String },
+
+   /// A downloaded model file or grammar did not match its checksum, even
+   /// after downloading it again.
+   #[error("checksum mismatch for {artifact}: expected {expected}, got {actual}")]
+   ChecksumMismatch { artifact: String, expected: String, actual: String },
+
+   /// `smgrep setup` could not download, verify or try out everything it
+   /// needs.
+   #[error("setup failed: {0}")]
+   Setup(String),
+
+   /// Index archive export or import failed.
+   #[error("archive error: {0}")]
+   Archive(#[from] ArchiveError),
+
    /// Server error occurred during a specific operation.
    #[error("server error during {op}: {reason}")]
    Server { op: &'static str, reason: String },
@@ -80,6 +205,18 @@ pub enum Error {
    #[error("failed to spawn daemon: {0}")]
    DaemonSpawn(#[source] io::Error),
 
+   /// A daemon is serving the store, and would overwrite the changes.
+   #[error("a daemon is serving store {0}; stop it with `smgrep stop` first")]
+   DaemonRunning(String),
+
+   /// Another process held a store's lock for longer than
+   /// `lock_timeout_secs`.
+   #[error(
+      "store {store_id} is locked by {holder}; retry once it finishes or raise `lock_timeout_secs`",
+      holder = holder.map_or_else(|| "another process".to_string(), |pid| format!("process {pid}"))
+   )]
+   StoreLocked { store_id: String, holder: Option<u32> },
+
    /// Failed to execute a Claude command.
    #[error("failed to run claude command: {0}")]
    ClaudeSpawn(#[source] io::Error),
@@ -142,6 +279,57 @@ pub enum IpcError {
    /// Failed to write data to the IPC channel.
    #[error("failed to write: {0}")]
    Write(#[source] io::Error),
+
+   /// A well-formed request carried out-of-bounds values.
+   #[error("invalid request: {0}")]
+   InvalidRequest(String),
+}
+
+impl IpcError {
+   /// Checks whether the offending frame was fully consumed, leaving the
+   /// connection usable for an error reply.
+   pub const fn is_malformed_frame(&self) -> bool {
+      matches!(self, Self::MessageTooLarge(_) | Self::Deserialize(_) | Self::InvalidRequest(_))
+   }
+}
+
+/// Errors that can occur while exporting or importing index archives.
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+   /// The requested store has no data or metadata on disk.
+   #[error("store not found: {0}")]
+   StoreNotFound(String),
+
+   /// A store with the target ID already exists.
+   #[error("store already exists: {0} (use --force to replace it)")]
+   StoreExists(String),
+
+   /// The archive does not start with a manifest entry.
+   #[error("archive is missing its manifest")]
+   MissingManifest,
+
+   /// The archive has no metadata entry.
+   #[error("archive is missing store metadata")]
+   MissingMeta,
+
+   /// The archive was written by a newer, incompatible version.
+   #[error("unsupported archive format version {0}")]
+   UnsupportedVersion(u32),
+
+   /// The archive was built with different embedding models.
+   #[error(
+      "archive built with {archive}, but current config uses {current} (use --force to import \
+       anyway)"
+   )]
+   ModelMismatch { archive: String, current: String },
+
+   /// The store ID to import into is not a plain file name.
+   #[error("invalid store id: {0:?}")]
+   InvalidStoreId(String),
+
+   /// An archive entry has an unexpected or unsafe path.
+   #[error("invalid archive entry: {path}", path = _0.display())]
+   InvalidEntry(PathBuf),
 }
 
 /// Errors that can occur during code chunking operations.
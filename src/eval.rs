@@ -0,0 +1,324 @@
+//! Retrieval evaluation.
+//!
+//! An eval suite is a YAML list of queries, each with the code it should
+//! find. Scoring where that code ranks in the search results gives recall@k
+//! and mean reciprocal rank, so that chunking and ranking changes can be
+//! measured against a fixed set of questions instead of a few spot checks.
+//!
+//! ```yaml
+//! - query: where are database connections opened
+//!   expect: [src/db.rs:10-40]
+//! - query: load the config file
+//!   expect: [src/config.rs, src/cli/args.rs:12]
+//! ```
+
+use std::{
+   fs,
+   path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, error::Error, types::SearchResult};
+
+/// A query and the code it should find.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvalCase {
+   pub query:  String,
+   /// Code answering the query; each is scored separately for recall.
+   pub expect: Vec<Target>,
+}
+
+/// Code a query should find: a whole file, given as `<path>`, or lines of
+/// one, given as `<path>:<line>` or `<path>:<start>-<end>` (1-based and
+/// inclusive). Paths are relative to the searched directory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Target {
+   pub path:  PathBuf,
+   pub lines: Option<(u32, u32)>,
+}
+
+impl TryFrom<String> for Target {
+   type Error = String;
+
+   fn try_from(s: String) -> Result<Self, String> {
+      let Some((path, lines)) = s
+         .rsplit_once(':')
+         .filter(|(_, lines)| lines.chars().all(|c| c.is_ascii_digit() || c == '-'))
+      else {
+         return Ok(Self { path: PathBuf::from(s), lines: None });
+      };
+      let invalid = || format!("invalid target `{s}`: expected <path>:<start>-<end>");
+      let (start, end) = lines.split_once('-').unwrap_or((lines, lines));
+      let start: u32 = start.parse().map_err(|_| invalid())?;
+      let end: u32 = end.parse().map_err(|_| invalid())?;
+      if path.is_empty() || start == 0 || end < start {
+         return Err(invalid());
+      }
+      Ok(Self { path: PathBuf::from(path), lines: Some((start, end)) })
+   }
+}
+
+impl Target {
+   /// Whether `result`, a chunk of a file under `root`, is this code.
+   fn matches(&self, result: &SearchResult, root: &Path) -> bool {
+      let path = result.path.strip_prefix(root).unwrap_or(&result.path);
+      path == self.path
+         && self.lines.is_none_or(|(start, end)| {
+            result.start_line < end && start <= result.start_line + result.num_lines
+         })
+   }
+}
+
+/// Reads an eval suite from a YAML file.
+pub fn load_suite(path: &Path) -> Result<Vec<EvalCase>> {
+   let invalid = |reason: String| Error::InvalidEvalSuite { path: path.to_path_buf(), reason };
+   let text = fs::read_to_string(path)?;
+   let cases: Vec<EvalCase> = serde_yaml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+   if let Some(case) = cases.iter().find(|case| case.expect.is_empty()) {
+      return Err(invalid(format!("`{}` expects no results", case.query)));
+   }
+   Ok(cases)
+}
+
+/// Where the code expected by one query ranked.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryScore {
+   pub query: String,
+   /// 1-based rank of the first result matching each target, `None` when no
+   /// result did.
+   pub ranks: Vec<Option<usize>>,
+   /// Why the search failed; its targets all count as missed.
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub error: Option<String>,
+}
+
+impl QueryScore {
+   /// Scores `results`, chunks of files under `root` in ranked order.
+   pub fn new(case: &EvalCase, results: &[SearchResult], root: &Path) -> Self {
+      let ranks = case
+         .expect
+         .iter()
+         .map(|target| {
+            results
+               .iter()
+               .position(|result| target.matches(result, root))
+               .map(|i| i + 1)
+         })
+         .collect();
+      Self { query: case.query.clone(), ranks, error: None }
+   }
+
+   /// Scores a query whose search failed with `error`.
+   pub fn failed(case: &EvalCase, error: String) -> Self {
+      Self { query: case.query.clone(), ranks: vec![None; case.expect.len()], error: Some(error) }
+   }
+
+   /// Rank of the first result matching any target.
+   pub fn first_hit(&self) -> Option<usize> {
+      self.ranks.iter().flatten().min().copied()
+   }
+
+   /// Share of the targets found in the top `k` results.
+   pub fn recall_at(&self, k: usize) -> f64 {
+      let found = self
+         .ranks
+         .iter()
+         .flatten()
+         .filter(|&&rank| rank <= k)
+         .count();
+      found as f64 / self.ranks.len().max(1) as f64
+   }
+}
+
+/// Mean recall at one cutoff.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RecallAt {
+   pub k:      usize,
+   pub recall: f64,
+}
+
+/// Scores of a whole suite.
+#[derive(Debug, Serialize)]
+pub struct EvalReport {
+   /// Mean recall@k over the queries, for each requested k.
+   pub recall:  Vec<RecallAt>,
+   /// Mean reciprocal rank of each query's first hit, 0 for a miss.
+   pub mrr:     f64,
+   pub queries: Vec<QueryScore>,
+}
+
+impl EvalReport {
+   pub fn new(queries: Vec<QueryScore>, ks: &[usize]) -> Self {
+      let mean = |score: &dyn Fn(&QueryScore) -> f64| {
+         queries.iter().map(score).sum::<f64>() / queries.len().max(1) as f64
+      };
+      let recall = ks
+         .iter()
+         .map(|&k| RecallAt { k, recall: mean(&|query| query.recall_at(k)) })
+         .collect();
+      let mrr = mean(&|query| query.first_hit().map_or(0.0, |rank| 1.0 / rank as f64));
+      Self { recall, mrr, queries }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::sync::Arc;
+
+   use tokio_util::sync::CancellationToken;
+
+   use super::*;
+   use crate::{
+      Str,
+      chunker::{Chunker, FallbackChunker},
+      embed::{Embedder, mock::MockEmbedder},
+      meta::FileHash,
+      search::{Retrieval, SearchEngine, SearchFilter},
+      store::{FlatStore, Store},
+      types::VectorRecord,
+   };
+
+   fn result(path: &str, start_line: u32, num_lines: u32) -> SearchResult {
+      SearchResult {
+         path: PathBuf::from(path),
+         content: Str::default(),
+         score: 1.0,
+         start_line,
+         num_lines,
+         chunk_type: None,
+         is_anchor: None,
+         breakdown: None,
+         bridges: None,
+         language: None,
+         context_prev: None,
+         context_next: None,
+         linked: Vec::new(),
+      }
+   }
+
+   fn case(query: &str, expect: &[&str]) -> EvalCase {
+      EvalCase {
+         query:  query.to_string(),
+         expect: expect
+            .iter()
+            .map(|target| Target::try_from((*target).to_string()).unwrap())
+            .collect(),
+      }
+   }
+
+   #[test]
+   fn parses_targets() {
+      let file = Target::try_from("src/lib.rs".to_string()).unwrap();
+      assert_eq!(file, Target { path: PathBuf::from("src/lib.rs"), lines: None });
+      let range = Target::try_from("src/lib.rs:10-24".to_string()).unwrap();
+      assert_eq!(range.lines, Some((10, 24)));
+      let line = Target::try_from("C:/repo/main.rs:7".to_string()).unwrap();
+      assert_eq!(line, Target { path: PathBuf::from("C:/repo/main.rs"), lines: Some((7, 7)) });
+
+      for invalid in ["src/lib.rs:0-3", "src/lib.rs:9-3", ":1-2", "a.rs:-"] {
+         assert!(Target::try_from(invalid.to_string()).is_err(), "{invalid}");
+      }
+   }
+
+   #[test]
+   fn scores_recall_and_mrr() {
+      let root = Path::new("/repo");
+      let results =
+         [result("/repo/a.rs", 0, 10), result("/repo/b.rs", 30, 10), result("/repo/c.rs", 0, 10)];
+      let queries = vec![
+         // Lines 31-40 of b.rs are the second result
+         QueryScore::new(&case("b", &["b.rs:35-50"]), &results, root),
+         // c.rs is third, lines 20-30 of a.rs are not returned
+         QueryScore::new(&case("ac", &["a.rs:20-30", "c.rs"]), &results, root),
+         QueryScore::new(&case("d", &["d.rs"]), &results, root),
+         QueryScore::failed(&case("e", &["e.rs"]), "cancelled".to_string()),
+      ];
+      assert_eq!(queries[0].ranks, [Some(2)]);
+      assert_eq!(queries[1].ranks, [None, Some(3)]);
+
+      let report = EvalReport::new(queries, &[1, 3]);
+      assert_eq!(report.recall[0].recall, 0.0);
+      assert!((report.recall[1].recall - 1.5 / 4.0).abs() < 1e-9);
+      assert!((report.mrr - (1.0 / 2.0 + 1.0 / 3.0) / 4.0).abs() < 1e-9);
+   }
+
+   /// Indexes every file of `corpus` into `store` as the sync would, without
+   /// anchor chunks.
+   async fn index_corpus(corpus: &Path, store: &dyn Store, embedder: &dyn Embedder) {
+      let mut records = Vec::new();
+      for entry in fs::read_dir(corpus).unwrap() {
+         let path = Arc::new(entry.unwrap().path());
+         let content = Str::from(fs::read_to_string(&*path).unwrap());
+         let hash = FileHash::sum(content.as_bytes());
+         let chunks = FallbackChunker.chunk(&content, &path).await.unwrap();
+         let texts: Vec<Str> = chunks.iter().map(|chunk| chunk.content.clone()).collect();
+         let embeddings = embedder.compute_hybrid(&texts).await.unwrap();
+         for (i, (chunk, embedding)) in chunks.into_iter().zip(embeddings).enumerate() {
+            records.push(VectorRecord {
+               id: format!("{}:{i}", path.display()),
+               path: Arc::clone(&path),
+               hash,
+               chunk_hash: FileHash::sum(chunk.content.as_bytes()),
+               content: chunk.content,
+               start_line: chunk.start_line as u32,
+               end_line: chunk.end_line as u32,
+               chunk_index: Some(i as u32),
+               is_anchor: Some(false),
+               chunk_type: chunk.chunk_type,
+               context_prev: None,
+               context_next: None,
+               vector: embedding.dense,
+               colbert: embedding.colbert,
+               colbert_scale: embedding.colbert_scale,
+               indexed_at: Some(0),
+               crate_name: None,
+               bridges: None,
+               language: None,
+            });
+         }
+      }
+      store.insert_batch("eval", records).await.unwrap();
+   }
+
+   /// Runs the benchmark suite in `tests/fixtures/eval` through search.
+   #[tokio::test]
+   async fn benchmark_corpus() {
+      let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/eval");
+      let corpus = fixture.join("corpus");
+      let cases = load_suite(&fixture.join("suite.yaml")).unwrap();
+
+      let dir = tempfile::TempDir::new().unwrap();
+      let store: Arc<dyn Store> =
+         Arc::new(FlatStore::with_data_dir(dir.path().to_path_buf()).unwrap());
+      let embedder: Arc<dyn Embedder> = Arc::new(MockEmbedder::default());
+      index_corpus(&corpus, &*store, &*embedder).await;
+
+      let engine = SearchEngine::new(store, embedder);
+      let queries: Vec<String> = cases.iter().map(|case| case.query.clone()).collect();
+      let responses = engine
+         .search_batch(
+            "eval",
+            &queries,
+            10,
+            1,
+            SearchFilter::default(),
+            Retrieval { rerank: true, ..Retrieval::default() },
+            &CancellationToken::new(),
+         )
+         .await
+         .unwrap();
+      let scores = cases
+         .iter()
+         .zip(responses)
+         .map(|(case, response)| QueryScore::new(case, &response.unwrap().results, &corpus))
+         .collect();
+
+      let report = EvalReport::new(scores, &[1, 5]);
+      assert!(report.recall[0].recall >= 0.8, "{report:#?}");
+      assert!(report.recall[1].recall >= 0.95, "{report:#?}");
+      assert!(report.mrr >= 0.85, "{report:#?}");
+   }
+}
@@ -1,20 +1,25 @@
 //! File discovery for local file systems and git repositories.
 
 use std::{
+   collections::HashSet,
    fs,
+   io::Read,
    path::{Path, PathBuf},
    process::Command,
 };
 
 use git2::Repository;
 
+use super::IgnorePatterns;
 use crate::{
+   config,
    error::{Error, Result},
-   grammar::EXTENSION_MAP,
+   grammar::GrammarManager,
 };
 
 /// Additional extensions for text-based files without tree-sitter grammar
-/// support. Extensions with grammar support are derived from [`EXTENSION_MAP`].
+/// support. Extensions with grammar support are derived from
+/// [`EXTENSION_MAP`](crate::grammar::EXTENSION_MAP).
 const ADDITIONAL_EXTENSIONS: &[&str] = &[
    "swift",
    "vue",
@@ -46,9 +51,6 @@ const ADDITIONAL_EXTENSIONS: &[&str] = &[
    "cr",
 ];
 
-/// Maximum file size in bytes (1 MB) for files to be included in discovery.
-const MAX_FILE_SIZE: u64 = 1024 * 1024;
-
 /// Abstraction for file system operations to discover source files.
 pub trait FileSystem {
    /// Returns an iterator of all discoverable files under the given root path.
@@ -68,49 +70,37 @@ impl LocalFileSystem {
       let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
       let filename = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
 
-      // Check grammar-supported extensions first
-      EXTENSION_MAP.iter().any(|(e, _)| ext.eq_ignore_ascii_case(e))
+      // Check grammar-supported and configured extensions first
+      (!ext.is_empty() && GrammarManager::extension_to_language(ext).is_some())
          // Then additional text-based extensions
          || ADDITIONAL_EXTENSIONS.iter().any(|&e| ext.eq_ignore_ascii_case(e))
          // Special filename patterns
          || filename.eq_ignore_ascii_case("dockerfile")
          || filename.eq_ignore_ascii_case("makefile")
+         // Extensionless scripts
+         || (path.extension().is_none() && has_shebang(path))
    }
 
-   fn should_include_file(path: &Path, metadata: Option<&fs::Metadata>) -> bool {
-      if !Self::is_supported_extension(path) {
-         return false;
-      }
-
-      if let Some(filename) = path.file_name().and_then(|f| f.to_str())
-         && filename.starts_with('.')
-      {
-         return false;
-      }
+   /// Whether `path` names a file worth indexing. Size and content are checked
+   /// when the file is read; see [`super::sniff`].
+   fn should_include_file(path: &Path) -> bool {
+      let hidden = path
+         .file_name()
+         .and_then(|f| f.to_str())
+         .is_some_and(|filename| filename.starts_with('.'));
 
-      // Check file size if metadata provided, otherwise check via fs
-      match metadata {
-         Some(m) => m.len() <= MAX_FILE_SIZE,
-         None => fs::metadata(path)
-            .map(|m| m.len() <= MAX_FILE_SIZE)
-            .unwrap_or(true),
-      }
+      !hidden && Self::is_supported_extension(path)
    }
 
-   fn get_git_files(root: &Path) -> Result<Vec<PathBuf>> {
+   fn get_git_files(root: &Path, collector: &mut Collector) -> Result<()> {
       let repo = Repository::open(root).map_err(Error::OpenRepository)?;
 
-      let mut files = Vec::new();
-
       let index = repo.index().map_err(Error::ReadIndex)?;
 
       for entry in index.iter() {
          let path_bytes = entry.path.as_slice();
          if let Ok(path_str) = std::str::from_utf8(path_bytes) {
-            let file_path = root.join(path_str);
-            if file_path.exists() && Self::should_include_file(&file_path, None) {
-               files.push(file_path);
-            }
+            collector.visit_listed(root.join(path_str));
          }
       }
 
@@ -122,29 +112,120 @@ impl LocalFileSystem {
          && output.status.success()
       {
          for line in String::from_utf8_lossy(&output.stdout).lines() {
-            let file_path = root.join(line);
-            if file_path.exists() && Self::should_include_file(&file_path, None) {
-               files.push(file_path);
-            }
+            collector.visit_listed(root.join(line));
          }
       }
 
-      Ok(files)
+      Ok(())
    }
 
    fn is_git_repository(path: &Path) -> bool {
       path.join(".git").exists()
    }
+}
+
+impl FileSystem for LocalFileSystem {
+   fn get_files(&self, root: &Path) -> Result<Box<dyn Iterator<Item = PathBuf>>> {
+      let mut collector = Collector::new(root, config::get().follow_symlinks);
+      if Repository::open(root).is_ok() {
+         Self::get_git_files(root, &mut collector)?;
+      } else {
+         collector.walk_dir(root, root);
+      }
+
+      Ok(Box::new(collector.files.into_iter()))
+   }
+}
+
+/// Accumulates discovered files for a single root.
+///
+/// Directories are tracked by canonical path so symlink cycles terminate, and
+/// files reachable through several links, symbolic or hard, are reported
+/// once. A file inside the root is reported at its real location under the
+/// root; a link target outside the root is reported at the first link path
+/// that reached it. Paths matched
+/// by [`IgnorePatterns`] are skipped, and ignored directories are not entered.
+struct Collector {
+   root:            PathBuf,
+   canonical_root:  PathBuf,
+   follow_symlinks: bool,
+   ignore:          IgnorePatterns,
+   visited_dirs:    HashSet<PathBuf>,
+   seen_files:      HashSet<FileId>,
+   files:           Vec<PathBuf>,
+}
+
+impl Collector {
+   fn new(root: &Path, follow_symlinks: bool) -> Self {
+      let canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+      Self {
+         root: root.to_path_buf(),
+         visited_dirs: HashSet::from([canonical_root.clone()]),
+         canonical_root,
+         follow_symlinks,
+         ignore: IgnorePatterns::new(root),
+         seen_files: HashSet::new(),
+         files: Vec::new(),
+      }
+   }
+
+   /// Records `path` unless the file it resolves to was already seen.
+   fn add_file(&mut self, path: PathBuf) {
+      let Ok(canonical) = fs::canonicalize(&path) else {
+         return;
+      };
+      let Some(id) = file_id(&canonical) else {
+         return;
+      };
+      if !self.seen_files.insert(id) {
+         return;
+      }
+
+      let path = match canonical.strip_prefix(&self.canonical_root) {
+         Ok(rel) => self.root.join(rel),
+         Err(_) => path,
+      };
+      self.files.push(path);
+   }
 
-   fn get_walkdir_files(root: &Path) -> Vec<PathBuf> {
-      Self::get_walkdir_files_recursive(root, root)
+   /// Marks a directory as entered, returning `false` if it was already
+   /// walked (through a symlink cycle or a second link).
+   fn enter_dir(&mut self, dir: &Path) -> bool {
+      fs::canonicalize(dir).is_ok_and(|canonical| self.visited_dirs.insert(canonical))
    }
 
-   fn get_walkdir_files_recursive(dir: &Path, root: &Path) -> Vec<PathBuf> {
-      let mut files = Vec::new();
+   /// Handles a path listed by git, which may be a symlink to a directory.
+   fn visit_listed(&mut self, path: PathBuf) {
+      let Ok(link_meta) = fs::symlink_metadata(&path) else {
+         return;
+      };
+      if self.ignore.is_ignored(&path) {
+         return;
+      }
 
+      if link_meta.is_symlink() {
+         if !self.follow_symlinks {
+            return;
+         }
+         let Ok(target_meta) = fs::metadata(&path) else {
+            return;
+         };
+         if target_meta.is_dir() {
+            if self.enter_dir(&path) {
+               self.walk_dir(&path, &path);
+            }
+            return;
+         }
+      }
+
+      if LocalFileSystem::should_include_file(&path) {
+         self.add_file(path);
+      }
+   }
+
+   fn walk_dir(&mut self, dir: &Path, root: &Path) {
       let Ok(entries) = fs::read_dir(dir) else {
-         return files;
+         return;
       };
 
       for entry in entries.filter_map(|e| e.ok()) {
@@ -155,43 +236,67 @@ impl LocalFileSystem {
          {
             continue;
          }
+         if self.ignore.is_ignored(&path) {
+            continue;
+         }
 
-         let Ok(file_type) = entry.file_type() else {
+         let Ok(mut file_type) = entry.file_type() else {
             continue;
          };
 
+         if file_type.is_symlink() {
+            if !self.follow_symlinks {
+               continue;
+            }
+            let Ok(target_meta) = fs::metadata(&path) else {
+               continue;
+            };
+            file_type = target_meta.file_type();
+         }
+
          if file_type.is_dir() {
-            if path != root && Self::is_git_repository(&path) {
-               if let Ok(git_files) = Self::get_git_files(&path) {
-                  files.extend(git_files);
-               } else {
-                  files.extend(Self::get_walkdir_files_recursive(&path, &path));
+            if !self.enter_dir(&path) {
+               continue;
+            }
+            if path != root && LocalFileSystem::is_git_repository(&path) {
+               if LocalFileSystem::get_git_files(&path, self).is_err() {
+                  self.walk_dir(&path, &path);
                }
             } else {
-               files.extend(Self::get_walkdir_files_recursive(&path, root));
+               self.walk_dir(&path, root);
             }
-         } else if file_type.is_file()
-            && let Ok(metadata) = entry.metadata()
-            && Self::should_include_file(&path, Some(&metadata))
-         {
-            files.push(path);
+         } else if file_type.is_file() && LocalFileSystem::should_include_file(&path) {
+            self.add_file(path);
          }
       }
-
-      files
    }
 }
 
-impl FileSystem for LocalFileSystem {
-   fn get_files(&self, root: &Path) -> Result<Box<dyn Iterator<Item = PathBuf>>> {
-      let files = if Repository::open(root).is_ok() {
-         Self::get_git_files(root)?
-      } else {
-         Self::get_walkdir_files(root)
-      };
+/// Whether the file starts with `#!`, as scripts without an extension do.
+fn has_shebang(path: &Path) -> bool {
+   let mut magic = [0; 2];
+   fs::File::open(path)
+      .and_then(|mut file| file.read_exact(&mut magic))
+      .is_ok_and(|()| magic == *b"#!")
+}
 
-      Ok(Box::new(files.into_iter()))
-   }
+/// Identity of a file: device and inode on Unix, so hard links to one file
+/// match; the canonical path elsewhere.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(unix)]
+fn file_id(canonical: &Path) -> Option<FileId> {
+   use std::os::unix::fs::MetadataExt;
+
+   fs::metadata(canonical).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(canonical: &Path) -> Option<FileId> {
+   Some(canonical.to_path_buf())
 }
 
 impl Default for LocalFileSystem {
@@ -214,7 +319,83 @@ mod tests {
 
    #[test]
    fn hidden_files_filtered() {
-      assert!(!LocalFileSystem::should_include_file(Path::new(".hidden.rs"), None));
-      assert!(LocalFileSystem::should_include_file(Path::new("visible.rs"), None));
+      assert!(!LocalFileSystem::should_include_file(Path::new(".hidden.rs")));
+      assert!(LocalFileSystem::should_include_file(Path::new("visible.rs")));
+   }
+
+   #[cfg(unix)]
+   #[test]
+   fn symlinked_dirs_followed_once_without_cycles() {
+      use std::os::unix::fs::symlink;
+
+      let temp = tempfile::TempDir::new().unwrap();
+      let root = temp.path();
+      fs::create_dir_all(root.join("src/nested")).unwrap();
+      fs::write(root.join("src/lib.rs"), "fn a() {}").unwrap();
+      fs::write(root.join("src/nested/mod.rs"), "fn b() {}").unwrap();
+      symlink(root.join("src"), root.join("alias")).unwrap();
+      symlink(root, root.join("src/nested/loop")).unwrap();
+      symlink(root.join("src/lib.rs"), root.join("linked.rs")).unwrap();
+
+      let outside = tempfile::TempDir::new().unwrap();
+      fs::write(outside.path().join("ext.rs"), "fn c() {}").unwrap();
+      symlink(outside.path(), root.join("vendor")).unwrap();
+
+      let mut collector = Collector::new(root, true);
+      collector.walk_dir(root, root);
+      let mut files = collector.files;
+      files.sort();
+
+      let mut expected =
+         vec![root.join("src/lib.rs"), root.join("src/nested/mod.rs"), root.join("vendor/ext.rs")];
+      expected.sort();
+      assert_eq!(files, expected);
+
+      let mut collector = Collector::new(root, false);
+      collector.walk_dir(root, root);
+      assert_eq!(collector.files.len(), 2);
+   }
+
+   #[cfg(unix)]
+   #[test]
+   fn hard_links_reported_once() {
+      let temp = tempfile::TempDir::new().unwrap();
+      let root = temp.path();
+      fs::create_dir_all(root.join("src")).unwrap();
+      fs::write(root.join("src/lib.rs"), "fn a() {}").unwrap();
+      fs::hard_link(root.join("src/lib.rs"), root.join("copy.rs")).unwrap();
+      fs::write(root.join("other.rs"), "fn a() {}").unwrap();
+
+      let mut collector = Collector::new(root, true);
+      collector.walk_dir(root, root);
+      assert_eq!(collector.files.len(), 2);
+      assert!(collector.files.contains(&root.join("other.rs")));
+   }
+
+   #[test]
+   fn ignored_paths_skipped_outside_git() {
+      let temp = tempfile::TempDir::new().unwrap();
+      let root = temp.path();
+      fs::create_dir_all(root.join("app/node_modules/pkg")).unwrap();
+      fs::create_dir_all(root.join("app/gen")).unwrap();
+      fs::write(root.join("app/main.js"), "main()").unwrap();
+      fs::write(root.join("app/node_modules/pkg/index.js"), "dep()").unwrap();
+      fs::write(root.join("app/gen/out.js"), "gen()").unwrap();
+      fs::write(root.join("app/.gitignore"), "gen/\n").unwrap();
+
+      let mut collector = Collector::new(root, false);
+      collector.walk_dir(root, root);
+      assert_eq!(collector.files, vec![root.join("app/main.js")]);
+   }
+
+   #[test]
+   fn extensionless_scripts_included_by_shebang() {
+      let temp = tempfile::TempDir::new().unwrap();
+      let root = temp.path();
+      fs::write(root.join("deploy"), "#!/usr/bin/env bash\necho hi\n").unwrap();
+      fs::write(root.join("LICENSE"), "MIT License\n").unwrap();
+
+      assert!(LocalFileSystem::should_include_file(&root.join("deploy")));
+      assert!(!LocalFileSystem::should_include_file(&root.join("LICENSE")));
    }
 }
@@ -0,0 +1,196 @@
+//! Detecting the encoding of non-UTF-8 sources and transcoding them to UTF-8.
+//!
+//! Files are read as UTF-8 when they are valid UTF-8 or start with a byte
+//! order mark. Otherwise each legacy encoding below is tried in turn and kept
+//! if the file decodes cleanly and mostly into characters of the script the
+//! encoding is for; anything else is read as Windows-1252, a superset of
+//! Latin-1. Transcoding keeps line breaks, so line numbers match the original
+//! file.
+
+use encoding_rs::{
+   DecoderResult, EUC_JP, EUC_KR, Encoding, GBK, SHIFT_JIS, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252,
+};
+
+use crate::Str;
+
+/// Legacy encoding tried when a file is not UTF-8.
+struct Candidate {
+   encoding: &'static Encoding,
+   /// Characters of the script the encoding is for.
+   script:   fn(char) -> bool,
+   /// Characters that text in this encoding hardly contains, but that text in
+   /// a similar encoding decodes to.
+   foreign:  fn(char) -> bool,
+   /// Whether the encoding's characters are pairs of bytes above 0x7F.
+   paired:   bool,
+}
+
+/// Candidates in the order they are tried.
+const CANDIDATES: &[Candidate] = &[
+   Candidate { encoding: SHIFT_JIS, script: is_kana, foreign: |_| false, paired: false },
+   Candidate { encoding: EUC_JP, script: is_kana, foreign: |_| false, paired: false },
+   // Chinese read as EUC-KR is a mix of Hangul and Hanja; Korean text has
+   // almost no Hanja.
+   Candidate { encoding: EUC_KR, script: is_hangul, foreign: is_han, paired: true },
+   Candidate { encoding: GBK, script: is_han, foreign: |_| false, paired: true },
+];
+
+/// Share of the non-ASCII characters that must belong to the candidate's
+/// script.
+const MIN_SCRIPT_SHARE: f32 = 0.3;
+
+/// Share of the non-ASCII characters that may be foreign to the candidate.
+const MAX_FOREIGN_SHARE: f32 = 0.05;
+
+/// Share of the bytes above 0x7F that must sit next to another, for
+/// encodings whose characters are such pairs. Latin-1 text has isolated high
+/// bytes, which such encodings would otherwise read as ideographs.
+const MIN_PAIRED_SHARE: f32 = 0.8;
+
+/// Whether a file starts with a UTF-16 byte order mark, so that its NUL bytes
+/// are text.
+pub fn is_utf16(bytes: &[u8]) -> bool {
+   Encoding::for_bom(bytes)
+      .is_some_and(|(encoding, _)| encoding == UTF_16LE || encoding == UTF_16BE)
+}
+
+/// Finds the encoding of `bytes`, which may stop in the middle of a
+/// character. `hint` is the encoding a previous version of the file had; it
+/// is kept while the content still decodes cleanly with it.
+pub fn detect(bytes: &[u8], hint: Option<&str>) -> &'static Encoding {
+   if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+      return encoding;
+   }
+   if !std::str::from_utf8(bytes).is_err_and(|e| e.error_len().is_some()) {
+      return UTF_8;
+   }
+   if let Some(encoding) = hint.and_then(|label| Encoding::for_label(label.as_bytes()))
+      && encoding != UTF_8
+      && decode_strict(encoding, bytes).is_some()
+   {
+      return encoding;
+   }
+
+   let high = bytes.iter().filter(|b| !b.is_ascii()).count();
+   let paired = bytes
+      .iter()
+      .enumerate()
+      .filter(|&(i, b)| {
+         !b.is_ascii()
+            && ((i > 0 && !bytes[i - 1].is_ascii())
+               || bytes.get(i + 1).is_some_and(|n| !n.is_ascii()))
+      })
+      .count();
+   let paired_share = paired as f32 / high.max(1) as f32;
+
+   CANDIDATES
+      .iter()
+      .find(|candidate| {
+         if candidate.paired && paired_share < MIN_PAIRED_SHARE {
+            return false;
+         }
+         decode_strict(candidate.encoding, bytes).is_some_and(|text| {
+            let non_ascii = text.chars().filter(|c| !c.is_ascii()).count().max(1) as f32;
+            let share = |class: fn(char) -> bool| {
+               text.chars().filter(|&c| class(c)).count() as f32 / non_ascii
+            };
+            share(candidate.script) >= MIN_SCRIPT_SHARE
+               && share(candidate.foreign) <= MAX_FOREIGN_SHARE
+         })
+      })
+      .map_or(WINDOWS_1252, |candidate| candidate.encoding)
+}
+
+/// Decodes `bytes` to UTF-8 text, returning the encoding that was used; see
+/// [`detect`] for `hint`.
+pub fn decode(bytes: &[u8], hint: Option<&str>) -> (Str, &'static Encoding) {
+   let encoding = detect(bytes, hint);
+   if encoding == UTF_8 {
+      let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+      return (Str::from_utf8_lossy(bytes), encoding);
+   }
+   let (text, encoding, _) = encoding.decode(bytes);
+   (Str::from_string(text.into_owned()), encoding)
+}
+
+/// Name to record for a file's encoding, or `None` for UTF-8.
+pub fn label(encoding: &'static Encoding) -> Option<&'static str> {
+   (encoding != UTF_8).then(|| encoding.name())
+}
+
+/// Decodes `bytes` without a BOM, or `None` if they are malformed. A
+/// character cut off at the end is not an error.
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+   let mut decoder = encoding.new_decoder_without_bom_handling();
+   let mut text =
+      String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(bytes.len())?);
+   let (result, _) = decoder.decode_to_string_without_replacement(bytes, &mut text, false);
+   matches!(result, DecoderResult::InputEmpty).then_some(text)
+}
+
+const fn is_kana(c: char) -> bool {
+   matches!(c, '\u{3040}'..='\u{30FF}')
+}
+
+const fn is_hangul(c: char) -> bool {
+   matches!(c, '\u{AC00}'..='\u{D7AF}' | '\u{3130}'..='\u{318F}')
+}
+
+const fn is_han(c: char) -> bool {
+   matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{3000}'..='\u{303F}')
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn encoded(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+      encoding.encode(text).0.into_owned()
+   }
+
+   #[test]
+   fn detects_legacy_encodings() {
+      let cases = [
+         (SHIFT_JIS, "// ファイルを読み込みます\nfn read() {}\n"),
+         (EUC_JP, "// ファイルを読み込みます\nfn read() {}\n"),
+         (EUC_KR, "// 파일을 읽습니다\nfn read() {}\n"),
+         (GBK, "// 这是一个读取文件内容的函数\nfn read() {}\n"),
+         (WINDOWS_1252, "# Créé par José, déjà testé\nprint('olé')\n"),
+      ];
+      for (encoding, text) in cases {
+         let bytes = encoded(encoding, text);
+         assert_eq!(detect(&bytes, None), encoding, "{}", encoding.name());
+         let (decoded, used) = decode(&bytes, None);
+         assert_eq!(decoded.as_str(), text);
+         assert_eq!(used, encoding);
+      }
+   }
+
+   #[test]
+   fn utf8_and_boms() {
+      let text = "fn main() { println!(\"héllo\"); }\n";
+      assert_eq!(decode(text.as_bytes(), None).1, UTF_8);
+      assert_eq!(
+         decode(&[b"\xEF\xBB\xBF", text.as_bytes()].concat(), None)
+            .0
+            .as_str(),
+         text
+      );
+      // Cut in the middle of "é".
+      assert_eq!(detect(&text.as_bytes()[..24], None), UTF_8);
+
+      let mut utf16 = vec![0xff, 0xfe];
+      utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+      assert!(is_utf16(&utf16));
+      assert_eq!(decode(&utf16, None), (Str::copy_from_str(text), UTF_16LE));
+   }
+
+   #[test]
+   fn hint_kept_while_it_decodes() {
+      let bytes = encoded(WINDOWS_1252, "caf\u{e9}\n");
+      assert_eq!(detect(&bytes, Some("Shift_JIS")), WINDOWS_1252);
+      let bytes = encoded(SHIFT_JIS, "// コメント\n");
+      assert_eq!(detect(&bytes, Some("EUC-JP")), SHIFT_JIS);
+      assert_eq!(detect(&bytes, Some("Shift_JIS")), SHIFT_JIS);
+   }
+}
@@ -1,8 +1,16 @@
 //! Ignore pattern handling for filtering files during discovery and watching.
 
-use std::path::Path;
-
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+   collections::HashMap,
+   path::{Path, PathBuf},
+   sync::Arc,
+};
+
+use ignore::{
+   Match,
+   gitignore::{Gitignore, GitignoreBuilder, Glob},
+};
+use parking_lot::RwLock;
 
 /// Default patterns for files and directories to ignore during file discovery.
 const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
@@ -35,42 +43,106 @@ const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
    "**/.pytest_cache/**",
 ];
 
-/// Manages file and directory ignore patterns from `.gitignore` and `.smignore`
-/// files.
+/// Ignore files read in every directory, lowest precedence first.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".smignore", ".smgrepignore"];
+
+/// Git's per-repository excludes, relative to the repository root.
+const GIT_EXCLUDE: &str = ".git/info/exclude";
+
+/// Decides which files under a root are ignored, with git's semantics.
+///
+/// Patterns come from, lowest precedence first: the built-in defaults, the
+/// global gitignore (`core.excludesFile`), and then for each directory from
+/// the root down to the file its `.git/info/exclude`, `.gitignore`,
+/// `.smignore` and `.smgrepignore`. The last pattern matching a path or one
+/// of its parents wins, so deeper files can re-include what shallower ones
+/// exclude. Directory ignore files are read on first use and cached.
 pub struct IgnorePatterns {
-   gitignore: Option<Gitignore>,
+   root:     PathBuf,
+   defaults: Gitignore,
+   global:   Gitignore,
+   dirs:     RwLock<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
 }
 
 impl IgnorePatterns {
-   /// Creates ignore patterns by loading default patterns, `.gitignore`, and
-   /// `.smignore`.
+   /// Creates ignore patterns for files under `root`.
    pub fn new(root: &Path) -> Self {
       let mut builder = GitignoreBuilder::new(root);
-
       for pattern in DEFAULT_IGNORE_PATTERNS {
          let _ = builder.add_line(None, pattern);
       }
+      let defaults = builder.build().unwrap_or_else(|_| Gitignore::empty());
 
-      let gitignore = root.join(".gitignore");
-      if gitignore.exists() {
-         let _ = builder.add(&gitignore);
+      let (global, err) = Gitignore::global();
+      if let Some(err) = err {
+         tracing::debug!("global gitignore not loaded: {err}");
       }
 
-      let smgrep_ignore = root.join(".smignore");
-      if smgrep_ignore.exists() {
-         let _ = builder.add(&smgrep_ignore);
-      }
-
-      Self { gitignore: builder.build().ok() }
+      Self { root: root.to_path_buf(), defaults, global, dirs: RwLock::new(HashMap::new()) }
    }
 
    /// Checks whether a path matches any ignore patterns.
    pub fn is_ignored(&self, path: &Path) -> bool {
-      if let Some(ref gi) = self.gitignore {
-         gi.matched(path, path.is_dir()).is_ignore()
-      } else {
-         false
+      let is_dir = path.is_dir();
+      let Ok(relative) = path.strip_prefix(&self.root) else {
+         return self.defaults.matched(path, is_dir).is_ignore();
+      };
+
+      // An edited ignore file is read again on next use.
+      if let Some(name) = path.file_name().and_then(|n| n.to_str())
+         && IGNORE_FILES.contains(&name)
+         && let Some(dir) = path.parent()
+      {
+         self.dirs.write().remove(dir);
+      }
+
+      let mut ignored = false;
+      let mut apply = |matched: Match<&Glob>| match matched {
+         Match::Ignore(_) => ignored = true,
+         Match::Whitelist(_) => ignored = false,
+         Match::None => {},
+      };
+      apply(self.defaults.matched_path_or_any_parents(path, is_dir));
+      apply(self.global.matched_path_or_any_parents(relative, is_dir));
+
+      let mut dir = self.root.clone();
+      let parents = relative.parent().into_iter().flat_map(Path::components);
+      for component in std::iter::once(None).chain(parents.map(Some)) {
+         if let Some(component) = component {
+            dir.push(component);
+         }
+         if let Some(matcher) = self.dir_matcher(&dir) {
+            apply(matcher.matched_path_or_any_parents(path, is_dir));
+         }
       }
+      ignored
+   }
+
+   /// Patterns from the ignore files in `dir` itself, if it has any.
+   fn dir_matcher(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+      if let Some(matcher) = self.dirs.read().get(dir) {
+         return matcher.clone();
+      }
+
+      let mut builder = GitignoreBuilder::new(dir);
+      let mut found = false;
+      for name in std::iter::once(GIT_EXCLUDE).chain(IGNORE_FILES.iter().copied()) {
+         let file = dir.join(name);
+         if file.is_file() {
+            found = true;
+            if let Some(err) = builder.add(&file) {
+               tracing::warn!("failed to read {}: {err}", file.display());
+            }
+         }
+      }
+      let matcher = found
+         .then(|| builder.build().ok())
+         .flatten()
+         .filter(|m| !m.is_empty())
+         .map(Arc::new);
+
+      self.dirs.write().insert(dir.to_path_buf(), matcher.clone());
+      matcher
    }
 }
 
@@ -201,4 +273,51 @@ mod tests {
 
       assert!(ignore.is_ignored(&secret_file));
    }
+
+   #[test]
+   fn nested_ignore_files_apply_below_their_directory() {
+      let tmp = TempDir::new().unwrap();
+      let pkg = tmp.path().join("pkg");
+      fs::create_dir_all(pkg.join("assets")).unwrap();
+      fs::write(tmp.path().join(".gitignore"), "*.dat\n").unwrap();
+      fs::write(pkg.join(".gitignore"), "/assets/\n!keep.dat\n").unwrap();
+      fs::write(pkg.join(".smgrepignore"), "*.gen.ts\n").unwrap();
+
+      let ignore = IgnorePatterns::new(tmp.path());
+
+      assert!(ignore.is_ignored(&pkg.join("assets").join("logo.svg")));
+      assert!(ignore.is_ignored(&pkg.join("types.gen.ts")));
+      assert!(ignore.is_ignored(&pkg.join("drop.dat")));
+      assert!(!ignore.is_ignored(&pkg.join("keep.dat")));
+      assert!(ignore.is_ignored(&tmp.path().join("keep.dat")));
+      assert!(!ignore.is_ignored(&tmp.path().join("types.gen.ts")));
+   }
+
+   #[test]
+   fn respects_git_info_exclude() {
+      let tmp = TempDir::new().unwrap();
+      let info = tmp.path().join(".git").join("info");
+      fs::create_dir_all(&info).unwrap();
+      fs::write(info.join("exclude"), "scratch/\n").unwrap();
+      fs::create_dir_all(tmp.path().join("scratch")).unwrap();
+
+      let ignore = IgnorePatterns::new(tmp.path());
+
+      assert!(ignore.is_ignored(&tmp.path().join("scratch").join("notes.md")));
+      assert!(!ignore.is_ignored(&tmp.path().join("notes.md")));
+   }
+
+   #[test]
+   fn edited_ignore_file_reloaded() {
+      let tmp = TempDir::new().unwrap();
+      let ignore_file = tmp.path().join(".smgrepignore");
+      let log = tmp.path().join("app.log");
+
+      let ignore = IgnorePatterns::new(tmp.path());
+      assert!(!ignore.is_ignored(&log));
+
+      fs::write(&ignore_file, "*.log\n").unwrap();
+      assert!(!ignore.is_ignored(&ignore_file));
+      assert!(ignore.is_ignored(&log));
+   }
 }
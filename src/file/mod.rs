@@ -1,13 +1,16 @@
 //! File system operations for code discovery, ignore patterns, and watching.
 
 pub mod discovery;
+pub mod encoding;
 pub mod ignore;
+pub mod sniff;
 pub mod watcher;
 
 use std::path::Path;
 
 pub use discovery::*;
 pub use ignore::*;
+pub use sniff::*;
 pub use watcher::*;
 
 /// Converts a path to a normalized string representation with forward slashes.
@@ -0,0 +1,74 @@
+//! Content checks that keep binary, minified and oversized files out of the
+//! index.
+
+use std::path::Path;
+
+use super::encoding;
+use crate::config;
+
+/// Bytes at the start of a file searched for NUL, as git does.
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Files smaller than this are never treated as minified, so that short
+/// one-liners and single-paragraph docs are kept.
+const MINIFIED_MIN_LEN: usize = 4096;
+
+/// Why a file is left out of the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unindexable {
+   /// Larger than `max_file_size_kb`.
+   TooLarge,
+   /// Contains NUL bytes, and is not UTF-16.
+   Binary,
+   /// Lines average more than `minified_line_length` bytes.
+   Minified,
+}
+
+/// Checks a file's size on disk, before it is read.
+pub fn check_size(path: &Path) -> Option<Unindexable> {
+   let len = std::fs::metadata(path).ok()?.len();
+   (len > config::get().max_file_size_kb * 1024).then_some(Unindexable::TooLarge)
+}
+
+/// Checks a file's content, with the limits from configuration.
+pub fn check_content(content: &[u8]) -> Option<Unindexable> {
+   let cfg = config::get();
+   classify(content, cfg.max_file_size_kb * 1024, cfg.minified_line_length)
+}
+
+fn classify(content: &[u8], max_len: u64, minified_line_length: usize) -> Option<Unindexable> {
+   if content.len() as u64 > max_len {
+      return Some(Unindexable::TooLarge);
+   }
+   if content[..content.len().min(BINARY_SNIFF_LEN)].contains(&0) && !encoding::is_utf16(content) {
+      return Some(Unindexable::Binary);
+   }
+   if minified_line_length > 0 && content.len() >= MINIFIED_MIN_LEN {
+      let lines = content.iter().filter(|&&b| b == b'\n').count() + 1;
+      if content.len() / lines > minified_line_length {
+         return Some(Unindexable::Minified);
+      }
+   }
+   None
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn classifies_content() {
+      let source = "fn main() {\n    println!(\"hi\");\n}\n".repeat(200);
+      assert_eq!(classify(source.as_bytes(), 1 << 20, 1000), None);
+      assert_eq!(classify(source.as_bytes(), 100, 1000), Some(Unindexable::TooLarge));
+
+      let mut binary = source.clone().into_bytes();
+      binary[10] = 0;
+      assert_eq!(classify(&binary, 1 << 20, 1000), Some(Unindexable::Binary));
+
+      let bundle = format!("{}\n", "var a=function(){return 1};".repeat(400));
+      assert_eq!(classify(bundle.as_bytes(), 1 << 20, 1000), Some(Unindexable::Minified));
+      assert_eq!(classify(bundle.as_bytes(), 1 << 20, 0), None);
+      assert_eq!(classify(&bundle.as_bytes()[..2000], 1 << 20, 1000), None);
+   }
+}
@@ -13,13 +13,19 @@ struct JsonOutput {
 
 #[derive(Debug, Serialize)]
 struct JsonResult {
-   path:       String,
-   content:    String,
-   score:      f32,
-   chunk_type: String,
-   start_line: u32,
-   num_lines:  u32,
-   is_anchor:  bool,
+   path:         String,
+   content:      String,
+   score:        f32,
+   chunk_type:   String,
+   start_line:   u32,
+   num_lines:    u32,
+   is_anchor:    bool,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   language:     Option<String>,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   context_prev: Option<String>,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   context_next: Option<String>,
 }
 
 impl From<&SearchResult> for JsonResult {
@@ -36,6 +42,9 @@ impl From<&SearchResult> for JsonResult {
          start_line: result.start_line,
          num_lines: result.num_lines,
          is_anchor: result.is_anchor.unwrap_or(false),
+         language: result.language.as_ref().map(ToString::to_string),
+         context_prev: result.context_prev.as_ref().map(ToString::to_string),
+         context_next: result.context_next.as_ref().map(ToString::to_string),
       }
    }
 }
@@ -62,22 +71,34 @@ mod tests {
    fn test_json_formatter() {
       let results = vec![
          SearchResult {
-            path:       "src/main.rs".into(),
-            content:    "fn main() {}".into(),
-            score:      0.95,
-            start_line: 10,
-            num_lines:  1,
-            chunk_type: Some(ChunkType::Function),
-            is_anchor:  Some(false),
+            path:         "src/main.rs".into(),
+            content:      "fn main() {}".into(),
+            score:        0.95,
+            start_line:   10,
+            num_lines:    1,
+            chunk_type:   Some(ChunkType::Function),
+            is_anchor:    Some(false),
+            breakdown:    None,
+            bridges:      None,
+            language:     None,
+            context_prev: None,
+            context_next: None,
+            linked:       Vec::new(),
          },
          SearchResult {
-            path:       "src/lib.rs".into(),
-            content:    "pub fn test() {}".into(),
-            score:      0.87,
-            start_line: 5,
-            num_lines:  1,
-            chunk_type: Some(ChunkType::Function),
-            is_anchor:  Some(true),
+            path:         "src/lib.rs".into(),
+            content:      "pub fn test() {}".into(),
+            score:        0.87,
+            start_line:   5,
+            num_lines:    1,
+            chunk_type:   Some(ChunkType::Function),
+            is_anchor:    Some(true),
+            breakdown:    None,
+            bridges:      None,
+            language:     None,
+            context_prev: None,
+            context_next: None,
+            linked:       Vec::new(),
          },
       ];
 
@@ -151,20 +151,62 @@ pub fn detect_language(path: &Path) -> Option<&'static str> {
 /// Constructs a header from file path and context breadcrumbs, then appends the
 /// content.
 pub fn format_chunk_text(context: &[String], file_path: &str, content: &str) -> String {
+   format!("{}\n---\n{content}", chunk_header(context, file_path))
+}
+
+/// Context breadcrumbs of a chunk joined by ` > `, starting with a file label.
+pub fn chunk_header<S: AsRef<str>>(context: &[S], file_path: &str) -> String {
    let file_label = if file_path.is_empty() {
       "unknown"
    } else {
       file_path
    };
-   let has_file_label = context.iter().any(|entry| entry.starts_with("File: "));
-
-   let header = if context.is_empty() {
+   let has_file_label = context
+      .iter()
+      .any(|entry| entry.as_ref().starts_with("File: "));
+   let breadcrumbs = context
+      .iter()
+      .map(AsRef::as_ref)
+      .collect::<Vec<&str>>()
+      .join(" > ");
+
+   if context.is_empty() {
       format!("File: {file_label}")
    } else if has_file_label {
-      context.join(" > ")
+      breadcrumbs
    } else {
-      format!("File: {file_label} > {}", context.join(" > "))
-   };
+      format!("File: {file_label} > {breadcrumbs}")
+   }
+}
 
-   format!("{header}\n---\n{content}")
+/// Fills an `embed_template` for a chunk: `{content}` with its content,
+/// `{path}` with its file and `{context}` with its [`chunk_header`]. Other
+/// text, including placeholders inside the substituted values, is kept as is.
+pub fn render_embed_template<S: AsRef<str>>(
+   template: &str,
+   context: &[S],
+   file_path: &str,
+   content: &str,
+) -> String {
+   let mut text = String::with_capacity(template.len() + content.len());
+   let mut rest = template;
+   while let Some(start) = rest.find('{') {
+      text.push_str(&rest[..start]);
+      rest = &rest[start..];
+      if let Some(tail) = rest.strip_prefix("{content}") {
+         text.push_str(content);
+         rest = tail;
+      } else if let Some(tail) = rest.strip_prefix("{path}") {
+         text.push_str(file_path);
+         rest = tail;
+      } else if let Some(tail) = rest.strip_prefix("{context}") {
+         text.push_str(&chunk_header(context, file_path));
+         rest = tail;
+      } else {
+         text.push('{');
+         rest = &rest[1..];
+      }
+   }
+   text.push_str(rest);
+   text
 }
@@ -169,7 +169,8 @@ impl Formatter for HumanFormatter {
          } else {
             result.content.to_string()
          };
-         let highlighted = self.highlight_code(&code, detect_language(Path::new(&result.path)));
+         let language = detect_language(Path::new(&result.path)).or(result.language.as_deref());
+         let highlighted = self.highlight_code(&code, language);
 
          for (line_idx, line) in highlighted.lines().enumerate() {
             let line_num = result.start_line + line_idx as u32 + 1;
@@ -315,6 +316,12 @@ mod tests {
          num_lines: content.lines().count() as u32,
          chunk_type: Some(ChunkType::Function),
          is_anchor: Some(false),
+         breakdown: None,
+         bridges: None,
+         language: None,
+         context_prev: None,
+         context_next: None,
+         linked: Vec::new(),
          content,
       }
    }
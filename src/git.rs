@@ -1,11 +1,20 @@
 //! Git repository utilities for store identification and file tracking
 
-use std::path::{Path, PathBuf};
+use std::{
+   collections::HashMap,
+   path::{Path, PathBuf},
+};
 
-use git2::Repository;
+use git2::{BranchType, DiffOptions, Oid, Repository, Sort};
 use sha2::{Digest, Sha256};
 
-use crate::error::{Error, Result};
+use crate::{
+   config,
+   error::{Error, Result},
+};
+
+/// Separates a branch store's ID from the branch it is for.
+const BRANCH_SEPARATOR: char = '@';
 
 /// Checks if a path is a git repository
 pub fn is_git_repo(path: &Path) -> bool {
@@ -19,6 +28,13 @@ pub fn get_repo_root(path: &Path) -> Option<PathBuf> {
       .and_then(|repo| repo.workdir().map(|p| p.to_path_buf()))
 }
 
+/// Returns the commit checked out in the repository containing `path`
+pub fn head_commit(path: &Path) -> Option<String> {
+   let repo = Repository::discover(path).ok()?;
+   let commit = repo.head().ok()?.target()?;
+   Some(commit.to_string())
+}
+
 /// Returns the URL of the origin remote
 pub fn get_remote_url(repo: &Repository) -> Option<String> {
    repo
@@ -49,26 +65,293 @@ pub fn get_tracked_files(repo: &Repository) -> Result<Vec<PathBuf>> {
    Ok(files)
 }
 
+/// Run of consecutive lines added to a file relative to a base revision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddedHunk {
+   /// Absolute path of the file in the working tree.
+   pub path:       PathBuf,
+   /// First added line, 1-based.
+   pub start_line: u32,
+   /// Last added line, 1-based and inclusive.
+   pub end_line:   u32,
+   pub content:    String,
+}
+
+/// Collects the lines added in the working tree relative to `base`, including
+/// staged changes and untracked files, grouped into consecutive runs
+pub fn added_hunks(path: &Path, base: &str) -> Result<Vec<AddedHunk>> {
+   let repo = Repository::discover(path).map_err(Error::OpenRepository)?;
+   let workdir = repo
+      .workdir()
+      .ok_or_else(|| Error::NoWorkingDirectory(repo.path().to_path_buf()))?
+      .to_path_buf();
+   let tree = repo.revparse_single(base)?.peel_to_tree()?;
+
+   let mut opts = DiffOptions::new();
+   opts
+      .context_lines(0)
+      .include_untracked(true)
+      .recurse_untracked_dirs(true)
+      .show_untracked_content(true);
+   let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut opts))?;
+
+   let mut hunks: Vec<AddedHunk> = Vec::new();
+   diff.foreach(
+      &mut |_, _| true,
+      None,
+      None,
+      Some(&mut |delta, _, line| {
+         if line.origin() != '+' {
+            return true;
+         }
+         let (Some(rel), Some(lineno)) = (delta.new_file().path(), line.new_lineno()) else {
+            return true;
+         };
+         let path = workdir.join(rel);
+         let text = String::from_utf8_lossy(line.content());
+
+         match hunks.last_mut() {
+            Some(hunk) if hunk.path == path && hunk.end_line + 1 == lineno => {
+               hunk.content.push_str(&text);
+               hunk.end_line = lineno;
+            },
+            _ => hunks.push(AddedHunk {
+               path,
+               start_line: lineno,
+               end_line: lineno,
+               content: text.into_owned(),
+            }),
+         }
+         true
+      }),
+   )?;
+
+   Ok(hunks)
+}
+
+/// Most recent commit to change a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastCommit {
+   pub id:     String,
+   pub author: String,
+   pub email:  String,
+   /// Author time, in seconds since the Unix epoch.
+   pub time:   i64,
+}
+
+/// Finds the last commit reachable from `HEAD` that changed each of `paths`,
+/// like `git log -1 -- <path>` but in a single walk of the history. Paths
+/// not tracked at `HEAD` are left out of the result.
+pub fn last_commits(path: &Path, paths: &[PathBuf]) -> Result<HashMap<PathBuf, LastCommit>> {
+   let repo = Repository::discover(path).map_err(Error::OpenRepository)?;
+   let workdir = repo
+      .workdir()
+      .ok_or_else(|| Error::NoWorkingDirectory(repo.path().to_path_buf()))?
+      .canonicalize()?;
+   let head = repo.head()?.peel_to_commit()?;
+   let head_tree = head.tree()?;
+
+   // A file is attributed to the first commit, walking back from HEAD, whose
+   // blob for it differs from that of every parent.
+   let mut pending: Vec<(&PathBuf, PathBuf)> = paths
+      .iter()
+      .filter_map(|p| {
+         let rel = p
+            .canonicalize()
+            .ok()?
+            .strip_prefix(&workdir)
+            .ok()?
+            .to_path_buf();
+         head_tree.get_path(&rel).ok()?;
+         Some((p, rel))
+      })
+      .collect();
+
+   let mut found = HashMap::new();
+   let mut walk = repo.revwalk()?;
+   walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+   walk.push(head.id())?;
+
+   for id in walk {
+      if pending.is_empty() {
+         break;
+      }
+      let commit = repo.find_commit(id?)?;
+      let tree = commit.tree()?;
+      let parents: Vec<git2::Tree<'_>> = commit
+         .parents()
+         .map(|p| p.tree())
+         .collect::<Result<_, _>>()?;
+      let blob = |tree: &git2::Tree<'_>, rel: &Path| -> Option<Oid> {
+         tree.get_path(rel).ok().map(|e| e.id())
+      };
+
+      pending.retain(|(path, rel)| {
+         let current = blob(&tree, rel);
+         if current.is_none() || parents.iter().any(|p| blob(p, rel) == current) {
+            return true;
+         }
+         let author = commit.author();
+         found.insert((*path).clone(), LastCommit {
+            id:     commit.id().to_string(),
+            author: author.name().unwrap_or_default().to_string(),
+            email:  author.email().unwrap_or_default().to_string(),
+            time:   author.when().seconds(),
+         });
+         false
+      });
+   }
+
+   Ok(found)
+}
+
 /// Resolves a store ID from a path, using git remote if available or directory
 /// name and hash
+///
+/// A linked worktree is named after the repository it belongs to, and gets
+/// the store `<store>@<branch>` of the branch it has checked out, or
+/// `<store>@<worktree>` when detached. With `branch_stores` set, so does a
+/// checkout of any branch but the default one.
 pub fn resolve_store_id(path: &Path) -> Result<String> {
+   resolve_store_id_with(path, config::get().branch_stores)
+}
+
+/// [`resolve_store_id`] with the `branch_stores` setting given, for use
+/// while the configuration is still being loaded.
+pub fn resolve_store_id_with(path: &Path, branch_stores: bool) -> Result<String> {
    let abs_path = path.canonicalize()?;
+   let repo = Repository::open(&abs_path).ok();
+   // Worktrees without a remote are named after the main checkout
+   let named_path = repo.as_ref().and_then(main_workdir).unwrap_or(abs_path);
 
-   if let Ok(repo) = Repository::open(&abs_path)
-      && let Some(remote_url) = get_remote_url(&repo)
-      && let Some(store_id) = extract_owner_repo(&remote_url)
+   let store_id = if let Some(store_id) = repo
+      .as_ref()
+      .and_then(get_remote_url)
+      .and_then(|url| extract_owner_repo(&url))
    {
-      return Ok(store_id);
+      store_id
+   } else {
+      let dir_name = named_path
+         .file_name()
+         .and_then(|n| n.to_str())
+         .unwrap_or("unknown");
+
+      let path_hash = compute_path_hash(&named_path);
+
+      format!("{}-{}", dir_name, &path_hash[..8])
+   };
+
+   let namespace = match &repo {
+      Some(repo) if repo.is_worktree() => worktree_namespace(repo),
+      Some(repo) if branch_stores => feature_branch(repo),
+      _ => None,
+   };
+   Ok(match namespace {
+      Some(namespace) => format!("{store_id}{BRANCH_SEPARATOR}{}", branch_suffix(&namespace)),
+      None => store_id,
+   })
+}
+
+/// The store that a branch or worktree store belongs to.
+pub fn base_store_id(store_id: &str) -> Option<&str> {
+   store_id.rsplit_once(BRANCH_SEPARATOR).map(|(base, _)| base)
+}
+
+/// The checked-out branch, if it is not the default branch. Detached heads
+/// and repositories without a known default branch have none.
+fn feature_branch(repo: &Repository) -> Option<String> {
+   let head = repo.head().ok()?;
+   if !head.is_branch() {
+      return None;
    }
+   let branch = head.shorthand()?;
+   (branch != default_branch(repo)?).then(|| branch.to_string())
+}
 
-   let dir_name = abs_path
+/// The branch `origin/HEAD` points to, or else `main` or `master`, whichever
+/// exists.
+fn default_branch(repo: &Repository) -> Option<String> {
+   if let Ok(origin_head) = repo.find_reference("refs/remotes/origin/HEAD")
+      && let Some(target) = origin_head.symbolic_target()
+      && let Some(branch) = target.strip_prefix("refs/remotes/origin/")
+   {
+      return Some(branch.to_string());
+   }
+   ["main", "master"]
+      .into_iter()
+      .find(|name| repo.find_branch(name, BranchType::Local).is_ok())
+      .map(str::to_string)
+}
+
+/// The checked-out branch of a linked worktree, or the worktree's name when
+/// its head is detached.
+fn worktree_namespace(repo: &Repository) -> Option<String> {
+   if let Ok(head) = repo.head()
+      && head.is_branch()
+   {
+      return head.shorthand().map(str::to_string);
+   }
+   repo
+      .path()
       .file_name()
-      .and_then(|n| n.to_str())
-      .unwrap_or("unknown");
+      .and_then(|name| name.to_str())
+      .map(str::to_string)
+}
+
+/// The main checkout of the repository that the linked worktree at `path`
+/// belongs to; `None` for anything but a linked worktree.
+pub fn main_worktree(path: &Path) -> Option<PathBuf> {
+   main_workdir(&Repository::open(path).ok()?)
+}
+
+fn main_workdir(repo: &Repository) -> Option<PathBuf> {
+   if !repo.is_worktree() {
+      return None;
+   }
+   let common_dir = repo.commondir().canonicalize().ok()?;
+   // A bare repository has no checkout, and is named after itself
+   Some(if common_dir.file_name() == Some(".git".as_ref()) {
+      common_dir.parent()?.to_path_buf()
+   } else {
+      common_dir
+   })
+}
 
-   let path_hash = compute_path_hash(&abs_path);
+/// Working directories of the checked-out submodules of the repository at
+/// `path`, and of their submodules in turn.
+pub fn submodule_roots(path: &Path) -> Vec<PathBuf> {
+   let Ok(repo) = Repository::open(path) else {
+      return Vec::new();
+   };
+   let Some(workdir) = repo.workdir() else {
+      return Vec::new();
+   };
+   let mut roots = Vec::new();
+   for submodule in repo.submodules().unwrap_or_default() {
+      let root = workdir.join(submodule.path());
+      // Submodules that were never initialized are empty directories
+      if Repository::open(&root).is_ok() {
+         roots.extend(submodule_roots(&root));
+         roots.push(root);
+      }
+   }
+   roots.sort();
+   roots
+}
 
-   Ok(format!("{}-{}", dir_name, &path_hash[..8]))
+/// `branch` with the characters that cannot appear in file or table names,
+/// such as the `/` of `feature/x`, replaced by `-`.
+fn branch_suffix(branch: &str) -> String {
+   branch
+      .chars()
+      .map(|c| {
+         if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+            c
+         } else {
+            '-'
+         }
+      })
+      .collect()
 }
 
 fn extract_owner_repo(url: &str) -> Option<String> {
@@ -130,6 +413,139 @@ mod tests {
       assert_eq!(extract_owner_repo(url), Some("can1357-smgrep".to_string()));
    }
 
+   #[test]
+   fn added_hunks_groups_new_lines() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let repo = Repository::init(dir.path()).unwrap();
+      std::fs::write(dir.path().join("lib.rs"), "fn a() {}\nfn b() {}\n").unwrap();
+
+      let mut index = repo.index().unwrap();
+      index.add_path(Path::new("lib.rs")).unwrap();
+      index.write().unwrap();
+      let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+      let sig = git2::Signature::now("smgrep", "smgrep@example.com").unwrap();
+      repo
+         .commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+         .unwrap();
+
+      std::fs::write(dir.path().join("lib.rs"), "fn a() {}\nfn x() {}\nfn y() {}\nfn b() {}\n")
+         .unwrap();
+      std::fs::write(dir.path().join("new.rs"), "fn z() {}\n").unwrap();
+
+      let workdir = repo.workdir().unwrap();
+      let hunks = added_hunks(dir.path(), "HEAD").unwrap();
+      assert_eq!(hunks, vec![
+         AddedHunk {
+            path:       workdir.join("lib.rs"),
+            start_line: 2,
+            end_line:   3,
+            content:    "fn x() {}\nfn y() {}\n".to_string(),
+         },
+         AddedHunk {
+            path:       workdir.join("new.rs"),
+            start_line: 1,
+            end_line:   1,
+            content:    "fn z() {}\n".to_string(),
+         },
+      ]);
+   }
+
+   #[test]
+   fn last_commits_follow_changes() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let repo = Repository::init(dir.path()).unwrap();
+      let commit = |file: &str, name: &str, time: i64| {
+         std::fs::write(dir.path().join(file), name).unwrap();
+         let mut index = repo.index().unwrap();
+         index.add_path(Path::new(file)).unwrap();
+         let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+         let sig =
+            git2::Signature::new(name, "dev@example.com", &git2::Time::new(time, 0)).unwrap();
+         let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+         repo
+            .commit(Some("HEAD"), &sig, &sig, name, &tree, &parent.iter().collect::<Vec<_>>())
+            .unwrap();
+      };
+      commit("a.rs", "alice", 1);
+      commit("b.rs", "bob", 2);
+      commit("a.rs", "carol", 3);
+      std::fs::write(dir.path().join("new.rs"), "").unwrap();
+
+      let paths = ["a.rs", "b.rs", "new.rs"].map(|p| dir.path().join(p));
+      let found = last_commits(dir.path(), &paths).unwrap();
+      assert_eq!(found[&paths[0]].author, "carol");
+      assert_eq!(found[&paths[0]].time, 3);
+      assert_eq!(found[&paths[1]].author, "bob");
+      assert!(!found.contains_key(&paths[2]));
+   }
+
+   #[test]
+   fn names_feature_branches() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let repo = Repository::init(dir.path()).unwrap();
+      let tree = repo
+         .find_tree(repo.index().unwrap().write_tree().unwrap())
+         .unwrap();
+      let sig = git2::Signature::now("smgrep", "smgrep@example.com").unwrap();
+      let id = repo.commit(None, &sig, &sig, "init", &tree, &[]).unwrap();
+      let commit = repo.find_commit(id).unwrap();
+      repo.branch("main", &commit, true).unwrap();
+      repo.branch("feature/x", &commit, false).unwrap();
+
+      repo.set_head("refs/heads/main").unwrap();
+      assert_eq!(feature_branch(&repo), None);
+      repo.set_head("refs/heads/feature/x").unwrap();
+      assert_eq!(feature_branch(&repo).as_deref(), Some("feature/x"));
+      repo.set_head_detached(id).unwrap();
+      assert_eq!(feature_branch(&repo), None);
+
+      assert_eq!(branch_suffix("feature/x"), "feature-x");
+      assert_eq!(base_store_id("can1357-smgrep@feature-x"), Some("can1357-smgrep"));
+      assert_eq!(base_store_id("can1357-smgrep"), None);
+   }
+
+   #[test]
+   fn resolves_worktrees_to_their_repository() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let main = dir.path().join("main");
+      let repo = Repository::init(&main).unwrap();
+      let tree = repo
+         .find_tree(repo.index().unwrap().write_tree().unwrap())
+         .unwrap();
+      let sig = git2::Signature::now("smgrep", "smgrep@example.com").unwrap();
+      repo
+         .commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+         .unwrap();
+
+      let linked = dir.path().join("linked");
+      repo.worktree("linked", &linked, None).unwrap();
+      let store_id = resolve_store_id(&main).unwrap();
+      assert_eq!(resolve_store_id(&linked).unwrap(), format!("{store_id}@linked"));
+      assert_eq!(main_worktree(&linked), Some(main.canonicalize().unwrap()));
+      assert_eq!(main_worktree(&main), None);
+   }
+
+   #[test]
+   fn branch_stores_follow_the_given_setting() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let repo = Repository::init(dir.path()).unwrap();
+      let tree = repo
+         .find_tree(repo.index().unwrap().write_tree().unwrap())
+         .unwrap();
+      let sig = git2::Signature::now("smgrep", "smgrep@example.com").unwrap();
+      let commit = repo
+         .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+         .unwrap();
+      repo
+         .branch("feature/x", &repo.find_commit(commit).unwrap(), false)
+         .unwrap();
+      repo.set_head("refs/heads/feature/x").unwrap();
+
+      let store_id = resolve_store_id_with(dir.path(), false).unwrap();
+      assert!(!store_id.contains(BRANCH_SEPARATOR), "{store_id}");
+      assert_eq!(resolve_store_id_with(dir.path(), true).unwrap(), format!("{store_id}@feature-x"));
+   }
+
    #[test]
    fn path_hash_computed() {
       let path = Path::new("/tmp/test");
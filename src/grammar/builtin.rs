@@ -0,0 +1,57 @@
+//! Grammars compiled into the binary with the `builtin-grammars` feature.
+//!
+//! These cover the most common languages so that chunking still follows
+//! syntax when the WASM grammars cannot be downloaded, for instance behind a
+//! proxy that blocks GitHub release assets.
+
+use tree_sitter::Language;
+
+/// Languages with a native grammar in this build.
+#[cfg(feature = "builtin-grammars")]
+pub const LANGUAGES: &[&str] = &["rust", "python", "typescript", "tsx", "go"];
+
+#[cfg(not(feature = "builtin-grammars"))]
+pub const LANGUAGES: &[&str] = &[];
+
+/// Native grammar for `lang`, if one is compiled in.
+#[cfg(feature = "builtin-grammars")]
+pub fn language(lang: &str) -> Option<Language> {
+   let language = match lang {
+      "rust" => tree_sitter_rust::LANGUAGE,
+      "python" => tree_sitter_python::LANGUAGE,
+      "typescript" => tree_sitter_typescript::LANGUAGE_TYPESCRIPT,
+      "tsx" => tree_sitter_typescript::LANGUAGE_TSX,
+      "go" => tree_sitter_go::LANGUAGE,
+      _ => return None,
+   };
+   Some(Language::new(language))
+}
+
+#[cfg(not(feature = "builtin-grammars"))]
+pub const fn language(_lang: &str) -> Option<Language> {
+   None
+}
+
+#[cfg(all(test, feature = "builtin-grammars"))]
+mod tests {
+   use tree_sitter::Parser;
+
+   use super::*;
+
+   #[test]
+   fn builtin_grammars_parse() {
+      for (lang, source) in [
+         ("rust", "fn main() {}"),
+         ("python", "def main():\n    pass\n"),
+         ("typescript", "function main(): void {}"),
+         ("tsx", "const a = <div />;"),
+         ("go", "package main\nfunc main() {}\n"),
+      ] {
+         let mut parser = Parser::new();
+         parser.set_language(&language(lang).unwrap()).unwrap();
+         let tree = parser.parse(source, None).unwrap();
+         assert!(!tree.root_node().has_error(), "{lang}");
+      }
+      assert!(language("cobol").is_none());
+   }
+}
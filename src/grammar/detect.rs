@@ -0,0 +1,153 @@
+//! Language hints inside a file: vim and emacs modelines and `#!` lines.
+//!
+//! A modeline names the language outright and wins over the file's
+//! extension; a shebang is only consulted when the extension says nothing,
+//! which is the usual case for scripts in `bin/`.
+
+use super::{GRAMMAR_URLS, GrammarManager};
+
+/// Lines at each end of a file searched for a vim modeline, vim's default.
+const MODELINE_LINES: usize = 5;
+
+/// Interpreters and editor modes whose names are neither a language nor an
+/// extension in [`super::EXTENSION_MAP`].
+const ALIASES: &[(&str, &str)] = &[
+   ("node", "javascript"),
+   ("nodejs", "javascript"),
+   ("deno", "javascript"),
+   ("bun", "javascript"),
+   ("js", "javascript"),
+   ("ts-node", "typescript"),
+   ("zsh", "bash"),
+   ("ksh", "bash"),
+   ("dash", "bash"),
+   ("ash", "bash"),
+   ("shell-script", "bash"),
+   ("luajit", "lua"),
+   ("runghc", "haskell"),
+   ("runhaskell", "haskell"),
+   ("cs", "c_sharp"),
+   ("csharp", "c_sharp"),
+   ("objective-c", "objc"),
+   ("makefile", "make"),
+];
+
+/// Language named by a vim (`vim: set ft=python:`) or emacs
+/// (`-*- mode: ruby -*-`) modeline.
+pub fn modeline_language(content: &str) -> Option<&'static str> {
+   let head = content.lines().take(MODELINE_LINES);
+   let line_count = content.lines().count();
+   let tail = content.lines().skip(
+      line_count
+         .saturating_sub(MODELINE_LINES)
+         .max(MODELINE_LINES),
+   );
+
+   let emacs = content.lines().take(2).find_map(emacs_mode);
+   emacs
+      .or_else(|| head.chain(tail).find_map(vim_filetype))
+      .and_then(resolve)
+}
+
+/// Language of the interpreter on a `#!` first line, looking through `env`.
+pub fn shebang_language(content: &str) -> Option<&'static str> {
+   let line = content.lines().next()?.strip_prefix("#!")?;
+   let mut words = line
+      .split_whitespace()
+      .map(|w| w.rsplit('/').next().unwrap_or(w));
+   let mut program = words.next()?;
+   if program == "env" {
+      program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+   }
+   let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+   resolve(name)
+}
+
+/// Value of `ft=`, `filetype=`, `syn=` or `syntax=` in a vim modeline.
+fn vim_filetype(line: &str) -> Option<&str> {
+   let rest = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+      line.match_indices(marker).find_map(|(at, _)| {
+         let starts_word = line[..at]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+         starts_word.then(|| &line[at + marker.len()..])
+      })
+   })?;
+   rest
+      .split(|c: char| c.is_whitespace() || c == ':')
+      .find_map(|option| {
+         let (key, value) = option.split_once('=')?;
+         matches!(key, "ft" | "filetype" | "syn" | "syntax").then_some(value)
+      })
+}
+
+/// Major mode set between `-*-` markers, either alone or as `mode: name`.
+fn emacs_mode(line: &str) -> Option<&str> {
+   let (_, rest) = line.split_once("-*-")?;
+   let (inner, _) = rest.split_once("-*-")?;
+   if !inner.contains(':') {
+      return Some(inner.trim());
+   }
+   inner.split(';').find_map(|var| {
+      let (key, value) = var.split_once(':')?;
+      key.trim()
+         .eq_ignore_ascii_case("mode")
+         .then(|| value.trim())
+   })
+}
+
+/// Resolves a language, alias or extension to a language name.
+fn resolve(name: &str) -> Option<&'static str> {
+   if name.is_empty() {
+      return None;
+   }
+   GRAMMAR_URLS
+      .iter()
+      .map(|&(lang, ..)| (lang, lang))
+      .chain(ALIASES.iter().copied())
+      .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+      .map(|(_, lang)| lang)
+      .or_else(|| GrammarManager::extension_to_language(name))
+}
+
+#[cfg(test)]
+mod tests {
+   use std::path::Path;
+
+   use super::*;
+
+   #[test]
+   fn reads_shebangs() {
+      assert_eq!(shebang_language("#!/usr/bin/env python3\nprint(1)"), Some("python"));
+      assert_eq!(shebang_language("#!/bin/sh\necho hi"), Some("bash"));
+      assert_eq!(shebang_language("#!/usr/bin/env -S node --harmony\n"), Some("javascript"));
+      assert_eq!(shebang_language("#!/usr/local/bin/ruby2.7 -w\n"), Some("ruby"));
+      assert_eq!(shebang_language("#!/usr/bin/perl\n"), None);
+      assert_eq!(shebang_language("print(1)\n#!/bin/sh"), None);
+   }
+
+   #[test]
+   fn reads_modelines() {
+      assert_eq!(modeline_language("x = 1\n# vim: set ft=python ts=4 :\n"), Some("python"));
+      assert_eq!(modeline_language("// vim:filetype=cpp\nint x;"), Some("cpp"));
+      assert_eq!(modeline_language("# -*- mode: ruby; coding: utf-8 -*-\n"), Some("ruby"));
+      assert_eq!(modeline_language("#!/bin/sh\n# -*- shell-script -*-\n"), Some("bash"));
+      assert_eq!(modeline_language("# vim: ts=4 sw=4\n"), None);
+
+      let long: String = (0..20).map(|i| format!("line {i}\n")).collect();
+      assert_eq!(modeline_language(&format!("{long}/* vim: set ft=c: */\n")), Some("c"));
+      assert_eq!(modeline_language(&format!("x\n{long}\n# vim: ft=c\n{long}")), None);
+   }
+
+   #[test]
+   fn modeline_beats_extension_beats_shebang() {
+      let lang = |path: &str, content: &str| GrammarManager::language_for(Path::new(path), content);
+      assert_eq!(lang("build.inc", "# vim: ft=make\n"), Some("make"));
+      assert_eq!(lang("run.sh", "#!/bin/sh\n# -*- mode: python -*-\n"), Some("python"));
+      assert_eq!(lang("tool.rb", "#!/usr/bin/env python3\n"), Some("ruby"));
+      assert_eq!(lang("bin/tool", "#!/usr/bin/env python3\n"), Some("python"));
+      assert_eq!(lang("Makefile", "all:\n"), Some("make"));
+      assert_eq!(lang("notes", "hello\n"), None);
+   }
+}
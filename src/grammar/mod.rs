@@ -1,5 +1,8 @@
 //! Tree-sitter grammar management and loading
 
+pub mod builtin;
+pub mod detect;
+
 use std::path::{Path, PathBuf};
 
 use tokio::fs;
@@ -8,53 +11,59 @@ use tree_sitter::{Language, Parser, WasmStore, wasmtime};
 use crate::{
    config,
    error::{ChunkerError, ConfigError, Error, Result},
+   meta::FileHash,
 };
 
-/// Language name and URL pair for grammar downloads
-pub type GrammarPair = (&'static str, &'static str);
+/// Language name, download URL and SHA-256 of a grammar.
+///
+/// A grammar with a checksum is only loaded if it matches, so its URL must
+/// name a release tag rather than `latest`: the checksum is updated together
+/// with the tag when the grammar is upgraded. Grammars without one are
+/// loaded as downloaded.
+pub type GrammarSource = (&'static str, &'static str, Option<&'static str>);
 
 /// URLs for downloading tree-sitter WASM grammars
-pub const GRAMMAR_URLS: &[GrammarPair] = &[
+pub const GRAMMAR_URLS: &[GrammarSource] = &[
     // tree-sitter organization (official)
-    ("typescript", "https://github.com/tree-sitter/tree-sitter-typescript/releases/latest/download/tree-sitter-typescript.wasm"),
-    ("tsx",        "https://github.com/tree-sitter/tree-sitter-typescript/releases/latest/download/tree-sitter-tsx.wasm"),
-    ("python",     "https://github.com/tree-sitter/tree-sitter-python/releases/latest/download/tree-sitter-python.wasm"),
-    ("go",         "https://github.com/tree-sitter/tree-sitter-go/releases/latest/download/tree-sitter-go.wasm"),
-    ("rust",       "https://github.com/tree-sitter/tree-sitter-rust/releases/latest/download/tree-sitter-rust.wasm"),
-    ("javascript", "https://github.com/tree-sitter/tree-sitter-javascript/releases/latest/download/tree-sitter-javascript.wasm"),
-    ("c",          "https://github.com/tree-sitter/tree-sitter-c/releases/latest/download/tree-sitter-c.wasm"),
-    ("cpp",        "https://github.com/tree-sitter/tree-sitter-cpp/releases/latest/download/tree-sitter-cpp.wasm"),
-    ("java",       "https://github.com/tree-sitter/tree-sitter-java/releases/latest/download/tree-sitter-java.wasm"),
-    ("ruby",       "https://github.com/tree-sitter/tree-sitter-ruby/releases/latest/download/tree-sitter-ruby.wasm"),
-    ("php",        "https://github.com/tree-sitter/tree-sitter-php/releases/latest/download/tree-sitter-php.wasm"),
-    ("html",       "https://github.com/tree-sitter/tree-sitter-html/releases/latest/download/tree-sitter-html.wasm"),
-    ("css",        "https://github.com/tree-sitter/tree-sitter-css/releases/latest/download/tree-sitter-css.wasm"),
-    ("bash",       "https://github.com/tree-sitter/tree-sitter-bash/releases/latest/download/tree-sitter-bash.wasm"),
-    ("json",       "https://github.com/tree-sitter/tree-sitter-json/releases/latest/download/tree-sitter-json.wasm"),
-    ("c_sharp",    "https://github.com/tree-sitter/tree-sitter-c-sharp/releases/latest/download/tree-sitter-c_sharp.wasm"),
-    ("scala",      "https://github.com/tree-sitter/tree-sitter-scala/releases/latest/download/tree-sitter-scala.wasm"),
-    ("haskell",    "https://github.com/tree-sitter/tree-sitter-haskell/releases/latest/download/tree-sitter-haskell.wasm"),
-    ("ocaml",      "https://github.com/tree-sitter/tree-sitter-ocaml/releases/latest/download/tree-sitter-ocaml.wasm"),
-    ("regex",      "https://github.com/tree-sitter/tree-sitter-regex/releases/latest/download/tree-sitter-regex.wasm"),
-    ("julia",      "https://github.com/tree-sitter/tree-sitter-julia/releases/latest/download/tree-sitter-julia.wasm"),
-    ("verilog",    "https://github.com/tree-sitter/tree-sitter-verilog/releases/latest/download/tree-sitter-verilog.wasm"),
+    ("typescript", "https://github.com/tree-sitter/tree-sitter-typescript/releases/latest/download/tree-sitter-typescript.wasm", None),
+    ("tsx",        "https://github.com/tree-sitter/tree-sitter-typescript/releases/latest/download/tree-sitter-tsx.wasm", None),
+    ("python",     "https://github.com/tree-sitter/tree-sitter-python/releases/latest/download/tree-sitter-python.wasm", None),
+    ("go",         "https://github.com/tree-sitter/tree-sitter-go/releases/latest/download/tree-sitter-go.wasm", None),
+    ("rust",       "https://github.com/tree-sitter/tree-sitter-rust/releases/latest/download/tree-sitter-rust.wasm", None),
+    ("javascript", "https://github.com/tree-sitter/tree-sitter-javascript/releases/latest/download/tree-sitter-javascript.wasm", None),
+    ("c",          "https://github.com/tree-sitter/tree-sitter-c/releases/latest/download/tree-sitter-c.wasm", None),
+    ("cpp",        "https://github.com/tree-sitter/tree-sitter-cpp/releases/latest/download/tree-sitter-cpp.wasm", None),
+    ("java",       "https://github.com/tree-sitter/tree-sitter-java/releases/latest/download/tree-sitter-java.wasm", None),
+    ("ruby",       "https://github.com/tree-sitter/tree-sitter-ruby/releases/latest/download/tree-sitter-ruby.wasm", None),
+    ("php",        "https://github.com/tree-sitter/tree-sitter-php/releases/latest/download/tree-sitter-php.wasm", None),
+    ("html",       "https://github.com/tree-sitter/tree-sitter-html/releases/latest/download/tree-sitter-html.wasm", None),
+    ("css",        "https://github.com/tree-sitter/tree-sitter-css/releases/latest/download/tree-sitter-css.wasm", None),
+    ("bash",       "https://github.com/tree-sitter/tree-sitter-bash/releases/latest/download/tree-sitter-bash.wasm", None),
+    ("json",       "https://github.com/tree-sitter/tree-sitter-json/releases/latest/download/tree-sitter-json.wasm", None),
+    ("c_sharp",    "https://github.com/tree-sitter/tree-sitter-c-sharp/releases/latest/download/tree-sitter-c_sharp.wasm", None),
+    ("scala",      "https://github.com/tree-sitter/tree-sitter-scala/releases/latest/download/tree-sitter-scala.wasm", None),
+    ("haskell",    "https://github.com/tree-sitter/tree-sitter-haskell/releases/latest/download/tree-sitter-haskell.wasm", None),
+    ("ocaml",      "https://github.com/tree-sitter/tree-sitter-ocaml/releases/latest/download/tree-sitter-ocaml.wasm", None),
+    ("regex",      "https://github.com/tree-sitter/tree-sitter-regex/releases/latest/download/tree-sitter-regex.wasm", None),
+    ("julia",      "https://github.com/tree-sitter/tree-sitter-julia/releases/latest/download/tree-sitter-julia.wasm", None),
+    ("verilog",    "https://github.com/tree-sitter/tree-sitter-verilog/releases/latest/download/tree-sitter-verilog.wasm", None),
     // tree-sitter-grammars organization
-    ("zig",        "https://github.com/tree-sitter-grammars/tree-sitter-zig/releases/latest/download/tree-sitter-zig.wasm"),
-    ("lua",        "https://github.com/tree-sitter-grammars/tree-sitter-lua/releases/latest/download/tree-sitter-lua.wasm"),
-    ("yaml",       "https://github.com/tree-sitter-grammars/tree-sitter-yaml/releases/latest/download/tree-sitter-yaml.wasm"),
-    ("toml",       "https://github.com/tree-sitter-grammars/tree-sitter-toml/releases/latest/download/tree-sitter-toml.wasm"),
-    ("markdown",   "https://github.com/tree-sitter-grammars/tree-sitter-markdown/releases/latest/download/tree-sitter-markdown.wasm"),
-    ("kotlin",     "https://github.com/tree-sitter-grammars/tree-sitter-kotlin/releases/latest/download/tree-sitter-kotlin.wasm"),
-    ("make",       "https://github.com/tree-sitter-grammars/tree-sitter-make/releases/latest/download/tree-sitter-make.wasm"),
-    ("objc",       "https://github.com/tree-sitter-grammars/tree-sitter-objc/releases/latest/download/tree-sitter-objc.wasm"),
-    ("diff",       "https://github.com/tree-sitter-grammars/tree-sitter-diff/releases/latest/download/tree-sitter-diff.wasm"),
-    ("xml",        "https://github.com/tree-sitter-grammars/tree-sitter-xml/releases/latest/download/tree-sitter-xml.wasm"),
-    ("starlark",   "https://github.com/tree-sitter-grammars/tree-sitter-starlark/releases/latest/download/tree-sitter-starlark.wasm"),
-    ("hcl",        "https://github.com/tree-sitter-grammars/tree-sitter-hcl/releases/latest/download/tree-sitter-hcl.wasm"),
-    ("terraform",  "https://github.com/tree-sitter-grammars/tree-sitter-hcl/releases/latest/download/tree-sitter-terraform.wasm"),
-    ("odin",       "https://github.com/tree-sitter-grammars/tree-sitter-odin/releases/latest/download/tree-sitter-odin.wasm"),
+    ("zig",        "https://github.com/tree-sitter-grammars/tree-sitter-zig/releases/latest/download/tree-sitter-zig.wasm", None),
+    ("lua",        "https://github.com/tree-sitter-grammars/tree-sitter-lua/releases/latest/download/tree-sitter-lua.wasm", None),
+    ("yaml",       "https://github.com/tree-sitter-grammars/tree-sitter-yaml/releases/latest/download/tree-sitter-yaml.wasm", None),
+    ("toml",       "https://github.com/tree-sitter-grammars/tree-sitter-toml/releases/latest/download/tree-sitter-toml.wasm", None),
+    ("markdown",   "https://github.com/tree-sitter-grammars/tree-sitter-markdown/releases/latest/download/tree-sitter-markdown.wasm", None),
+    ("kotlin",     "https://github.com/tree-sitter-grammars/tree-sitter-kotlin/releases/latest/download/tree-sitter-kotlin.wasm", None),
+    ("make",       "https://github.com/tree-sitter-grammars/tree-sitter-make/releases/latest/download/tree-sitter-make.wasm", None),
+    ("objc",       "https://github.com/tree-sitter-grammars/tree-sitter-objc/releases/latest/download/tree-sitter-objc.wasm", None),
+    ("diff",       "https://github.com/tree-sitter-grammars/tree-sitter-diff/releases/latest/download/tree-sitter-diff.wasm", None),
+    ("xml",        "https://github.com/tree-sitter-grammars/tree-sitter-xml/releases/latest/download/tree-sitter-xml.wasm", None),
+    ("starlark",   "https://github.com/tree-sitter-grammars/tree-sitter-starlark/releases/latest/download/tree-sitter-starlark.wasm", None),
+    ("hcl",        "https://github.com/tree-sitter-grammars/tree-sitter-hcl/releases/latest/download/tree-sitter-hcl.wasm", None),
+    ("terraform",  "https://github.com/tree-sitter-grammars/tree-sitter-hcl/releases/latest/download/tree-sitter-terraform.wasm", None),
+    ("odin",       "https://github.com/tree-sitter-grammars/tree-sitter-odin/releases/latest/download/tree-sitter-odin.wasm", None),
     // elixir-lang organization
-    ("elixir",     "https://github.com/elixir-lang/tree-sitter-elixir/releases/latest/download/tree-sitter-elixir.wasm"),
+    ("elixir",     "https://github.com/elixir-lang/tree-sitter-elixir/releases/latest/download/tree-sitter-elixir.wasm", None),
 ];
 
 /// Maps file extensions to language names
@@ -168,20 +177,45 @@ impl GrammarManager {
       &self.grammar_dir
    }
 
-   /// Converts a file extension to a language name
+   /// Converts a file extension to a language name, preferring the
+   /// `language_overrides` config over [`EXTENSION_MAP`].
    pub fn extension_to_language(ext: &str) -> Option<&'static str> {
+      let overridden = config::get()
+         .language_overrides
+         .iter()
+         .find(|(e, _)| e.trim_start_matches('.').eq_ignore_ascii_case(ext));
+      if let Some((_, lang)) = overridden {
+         let known = GRAMMAR_URLS
+            .iter()
+            .find(|(l, ..)| l.eq_ignore_ascii_case(lang));
+         return Some(known.map_or(lang.as_str(), |&(l, ..)| l));
+      }
+
       EXTENSION_MAP
          .iter()
          .find(|(e, _)| e.eq_ignore_ascii_case(ext))
          .map(|(_, lang)| *lang)
    }
 
+   /// Picks the language of a file: a modeline in `content` first, then the
+   /// extension (or, without one, the file name, as for `Makefile`), then the
+   /// interpreter on a `#!` line.
+   pub fn language_for(path: &Path, content: &str) -> Option<&'static str> {
+      let by_name = path
+         .extension()
+         .or_else(|| path.file_name())
+         .and_then(|n| n.to_str());
+      detect::modeline_language(content)
+         .or_else(|| by_name.and_then(Self::extension_to_language))
+         .or_else(|| detect::shebang_language(content))
+   }
+
    /// Returns the download URL for a grammar by language name
    pub fn grammar_url(lang: &str) -> Option<&'static str> {
       GRAMMAR_URLS
          .iter()
-         .find(|(l, _)| l.eq_ignore_ascii_case(lang))
-         .map(|(_, url)| *url)
+         .find(|(l, ..)| l.eq_ignore_ascii_case(lang))
+         .map(|(_, url, _)| *url)
    }
 
    /// Returns the filesystem path for a grammar WASM file
@@ -198,16 +232,16 @@ impl GrammarManager {
    pub fn available_languages(&self) -> impl Iterator<Item = &'static str> + Clone {
       GRAMMAR_URLS
          .iter()
-         .filter(|(lang, _)| self.is_available(lang))
-         .map(|(lang, _)| *lang)
+         .filter(|(lang, ..)| self.is_available(lang))
+         .map(|(lang, ..)| *lang)
    }
 
    /// Returns an iterator of languages not available locally
    pub fn missing_languages(&self) -> impl Iterator<Item = &'static str> + Clone {
       GRAMMAR_URLS
          .iter()
-         .filter(|(lang, _)| !self.is_available(lang))
-         .map(|(lang, _)| *lang)
+         .filter(|(lang, ..)| !self.is_available(lang))
+         .map(|(lang, ..)| *lang)
    }
 
    fn load_language(&self, lang: &str, bytes: &[u8]) -> Result<Language> {
@@ -217,17 +251,39 @@ impl GrammarManager {
          .map_err(|e| ChunkerError::LoadLanguage { lang: lang.to_string(), reason: e }.into())
    }
 
-   /// Downloads and loads a grammar, using cached version if available
-   pub async fn download_grammar(&self, pair: GrammarPair) -> Result<Language> {
-      let (lang, url) = pair;
+   /// Checks a grammar's WASM file against the checksum of `source`, if it
+   /// has one.
+   pub fn verify(source: GrammarSource, bytes: &[u8]) -> Result<()> {
+      let (lang, _, Some(expected)) = source else {
+         return Ok(());
+      };
+      let actual = FileHash::sum(bytes).to_string();
+      if actual == expected {
+         return Ok(());
+      }
+      Err(Error::ChecksumMismatch {
+         artifact: format!("grammar {lang}"),
+         expected: expected.to_string(),
+         actual,
+      })
+   }
+
+   /// Downloads and loads a grammar, using cached version if available and
+   /// it matches the grammar's checksum.
+   pub async fn download_grammar(&self, source: GrammarSource) -> Result<Language> {
+      let (lang, url, _) = source;
       let dest = self.grammar_path(lang);
       if dest.exists() {
          let language = fs::read(&dest)
             .await
             .map_err(Error::from)
-            .and_then(|bytes| self.load_language(lang, &bytes));
-         if let Ok(language) = language {
-            return Ok(language);
+            .and_then(|bytes| {
+               Self::verify(source, &bytes)?;
+               self.load_language(lang, &bytes)
+            });
+         match language {
+            Ok(language) => return Ok(language),
+            Err(e) => tracing::debug!("replacing grammar for {lang}: {e}"),
          }
       }
 
@@ -248,6 +304,7 @@ impl GrammarManager {
 
       tracing::info!("downloaded grammar for {}", lang);
 
+      Self::verify(source, &bytes)?;
       let language = self.load_language(lang, &bytes)?;
 
       fs::write(&dest, &bytes)
@@ -257,38 +314,46 @@ impl GrammarManager {
       Ok(language)
    }
 
-   /// Gets a language by name, downloading if necessary
+   /// Gets a language by name, downloading if necessary. When the download
+   /// fails, falls back to a grammar compiled into the binary, if any.
    pub async fn get_language(&self, lang: &str) -> Result<Option<Language>> {
-      let pair = GRAMMAR_URLS
+      let source = GRAMMAR_URLS
          .iter()
-         .find(|(l, _)| l.eq_ignore_ascii_case(lang));
-      let Some(pair) = pair else {
+         .find(|(l, ..)| l.eq_ignore_ascii_case(lang));
+      let Some(source) = source else {
          return Ok(None);
       };
 
-      if let Some(cached) = self.languages.get(&pair.0).await {
+      if let Some(cached) = self.languages.get(&source.0).await {
          return Ok(Some(cached));
       }
 
-      let language = match self.download_grammar(*pair).await {
+      let language = match self.download_grammar(*source).await {
          Ok(lang) => lang,
-         Err(e) => {
-            tracing::warn!("failed to download grammar for {}: {}", pair.0, e);
-            return Err(e);
+         Err(e) => match builtin::language(source.0) {
+            Some(lang) => {
+               tracing::debug!("using built-in grammar for {}: {}", source.0, e);
+               lang
+            },
+            None => {
+               tracing::warn!("failed to download grammar for {}: {}", source.0, e);
+               return Err(e);
+            },
          },
       };
 
-      self.languages.insert(pair.0, language.clone()).await;
+      self.languages.insert(source.0, language.clone()).await;
       Ok(Some(language))
    }
 
-   /// Gets a language for a file path based on its extension
-   pub async fn get_language_for_path(&self, path: &Path) -> Result<Option<Language>> {
-      let lang = path
-         .extension()
-         .and_then(|e| e.to_str())
-         .and_then(Self::extension_to_language);
-      let Some(lang) = lang else {
+   /// Gets a language for a file from its path and content; see
+   /// [`Self::language_for`].
+   pub async fn get_language_for_path(
+      &self,
+      path: &Path,
+      content: &str,
+   ) -> Result<Option<Language>> {
+      let Some(lang) = Self::language_for(path, content) else {
          return Ok(None);
       };
       self.get_language(lang).await
@@ -307,3 +372,35 @@ impl Default for GrammarManager {
       Self::new().expect("failed to create grammar manager")
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn pinned_grammars_name_a_release() {
+      for (lang, url, sha256) in GRAMMAR_URLS {
+         if let Some(sha256) = sha256 {
+            assert!(!url.contains("/releases/latest/"), "{lang} is pinned to {url}");
+            assert!(
+               sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()),
+               "{lang} has checksum {sha256}"
+            );
+         }
+      }
+   }
+
+   #[test]
+   fn verifies_pinned_checksums() {
+      let bytes = b"\0asm";
+      let sum: &str = FileHash::sum(bytes).to_string().leak();
+      let url = "https://example.com/v1.0.0/tree-sitter-x.wasm";
+
+      assert!(GrammarManager::verify(("x", url, None), bytes).is_ok());
+      assert!(GrammarManager::verify(("x", url, Some(sum)), bytes).is_ok());
+      assert!(matches!(
+         GrammarManager::verify(("x", url, Some(sum)), b"other"),
+         Err(Error::ChecksumMismatch { .. })
+      ));
+   }
+}
@@ -0,0 +1,172 @@
+//! User hook commands run around indexing and searching.
+//!
+//! Each hook is a shell command from the config. It runs in the repository
+//! root with a JSON payload on stdin and these environment variables:
+//!
+//! - `SMGREP_HOOK`: `pre_index`, `post_index` or `post_search`
+//! - `SMGREP_ROOT`: the repository root
+//! - `SMGREP_STORE_ID`: the store being indexed or searched
+//!
+//! Stderr is passed through. Only `post_search` reads stdout; the other hooks
+//! discard it so they cannot interfere with JSON or MCP output.
+
+use std::{path::Path, process::Stdio, time::Duration};
+
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, process::Command, time};
+
+use crate::{Result, config, error::Error};
+
+/// Point in a workflow at which a hook runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+   /// Before files are scanned for indexing; a failure aborts the index.
+   PreIndex,
+   /// After indexing finishes; a failure is only logged.
+   PostIndex,
+   /// After a CLI search, with the results as they would be printed by
+   /// `--json`; non-empty stdout replaces them.
+   PostSearch,
+}
+
+impl Hook {
+   pub const fn name(self) -> &'static str {
+      match self {
+         Self::PreIndex => "pre_index",
+         Self::PostIndex => "post_index",
+         Self::PostSearch => "post_search",
+      }
+   }
+
+   /// Configured command for this hook.
+   fn command(self) -> Option<&'static str> {
+      let cfg = config::get();
+      match self {
+         Self::PreIndex => cfg.pre_index_hook.as_deref(),
+         Self::PostIndex => cfg.post_index_hook.as_deref(),
+         Self::PostSearch => cfg.post_search_hook.as_deref(),
+      }
+      .filter(|c| !c.trim().is_empty())
+   }
+}
+
+/// Runs `hook` if configured, returning its stdout, or `None` when no command
+/// is set.
+pub async fn run<T: Serialize>(
+   hook: Hook,
+   root: &Path,
+   store_id: &str,
+   payload: &T,
+) -> Result<Option<Vec<u8>>> {
+   let Some(command) = hook.command() else {
+      return Ok(None);
+   };
+   let timeout = Duration::from_secs(config::get().hook_timeout_secs);
+   run_command(command, hook, root, store_id, payload, timeout)
+      .await
+      .map(Some)
+}
+
+/// Runs an index hook and logs a failure instead of returning it.
+pub async fn run_logged<T: Serialize>(hook: Hook, root: &Path, store_id: &str, payload: &T) {
+   if let Err(e) = run(hook, root, store_id, payload).await {
+      tracing::warn!("{e}");
+   }
+}
+
+async fn run_command<T: Serialize>(
+   command: &str,
+   hook: Hook,
+   root: &Path,
+   store_id: &str,
+   payload: &T,
+   timeout: Duration,
+) -> Result<Vec<u8>> {
+   let fail = |reason: String| Error::Hook { hook: hook.name(), reason };
+   let input = serde_json::to_vec(payload)?;
+
+   let mut child = shell(command)
+      .current_dir(root)
+      .env("SMGREP_HOOK", hook.name())
+      .env("SMGREP_ROOT", root)
+      .env("SMGREP_STORE_ID", store_id)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::inherit())
+      .kill_on_drop(true)
+      .spawn()
+      .map_err(|e| fail(format!("failed to run `{command}`: {e}")))?;
+
+   // Input is written while output is read so that a hook echoing a large
+   // payload cannot fill both pipes. A hook may exit without reading its
+   // input; that is not an error.
+   let stdin = child.stdin.take();
+   let write = async move {
+      if let Some(mut stdin) = stdin {
+         let _ = stdin.write_all(&input).await;
+      }
+   };
+
+   let (_, output) =
+      time::timeout(timeout, async { tokio::join!(write, child.wait_with_output()) })
+         .await
+         .map_err(|_| fail(format!("timed out after {}s", timeout.as_secs())))?;
+   let output = output.map_err(|e| fail(e.to_string()))?;
+
+   if !output.status.success() {
+      return Err(fail(format!("`{command}` exited with {}", output.status)));
+   }
+   Ok(output.stdout)
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+   let mut cmd = Command::new("sh");
+   cmd.arg("-c").arg(command);
+   cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+   let mut cmd = Command::new("cmd");
+   cmd.arg("/C").arg(command);
+   cmd
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+   use super::*;
+
+   const TIMEOUT: Duration = Duration::from_secs(10);
+
+   #[tokio::test]
+   async fn passes_payload_and_environment() {
+      let dir = tempfile::tempdir().unwrap();
+      let stdout = run_command(
+         r#"printf '%s %s ' "$SMGREP_HOOK" "$SMGREP_STORE_ID"; cat"#,
+         Hook::PostSearch,
+         dir.path(),
+         "store",
+         &serde_json::json!({ "results": [] }),
+         TIMEOUT,
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(String::from_utf8(stdout).unwrap(), r#"post_search store {"results":[]}"#);
+   }
+
+   #[tokio::test]
+   async fn reports_failures() {
+      let dir = tempfile::tempdir().unwrap();
+      let err = run_command("exit 3", Hook::PreIndex, dir.path(), "store", &(), TIMEOUT)
+         .await
+         .unwrap_err();
+      assert!(matches!(err, Error::Hook { hook: "pre_index", .. }), "{err}");
+
+      let err = run_command("sleep 5", Hook::PreIndex, dir.path(), "store", &(), Duration::ZERO)
+         .await
+         .unwrap_err();
+      assert!(err.to_string().contains("timed out"), "{err}");
+   }
+}
@@ -1,37 +1,253 @@
+//! Advisory locking of a store between processes.
+//!
+//! Everything that writes a store (syncs, the daemon's watcher and
+//! compaction, imports, cleanup) holds its lock exclusively, and commands
+//! that need a consistent view of it (export, verify) share it. The lock is
+//! an OS file lock on `<data_dir>/<store_id>.lock`, released by the OS when
+//! its holder exits; the exclusive holder writes its PID into the file so
+//! that waiters can tell who they are waiting for. Where the filesystem has
+//! no file locks, a `<store_id>.lock.pid` file created by the holder stands
+//! in for the lock, and is taken over once that process is no longer
+//! running.
+
 use std::{
-   fs::{self, File, OpenOptions},
-   path::PathBuf,
+   fs::{self, File, OpenOptions, TryLockError},
+   io::{self, Read, Seek, Write},
+   path::{Path, PathBuf},
+   process, thread,
+   time::{Duration, Instant},
 };
 
-use crate::{Result, config};
+use crate::{Result, config, error::Error};
+
+/// Interval between attempts to take a lock that is held.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockMode {
+   Shared,
+   Exclusive,
+}
 
+/// Lock on a store, released when dropped.
 pub struct IndexLock {
-   file: File,
+   file:   File,
+   mode:   LockMode,
+   /// PID file standing in for the OS lock, when there is none.
+   marker: Option<PathBuf>,
 }
 
 impl IndexLock {
+   /// Locks `store_id` for writing.
+   ///
+   /// Waits for other holders, giving up with [`Error::StoreLocked`] after
+   /// `lock_timeout_secs` if the lock is held by another process.
    pub fn acquire(store_id: &str) -> Result<Self> {
-      let lock_path: PathBuf = config::data_dir().join(format!("{store_id}.lock"));
+      Self::acquire_mode(store_id, LockMode::Exclusive)
+   }
+
+   /// Locks `store_id` for reading, alongside other readers but not writers.
+   pub fn acquire_shared(store_id: &str) -> Result<Self> {
+      Self::acquire_mode(store_id, LockMode::Shared)
+   }
 
+   fn acquire_mode(store_id: &str, mode: LockMode) -> Result<Self> {
+      let lock_path = config::data_dir().join(format!("{store_id}.lock"));
       if let Some(parent) = lock_path.parent() {
          fs::create_dir_all(parent)?;
       }
 
+      // Not truncated, as it holds the PID of the current holder
       let file = OpenOptions::new()
          .create(true)
-         .truncate(true)
+         .truncate(false)
          .read(true)
          .write(true)
          .open(&lock_path)?;
 
-      file.lock()?;
+      let mut waiter = Waiter::new(store_id);
+      loop {
+         let locked = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+         };
+         match locked {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => waiter.wait(read_pid(&file))?,
+            Err(TryLockError::Error(e)) if e.kind() == io::ErrorKind::Unsupported => {
+               let marker = lock_path.with_extension("lock.pid");
+               return Self::acquire_marker(file, marker, waiter);
+            },
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+         }
+      }
+
+      if mode == LockMode::Exclusive
+         && let Err(e) = write_pid(&file)
+      {
+         tracing::debug!("failed to record lock holder of {store_id}: {e}");
+      }
+      Ok(Self { file, mode, marker: None })
+   }
 
-      Ok(Self { file })
+   /// Takes the lock by creating `marker` with this process's PID, removing
+   /// it first if the process that created it is gone.
+   fn acquire_marker(file: File, marker: PathBuf, mut waiter: Waiter<'_>) -> Result<Self> {
+      loop {
+         match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&marker)
+         {
+            Ok(mut created) => {
+               write!(created, "{}", process::id())?;
+               return Ok(Self { file, mode: LockMode::Exclusive, marker: Some(marker) });
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+               // Empty while its creator is still writing the PID
+               let holder = fs::read_to_string(&marker)
+                  .ok()
+                  .and_then(|pid| pid.trim().parse().ok());
+               if let Some(pid) = holder
+                  && !process_alive(pid)
+               {
+                  tracing::warn!(
+                     "removing stale lock of {} held by process {pid}",
+                     waiter.store_id
+                  );
+                  remove_marker(&marker);
+                  continue;
+               }
+               waiter.wait(holder)?;
+            },
+            Err(e) => return Err(e.into()),
+         }
+      }
    }
 }
 
 impl Drop for IndexLock {
    fn drop(&mut self) {
+      if let Some(marker) = &self.marker {
+         remove_marker(marker);
+         return;
+      }
+      if self.mode == LockMode::Exclusive {
+         let _ = self.file.set_len(0);
+      }
       let _ = self.file.unlock();
    }
 }
+
+/// Paces attempts to take a held lock, up to the timeout when it is held by
+/// another process. Locks held within this process, as by the daemon's
+/// own sync, are waited for indefinitely.
+struct Waiter<'a> {
+   store_id: &'a str,
+   deadline: Option<Instant>,
+   logged:   bool,
+}
+
+impl<'a> Waiter<'a> {
+   fn new(store_id: &'a str) -> Self {
+      let timeout = config::get().lock_timeout_secs;
+      Self {
+         store_id,
+         deadline: (timeout > 0).then(|| Instant::now() + Duration::from_secs(timeout)),
+         logged: false,
+      }
+   }
+
+   /// Sleeps before the next attempt, or fails once the lock has been held
+   /// by another process for too long. `holder` is its PID, if known.
+   fn wait(&mut self, holder: Option<u32>) -> Result<()> {
+      let ours = holder == Some(process::id());
+      if !ours
+         && self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+      {
+         return Err(Error::StoreLocked { store_id: self.store_id.to_string(), holder });
+      }
+      if !ours && !self.logged {
+         let who =
+            holder.map_or_else(|| "another process".to_string(), |pid| format!("process {pid}"));
+         tracing::info!("waiting for {who} to release {}", self.store_id);
+         self.logged = true;
+      }
+      thread::sleep(POLL_INTERVAL);
+      Ok(())
+   }
+}
+
+/// PID recorded in a lock file, if its holder wrote one.
+fn read_pid(mut file: &File) -> Option<u32> {
+   let mut pid = String::new();
+   file.rewind().ok()?;
+   file.read_to_string(&mut pid).ok()?;
+   pid.trim().parse().ok()
+}
+
+fn write_pid(mut file: &File) -> io::Result<()> {
+   file.set_len(0)?;
+   file.rewind()?;
+   write!(file, "{}", process::id())
+}
+
+fn remove_marker(marker: &Path) {
+   if let Err(e) = fs::remove_file(marker)
+      && e.kind() != io::ErrorKind::NotFound
+   {
+      tracing::warn!("failed to remove {}: {e}", marker.display());
+   }
+}
+
+/// Whether a process with `pid` is running.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+   let Ok(pid) = libc::pid_t::try_from(pid) else {
+      return false;
+   };
+   // SAFETY: signal 0 performs the existence and permission checks of kill(2)
+   // without sending anything.
+   let sent = unsafe { libc::kill(pid, 0) };
+   sent == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process with `pid` is running; without a way to tell, any is
+/// assumed to be.
+#[cfg(not(unix))]
+const fn process_alive(_pid: u32) -> bool {
+   true
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn records_holder_pid() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let path = dir.path().join("store.lock");
+      let file = OpenOptions::new()
+         .create_new(true)
+         .read(true)
+         .write(true)
+         .open(&path)
+         .unwrap();
+      assert_eq!(read_pid(&file), None);
+
+      write_pid(&file).unwrap();
+      assert_eq!(read_pid(&file), Some(process::id()));
+      assert!(process_alive(process::id()));
+   }
+
+   #[cfg(unix)]
+   #[test]
+   fn detects_exited_process() {
+      let mut child = process::Command::new("true").spawn().unwrap();
+      let pid = child.id();
+      child.wait().unwrap();
+      assert!(!process_alive(pid));
+   }
+}
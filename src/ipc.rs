@@ -4,17 +4,164 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use crate::{Result, error::IpcError, types::SearchResponse};
+use crate::{
+   Result,
+   error::IpcError,
+   meta::SyncRun,
+   search::testfile::TestFilter,
+   types::{SearchResponse, SyncProgress},
+};
+
+/// Largest frame the daemon accepts from a client. Requests carry a query and
+/// a handful of small fields, so anything bigger is rejected before
+/// deserialization.
+pub const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Largest frame a client accepts from the daemon.
+pub const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest search query, in bytes.
+pub const MAX_QUERY_LEN: usize = 4096;
+
+/// Largest number of results a single search may ask for.
+pub const MAX_SEARCH_LIMIT: usize = 1000;
+
+/// Most components a search path filter may have.
+pub const MAX_PATH_COMPONENTS: usize = 256;
+
+/// Most include and exclude globs a single search may carry.
+pub const MAX_GLOBS: usize = 64;
+
+/// Most candidates a single search may rescore with `ColBERT`.
+pub const MAX_RERANK_DEPTH: usize = 2000;
+
+/// Longest short string field (git hash, crate name, glob), in bytes.
+const MAX_NAME_LEN: usize = 256;
 
 /// Client request messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
-   Hello { git_hash: String },
-   Search { query: String, limit: usize, path: Option<PathBuf>, rerank: bool },
+   Hello {
+      git_hash: String,
+   },
+   Search {
+      query:         String,
+      limit:         usize,
+      path:          Option<PathBuf>,
+      rerank:        bool,
+      /// Scan every vector instead of using the approximate index.
+      exact:         bool,
+      /// Skip embeddings and query only the full-text index.
+      keyword:       bool,
+      /// Only match files indexed at or after this Unix timestamp (seconds).
+      indexed_after: Option<u64>,
+      /// Only match chunks from this Cargo workspace member.
+      crate_name:    Option<String>,
+      /// Keep only test code, or drop it, judging paths relative to `path`.
+      tests:         TestFilter,
+      /// Only match files accepted by one of these globs, relative to `path`.
+      include:       Vec<String>,
+      /// Skip files matching any of these globs, relative to `path`.
+      exclude:       Vec<String>,
+      /// Candidates to rescore with `ColBERT`; `None` uses `rerank_depth`.
+      rerank_depth:  Option<usize>,
+      /// Rescore the top results with the cross-encoder after `ColBERT`.
+      cross_rerank:  bool,
+      /// Client-chosen id under which a [`Request::Cancel`] can stop this
+      /// search.
+      request_id:    Option<u64>,
+   },
    Health,
-   Shutdown,
+   /// Stops the daemon. Unless `force` is set, files being indexed are
+   /// finished and their metadata saved first.
+   Shutdown {
+      force: bool,
+   },
+   /// Sync history of the daemon's store, oldest run first.
+   History,
+   /// Frees the daemon's models without stopping it; the next search loads
+   /// them again.
+   Unload,
+   /// Loads the daemon's models and store and runs a throwaway search
+   /// through them, so that the next search starts warm.
+   WarmUp,
+   /// Starts a sync of the daemon's root unless one is already running.
+   Sync,
+   /// Follows the running sync: the daemon answers with a
+   /// [`Response::Progress`] frame per update, the last one marked `done`.
+   Progress,
+   /// Stops the search started with this `request_id`, from this or any
+   /// other connection. The daemon answers as soon as it reads the request,
+   /// so on the search's own connection the answer may arrive before the
+   /// search's, which is then an error.
+   Cancel {
+      request_id: u64,
+   },
+}
+
+impl Request {
+   /// Checks every field against the protocol limits.
+   pub fn validate(&self) -> Result<(), IpcError> {
+      match self {
+         Self::Hello { git_hash } => check_len("git hash", git_hash, MAX_NAME_LEN),
+         Self::Search {
+            query, limit, path, crate_name, include, exclude, rerank_depth, ..
+         } => {
+            if query.trim().is_empty() {
+               return Err(IpcError::InvalidRequest("query is required".to_string()));
+            }
+            check_len("query", query, MAX_QUERY_LEN)?;
+            if !(1..=MAX_SEARCH_LIMIT).contains(limit) {
+               return Err(IpcError::InvalidRequest(format!(
+                  "limit must be between 1 and {MAX_SEARCH_LIMIT}, got {limit}"
+               )));
+            }
+            if let Some(path) = path
+               && path.components().count() > MAX_PATH_COMPONENTS
+            {
+               return Err(IpcError::InvalidRequest(format!(
+                  "path has more than {MAX_PATH_COMPONENTS} components"
+               )));
+            }
+            if let Some(depth) = rerank_depth
+               && *depth > MAX_RERANK_DEPTH
+            {
+               return Err(IpcError::InvalidRequest(format!(
+                  "rerank depth must be at most {MAX_RERANK_DEPTH}, got {depth}"
+               )));
+            }
+            if include.len() + exclude.len() > MAX_GLOBS {
+               return Err(IpcError::InvalidRequest(format!("more than {MAX_GLOBS} path globs")));
+            }
+            for glob in include.iter().chain(exclude) {
+               check_len("glob", glob, MAX_NAME_LEN)?;
+            }
+            crate_name
+               .as_deref()
+               .map_or(Ok(()), |name| check_len("crate name", name, MAX_NAME_LEN))
+         },
+         Self::Health
+         | Self::Shutdown { .. }
+         | Self::History
+         | Self::Unload
+         | Self::WarmUp
+         | Self::Sync
+         | Self::Progress
+         | Self::Cancel { .. } => Ok(()),
+      }
+   }
+}
+
+fn check_len(field: &str, value: &str, max: usize) -> Result<(), IpcError> {
+   if value.len() > max {
+      return Err(IpcError::InvalidRequest(format!(
+         "{field} is {} bytes, limit is {max}",
+         value.len()
+      )));
+   }
+   Ok(())
 }
 
 /// Server response messages
@@ -25,14 +172,33 @@ pub enum Response {
    Health { status: ServerStatus },
    Shutdown { success: bool },
    Error { message: String },
+   History { runs: Vec<SyncRun> },
+   Unload { unloaded: bool },
+   WarmUp { ms: u64 },
+   Sync { started: bool },
+   Progress { progress: SyncProgress, done: bool },
+   Cancel { found: bool },
 }
 
 /// Server health status information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerStatus {
-   pub indexing: bool,
-   pub progress: u8,
-   pub files:    usize,
+   pub indexing:      bool,
+   pub progress:      u8,
+   /// Files with recorded metadata.
+   pub files:         usize,
+   /// Chunks in the store.
+   pub rows:          u64,
+   /// Whether the embedding models are in memory.
+   pub models_loaded: bool,
+   /// Device the models run on, such as `cpu` or `cuda:0`.
+   pub device:        String,
+   /// Unix timestamp (seconds) at which the last recorded sync finished.
+   pub last_sync:     Option<u64>,
+   /// Resident memory of the daemon in bytes, where the platform reports it.
+   pub memory_bytes:  Option<u64>,
+   /// Embedding batches waiting for a worker.
+   pub queue_depth:   usize,
 }
 
 /// Stack-allocated buffer for socket I/O operations
@@ -83,6 +249,32 @@ impl SocketBuffer {
    where
       R: AsyncRead + Unpin,
       T: Deserialize<'de>,
+   {
+      self.read_frame(reader, MAX_RESPONSE_SIZE).await?;
+      postcard::from_bytes(&self.buf).map_err(|e| IpcError::Deserialize(e).into())
+   }
+
+   /// Receives a client request, enforcing [`MAX_REQUEST_SIZE`] and
+   /// [`Request::validate`].
+   ///
+   /// Oversized, undecodable and invalid frames are consumed in full, so on
+   /// errors where [`IpcError::is_malformed_frame`] holds the caller can reply
+   /// and keep serving the connection.
+   pub async fn recv_request<R>(&mut self, reader: &mut R) -> Result<Request>
+   where
+      R: AsyncRead + Unpin,
+   {
+      self.read_frame(reader, MAX_REQUEST_SIZE).await?;
+      let request: Request = postcard::from_bytes(&self.buf).map_err(IpcError::Deserialize)?;
+      request.validate()?;
+      Ok(request)
+   }
+
+   /// Reads one length-prefixed frame into the buffer. Frames over `limit`
+   /// are skipped rather than buffered.
+   async fn read_frame<R>(&mut self, reader: &mut R, limit: usize) -> Result<(), IpcError>
+   where
+      R: AsyncRead + Unpin,
    {
       let mut len_buf = [0u8; 4];
       reader
@@ -91,8 +283,14 @@ impl SocketBuffer {
          .map_err(IpcError::Read)?;
       let len = u32::from_le_bytes(len_buf) as usize;
 
-      if len > 16 * 1024 * 1024 {
-         return Err(IpcError::MessageTooLarge(len).into());
+      if len > limit {
+         let skipped = io::copy(&mut (&mut *reader).take(len as u64), &mut io::sink())
+            .await
+            .map_err(IpcError::Read)?;
+         if skipped < len as u64 {
+            return Err(IpcError::Read(io::ErrorKind::UnexpectedEof.into()));
+         }
+         return Err(IpcError::MessageTooLarge(len));
       }
 
       self.buf.resize(len, 0u8);
@@ -100,6 +298,74 @@ impl SocketBuffer {
          .read_exact(self.buf.as_mut_slice())
          .await
          .map_err(IpcError::Read)?;
-      postcard::from_bytes(&self.buf).map_err(|e| IpcError::Deserialize(e).into())
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn frame(payload: &[u8]) -> Vec<u8> {
+      let mut out = (payload.len() as u32).to_le_bytes().to_vec();
+      out.extend_from_slice(payload);
+      out
+   }
+
+   fn search(query: &str, limit: usize) -> Request {
+      Request::Search {
+         query: query.to_string(),
+         limit,
+         path: None,
+         rerank: true,
+         exact: false,
+         keyword: false,
+         indexed_after: None,
+         crate_name: None,
+         tests: TestFilter::All,
+         include: vec![],
+         exclude: vec![],
+         rerank_depth: None,
+         cross_rerank: false,
+         request_id: None,
+      }
+   }
+
+   #[test]
+   fn validates_search_bounds() {
+      assert!(search("retry policy", 10).validate().is_ok());
+      assert!(search("  ", 10).validate().is_err());
+      assert!(search("retry", 0).validate().is_err());
+      assert!(search("retry", MAX_SEARCH_LIMIT + 1).validate().is_err());
+
+      let mut deep = search("retry", 10);
+      if let Request::Search { rerank_depth, .. } = &mut deep {
+         *rerank_depth = Some(MAX_RERANK_DEPTH + 1);
+      }
+      assert!(deep.validate().is_err());
+      assert!(
+         search(&"q".repeat(MAX_QUERY_LEN + 1), 10)
+            .validate()
+            .is_err()
+      );
+   }
+
+   #[tokio::test]
+   async fn malformed_frames_leave_stream_usable() {
+      let mut wire = frame(&vec![0xff; MAX_REQUEST_SIZE + 1]);
+      wire.extend(frame(&[0xff, 0xff, 0xff]));
+      wire.extend(frame(&postcard::to_allocvec(&search("", 10)).unwrap()));
+      wire.extend(frame(&postcard::to_allocvec(&Request::Health).unwrap()));
+
+      let mut reader = wire.as_slice();
+      let mut buffer = SocketBuffer::new();
+      for _ in 0..3 {
+         match buffer.recv_request(&mut reader).await {
+            Err(crate::Error::Ipc(e)) => assert!(e.is_malformed_frame(), "{e}"),
+            other => panic!("expected malformed frame, got {other:?}"),
+         }
+      }
+      assert!(matches!(buffer.recv_request(&mut reader).await, Ok(Request::Health)));
+      assert!(buffer.recv_request(&mut reader).await.is_err());
    }
 }
@@ -6,27 +6,36 @@
 
 #![feature(portable_simd)]
 
+pub mod archive;
 pub mod chunker;
 pub mod cmd;
 pub mod config;
+pub mod disk;
+pub mod docs;
 pub mod embed;
 pub mod error;
+pub mod eval;
 pub mod file;
 pub mod format;
 pub mod git;
 pub mod grammar;
+pub mod hooks;
 pub mod index_lock;
 pub mod ipc;
 pub mod meta;
+pub mod reranker;
 pub mod search;
 pub mod serde_arc_pathbuf;
 mod sstr;
 pub mod store;
+pub mod summarize;
 pub mod sync;
 pub mod types;
+pub mod usage;
 pub mod usock;
 pub mod util;
 pub mod version;
+pub mod workspace;
 
 pub use error::{Error, Result};
 pub use sstr::Str;
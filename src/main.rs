@@ -1,13 +1,26 @@
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+   path::{Path, PathBuf},
+   sync::LazyLock,
+   time::Duration,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use smgrep::{
    Result,
-   cmd::{self, search::SearchOptions},
-   version,
+   cmd::{
+      self, bench::BenchOptions, check::CheckOptions, eval::EvalOptions, search::SearchOptions,
+      similar::CodeRange,
+   },
+   config::{self, Profile, StoreTarget},
+   reranker::RerankMode,
+   search::testfile::TestFilter,
+   util, version,
 };
 use tracing::Level;
-use tracing_subscriber::EnvFilter;
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard, TraceStyle};
+use tracing_subscriber::{
+   EnvFilter, Layer, filter::Targets, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
 static VERSION_STRING: LazyLock<String> = LazyLock::new(version::version_string);
 
@@ -24,6 +37,34 @@ struct Cli {
    #[arg(long, env = "SMGREP_STORE")]
    store: Option<String>,
 
+   #[arg(
+      long,
+      global = true,
+      value_enum,
+      help = "Settings preset (overrides config and SMGREP_PROFILE)"
+   )]
+   profile: Option<Profile>,
+
+   #[arg(
+      long,
+      global = true,
+      value_name = "ID",
+      help = "Dense embedding model to use, binding the store to it"
+   )]
+   model: Option<String>,
+
+   #[arg(
+      long,
+      global = true,
+      value_name = "FILE",
+      num_args = 0..=1,
+      require_equals = true,
+      help = "Write a Chrome trace of where the command spends its time (default: \
+              smgrep-<command>-<time>.trace.json)"
+   )]
+   #[allow(clippy::option_option, reason = "tells `--trace` from `--trace=FILE`")]
+   trace: Option<Option<PathBuf>>,
+
    #[command(subcommand)]
    command: Option<Cmd>,
 
@@ -36,8 +77,8 @@ struct Cli {
 enum Cmd {
    #[command(about = "Search indexed code semantically")]
    Search {
-      #[arg(help = "Search query")]
-      query: String,
+      #[arg(help = "Search query", required_unless_present = "batch")]
+      query: Option<String>,
 
       #[arg(help = "Directory to search (default: cwd)")]
       path: Option<PathBuf>,
@@ -75,8 +116,243 @@ enum Cmd {
       #[arg(long, help = "Skip ColBERT reranking")]
       no_rerank: bool,
 
+      #[arg(
+         long,
+         value_name = "N",
+         conflicts_with = "no_rerank",
+         help = "Rescore the top N candidates with ColBERT (default: rerank_depth)"
+      )]
+      rerank_depth: Option<usize>,
+
+      #[arg(
+         long,
+         value_enum,
+         value_name = "MODE",
+         default_value_t = RerankMode::Colbert,
+         conflicts_with = "no_rerank",
+         help = "Rescore with ColBERT, or with ColBERT then a cross-encoder over the top results"
+      )]
+      rerank: RerankMode,
+
       #[arg(long, help = "Disable ANSI colors and use simpler formatting")]
       plain: bool,
+
+      #[arg(long, help = "Scan every vector instead of using the approximate index")]
+      exact: bool,
+
+      #[arg(
+         long,
+         conflicts_with = "exact",
+         help = "Match keywords with the full-text index only, without embedding the query"
+      )]
+      keyword: bool,
+
+      #[arg(
+         long,
+         help = "Re-index if the store was built with different models; stop a daemon serving the \
+                 store first"
+      )]
+      migrate: bool,
+
+      #[arg(
+         long,
+         value_name = "AGE",
+         value_parser = util::parse_age,
+         help = "Only match files indexed within AGE (e.g. 12h, 7d, 2w)"
+      )]
+      changed_since: Option<Duration>,
+
+      #[arg(
+         long,
+         help = "Re-read results from the working tree and mark code changed since indexing"
+      )]
+      fresh: bool,
+
+      #[arg(
+         long,
+         requires = "fresh",
+         help = "With --fresh, also show the indexed version of changed results"
+      )]
+      show_stored: bool,
+
+      #[arg(long, help = "Preview the chunks before and after each result")]
+      show_context: bool,
+
+      #[arg(
+         long = "crate",
+         value_name = "NAME",
+         help = "Only match code from this Cargo workspace member"
+      )]
+      crate_name: Option<String>,
+
+      #[arg(
+         long,
+         value_name = "GLOB",
+         help = "Only match files matching GLOB, relative to the search path (repeatable)"
+      )]
+      include: Vec<String>,
+
+      #[arg(long, value_name = "GLOB", help = "Skip files matching GLOB (repeatable)")]
+      exclude: Vec<String>,
+
+      #[arg(long, help = "Show the dense, full-text and ColBERT scores behind each result")]
+      explain: bool,
+
+      #[arg(long, help = "Show the author and date of the last commit to change each result")]
+      blame: bool,
+
+      #[arg(
+         long,
+         value_name = "NAME",
+         help = "Only match files last changed by an author whose name or email contains NAME"
+      )]
+      author: Option<String>,
+
+      #[arg(
+         long,
+         conflicts_with = "no_tests",
+         help = "Only match test code: test files, test directories and Rust test items"
+      )]
+      tests_only: bool,
+
+      #[arg(long, help = "Skip test code: test files, test directories and Rust test items")]
+      no_tests: bool,
+
+      #[arg(
+         long,
+         value_name = "FILE",
+         conflicts_with_all = ["path", "dry_run"],
+         help = "Run a search per line of FILE (- for stdin); the directory is then the only \
+                 positional argument"
+      )]
+      batch: Option<PathBuf>,
+   },
+
+   #[command(about = "Rank files by their imports, exports and opening lines")]
+   Files {
+      #[arg(help = "Search query")]
+      query: String,
+
+      #[arg(help = "Directory to search (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(short = 'm', long, default_value = "10", help = "Maximum files")]
+      max: usize,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Find indexed code similar to a range of lines")]
+   Similar {
+      #[arg(
+         value_name = "PATH:START-END",
+         value_parser = CodeRange::parse,
+         help = "Lines to match, 1-based and inclusive (e.g. src/lib.rs:10-40)"
+      )]
+      range: CodeRange,
+
+      #[arg(help = "Directory to search (default: whole index)")]
+      path: Option<PathBuf>,
+
+      #[arg(short = 'm', long, default_value = "10", help = "Maximum results")]
+      max: usize,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Fail when added code duplicates indexed code or matches a policy")]
+   Check {
+      #[arg(help = "Directory to check (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(long, default_value = "HEAD", help = "Revision to compare the working tree against")]
+      base: String,
+
+      #[arg(
+         long,
+         default_value = "0.9",
+         help = "Similarity to indexed code at which added code counts as a clone"
+      )]
+      threshold: f32,
+
+      #[arg(long, help = "Only apply policy rules, skipping the clone check")]
+      no_clones: bool,
+
+      #[arg(long, value_name = "FILE", help = "Policy file (default: .smgrep-policy.toml)")]
+      policy: Option<PathBuf>,
+
+      #[arg(long, default_value = "3", help = "Ignore added hunks with fewer non-blank lines")]
+      min_lines: usize,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Report clusters of near-identical indexed chunks")]
+   Dupes {
+      #[arg(help = "Directory to scan (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(long, default_value = "0.95", help = "Similarity at which chunks count as duplicates")]
+      threshold: f32,
+
+      #[arg(short = 'm', long, default_value = "20", help = "Maximum clusters")]
+      max: usize,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Measure search quality against a suite of queries and expected results")]
+   Eval {
+      #[arg(help = "YAML file of queries and the code each should find")]
+      suite: PathBuf,
+
+      #[arg(help = "Directory to search; expected paths are relative to it (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(
+         short = 'k',
+         value_delimiter = ',',
+         default_value = "1,5,10",
+         help = "Cutoffs to report recall at"
+      )]
+      ks: Vec<usize>,
+
+      #[arg(long, default_value = "1", help = "Maximum results per file")]
+      per_file: usize,
+
+      #[arg(long, help = "Skip ColBERT reranking")]
+      no_rerank: bool,
+
+      #[arg(
+         long,
+         value_enum,
+         default_value_t = RerankMode::Colbert,
+         conflicts_with = "no_rerank",
+         help = "Rescore with ColBERT, or with ColBERT then a cross-encoder over the top results"
+      )]
+      rerank: RerankMode,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Time chunking, embedding, inserts and searches for regression tracking")]
+   Bench {
+      #[arg(help = "Directory to sample files from and search (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(long, default_value = "200", help = "Files to chunk, embed and insert")]
+      files: usize,
+
+      #[arg(long, default_value = "20", help = "Searches to time with and without reranking")]
+      queries: usize,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
    },
 
    #[command(about = "Index a directory for semantic search")]
@@ -89,6 +365,21 @@ enum Cmd {
 
       #[arg(short = 'r', long, help = "Delete and re-index")]
       reset: bool,
+
+      #[arg(long, help = "Hash every file, even those whose mtime and size are unchanged")]
+      paranoid: bool,
+   },
+
+   #[command(about = "Check the index against its metadata and the files on disk")]
+   Verify {
+      #[arg(help = "Directory of the index to check (default: cwd)")]
+      path: Option<PathBuf>,
+
+      #[arg(long, help = "Drop mismatched files from the index so the next sync re-indexes them")]
+      repair: bool,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
    },
 
    #[command(about = "Start a background daemon for faster searches")]
@@ -101,13 +392,64 @@ enum Cmd {
    Stop {
       #[arg(long, help = "Directory of server to stop (default: cwd)")]
       path: Option<PathBuf>,
+
+      #[arg(long, help = "Stop immediately instead of finishing files being indexed")]
+      force: bool,
    },
 
    #[command(name = "stop-all", about = "Stop all running daemons")]
-   StopAll,
+   StopAll {
+      #[arg(long, help = "Stop immediately instead of finishing files being indexed")]
+      force: bool,
+   },
+
+   #[command(about = "Free a daemon's models without stopping it")]
+   Unload {
+      #[arg(long, help = "Directory of server to unload (default: cwd)")]
+      path: Option<PathBuf>,
+   },
+
+   #[command(about = "Load a daemon's models and index ahead of the first search")]
+   Warmup {
+      #[arg(long, help = "Directory of server to warm up (default: cwd)")]
+      path: Option<PathBuf>,
+   },
 
    #[command(about = "Show status of running daemons")]
-   Status,
+   Status {
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
+
+   #[command(about = "Show statistics recorded on this machine")]
+   Stats {
+      #[arg(
+         long,
+         required_unless_present_any = ["history", "index"],
+         help = "Show local search usage statistics"
+      )]
+      usage: bool,
+
+      #[arg(
+         long,
+         conflicts_with_all = ["usage", "reset"],
+         help = "Show recent sync runs of the current store"
+      )]
+      history: bool,
+
+      #[arg(
+         long,
+         conflicts_with_all = ["usage", "reset", "history"],
+         help = "Show the size of the current store's dense vectors"
+      )]
+      index: bool,
+
+      #[arg(long, help = "Clear recorded usage statistics")]
+      reset: bool,
+
+      #[arg(long, help = "JSON output")]
+      json: bool,
+   },
 
    #[command(about = "Remove index data and metadata for a store")]
    Clean {
@@ -118,14 +460,48 @@ enum Cmd {
       all: bool,
    },
 
+   #[command(about = "Export a store as a portable archive")]
+   Export {
+      #[arg(help = "Store ID to export (default: current directory's store)")]
+      store_id: Option<String>,
+
+      #[arg(short = 'o', long, help = "Output file (default: <store>.smgrep)")]
+      output: Option<PathBuf>,
+   },
+
+   #[command(about = "Import a store from an exported archive")]
+   Import {
+      #[arg(help = "Archive file to import")]
+      archive: PathBuf,
+
+      #[arg(long, help = "Store ID to import as (default: ID recorded in the archive)")]
+      store_id: Option<String>,
+
+      #[arg(short = 'f', long, help = "Replace an existing store and ignore model mismatches")]
+      force: bool,
+   },
+
    #[command(about = "Download and configure embedding models")]
-   Setup,
+   Setup {
+      #[arg(
+         long,
+         value_name = "PATH",
+         help = "Install models and grammars from a local directory or tar archive"
+      )]
+      from_dir: Option<PathBuf>,
+   },
 
    #[command(about = "Check system configuration and dependencies")]
    Doctor,
 
-   #[command(about = "List indexed files in a directory")]
-   List,
+   #[command(about = "List indexed stores, or the files in the current store")]
+   List {
+      #[arg(long, help = "List files in the current store with their index times")]
+      files: bool,
+   },
+
+   #[command(about = "List registered embedding models and the stores bound to them")]
+   Models,
 
    #[command(name = "claude-install", about = "Install smgrep as a Claude Code MCP server")]
    ClaudeInstall,
@@ -134,12 +510,51 @@ enum Cmd {
    Mcp,
 }
 
-fn main() -> Result<()> {
-   tracing_subscriber::fmt()
-      .with_env_filter(EnvFilter::from_default_env().add_directive(Level::WARN.into()))
-      .init();
+impl Cmd {
+   /// Directory whose store the command works on, when it takes one.
+   fn path(&self) -> Option<&Path> {
+      match self {
+         Self::Search { path, .. }
+         | Self::Files { path, .. }
+         | Self::Similar { path, .. }
+         | Self::Check { path, .. }
+         | Self::Dupes { path, .. }
+         | Self::Eval { path, .. }
+         | Self::Bench { path, .. }
+         | Self::Index { path, .. }
+         | Self::Verify { path, .. }
+         | Self::Serve { path }
+         | Self::Stop { path, .. }
+         | Self::Unload { path }
+         | Self::Warmup { path } => path.as_deref(),
+         _ => None,
+      }
+   }
+}
 
-   let cli = Cli::parse();
+fn main() -> Result<()> {
+   let matches = Cli::command().get_matches();
+   let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+   let trace = cli.trace.clone().map(|file| {
+      file.unwrap_or_else(|| {
+         let command = matches.subcommand_name().unwrap_or("search");
+         PathBuf::from(format!("smgrep-{command}-{}.trace.json", util::unix_now()))
+      })
+   });
+   // Flushes the trace when main returns
+   let _trace_guard = init_tracing(trace);
+
+   if let Some(profile) = cli.profile {
+      config::set_profile_override(profile);
+   }
+   config::set_store_target(StoreTarget {
+      store_id: cli.store.clone(),
+      path:     cli
+         .command
+         .as_ref()
+         .and_then(Cmd::path)
+         .map(Path::to_path_buf),
+   });
 
    // On macOS Apple Silicon with Metal, use single-threaded runtime for the serve
    // command. The candle Metal backend creates a command buffer at initialization
@@ -167,7 +582,46 @@ fn main() -> Result<()> {
    }
 }
 
+/// Logs warnings, or what `RUST_LOG` asks for, and records smgrep's
+/// spans to `trace` in the Chrome trace format when given.
+fn init_tracing(trace: Option<PathBuf>) -> Option<FlushGuard> {
+   let log = tracing_subscriber::fmt::layer()
+      .with_filter(EnvFilter::from_default_env().add_directive(Level::WARN.into()));
+   let Some(path) = trace else {
+      tracing_subscriber::registry().with(log).init();
+      return None;
+   };
+   let (chrome, guard) = ChromeLayerBuilder::new()
+      .file(&path)
+      .trace_style(TraceStyle::Async)
+      .include_args(true)
+      .build();
+   tracing_subscriber::registry()
+      .with(log)
+      .with(chrome.with_filter(Targets::new().with_target("smgrep", Level::TRACE)))
+      .init();
+   eprintln!("Writing trace to {}", path.display());
+   Some(guard)
+}
+
 async fn run_command(cli: Cli) -> Result<()> {
+   if let Some(model) = cli.model.clone() {
+      let (bind, path) = match &cli.command {
+         None | Some(Cmd::Mcp) => (true, None),
+         Some(
+            command @ (Cmd::Search { .. }
+            | Cmd::Index { .. }
+            | Cmd::Serve { .. }
+            | Cmd::Check { .. }
+            | Cmd::Similar { .. }
+            | Cmd::Eval { .. }
+            | Cmd::Bench { .. }),
+         ) => (true, command.path()),
+         Some(_) => (false, None),
+      };
+      cmd::models::select(model, bind, cli.store.as_deref(), path).await?;
+   }
+
    if cli.command.is_none() && !cli.query.is_empty() {
       let query = cli.query.join(" ");
       return cmd::search::execute(query, None, 10, 1, SearchOptions::default(), cli.store).await;
@@ -186,29 +640,133 @@ async fn run_command(cli: Cli) -> Result<()> {
          dry_run,
          json,
          no_rerank,
+         rerank_depth,
+         rerank,
          plain,
+         exact,
+         keyword,
+         migrate,
+         changed_since,
+         fresh,
+         show_stored,
+         show_context,
+         crate_name,
+         include,
+         exclude,
+         explain,
+         blame,
+         author,
+         tests_only,
+         no_tests,
+         batch,
       }) => {
-         cmd::search::execute(
-            query,
+         let options = SearchOptions {
+            content,
+            compact,
+            scores,
+            sync,
+            dry_run,
+            json,
+            no_rerank,
+            rerank_depth,
+            rerank,
+            plain,
+            exact,
+            keyword,
+            migrate,
+            changed_since,
+            fresh,
+            show_stored,
+            show_context,
+            crate_name,
+            include,
+            exclude,
+            explain,
+            blame,
+            author,
+            tests: if tests_only {
+               TestFilter::Only
+            } else if no_tests {
+               TestFilter::Exclude
+            } else {
+               TestFilter::All
+            },
+         };
+         match batch {
+            // A batch takes its queries from the file, so the only positional
+            // argument is the directory.
+            Some(batch) => {
+               let path = query.map(PathBuf::from);
+               cmd::search::execute_batch(batch, path, max, per_file, options, cli.store).await
+            },
+            None => {
+               let query = query.unwrap_or_default();
+               cmd::search::execute(query, path, max, per_file, options, cli.store).await
+            },
+         }
+      },
+      Some(Cmd::Files { query, path, max, json }) => {
+         cmd::files::execute(query, path, max, json, cli.store).await
+      },
+      Some(Cmd::Similar { range, path, max, json }) => {
+         cmd::similar::execute(range, path, max, json, cli.store).await
+      },
+      Some(Cmd::Check { path, base, threshold, no_clones, policy, min_lines, json }) => {
+         cmd::check::execute(
             path,
-            max,
-            per_file,
-            SearchOptions { content, compact, scores, sync, dry_run, json, no_rerank, plain },
+            CheckOptions {
+               base,
+               threshold: (!no_clones).then_some(threshold),
+               policy,
+               min_lines,
+               json,
+            },
             cli.store,
          )
          .await
       },
-      Some(Cmd::Index { path, dry_run, reset }) => {
-         cmd::index::execute(path, dry_run, reset, cli.store).await
+      Some(Cmd::Dupes { path, threshold, max, json }) => {
+         cmd::dupes::execute(path, threshold, max, json, cli.store).await
+      },
+      Some(Cmd::Eval { suite, path, ks, per_file, no_rerank, rerank, json }) => {
+         cmd::eval::execute(
+            suite,
+            path,
+            EvalOptions { ks, per_file, no_rerank, rerank, json },
+            cli.store,
+         )
+         .await
+      },
+      Some(Cmd::Bench { path, files, queries, json }) => {
+         cmd::bench::execute(path, BenchOptions { files, queries, json }, cli.store).await
+      },
+      Some(Cmd::Index { path, dry_run, reset, paranoid }) => {
+         cmd::index::execute(path, dry_run, reset, paranoid, cli.store).await
+      },
+      Some(Cmd::Verify { path, repair, json }) => {
+         cmd::verify::execute(path, repair, json, cli.store).await
       },
       Some(Cmd::Serve { path }) => cmd::serve::execute(path, cli.store).await,
-      Some(Cmd::Stop { path }) => cmd::stop::execute(path).await,
-      Some(Cmd::StopAll) => cmd::stop_all::execute().await,
-      Some(Cmd::Status) => cmd::status::execute().await,
+      Some(Cmd::Stop { path, force }) => cmd::stop::execute(path, force).await,
+      Some(Cmd::StopAll { force }) => cmd::stop_all::execute(force).await,
+      Some(Cmd::Unload { path }) => cmd::unload::execute(path).await,
+      Some(Cmd::Warmup { path }) => cmd::warmup::execute(path).await,
+      Some(Cmd::Status { json }) => cmd::status::execute(json).await,
+      Some(Cmd::Stats { index: true, json, .. }) => {
+         cmd::stats::execute_index(cli.store, json).await
+      },
+      Some(Cmd::Stats { history: true, json, .. }) => cmd::stats::execute_history(cli.store, json),
+      Some(Cmd::Stats { reset, json, .. }) => cmd::stats::execute(reset, json),
       Some(Cmd::Clean { store_id, all }) => cmd::clean::execute(store_id, all),
-      Some(Cmd::Setup) => cmd::setup::execute().await,
+      Some(Cmd::Export { store_id, output }) => cmd::export::execute(store_id, output),
+      Some(Cmd::Import { archive, store_id, force }) => {
+         cmd::import::execute(archive, store_id, force)
+      },
+      Some(Cmd::Setup { from_dir }) => cmd::setup::execute(from_dir).await,
       Some(Cmd::Doctor) => cmd::doctor::execute(),
-      Some(Cmd::List) => cmd::list::execute(),
+      Some(Cmd::List { files: false }) => cmd::list::execute(),
+      Some(Cmd::List { files: true }) => cmd::list::execute_files(cli.store).await,
+      Some(Cmd::Models) => cmd::models::execute(),
       Some(Cmd::ClaudeInstall) => cmd::claude_install::execute(),
       Some(Cmd::Mcp) => cmd::mcp::execute().await,
       None => {
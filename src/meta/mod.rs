@@ -0,0 +1,1449 @@
+//! File metadata tracking for incremental indexing
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use std::{
+   collections::{HashMap, HashSet, VecDeque},
+   fmt, fs,
+   io::{self, Read},
+   path::{Path, PathBuf},
+   time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+   Error, Result,
+   config::{self, FileHashAlgorithm, LongChunks, MetaBackend, Pooling, WindowPooling},
+};
+
+/// Metadata for a single file
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct FileMeta {
+   pub hash:     FileHash,
+   pub mtime:    u64,
+   /// Size in bytes; 0 for files recorded before sizes were
+   #[serde(default)]
+   pub size:     u64,
+   /// Encoding the file was transcoded from, when it is not UTF-8
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub encoding: Option<String>,
+}
+
+impl FileMeta {
+   pub const fn stat(&self) -> FileStat {
+      FileStat { mtime: self.mtime, size: self.size }
+   }
+}
+
+/// Modification time (Unix seconds) and size of a file; a file whose stat
+/// matches the recorded one is taken to be unchanged without hashing it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileStat {
+   pub mtime: u64,
+   pub size:  u64,
+}
+
+/// Hash of file or chunk contents; SHA-256 unless a file is hashed with
+/// another [`FileHashAlgorithm`]
+#[derive(Serialize, Deserialize, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct FileHash([u8; 32]);
+
+impl FileHash {
+   /// Creates a hash from a byte slice, verifying length
+   pub fn from_slice(slice: &[u8]) -> Option<Self> {
+      let (this, rem) = slice.split_first_chunk()?;
+      rem.is_empty().then_some(Self(*this))
+   }
+
+   pub const fn new(hash: [u8; 32]) -> Self {
+      Self(hash)
+   }
+
+   /// Computes SHA-256 hash of data
+   pub fn sum(dat: impl AsRef<[u8]>) -> Self {
+      Self(Sha256::digest(dat.as_ref()).into())
+   }
+
+   /// Computes SHA-256 hash of everything read from `reader`, without
+   /// holding it in memory
+   pub fn sum_reader(mut reader: impl Read) -> io::Result<Self> {
+      let mut hasher = Sha256::new();
+      io::copy(&mut reader, &mut hasher)?;
+      Ok(Self(hasher.finalize().into()))
+   }
+
+   /// Computes the hash of a file's contents with `algorithm`
+   pub fn digest(algorithm: FileHashAlgorithm, dat: impl AsRef<[u8]>) -> Self {
+      match algorithm {
+         FileHashAlgorithm::Sha256 => Self::sum(dat),
+         FileHashAlgorithm::Blake3 => Self(blake3::hash(dat.as_ref()).into()),
+      }
+   }
+
+   /// Computes the hash of everything read from `reader` with `algorithm`,
+   /// without holding it in memory
+   pub fn digest_reader(algorithm: FileHashAlgorithm, mut reader: impl Read) -> io::Result<Self> {
+      match algorithm {
+         FileHashAlgorithm::Sha256 => Self::sum_reader(reader),
+         FileHashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut reader, &mut hasher)?;
+            Ok(Self(hasher.finalize().into()))
+         },
+      }
+   }
+}
+
+impl AsRef<[u8]> for FileHash {
+   fn as_ref(&self) -> &[u8] {
+      &self.0
+   }
+}
+
+impl AsMut<[u8]> for FileHash {
+   fn as_mut(&mut self) -> &mut [u8] {
+      &mut self.0
+   }
+}
+
+impl fmt::Display for FileHash {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{}", hex::encode(self.0))
+   }
+}
+
+impl fmt::Debug for FileHash {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "Hash({})", hex::encode(self.0))
+   }
+}
+
+impl std::ops::Deref for FileHash {
+   type Target = [u8];
+
+   fn deref(&self) -> &Self::Target {
+      &self.0
+   }
+}
+
+impl std::ops::DerefMut for FileHash {
+   fn deref_mut(&mut self) -> &mut Self::Target {
+      &mut self.0
+   }
+}
+
+/// Signature of the embedding models and dimensions used to build an index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ModelSignature {
+   pub dense_model:     String,
+   pub colbert_model:   String,
+   pub dense_dim:       usize,
+   pub colbert_dim:     usize,
+   /// Dense pooling, if set in the config rather than left to the model.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub dense_pooling:   Option<Pooling>,
+   /// Whether `ColBERT` inputs carry query and document markers.
+   #[serde(default)]
+   pub colbert_markers: bool,
+   /// Pooling of dense window embeddings, if long chunks are embedded in
+   /// windows rather than split.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub window_pooling:  Option<WindowPooling>,
+   /// Size dense embeddings are truncated to, if below `dense_dim`.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub truncate_dim:    Option<usize>,
+   /// Template chunks are embedded with, if it adds to their content.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub embed_template:  Option<String>,
+}
+
+impl ModelSignature {
+   pub fn current() -> Self {
+      let cfg = config::get();
+
+      Self {
+         dense_model:     cfg.dense_model.clone(),
+         colbert_model:   cfg.colbert_model.clone(),
+         dense_dim:       cfg.dense_dim,
+         colbert_dim:     cfg.colbert_dim,
+         dense_pooling:   cfg.dense_pooling,
+         colbert_markers: cfg.colbert_markers,
+         window_pooling:  (cfg.long_chunks == LongChunks::Windows).then_some(cfg.window_pooling),
+         truncate_dim:    Some(cfg.dense_vector_dim()).filter(|&dim| dim < cfg.dense_dim),
+         embed_template:  Some(cfg.embed_template.clone()).filter(|t| t != "{content}"),
+      }
+   }
+}
+
+impl fmt::Display for ModelSignature {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(
+         f,
+         "{} ({}d) + {} ({}d)",
+         self.dense_model, self.dense_dim, self.colbert_model, self.colbert_dim
+      )?;
+      if let Some(pooling) = self.dense_pooling {
+         write!(f, " with {} pooling", pooling.as_str())?;
+      }
+      if self.colbert_markers {
+         f.write_str(", ColBERT markers")?;
+      }
+      if let Some(pooling) = self.window_pooling {
+         write!(f, ", {}-pooled windows", pooling.as_str())?;
+      }
+      if let Some(dim) = self.truncate_dim {
+         write!(f, ", dense truncated to {dim}d")?;
+      }
+      if let Some(template) = &self.embed_template {
+         write!(f, ", embedding {template:?}")?;
+      }
+      Ok(())
+   }
+}
+
+/// Sync runs kept in a store's history.
+pub const SYNC_HISTORY_LEN: usize = 50;
+
+/// What started a sync run
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncActor {
+   /// `smgrep index`
+   Index,
+   /// `smgrep search --sync`
+   Search,
+   /// The initial sync of `smgrep serve`
+   Daemon,
+}
+
+impl fmt::Display for SyncActor {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      f.write_str(match self {
+         Self::Index => "index",
+         Self::Search => "search",
+         Self::Daemon => "daemon",
+      })
+   }
+}
+
+/// Outcome of one sync run, as kept in a store's history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SyncRun {
+   pub actor:       SyncActor,
+   /// Unix timestamps (seconds)
+   pub started_at:  u64,
+   pub finished_at: u64,
+   pub duration_ms: u64,
+   pub processed:   usize,
+   pub indexed:     usize,
+   pub deleted:     usize,
+   /// Files that failed to index, or 1 when the run itself failed
+   pub errors:      usize,
+   pub last_error:  Option<String>,
+}
+
+impl SyncRun {
+   /// Creates a run started at `started` and finishing now, with no counts.
+   pub fn new(actor: SyncActor, started: SystemTime) -> Self {
+      let finished = SystemTime::now();
+      let unix_secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+
+      Self {
+         actor,
+         started_at: unix_secs(started),
+         finished_at: unix_secs(finished),
+         duration_ms: finished
+            .duration_since(started)
+            .map_or(0, |d| d.as_millis() as u64),
+         processed: 0,
+         indexed: 0,
+         deleted: 0,
+         errors: 0,
+         last_error: None,
+      }
+   }
+}
+
+/// Current on-disk metadata format version.
+///
+/// Version 0 covers files written before versioning, including the legacy
+/// `hashes`-only layout.
+pub const META_VERSION: u32 = 1;
+
+/// Files above which [`MetaBackend::Auto`] moves a store's metadata from JSON
+/// to `SQLite`.
+pub const SQLITE_META_MIN_FILES: usize = 20_000;
+
+/// Files changed since the last save, so that a database can write just those
+#[derive(Default)]
+struct Changes {
+   upserted: HashSet<PathBuf>,
+   removed:  HashSet<PathBuf>,
+   /// Every file is to be rewritten, as after a reset
+   all:      bool,
+}
+
+impl Changes {
+   fn upsert(&mut self, path: &Path) {
+      if !self.all {
+         self.removed.remove(path);
+         self.upserted.insert(path.to_path_buf());
+      }
+   }
+
+   fn remove(&mut self, path: &Path) {
+      if !self.all {
+         self.upserted.remove(path);
+         self.removed.insert(path.to_path_buf());
+      }
+   }
+
+   fn replace_all(&mut self) {
+      *self = Self { all: true, ..Self::default() };
+   }
+}
+
+/// Persistent store for file metadata and hashes
+#[derive(Serialize, Deserialize, Default)]
+pub struct MetaStore {
+   #[serde(default)]
+   version:        u32,
+   #[serde(default)]
+   files:          HashMap<PathBuf, FileMeta>,
+   #[serde(default, skip_serializing)]
+   hashes:         HashMap<PathBuf, FileHash>,
+   #[serde(default)]
+   model:          Option<ModelSignature>,
+   #[serde(default)]
+   history:        VecDeque<SyncRun>,
+   /// Algorithm the file hashes were computed with; stores from before it
+   /// was recorded used SHA-256
+   #[serde(default)]
+   file_hash:      FileHashAlgorithm,
+   /// Directory the store's rows give paths relative to; stores from before
+   /// it was recorded give absolute paths
+   #[serde(default)]
+   root:           Option<PathBuf>,
+   /// Store whose rows this branch store shares; its own rows only cover the
+   /// files that differ from the base
+   #[serde(default)]
+   base:           Option<String>,
+   /// Files of the base store that are deleted in this one
+   #[serde(default)]
+   removed:        HashSet<PathBuf>,
+   /// Commit checked out when the store was last synced
+   #[serde(default)]
+   head:           Option<String>,
+   /// Files of the base store, under this store's root
+   #[serde(skip)]
+   inherited:      HashMap<PathBuf, FileMeta>,
+   /// When the base store's metadata was saved, as of loading this one
+   #[serde(skip)]
+   base_modified:  Option<SystemTime>,
+   #[serde(skip)]
+   path:           PathBuf,
+   #[serde(skip)]
+   dirty:          bool,
+   #[serde(skip)]
+   model_mismatch: bool,
+   #[serde(skip)]
+   needs_rebuild:  bool,
+   #[serde(skip)]
+   changes:        Changes,
+   /// Files in the other format, removed once saved in this one
+   #[serde(skip)]
+   stale:          Vec<PathBuf>,
+   #[cfg(feature = "sqlite")]
+   #[serde(skip)]
+   db:             Option<sqlite::MetaDb>,
+}
+
+impl MetaStore {
+   /// Loads metadata store from disk, creating if it doesn't exist
+   ///
+   /// A corrupt file is replaced by its backup from the previous save, or
+   /// failing that by an empty store that
+   /// [`needs_rebuild`](Self::needs_rebuild). A branch store also loads the
+   /// files of its base store.
+   pub fn load(store_id: &str) -> Result<Self> {
+      let mut store = Self::load_own(store_id)?;
+      store.inherit_base();
+      Ok(store)
+   }
+
+   /// [`load`](Self::load) without the files of a base store.
+   fn load_own(store_id: &str) -> Result<Self> {
+      let path = json_path(store_id);
+
+      let (mut store, existed) = Self::read_any(store_id, &path)?;
+      if !store.stale.is_empty() {
+         store.changes.replace_all();
+         store.dirty = true;
+      }
+      store.path = path;
+      store.migrate(store_id)?;
+
+      let current_model = ModelSignature::current();
+      let model_mismatch = match (&store.model, existed) {
+         (Some(model), true) => model != &current_model,
+         (None, true) => true,
+         _ => false,
+      };
+
+      // Keep the recorded signature until the index is actually rebuilt, so a
+      // mismatch survives saves that don't re-embed anything.
+      if !model_mismatch {
+         store.model = Some(current_model);
+      }
+      store.model_mismatch = model_mismatch;
+      store.dirty = store.dirty || model_mismatch || !existed;
+
+      Ok(store)
+   }
+
+   fn empty() -> Self {
+      Self { version: META_VERSION, file_hash: config::get().file_hash, ..Self::default() }
+   }
+
+   /// Reads the store in the configured format, converting it from the other
+   /// one if it was only saved in that.
+   #[cfg(feature = "sqlite")]
+   fn read_any(store_id: &str, path: &Path) -> Result<(Self, bool)> {
+      let db_path = sqlite_path(path);
+      let use_db = match config::get().meta_backend {
+         MetaBackend::Auto => db_path.exists(),
+         MetaBackend::Json => false,
+         MetaBackend::Sqlite => true,
+      };
+
+      if use_db {
+         let (db, saved) = Self::read_db(store_id, &db_path)?;
+         let converting = saved.is_none();
+         let (mut store, existed) = match saved {
+            Some(saved) => saved,
+            None => Self::read_json(store_id, path)?,
+         };
+         if converting && existed {
+            store.stale.extend([path.to_path_buf(), backup_path(path)]);
+         }
+         store.db = Some(db);
+         return Ok((store, existed));
+      }
+
+      let (mut store, mut existed) = Self::read_json(store_id, path)?;
+      if db_path.exists() {
+         if !existed && let (_, Some((saved, saved_existed))) = Self::read_db(store_id, &db_path)? {
+            (store, existed) = (saved, saved_existed);
+         }
+         store.stale.push(db_path);
+      }
+      Ok((store, existed))
+   }
+
+   #[cfg(not(feature = "sqlite"))]
+   fn read_any(store_id: &str, path: &Path) -> Result<(Self, bool)> {
+      if config::get().meta_backend == MetaBackend::Sqlite {
+         return Err(Error::BackendUnavailable("sqlite"));
+      }
+      Self::read_json(store_id, path)
+   }
+
+   /// Reads the store's JSON file, or its backup if the file is corrupt.
+   fn read_json(store_id: &str, path: &Path) -> Result<(Self, bool)> {
+      match Self::read(path) {
+         Ok(Some(store)) => Ok((store, true)),
+         Ok(None) => Ok((Self::empty(), false)),
+         Err(Error::Json(e)) => {
+            tracing::warn!("metadata of {store_id} is corrupt: {e}");
+            match Self::read(&backup_path(path)) {
+               Ok(Some(mut store)) => {
+                  tracing::warn!("restored metadata of {store_id} from its backup");
+                  store.dirty = true;
+                  Ok((store, true))
+               },
+               _ => Ok((Self { needs_rebuild: true, ..Self::empty() }, false)),
+            }
+         },
+         Err(e) => Err(e),
+      }
+   }
+
+   /// Reads a store from `path`, `None` if there is no file.
+   fn read(path: &Path) -> Result<Option<Self>> {
+      match fs::read(path) {
+         Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+         Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+         Err(e) => Err(e.into()),
+      }
+   }
+
+   /// Opens the store's database and reads it, `None` if nothing was saved
+   /// to it. A corrupt database is replaced by an empty one.
+   #[cfg(feature = "sqlite")]
+   fn read_db(store_id: &str, db_path: &Path) -> Result<(sqlite::MetaDb, Option<(Self, bool)>)> {
+      let read = sqlite::MetaDb::open(db_path).and_then(|db| {
+         let contents = db.read()?;
+         Ok((db, contents))
+      });
+      match read {
+         Ok((db, contents)) => match contents.map(Self::from_contents).transpose() {
+            Ok(saved) => return Ok((db, saved.map(|store| (store, true)))),
+            Err(e) => tracing::warn!("metadata of {store_id} is corrupt: {e}"),
+         },
+         Err(e) if e.is_corrupt() => tracing::warn!("metadata of {store_id} is corrupt: {e}"),
+         Err(e) => return Err(e.into()),
+      }
+      fs::remove_file(db_path)?;
+      let rebuilt = Self { needs_rebuild: true, ..Self::empty() };
+      Ok((sqlite::MetaDb::open(db_path)?, Some((rebuilt, false))))
+   }
+
+   #[cfg(feature = "sqlite")]
+   fn from_contents(contents: sqlite::Contents) -> serde_json::Result<Self> {
+      fn value<T: serde::de::DeserializeOwned>(
+         state: &HashMap<String, String>,
+         key: &str,
+      ) -> serde_json::Result<Option<T>> {
+         state.get(key).map(|v| serde_json::from_str(v)).transpose()
+      }
+
+      Ok(Self {
+         version: value(&contents.state, "version")?.unwrap_or_default(),
+         model: value(&contents.state, "model")?.flatten(),
+         history: value(&contents.state, "history")?.unwrap_or_default(),
+         file_hash: value(&contents.state, "file_hash")?.unwrap_or_default(),
+         root: value(&contents.state, "root")?.flatten(),
+         base: value(&contents.state, "base")?.flatten(),
+         removed: value(&contents.state, "removed")?.unwrap_or_default(),
+         head: value(&contents.state, "head")?.flatten(),
+         files: contents.files,
+         ..Self::empty()
+      })
+   }
+
+   /// Upgrades a store loaded from disk to [`META_VERSION`], one step at a
+   /// time.
+   fn migrate(&mut self, store_id: &str) -> Result<()> {
+      if self.version > META_VERSION {
+         return Err(Error::MetaVersionTooNew {
+            store_id:  store_id.to_string(),
+            found:     self.version,
+            supported: META_VERSION,
+         });
+      }
+
+      if self.version == 0 {
+         self.migrate_legacy_hashes();
+      }
+
+      if self.version != META_VERSION {
+         self.version = META_VERSION;
+         self.dirty = true;
+      }
+      Ok(())
+   }
+
+   fn migrate_legacy_hashes(&mut self) {
+      self.changes.replace_all();
+      for (path, hash) in self.hashes.drain() {
+         self
+            .files
+            .entry(path)
+            .or_insert_with(|| FileMeta { hash, ..FileMeta::default() });
+      }
+   }
+
+   /// Loads the files of the base store, moved under this store's root. A
+   /// base that is gone, was rebuilt with other models or has no root
+   /// leaves the store on its own, so that the next sync indexes the files
+   /// it shared.
+   fn inherit_base(&mut self) {
+      let Some(base) = self.base.clone() else {
+         return;
+      };
+      if let Some(files) = self
+         .root
+         .as_deref()
+         .and_then(|root| base_files(&base, root))
+      {
+         self.inherited = files;
+         self.base_modified = modified(&base);
+      } else {
+         tracing::info!("no longer sharing the rows of {base}");
+         self.base = None;
+         self.head = None;
+         self.removed.clear();
+         self.inherited.clear();
+         self.base_modified = None;
+         self.dirty = true;
+      }
+   }
+
+   /// Metadata of a file, this store's own or else its base's
+   fn entry(&self, path: &Path) -> Option<&FileMeta> {
+      self.files.get(path).or_else(|| {
+         if self.removed.contains(path) {
+            None
+         } else {
+            self.inherited.get(path)
+         }
+      })
+   }
+
+   /// Gets the stored hash for a file
+   pub fn get_hash(&self, path: &Path) -> Option<FileHash> {
+      self.entry(path).map(|m| m.hash)
+   }
+
+   /// Gets the stored modification time for a file
+   pub fn get_mtime(&self, path: &Path) -> Option<u64> {
+      self.entry(path).map(|m| m.mtime)
+   }
+
+   /// Gets the stored modification time and size of a file
+   pub fn get_stat(&self, path: &Path) -> Option<FileStat> {
+      self.entry(path).map(FileMeta::stat)
+   }
+
+   /// Gets the encoding a file was transcoded from, if it is not UTF-8
+   pub fn get_encoding(&self, path: &Path) -> Option<&str> {
+      self.entry(path)?.encoding.as_deref()
+   }
+
+   /// Gets the complete metadata for a file
+   pub fn get_meta(&self, path: &Path) -> Option<&FileMeta> {
+      self.entry(path)
+   }
+
+   /// Updates the hash for a file
+   pub fn set_hash(&mut self, path: &Path, hash: FileHash) {
+      self.changes.upsert(path);
+      self.removed.remove(path);
+      if let Some(meta) = self.files.get_mut(path) {
+         meta.hash = hash;
+      } else {
+         self
+            .files
+            .insert(path.to_path_buf(), FileMeta { hash, ..FileMeta::default() });
+      }
+      self.dirty = true;
+   }
+
+   /// Records the encoding a file was transcoded from; `None` for UTF-8
+   pub fn set_encoding(&mut self, path: &Path, encoding: Option<&str>) {
+      if let Some(meta) = self.files.get_mut(path) {
+         meta.encoding = encoding.map(str::to_string);
+         self.changes.upsert(path);
+         self.dirty = true;
+      }
+   }
+
+   /// Sets complete metadata for a file
+   pub fn set_meta(
+      &mut self,
+      path: PathBuf,
+      hash: FileHash,
+      stat: FileStat,
+      encoding: Option<&str>,
+   ) {
+      let encoding = encoding.map(str::to_string);
+      self.changes.upsert(&path);
+      self.removed.remove(&path);
+      let FileStat { mtime, size } = stat;
+      self
+         .files
+         .insert(path, FileMeta { hash, mtime, size, encoding });
+      self.dirty = true;
+   }
+
+   /// Records the current stat of a file found unchanged by its hash, so the
+   /// next sync can skip hashing it
+   pub fn set_stat(&mut self, path: &Path, stat: FileStat) {
+      if let Some(meta) = self.files.get_mut(path)
+         && meta.stat() != stat
+      {
+         meta.mtime = stat.mtime;
+         meta.size = stat.size;
+         self.changes.upsert(path);
+         self.dirty = true;
+      }
+   }
+
+   /// Removes metadata for a file, and marks a file of the base store as
+   /// deleted from this one
+   pub fn remove(&mut self, path: &Path) {
+      self.changes.remove(path);
+      self.files.remove(path);
+      if self.inherited.contains_key(path) {
+         self.removed.insert(path.to_path_buf());
+      }
+      self.dirty = true;
+   }
+
+   /// Whether the metadata was lost to corruption and should be rebuilt
+   /// from the store with [`rebuild_from`](Self::rebuild_from)
+   pub const fn needs_rebuild(&self) -> bool {
+      self.needs_rebuild
+   }
+
+   /// Recovers lost metadata from the file hashes of the store's rows.
+   ///
+   /// Modification times are unknown, so the next sync hashes every file,
+   /// but only re-indexes those that changed.
+   pub fn rebuild_from(&mut self, hashes: HashMap<PathBuf, FileHash>) {
+      // Module summaries are stored under their directory
+      self.files.extend(
+         hashes
+            .into_iter()
+            .filter(|(path, _)| !path.is_dir())
+            .map(|(path, hash)| (path, FileMeta { hash, ..FileMeta::default() })),
+      );
+      self.changes.replace_all();
+      self.needs_rebuild = false;
+      self.dirty = true;
+   }
+
+   /// Saves the metadata store to disk if dirty
+   pub fn save(&mut self) -> Result<()> {
+      if !self.dirty {
+         return Ok(());
+      }
+
+      if let Some(parent) = self.path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+
+      #[cfg(feature = "sqlite")]
+      let saved = self.save_db()?;
+      #[cfg(not(feature = "sqlite"))]
+      let saved = false;
+      if !saved {
+         self.save_json()?;
+      }
+
+      for stale in self.stale.drain(..) {
+         if let Err(e) = fs::remove_file(&stale)
+            && e.kind() != io::ErrorKind::NotFound
+         {
+            tracing::warn!("failed to remove {}: {e}", stale.display());
+         }
+      }
+      self.changes = Changes::default();
+      self.dirty = false;
+      Ok(())
+   }
+
+   /// Saves to the store's database if it has one, moving it to one first
+   /// once it outgrows JSON; `false` if it is kept as JSON.
+   #[cfg(feature = "sqlite")]
+   fn save_db(&mut self) -> Result<bool> {
+      if self.db.is_none()
+         && config::get().meta_backend == MetaBackend::Auto
+         && self.files.len() > SQLITE_META_MIN_FILES
+      {
+         self.db = Some(sqlite::MetaDb::open(&sqlite_path(&self.path))?);
+         self.changes.replace_all();
+         self
+            .stale
+            .extend([self.path.clone(), backup_path(&self.path)]);
+      }
+      let Some(db) = &self.db else {
+         return Ok(false);
+      };
+
+      let state = [
+         ("version", serde_json::to_string(&self.version)?),
+         ("model", serde_json::to_string(&self.model)?),
+         ("history", serde_json::to_string(&self.history)?),
+         ("file_hash", serde_json::to_string(&self.file_hash)?),
+         ("root", serde_json::to_string(&self.root)?),
+         ("base", serde_json::to_string(&self.base)?),
+         ("removed", serde_json::to_string(&self.removed)?),
+         ("head", serde_json::to_string(&self.head)?),
+      ];
+      if self.changes.all {
+         let files = self.files.iter().map(|(path, meta)| (path.as_path(), meta));
+         db.write(true, files, std::iter::empty(), &state)?;
+      } else {
+         let upserted = self
+            .changes
+            .upserted
+            .iter()
+            .filter_map(|path| Some((path.as_path(), self.files.get(path)?)));
+         let removed = self.changes.removed.iter().map(PathBuf::as_path);
+         db.write(false, upserted, removed, &state)?;
+      }
+      Ok(true)
+   }
+
+   /// Replaces the JSON file, keeping the previous one as a backup.
+   fn save_json(&self) -> Result<()> {
+      // Written next to the file and renamed into place, so a crash mid-write
+      // leaves the previous version intact
+      let tmp = self.path.with_extension("json.tmp");
+      fs::write(&tmp, serde_json::to_vec(&self)?)?;
+      if self.path.exists() {
+         let backup = backup_path(&self.path);
+         let _ = fs::remove_file(&backup);
+         if let Err(e) =
+            fs::hard_link(&self.path, &backup).or_else(|_| fs::copy(&self.path, &backup).map(drop))
+         {
+            tracing::warn!("failed to back up {}: {e}", self.path.display());
+         }
+      }
+      fs::rename(&tmp, &self.path)?;
+      Ok(())
+   }
+
+   /// The metadata in the JSON format, whichever it is saved in.
+   pub fn to_json(&self) -> Result<Vec<u8>> {
+      Ok(serde_json::to_vec(self)?)
+   }
+
+   /// Returns an iterator over all tracked file paths, including those
+   /// shared with the base store
+   pub fn all_paths(&self) -> impl Iterator<Item = &PathBuf> {
+      let inherited = self
+         .inherited
+         .keys()
+         .filter(|path| !self.files.contains_key(*path) && !self.removed.contains(*path));
+      self.files.keys().chain(inherited)
+   }
+
+   /// Deletes all metadata for files with a given path prefix. Files shared
+   /// with the base store are left to it, as are their rows.
+   pub fn delete_by_prefix(&mut self, prefix: &Path) {
+      let changes = &mut self.changes;
+      self.files.retain(|path, _| {
+         let keep = !path.starts_with(prefix);
+         if !keep {
+            changes.remove(path);
+         }
+         keep
+      });
+      self.removed.retain(|path| !path.starts_with(prefix));
+      self.dirty = true;
+   }
+
+   /// Whether the stored model signature differs from the current configuration
+   pub const fn model_mismatch(&self) -> bool {
+      self.model_mismatch
+   }
+
+   /// Signature of the models the index was built with, if recorded
+   pub const fn model(&self) -> Option<&ModelSignature> {
+      self.model.as_ref()
+   }
+
+   /// Fails with [`Error::ModelMismatch`] if the index was built with models
+   /// other than the configured ones.
+   pub fn ensure_model_matches(&self, store_id: &str) -> Result<()> {
+      match &self.model {
+         Some(indexed) if self.model_mismatch => Err(Error::ModelMismatch {
+            store_id: store_id.to_string(),
+            indexed:  indexed.to_string(),
+            current:  ModelSignature::current().to_string(),
+         }),
+         _ => Ok(()),
+      }
+   }
+
+   /// Appends a run to the sync history, dropping the oldest beyond
+   /// [`SYNC_HISTORY_LEN`].
+   pub fn record_sync(&mut self, run: SyncRun) {
+      if self.history.len() >= SYNC_HISTORY_LEN {
+         self.history.pop_front();
+      }
+      self.history.push_back(run);
+      self.dirty = true;
+   }
+
+   /// Recorded sync runs, oldest first
+   pub const fn history(&self) -> &VecDeque<SyncRun> {
+      &self.history
+   }
+
+   /// Whether the file hashes were computed with another algorithm than the
+   /// configured one, so that every file has to be hashed again
+   pub fn hash_mismatch(&self) -> bool {
+      self.file_hash != config::get().file_hash
+   }
+
+   /// Records that every file was hashed with the configured algorithm
+   pub fn record_file_hash(&mut self) {
+      if self.hash_mismatch() {
+         self.file_hash = config::get().file_hash;
+         self.dirty = true;
+      }
+   }
+
+   /// Directory the store's rows give paths relative to, if they are not
+   /// absolute
+   pub fn root(&self) -> Option<&Path> {
+      self.root.as_deref()
+   }
+
+   /// Makes `root` the directory the store's rows give paths relative to,
+   /// moving the tracked files over from the previous root, as after the
+   /// repository was moved or the store imported from another machine.
+   /// Returns whether the root changed.
+   pub fn set_root(&mut self, root: &Path) -> bool {
+      if self.root.as_deref() == Some(root) {
+         return false;
+      }
+      if let Some(previous) = self.root.take() {
+         self.files = std::mem::take(&mut self.files)
+            .into_iter()
+            .map(|(path, meta)| (rebase(&previous, root, path), meta))
+            .collect();
+         self.removed = std::mem::take(&mut self.removed)
+            .into_iter()
+            .map(|path| rebase(&previous, root, path))
+            .collect();
+         self.changes.replace_all();
+      }
+      self.root = Some(root.to_path_buf());
+      self.inherit_base();
+      self.dirty = true;
+      true
+   }
+
+   /// Store whose rows this branch store shares, if any
+   pub fn base(&self) -> Option<&str> {
+      self.base.as_deref()
+   }
+
+   /// Makes this store share the rows of `base`, taking its files as
+   /// indexed, so that only the files that differ from it are indexed here.
+   /// Both stores need a root. Returns whether the base could be shared.
+   pub fn set_base(&mut self, base: &str) -> bool {
+      let Some(files) = self.root.as_deref().and_then(|root| base_files(base, root)) else {
+         return false;
+      };
+      self.base = Some(base.to_string());
+      self.inherited = files;
+      self.base_modified = modified(base);
+      self.dirty = true;
+      true
+   }
+
+   /// Whether the base store was saved since this store was last synced,
+   /// so that the files it shares may have changed under it
+   pub fn base_moved(&self) -> bool {
+      let unix_secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+      let synced = self.history.back().map_or(0, |run| run.finished_at);
+      self
+         .base_modified
+         .is_some_and(|modified| unix_secs(modified) > synced)
+   }
+
+   /// Commit checked out when the store was last synced
+   pub fn head(&self) -> Option<&str> {
+      self.head.as_deref()
+   }
+
+   /// Records the commit checked out at the end of a sync
+   pub fn set_head(&mut self, head: Option<String>) {
+      if self.head != head {
+         self.head = head;
+         self.dirty = true;
+      }
+   }
+
+   /// Clears all tracked metadata and records the current model signature
+   pub fn reset_for_model_change(&mut self) {
+      self.files.clear();
+      self.base = None;
+      self.removed.clear();
+      self.inherited.clear();
+      self.base_modified = None;
+      self.changes.replace_all();
+      self.file_hash = config::get().file_hash;
+      self.model = Some(ModelSignature::current());
+      self.dirty = true;
+      self.model_mismatch = false;
+   }
+}
+
+/// `path` under `to` if it lies under `from`, and as is otherwise.
+fn rebase(from: &Path, to: &Path, path: PathBuf) -> PathBuf {
+   match path.strip_prefix(from) {
+      Ok(relative) => to.join(relative),
+      Err(_) => path,
+   }
+}
+
+/// Files of the store `base`, moved from its root to `root`; `None` if it
+/// cannot be shared.
+fn base_files(base: &str, root: &Path) -> Option<HashMap<PathBuf, FileMeta>> {
+   if !exists(base) {
+      return None;
+   }
+   let store = MetaStore::load_own(base)
+      .ok()
+      .filter(|store| !store.model_mismatch)?;
+   let base_root = store.root?;
+   Some(
+      store
+         .files
+         .into_iter()
+         .map(|(path, meta)| (rebase(&base_root, root, path), meta))
+         .collect(),
+   )
+}
+
+/// Store a branch store shares rows with, and the files whose rows in the
+/// base it leaves out because it replaced or deleted them.
+pub struct Overlay {
+   pub base:      String,
+   pub base_root: PathBuf,
+   pub root:      PathBuf,
+   /// Paths under `root`.
+   pub shadowed:  HashSet<PathBuf>,
+}
+
+/// How the branch store `store_id` shares rows with its base store, read
+/// without loading the base's metadata; `None` for stores on their own.
+pub fn overlay(store_id: &str) -> Option<Overlay> {
+   let store = MetaStore::load_own(store_id).ok()?;
+   let base = store.base?;
+   let root = store.root?;
+   let base_root = stored_root(&base)?;
+   let mut shadowed = store.removed;
+   shadowed.extend(store.files.into_keys());
+   Some(Overlay { base, base_root, root, shadowed })
+}
+
+/// When the metadata of `store_id` was last saved, in either format.
+pub fn modified(store_id: &str) -> Option<SystemTime> {
+   let path = json_path(store_id);
+   [sqlite_path(&path), path]
+      .iter()
+      .filter_map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+      .max()
+}
+
+/// JSON metadata file of `store_id`.
+pub fn json_path(store_id: &str) -> PathBuf {
+   config::meta_dir().join(format!("{store_id}.json"))
+}
+
+/// Copy of the JSON metadata at `path` as of its previous save.
+fn backup_path(path: &Path) -> PathBuf {
+   path.with_extension("json.bak")
+}
+
+/// Metadata database next to the JSON metadata at `path`.
+fn sqlite_path(path: &Path) -> PathBuf {
+   path.with_extension("sqlite")
+}
+
+/// Whether any metadata of `store_id` is saved, in either format.
+pub fn exists(store_id: &str) -> bool {
+   let path = json_path(store_id);
+   path.is_file() || sqlite_path(&path).is_file()
+}
+
+/// Directory the rows of `store_id` give paths relative to, read without
+/// loading the rest of its metadata; `None` for stores that give absolute
+/// paths.
+pub fn stored_root(store_id: &str) -> Option<PathBuf> {
+   #[derive(Deserialize)]
+   struct Root {
+      #[serde(default)]
+      root: Option<PathBuf>,
+   }
+
+   let path = json_path(store_id);
+   #[cfg(feature = "sqlite")]
+   {
+      let db_path = sqlite_path(&path);
+      if config::get().meta_backend != MetaBackend::Json
+         && db_path.is_file()
+         && let Some(root) = sqlite::MetaDb::open(&db_path)
+            .and_then(|db| db.state("root"))
+            .ok()
+            .flatten()
+      {
+         return serde_json::from_str::<Option<PathBuf>>(&root)
+            .ok()
+            .flatten();
+      }
+   }
+   let content = fs::read(&path).ok()?;
+   serde_json::from_slice::<Root>(&content).ok()?.root
+}
+
+/// Removes all metadata of `store_id`, in either format.
+pub fn delete_files(store_id: &str) -> Result<()> {
+   let path = json_path(store_id);
+   for file in [backup_path(&path), sqlite_path(&path), path] {
+      match fs::remove_file(&file) {
+         Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+         _ => {},
+      }
+   }
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use std::fs;
+
+   use tempfile::TempDir;
+
+   use super::*;
+
+   fn with_temp_home(f: impl FnOnce(&TempDir)) {
+      let temp_dir = TempDir::new().unwrap();
+      // SAFETY: we are setting the HOME environment variable to a temporary directory
+      unsafe {
+         std::env::set_var("HOME", temp_dir.path());
+      }
+      f(&temp_dir);
+   }
+
+   #[test]
+   fn load_nonexistent_creates_empty() {
+      with_temp_home(|_| {
+         // Use unique store_id to avoid collision with other tests due to OnceLock
+         // caching
+         let store = MetaStore::load("load_nonexistent_test").unwrap();
+         assert_eq!(store.files.len(), 0);
+      });
+   }
+
+   #[test]
+   fn set_and_get_hash() {
+      with_temp_home(|_| {
+         let hash = FileHash::sum(b"abc123");
+         // Use unique store_id to avoid collision with other tests due to OnceLock
+         // caching
+         let mut store = MetaStore::load("set_and_get_hash_test").unwrap();
+         store.set_hash(Path::new("/path/to/file"), hash);
+
+         assert_eq!(store.get_hash("/path/to/file".as_ref()), Some(hash));
+         assert!(store.dirty);
+      });
+   }
+
+   #[test]
+   fn save_and_load_roundtrip() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("roundtrip_test").unwrap();
+         let hash1 = FileHash::sum(b"hash1");
+         let hash2 = FileHash::sum(b"hash2");
+         store.set_hash(Path::new("/file1"), hash1);
+         store.set_hash(Path::new("/file2"), hash2);
+         store.save().unwrap();
+
+         let loaded = MetaStore::load("roundtrip_test").unwrap();
+         assert_eq!(loaded.get_hash("/file1".as_ref()), Some(hash1));
+         assert_eq!(loaded.get_hash("/file2".as_ref()), Some(hash2));
+      });
+   }
+
+   #[test]
+   fn remove_hash() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("remove_hash_test").unwrap();
+         let hash = FileHash::sum(b"hash1");
+         store.set_hash(Path::new("/file1"), hash);
+         store.remove("/file1".as_ref());
+
+         assert_eq!(store.get_hash("/file1".as_ref()), None);
+      });
+   }
+
+   #[test]
+   fn set_stat_refreshes_tracked_files() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("set_stat_test").unwrap();
+         let stat = FileStat { mtime: 10, size: 3 };
+         store.set_meta(PathBuf::from("/file1"), FileHash::sum(b"abc"), stat, None);
+         store.save().unwrap();
+
+         store.set_stat(Path::new("/file1"), stat);
+         assert!(!store.dirty);
+         let touched = FileStat { mtime: 20, ..stat };
+         store.set_stat(Path::new("/file1"), touched);
+         store.set_stat(Path::new("/untracked"), touched);
+         assert_eq!(store.get_stat("/file1".as_ref()), Some(touched));
+         assert_eq!(store.get_stat("/untracked".as_ref()), None);
+         assert!(store.dirty);
+      });
+   }
+
+   #[test]
+   fn records_file_hash_algorithm() {
+      with_temp_home(|_| {
+         let blake3 = FileHash::digest(FileHashAlgorithm::Blake3, b"fn main() {}");
+         assert_ne!(blake3, FileHash::sum(b"fn main() {}"));
+         assert_eq!(
+            FileHash::digest_reader(FileHashAlgorithm::Blake3, &b"fn main() {}"[..]).unwrap(),
+            blake3
+         );
+
+         let mut store = MetaStore::load("file_hash_test").unwrap();
+         assert!(!store.hash_mismatch());
+         store.file_hash = FileHashAlgorithm::Blake3;
+         store.save().unwrap();
+
+         let mut store = MetaStore::load("file_hash_test").unwrap();
+         assert!(store.hash_mismatch());
+         store.record_file_hash();
+         store.save().unwrap();
+         assert!(!MetaStore::load("file_hash_test").unwrap().hash_mismatch());
+      });
+   }
+
+   #[test]
+   fn moves_files_to_new_root() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("root_test").unwrap();
+         assert_eq!(stored_root("root_test"), None);
+         assert!(store.set_root(Path::new("/old/repo")));
+         store.set_hash(Path::new("/old/repo/src/lib.rs"), FileHash::sum(b"lib"));
+         store.save().unwrap();
+         assert_eq!(stored_root("root_test").as_deref(), Some(Path::new("/old/repo")));
+
+         let mut store = MetaStore::load("root_test").unwrap();
+         assert!(!store.set_root(Path::new("/old/repo")));
+         assert!(store.set_root(Path::new("/new/repo")));
+         assert_eq!(store.root(), Some(Path::new("/new/repo")));
+         assert_eq!(store.get_hash(Path::new("/new/repo/src/lib.rs")), Some(FileHash::sum(b"lib")));
+         assert_eq!(store.get_hash(Path::new("/old/repo/src/lib.rs")), None);
+      });
+   }
+
+   #[test]
+   fn branch_stores_share_base_files() {
+      with_temp_home(|_| {
+         let lib = FileHash::sum(b"lib");
+         let mut base = MetaStore::load("share_test").unwrap();
+         base.set_root(Path::new("/main"));
+         base.set_hash(Path::new("/main/lib.rs"), lib);
+         base.set_hash(Path::new("/main/old.rs"), FileHash::sum(b"old"));
+         base.save().unwrap();
+
+         let mut branch = MetaStore::load("share_test@feature").unwrap();
+         branch.set_root(Path::new("/work"));
+         assert!(branch.set_base("share_test"));
+         assert_eq!(branch.get_hash(Path::new("/work/lib.rs")), Some(lib));
+         assert_eq!(branch.all_paths().count(), 2);
+
+         branch.remove(Path::new("/work/old.rs"));
+         branch.set_hash(Path::new("/work/new.rs"), FileHash::sum(b"new"));
+         assert_eq!(branch.get_hash(Path::new("/work/old.rs")), None);
+         branch.save().unwrap();
+
+         let shared = overlay("share_test@feature").unwrap();
+         assert_eq!(shared.base, "share_test");
+         assert_eq!(shared.base_root, Path::new("/main"));
+         let shadowed: HashSet<_> =
+            [PathBuf::from("/work/old.rs"), PathBuf::from("/work/new.rs")].into();
+         assert_eq!(shared.shadowed, shadowed);
+
+         let branch = MetaStore::load("share_test@feature").unwrap();
+         assert_eq!(branch.get_hash(Path::new("/work/lib.rs")), Some(lib));
+         assert_eq!(branch.all_paths().count(), 2);
+         assert!(overlay("share_test").is_none());
+      });
+   }
+
+   #[test]
+   fn all_paths_returns_keys() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("all_paths_test").unwrap();
+         let hash1 = FileHash::sum(b"hash1");
+         let hash2 = FileHash::sum(b"hash2");
+         store.set_hash(Path::new("/file1"), hash1);
+         store.set_hash(Path::new("/file2"), hash2);
+
+         let paths: Vec<_> = store.all_paths().collect();
+         assert_eq!(paths.len(), 2);
+         assert!(paths.contains(&&PathBuf::from("/file1")));
+         assert!(paths.contains(&&PathBuf::from("/file2")));
+      });
+   }
+
+   #[test]
+   fn sync_history_is_capped() {
+      with_temp_home(|_| {
+         let mut store = MetaStore::load("history_test").unwrap();
+         for processed in 0..SYNC_HISTORY_LEN + 5 {
+            let mut run = SyncRun::new(SyncActor::Index, SystemTime::now());
+            run.processed = processed;
+            store.record_sync(run);
+         }
+         store.save().unwrap();
+
+         let store = MetaStore::load("history_test").unwrap();
+         assert_eq!(store.history().len(), SYNC_HISTORY_LEN);
+         assert_eq!(store.history().front().map(|r| r.processed), Some(5));
+         assert_eq!(store.history().back().map(|r| r.processed), Some(SYNC_HISTORY_LEN + 4));
+      });
+   }
+
+   #[test]
+   fn migrates_legacy_hashes_layout() {
+      with_temp_home(|_| {
+         let store_id = "legacy_layout_test";
+         let meta_path = config::meta_dir().join(format!("{store_id}.json"));
+         fs::create_dir_all(meta_path.parent().unwrap()).unwrap();
+
+         let hash = FileHash::sum(b"legacy");
+         let legacy = serde_json::json!({ "hashes": { "/legacy": hash } });
+         fs::write(&meta_path, serde_json::to_string(&legacy).unwrap()).unwrap();
+
+         let mut store = MetaStore::load(store_id).unwrap();
+         assert_eq!(store.version, META_VERSION);
+         assert_eq!(store.get_hash(Path::new("/legacy")), Some(hash));
+
+         store.save().unwrap();
+         let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+         assert_eq!(saved["version"], META_VERSION);
+      });
+   }
+
+   #[test]
+   fn rejects_newer_meta_version() {
+      with_temp_home(|_| {
+         let store_id = "future_meta_test";
+         let meta_path = config::meta_dir().join(format!("{store_id}.json"));
+         fs::create_dir_all(meta_path.parent().unwrap()).unwrap();
+
+         let future = serde_json::json!({ "version": META_VERSION + 1, "files": {} });
+         fs::write(&meta_path, serde_json::to_string(&future).unwrap()).unwrap();
+
+         assert!(matches!(MetaStore::load(store_id), Err(Error::MetaVersionTooNew { .. })));
+      });
+   }
+
+   #[test]
+   fn restores_corrupt_meta_from_backup() {
+      with_temp_home(|_| {
+         let store_id = "corrupt_backup_test";
+         let mut store = MetaStore::load(store_id).unwrap();
+         store.set_hash(Path::new("/file1"), FileHash::sum(b"v1"));
+         store.save().unwrap();
+         store.set_hash(Path::new("/file1"), FileHash::sum(b"v2"));
+         store.save().unwrap();
+
+         // A write cut short
+         let meta_path = config::meta_dir().join(format!("{store_id}.json"));
+         fs::write(&meta_path, b"{\"version\":1,\"files\":{\"/fi").unwrap();
+
+         let store = MetaStore::load(store_id).unwrap();
+         assert!(!store.needs_rebuild());
+         assert_eq!(store.get_hash(Path::new("/file1")), Some(FileHash::sum(b"v1")));
+      });
+   }
+
+   #[test]
+   fn rebuilds_corrupt_meta_without_backup() {
+      with_temp_home(|temp| {
+         let store_id = "corrupt_rebuild_test";
+         let meta_path = config::meta_dir().join(format!("{store_id}.json"));
+         fs::create_dir_all(meta_path.parent().unwrap()).unwrap();
+         fs::write(&meta_path, b"\xff\xfe").unwrap();
+
+         let mut store = MetaStore::load(store_id).unwrap();
+         assert!(store.needs_rebuild());
+         assert!(!store.model_mismatch());
+
+         let file = temp.path().join("lib.rs");
+         fs::write(&file, "fn main() {}").unwrap();
+         let hash = FileHash::sum(b"fn main() {}");
+         store.rebuild_from(HashMap::from([
+            (file.clone(), hash),
+            (temp.path().to_path_buf(), FileHash::sum(b"module summary")),
+         ]));
+         assert!(!store.needs_rebuild());
+         assert_eq!(store.get_hash(&file), Some(hash));
+         assert_eq!(store.get_mtime(&file), Some(0));
+         assert_eq!(store.all_paths().count(), 1);
+
+         store.save().unwrap();
+         assert_eq!(MetaStore::load(store_id).unwrap().get_hash(&file), Some(hash));
+      });
+   }
+
+   #[cfg(feature = "sqlite")]
+   #[test]
+   fn moves_large_stores_to_sqlite() {
+      with_temp_home(|_| {
+         let store_id = "sqlite_move_test";
+         let mut store = MetaStore::load(store_id).unwrap();
+         for i in 0..=SQLITE_META_MIN_FILES {
+            store.set_hash(&PathBuf::from(format!("/repo/{i}.rs")), FileHash::sum(i.to_le_bytes()));
+         }
+         store.save().unwrap();
+         let path = json_path(store_id);
+         assert!(!path.exists());
+         assert!(sqlite_path(&path).exists());
+
+         let mut store = MetaStore::load(store_id).unwrap();
+         assert_eq!(store.all_paths().count(), SQLITE_META_MIN_FILES + 1);
+         assert!(!store.model_mismatch());
+         store.remove(Path::new("/repo/0.rs"));
+         store.set_hash(Path::new("/repo/1.rs"), FileHash::sum(b"edited"));
+         store.save().unwrap();
+
+         let store = MetaStore::load(store_id).unwrap();
+         assert_eq!(store.all_paths().count(), SQLITE_META_MIN_FILES);
+         assert_eq!(store.get_hash(Path::new("/repo/0.rs")), None);
+         assert_eq!(store.get_hash(Path::new("/repo/1.rs")), Some(FileHash::sum(b"edited")));
+
+         let exported: serde_json::Value =
+            serde_json::from_slice(&store.to_json().unwrap()).unwrap();
+         assert_eq!(exported["files"].as_object().unwrap().len(), SQLITE_META_MIN_FILES);
+      });
+   }
+
+   #[test]
+   fn detects_model_change_and_resets() {
+      with_temp_home(|_temp| {
+         let store_id = "model_change_test";
+         let meta_path = config::meta_dir().join(format!("{store_id}.json"));
+
+         fs::create_dir_all(meta_path.parent().unwrap()).unwrap();
+
+         let legacy = serde_json::json!({
+            "files": {},
+            "model": {
+               "dense_model": "legacy-dense",
+               "colbert_model": "legacy-colbert",
+               "dense_dim": 128,
+               "colbert_dim": 64,
+            },
+         });
+
+         fs::write(&meta_path, serde_json::to_string(&legacy).unwrap()).unwrap();
+
+         let mut store = MetaStore::load(store_id).unwrap();
+         assert!(store.model_mismatch());
+         assert!(matches!(store.ensure_model_matches(store_id), Err(Error::ModelMismatch { .. })));
+
+         // Saving without re-indexing must not overwrite the recorded models
+         store.save().unwrap();
+         let mut store = MetaStore::load(store_id).unwrap();
+         assert!(store.model_mismatch());
+         assert_eq!(store.model().unwrap().dense_model, "legacy-dense");
+
+         store.reset_for_model_change();
+         assert!(!store.model_mismatch());
+         assert_eq!(store.all_paths().count(), 0);
+
+         store.save().unwrap();
+
+         let reloaded = MetaStore::load(store_id).unwrap();
+         assert!(!reloaded.model_mismatch());
+      });
+   }
+}
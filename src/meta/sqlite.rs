@@ -0,0 +1,222 @@
+//! `SQLite` backend for [`MetaStore`](super::MetaStore).
+//!
+//! Keeps a store's metadata in `<meta_dir>/<store_id>.sqlite` with a row per
+//! file, so a save writes only the files changed since the previous one
+//! rather than the whole store, which matters once a repository has hundreds
+//! of thousands of files.
+
+use std::{
+   collections::HashMap,
+   path::{Path, PathBuf},
+};
+
+use parking_lot::Mutex;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, params};
+
+use super::{FileHash, FileMeta};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+   path     TEXT PRIMARY KEY,
+   hash     BLOB NOT NULL,
+   mtime    INTEGER NOT NULL,
+   size     INTEGER NOT NULL,
+   encoding TEXT
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS state (
+   key   TEXT PRIMARY KEY,
+   value TEXT NOT NULL
+);
+";
+
+/// Errors that can occur reading or writing a metadata database.
+#[derive(Debug, thiserror::Error)]
+pub enum MetaDbError {
+   #[error("failed to open metadata database: {0}")]
+   Open(#[source] rusqlite::Error),
+
+   #[error("failed to read metadata: {0}")]
+   Read(#[source] rusqlite::Error),
+
+   #[error("failed to write metadata: {0}")]
+   Write(#[source] rusqlite::Error),
+}
+
+impl MetaDbError {
+   /// Whether the database file is damaged, rather than out of reach.
+   pub fn is_corrupt(&self) -> bool {
+      let (Self::Open(e) | Self::Read(e) | Self::Write(e)) = self;
+      matches!(e.sqlite_error_code(), Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase))
+   }
+}
+
+/// Everything saved to a metadata database.
+pub struct Contents {
+   pub files: HashMap<PathBuf, FileMeta>,
+   /// Store-wide values by key, as JSON.
+   pub state: HashMap<String, String>,
+}
+
+/// Open metadata database of one store.
+pub struct MetaDb {
+   conn: Mutex<Connection>,
+}
+
+impl MetaDb {
+   /// Opens the database at `path`, creating it if needed.
+   pub fn open(path: &Path) -> Result<Self, MetaDbError> {
+      let conn = Connection::open(path).map_err(MetaDbError::Open)?;
+      conn.execute_batch(SCHEMA).map_err(MetaDbError::Open)?;
+      Ok(Self { conn: Mutex::new(conn) })
+   }
+
+   /// Reads the whole database, `None` if nothing was saved to it yet.
+   pub fn read(&self) -> Result<Option<Contents>, MetaDbError> {
+      let conn = self.conn.lock();
+      let mut stmt = conn
+         .prepare("SELECT key, value FROM state")
+         .map_err(MetaDbError::Read)?;
+      let state = stmt
+         .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+         .and_then(|rows| rows.collect::<rusqlite::Result<HashMap<String, String>>>())
+         .map_err(MetaDbError::Read)?;
+      if state.is_empty() {
+         return Ok(None);
+      }
+
+      let mut stmt = conn
+         .prepare("SELECT path, hash, mtime, size, encoding FROM files")
+         .map_err(MetaDbError::Read)?;
+      let rows = stmt
+         .query_map([], |row| {
+            let path: String = row.get(0)?;
+            let hash: Vec<u8> = row.get(1)?;
+            let mtime: i64 = row.get(2)?;
+            let size: i64 = row.get(3)?;
+            Ok((PathBuf::from(path), hash, mtime as u64, size as u64, row.get(4)?))
+         })
+         .map_err(MetaDbError::Read)?;
+      let mut files = HashMap::new();
+      for row in rows {
+         let (path, hash, mtime, size, encoding) = row.map_err(MetaDbError::Read)?;
+         // A file whose hash cannot be read is indexed again
+         if let Some(hash) = FileHash::from_slice(&hash) {
+            files.insert(path, FileMeta { hash, mtime, size, encoding });
+         }
+      }
+      Ok(Some(Contents { files, state }))
+   }
+
+   /// Reads one store-wide value, `None` if it was never saved.
+   pub fn state(&self, key: &str) -> Result<Option<String>, MetaDbError> {
+      let conn = self.conn.lock();
+      conn
+         .query_row("SELECT value FROM state WHERE key = ?1", [key], |row| row.get(0))
+         .optional()
+         .map_err(MetaDbError::Read)
+   }
+
+   /// Writes `upserted` and `state` and deletes `removed` in one
+   /// transaction, first deleting every file if `replace` is set.
+   pub fn write<'a>(
+      &self,
+      replace: bool,
+      upserted: impl Iterator<Item = (&'a Path, &'a FileMeta)>,
+      removed: impl Iterator<Item = &'a Path>,
+      state: &[(&str, String)],
+   ) -> Result<(), MetaDbError> {
+      let mut conn = self.conn.lock();
+      let tx = conn.transaction().map_err(MetaDbError::Write)?;
+      {
+         if replace {
+            tx.execute("DELETE FROM files", [])
+               .map_err(MetaDbError::Write)?;
+         }
+         let mut delete = tx
+            .prepare("DELETE FROM files WHERE path = ?1")
+            .map_err(MetaDbError::Write)?;
+         for path in removed {
+            delete
+               .execute([path.to_string_lossy()])
+               .map_err(MetaDbError::Write)?;
+         }
+         let mut upsert = tx
+            .prepare(
+               "INSERT OR REPLACE INTO files (path, hash, mtime, size, encoding) VALUES (?1, ?2, \
+                ?3, ?4, ?5)",
+            )
+            .map_err(MetaDbError::Write)?;
+         for (path, meta) in upserted {
+            upsert
+               .execute(params![
+                  path.to_string_lossy(),
+                  &meta.hash[..],
+                  meta.mtime as i64,
+                  meta.size as i64,
+                  meta.encoding
+               ])
+               .map_err(MetaDbError::Write)?;
+         }
+         let mut set = tx
+            .prepare("INSERT OR REPLACE INTO state (key, value) VALUES (?1, ?2)")
+            .map_err(MetaDbError::Write)?;
+         for (key, value) in state {
+            set.execute(params![key, value])
+               .map_err(MetaDbError::Write)?;
+         }
+      }
+      tx.commit().map_err(MetaDbError::Write)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use tempfile::TempDir;
+
+   use super::*;
+   use crate::meta::FileStat;
+
+   fn meta(content: &str, mtime: u64) -> FileMeta {
+      FileMeta { hash: FileHash::sum(content), mtime, size: content.len() as u64, encoding: None }
+   }
+
+   #[test]
+   fn writes_incrementally() {
+      let dir = TempDir::new().unwrap();
+      let db = MetaDb::open(&dir.path().join("store.sqlite")).unwrap();
+      assert!(db.read().unwrap().is_none());
+
+      let (a, b) = (Path::new("/repo/a.rs"), Path::new("/repo/b.rs"));
+      let (meta_a, meta_b) = (meta("a", 1), meta("b", 2));
+      let state = [("version", "1".to_string())];
+      db.write(false, [(a, &meta_a), (b, &meta_b)].into_iter(), std::iter::empty(), &state)
+         .unwrap();
+
+      let edited = FileMeta { encoding: Some("UTF-16LE".to_string()), ..meta("a2", 3) };
+      db.write(false, std::iter::once((a, &edited)), std::iter::once(b), &state)
+         .unwrap();
+
+      let contents = db.read().unwrap().unwrap();
+      assert_eq!(contents.state["version"], "1");
+      assert_eq!(db.state("version").unwrap().as_deref(), Some("1"));
+      assert_eq!(db.state("root").unwrap(), None);
+      assert_eq!(contents.files.len(), 1);
+      let saved = &contents.files[a];
+      assert_eq!((saved.hash, saved.stat()), (edited.hash, FileStat { mtime: 3, size: 2 }));
+      assert_eq!(saved.encoding.as_deref(), Some("UTF-16LE"));
+
+      db.write(true, std::iter::empty(), std::iter::empty(), &state)
+         .unwrap();
+      assert!(db.read().unwrap().unwrap().files.is_empty());
+   }
+
+   #[test]
+   fn detects_corrupt_database() {
+      let dir = TempDir::new().unwrap();
+      let path = dir.path().join("store.sqlite");
+      std::fs::write(&path, "not a database, just some text long enough to fill a header").unwrap();
+
+      let err = MetaDb::open(&path).err().unwrap();
+      assert!(err.is_corrupt());
+   }
+}
@@ -0,0 +1,263 @@
+//! Cross-encoder reranking, an optional stage after `ColBERT`.
+//!
+//! A cross-encoder reads the query and a chunk together in one model pass, so
+//! it catches matches that token-level `MaxSim` misses. That pass is much
+//! slower than `MaxSim`, so only the top `cross_encoder_depth` results of a
+//! search are rescored, and only with `--rerank cross`. The model is
+//! downloaded and loaded on first use.
+
+use std::{cmp::Ordering, fs, sync::Arc};
+
+use candle_core::{DType, Device, Module, Tensor};
+use candle_nn::{Linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use parking_lot::RwLock;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tokio::sync::Mutex;
+
+use crate::{
+   config,
+   embed::{
+      CandleEmbedder,
+      candle::{EmbeddingError, optimal_dtype},
+   },
+   error::Result,
+   types::SearchResult,
+};
+
+/// Stages that rescore a search's candidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RerankMode {
+   /// `ColBERT` `MaxSim` over the top `rerank_depth` candidates.
+   #[default]
+   Colbert,
+   /// `ColBERT`, then the cross-encoder over the top `cross_encoder_depth`
+   /// results.
+   Cross,
+}
+
+/// BERT sequence classifier scoring (query, chunk) pairs.
+struct Model {
+   bert:       BertModel,
+   pooler:     Linear,
+   classifier: Linear,
+   tokenizer:  Tokenizer,
+   device:     Device,
+}
+
+/// Cross-encoder configured by `cross_encoder_model`, loaded on first use.
+#[derive(Default)]
+pub struct CrossEncoder {
+   model:     RwLock<Option<Arc<Model>>>,
+   init_lock: Mutex<()>,
+}
+
+impl CrossEncoder {
+   pub fn new() -> Self {
+      Self::default()
+   }
+
+   async fn model(&self) -> Result<Arc<Model>> {
+      if let Some(model) = self.model.read().as_ref() {
+         return Ok(Arc::clone(model));
+      }
+      let _guard = self.init_lock.lock().await;
+      if let Some(model) = self.model.read().as_ref() {
+         return Ok(Arc::clone(model));
+      }
+      let model = Arc::new(Self::load().await?);
+      *self.model.write() = Some(Arc::clone(&model));
+      Ok(model)
+   }
+
+   /// Drops the model if it is loaded, returning whether it was. The next
+   /// rerank loads it again.
+   pub fn unload(&self) -> bool {
+      self.model.write().take().is_some()
+   }
+
+   async fn load() -> Result<Model> {
+      let cfg = config::get();
+      let device = if cfg.disable_gpu {
+         Device::Cpu
+      } else {
+         CandleEmbedder::select_device()
+      };
+      let model_path = CandleEmbedder::download_model(&cfg.cross_encoder_model).await?;
+
+      if cfg.debug_models {
+         tracing::info!("loading cross-encoder from {:?}", model_path);
+      }
+
+      let mut tokenizer = Tokenizer::from_file(model_path.join("tokenizer.json"))
+         .map_err(EmbeddingError::LoadTokenizer)?;
+      tokenizer
+         .with_truncation(Some(TruncationParams {
+            max_length: cfg.cross_encoder_max_length,
+            ..Default::default()
+         }))
+         .map_err(EmbeddingError::LoadTokenizer)?;
+      tokenizer.with_padding(Some(PaddingParams::default()));
+
+      let config: BertConfig = serde_json::from_str(
+         &fs::read_to_string(model_path.join("config.json")).map_err(EmbeddingError::ReadConfig)?,
+      )?;
+
+      // SAFETY: VarBuilder::from_mmaped_safetensors is safe to use as it properly
+      // handles memory mapping
+      let vb = unsafe {
+         VarBuilder::from_mmaped_safetensors(
+            &[model_path.join("model.safetensors")],
+            optimal_dtype(&device),
+            &device,
+         )
+         .map_err(EmbeddingError::LoadWeights)?
+      };
+
+      let bert = BertModel::load(vb.clone(), &config).map_err(EmbeddingError::LoadModel)?;
+      let pooler =
+         candle_nn::linear(config.hidden_size, config.hidden_size, vb.pp("bert.pooler.dense"))
+            .map_err(EmbeddingError::LoadClassifier)?;
+      let classifier = candle_nn::linear(config.hidden_size, 1, vb.pp("classifier"))
+         .map_err(EmbeddingError::LoadClassifier)?;
+
+      Ok(Model { bert, pooler, classifier, tokenizer, device })
+   }
+
+   /// Scores how relevant each of `texts` is to `query`, between 0 and 1.
+   pub async fn score(&self, query: &str, texts: &[&str]) -> Result<Vec<f32>> {
+      if texts.is_empty() {
+         return Ok(Vec::new());
+      }
+      let model = self.model().await?;
+
+      let pairs: Vec<(&str, &str)> = texts.iter().map(|&text| (query, text)).collect();
+      let encodings = model
+         .tokenizer
+         .encode_batch(pairs, true)
+         .map_err(EmbeddingError::from)?;
+      let seq_len = encodings.first().map_or(0, |e| e.len());
+      let tensor = |field: fn(&tokenizers::Encoding) -> &[u32]| {
+         let data: Vec<u32> = encodings.iter().flat_map(|e| field(e).to_vec()).collect();
+         Tensor::from_vec(data, (texts.len(), seq_len), &model.device)
+            .map_err(EmbeddingError::CreateTensor)
+      };
+      let input_ids = tensor(tokenizers::Encoding::get_ids)?;
+      let type_ids = tensor(tokenizers::Encoding::get_type_ids)?;
+      let attention_mask = tensor(tokenizers::Encoding::get_attention_mask)?;
+
+      let hidden = model
+         .bert
+         .forward(&input_ids, &type_ids, Some(&attention_mask))
+         .map_err(EmbeddingError::ForwardPass)?;
+      let cls = hidden
+         .narrow(1, 0, 1)
+         .and_then(|t| t.squeeze(1))
+         .map_err(EmbeddingError::ExtractCls)?;
+      let logits = model
+         .pooler
+         .forward(&cls)
+         .and_then(|t| t.tanh())
+         .and_then(|t| model.classifier.forward(&t))
+         .and_then(|t| t.squeeze(1))
+         .map_err(EmbeddingError::ForwardPass)?;
+
+      let logits: Vec<f32> = logits
+         .to_dtype(DType::F32)
+         .map_err(EmbeddingError::DtypeConversion)?
+         .to_device(&Device::Cpu)
+         .map_err(EmbeddingError::Convert)?
+         .to_vec1()
+         .map_err(EmbeddingError::ConvertToVec)?;
+      Ok(logits.into_iter().map(sigmoid).collect())
+   }
+
+   /// Reorders the top `depth` results by cross-encoder relevance to `query`;
+   /// see [`reorder`].
+   #[tracing::instrument(name = "cross_rerank", skip_all, fields(depth = depth))]
+   pub async fn rerank(
+      &self,
+      query: &str,
+      results: &mut Vec<SearchResult>,
+      depth: usize,
+   ) -> Result<()> {
+      let depth = depth.min(results.len());
+      let texts: Vec<&str> = results[..depth]
+         .iter()
+         .map(|r| r.content.as_str())
+         .collect();
+      let scores = self.score(query, &texts).await?;
+      reorder(results, &scores);
+      Ok(())
+   }
+}
+
+/// Sorts the results that have a cross-encoder score, the first
+/// `scores.len()`, by that score. They take over the scores of the ranks they
+/// move into, so scores still decrease down the list.
+pub fn reorder(results: &mut Vec<SearchResult>, scores: &[f32]) {
+   let mut rank_scores: Vec<f32> = results[..scores.len()].iter().map(|r| r.score).collect();
+   rank_scores.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+   let mut top: Vec<(f32, SearchResult)> = scores
+      .iter()
+      .copied()
+      .zip(results.drain(..scores.len()))
+      .collect();
+   top.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+   let top = top
+      .into_iter()
+      .zip(rank_scores)
+      .map(|((cross, mut result), score)| {
+         result.score = score;
+         if let Some(breakdown) = &mut result.breakdown {
+            breakdown.cross = Some(cross);
+         }
+         result
+      });
+   results.splice(0..0, top);
+}
+
+fn sigmoid(x: f32) -> f32 {
+   1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::types::ScoreBreakdown;
+
+   fn result(content: &str, score: f32) -> SearchResult {
+      SearchResult {
+         path: "/repo/src/lib.rs".into(),
+         content: content.into(),
+         score,
+         start_line: 1,
+         num_lines: 1,
+         chunk_type: None,
+         is_anchor: None,
+         breakdown: Some(ScoreBreakdown { fused: score, ..Default::default() }),
+         bridges: None,
+         language: None,
+         context_prev: None,
+         context_next: None,
+         linked: Vec::new(),
+      }
+   }
+
+   #[test]
+   fn reorders_top_results_by_cross_score() {
+      let mut results =
+         vec![result("a", 0.9), result("b", 0.8), result("c", 0.7), result("d", 0.1)];
+
+      reorder(&mut results, &[0.2, 0.3, 0.95]);
+
+      let order: Vec<&str> = results.iter().map(|r| r.content.as_str()).collect();
+      assert_eq!(order, ["c", "b", "a", "d"]);
+      let scores: Vec<f32> = results.iter().map(|r| r.score).collect();
+      assert_eq!(scores, [0.9, 0.8, 0.7, 0.1]);
+      assert_eq!(results[0].breakdown.unwrap().cross, Some(0.95));
+      assert_eq!(results[3].breakdown.unwrap().cross, None);
+   }
+}
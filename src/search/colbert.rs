@@ -1,13 +1,31 @@
 //! `ColBERT` `MaxSim` scoring with SIMD optimization and quantized inference.
-
-use std::simd::{
-   Simd,
-   cmp::SimdPartialEq,
-   f32x8,
-   num::{SimdFloat, SimdInt},
+//!
+//! Large rerank batches run as one matmul on the GPU when there is one; see
+//! [`max_sim_batch`].
+
+use std::{
+   simd::{
+      LaneCount, Simd, StdFloat, SupportedLaneCount,
+      cmp::SimdPartialEq,
+      f32x8,
+      num::{SimdFloat, SimdInt, SimdUint},
+   },
+   sync::OnceLock,
 };
 
+use candle_core::{Device, Tensor};
 use ndarray::Array2;
+use rayon::{
+   ThreadPool, ThreadPoolBuilder,
+   iter::{IntoParallelRefIterator, ParallelIterator},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config, embed::CandleEmbedder};
+
+/// Documents from which [`max_sim_batch`] moves to the GPU. Below this, the
+/// transfer costs more than the CPU loop.
+pub const GPU_MIN_DOCS: usize = 64;
 
 /// Computes `MaxSim` score between query and document token matrices.
 ///
@@ -92,16 +110,82 @@ pub fn dequantize_colbert(quantized: &[u8], scale: f64, dim: usize) -> Array2<f3
       .expect("data length must match shape")
 }
 
+/// Instruction set used for quantized `MaxSim`, picked once at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdLevel {
+   /// 16 lanes with FMA, on x86-64 with AVX-512F and AVX-512BW.
+   Avx512,
+   /// 8 lanes with FMA, on x86-64 with AVX2 and FMA.
+   Avx2,
+   /// 8 lanes with FMA, in pairs of NEON registers on aarch64.
+   Neon,
+   /// 8 lanes without FMA, lowered to whatever the build target supports.
+   Portable,
+}
+
+impl SimdLevel {
+   /// Every level, best first.
+   pub const ALL: [Self; 4] = [Self::Avx512, Self::Avx2, Self::Neon, Self::Portable];
+
+   /// Best level this CPU supports, detected on first use.
+   pub fn detect() -> Self {
+      static LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+      *LEVEL.get_or_init(|| {
+         Self::ALL
+            .into_iter()
+            .find(|level| level.is_supported())
+            .unwrap_or(Self::Portable)
+      })
+   }
+
+   /// Whether this CPU can run the level.
+   pub fn is_supported(self) -> bool {
+      match self {
+         #[cfg(target_arch = "x86_64")]
+         Self::Avx512 => {
+            is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw")
+         },
+         #[cfg(target_arch = "x86_64")]
+         Self::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+         #[cfg(target_arch = "aarch64")]
+         Self::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+         Self::Portable => true,
+         _ => false,
+      }
+   }
+
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Avx512 => "avx512",
+         Self::Avx2 => "avx2",
+         Self::Neon => "neon",
+         Self::Portable => "portable",
+      }
+   }
+}
+
 /// Computes `MaxSim` score directly on quantized document embeddings without
 /// dequantization.
 ///
 /// Streams dot products on-the-fly with SIMD acceleration, skipping all-zero
-/// padding tokens.
+/// padding tokens. Uses the best [`SimdLevel`] this CPU supports.
 pub fn max_sim_quantized(
    query: &Array2<f32>,
    doc_quantized: &[u8],
    doc_scale: f64,
    dim: usize,
+) -> f32 {
+   max_sim_quantized_with(SimdLevel::detect(), query, doc_quantized, doc_scale, dim)
+}
+
+/// [`max_sim_quantized`] at a given [`SimdLevel`], which falls back to
+/// [`SimdLevel::Portable`] when the CPU does not support it.
+pub fn max_sim_quantized_with(
+   level: SimdLevel,
+   query: &Array2<f32>,
+   doc_quantized: &[u8],
+   doc_scale: f64,
+   dim: usize,
 ) -> f32 {
    if query.is_empty() || doc_quantized.is_empty() || dim == 0 {
       return 0.0;
@@ -109,67 +193,100 @@ pub fn max_sim_quantized(
 
    debug_assert_eq!(query.ncols(), dim, "dimension mismatch");
 
-   let valid_len = (doc_quantized.len() / dim) * dim;
-   let doc_quantized = &doc_quantized[..valid_len];
-   let num_doc_tokens = valid_len / dim;
-   let scale_factor = doc_scale as f32;
-   const LANES: usize = 8;
-   let chunk_count = dim / LANES;
-   let remainder = dim % LANES;
-   let mut q_chunks: Box<[Simd<f32, LANES>]> =
-      vec![Simd::splat(0.0); chunk_count].into_boxed_slice();
-   let scale_simd = Simd::splat(scale_factor);
-   let zero_i8 = Simd::<i8, LANES>::splat(0);
+   match level {
+      #[cfg(target_arch = "x86_64")]
+      SimdLevel::Avx512 if level.is_supported() => {
+         // SAFETY: the CPU supports AVX-512F and AVX-512BW, checked just above.
+         unsafe { max_sim_avx512(query, doc_quantized, doc_scale, dim) }
+      },
+      #[cfg(target_arch = "x86_64")]
+      SimdLevel::Avx2 if level.is_supported() => {
+         // SAFETY: the CPU supports AVX2 and FMA, checked just above.
+         unsafe { max_sim_avx2(query, doc_quantized, doc_scale, dim) }
+      },
+      #[cfg(target_arch = "aarch64")]
+      SimdLevel::Neon if level.is_supported() => {
+         // SAFETY: the CPU supports NEON, checked just above.
+         unsafe { max_sim_neon(query, doc_quantized, doc_scale, dim) }
+      },
+      _ => max_sim_kernel::<8, false>(query, doc_quantized, doc_scale, dim),
+   }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+fn max_sim_avx512(query: &Array2<f32>, doc: &[u8], scale: f64, dim: usize) -> f32 {
+   max_sim_kernel::<16, true>(query, doc, scale, dim)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn max_sim_avx2(query: &Array2<f32>, doc: &[u8], scale: f64, dim: usize) -> f32 {
+   max_sim_kernel::<8, true>(query, doc, scale, dim)
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+fn max_sim_neon(query: &Array2<f32>, doc: &[u8], scale: f64, dim: usize) -> f32 {
+   max_sim_kernel::<8, true>(query, doc, scale, dim)
+}
+
+/// Quantized `MaxSim` with `LANES`-wide vectors, fusing multiply and add when
+/// `FMA` is set. Inlined into each target-feature wrapper so that it is
+/// compiled for that instruction set.
+///
+/// Dot products are taken against the raw int8 values and scaled once per
+/// token rather than once per element.
+#[inline(always)]
+fn max_sim_kernel<const LANES: usize, const FMA: bool>(
+   query: &Array2<f32>,
+   doc_quantized: &[u8],
+   doc_scale: f64,
+   dim: usize,
+) -> f32
+where
+   LaneCount<LANES>: SupportedLaneCount,
+{
+   let scale = doc_scale as f32;
+   let tail_start = dim / LANES * LANES;
+   let zero = Simd::<i8, LANES>::splat(0);
 
    let mut total_score = 0.0;
-   let mut d_buf = [0i8; LANES];
 
-   for q_idx in 0..query.nrows() {
-      let q_view = query.row(q_idx);
+   for q_view in query.rows() {
       let q_row = q_view.as_slice().unwrap();
       let mut max_dot = f32::NEG_INFINITY;
       let mut found_token = false;
 
-      for (chunk_idx, slot) in q_chunks.iter_mut().enumerate() {
-         let offset = chunk_idx * LANES;
-         *slot = Simd::<f32, LANES>::from_slice(&q_row[offset..offset + LANES]);
-      }
-      let q_tail = &q_row[chunk_count * LANES..];
-
-      for d_idx in 0..num_doc_tokens {
-         let d_start = d_idx * dim;
-         let d_slice = &doc_quantized[d_start..d_start + dim];
-
+      for d_slice in doc_quantized.chunks_exact(dim) {
+         let mut acc = Simd::<f32, LANES>::splat(0.0);
          let mut is_padding = true;
-         let mut dot = 0.0f32;
 
-         for (chunk_idx, q_chunk) in q_chunks.iter().enumerate() {
-            let offset = chunk_idx * LANES;
-            for lane in 0..LANES {
-               d_buf[lane] = d_slice[offset + lane] as i8;
-            }
-            let d_chunk = Simd::<i8, LANES>::from_array(d_buf);
-            if d_chunk.simd_ne(zero_i8).any() {
+         for (q_chunk, d_chunk) in q_row[..tail_start]
+            .chunks_exact(LANES)
+            .zip(d_slice[..tail_start].chunks_exact(LANES))
+         {
+            let d = Simd::<u8, LANES>::from_slice(d_chunk).cast::<i8>();
+            if d.simd_ne(zero).any() {
                is_padding = false;
             }
-            let d_f32 = d_chunk.cast::<f32>() * scale_simd;
-            dot += (*q_chunk * d_f32).reduce_sum();
+            let q = Simd::<f32, LANES>::from_slice(q_chunk);
+            let d = d.cast::<f32>();
+            acc = if FMA { q.mul_add(d, acc) } else { acc + q * d };
          }
 
-         if remainder > 0 {
-            let base = chunk_count * LANES;
-            for i in 0..remainder {
-               let quantized_val = d_slice[base + i] as i8 as f32;
-               if quantized_val != 0.0 {
-                  is_padding = false;
-               }
-               dot += q_tail[i] * quantized_val * scale_factor;
+         let mut dot = acc.reduce_sum();
+         for (&q, &d) in q_row[tail_start..].iter().zip(&d_slice[tail_start..]) {
+            let d = d as i8;
+            if d != 0 {
+               is_padding = false;
             }
+            dot += q * d as f32;
          }
 
          if !is_padding {
             found_token = true;
-            max_dot = max_dot.max(dot);
+            max_dot = max_dot.max(dot * scale);
          }
       }
 
@@ -181,6 +298,357 @@ pub fn max_sim_quantized(
    total_score
 }
 
+/// `ColBERT` query tokens quantized to int8 with one scale per token, so that
+/// `MaxSim` against quantized documents runs on integer dot products.
+///
+/// Values are kept widened to i16, the width the kernels multiply at.
+#[derive(Debug, Clone)]
+pub struct QuantizedQuery {
+   data:   Vec<i16>,
+   scales: Vec<f32>,
+   dim:    usize,
+}
+
+impl QuantizedQuery {
+   /// Quantizes each token of `query` by its largest absolute value.
+   pub fn new(query: &Array2<f32>) -> Self {
+      let dim = query.ncols();
+      let mut data = Vec::with_capacity(query.len());
+      let mut scales = Vec::with_capacity(query.nrows());
+      for row in query.rows() {
+         let max = row
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold(0.0f32, |max, v| max.max(v.abs()));
+         if max == 0.0 {
+            data.extend(std::iter::repeat_n(0, dim));
+            scales.push(0.0);
+            continue;
+         }
+         let inv = 127.0 / max;
+         data.extend(
+            row.iter()
+               .map(|&v| (v * inv).round().clamp(-127.0, 127.0) as i16),
+         );
+         scales.push(max / 127.0);
+      }
+      Self { data, scales, dim }
+   }
+
+   pub const fn dim(&self) -> usize {
+      self.dim
+   }
+}
+
+/// Computes `MaxSim` between an int8 query and a quantized document, like
+/// [`max_sim_quantized`] but without converting document bytes to floats.
+///
+/// Dot products accumulate in integers; each query token's best one is
+/// scaled once, by its own scale and the document's. Uses the best
+/// [`SimdLevel`] this CPU supports.
+pub fn max_sim_int8(query: &QuantizedQuery, doc_quantized: &[u8], doc_scale: f64) -> f32 {
+   max_sim_int8_scratch(query, doc_quantized, doc_scale, &mut MaxSimScratch::default())
+}
+
+/// [`max_sim_int8`] reusing the buffers in `scratch`, for scoring many
+/// documents without reallocating.
+pub fn max_sim_int8_scratch(
+   query: &QuantizedQuery,
+   doc_quantized: &[u8],
+   doc_scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   max_sim_int8_with(SimdLevel::detect(), query, doc_quantized, doc_scale, scratch)
+}
+
+/// Buffers for [`max_sim_int8_scratch`]: the best dot product per query
+/// token and the document token being scored, widened.
+#[derive(Debug, Default)]
+pub struct MaxSimScratch {
+   best:  Vec<i32>,
+   token: Vec<i16>,
+}
+
+/// [`max_sim_int8_scratch`] at a given [`SimdLevel`], which falls back to
+/// [`SimdLevel::Portable`] when the CPU does not support it.
+pub fn max_sim_int8_with(
+   level: SimdLevel,
+   query: &QuantizedQuery,
+   doc_quantized: &[u8],
+   doc_scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   if query.data.is_empty() || doc_quantized.is_empty() || query.dim == 0 {
+      return 0.0;
+   }
+
+   match level {
+      #[cfg(target_arch = "x86_64")]
+      SimdLevel::Avx512 if is_x86_feature_detected!("avx512vnni") && level.is_supported() => {
+         // SAFETY: the CPU supports AVX-512F, AVX-512BW and AVX-512 VNNI,
+         // checked just above.
+         unsafe { max_sim_int8_avx512_vnni(query, doc_quantized, doc_scale, scratch) }
+      },
+      #[cfg(target_arch = "x86_64")]
+      SimdLevel::Avx512 if level.is_supported() => {
+         // SAFETY: the CPU supports AVX-512F and AVX-512BW, checked just above.
+         unsafe { max_sim_int8_avx512(query, doc_quantized, doc_scale, scratch) }
+      },
+      #[cfg(target_arch = "x86_64")]
+      SimdLevel::Avx2 if level.is_supported() => {
+         // SAFETY: the CPU supports AVX2 and FMA, checked just above.
+         unsafe { max_sim_int8_avx2(query, doc_quantized, doc_scale, scratch) }
+      },
+      #[cfg(target_arch = "aarch64")]
+      SimdLevel::Neon if level.is_supported() => {
+         // SAFETY: the CPU supports NEON, checked just above.
+         unsafe { max_sim_int8_neon(query, doc_quantized, doc_scale, scratch) }
+      },
+      _ => max_sim_int8_kernel::<16>(query, doc_quantized, doc_scale, scratch),
+   }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw,avx512vnni")]
+fn max_sim_int8_avx512_vnni(
+   query: &QuantizedQuery,
+   doc: &[u8],
+   scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   max_sim_int8_kernel::<32>(query, doc, scale, scratch)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw")]
+fn max_sim_int8_avx512(
+   query: &QuantizedQuery,
+   doc: &[u8],
+   scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   max_sim_int8_kernel::<32>(query, doc, scale, scratch)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn max_sim_int8_avx2(
+   query: &QuantizedQuery,
+   doc: &[u8],
+   scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   max_sim_int8_kernel::<32>(query, doc, scale, scratch)
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+fn max_sim_int8_neon(
+   query: &QuantizedQuery,
+   doc: &[u8],
+   scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32 {
+   max_sim_int8_kernel::<16>(query, doc, scale, scratch)
+}
+
+/// Integer `MaxSim` with `LANES` values per step. Products of two int8 values
+/// fit in i16 and are summed into i32 lanes, the shape that `pmaddwd`, VNNI
+/// and `smlal` accelerate. Inlined into each target-feature wrapper so that
+/// it is compiled for that instruction set.
+#[inline(always)]
+fn max_sim_int8_kernel<const LANES: usize>(
+   query: &QuantizedQuery,
+   doc_quantized: &[u8],
+   doc_scale: f64,
+   scratch: &mut MaxSimScratch,
+) -> f32
+where
+   LaneCount<LANES>: SupportedLaneCount,
+{
+   let dim = query.dim;
+   let tail_start = dim / LANES * LANES;
+   let MaxSimScratch { best, token: d_slice } = scratch;
+   best.clear();
+   best.resize(query.scales.len(), i32::MIN);
+   d_slice.clear();
+   d_slice.resize(dim, 0);
+   let mut found_token = false;
+
+   for d_bytes in doc_quantized.chunks_exact(dim) {
+      if d_bytes.iter().all(|&b| b == 0) {
+         continue;
+      }
+      found_token = true;
+      // Widened once per document token and reused for every query token.
+      for (wide, &b) in d_slice.iter_mut().zip(d_bytes) {
+         *wide = i16::from(b as i8);
+      }
+
+      for (q_row, best) in query.data.chunks_exact(dim).zip(best.iter_mut()) {
+         let mut acc = Simd::<i32, LANES>::splat(0);
+         for (q_chunk, d_chunk) in q_row[..tail_start]
+            .chunks_exact(LANES)
+            .zip(d_slice[..tail_start].chunks_exact(LANES))
+         {
+            let q = Simd::<i16, LANES>::from_slice(q_chunk);
+            let d = Simd::<i16, LANES>::from_slice(d_chunk);
+            acc += (q * d).cast::<i32>();
+         }
+
+         let mut dot = acc.reduce_sum();
+         for (&q, &d) in q_row[tail_start..].iter().zip(&d_slice[tail_start..]) {
+            dot += i32::from(q) * i32::from(d);
+         }
+         *best = (*best).max(dot);
+      }
+   }
+
+   if !found_token {
+      return 0.0;
+   }
+   let total: f32 = best
+      .iter()
+      .zip(&query.scales)
+      .map(|(&dot, &scale)| dot as f32 * scale)
+      .sum();
+   total * doc_scale as f32
+}
+
+/// Computes [`max_sim_quantized`] between the query and each of `docs`, given
+/// as quantized embeddings and their scales.
+///
+/// From [`GPU_MIN_DOCS`] documents on, and when a GPU is available, every
+/// score comes from a single batched matmul on the device. Otherwise, or if
+/// the device fails, documents are scored on the CPU with [`max_sim_int8`],
+/// spread over a pool of `default_threads()` workers that each reuse one
+/// [`MaxSimScratch`]. Documents left once `cancel` fires are not scored, and
+/// only the scores before the first of them are returned.
+#[tracing::instrument(name = "colbert_rerank", skip_all, fields(docs = docs.len()))]
+pub fn max_sim_batch(
+   query: &Array2<f32>,
+   docs: &[(&[u8], f64)],
+   dim: usize,
+   cancel: Option<&CancellationToken>,
+) -> Vec<f32> {
+   if docs.len() >= GPU_MIN_DOCS
+      && !query.is_empty()
+      && let Some(device) = gpu()
+   {
+      match max_sim_on_device(query, docs, dim, device) {
+         Ok(scores) => return scores,
+         Err(e) => tracing::warn!("batched MaxSim failed on {device:?}, scoring on CPU: {e}"),
+      }
+   }
+
+   debug_assert_eq!(query.ncols(), dim, "dimension mismatch");
+   let quantized = QuantizedQuery::new(query);
+   let score = |scratch: &mut MaxSimScratch, &(doc, scale): &(&[u8], f64)| {
+      (!cancel.is_some_and(CancellationToken::is_cancelled))
+         .then(|| max_sim_int8_scratch(&quantized, doc, scale, scratch))
+   };
+   let scores: Vec<Option<f32>> = match cpu_pool() {
+      Some(pool) if docs.len() > 1 => pool.install(|| {
+         docs
+            .par_iter()
+            .map_init(MaxSimScratch::default, score)
+            .collect()
+      }),
+      _ => {
+         let mut scratch = MaxSimScratch::default();
+         docs.iter().map(|doc| score(&mut scratch, doc)).collect()
+      },
+   };
+   scores.into_iter().map_while(|score| score).collect()
+}
+
+/// Worker pool for CPU `MaxSim`, sized by `default_threads()`. `None` if the
+/// pool could not be started, in which case documents are scored serially.
+fn cpu_pool() -> Option<&'static ThreadPool> {
+   static POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
+   POOL
+      .get_or_init(|| {
+         ThreadPoolBuilder::new()
+            .num_threads(config::get().default_threads())
+            .thread_name(|i| format!("maxsim-{i}"))
+            .build()
+            .inspect_err(|e| tracing::warn!("failed to start MaxSim workers: {e}"))
+            .ok()
+      })
+      .as_ref()
+}
+
+/// GPU for batched `MaxSim`, unless there is none or `disable_gpu` is set.
+fn gpu() -> Option<&'static Device> {
+   static GPU: OnceLock<Option<Device>> = OnceLock::new();
+   GPU.get_or_init(|| {
+      if config::get().disable_gpu {
+         return None;
+      }
+      let device = CandleEmbedder::select_device();
+      (!device.is_cpu()).then_some(device)
+   })
+   .as_ref()
+}
+
+/// Scores every document in one matmul on `device`. Documents are padded to
+/// the longest one; padding tokens get a similarity of minus infinity, so
+/// they never win the maximum.
+fn max_sim_on_device(
+   query: &Array2<f32>,
+   docs: &[(&[u8], f64)],
+   dim: usize,
+   device: &Device,
+) -> candle_core::Result<Vec<f32>> {
+   let dequantized: Vec<(Vec<f32>, usize)> = docs
+      .iter()
+      .map(|&(doc, scale)| {
+         let mut tokens = Vec::new();
+         let count = dequantize_colbert_scratch(doc, scale, dim, &mut tokens);
+         (tokens, count)
+      })
+      .collect();
+   let max_tokens = dequantized
+      .iter()
+      .map(|(_, count)| *count)
+      .max()
+      .unwrap_or(0);
+   if max_tokens == 0 {
+      return Ok(vec![0.0; docs.len()]);
+   }
+
+   let mut padded = vec![0.0f32; docs.len() * max_tokens * dim];
+   let mut mask = vec![f32::NEG_INFINITY; docs.len() * max_tokens];
+   for (i, (tokens, count)) in dequantized.iter().enumerate() {
+      padded[i * max_tokens * dim..][..tokens.len()].copy_from_slice(tokens);
+      mask[i * max_tokens..][..*count].fill(0.0);
+   }
+
+   let docs_t = Tensor::from_vec(padded, (docs.len(), max_tokens, dim), device)?;
+   let mask_t = Tensor::from_vec(mask, (docs.len(), max_tokens, 1), device)?;
+   let query_t = Tensor::from_iter(query.iter().copied(), device)?
+      .reshape((query.nrows(), dim))?
+      .t()?
+      .contiguous()?;
+
+   // (docs, doc tokens, query tokens): the maximum over doc tokens, summed
+   // over query tokens.
+   let scores = docs_t
+      .broadcast_matmul(&query_t)?
+      .broadcast_add(&mask_t)?
+      .max(1)?
+      .sum(1)?
+      .to_device(&Device::Cpu)?
+      .to_vec1::<f32>()?;
+
+   // Documents made only of padding score 0, as in `max_sim_quantized`.
+   Ok(scores
+      .into_iter()
+      .zip(&dequantized)
+      .map(|(score, (_, count))| if *count == 0 { 0.0 } else { score })
+      .collect())
+}
+
 /// Dequantizes int8 `ColBERT` embeddings into a reusable scratch buffer.
 ///
 /// Clears and reuses the provided buffer to avoid allocation. Returns number of
@@ -233,7 +701,7 @@ pub fn dequantize_colbert_scratch(
 /// SIMD-optimized dot product using 8-lane vectors.
 /// Falls back to scalar for tail elements that don't fit in a full SIMD lane.
 #[inline]
-fn dot_product_simd(a: &[f32], b: &[f32]) -> f32 {
+pub fn dot_product_simd(a: &[f32], b: &[f32]) -> f32 {
    debug_assert_eq!(a.len(), b.len(), "dot product requires equal-length vectors");
 
    const LANES: usize = 8;
@@ -436,6 +904,90 @@ mod tests {
       assert!((score - expected_doc1.max(expected_doc3)).abs() < 1e-4);
    }
 
+   #[test]
+   fn simd_levels_agree() {
+      for dim in [13, 96] {
+         let query = matrix(
+            (0..3 * dim)
+               .map(|i| ((i * 7) % 11) as f32 / 11.0 - 0.5)
+               .collect(),
+            dim,
+         );
+         let mut doc: Vec<u8> = (0..4 * dim).map(|i| ((i * 37) % 255) as u8).collect();
+         doc[dim..2 * dim].fill(0);
+         let scale = 0.01;
+
+         let portable = max_sim_quantized_with(SimdLevel::Portable, &query, &doc, scale, dim);
+         for level in SimdLevel::ALL {
+            let score = max_sim_quantized_with(level, &query, &doc, scale, dim);
+            assert!(
+               (score - portable).abs() < 1e-3,
+               "{} at {dim}: {score} vs {portable}",
+               level.as_str()
+            );
+         }
+      }
+      assert!(SimdLevel::detect().is_supported());
+   }
+
+   #[test]
+   fn int8_max_sim_matches_float_kernel() {
+      for dim in [13, 96] {
+         let query = matrix(
+            (0..3 * dim)
+               .map(|i| ((i * 7) % 11) as f32 / 11.0 - 0.5)
+               .collect(),
+            dim,
+         );
+         let quantized = QuantizedQuery::new(&query);
+         let mut doc: Vec<u8> = (0..4 * dim).map(|i| ((i * 37) % 255) as u8).collect();
+         doc[dim..2 * dim].fill(0);
+         let scale = 0.01;
+         let mut scratch = MaxSimScratch::default();
+
+         let expected = max_sim_quantized(&query, &doc, scale, dim);
+         for level in SimdLevel::ALL {
+            let score = max_sim_int8_with(level, &quantized, &doc, scale, &mut scratch);
+            assert!(
+               (score - expected).abs() <= expected.abs() * 0.01,
+               "{} at {dim}: {score} vs {expected}",
+               level.as_str()
+            );
+         }
+         assert!(max_sim_int8(&quantized, &vec![0; 2 * dim], scale).abs() < f32::EPSILON);
+      }
+   }
+
+   #[test]
+   fn batched_max_sim_matches_per_document_scores() {
+      let dim = 2;
+      let query = matrix(vec![1.0, 0.5, -0.25, 1.0], dim);
+      let docs: Vec<(Vec<u8>, f64)> = vec![
+         (vec![127, 64, 0, 0, 100, 50], 1.0 / 127.0),
+         (vec![-127i8 as u8, 20], 0.5 / 127.0),
+         (vec![0, 0], 1.0),
+      ];
+      let docs: Vec<(&[u8], f64)> = docs.iter().map(|(d, s)| (d.as_slice(), *s)).collect();
+
+      let expected: Vec<f32> = docs
+         .iter()
+         .map(|&(doc, scale)| max_sim_quantized(&query, doc, scale, dim))
+         .collect();
+      let batched = max_sim_on_device(&query, &docs, dim, &Device::Cpu).unwrap();
+      for (b, e) in batched.iter().zip(&expected) {
+         assert!((b - e).abs() < 1e-4, "batched {b} vs {e}");
+      }
+      let scored = max_sim_batch(&query, &docs, dim, None);
+      assert_eq!(scored.len(), expected.len());
+      for (s, e) in scored.iter().zip(&expected) {
+         assert!((s - e).abs() < 0.02, "batched {s} vs {e}");
+      }
+
+      let cancel = CancellationToken::new();
+      cancel.cancel();
+      assert!(max_sim_batch(&query, &docs, dim, Some(&cancel)).is_empty());
+   }
+
    #[test]
    fn test_dequantize_colbert_scratch() {
       let quantized = vec![127, 0, -127i8 as u8, 64];
@@ -0,0 +1,157 @@
+//! Fusion of the dense, full-text and `ColBERT` signals into one score.
+//!
+//! Backends record each signal a candidate received in a [`ScoreBreakdown`];
+//! [`fuse`] then combines them according to the `fusion*` settings.
+
+use crate::{
+   config::{self, FusionMethod},
+   types::{ScoreBreakdown, SearchResult},
+};
+
+/// Weights and method used to combine component scores.
+#[derive(Debug, Clone, Copy)]
+pub struct Fusion {
+   pub method:  FusionMethod,
+   pub dense:   f32,
+   pub fts:     f32,
+   pub colbert: f32,
+   pub rrf_k:   f32,
+}
+
+impl Fusion {
+   /// Reads the fusion settings from the global config.
+   pub fn from_config() -> Self {
+      let cfg = config::get();
+      Self {
+         method:  cfg.fusion,
+         dense:   cfg.fusion_dense_weight,
+         fts:     cfg.fusion_fts_weight,
+         colbert: cfg.fusion_colbert_weight,
+         rrf_k:   cfg.fusion_rrf_k,
+      }
+   }
+
+   /// Weighted mean of the components. A missing full-text score counts as
+   /// zero, while a missing `ColBERT` score drops out along with its weight so
+   /// that reranked and unreranked results stay comparable.
+   fn weighted(&self, b: &ScoreBreakdown) -> f32 {
+      let mut sum = self.dense * b.dense.unwrap_or(0.0) + self.fts * b.fts.unwrap_or(0.0);
+      let mut total = self.dense + self.fts;
+      if let Some(colbert) = b.colbert {
+         sum += self.colbert * colbert;
+         total += self.colbert;
+      }
+      if total > 0.0 { sum / total } else { 0.0 }
+   }
+}
+
+/// Combines the breakdown of every result into its score, overwriting
+/// `score`. Results without a breakdown keep their score.
+pub fn fuse(results: &mut [SearchResult], fusion: &Fusion) {
+   match fusion.method {
+      FusionMethod::Weighted => {
+         for result in results {
+            if let Some(breakdown) = &mut result.breakdown {
+               breakdown.fused = fusion.weighted(breakdown);
+               result.score = breakdown.fused;
+            }
+         }
+      },
+      FusionMethod::Rrf => {
+         let dense = ranks(results, |b| b.dense);
+         let fts = ranks(results, |b| b.fts);
+         let colbert = ranks(results, |b| b.colbert);
+         let rrf = |weight: f32, rank: Option<usize>| {
+            rank.map_or(0.0, |r| weight / (fusion.rrf_k + (r + 1) as f32))
+         };
+
+         for (i, result) in results.iter_mut().enumerate() {
+            if let Some(breakdown) = &mut result.breakdown {
+               breakdown.fused = rrf(fusion.dense, dense[i])
+                  + rrf(fusion.fts, fts[i])
+                  + rrf(fusion.colbert, colbert[i]);
+               result.score = breakdown.fused;
+            }
+         }
+      },
+   }
+}
+
+/// Ranks results by one component, highest first; results without that
+/// component get `None`.
+fn ranks(
+   results: &[SearchResult],
+   component: impl Fn(&ScoreBreakdown) -> Option<f32>,
+) -> Vec<Option<usize>> {
+   let mut scored: Vec<(usize, f32)> = results
+      .iter()
+      .enumerate()
+      .filter_map(|(i, r)| r.breakdown.as_ref().and_then(&component).map(|s| (i, s)))
+      .collect();
+   scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+   let mut ranks = vec![None; results.len()];
+   for (rank, (i, _)) in scored.into_iter().enumerate() {
+      ranks[i] = Some(rank);
+   }
+   ranks
+}
+
+#[cfg(test)]
+mod tests {
+   use std::path::PathBuf;
+
+   use super::*;
+
+   fn result(dense: f32, fts: Option<f32>, colbert: Option<f32>) -> SearchResult {
+      SearchResult {
+         path:         PathBuf::from("a.rs"),
+         content:      "".into(),
+         score:        0.0,
+         start_line:   1,
+         num_lines:    1,
+         chunk_type:   None,
+         is_anchor:    None,
+         breakdown:    Some(ScoreBreakdown {
+            dense: Some(dense),
+            fts,
+            colbert,
+            ..Default::default()
+         }),
+         bridges:      None,
+         language:     None,
+         context_prev: None,
+         context_next: None,
+         linked:       Vec::new(),
+      }
+   }
+
+   fn fusion(method: FusionMethod) -> Fusion {
+      Fusion { method, dense: 0.5, fts: 0.25, colbert: 0.25, rrf_k: 60.0 }
+   }
+
+   #[test]
+   fn weighted_drops_missing_colbert() {
+      let mut results = vec![result(0.8, Some(1.0), Some(0.4)), result(0.8, None, None)];
+      fuse(&mut results, &fusion(FusionMethod::Weighted));
+
+      assert!((results[0].score - 0.75).abs() < 1e-6);
+      // Without ColBERT the weights are 0.5 dense and 0.25 full-text.
+      assert!((results[1].score - 0.4 / 0.75).abs() < 1e-6);
+      assert_eq!(results[1].breakdown.unwrap().fused, results[1].score);
+   }
+
+   #[test]
+   fn rrf_rewards_agreement() {
+      let mut results = vec![
+         result(0.9, None, None),
+         result(0.5, Some(1.0), Some(0.9)),
+         result(0.1, Some(0.5), Some(0.1)),
+      ];
+      fuse(&mut results, &fusion(FusionMethod::Rrf));
+
+      assert!(results[1].score > results[0].score);
+      assert!(results[1].score > results[2].score);
+      assert!((results[0].score - 0.5 / 61.0).abs() < 1e-6);
+   }
+}
@@ -0,0 +1,334 @@
+//! Glob filters on result paths, from `--include` and `--exclude`.
+//!
+//! Globs are anchored at the searched directory unless they start with `**`
+//! or are absolute. Stores that filter rows in SQL narrow their candidates
+//! with [`PathGlobs::like_predicate`] before taking the vector limit, so that
+//! a narrow filter does not starve the result count; [`PathGlobs::matches`]
+//! then applies the exact globs to what comes back.
+
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::{
+   error::{Error, Result},
+   store,
+};
+
+/// Compiled include and exclude globs.
+#[derive(Debug, Clone, Default)]
+pub struct PathGlobs {
+   include:     Vec<Pattern>,
+   exclude:     Vec<Pattern>,
+   include_set: GlobSet,
+   exclude_set: GlobSet,
+}
+
+/// A glob and the directory it is anchored at, kept apart so that each can be
+/// escaped for its target syntax.
+#[derive(Debug, Clone)]
+struct Pattern {
+   anchor: Option<String>,
+   glob:   String,
+}
+
+impl Pattern {
+   fn new(root: &Path, glob: &str) -> Self {
+      let anchored = !glob.starts_with("**") && !Path::new(glob).is_absolute();
+      Self {
+         anchor: anchored.then(|| {
+            store::path_to_store_value(root)
+               .trim_end_matches('/')
+               .to_owned()
+         }),
+         glob:   glob.trim_start_matches("./").to_owned(),
+      }
+   }
+
+   fn to_glob(&self) -> String {
+      match &self.anchor {
+         Some(anchor) => format!("{}/{}", globset::escape(anchor), self.glob),
+         None => self.glob.clone(),
+      }
+   }
+
+   fn to_like(&self, mode: Like) -> Option<String> {
+      let glob = glob_to_like(&self.glob, mode)?;
+      Some(match &self.anchor {
+         Some(anchor) => format!("{}/{glob}", store::escape_path_for_like(Path::new(anchor))),
+         None => glob,
+      })
+   }
+}
+
+/// How a glob is approximated by a `LIKE` pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Like {
+   /// Match every path the glob matches, and possibly more.
+   Superset,
+   /// Match only paths the glob matches, or give up.
+   Subset,
+}
+
+impl PathGlobs {
+   /// Compiles `include` and `exclude` globs anchored at `root`.
+   pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<Self> {
+      let include: Vec<Pattern> = include.iter().map(|g| Pattern::new(root, g)).collect();
+      let exclude: Vec<Pattern> = exclude.iter().map(|g| Pattern::new(root, g)).collect();
+      Ok(Self {
+         include_set: build_set(&include)?,
+         exclude_set: build_set(&exclude)?,
+         include,
+         exclude,
+      })
+   }
+
+   pub const fn is_empty(&self) -> bool {
+      self.include.is_empty() && self.exclude.is_empty()
+   }
+
+   /// Include globs as given, before anchoring.
+   pub fn include_globs(&self) -> Vec<String> {
+      self.include.iter().map(|p| p.glob.clone()).collect()
+   }
+
+   /// Exclude globs as given, before anchoring.
+   pub fn exclude_globs(&self) -> Vec<String> {
+      self.exclude.iter().map(|p| p.glob.clone()).collect()
+   }
+
+   /// The same globs with their anchors written out, so that they keep their
+   /// meaning when given for another directory.
+   pub fn anchored(&self) -> Result<Self> {
+      let anchor = |patterns: &[Pattern]| -> Vec<Pattern> {
+         patterns
+            .iter()
+            .map(|p| Pattern { anchor: None, glob: p.to_glob() })
+            .collect()
+      };
+      let (include, exclude) = (anchor(&self.include), anchor(&self.exclude));
+      Ok(Self {
+         include_set: build_set(&include)?,
+         exclude_set: build_set(&exclude)?,
+         include,
+         exclude,
+      })
+   }
+
+   /// The same globs for paths relative to `root`, as a store that keeps its
+   /// paths relative to it holds them.
+   pub fn relative_to(&self, root: &Path) -> Result<Self> {
+      let root = store::path_to_store_value(root);
+      let root = root.trim_end_matches('/');
+      let relative = |path: &str| -> Option<String> {
+         let rest = path.strip_prefix(root)?;
+         (rest.is_empty() || rest.starts_with('/')).then(|| rest.trim_start_matches('/').to_owned())
+      };
+      let rebase = |patterns: &[Pattern]| -> Vec<Pattern> {
+         patterns
+            .iter()
+            .map(|p| match &p.anchor {
+               Some(anchor) => match relative(anchor) {
+                  Some(anchor) if anchor.is_empty() => {
+                     Pattern { anchor: None, glob: p.glob.clone() }
+                  },
+                  Some(anchor) => Pattern { anchor: Some(anchor), glob: p.glob.clone() },
+                  None => p.clone(),
+               },
+               None => Pattern {
+                  anchor: None,
+                  glob:   relative(&p.glob).unwrap_or_else(|| p.glob.clone()),
+               },
+            })
+            .collect()
+      };
+      let (include, exclude) = (rebase(&self.include), rebase(&self.exclude));
+      Ok(Self {
+         include_set: build_set(&include)?,
+         exclude_set: build_set(&exclude)?,
+         include,
+         exclude,
+      })
+   }
+
+   /// Whether `path` matches an include glob, if any are set, and no exclude
+   /// glob.
+   pub fn matches(&self, path: &Path) -> bool {
+      let path = store::path_to_store_value(path);
+      (self.include.is_empty() || self.include_set.is_match(&path))
+         && !self.exclude_set.is_match(&path)
+   }
+
+   /// SQL predicate on the `path` column that keeps every matching row. It
+   /// may keep some rows the globs reject, so results still go through
+   /// [`Self::matches`]. `None` when nothing can be expressed with `LIKE`.
+   pub fn like_predicate(&self) -> Option<String> {
+      let mut clauses = Vec::new();
+      let any: Option<Vec<String>> = self
+         .include
+         .iter()
+         .map(|p| {
+            p.to_like(Like::Superset)
+               .map(|like| format!("path LIKE '{like}'"))
+         })
+         .collect();
+      if let Some(any) = any.filter(|any| !any.is_empty()) {
+         clauses.push(format!("({})", any.join(" OR ")));
+      }
+      clauses.extend(
+         self
+            .exclude
+            .iter()
+            .filter_map(|p| p.to_like(Like::Subset))
+            .map(|like| format!("path NOT LIKE '{like}'")),
+      );
+      (!clauses.is_empty()).then(|| clauses.join(" AND "))
+   }
+}
+
+fn build_set(patterns: &[Pattern]) -> Result<GlobSet> {
+   let mut set = GlobSetBuilder::new();
+   for pattern in patterns {
+      let glob = GlobBuilder::new(&pattern.to_glob())
+         .literal_separator(true)
+         .build()
+         .map_err(|e| Error::InvalidGlob { glob: pattern.glob.clone(), reason: e.to_string() })?;
+      set.add(glob);
+   }
+   set.build().map_err(|e| Error::InvalidGlob {
+      glob:   patterns
+         .iter()
+         .map(|p| p.glob.as_str())
+         .collect::<Vec<_>>()
+         .join(", "),
+      reason: e.to_string(),
+   })
+}
+
+/// Translates a glob to a `LIKE` pattern escaped for a single-quoted SQL
+/// string. `LIKE` wildcards cross `/` where glob wildcards do not, so only
+/// `**` translates exactly; with [`Like::Subset`] anything else gives up.
+fn glob_to_like(glob: &str, mode: Like) -> Option<String> {
+   let mut like = String::with_capacity(glob.len() + 4);
+   let mut chars = glob.chars().peekable();
+
+   while let Some(c) = chars.next() {
+      match c {
+         '*' if chars.next_if_eq(&'*').is_some() => {
+            like.push('%');
+            // `a/**/b` also matches `a/b`, which `a/%/b` would not.
+            if chars.next_if_eq(&'/').is_some() && mode == Like::Subset {
+               like.push('/');
+            }
+         },
+         '*' | '{' if mode == Like::Superset => {
+            like.push('%');
+            if c == '{' {
+               chars.by_ref().find(|&c| c == '}');
+            }
+         },
+         '?' | '[' if mode == Like::Superset => {
+            like.push('_');
+            if c == '[' {
+               chars.by_ref().find(|&c| c == ']');
+            }
+         },
+         '*' | '{' | '?' | '[' => return None,
+         '\\' => push_literal(&mut like, chars.next()?),
+         c => push_literal(&mut like, c),
+      }
+   }
+   Some(like)
+}
+
+fn push_literal(like: &mut String, c: char) {
+   match c {
+      '%' | '_' | '\\' => {
+         like.push('\\');
+         like.push(c);
+      },
+      '\'' => like.push_str("''"),
+      c => like.push(c),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn globs(include: &[&str], exclude: &[&str]) -> PathGlobs {
+      let owned = |globs: &[&str]| globs.iter().map(ToString::to_string).collect::<Vec<_>>();
+      PathGlobs::new(Path::new("/repo"), &owned(include), &owned(exclude)).unwrap()
+   }
+
+   #[test]
+   fn matches_anchored_globs() {
+      let globs = globs(&["src/**/*.rs"], &["**/generated/**"]);
+
+      assert!(globs.matches(Path::new("/repo/src/lib.rs")));
+      assert!(globs.matches(Path::new("/repo/src/store/mod.rs")));
+      assert!(!globs.matches(Path::new("/repo/src/generated/api.rs")));
+      assert!(!globs.matches(Path::new("/repo/tests/lib.rs")));
+      assert!(!globs.matches(Path::new("/repo/src/lib.ts")));
+   }
+
+   #[test]
+   fn builds_like_predicate() {
+      let globs = globs(&["src/**/*.rs", "a_b?.[ch]"], &["**/generated/**", "*.md"]);
+
+      assert_eq!(
+         globs.like_predicate().as_deref(),
+         Some(
+            "(path LIKE '/repo/src/%%.rs' OR path LIKE '/repo/a\\_b_._') AND path NOT LIKE \
+             '%/generated/%'"
+         )
+      );
+      assert!(PathGlobs::default().like_predicate().is_none());
+   }
+
+   #[test]
+   fn keeps_anchors_for_another_directory() {
+      let anchored = globs(&["src/**/*.rs"], &["**/generated/**"])
+         .anchored()
+         .unwrap();
+      let resent = PathGlobs::new(
+         Path::new("/repo/vendor/lib"),
+         &anchored.include_globs(),
+         &anchored.exclude_globs(),
+      )
+      .unwrap();
+
+      assert!(resent.matches(Path::new("/repo/src/lib.rs")));
+      assert!(!resent.matches(Path::new("/repo/vendor/lib/src/lib.rs")));
+      assert!(!resent.matches(Path::new("/repo/src/generated/api.rs")));
+   }
+
+   #[test]
+   fn rebases_globs_onto_root() {
+      let relative = globs(&["src/**/*.rs", "/repo/lib/*.rs"], &["**/generated/**"])
+         .relative_to(Path::new("/repo"))
+         .unwrap();
+
+      assert!(relative.matches(Path::new("src/store/mod.rs")));
+      assert!(relative.matches(Path::new("lib/a.rs")));
+      assert!(!relative.matches(Path::new("generated/api.rs")));
+      assert!(!relative.matches(Path::new("/repo/src/lib.rs")));
+      assert_eq!(
+         relative.like_predicate().as_deref(),
+         Some("(path LIKE 'src/%%.rs' OR path LIKE 'lib/%.rs') AND path NOT LIKE '%/generated/%'")
+      );
+
+      let nested = PathGlobs::new(Path::new("/repo/src"), &["*.rs".to_string()], &[])
+         .unwrap()
+         .relative_to(Path::new("/repo"))
+         .unwrap();
+      assert!(nested.matches(Path::new("src/lib.rs")));
+      assert!(!nested.matches(Path::new("lib.rs")));
+   }
+
+   #[test]
+   fn rejects_invalid_globs() {
+      let err = PathGlobs::new(Path::new("/repo"), &["src/[".to_string()], &[]).unwrap_err();
+      assert!(matches!(err, Error::InvalidGlob { .. }), "{err}");
+   }
+}
@@ -0,0 +1,93 @@
+//! Read-through hydration of search results from the working tree.
+//!
+//! Stored chunk content reflects the file as it was when indexed. Hydration
+//! re-reads the same line range from disk so callers can show current code and
+//! flag results whose source has drifted since.
+
+use serde::{Deserialize, Serialize};
+
+/// How a stored chunk compares to the file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Drift {
+   /// The lines on disk match the indexed content.
+   Fresh,
+   /// The lines on disk differ from the indexed content.
+   Changed,
+   /// The file no longer exists or could not be read.
+   Missing,
+}
+
+impl Drift {
+   pub const fn is_drifted(self) -> bool {
+      !matches!(self, Self::Fresh)
+   }
+}
+
+/// Current content for a stored chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hydrated {
+   pub drift:   Drift,
+   /// Lines read from disk covering the same range as the stored content;
+   /// `None` when the file is missing.
+   pub content: Option<String>,
+}
+
+/// Compares `stored` (starting at zero-based `start_line`) against the current
+/// contents of its file.
+///
+/// Line endings and trailing whitespace are ignored so re-saving a file in an
+/// editor does not count as drift.
+pub fn hydrate(file: Option<&str>, start_line: usize, stored: &str) -> Hydrated {
+   let Some(file) = file else {
+      return Hydrated { drift: Drift::Missing, content: None };
+   };
+
+   let stored_lines: Vec<&str> = stored.lines().collect();
+   let current_lines: Vec<&str> = file
+      .lines()
+      .skip(start_line)
+      .take(stored_lines.len().max(1))
+      .collect();
+
+   let same = stored_lines.len() == current_lines.len()
+      && stored_lines
+         .iter()
+         .zip(&current_lines)
+         .all(|(a, b)| a.trim_end() == b.trim_end());
+
+   Hydrated {
+      drift:   if same { Drift::Fresh } else { Drift::Changed },
+      content: Some(current_lines.join("\n")),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const FILE: &str = "fn a() {}\r\nfn b() {\n   1\n}\nfn c() {}\n";
+
+   #[test]
+   fn unchanged_range_is_fresh() {
+      let h = hydrate(Some(FILE), 1, "fn b() {\n   1  \n}");
+      assert_eq!(h.drift, Drift::Fresh);
+      assert_eq!(h.content.as_deref(), Some("fn b() {\n   1\n}"));
+   }
+
+   #[test]
+   fn edited_range_is_changed() {
+      let h = hydrate(Some(FILE), 1, "fn b() {\n   2\n}");
+      assert_eq!(h.drift, Drift::Changed);
+      assert_eq!(h.content.as_deref(), Some("fn b() {\n   1\n}"));
+
+      let truncated = hydrate(Some("fn a() {}\n"), 1, "fn b() {}");
+      assert_eq!(truncated.drift, Drift::Changed);
+   }
+
+   #[test]
+   fn missing_file_is_reported() {
+      let h = hydrate(None, 0, "fn a() {}");
+      assert_eq!(h, Hydrated { drift: Drift::Missing, content: None });
+   }
+}
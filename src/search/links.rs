@@ -0,0 +1,68 @@
+//! Linking results to code on the other side of an FFI boundary.
+//!
+//! Results that carry bridge keys (see [`crate::chunker::bridge`]) are
+//! followed to chunks sharing a key in another language, which are attached
+//! as [`LinkedResult`]s instead of competing for a slot in the ranking.
+
+use crate::{
+   Str,
+   chunker::bridge,
+   store::Store,
+   types::{LinkedResult, SearchResult},
+};
+
+/// Leading results whose bridge keys are followed.
+const LINK_DEPTH: usize = 5;
+
+/// Most linked results attached to one result.
+const MAX_LINKS: usize = 3;
+
+/// Attaches linked results to the top results. Lookup failures are logged
+/// and leave the results unlinked.
+pub async fn link_bridges(store: &dyn Store, store_id: &str, results: &mut [SearchResult]) {
+   for result in results.iter_mut().take(LINK_DEPTH) {
+      let (Some(side), Some(bridges)) = (bridge::side(&result.path), result.bridges.clone()) else {
+         continue;
+      };
+
+      for key in bridge::keys(&bridges) {
+         if result.linked.len() >= MAX_LINKS {
+            break;
+         }
+
+         let found = match store.find_bridged(store_id, key, MAX_LINKS * 2).await {
+            Ok(found) => found,
+            Err(e) => {
+               tracing::debug!("skipping bridge links: {e}");
+               return;
+            },
+         };
+
+         for other in found {
+            let crosses = bridge::side(&other.path).is_some_and(|s| s != side);
+            let shares_key = other
+               .bridges
+               .as_deref()
+               .is_some_and(|b| bridge::keys(b).any(|k| k == key));
+            let duplicate = result
+               .linked
+               .iter()
+               .any(|l| l.path == other.path && l.start_line == other.start_line);
+            if !crosses || !shares_key || duplicate {
+               continue;
+            }
+
+            result.linked.push(LinkedResult {
+               symbol:     Str::copy_from_str(key),
+               path:       other.path,
+               content:    other.content,
+               start_line: other.start_line,
+               num_lines:  other.num_lines,
+            });
+            if result.linked.len() >= MAX_LINKS {
+               break;
+            }
+         }
+      }
+   }
+}
@@ -2,68 +2,541 @@
 //! result ranking.
 
 pub mod colbert;
+pub mod fusion;
+pub mod glob;
+pub mod hydrate;
+pub mod links;
+pub mod query;
 pub mod ranking;
+pub mod route;
+pub mod testfile;
 
-use std::{cmp::Ordering, path::Path, sync::Arc};
+use std::{cmp::Ordering, path::Path, sync::Arc, time::Instant};
 
+use futures::stream::{self, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use self::{glob::PathGlobs, query::Query, testfile::TestFilter};
 use crate::{
-   embed::Embedder,
-   error::Result,
-   store::{SearchParams, Store},
-   types::SearchResponse,
+   config,
+   embed::{Embedder, QueryEmbedding, candle::EmbeddingError},
+   error::{Error, Result},
+   reranker::CrossEncoder,
+   store::{FileSearchParams, KeywordParams, SearchParams, Store},
+   types::{QueryRoute, SearchResponse, SearchResult, SearchStage, StageTiming},
+   util::{cancellable, check_cancelled},
 };
 
+/// Extra candidates fetched when query operators, globs or the test filter
+/// may filter some out.
+pub const FILTER_OVERFETCH: usize = 4;
+/// Searches of a batch run at once.
+pub const BATCH_CONCURRENCY: usize = 8;
+
+/// Restricts which indexed chunks a search may return.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchFilter<'a> {
+   /// Only return chunks from files under this path.
+   pub path:          Option<&'a Path>,
+   /// Only return chunks from files accepted by these globs.
+   pub globs:         Option<&'a PathGlobs>,
+   /// Only return chunks from files indexed at or after this Unix timestamp
+   /// (seconds).
+   pub indexed_after: Option<u64>,
+   /// Only return chunks from this Cargo workspace member.
+   pub crate_name:    Option<&'a str>,
+   /// Keep only test code, or drop it.
+   pub tests:         TestFilter,
+   /// Directory `tests` judges result paths relative to, so that a directory
+   /// above it does not make every result a test.
+   pub tests_root:    Option<&'a Path>,
+}
+
+impl SearchFilter<'_> {
+   /// Whether results of the store query may be dropped afterwards, by the
+   /// operators of `query`, the globs or the test filter.
+   pub fn drops_results(self, query: &Query) -> bool {
+      query.has_filters() || self.globs.is_some_and(|g| !g.is_empty()) || !self.tests.is_all()
+   }
+}
+
+/// Controls how a search retrieves and scores candidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Retrieval {
+   /// Rescore the top candidates with `ColBERT`.
+   pub rerank:  bool,
+   /// Scan every vector instead of using the approximate index.
+   pub exact:   bool,
+   /// Skip embeddings and query only the full-text index.
+   pub keyword: bool,
+
+   /// Candidates to rescore with `ColBERT`; `None` uses `rerank_depth`.
+   pub rerank_depth: Option<usize>,
+   /// Rescore the top results with the cross-encoder after `ColBERT`.
+   pub cross:        bool,
+}
+
+impl Retrieval {
+   /// Results the cross-encoder rescores; 0 when it is off.
+   pub fn cross_depth(self) -> usize {
+      if self.cross {
+         config::get().cross_encoder_depth
+      } else {
+         0
+      }
+   }
+}
+
+/// The parameters of [`SearchEngine::search`] shared by every query of a
+/// batch.
+#[derive(Clone, Copy)]
+struct SearchRequest<'a> {
+   store_id:       &'a str,
+   limit:          usize,
+   per_file_limit: usize,
+   filter:         SearchFilter<'a>,
+   retrieval:      Retrieval,
+}
+
 /// High-level search engine orchestrating embeddings, vector search, and
 /// reranking.
 pub struct SearchEngine {
-   store:    Arc<dyn Store>,
-   embedder: Arc<dyn Embedder>,
+   store:         Arc<dyn Store>,
+   embedder:      Arc<dyn Embedder>,
+   cross_encoder: CrossEncoder,
 }
 
 impl SearchEngine {
    pub fn new(store: Arc<dyn Store>, embedder: Arc<dyn Embedder>) -> Self {
-      Self { store, embedder }
+      Self { store, embedder, cross_encoder: CrossEncoder::new() }
+   }
+
+   /// Ranks the files under `path` by how well their anchor chunks (see
+   /// [`crate::chunker::anchor`]) match a natural language query, returning
+   /// the anchors of the best `limit`.
+   pub async fn search_files(
+      &self,
+      store_id: &str,
+      query: &str,
+      limit: usize,
+      path: Option<&Path>,
+      cancel: &CancellationToken,
+   ) -> Result<Vec<SearchResult>> {
+      let query_enc = cancellable(cancel, self.embedder.encode_query(query)).await?;
+      let search = self.store.search_files(FileSearchParams {
+         store_id,
+         query_vector: &query_enc.dense,
+         limit,
+         path_filter: path,
+      });
+      cancellable(cancel, search).await
    }
 
    /// Searches a store for code matching a natural language query.
    ///
    /// Performs vector search, applies structural boosting, and optionally
-   /// reranks with `ColBERT`. Results are limited both globally and per-file.
+   /// reranks with `ColBERT`, then with the cross-encoder when
+   /// [`Retrieval::cross`] is set. Results are limited both globally and
+   /// per-file, and the top ones are linked across FFI boundaries. Operators
+   /// in the query (see [`query`]) are stripped before embedding and applied
+   /// to the candidates. Queries that look like a path or a symbol are
+   /// routed (see [`route`]) and the route is reported in the response. With
+   /// [`Retrieval::keyword`] the query is never embedded and never routed;
+   /// see [`keyword_search`]. Fails with [`Error::Cancelled`] once `cancel`
+   /// fires, abandoning the embedding or store query in flight.
+   #[allow(clippy::too_many_arguments, reason = "mirrors the search request fields")]
+   #[tracing::instrument(skip_all, fields(query = %query))]
    pub async fn search(
       &self,
       store_id: &str,
       query: &str,
       limit: usize,
       per_file_limit: usize,
-      path_filter: Option<&Path>,
-      rerank: bool,
+      filter: SearchFilter<'_>,
+      retrieval: Retrieval,
+      cancel: &CancellationToken,
+   ) -> Result<SearchResponse> {
+      if retrieval.keyword {
+         return cancellable(
+            cancel,
+            keyword_search(&*self.store, store_id, query, limit, per_file_limit, filter),
+         )
+         .await;
+      }
+
+      let query = parse_query(query)?;
+      let embed_started = Instant::now();
+      let query_enc = cancellable(cancel, self.embedder.encode_query(&query.text)).await?;
+      let embedding = StageTiming::since(SearchStage::Embedding, embed_started);
+      let request = SearchRequest { store_id, limit, per_file_limit, filter, retrieval };
+      self
+         .search_embedded(&request, &query, &query_enc, embedding, cancel)
+         .await
+   }
+
+   /// Searches for each of `queries` as [`Self::search`] does, embedding them
+   /// all in one batch and running up to [`BATCH_CONCURRENCY`] searches at
+   /// once.
+   ///
+   /// Responses are in query order. A query that fails, such as one made only
+   /// of operators, fails alone; the batch fails only when embedding does or
+   /// `cancel` fires first. Every response reports the whole batch's
+   /// embedding time.
+   #[allow(clippy::too_many_arguments, reason = "mirrors the search request fields")]
+   #[tracing::instrument(skip_all, fields(queries = queries.len()))]
+   pub async fn search_batch(
+      &self,
+      store_id: &str,
+      queries: &[String],
+      limit: usize,
+      per_file_limit: usize,
+      filter: SearchFilter<'_>,
+      retrieval: Retrieval,
+      cancel: &CancellationToken,
+   ) -> Result<Vec<Result<SearchResponse>>> {
+      if retrieval.keyword {
+         return Ok(stream::iter(queries)
+            .map(|query| {
+               self.search(store_id, query, limit, per_file_limit, filter, retrieval, cancel)
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await);
+      }
+
+      let parsed: Vec<Result<Query>> = queries.iter().map(|query| parse_query(query)).collect();
+      let texts: Vec<&str> = parsed
+         .iter()
+         .flatten()
+         .map(|query| query.text.as_str())
+         .collect();
+      let embed_started = Instant::now();
+      let embeddings = cancellable(cancel, self.embedder.encode_queries(&texts)).await?;
+      let embedding = StageTiming::since(SearchStage::Embedding, embed_started);
+      if embeddings.len() != texts.len() {
+         return Err(
+            EmbeddingError::CountMismatch { expected: texts.len(), got: embeddings.len() }.into(),
+         );
+      }
+
+      let mut embeddings = embeddings.into_iter();
+      let jobs: Vec<Result<(Query, QueryEmbedding)>> = parsed
+         .into_iter()
+         .filter_map(|query| match query {
+            Ok(query) => embeddings.next().map(|query_enc| Ok((query, query_enc))),
+            Err(e) => Some(Err(e)),
+         })
+         .collect();
+      let request = &SearchRequest { store_id, limit, per_file_limit, filter, retrieval };
+      Ok(stream::iter(jobs)
+         .map(|job| async move {
+            let (query, query_enc) = job?;
+            self
+               .search_embedded(request, &query, &query_enc, embedding, cancel)
+               .await
+         })
+         .buffered(BATCH_CONCURRENCY)
+         .collect()
+         .await)
+   }
+
+   /// Runs [`Self::search`] for a query that has already been embedded.
+   async fn search_embedded(
+      &self,
+      request: &SearchRequest<'_>,
+      query: &Query,
+      query_enc: &QueryEmbedding,
+      embedding: StageTiming,
+      cancel: &CancellationToken,
    ) -> Result<SearchResponse> {
-      let query_enc = self.embedder.encode_query(query).await?;
-      let mut response = self
-         .store
-         .search(SearchParams {
+      let SearchRequest { store_id, limit, per_file_limit, filter, retrieval } = *request;
+      // The cross-encoder picks the final results from among its depth.
+      let kept = limit.max(retrieval.cross_depth());
+      let retrieve = async |filter: SearchFilter<'_>| -> Result<SearchResponse> {
+         let fetch = async |limit: usize| -> Result<SearchResponse> {
+            let search = self.store.search(SearchParams {
+               store_id,
+               query_text: &query.text,
+               query_vector: &query_enc.dense,
+               query_colbert: &query_enc.colbert,
+               limit,
+               path_filter: filter.path,
+               path_globs: filter.globs,
+               indexed_after: filter.indexed_after,
+               crate_name: filter.crate_name,
+               rerank: retrieval.rerank,
+               rerank_depth: retrieval.rerank_depth,
+               nprobes: None,
+               exact: retrieval.exact,
+               cancel: Some(cancel),
+            });
+            let search =
+               search.instrument(tracing::info_span!("store_search", rerank = retrieval.rerank));
+            let response = cancellable(cancel, search).await?;
+            // Reranking stops early rather than failing, so a search cancelled
+            // while ranking returns here with partial scores.
+            check_cancelled(Some(cancel))?;
+            Ok(response)
+         };
+         fetch_matching(query, filter, fetch_limit(query, filter, kept), kept, fetch).await
+      };
+
+      // Path queries are restricted to the files they name, unless the
+      // caller already restricted paths with globs of its own.
+      let retrieval_started = Instant::now();
+      let mut route = route::classify(&query.text);
+      let path_globs = match route {
+         QueryRoute::Path if filter.globs.is_none() => Some(route::path_globs(&query.text)?),
+         QueryRoute::Path => {
+            route = QueryRoute::Semantic;
+            None
+         },
+         _ => None,
+      };
+      let mut response = match &path_globs {
+         Some(globs) => retrieve(SearchFilter { globs: Some(globs), ..filter }).await?,
+         None => retrieve(filter).await?,
+      };
+      if route == QueryRoute::Path && response.results.is_empty() {
+         route = QueryRoute::Semantic;
+         response = retrieve(filter).await?;
+      }
+      record_stages(
+         &mut response,
+         embedding,
+         StageTiming::since(SearchStage::Retrieval, retrieval_started),
+      );
+      if route == QueryRoute::Symbol {
+         route::apply_symbol_boost(&mut response.results, route::symbol_name(&query.text));
+      }
+      response.route = route;
+
+      finish(&mut response, query, filter, kept, per_file_limit);
+      if retrieval.cross {
+         let started = Instant::now();
+         let rerank =
+            self
+               .cross_encoder
+               .rerank(&query.text, &mut response.results, retrieval.cross_depth());
+         cancellable(cancel, rerank).await?;
+         response
+            .stages
+            .push(StageTiming::since(SearchStage::Cross, started));
+         response.results.truncate(limit);
+      }
+      links::link_bridges(&*self.store, store_id, &mut response.results).await;
+      Ok(response)
+   }
+}
+
+/// Searches a store's full-text index without embedding the query.
+///
+/// Needs no model, so it works before one has been downloaded. Results go
+/// through the same boosting and limits as [`SearchEngine::search`].
+pub async fn keyword_search(
+   store: &dyn Store,
+   store_id: &str,
+   query: &str,
+   limit: usize,
+   per_file_limit: usize,
+   filter: SearchFilter<'_>,
+) -> Result<SearchResponse> {
+   let query = parse_query(query)?;
+   let started = Instant::now();
+   let fetch = async |limit: usize| {
+      store
+         .keyword_search(KeywordParams {
             store_id,
-            query_text: query,
-            query_vector: &query_enc.dense,
-            query_colbert: &query_enc.colbert,
-            limit: limit * 2,
-            path_filter,
-            rerank,
+            query_text: &query.text,
+            limit,
+            path_filter: filter.path,
+            path_globs: filter.globs,
+            indexed_after: filter.indexed_after,
+            crate_name: filter.crate_name,
          })
-         .await?;
+         .await
+   };
+   let mut response =
+      fetch_matching(&query, filter, fetch_limit(&query, filter, limit), limit, fetch).await?;
+   response
+      .stages
+      .push(StageTiming::since(SearchStage::Retrieval, started));
+
+   finish(&mut response, &query, filter, limit, per_file_limit);
+   links::link_bridges(store, store_id, &mut response.results).await;
+   Ok(response)
+}
+
+/// Puts the embedding and retrieval stages ahead of those the store
+/// reported. Retrieval covers everything the store did, so its reranking is
+/// taken out.
+pub fn record_stages(
+   response: &mut SearchResponse,
+   embedding: StageTiming,
+   retrieval: StageTiming,
+) {
+   let reranking: u64 = response
+      .stages
+      .iter()
+      .filter(|s| s.stage == SearchStage::Rerank)
+      .map(|s| s.ms)
+      .sum();
+   let retrieval = StageTiming { ms: retrieval.ms.saturating_sub(reranking), ..retrieval };
+   response.stages.splice(0..0, [embedding, retrieval]);
+}
+
+/// Parses query operators, rejecting queries with nothing left to search for.
+pub fn parse_query(query: &str) -> Result<Query> {
+   let query = Query::parse(query);
+   if query.text.is_empty() {
+      return Err(Error::EmptyQuery);
+   }
+   Ok(query)
+}
+
+/// Candidates to request from the store for `limit` results.
+fn fetch_limit(query: &Query, filter: SearchFilter<'_>, limit: usize) -> usize {
+   if filter.drops_results(query) {
+      limit * 2 * FILTER_OVERFETCH
+   } else {
+      limit * 2
+   }
+}
 
-      ranking::apply_structural_boost(&mut response.results);
+/// Asks `fetch` for `limit` candidates and drops those [`retain_matching`]
+/// rejects, asking again for twice as many while fewer than `wanted` are
+/// left and the store returned all it was asked for.
+pub async fn fetch_matching(
+   query: &Query,
+   filter: SearchFilter<'_>,
+   mut limit: usize,
+   wanted: usize,
+   fetch: impl AsyncFn(usize) -> Result<SearchResponse>,
+) -> Result<SearchResponse> {
+   loop {
+      let mut response = fetch(limit).await?;
+      let exhausted = response.results.len() < limit;
+      retain_matching(&mut response.results, query, filter);
+      if response.results.len() >= wanted || exhausted {
+         return Ok(response);
+      }
+      limit = limit.saturating_mul(2);
+   }
+}
+
+/// Drops results rejected by the query's operators, the path globs or the
+/// test filter.
+pub fn retain_matching(results: &mut Vec<SearchResult>, query: &Query, filter: SearchFilter<'_>) {
+   if query.has_filters() {
+      results.retain(|r| query.matches(&r.path, &r.content));
+   }
+   if let Some(globs) = filter.globs.filter(|g| !g.is_empty()) {
+      results.retain(|r| globs.matches(&r.path));
+   }
+   if !filter.tests.is_all() {
+      results.retain(|r| {
+         let path = filter
+            .tests_root
+            .and_then(|root| r.path.strip_prefix(root).ok())
+            .unwrap_or(&r.path);
+         filter.tests.accepts(path, &r.content)
+      });
+   }
+}
+
+/// Drops results rejected by the query's operators or `filter`, applies
+/// structural boosting, then the per-file and global result limits.
+fn finish(
+   response: &mut SearchResponse,
+   query: &Query,
+   filter: SearchFilter<'_>,
+   limit: usize,
+   per_file_limit: usize,
+) {
+   retain_matching(&mut response.results, query, filter);
+   ranking::apply_structural_boost(&mut response.results, ranking::BoostRules::from_config());
+
+   response
+      .results
+      .sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
 
-      response
-         .results
-         .sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+   if per_file_limit > 0 {
+      response.results =
+         ranking::apply_per_file_limit(std::mem::take(&mut response.results), per_file_limit);
+   }
+
+   response.results.truncate(limit);
+}
+
+#[cfg(test)]
+mod tests {
+   use std::{cell::RefCell, path::PathBuf};
+
+   use super::*;
+   use crate::{
+      Str,
+      types::{SearchStatus, VectorSearchMode},
+   };
 
-      if per_file_limit > 0 {
-         response.results = ranking::apply_per_file_limit(response.results, per_file_limit);
+   fn result(path: &str) -> SearchResult {
+      SearchResult {
+         path:         PathBuf::from(path),
+         content:      Str::default(),
+         score:        1.0,
+         start_line:   1,
+         num_lines:    10,
+         chunk_type:   None,
+         is_anchor:    Some(false),
+         breakdown:    None,
+         bridges:      None,
+         language:     None,
+         context_prev: None,
+         context_next: None,
+         linked:       Vec::new(),
       }
+   }
 
-      response.results.truncate(limit);
+   #[tokio::test]
+   async fn fetches_more_until_enough_pass_the_filter() {
+      let ranked: Vec<SearchResult> = (0..8)
+         .map(|i| result(&format!("/repo/src/{i}.rs")))
+         .chain([result("/repo/tests/a.rs"), result("/repo/tests/b.rs")])
+         .collect();
+      let limits = RefCell::new(Vec::new());
+      let fetch = async |limit: usize| -> Result<SearchResponse> {
+         limits.borrow_mut().push(limit);
+         Ok(SearchResponse {
+            results:  ranked.iter().take(limit).cloned().collect(),
+            status:   SearchStatus::Ready,
+            progress: None,
+            mode:     VectorSearchMode::Exact,
+            route:    QueryRoute::Semantic,
+            stages:   Vec::new(),
+         })
+      };
+      let filter = SearchFilter {
+         tests: TestFilter::Only,
+         tests_root: Some(Path::new("/repo")),
+         ..SearchFilter::default()
+      };
+      let query = parse_query("retry").unwrap();
 
-      Ok(response)
+      let response = fetch_matching(&query, filter, 4, 2, fetch).await.unwrap();
+      assert_eq!(response.results.len(), 2);
+      assert_eq!(*limits.borrow(), [4, 8, 16]);
+   }
+
+   #[test]
+   fn judges_tests_below_the_root() {
+      let filter = SearchFilter {
+         tests: TestFilter::Exclude,
+         tests_root: Some(Path::new("/work/test")),
+         ..SearchFilter::default()
+      };
+      let mut results = vec![result("/work/test/src/lib.rs"), result("/work/test/tests/it.rs")];
+      retain_matching(&mut results, &parse_query("retry").unwrap(), filter);
+      assert_eq!(results.len(), 1);
+      assert_eq!(results[0].path, Path::new("/work/test/src/lib.rs"));
    }
 }
@@ -0,0 +1,193 @@
+//! Operators in query strings, in the style of GitHub code search.
+//!
+//! A query may mix free text with `"exact phrases"`, `-term` or `NOT term`
+//! exclusions, and `path:fragment` / `-path:fragment` filters. Operators are
+//! stripped from the text that is embedded and matched by the full-text index,
+//! then enforced on the candidates a store returns.
+
+use std::path::Path;
+
+/// A query split into its free text and operators.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+   /// Free text and phrase contents, for the embedder and full-text index.
+   pub text:           String,
+   /// Phrases a result's content must contain.
+   pub phrases:        Vec<String>,
+   /// Terms or phrases a result's content must not contain.
+   pub excluded:       Vec<String>,
+   /// Fragments a result's path must contain, all of them.
+   pub paths:          Vec<String>,
+   /// Fragments a result's path must not contain.
+   pub excluded_paths: Vec<String>,
+}
+
+impl Query {
+   /// Parses a query string. Unbalanced quotes run to the end of the query.
+   pub fn parse(input: &str) -> Self {
+      let mut query = Self::default();
+      let mut words = Vec::new();
+      let mut negate_next = false;
+
+      for token in tokenize(input) {
+         let negated = std::mem::take(&mut negate_next) || token.negated;
+         match token.kind {
+            TokenKind::Word(word) if !token.negated && !token.quoted && word == "NOT" => {
+               negate_next = true;
+            },
+            TokenKind::Path(fragment) if !fragment.is_empty() => {
+               if negated {
+                  query.excluded_paths.push(fragment);
+               } else {
+                  query.paths.push(fragment);
+               }
+            },
+            TokenKind::Path(_) => {},
+            TokenKind::Word(word) if negated => query.excluded.push(word),
+            TokenKind::Word(word) => {
+               if token.quoted {
+                  query.phrases.push(word.clone());
+               }
+               words.push(word);
+            },
+         }
+      }
+
+      query.text = words.join(" ");
+      query
+   }
+
+   /// Whether the query has operators to enforce on results.
+   pub const fn has_filters(&self) -> bool {
+      !self.phrases.is_empty()
+         || !self.excluded.is_empty()
+         || !self.paths.is_empty()
+         || !self.excluded_paths.is_empty()
+   }
+
+   /// Checks a result's path and content against the operators. Phrases and
+   /// excluded terms match case-insensitively on word boundaries; path
+   /// fragments match case-insensitively anywhere in the path.
+   pub fn matches(&self, path: &Path, content: &str) -> bool {
+      let path = path.to_string_lossy();
+      self.paths.iter().all(|p| contains_ci(&path, p))
+         && !self.excluded_paths.iter().any(|p| contains_ci(&path, p))
+         && self.phrases.iter().all(|p| contains_word_ci(content, p))
+         && !self.excluded.iter().any(|t| contains_word_ci(content, t))
+   }
+}
+
+#[derive(Debug)]
+enum TokenKind {
+   Word(String),
+   Path(String),
+}
+
+#[derive(Debug)]
+struct Token {
+   kind:    TokenKind,
+   negated: bool,
+   quoted:  bool,
+}
+
+/// Splits a query on whitespace, keeping quoted runs together.
+fn tokenize(input: &str) -> Vec<Token> {
+   let mut tokens = Vec::new();
+   let mut chars = input.chars().peekable();
+
+   loop {
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+      let Some(&first) = chars.peek() else {
+         break;
+      };
+
+      let negated = first == '-';
+      if negated {
+         chars.next();
+      }
+
+      let mut raw = String::new();
+      let mut quoted = false;
+      let mut in_quotes = false;
+      while let Some(&c) = chars.peek() {
+         if c == '"' {
+            quoted = true;
+            in_quotes = !in_quotes;
+         } else if c.is_whitespace() && !in_quotes {
+            break;
+         } else {
+            raw.push(c);
+         }
+         chars.next();
+      }
+
+      if raw.is_empty() {
+         // A lone `-` or `""` carries nothing to search for.
+         continue;
+      }
+
+      let kind = match raw.strip_prefix("path:") {
+         Some(fragment) => TokenKind::Path(fragment.to_string()),
+         None => TokenKind::Word(raw),
+      };
+      tokens.push(Token { kind, negated, quoted });
+   }
+
+   tokens
+}
+
+fn contains_ci(haystack: &str, needle: &str) -> bool {
+   haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Case-insensitive search for `needle` not directly preceded or followed by
+/// an identifier character.
+pub(super) fn contains_word_ci(haystack: &str, needle: &str) -> bool {
+   let haystack = haystack.to_lowercase();
+   let needle = needle.to_lowercase();
+   let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+   haystack.match_indices(&needle).any(|(start, _)| {
+      let before = haystack[..start].chars().next_back();
+      let after = haystack[start + needle.len()..].chars().next();
+      !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parses_operators() {
+      let query =
+         Query::parse(r#"retry "backoff delay" -mock NOT sleep path:src/net -path:"vendor dir""#);
+
+      assert_eq!(query.text, "retry backoff delay");
+      assert_eq!(query.phrases, ["backoff delay"]);
+      assert_eq!(query.excluded, ["mock", "sleep"]);
+      assert_eq!(query.paths, ["src/net"]);
+      assert_eq!(query.excluded_paths, ["vendor dir"]);
+      assert!(query.has_filters());
+   }
+
+   #[test]
+   fn plain_queries_pass_through() {
+      let query = Query::parse("  where is the config loaded  ");
+      assert_eq!(query.text, "where is the config loaded");
+      assert!(!query.has_filters());
+
+      assert_eq!(Query::parse("a - b \"\"").text, "a b");
+   }
+
+   #[test]
+   fn matches_on_word_boundaries() {
+      let query = Query::parse(r#""Retry Loop" -test path:SRC/"#);
+      let path = Path::new("/repo/src/net.rs");
+
+      assert!(query.matches(path, "fn run() { retry loop; latest() }"));
+      assert!(!query.matches(path, "fn run() { retry loop; test() }"));
+      assert!(!query.matches(path, "fn run() { retry loops }"));
+      assert!(!query.matches(Path::new("/repo/lib/net.rs"), "retry loop"));
+   }
+}
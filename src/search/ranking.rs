@@ -1,40 +1,142 @@
 //! Result ranking utilities for boosting code structure and limiting per-file
 //! results.
 
-use std::path::Path;
+use std::{
+   collections::{BTreeMap, HashMap},
+   hash::BuildHasher,
+   path::{Path, PathBuf},
+   sync::OnceLock,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::{
+   config::{self, Config, PathBoost},
+   types::{ChunkType, SearchResult},
+};
+
+/// Compiled `structure_boosts` and `path_boosts` settings.
+#[derive(Debug, Clone)]
+pub struct BoostRules {
+   structure: Vec<(ChunkType, f32)>,
+   paths:     Vec<(GlobSet, f32)>,
+}
+
+impl BoostRules {
+   /// Compiles rules, skipping unknown chunk types and invalid globs with a
+   /// warning.
+   pub fn new(structure: &BTreeMap<String, f32>, paths: &[PathBoost]) -> Self {
+      let structure = structure
+         .iter()
+         .filter_map(|(name, &multiplier)| {
+            let chunk_type = ChunkType::from_lowercase_str(name);
+            if chunk_type.as_lowercase_str() != name {
+               tracing::warn!("ignoring structure boost for unknown chunk type `{name}`");
+               return None;
+            }
+            Some((chunk_type, multiplier))
+         })
+         .collect();
+
+      let paths = paths
+         .iter()
+         .filter_map(|rule| {
+            let mut set = GlobSetBuilder::new();
+            for glob in &rule.globs {
+               match GlobBuilder::new(glob)
+                  .case_insensitive(true)
+                  .literal_separator(true)
+                  .build()
+               {
+                  Ok(glob) => {
+                     set.add(glob);
+                  },
+                  Err(e) => tracing::warn!("ignoring path boost glob `{glob}`: {e}"),
+               }
+            }
+            set.build()
+               .inspect_err(|e| tracing::warn!("ignoring path boost rule: {e}"))
+               .ok()
+               .map(|set| (set, rule.multiplier))
+         })
+         .collect();
 
-use crate::types::{ChunkType, SearchResult};
+      Self { structure, paths }
+   }
+
+   /// Rules from the loaded configuration, compiled once.
+   pub fn from_config() -> &'static Self {
+      static RULES: OnceLock<BoostRules> = OnceLock::new();
+      RULES.get_or_init(|| {
+         let cfg = config::get();
+         Self::new(&cfg.structure_boosts, &cfg.path_boosts)
+      })
+   }
+
+   fn structure(&self, chunk_type: Option<ChunkType>) -> Option<f32> {
+      let chunk_type = chunk_type?;
+      self
+         .structure
+         .iter()
+         .find(|(ty, _)| *ty == chunk_type)
+         .map(|&(_, multiplier)| multiplier)
+   }
 
-fn contains_ci(haystack: &str, needle: &str) -> bool {
-   haystack
-      .as_bytes()
-      .windows(needle.len())
-      .any(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+   /// Product of the multipliers of every rule matching `path`; `None` when
+   /// none match.
+   fn file_type(&self, path: &Path) -> Option<f32> {
+      self
+         .paths
+         .iter()
+         .filter(|(set, _)| set.is_match(path))
+         .map(|&(_, multiplier)| multiplier)
+         .reduce(|a, b| a * b)
+   }
 }
 
-/// Applies score multipliers based on chunk type and file category.
+impl Default for BoostRules {
+   fn default() -> Self {
+      let cfg = Config::default();
+      Self::new(&cfg.structure_boosts, &cfg.path_boosts)
+   }
+}
+
+/// Applies score multipliers based on chunk type and file path.
 ///
-/// Boosts functions, classes, interfaces, methods, and type aliases by 1.25x.
-/// Penalizes test files (0.85x) and documentation/config files (0.5x).
-pub fn apply_structural_boost(results: &mut [SearchResult]) {
+/// By default functions, classes, interfaces, methods and type aliases are
+/// boosted by 1.25x, test files penalized by 0.85x and documentation and
+/// config files by 0.5x; see [`BoostRules`]. Applied multipliers are recorded
+/// in the result's breakdown.
+pub fn apply_structural_boost(results: &mut [SearchResult], rules: &BoostRules) {
    for result in results.iter_mut() {
-      if let Some(
-         ChunkType::Function
-         | ChunkType::Class
-         | ChunkType::Interface
-         | ChunkType::Method
-         | ChunkType::TypeAlias,
-      ) = result.chunk_type
-      {
-         result.score *= 1.25;
-      }
+      let structure = rules.structure(result.chunk_type);
+      let file_type = rules.file_type(&result.path);
 
-      if is_test_file(&result.path) {
-         result.score *= 0.85;
+      result.score *= structure.unwrap_or(1.0) * file_type.unwrap_or(1.0);
+      if let Some(breakdown) = &mut result.breakdown {
+         breakdown.structure = structure;
+         breakdown.file_type = file_type;
       }
+   }
+}
 
-      if is_doc_or_config(&result.path) {
-         result.score *= 0.5;
+/// Blends each result's file-level anchor similarity into its score.
+///
+/// Scores are scaled by `1 + weight * sim`, so the anchor acts as a tiebreak
+/// between chunks of similar relevance rather than overriding the chunk score.
+/// Negative similarities are clamped to zero.
+pub fn apply_anchor_boost<S: BuildHasher>(
+   results: &mut [SearchResult],
+   anchor_sims: &HashMap<PathBuf, f32, S>,
+   weight: f32,
+) {
+   for result in results.iter_mut() {
+      if let Some(&sim) = anchor_sims.get(&result.path) {
+         let boost = weight.mul_add(sim.max(0.0), 1.0);
+         result.score *= boost;
+         if let Some(breakdown) = &mut result.breakdown {
+            breakdown.anchor = Some(boost);
+         }
       }
    }
 }
@@ -112,40 +214,12 @@ pub fn apply_per_file_limit(mut results: Vec<SearchResult>, limit: usize) -> Vec
    final_results
 }
 
-fn is_test_file(path: &Path) -> bool {
-   let Some(path_str) = path.to_str() else {
-      return false;
-   };
-   contains_ci(path_str, ".test.")
-      || contains_ci(path_str, ".spec.")
-      || contains_ci(path_str, "__tests__")
-}
-
-fn is_doc_or_config(path: &Path) -> bool {
-   if path.extension().is_some_and(|ext| {
-      ext.eq_ignore_ascii_case("md")
-         || ext.eq_ignore_ascii_case("mdx")
-         || ext.eq_ignore_ascii_case("txt")
-         || ext.eq_ignore_ascii_case("json")
-         || ext.eq_ignore_ascii_case("yaml")
-         || ext.eq_ignore_ascii_case("yml")
-         || ext.eq_ignore_ascii_case("lock")
-   }) {
-      return true;
-   }
-
-   let Some(path_str) = path.to_str() else {
-      return false;
-   };
-   contains_ci(path_str, "/docs/")
-}
-
 #[cfg(test)]
 mod tests {
    use std::path::PathBuf;
 
    use super::*;
-   use crate::Str;
+   use crate::{Str, types::ScoreBreakdown};
 
    fn make_result(path: &str, start_line: u32, score: f32, chunk_type: ChunkType) -> SearchResult {
       SearchResult {
@@ -156,6 +230,12 @@ mod tests {
          num_lines: 10,
          chunk_type: Some(chunk_type),
          is_anchor: Some(false),
+         breakdown: None,
+         bridges: None,
+         language: None,
+         context_prev: None,
+         context_next: None,
+         linked: Vec::new(),
       }
    }
 
@@ -168,7 +248,7 @@ mod tests {
          make_result("README.md", 1, 1.0, ChunkType::Other),
       ];
 
-      apply_structural_boost(&mut results);
+      apply_structural_boost(&mut results, &BoostRules::default());
 
       assert!((results[0].score - 1.25).abs() < 1e-6);
       assert!((results[1].score - 1.0).abs() < 1e-6);
@@ -176,6 +256,70 @@ mod tests {
       assert!((results[3].score - 0.5).abs() < 1e-6);
    }
 
+   #[test]
+   fn structural_boost_records_multipliers() {
+      let mut results = vec![
+         make_result("src/api.test.ts", 1, 1.0, ChunkType::Function),
+         make_result("docs/__tests__/notes.md", 1, 1.0, ChunkType::Other),
+      ];
+      for result in &mut results {
+         result.breakdown = Some(ScoreBreakdown { fused: 1.0, ..Default::default() });
+      }
+
+      apply_structural_boost(&mut results, &BoostRules::default());
+      apply_anchor_boost(
+         &mut results,
+         &HashMap::from([(PathBuf::from("src/api.test.ts"), 0.5)]),
+         0.2,
+      );
+
+      let first = results[0].breakdown.unwrap();
+      assert_eq!(first.structure, Some(1.25));
+      assert_eq!(first.file_type, Some(0.85));
+      assert!((first.anchor.unwrap() - 1.1).abs() < 1e-6);
+      assert!((results[0].score - 1.25 * 0.85 * 1.1).abs() < 1e-6);
+
+      let second = results[1].breakdown.unwrap();
+      assert_eq!(second.structure, None);
+      assert!((second.file_type.unwrap() - 0.425).abs() < 1e-6);
+      assert_eq!(second.anchor, None);
+   }
+
+   #[test]
+   fn custom_boost_rules() {
+      let structure = BTreeMap::from([("block".to_string(), 2.0), ("nonsense".to_string(), 3.0)]);
+      let rules = BoostRules::new(&structure, &[
+         PathBoost { globs: vec!["**/schemas/*.JSON".to_string()], multiplier: 1.5 },
+         PathBoost { globs: vec!["[".to_string(), "**/*.json".to_string()], multiplier: 0.5 },
+      ]);
+      let mut results = vec![
+         make_result("/repo/schemas/user.json", 1, 1.0, ChunkType::Block),
+         make_result("/repo/src/main.rs", 1, 1.0, ChunkType::Function),
+      ];
+
+      apply_structural_boost(&mut results, &rules);
+
+      assert!((results[0].score - 2.0 * 1.5 * 0.5).abs() < 1e-6);
+      assert!((results[1].score - 1.0).abs() < 1e-6);
+   }
+
+   #[test]
+   fn test_apply_anchor_boost() {
+      let mut results = vec![
+         make_result("src/a.rs", 1, 1.0, ChunkType::Function),
+         make_result("src/b.rs", 1, 1.0, ChunkType::Function),
+         make_result("src/c.rs", 1, 1.0, ChunkType::Function),
+      ];
+      let sims =
+         HashMap::from([(PathBuf::from("src/a.rs"), 0.8), (PathBuf::from("src/b.rs"), -0.3)]);
+
+      apply_anchor_boost(&mut results, &sims, 0.1);
+
+      assert!((results[0].score - 1.08).abs() < 1e-6);
+      assert!((results[1].score - 1.0).abs() < 1e-6);
+      assert!((results[2].score - 1.0).abs() < 1e-6);
+   }
+
    #[test]
    fn test_deduplicate() {
       let results = vec![
@@ -214,19 +358,17 @@ mod tests {
    }
 
    #[test]
-   fn test_is_test_file() {
-      assert!(is_test_file(Path::new("src/main.test.ts")));
-      assert!(is_test_file(Path::new("src/component.spec.js")));
-      assert!(is_test_file(Path::new("src/__tests__/utils.js")));
-      assert!(!is_test_file(Path::new("src/main.rs")));
-   }
-
-   #[test]
-   fn test_is_doc_or_config() {
-      assert!(is_doc_or_config(Path::new("README.md")));
-      assert!(is_doc_or_config(Path::new("package.json")));
-      assert!(is_doc_or_config(Path::new("config.yaml")));
-      assert!(is_doc_or_config(Path::new("docs/guide.md")));
-      assert!(!is_doc_or_config(Path::new("src/main.rs")));
+   fn default_rules_classify_paths() {
+      let rules = BoostRules::default();
+      let file_type = |path: &str| rules.file_type(Path::new(path));
+
+      assert_eq!(file_type("src/main.test.ts"), Some(0.85));
+      assert_eq!(file_type("src/component.spec.js"), Some(0.85));
+      assert_eq!(file_type("src/__tests__/utils.js"), Some(0.85));
+      assert_eq!(file_type("README.md"), Some(0.5));
+      assert_eq!(file_type("package.json"), Some(0.5));
+      assert_eq!(file_type("config.YAML"), Some(0.5));
+      assert_eq!(file_type("docs/guide.md"), Some(0.5));
+      assert_eq!(file_type("src/main.rs"), None);
    }
 }
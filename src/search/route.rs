@@ -0,0 +1,137 @@
+//! Routing queries that name a file or a symbol rather than describe code.
+//!
+//! A query such as `src/auth/token.rs` is looked up by path: results are
+//! restricted to matching files, falling back to a plain search when none
+//! match. A query such as `HttpClient::send` is searched as usual, with chunks
+//! that mention the symbol's name boosted.
+
+use std::{cmp::Ordering, path::Path};
+
+use super::{glob::PathGlobs, query};
+use crate::{
+   Result,
+   grammar::GrammarManager,
+   types::{QueryRoute, SearchResult},
+};
+
+/// Score multiplier for results that mention a symbol query's name.
+pub const SYMBOL_BOOST: f32 = 1.5;
+
+/// Separators between the segments of a qualified symbol, across languages.
+const SYMBOL_SEPARATORS: &[&str] = &["::", "->", ".", "#"];
+
+/// Classifies a query's free text. Only single-token queries are routed.
+pub fn classify(text: &str) -> QueryRoute {
+   let text = text.trim();
+   if text.is_empty() || text.contains(char::is_whitespace) {
+      return QueryRoute::Semantic;
+   }
+   if is_path_like(text) {
+      QueryRoute::Path
+   } else if is_symbol_like(text) {
+      QueryRoute::Symbol
+   } else {
+      QueryRoute::Semantic
+   }
+}
+
+fn is_path_like(text: &str) -> bool {
+   if text.contains("::") {
+      return false;
+   }
+   let has_known_extension = Path::new(text)
+      .extension()
+      .and_then(|e| e.to_str())
+      .and_then(GrammarManager::extension_to_language)
+      .is_some();
+   has_known_extension || (text.contains('/') && text.chars().any(char::is_alphanumeric))
+}
+
+fn is_symbol_like(text: &str) -> bool {
+   let text = text.strip_suffix("()").unwrap_or(text);
+   let is_ident = |s: &str| {
+      s.chars()
+         .next()
+         .is_some_and(|c| c.is_alphabetic() || c == '_')
+         && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+   };
+
+   let segments = split_symbol(text);
+   if segments.len() > 1 {
+      return segments.iter().all(|s| is_ident(s));
+   }
+   // A lone word is only a symbol when it is written like one: `snake_case`,
+   // `camelCase` or `PascalCase`, but not a plain `word` or `Word`.
+   is_ident(text) && (text.contains('_') || text.chars().skip(1).any(char::is_uppercase))
+}
+
+fn split_symbol(text: &str) -> Vec<&str> {
+   let mut segments = vec![text];
+   for sep in SYMBOL_SEPARATORS {
+      segments = segments.into_iter().flat_map(|s| s.split(sep)).collect();
+   }
+   segments
+}
+
+/// Unqualified name of a symbol query, e.g. `send` for `HttpClient::send()`.
+pub fn symbol_name(text: &str) -> &str {
+   let text = text.trim();
+   let text = text.strip_suffix("()").unwrap_or(text);
+   split_symbol(text).last().copied().unwrap_or(text)
+}
+
+/// Globs matching files whose path ends with a path query, or directories
+/// named by it.
+pub fn path_globs(text: &str) -> Result<PathGlobs> {
+   let path = globset::escape(text.trim().trim_start_matches("./").trim_matches('/'));
+   PathGlobs::new(Path::new("/"), &[format!("**/{path}"), format!("**/{path}/**")], &[])
+}
+
+/// Boosts results that mention `name` as a whole word and re-sorts them.
+pub fn apply_symbol_boost(results: &mut [SearchResult], name: &str) {
+   for result in results.iter_mut() {
+      if query::contains_word_ci(&result.content, name) {
+         result.score *= SYMBOL_BOOST;
+         if let Some(breakdown) = &mut result.breakdown {
+            breakdown.symbol = Some(SYMBOL_BOOST);
+         }
+      }
+   }
+   results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn classifies_queries() {
+      assert_eq!(classify("src/auth/token.rs"), QueryRoute::Path);
+      assert_eq!(classify("token.rs"), QueryRoute::Path);
+      assert_eq!(classify("src/auth/"), QueryRoute::Path);
+      assert_eq!(classify("HttpClient::send"), QueryRoute::Symbol);
+      assert_eq!(classify("client.send()"), QueryRoute::Symbol);
+      assert_eq!(classify("parse_chunk_type"), QueryRoute::Symbol);
+      assert_eq!(classify("HttpClient"), QueryRoute::Symbol);
+      assert_eq!(classify("authentication"), QueryRoute::Semantic);
+      assert_eq!(classify("Authentication"), QueryRoute::Semantic);
+      assert_eq!(classify("how is the token refreshed"), QueryRoute::Semantic);
+   }
+
+   #[test]
+   fn extracts_symbol_names() {
+      assert_eq!(symbol_name("HttpClient::send"), "send");
+      assert_eq!(symbol_name("client.send()"), "send");
+      assert_eq!(symbol_name("parse_chunk_type"), "parse_chunk_type");
+   }
+
+   #[test]
+   fn path_globs_match_suffixes() {
+      let globs = path_globs("./src/auth/token.rs").unwrap();
+      assert!(globs.matches(Path::new("/repo/src/auth/token.rs")));
+      assert!(!globs.matches(Path::new("/repo/src/auth/token.rs.bak")));
+
+      let globs = path_globs("src/auth").unwrap();
+      assert!(globs.matches(Path::new("/repo/src/auth/token.rs")));
+   }
+}
@@ -0,0 +1,117 @@
+//! Telling test code from the rest, for `--tests-only` and `--no-tests`.
+//!
+//! A chunk is test code when its file is a test file by name or directory,
+//! or, in Rust, where tests live next to the code, when the chunk itself is
+//! marked with `#[cfg(test)]` or a test attribute.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Directories holding only tests.
+const TEST_DIRS: &[&str] = &["tests", "test", "__tests__", "spec"];
+
+/// Rust attributes marking test code.
+const RUST_TEST_ATTRS: &[&str] = &["#[cfg(test)]", "#[test]", "#[tokio::test"];
+
+/// Which results a search keeps with respect to test code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TestFilter {
+   /// Keep everything.
+   #[default]
+   All,
+   /// Keep only test code.
+   Only,
+   /// Drop test code.
+   Exclude,
+}
+
+impl TestFilter {
+   pub const fn is_all(self) -> bool {
+      matches!(self, Self::All)
+   }
+
+   /// Whether a chunk at `path` with `content` passes the filter.
+   pub fn accepts(self, path: &Path, content: &str) -> bool {
+      match self {
+         Self::All => true,
+         Self::Only => is_test_chunk(path, content),
+         Self::Exclude => !is_test_chunk(path, content),
+      }
+   }
+}
+
+/// Whether `path` names a test file: `foo.test.ts`, `foo.spec.js`,
+/// `foo_test.go`, `test_foo.py`, `FooTest.java`, or anything under a
+/// `tests/`, `test/`, `__tests__/` or `spec/` directory.
+pub fn is_test_file(path: &Path) -> bool {
+   let in_test_dir = path.parent().is_some_and(|dir| {
+      dir.components().any(|c| {
+         let name = c.as_os_str().to_string_lossy();
+         TEST_DIRS.iter().any(|d| name.eq_ignore_ascii_case(d))
+      })
+   });
+   if in_test_dir {
+      return true;
+   }
+
+   let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+      return false;
+   };
+   let lower = name.to_ascii_lowercase();
+   if lower.contains(".test.") || lower.contains(".spec.") {
+      return true;
+   }
+
+   let stem = name.split('.').next().unwrap_or(name);
+   let lower_stem = stem.to_ascii_lowercase();
+   lower_stem.ends_with("_test")
+      || lower_stem.ends_with("_spec")
+      || lower_stem.starts_with("test_")
+      || stem.ends_with("Test")
+      || stem.ends_with("Tests")
+}
+
+/// Whether a chunk is test code: it comes from a test file, or it is Rust
+/// code carrying a test attribute.
+pub fn is_test_chunk(path: &Path, content: &str) -> bool {
+   is_test_file(path)
+      || (path.extension().is_some_and(|e| e == "rs")
+         && RUST_TEST_ATTRS.iter().any(|attr| content.contains(attr)))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn detects_test_files() {
+      for path in [
+         "/repo/src/app.test.ts",
+         "/repo/src/app.spec.js",
+         "/repo/pkg/server_test.go",
+         "/repo/pkg/test_server.py",
+         "/repo/src/main/java/ServerTest.java",
+         "/repo/tests/integration.rs",
+         "/repo/web/__tests__/app.tsx",
+      ] {
+         assert!(is_test_file(Path::new(path)), "{path}");
+      }
+      for path in ["/repo/src/contest.rs", "/repo/src/testing.go", "/repo/src/attest.py"] {
+         assert!(!is_test_file(Path::new(path)), "{path}");
+      }
+   }
+
+   #[test]
+   fn detects_rust_test_chunks() {
+      let path = Path::new("/repo/src/lib.rs");
+      assert!(is_test_chunk(path, "#[cfg(test)]\nmod tests {}"));
+      assert!(is_test_chunk(path, "#[tokio::test]\nasync fn runs() {}"));
+      assert!(!is_test_chunk(path, "fn run() {}"));
+      assert!(!is_test_chunk(Path::new("/repo/README.md"), "#[test]"));
+
+      assert!(TestFilter::Only.accepts(path, "#[test]\nfn it() {}"));
+      assert!(TestFilter::Exclude.accepts(path, "fn run() {}"));
+      assert!(!TestFilter::Exclude.accepts(Path::new("/repo/tests/it.rs"), "fn run() {}"));
+   }
+}
@@ -0,0 +1,235 @@
+//! Default local backend: flat files for small stores, `LanceDB` beyond.
+//!
+//! New stores start in a [`FlatStore`], which needs no table setup or index
+//! builds. Once a store grows past `flat_store_max_chunks` rows its records
+//! move into a [`LanceStore`] and stay there. Stores already in Lance are
+//! left where they are.
+
+use std::{
+   collections::HashMap,
+   path::{Path, PathBuf},
+};
+
+use parking_lot::RwLock;
+
+use crate::{
+   config,
+   embed::HybridEmbedding,
+   error::Result,
+   meta::FileHash,
+   store::{
+      ChunkVector, FileSearchParams, FlatStore, KeywordParams, LanceStore, SearchParams, Store,
+   },
+   types::{IndexedFile, SearchResponse, SearchResult, StoreInfo, VectorRecord},
+};
+
+/// Backend currently holding a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tier {
+   Flat,
+   Lance,
+}
+
+/// [`Store`] that keeps each store flat until it outgrows the threshold.
+pub struct AutoStore {
+   flat:     FlatStore,
+   lance:    LanceStore,
+   max_flat: usize,
+   tiers:    RwLock<HashMap<String, Tier>>,
+}
+
+impl AutoStore {
+   /// Creates a store using the data directory and threshold from
+   /// configuration.
+   pub fn new() -> Result<Self> {
+      Ok(Self {
+         flat:     FlatStore::new()?,
+         lance:    LanceStore::new()?,
+         max_flat: config::get().flat_store_max_chunks,
+         tiers:    RwLock::new(HashMap::new()),
+      })
+   }
+
+   async fn tier(&self, store_id: &str) -> Result<Tier> {
+      if let Some(&tier) = self.tiers.read().get(store_id) {
+         return Ok(tier);
+      }
+
+      let in_lance = !self.flat.exists(store_id)
+         && self.lance.has_table(store_id)
+         && !self.lance.is_empty(store_id).await?;
+      let tier = if in_lance { Tier::Lance } else { Tier::Flat };
+      self.tiers.write().insert(store_id.to_string(), tier);
+      Ok(tier)
+   }
+
+   async fn store(&self, store_id: &str) -> Result<&dyn Store> {
+      Ok(match self.tier(store_id).await? {
+         Tier::Flat => &self.flat,
+         Tier::Lance => &self.lance,
+      })
+   }
+
+   /// Moves a flat store into Lance. Searches keep going to the flat store
+   /// until every record has been copied.
+   async fn promote(&self, store_id: &str) -> Result<()> {
+      let records = self.flat.records(store_id).await?;
+      tracing::info!(
+         "{store_id} has {} chunks, more than flat_store_max_chunks; moving it to lance",
+         records.len()
+      );
+      // Leftovers of an earlier, interrupted move would be duplicated.
+      if self.lance.has_table(store_id) {
+         self.lance.delete_store(store_id).await?;
+      }
+      self.lance.insert_batch(store_id, records).await?;
+      self.tiers.write().insert(store_id.to_string(), Tier::Lance);
+      self.flat.delete_store(store_id).await
+   }
+}
+
+#[async_trait::async_trait]
+impl Store for AutoStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if self.tier(store_id).await? == Tier::Lance {
+         return self.lance.insert_batch(store_id, records).await;
+      }
+      self.flat.insert_batch(store_id, records).await?;
+      if self.flat.row_count(store_id).await? > self.max_flat {
+         self.promote(store_id).await?;
+      }
+      Ok(())
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      self.store(params.store_id).await?.search(params).await
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      self
+         .store(params.store_id)
+         .await?
+         .keyword_search(params)
+         .await
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      self
+         .store(store_id)
+         .await?
+         .find_bridged(store_id, key, limit)
+         .await
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      self
+         .store(params.store_id)
+         .await?
+         .search_files(params)
+         .await
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      self.store(store_id).await?.chunk_vectors(store_id).await
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      self
+         .store(store_id)
+         .await?
+         .delete_file(store_id, file_path)
+         .await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      self
+         .store(store_id)
+         .await?
+         .delete_files(store_id, file_paths)
+         .await
+   }
+
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      if self.tier(store_id).await? == Tier::Lance {
+         return self
+            .lance
+            .replace_files(store_id, file_paths, records)
+            .await;
+      }
+      self.flat.delete_files(store_id, file_paths).await?;
+      let written = records.len();
+      self.insert_batch(store_id, records).await?;
+      Ok(written)
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      self.tiers.write().remove(store_id);
+      self.flat.delete_store(store_id).await?;
+      if self.lance.has_table(store_id) {
+         self.lance.delete_store(store_id).await?;
+      }
+      Ok(())
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      self.store(store_id).await?.get_info(store_id).await
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      self.store(store_id).await?.list_files(store_id).await
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      self.store(store_id).await?.is_empty(store_id).await
+   }
+
+   async fn create_fts_index(&self, store_id: &str) -> Result<()> {
+      self.store(store_id).await?.create_fts_index(store_id).await
+   }
+
+   async fn create_vector_index(&self, store_id: &str) -> Result<()> {
+      self
+         .store(store_id)
+         .await?
+         .create_vector_index(store_id)
+         .await
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      self.store(store_id).await?.get_file_hashes(store_id).await
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      self
+         .store(store_id)
+         .await?
+         .get_chunk_embeddings(store_id, file_paths)
+         .await
+   }
+
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      self.store(store_id).await?.optimize(store_id).await
+   }
+
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      self.store(store_id).await?.warm_up(store_id).await
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      self.store(store_id).await?.relativize(store_id, root).await
+   }
+}
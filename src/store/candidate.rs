@@ -0,0 +1,225 @@
+//! Candidate scoring shared by backends that rank rows in-process.
+//!
+//! Backends without a native hybrid search pipeline fetch a pool of
+//! candidates, then hand them here for dense scoring, `ColBERT` reranking and
+//! score fusion.
+
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use ndarray::Array2;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+   Str, config,
+   search::{
+      colbert::max_sim_batch,
+      fusion::{self, Fusion},
+   },
+   store::ChunkVector,
+   types::{ChunkType, ScoreBreakdown, SearchResult, SearchStage, StageTiming},
+};
+
+/// Row fetched during search, before scoring.
+pub struct Candidate {
+   pub path:          PathBuf,
+   pub content:       String,
+   pub start_line:    u32,
+   pub end_line:      u32,
+   pub vector:        Vec<f32>,
+   pub colbert:       Option<Vec<u8>>,
+   pub colbert_scale: f64,
+   pub is_anchor:     Option<bool>,
+   pub chunk_type:    Option<ChunkType>,
+   pub context_prev:  Option<String>,
+   pub context_next:  Option<String>,
+   /// Full-text score, for candidates returned by the full-text query.
+   pub fts:           Option<f32>,
+   pub bridges:       Option<String>,
+   pub language:      Option<String>,
+   pub score:         f32,
+}
+
+impl Candidate {
+   /// Converts to a result carrying the neighbouring chunks alongside it.
+   pub fn into_result(self, breakdown: Option<ScoreBreakdown>) -> SearchResult {
+      SearchResult {
+         path: self.path,
+         content: self.content.into(),
+         score: self.score,
+         start_line: self.start_line,
+         num_lines: self.end_line.saturating_sub(self.start_line).max(1),
+         chunk_type: self.chunk_type,
+         is_anchor: self.is_anchor,
+         breakdown,
+         bridges: self.bridges.map(Str::from),
+         language: self.language.map(Str::from),
+         context_prev: self.context_prev.map(Str::from),
+         context_next: self.context_next.map(Str::from),
+         linked: Vec::new(),
+      }
+   }
+}
+
+/// Keeps the candidates that are chunks of code, dropping anchors and module
+/// summaries.
+pub fn chunk_vectors(candidates: Vec<Candidate>) -> Vec<ChunkVector> {
+   candidates
+      .into_iter()
+      .filter(|c| c.is_anchor != Some(true) && c.chunk_type != Some(ChunkType::Module))
+      .map(|c| ChunkVector {
+         path:       c.path,
+         start_line: c.start_line,
+         end_line:   c.end_line,
+         vector:     c.vector,
+      })
+      .collect()
+}
+
+/// Scores candidates returned by a full-text query, in relevance order, by
+/// their rank.
+pub fn mark_fts(candidates: &mut [Candidate]) {
+   for (rank, cand) in candidates.iter_mut().enumerate() {
+      cand.fts = Some(ScoreBreakdown::fts_rank_score(rank));
+   }
+}
+
+/// Deduplicates candidates by location, scores them against the dense query
+/// vector, reranks the first `rerank_depth` with `ColBERT` when a query
+/// matrix is given and fuses the signals into each result's score. Reranking
+/// stops early once `cancel` fires. Also returns the time spent, as the
+/// search's rerank stage.
+pub fn rank(
+   candidates: Vec<Candidate>,
+   query_vector: &[f32],
+   query_colbert: Option<&Array2<f32>>,
+   rerank_depth: usize,
+   cancel: Option<&CancellationToken>,
+) -> (Vec<SearchResult>, StageTiming) {
+   let started = Instant::now();
+   let mut candidates = dedup(candidates);
+
+   for cand in &mut candidates {
+      cand.score = dot(query_vector, &cand.vector);
+   }
+   sort_by_score(&mut candidates, |c| c.score);
+
+   let mut colbert_scores = vec![None; candidates.len()];
+   if let Some(query) = query_colbert {
+      let (indices, docs): (Vec<usize>, Vec<(&[u8], f64)>) = candidates
+         .iter()
+         .take(rerank_depth)
+         .enumerate()
+         .filter_map(|(i, cand)| {
+            let colbert = cand.colbert.as_deref().filter(|c| !c.is_empty())?;
+            Some((i, (colbert, cand.colbert_scale)))
+         })
+         .unzip();
+      let scores = max_sim_batch(query, &docs, config::get().colbert_dim, cancel);
+      for (i, score) in indices.into_iter().zip(scores) {
+         colbert_scores[i] = Some(score);
+      }
+   }
+
+   let mut results: Vec<SearchResult> = candidates
+      .into_iter()
+      .zip(colbert_scores)
+      .map(|(cand, colbert)| {
+         let breakdown = ScoreBreakdown {
+            dense: Some(cand.score),
+            fts: cand.fts,
+            colbert,
+            fused: cand.score,
+            ..Default::default()
+         };
+         cand.into_result(Some(breakdown))
+      })
+      .collect();
+
+   fusion::fuse(&mut results, &Fusion::from_config());
+   sort_by_score(&mut results, |r| r.score);
+   (results, StageTiming::since(SearchStage::Rerank, started))
+}
+
+/// Deduplicates candidates already ordered by full-text relevance and scores
+/// them by reciprocal rank, for searches that have no query embedding.
+pub fn rank_by_position(candidates: Vec<Candidate>) -> Vec<SearchResult> {
+   dedup(candidates)
+      .into_iter()
+      .enumerate()
+      .map(|(rank, cand)| {
+         let score = ScoreBreakdown::fts_rank_score(rank);
+         let breakdown = ScoreBreakdown { fts: Some(score), fused: score, ..Default::default() };
+         let mut result = cand.into_result(Some(breakdown));
+         result.score = score;
+         result
+      })
+      .collect()
+}
+
+/// Converts rows matched by a bridge key into results without context lines.
+pub fn bridged(candidates: Vec<Candidate>) -> Vec<SearchResult> {
+   candidates
+      .into_iter()
+      .map(|mut cand| {
+         cand.context_prev = None;
+         cand.context_next = None;
+         cand.into_result(None)
+      })
+      .collect()
+}
+
+/// Scores anchor rows by dense similarity to the query and keeps the best
+/// `limit`.
+pub fn rank_anchors(
+   candidates: Vec<Candidate>,
+   query_vector: &[f32],
+   limit: usize,
+) -> Vec<SearchResult> {
+   let mut results: Vec<SearchResult> = candidates
+      .into_iter()
+      .map(|cand| {
+         let score = dot(query_vector, &cand.vector);
+         let breakdown = ScoreBreakdown { dense: Some(score), fused: score, ..Default::default() };
+         let mut result = cand.into_result(Some(breakdown));
+         result.score = score;
+         result
+      })
+      .collect();
+   sort_by_score(&mut results, |r| r.score);
+   results.truncate(limit);
+   results
+}
+
+/// Drops repeated locations, keeping the first occurrence and carrying over
+/// any full-text score found on a later one.
+fn dedup(candidates: Vec<Candidate>) -> Vec<Candidate> {
+   let mut index: HashMap<(PathBuf, u32), usize> = HashMap::with_capacity(candidates.len());
+   let mut unique: Vec<Candidate> = Vec::with_capacity(candidates.len());
+
+   for cand in candidates {
+      match index.get(&(cand.path.clone(), cand.start_line)) {
+         Some(&i) => {
+            if unique[i].fts.is_none() {
+               unique[i].fts = cand.fts;
+            }
+         },
+         None => {
+            index.insert((cand.path.clone(), cand.start_line), unique.len());
+            unique.push(cand);
+         },
+      }
+   }
+   unique
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+   a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn sort_by_score<T>(items: &mut [T], score: impl Fn(&T) -> f32) {
+   items.sort_by(|a, b| {
+      score(b)
+         .partial_cmp(&score(a))
+         .unwrap_or(std::cmp::Ordering::Equal)
+   });
+}
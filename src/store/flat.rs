@@ -0,0 +1,763 @@
+//! Flat-file storage backend for small repositories.
+//!
+//! Keeps every chunk of a store in memory and persists the store as a single
+//! postcard file at `<data_dir>/<store_id>/index.flat`, rewritten after each
+//! change. Search scores every row by brute force with the SIMD kernels from
+//! [`colbert`], which for a few thousand chunks is quicker than opening,
+//! writing and indexing a Lance table.
+
+use std::{
+   collections::{HashMap, HashSet, hash_map::Entry},
+   fs,
+   path::{Path, PathBuf},
+   sync::Arc,
+};
+
+use ndarray::Array2;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+   chunker::bridge,
+   config,
+   embed::HybridEmbedding,
+   error::Result,
+   meta::FileHash,
+   search::{colbert, glob::PathGlobs, ranking},
+   store::{
+      self, ChunkVector, FileSearchParams, KeywordParams, SearchParams,
+      candidate::{self, Candidate, sort_by_score},
+      rooted,
+   },
+   types::{
+      IndexedFile, QueryRoute, SearchResponse, SearchResult, SearchStatus, StageTiming, StoreInfo,
+      VectorRecord, VectorSearchMode,
+   },
+};
+
+/// Current file layout version, written ahead of the records.
+///
+/// Bump this whenever [`VectorRecord`] changes. Flat stores are small, so
+/// files in an older layout are cleared and re-indexed rather than converted.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// File name of the index inside a store's data directory.
+const INDEX_FILE: &str = "index.flat";
+
+/// Rows ranked by keyword matches that go on to fusion and reranking.
+const KEYWORD_CANDIDATES: usize = 50;
+
+/// Results whose file anchor is compared against the query.
+const ANCHOR_CAP: usize = 100;
+
+/// Errors that can occur during flat store operations.
+#[derive(Debug, thiserror::Error)]
+pub enum FlatStoreError {
+   #[error("failed to decode index: {0}")]
+   Decode(#[source] postcard::Error),
+
+   #[error("failed to encode index: {0}")]
+   Encode(#[source] postcard::Error),
+
+   #[error(
+      "index uses format v{found}, but this smgrep supports up to v{supported}; upgrade smgrep or \
+       run `smgrep clean`"
+   )]
+   FormatTooNew { found: u32, supported: u32 },
+
+   #[error("index task failed: {0}")]
+   Task(#[from] tokio::task::JoinError),
+}
+
+/// Contents of an index file after the version.
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+   dense_dim: usize,
+   records:   Vec<VectorRecord>,
+}
+
+impl Index {
+   fn open(path: &Path, store_id: &str) -> Result<Self> {
+      let dense_dim = config::get().dense_vector_dim();
+      let bytes = match fs::read(path) {
+         Ok(bytes) => bytes,
+         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Self { dense_dim, records: Vec::new() });
+         },
+         Err(e) => return Err(e.into()),
+      };
+
+      let (version, body) =
+         postcard::take_from_bytes::<u32>(&bytes).map_err(FlatStoreError::Decode)?;
+      if version > FORMAT_VERSION {
+         return Err(
+            FlatStoreError::FormatTooNew { found: version, supported: FORMAT_VERSION }.into(),
+         );
+      }
+      if version < FORMAT_VERSION {
+         tracing::warn!("index {store_id} uses format v{version}; clearing for re-index");
+         return Ok(Self { dense_dim, records: Vec::new() });
+      }
+      let mut index: Self = postcard::from_bytes(body).map_err(FlatStoreError::Decode)?;
+      if index.dense_dim != dense_dim {
+         tracing::warn!(
+            "index {store_id} has {}-dim vectors; clearing for re-index",
+            index.dense_dim
+         );
+         index = Self { dense_dim, records: Vec::new() };
+      }
+      Ok(index)
+   }
+
+   /// Writes the index next to `path` and renames it into place, so a crash
+   /// mid-write leaves the previous version intact.
+   fn save(&self, path: &Path) -> Result<()> {
+      if let Some(parent) = path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+      let version = postcard::to_allocvec(&FORMAT_VERSION).map_err(FlatStoreError::Encode)?;
+      let bytes = postcard::to_extend(self, version).map_err(FlatStoreError::Encode)?;
+
+      let tmp = path.with_extension("flat.tmp");
+      fs::write(&tmp, bytes)?;
+      fs::rename(&tmp, path)?;
+      Ok(())
+   }
+}
+
+/// Single-file, in-memory implementation of [`Store`](super::Store).
+pub struct FlatStore {
+   indexes:  RwLock<HashMap<String, Arc<RwLock<Index>>>>,
+   data_dir: PathBuf,
+}
+
+impl FlatStore {
+   /// Creates a new store using the data directory from configuration.
+   pub fn new() -> Result<Self> {
+      Self::with_data_dir(config::data_dir().clone())
+   }
+
+   /// Creates a new store rooted at `data_dir`.
+   pub fn with_data_dir(data_dir: PathBuf) -> Result<Self> {
+      fs::create_dir_all(&data_dir)?;
+      Ok(Self { indexes: RwLock::new(HashMap::new()), data_dir })
+   }
+
+   fn index_path(&self, store_id: &str) -> PathBuf {
+      self.data_dir.join(store_id).join(INDEX_FILE)
+   }
+
+   /// Whether the store has been written to disk.
+   pub fn exists(&self, store_id: &str) -> bool {
+      self.index_path(store_id).exists()
+   }
+
+   /// Number of rows in the store, anchors included.
+   pub async fn row_count(&self, store_id: &str) -> Result<usize> {
+      self.read(store_id, |index| index.records.len()).await
+   }
+
+   /// Copies every record in the store, anchors included.
+   pub async fn records(&self, store_id: &str) -> Result<Vec<VectorRecord>> {
+      self.read(store_id, |index| index.records.clone()).await
+   }
+
+   async fn load(&self, store_id: &str) -> Result<Arc<RwLock<Index>>> {
+      if let Some(index) = self.indexes.read().get(store_id) {
+         return Ok(Arc::clone(index));
+      }
+
+      let path = self.index_path(store_id);
+      let id = store_id.to_string();
+      let index = tokio::task::spawn_blocking(move || Index::open(&path, &id))
+         .await
+         .map_err(FlatStoreError::Task)??;
+
+      let mut indexes = self.indexes.write();
+      Ok(match indexes.entry(store_id.to_string()) {
+         Entry::Occupied(e) => Arc::clone(e.get()),
+         Entry::Vacant(e) => Arc::clone(e.insert(Arc::new(RwLock::new(index)))),
+      })
+   }
+
+   /// Runs `f` against the store's records on the blocking thread pool.
+   async fn read<T, F>(&self, store_id: &str, f: F) -> Result<T>
+   where
+      T: Send + 'static,
+      F: FnOnce(&Index) -> T + Send + 'static,
+   {
+      let index = self.load(store_id).await?;
+      Ok(tokio::task::spawn_blocking(move || f(&index.read()))
+         .await
+         .map_err(FlatStoreError::Task)?)
+   }
+
+   /// Applies `f` to the store's records and persists them.
+   async fn modify<F>(&self, store_id: &str, f: F) -> Result<()>
+   where
+      F: FnOnce(&mut Index) + Send + 'static,
+   {
+      let index = self.load(store_id).await?;
+      let path = self.index_path(store_id);
+      tokio::task::spawn_blocking(move || {
+         let mut index = index.write();
+         f(&mut index);
+         index.save(&path)
+      })
+      .await
+      .map_err(FlatStoreError::Task)?
+   }
+}
+
+impl Default for FlatStore {
+   fn default() -> Self {
+      Self::new().expect("failed to create FlatStore")
+   }
+}
+
+/// Search restrictions moved onto the blocking pool.
+struct RowFilter {
+   path:          Option<String>,
+   globs:         Option<PathGlobs>,
+   indexed_after: Option<u64>,
+   crate_name:    Option<String>,
+}
+
+impl RowFilter {
+   fn accepts(&self, record: &VectorRecord) -> bool {
+      !record.is_anchor.unwrap_or(false)
+         && self
+            .path
+            .as_deref()
+            .is_none_or(|prefix| store::path_to_store_value(&record.path).starts_with(prefix))
+         && self
+            .globs
+            .as_ref()
+            .is_none_or(|globs| globs.matches(&record.path))
+         && self
+            .indexed_after
+            .is_none_or(|after| record.indexed_at.is_some_and(|t| t >= after))
+         && self
+            .crate_name
+            .as_deref()
+            .is_none_or(|name| record.crate_name.as_deref() == Some(name))
+   }
+}
+
+/// Lowercased words of `text` that keyword search matches on.
+fn terms(text: &str) -> Vec<String> {
+   let mut terms: Vec<String> = text
+      .split(|c: char| !c.is_alphanumeric() && c != '_')
+      .filter(|t| !t.is_empty())
+      .map(str::to_lowercase)
+      .collect();
+   terms.sort();
+   terms.dedup();
+   terms
+}
+
+/// Indices of the `limit` rows containing the most distinct `terms`, best
+/// first.
+fn keyword_matches(rows: &[&VectorRecord], terms: &[String], limit: usize) -> Vec<usize> {
+   if terms.is_empty() {
+      return Vec::new();
+   }
+   let mut hits: Vec<(usize, usize)> = rows
+      .iter()
+      .enumerate()
+      .filter_map(|(i, record)| {
+         let content = record.content.to_lowercase();
+         let count = terms
+            .iter()
+            .filter(|t| content.contains(t.as_str()))
+            .count();
+         (count > 0).then_some((count, i))
+      })
+      .collect();
+   hits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+   hits.into_iter().take(limit).map(|(_, i)| i).collect()
+}
+
+fn to_candidate(record: &VectorRecord) -> Candidate {
+   Candidate {
+      path:          record.path.as_ref().clone(),
+      content:       record.content.to_string(),
+      start_line:    record.start_line,
+      end_line:      record.end_line,
+      vector:        record.vector.clone(),
+      colbert:       Some(record.colbert.clone()),
+      colbert_scale: record.colbert_scale,
+      is_anchor:     record.is_anchor,
+      chunk_type:    record.chunk_type,
+      context_prev:  record.context_prev.as_ref().map(ToString::to_string),
+      context_next:  record.context_next.as_ref().map(ToString::to_string),
+      fts:           None,
+      bridges:       record.bridges.as_ref().map(ToString::to_string),
+      language:      record.language.as_ref().map(ToString::to_string),
+      score:         0.0,
+   }
+}
+
+#[allow(clippy::too_many_arguments, reason = "one argument per search parameter")]
+fn search_records(
+   index: &Index,
+   query_text: &str,
+   query_vector: &[f32],
+   query_colbert: Option<&Array2<f32>>,
+   rerank_depth: usize,
+   limit: usize,
+   filter: &RowFilter,
+   cancel: Option<&CancellationToken>,
+) -> (Vec<SearchResult>, StageTiming) {
+   let rows: Vec<&VectorRecord> = index.records.iter().filter(|r| filter.accepts(r)).collect();
+
+   let mut dense: Vec<(f32, usize)> = rows
+      .iter()
+      .enumerate()
+      .map(|(i, record)| (colbert::dot_product_simd(query_vector, &record.vector), i))
+      .collect();
+   sort_by_score(&mut dense, |&(score, _)| score);
+
+   let mut candidates: Vec<Candidate> = dense
+      .iter()
+      .take(store::DENSE_CANDIDATES.max(rerank_depth))
+      .map(|&(_, i)| to_candidate(rows[i]))
+      .collect();
+   let mut keyword: Vec<Candidate> = keyword_matches(&rows, &terms(query_text), KEYWORD_CANDIDATES)
+      .into_iter()
+      .map(|i| to_candidate(rows[i]))
+      .collect();
+   candidate::mark_fts(&mut keyword);
+   candidates.extend(keyword);
+
+   let (mut results, rerank) =
+      candidate::rank(candidates, query_vector, query_colbert, rerank_depth, cancel);
+
+   let anchor_weight = config::get().anchor_weight;
+   if anchor_weight > 0.0 && !results.is_empty() {
+      let head = results.len().min(ANCHOR_CAP);
+      let paths: HashSet<&Path> = results[..head].iter().map(|r| r.path.as_path()).collect();
+      let sims: HashMap<PathBuf, f32> = index
+         .records
+         .iter()
+         .filter(|r| r.is_anchor == Some(true) && paths.contains(r.path.as_path()))
+         .map(|r| (r.path.as_ref().clone(), colbert::dot_product_simd(query_vector, &r.vector)))
+         .collect();
+      ranking::apply_anchor_boost(&mut results[..head], &sims, anchor_weight);
+      sort_by_score(&mut results, |r| r.score);
+   }
+
+   results.truncate(limit);
+   (results, rerank)
+}
+
+#[async_trait::async_trait]
+impl super::Store for FlatStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if records.is_empty() {
+         return Ok(());
+      }
+      self
+         .modify(store_id, move |index| index.records.extend(records))
+         .await
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let query_text = params.query_text.to_owned();
+      let query_vector = params.query_vector.to_vec();
+      let query_colbert =
+         (params.rerank && !params.query_colbert.is_empty()).then(|| params.query_colbert.clone());
+      let filter = RowFilter {
+         path:          params.path_filter.map(store::path_to_store_value),
+         globs:         params.path_globs.cloned(),
+         indexed_after: params.indexed_after,
+         crate_name:    params.crate_name.map(str::to_owned),
+      };
+      let rerank_depth = params.rerank_count();
+      let limit = params.limit;
+      let cancel = params.cancel.cloned();
+
+      let (results, rerank) = self
+         .read(params.store_id, move |index| {
+            search_records(
+               index,
+               &query_text,
+               &query_vector,
+               query_colbert.as_ref(),
+               rerank_depth,
+               limit,
+               &filter,
+               cancel.as_ref(),
+            )
+         })
+         .await?;
+
+      // Every row is scored, so search is always exact.
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: VectorSearchMode::Exact,
+         route: QueryRoute::Semantic,
+         stages: vec![rerank],
+      })
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let terms = terms(params.query_text);
+      let filter = RowFilter {
+         path:          params.path_filter.map(store::path_to_store_value),
+         globs:         params.path_globs.cloned(),
+         indexed_after: params.indexed_after,
+         crate_name:    params.crate_name.map(str::to_owned),
+      };
+      let limit = params.limit;
+
+      let results = self
+         .read(params.store_id, move |index| {
+            let rows: Vec<&VectorRecord> =
+               index.records.iter().filter(|r| filter.accepts(r)).collect();
+            let candidates = keyword_matches(&rows, &terms, limit)
+               .into_iter()
+               .map(|i| to_candidate(rows[i]))
+               .collect();
+            candidate::rank_by_position(candidates)
+         })
+         .await?;
+
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: VectorSearchMode::Keyword,
+         route: QueryRoute::Semantic,
+         stages: Vec::new(),
+      })
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let key = key.to_string();
+      self
+         .read(store_id, move |index| {
+            let candidates = index
+               .records
+               .iter()
+               .filter(|r| {
+                  !r.is_anchor.unwrap_or(false)
+                     && r
+                        .bridges
+                        .as_deref()
+                        .is_some_and(|b| bridge::keys(b).any(|k| k == key))
+               })
+               .take(limit)
+               .map(to_candidate)
+               .collect();
+            candidate::bridged(candidates)
+         })
+         .await
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let prefix = params.path_filter.map(store::path_to_store_value);
+      let query_vector = params.query_vector.to_vec();
+      let limit = params.limit;
+      self
+         .read(params.store_id, move |index| {
+            let candidates = index
+               .records
+               .iter()
+               .filter(|r| {
+                  r.is_anchor == Some(true)
+                     && prefix
+                        .as_deref()
+                        .is_none_or(|p| store::path_to_store_value(&r.path).starts_with(p))
+               })
+               .map(to_candidate)
+               .collect();
+            candidate::rank_anchors(candidates, &query_vector, limit)
+         })
+         .await
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      self
+         .read(store_id, |index| {
+            candidate::chunk_vectors(index.records.iter().map(to_candidate).collect())
+         })
+         .await
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      self
+         .delete_files(store_id, &[file_path.to_path_buf()])
+         .await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      if file_paths.is_empty() || !self.exists(store_id) {
+         return Ok(());
+      }
+      let paths: HashSet<PathBuf> = file_paths.iter().cloned().collect();
+      self
+         .modify(store_id, move |index| {
+            index.records.retain(|r| !paths.contains(r.path.as_ref()));
+         })
+         .await
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      self.indexes.write().remove(store_id);
+      match fs::remove_file(self.index_path(store_id)) {
+         Ok(()) => Ok(()),
+         Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+         Err(e) => Err(e.into()),
+      }
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      Ok(StoreInfo {
+         store_id:        store_id.to_string(),
+         row_count:       self.row_count(store_id).await? as u64,
+         path:            self.index_path(store_id),
+         dense_dim:       config::get().dense_vector_dim(),
+         dense_quantized: false,
+      })
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      if !self.exists(store_id) {
+         return Ok(vec![]);
+      }
+      self
+         .read(store_id, |index| {
+            let mut files: Vec<IndexedFile> = Vec::new();
+            let mut positions: HashMap<&Path, usize> = HashMap::new();
+            for record in &index.records {
+               match positions.entry(record.path.as_path()) {
+                  Entry::Occupied(e) => {
+                     let file = &mut files[*e.get()];
+                     file.indexed_at = file.indexed_at.max(record.indexed_at);
+                  },
+                  Entry::Vacant(e) => {
+                     e.insert(files.len());
+                     files.push(IndexedFile {
+                        path:       record.path.as_ref().clone(),
+                        indexed_at: record.indexed_at,
+                     });
+                  },
+               }
+            }
+            files
+         })
+         .await
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      if !self.exists(store_id) {
+         return Ok(true);
+      }
+      Ok(self.row_count(store_id).await? == 0)
+   }
+
+   async fn create_fts_index(&self, _store_id: &str) -> Result<()> {
+      // Keyword search scans the content of every row; there is no index.
+      Ok(())
+   }
+
+   async fn create_vector_index(&self, _store_id: &str) -> Result<()> {
+      // Vectors are scanned exhaustively; there is no index to build.
+      Ok(())
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      if !self.exists(store_id) {
+         return Ok(HashMap::new());
+      }
+      self
+         .read(store_id, |index| {
+            index
+               .records
+               .iter()
+               .map(|r| (r.path.as_ref().clone(), r.hash))
+               .collect()
+         })
+         .await
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      if file_paths.is_empty() || !self.exists(store_id) {
+         return Ok(HashMap::new());
+      }
+      let paths: HashSet<PathBuf> = file_paths.iter().cloned().collect();
+      self
+         .read(store_id, move |index| {
+            index
+               .records
+               .iter()
+               .filter(|r| paths.contains(r.path.as_ref()))
+               .map(|r| {
+                  (r.chunk_hash, HybridEmbedding {
+                     dense:         r.vector.clone(),
+                     colbert:       r.colbert.clone(),
+                     colbert_scale: r.colbert_scale,
+                  })
+               })
+               .collect()
+         })
+         .await
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      if !self.exists(store_id) {
+         return Ok(true);
+      }
+      let root = root.to_path_buf();
+      self
+         .modify(store_id, move |index| {
+            index.records = rooted::stored_records(&root, std::mem::take(&mut index.records));
+         })
+         .await?;
+      Ok(true)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use tempfile::TempDir;
+
+   use super::*;
+   use crate::{Str, grammar::GrammarManager, store::Store, types::ChunkType};
+
+   fn record(path: &str, content: &'static str, axis: usize) -> VectorRecord {
+      let mut vector = vec![0.0; config::get().dense_vector_dim()];
+      vector[axis] = 1.0;
+      VectorRecord {
+         id: format!("{path}:0"),
+         path: Arc::new(PathBuf::from(path)),
+         hash: FileHash::sum(content),
+         chunk_hash: FileHash::sum(content),
+         content: content.into(),
+         start_line: 0,
+         end_line: 3,
+         chunk_index: Some(0),
+         is_anchor: Some(false),
+         chunk_type: Some(ChunkType::Function),
+         context_prev: None,
+         context_next: None,
+         vector,
+         colbert: vec![],
+         colbert_scale: 1.0,
+         indexed_at: Some(100),
+         crate_name: None,
+         bridges: bridge::collect(Path::new(path), content),
+         language: GrammarManager::language_for(Path::new(path), content).map(Str::from),
+      }
+   }
+
+   fn search_params<'a>(query: &'a [f32], colbert: &'a Array2<f32>) -> SearchParams<'a> {
+      SearchParams {
+         store_id:      "s",
+         query_text:    "config",
+         query_vector:  query,
+         query_colbert: colbert,
+         limit:         10,
+         path_filter:   None,
+         path_globs:    None,
+         indexed_after: None,
+         crate_name:    None,
+         rerank:        false,
+         rerank_depth:  None,
+         nprobes:       None,
+         exact:         false,
+         cancel:        None,
+      }
+   }
+
+   #[tokio::test]
+   async fn insert_search_persist_and_delete() {
+      let dir = TempDir::new().unwrap();
+      let store = FlatStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      assert!(store.is_empty("s").await.unwrap());
+      assert!(!store.exists("s"));
+
+      store
+         .insert_batch("s", vec![
+            record("/repo/a.rs", "fn connect_database() {}", 0),
+            record("/repo/b.rs", "fn parse_config() {}", 1),
+         ])
+         .await
+         .unwrap();
+
+      let mut query = vec![0.0; config::get().dense_vector_dim()];
+      query[1] = 1.0;
+      let colbert = Array2::zeros((0, 0));
+      let response = store.search(search_params(&query, &colbert)).await.unwrap();
+      assert_eq!(response.mode, VectorSearchMode::Exact);
+      assert_eq!(response.results.len(), 2);
+      assert_eq!(response.results[0].path, Path::new("/repo/b.rs"));
+      assert_eq!(response.results[0].language.as_deref(), Some("rust"));
+
+      // A fresh handle reads what the first one wrote.
+      let reopened = FlatStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      assert_eq!(reopened.row_count("s").await.unwrap(), 2);
+
+      reopened
+         .delete_file("s", Path::new("/repo/b.rs"))
+         .await
+         .unwrap();
+      assert_eq!(reopened.list_files("s").await.unwrap(), vec![IndexedFile {
+         path:       PathBuf::from("/repo/a.rs"),
+         indexed_at: Some(100),
+      }]);
+      let embeddings = reopened
+         .get_chunk_embeddings("s", &[PathBuf::from("/repo/a.rs")])
+         .await
+         .unwrap();
+      assert_eq!(embeddings[&FileHash::sum("fn connect_database() {}")].dense[0], 1.0);
+
+      reopened.delete_store("s").await.unwrap();
+      assert!(reopened.is_empty("s").await.unwrap());
+   }
+
+   #[tokio::test]
+   async fn keyword_and_bridge_lookups() {
+      let dir = TempDir::new().unwrap();
+      let store = FlatStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      store
+         .insert_batch("s", vec![
+            record("/repo/ffi.rs", "#[no_mangle]\npub extern \"C\" fn zs_init() {}", 0),
+            record("/repo/init.c", "int zs_init(void) {\n   return 0;\n}", 1),
+            record("/repo/config.rs", "fn parse_config() {}", 2),
+         ])
+         .await
+         .unwrap();
+
+      let response = store
+         .keyword_search(KeywordParams {
+            store_id:      "s",
+            query_text:    "config",
+            limit:         10,
+            path_filter:   None,
+            path_globs:    None,
+            indexed_after: None,
+            crate_name:    None,
+         })
+         .await
+         .unwrap();
+      assert_eq!(response.results.len(), 1);
+      assert_eq!(response.results[0].path, Path::new("/repo/config.rs"));
+
+      let found = store.find_bridged("s", "c:zs_init", 10).await.unwrap();
+      assert_eq!(found.len(), 2);
+   }
+}
@@ -2,38 +2,69 @@
 //! migration.
 
 use std::{
+   borrow::Cow,
    collections::{HashMap, HashSet, hash_map::Entry},
    fs,
    path::{Path, PathBuf},
    sync::Arc,
+   time::Instant,
 };
 
 use arrow_array::{
-   Array, BinaryArray, BooleanArray, FixedSizeListArray, Float32Array, Float64Array,
-   LargeBinaryArray, LargeStringArray, RecordBatch, RecordBatchReader, StringArray, UInt32Array,
+   Array, ArrayRef, BinaryArray, BooleanArray, FixedSizeListArray, Float32Array, Float64Array,
+   Int8Array, LargeBinaryArray, LargeStringArray, RecordBatch, RecordBatchReader, StringArray,
+   UInt8Array, UInt32Array, UInt64Array,
    builder::{
-      BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, LargeBinaryBuilder,
-      LargeStringBuilder, StringBuilder, UInt32Builder,
+      BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int8Builder,
+      LargeBinaryBuilder, LargeStringBuilder, StringBuilder, UInt8Builder, UInt32Builder,
+      UInt64Builder,
    },
 };
 use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use arrow_select::take::take_record_batch;
 use futures::TryStreamExt;
+use lance::index::vector::VectorIndexParams;
+use lance_index::{DatasetIndexExt, IndexType};
 use lancedb::{
-   Connection, Table, connect,
-   index::{Index, scalar::FullTextSearchQuery},
-   query::{ExecutableQuery, QueryBase, Select},
+   Connection, DistanceType, Table, connect,
+   index::{
+      Index,
+      scalar::FullTextSearchQuery,
+      vector::{IvfHnswPqIndexBuilder, IvfHnswSqIndexBuilder, IvfPqIndexBuilder},
+   },
+   query::{ExecutableQuery, QueryBase, Select, VectorQuery},
+   table::OptimizeAction,
 };
-use parking_lot::RwLock;
+use memmap2::Mmap;
+use parking_lot::{Mutex, RwLock};
 
 use crate::{
-   Str, config,
+   Str,
+   chunker::bridge,
+   config::{self, VectorIndexType, VectorMetric},
+   embed::HybridEmbedding,
    error::Result,
+   grammar::GrammarManager,
    meta::FileHash,
-   search::colbert::max_sim_quantized,
-   store,
-   types::{ChunkType, SearchResponse, SearchResult, SearchStatus, StoreInfo, VectorRecord},
+   search::{
+      colbert::max_sim_batch,
+      fusion::{self, Fusion},
+      glob::PathGlobs,
+      ranking,
+   },
+   store::{
+      self,
+      candidate::{self, Candidate},
+   },
+   types::{
+      ChunkType, IndexedFile, QueryRoute, ScoreBreakdown, SearchResponse, SearchResult,
+      SearchStage, SearchStatus, StageTiming, StoreInfo, VectorRecord, VectorSearchMode,
+   },
 };
 
+/// Columns read by every search, scanned during warm-up.
+const WARM_COLUMNS: &[&str] = &["vector", "path", "is_anchor"];
+
 /// Errors that can occur during `LanceDB` operations.
 #[derive(Debug, thiserror::Error)]
 pub enum StoreError {
@@ -49,11 +80,14 @@ pub enum StoreError {
    #[error("failed to create table: {0}")]
    CreateTable(#[source] lancedb::Error),
 
-   #[error("failed to sample table for migration check: {0}")]
-   SampleTableForMigration(#[source] lancedb::Error),
+   #[error("failed to read table schema: {0}")]
+   ReadSchema(#[source] lancedb::Error),
 
-   #[error("failed to read sample batch: {0}")]
-   ReadSampleBatch(#[source] lancedb::Error),
+   #[error(
+      "index uses schema v{found}, but this smgrep supports up to v{supported}; upgrade smgrep or \
+       run `smgrep clean`"
+   )]
+   SchemaTooNew { found: u32, supported: u32 },
 
    #[error("failed to read existing data for migration: {0}")]
    ReadExistingDataForMigration(#[source] lancedb::Error),
@@ -97,6 +131,18 @@ pub enum StoreError {
    #[error("failed to collect doc results: {0}")]
    CollectDocResults(#[source] lancedb::Error),
 
+   #[error("failed to execute keyword search: {0}")]
+   ExecuteKeywordSearch(#[source] lancedb::Error),
+
+   #[error("failed to collect keyword results: {0}")]
+   CollectKeywordResults(#[source] lancedb::Error),
+
+   #[error("failed to execute bridge lookup: {0}")]
+   ExecuteBridgeQuery(#[source] lancedb::Error),
+
+   #[error("failed to collect bridge lookup results: {0}")]
+   CollectBridgeResults(#[source] lancedb::Error),
+
    #[error("missing path column")]
    MissingPathColumn,
 
@@ -124,6 +170,15 @@ pub enum StoreError {
    #[error("failed to delete files: {0}")]
    DeleteFiles(#[source] lancedb::Error),
 
+   #[error("failed to delete chunks: {0}")]
+   DeleteChunks(#[source] lancedb::Error),
+
+   #[error("failed to replace chunks: {0}")]
+   ReplaceChunks(#[source] lancedb::Error),
+
+   #[error("chunk column type mismatch")]
+   ChunkColumnTypeMismatch,
+
    #[error("failed to drop table: {0}")]
    DropTable(#[source] lancedb::Error),
 
@@ -144,6 +199,21 @@ pub enum StoreError {
 
    #[error("failed to create vector index: {0}")]
    CreateVectorIndex(#[source] lancedb::Error),
+
+   #[error("failed to optimize table: {0}")]
+   Optimize(#[source] lancedb::Error),
+
+   #[error("failed to rewrite paths relative to the root: {0}")]
+   Relativize(#[source] lancedb::Error),
+
+   #[error(
+      "quantize_dense ranks by cosine distance, but vector_metric is \"{0}\"; set vector_metric = \
+       \"cosine\" or disable quantize_dense"
+   )]
+   QuantizedMetric(&'static str),
+
+   #[error("failed to warm up table: {0}")]
+   WarmUp(#[source] lancedb::Error),
 }
 
 /// Single-use [`RecordBatch`] iterator for `LanceDB` table creation.
@@ -195,11 +265,121 @@ impl RecordBatchReader for RecordBatchOnce {
    }
 }
 
+/// Current table layout version, stored in the Arrow schema metadata.
+///
+/// Bump this whenever columns are added, removed or change type, so existing
+/// tables are rewritten on open instead of failing with Arrow errors.
+pub const SCHEMA_VERSION: u32 = 7;
+
+const SCHEMA_VERSION_KEY: &str = "smgrep.schema_version";
+
+/// Columns a table must have for its rows to be carried over during a rewrite.
+const REQUIRED_COLUMNS: &[&str] = &["id", "path", "hash", "content", "start_line", "vector"];
+
+/// Action needed to bring an existing table up to [`SCHEMA_VERSION`].
+#[derive(Debug, PartialEq, Eq)]
+enum Migration {
+   /// Table already matches the current layout.
+   None,
+   /// Rows can be copied into a table with the current layout.
+   Rewrite,
+   /// Table must be dropped and re-indexed.
+   Rebuild(String),
+}
+
+/// Checks that quantized vectors are compared by cosine distance, the only
+/// metric that ignores the scale of each row. Only an explicit
+/// `vector_metric` can fail it, as quantizing defaults the metric to cosine.
+const fn check_quantized_metric(metric: VectorMetric) -> Result<(), StoreError> {
+   match metric {
+      VectorMetric::Cosine => Ok(()),
+      other => Err(StoreError::QuantizedMetric(other.as_str())),
+   }
+}
+
+const fn distance_type(metric: VectorMetric) -> DistanceType {
+   match metric {
+      VectorMetric::L2 => DistanceType::L2,
+      VectorMetric::Cosine => DistanceType::Cosine,
+      VectorMetric::Dot => DistanceType::Dot,
+   }
+}
+
+/// Quantizes a vector to `i8` symmetrically, returning the values and the
+/// scale that multiplies them back.
+fn quantize_vector(vector: &[f32]) -> (Vec<i8>, f32) {
+   let max = vector.iter().fold(0.0_f32, |max, x| max.max(x.abs()));
+   let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+   let values = vector
+      .iter()
+      .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+      .collect();
+   (values, scale)
+}
+
+/// Binary code of a vector: its sign bits, packed most significant first.
+fn binarize(vector: &[f32]) -> Vec<u8> {
+   let mut code = vec![0; vector.len().div_ceil(8)];
+   for (i, &x) in vector.iter().enumerate() {
+      if x > 0.0 {
+         code[i / 8] |= 0x80 >> (i % 8);
+      }
+   }
+   code
+}
+
+/// Dense vectors of a batch, read as `f32` whether they are stored that way
+/// or quantized.
+enum DenseVectors<'a> {
+   Float { values: &'a [f32], dim: usize },
+   Int8 { values: &'a [i8], scales: &'a Float32Array, dim: usize },
+}
+
+impl<'a> DenseVectors<'a> {
+   fn from_batch(batch: &'a RecordBatch) -> Result<Self, StoreError> {
+      let list = batch
+         .column_by_name("vector")
+         .and_then(|col| col.as_any().downcast_ref::<FixedSizeListArray>())
+         .ok_or(StoreError::VectorColumnTypeMismatch)?;
+      let dim = list.value_length() as usize;
+      let values = list.values().as_any();
+      if let Some(floats) = values.downcast_ref::<Float32Array>() {
+         return Ok(Self::Float { values: floats.values(), dim });
+      }
+      let ints = values
+         .downcast_ref::<Int8Array>()
+         .ok_or(StoreError::VectorValuesTypeMismatch)?;
+      let scales = batch
+         .column_by_name("vector_scale")
+         .and_then(|col| col.as_any().downcast_ref::<Float32Array>())
+         .ok_or(StoreError::VectorColumnTypeMismatch)?;
+      Ok(Self::Int8 { values: ints.values(), scales, dim })
+   }
+
+   fn get(&self, row: usize) -> Cow<'a, [f32]> {
+      match *self {
+         Self::Float { values, dim } => Cow::Borrowed(&values[row * dim..(row + 1) * dim]),
+         Self::Int8 { values, scales, dim } => {
+            let scale = scales.value(row);
+            Cow::Owned(
+               values[row * dim..(row + 1) * dim]
+                  .iter()
+                  .map(|&x| f32::from(x) * scale)
+                  .collect(),
+            )
+         },
+      }
+   }
+}
+
 /// `LanceDB` implementation of [`Store`](super::Store) with connection pooling
 /// and automatic migration.
 pub struct LanceStore {
    connections: RwLock<HashMap<String, Arc<Connection>>>,
    data_dir:    PathBuf,
+   /// Memory-locked mappings of each store's files, kept for as long as the
+   /// store should stay resident.
+   resident:    Mutex<HashMap<String, Vec<Mmap>>>,
 }
 
 impl LanceStore {
@@ -208,7 +388,74 @@ impl LanceStore {
       let data_dir = config::data_dir();
       fs::create_dir_all(data_dir)?;
 
-      Ok(Self { connections: RwLock::new(HashMap::new()), data_dir: data_dir.clone() })
+      Ok(Self {
+         connections: RwLock::new(HashMap::new()),
+         data_dir:    data_dir.clone(),
+         resident:    Mutex::new(HashMap::new()),
+      })
+   }
+
+   /// Maps the files of a store and locks them in memory if the store takes
+   /// at most `limit` bytes, replacing any earlier locks for it.
+   #[cfg(unix)]
+   fn lock_resident(&self, store_id: &str, limit: u64) -> Result<()> {
+      let dir = self.data_dir.join(store_id);
+      let mut resident = self.resident.lock();
+      resident.remove(store_id);
+
+      let size = crate::util::get_dir_size(&dir)?;
+      if size > limit {
+         tracing::info!(
+            "not locking {store_id} in memory: {} exceeds lock_index_max_mb",
+            crate::util::format_size(size)
+         );
+         return Ok(());
+      }
+
+      let mut maps = Vec::new();
+      for entry in walkdir::WalkDir::new(&dir)
+         .into_iter()
+         .filter_map(|e| e.ok())
+         .filter(|e| e.file_type().is_file())
+      {
+         let file = fs::File::open(entry.path())?;
+         if file.metadata()?.len() == 0 {
+            continue;
+         }
+         // SAFETY: Lance writes each data, index and manifest file once and
+         // never modifies it in place, so the mapped bytes cannot change
+         // underneath us. Files removed by compaction stay mapped until the
+         // next warm-up replaces these mappings.
+         let map = unsafe { Mmap::map(&file)? };
+         if let Err(e) = map.lock() {
+            tracing::warn!(
+               "cannot lock {store_id} in memory: {e}; raise the memlock limit (ulimit -l)"
+            );
+            return Ok(());
+         }
+         maps.push(map);
+      }
+
+      resident.insert(store_id.to_string(), maps);
+      tracing::info!("locked {store_id} in memory ({})", crate::util::format_size(size));
+      Ok(())
+   }
+
+   /// Mapped files cannot be deleted on Windows, which would block
+   /// compaction, so locking is Unix-only.
+   #[cfg(not(unix))]
+   fn lock_resident(&self, store_id: &str, _limit: u64) -> Result<()> {
+      tracing::warn!("not locking {store_id} in memory: only supported on Unix");
+      Ok(())
+   }
+
+   /// Whether the store's table exists on disk, without creating it.
+   pub fn has_table(&self, store_id: &str) -> bool {
+      self
+         .data_dir
+         .join(store_id)
+         .join(format!("{store_id}.lance"))
+         .exists()
    }
 
    async fn get_connection(&self, store_id: &str) -> Result<Arc<Connection>> {
@@ -267,33 +514,33 @@ impl LanceStore {
       store_id: &str,
       table: &Table,
    ) -> Result<()> {
-      let mut stream = table
-         .query()
-         .limit(1)
-         .execute()
-         .await
-         .map_err(StoreError::SampleTableForMigration)?;
+      let schema = table.schema().await.map_err(StoreError::ReadSchema)?;
 
-      let sample_batch = match stream.try_next().await {
-         Ok(Some(batch)) => batch,
-         Ok(None) => return Ok(()),
-         Err(e) => {
-            return Err(StoreError::ReadSampleBatch(e).into());
+      let cfg = config::get();
+      match Self::plan_migration(&schema, cfg.dense_vector_dim(), cfg.quantize_dense)? {
+         Migration::None => return Ok(()),
+         Migration::Rewrite => {
+            tracing::info!(
+               "rewriting index {store_id} from schema v{} to v{SCHEMA_VERSION}",
+               Self::schema_version(&schema)
+            );
+         },
+         Migration::Rebuild(reason) => {
+            tracing::warn!(
+               "index {store_id} cannot be upgraded ({reason}); rebuilding from scratch"
+            );
+            conn
+               .drop_table(store_id, &[])
+               .await
+               .map_err(StoreError::DropOldTableDuringMigration)?;
+            let schema = Self::create_schema();
+            conn
+               .create_table(store_id, RecordBatchOnce::new(Self::create_empty_batch(&schema)?))
+               .execute()
+               .await
+               .map_err(StoreError::CreateNewTableDuringMigration)?;
+            return Ok(());
          },
-      };
-
-      let Some(vector_col) = sample_batch.column_by_name("vector") else {
-         return Ok(());
-      };
-
-      let Some(vector_list) = vector_col.as_any().downcast_ref::<FixedSizeListArray>() else {
-         return Ok(());
-      };
-
-      let sample_vector_len = vector_list.value_length() as usize;
-
-      if sample_vector_len == config::get().dense_dim {
-         return Ok(());
       }
 
       let mut all_stream = table
@@ -350,9 +597,7 @@ impl LanceStore {
             let end_line_col = batch
                .column_by_name("end_line")
                .and_then(|col| col.as_any().downcast_ref::<UInt32Array>());
-            let vector_col = batch.column_by_name("vector");
-            let vector_list =
-               vector_col.and_then(|col| col.as_any().downcast_ref::<FixedSizeListArray>());
+            let vectors = DenseVectors::from_batch(&batch)?;
             let colbert_col = batch
                .column_by_name("colbert")
                .and_then(|col| col.as_any().downcast_ref::<LargeBinaryArray>());
@@ -374,6 +619,21 @@ impl LanceStore {
             let context_next_col = batch
                .column_by_name("context_next")
                .and_then(|col| col.as_any().downcast_ref::<StringArray>());
+            let indexed_at_col = batch
+               .column_by_name("indexed_at")
+               .and_then(|col| col.as_any().downcast_ref::<UInt64Array>());
+            let crate_name_col = batch
+               .column_by_name("crate_name")
+               .and_then(|col| col.as_any().downcast_ref::<StringArray>());
+            let chunk_hash_col = batch
+               .column_by_name("chunk_hash")
+               .and_then(|col| col.as_any().downcast_ref::<BinaryArray>());
+            let bridges_col = batch
+               .column_by_name("bridges")
+               .and_then(|col| col.as_any().downcast_ref::<StringArray>());
+            let language_col = batch
+               .column_by_name("language")
+               .and_then(|col| col.as_any().downcast_ref::<StringArray>());
 
             for row_idx in 0..batch.num_rows() {
                let id = id_col
@@ -398,19 +658,7 @@ impl LanceStore {
 
                let end_line = end_line_col.map_or(start_line, |arr| arr.value(row_idx));
 
-               let old_vector_values = vector_list
-                  .expect("vector column must exist during migration")
-                  .value(row_idx);
-               let old_vector_floats = old_vector_values
-                  .as_any()
-                  .downcast_ref::<Float32Array>()
-                  .expect("vector values must be Float32Array");
-
-               let old_vec: Vec<f32> = (0..old_vector_floats.len())
-                  .map(|i| old_vector_floats.value(i))
-                  .collect();
-
-               let new_vector = Self::normalize_vector(&old_vec);
+               let new_vector = Self::normalize_vector(&vectors.get(row_idx));
 
                let colbert = if let Some(col) = colbert_col
                   && !col.is_null(row_idx)
@@ -468,10 +716,50 @@ impl LanceStore {
                   None
                };
 
+               let indexed_at = if let Some(col) = indexed_at_col
+                  && !col.is_null(row_idx)
+               {
+                  Some(col.value(row_idx))
+               } else {
+                  None
+               };
+
+               let crate_name: Option<Str> = if let Some(col) = crate_name_col
+                  && !col.is_null(row_idx)
+               {
+                  Some(Str::copy_from_str(col.value(row_idx)))
+               } else {
+                  None
+               };
+
+               let chunk_hash = chunk_hash_col
+                  .filter(|col| !col.is_null(row_idx))
+                  .and_then(|col| FileHash::from_slice(col.value(row_idx)))
+                  .unwrap_or_else(|| FileHash::sum(content.as_bytes()));
+
+               // Rows written before bridges were recorded get them from
+               // their content.
+               let bridges: Option<Str> = if let Some(col) = bridges_col {
+                  (!col.is_null(row_idx)).then(|| Str::copy_from_str(col.value(row_idx)))
+               } else if is_anchor == Some(true) {
+                  None
+               } else {
+                  bridge::collect(&path, &content)
+               };
+
+               // Rows written before languages were recorded get them from
+               // their path.
+               let language: Option<Str> = if let Some(col) = language_col {
+                  (!col.is_null(row_idx)).then(|| Str::copy_from_str(col.value(row_idx)))
+               } else {
+                  GrammarManager::language_for(&path, "").map(Str::from)
+               };
+
                migrated_records.push(VectorRecord {
                   id,
                   path: std::sync::Arc::new(path),
                   hash,
+                  chunk_hash,
                   content,
                   start_line,
                   end_line,
@@ -483,6 +771,10 @@ impl LanceStore {
                   chunk_type,
                   context_prev,
                   context_next,
+                  indexed_at,
+                  crate_name,
+                  bridges,
+                  language,
                });
             }
          }
@@ -500,8 +792,84 @@ impl LanceStore {
       Ok(())
    }
 
+   /// Reads the layout version stamped into a table's schema metadata.
+   ///
+   /// Tables created before versioning was introduced report version 0.
+   fn schema_version(schema: &Schema) -> u32 {
+      schema
+         .metadata()
+         .get(SCHEMA_VERSION_KEY)
+         .and_then(|v| v.parse().ok())
+         .unwrap_or(0)
+   }
+
+   /// Decides how to bring an existing table up to the current layout.
+   fn plan_migration(
+      schema: &Schema,
+      dense_dim: usize,
+      quantized: bool,
+   ) -> Result<Migration, StoreError> {
+      let version = Self::schema_version(schema);
+      if version > SCHEMA_VERSION {
+         return Err(StoreError::SchemaTooNew { found: version, supported: SCHEMA_VERSION });
+      }
+
+      if let Some(missing) = REQUIRED_COLUMNS
+         .iter()
+         .find(|name| schema.field_with_name(name).is_err())
+      {
+         return Ok(Migration::Rebuild(format!("missing column `{missing}`")));
+      }
+
+      let DataType::FixedSizeList(_, dim) = schema.field_with_name("vector").unwrap().data_type()
+      else {
+         return Ok(Migration::Rebuild("vector column is not a fixed-size list".to_string()));
+      };
+
+      if version < SCHEMA_VERSION
+         || *dim as usize != dense_dim
+         || Self::is_quantized(schema) != quantized
+      {
+         Ok(Migration::Rewrite)
+      } else {
+         Ok(Migration::None)
+      }
+   }
+
+   /// Whether a table stores its dense vectors as `i8`, with their scales
+   /// in `vector_scale`.
+   fn is_quantized(schema: &Schema) -> bool {
+      matches!(
+         schema.field_with_name("vector").map(Field::data_type),
+         Ok(DataType::FixedSizeList(item, _)) if *item.data_type() == DataType::Int8
+      )
+   }
+
+   async fn is_quantized_table(table: &Table) -> Result<bool, StoreError> {
+      let schema = table.schema().await.map_err(StoreError::ReadSchema)?;
+      Ok(Self::is_quantized(&schema))
+   }
+
+   /// `columns` plus `vector_scale` for tables with quantized vectors.
+   fn with_vector_scale<'a>(columns: &[&'a str], quantized: bool) -> Vec<&'a str> {
+      let mut columns = columns.to_vec();
+      if quantized {
+         columns.push("vector_scale");
+      }
+      columns
+   }
+
+   fn vector_item(quantized: bool) -> Arc<Field> {
+      let item = if quantized {
+         DataType::Int8
+      } else {
+         DataType::Float32
+      };
+      Arc::new(Field::new("item", item, true))
+   }
+
    fn normalize_vector(old_vector: &[f32]) -> Vec<f32> {
-      let dim = config::get().dense_dim;
+      let dim = config::get().dense_vector_dim();
       let mut new_vector = vec![0.0; dim];
       let copy_len = old_vector.len().min(dim);
       new_vector[..copy_len].copy_from_slice(&old_vector[..copy_len]);
@@ -509,7 +877,9 @@ impl LanceStore {
    }
 
    fn create_schema() -> Arc<Schema> {
-      Arc::new(Schema::new(vec![
+      let cfg = config::get();
+      let metadata = HashMap::from([(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string())]);
+      let mut fields = vec![
          Field::new("id", DataType::Utf8, false),
          Field::new("path", DataType::Utf8, false),
          Field::new("hash", DataType::Binary, false),
@@ -519,8 +889,8 @@ impl LanceStore {
          Field::new(
             "vector",
             DataType::FixedSizeList(
-               Arc::new(Field::new("item", DataType::Float32, true)),
-               config::get().dense_dim as i32,
+               Self::vector_item(cfg.quantize_dense),
+               cfg.dense_vector_dim() as i32,
             ),
             false,
          ),
@@ -531,7 +901,24 @@ impl LanceStore {
          Field::new("chunk_type", DataType::Utf8, true),
          Field::new("context_prev", DataType::Utf8, true),
          Field::new("context_next", DataType::Utf8, true),
-      ]))
+         Field::new("indexed_at", DataType::UInt64, true),
+         Field::new("crate_name", DataType::Utf8, true),
+         Field::new("chunk_hash", DataType::Binary, false),
+         Field::new("bridges", DataType::Utf8, true),
+         Field::new("language", DataType::Utf8, true),
+         Field::new(
+            "vector_bits",
+            DataType::FixedSizeList(
+               Arc::new(Field::new("item", DataType::UInt8, true)),
+               cfg.dense_vector_dim().div_ceil(8) as i32,
+            ),
+            false,
+         ),
+      ];
+      if cfg.quantize_dense {
+         fields.push(Field::new("vector_scale", DataType::Float32, false));
+      }
+      Arc::new(Schema::new_with_metadata(fields, metadata))
    }
 
    fn create_empty_batch(schema: &Arc<Schema>) -> Result<RecordBatch> {
@@ -542,11 +929,16 @@ impl LanceStore {
       let start_line_array = UInt32Builder::new().finish();
       let end_line_array = UInt32Builder::new().finish();
 
-      let vector_values = Float32Builder::new().finish();
+      let quantized = Self::is_quantized(schema);
+      let vector_values: ArrayRef = if quantized {
+         Arc::new(Int8Builder::new().finish())
+      } else {
+         Arc::new(Float32Builder::new().finish())
+      };
       let vector_array = FixedSizeListArray::new(
-         Arc::new(Field::new("item", DataType::Float32, true)),
-         config::get().dense_dim as i32,
-         Arc::new(vector_values),
+         Self::vector_item(quantized),
+         config::get().dense_vector_dim() as i32,
+         vector_values,
          None,
       );
 
@@ -557,8 +949,19 @@ impl LanceStore {
       let chunk_type_array = StringBuilder::new().finish();
       let context_prev_array = StringBuilder::new().finish();
       let context_next_array = StringBuilder::new().finish();
+      let indexed_at_array = UInt64Builder::new().finish();
+      let crate_name_array = StringBuilder::new().finish();
+      let chunk_hash_array = BinaryBuilder::new().finish();
+      let bridges_array = StringBuilder::new().finish();
+      let language_array = StringBuilder::new().finish();
+      let vector_bits_array = FixedSizeListArray::new(
+         Arc::new(Field::new("item", DataType::UInt8, true)),
+         config::get().dense_vector_dim().div_ceil(8) as i32,
+         Arc::new(UInt8Builder::new().finish()),
+         None,
+      );
 
-      Ok(RecordBatch::try_new(schema.clone(), vec![
+      let mut columns: Vec<ArrayRef> = vec![
          Arc::new(id_array),
          Arc::new(path_array),
          Arc::new(hash_array),
@@ -573,8 +976,17 @@ impl LanceStore {
          Arc::new(chunk_type_array),
          Arc::new(context_prev_array),
          Arc::new(context_next_array),
-      ])
-      .map_err(StoreError::CreateEmptyBatch)?)
+         Arc::new(indexed_at_array),
+         Arc::new(crate_name_array),
+         Arc::new(chunk_hash_array),
+         Arc::new(bridges_array),
+         Arc::new(language_array),
+         Arc::new(vector_bits_array),
+      ];
+      if quantized {
+         columns.push(Arc::new(Float32Builder::new().finish()));
+      }
+      Ok(RecordBatch::try_new(schema.clone(), columns).map_err(StoreError::CreateEmptyBatch)?)
    }
 
    fn records_to_batch(records: Vec<VectorRecord>) -> Result<RecordBatch> {
@@ -583,6 +995,9 @@ impl LanceStore {
       }
 
       let cfg = config::get();
+      if cfg.quantize_dense {
+         check_quantized_metric(cfg.vector_metric())?;
+      }
       let schema = Self::create_schema();
       let _len = records.len();
 
@@ -593,6 +1008,9 @@ impl LanceStore {
       let mut start_line_builder = UInt32Builder::new();
       let mut end_line_builder = UInt32Builder::new();
       let mut vector_builder = Float32Builder::new();
+      let mut quantized_builder = Int8Builder::new();
+      let mut vector_scale_builder = Float32Builder::new();
+      let mut vector_bits_builder = UInt8Builder::new();
       let mut colbert_builder = LargeBinaryBuilder::new();
       let mut colbert_scale_builder = Float64Builder::new();
       let mut chunk_index_builder = UInt32Builder::new();
@@ -600,8 +1018,13 @@ impl LanceStore {
       let mut chunk_type_builder = StringBuilder::new();
       let mut context_prev_builder = StringBuilder::new();
       let mut context_next_builder = StringBuilder::new();
+      let mut indexed_at_builder = UInt64Builder::new();
+      let mut crate_name_builder = StringBuilder::new();
+      let mut chunk_hash_builder = BinaryBuilder::new();
+      let mut bridges_builder = StringBuilder::new();
+      let mut language_builder = StringBuilder::new();
 
-      let dim = cfg.dense_dim;
+      let dim = cfg.dense_vector_dim();
       for record in records {
          id_builder.append_value(&record.id);
          path_builder.append_value(store::path_to_store_value(&record.path));
@@ -614,8 +1037,13 @@ impl LanceStore {
             return Err(StoreError::VectorColumnTypeMismatch.into());
          }
 
-         for &val in &record.vector {
-            vector_builder.append_value(val);
+         vector_bits_builder.append_slice(&binarize(&record.vector));
+         if cfg.quantize_dense {
+            let (values, scale) = quantize_vector(&record.vector);
+            quantized_builder.append_slice(&values);
+            vector_scale_builder.append_value(scale);
+         } else {
+            vector_builder.append_slice(&record.vector);
          }
 
          colbert_builder.append_value(&record.colbert);
@@ -642,6 +1070,8 @@ impl LanceStore {
                ChunkType::TypeAlias => "type_alias",
                ChunkType::Block => "block",
                ChunkType::Other => "other",
+               ChunkType::Doc => "doc",
+               ChunkType::Module => "module",
             };
             chunk_type_builder.append_value(chunk_type_str);
          } else {
@@ -659,6 +1089,12 @@ impl LanceStore {
          } else {
             context_next_builder.append_null();
          }
+
+         indexed_at_builder.append_option(record.indexed_at);
+         crate_name_builder.append_option(record.crate_name.as_deref());
+         chunk_hash_builder.append_value(record.chunk_hash);
+         bridges_builder.append_option(record.bridges.as_deref());
+         language_builder.append_option(record.language.as_deref());
       }
 
       let id_array = id_builder.finish();
@@ -668,11 +1104,15 @@ impl LanceStore {
       let start_line_array = start_line_builder.finish();
       let end_line_array = end_line_builder.finish();
 
-      let vector_values_array = vector_builder.finish();
+      let vector_values_array: ArrayRef = if cfg.quantize_dense {
+         Arc::new(quantized_builder.finish())
+      } else {
+         Arc::new(vector_builder.finish())
+      };
       let vector_array = FixedSizeListArray::new(
-         Arc::new(Field::new("item", DataType::Float32, true)),
+         Self::vector_item(cfg.quantize_dense),
          dim as i32,
-         Arc::new(vector_values_array),
+         vector_values_array,
          None,
       );
 
@@ -683,8 +1123,19 @@ impl LanceStore {
       let chunk_type_array = chunk_type_builder.finish();
       let context_prev_array = context_prev_builder.finish();
       let context_next_array = context_next_builder.finish();
+      let indexed_at_array = indexed_at_builder.finish();
+      let crate_name_array = crate_name_builder.finish();
+      let chunk_hash_array = chunk_hash_builder.finish();
+      let bridges_array = bridges_builder.finish();
+      let language_array = language_builder.finish();
+      let vector_bits_array = FixedSizeListArray::new(
+         Arc::new(Field::new("item", DataType::UInt8, true)),
+         dim.div_ceil(8) as i32,
+         Arc::new(vector_bits_builder.finish()),
+         None,
+      );
 
-      Ok(RecordBatch::try_new(schema, vec![
+      let mut columns: Vec<ArrayRef> = vec![
          Arc::new(id_array),
          Arc::new(path_array),
          Arc::new(hash_array),
@@ -699,8 +1150,17 @@ impl LanceStore {
          Arc::new(chunk_type_array),
          Arc::new(context_prev_array),
          Arc::new(context_next_array),
-      ])
-      .map_err(StoreError::CreateRecordBatch)?)
+         Arc::new(indexed_at_array),
+         Arc::new(crate_name_array),
+         Arc::new(chunk_hash_array),
+         Arc::new(bridges_array),
+         Arc::new(language_array),
+         Arc::new(vector_bits_array),
+      ];
+      if cfg.quantize_dense {
+         columns.push(Arc::new(vector_scale_builder.finish()));
+      }
+      Ok(RecordBatch::try_new(schema, columns).map_err(StoreError::CreateRecordBatch)?)
    }
 
    fn parse_chunk_type(s: &str) -> ChunkType {
@@ -711,10 +1171,260 @@ impl LanceStore {
          "method" => ChunkType::Method,
          "type_alias" => ChunkType::TypeAlias,
          "block" => ChunkType::Block,
+         "doc" => ChunkType::Doc,
+         "module" => ChunkType::Module,
          _ => ChunkType::Other,
       }
    }
 
+   /// Builds the filter shared by searches over non-anchor rows.
+   fn search_filter(
+      path_filter: Option<&Path>,
+      path_globs: Option<&PathGlobs>,
+      indexed_after: Option<u64>,
+      crate_name: Option<&str>,
+   ) -> String {
+      let mut filter = "(is_anchor IS NULL OR is_anchor = false)".to_owned();
+      // Applied in the filter, so the vector limit is taken over matching rows.
+      if let Some(globs) = path_globs.and_then(PathGlobs::like_predicate) {
+         filter = format!("{globs} AND {filter}");
+      }
+      if let Some(ts) = indexed_after {
+         filter = format!("indexed_at >= {ts} AND {filter}");
+      }
+      if let Some(name) = crate_name {
+         let name = name.replace('\'', "''");
+         filter = format!("crate_name = '{name}' AND {filter}");
+      }
+      if let Some(path) = path_filter {
+         let prefix = store::escape_path_for_like(path);
+         filter = format!("path LIKE '{prefix}%' AND {filter}");
+      }
+      filter
+   }
+
+   /// Reads the row at `row` of a search batch as a [`Candidate`] without its
+   /// embeddings.
+   fn candidate_from_row(batch: &RecordBatch, row: usize) -> Result<Candidate> {
+      let string = |name: &str| -> Option<String> {
+         let col = batch.column_by_name(name)?;
+         if col.is_null(row) {
+            return None;
+         }
+         if let Some(arr) = col.as_any().downcast_ref::<StringArray>() {
+            Some(arr.value(row).to_string())
+         } else {
+            col.as_any()
+               .downcast_ref::<LargeStringArray>()
+               .map(|arr| arr.value(row).to_string())
+         }
+      };
+      let line = |name: &str| {
+         batch
+            .column_by_name(name)
+            .and_then(|col| col.as_any().downcast_ref::<UInt32Array>())
+            .map_or(0, |arr| arr.value(row))
+      };
+
+      Ok(Candidate {
+         path:          string("path").ok_or(StoreError::MissingPathColumn)?.into(),
+         content:       string("content").ok_or(StoreError::ContentColumnTypeMismatch)?,
+         start_line:    line("start_line"),
+         end_line:      line("end_line"),
+         vector:        Vec::new(),
+         colbert:       None,
+         colbert_scale: 1.0,
+         is_anchor:     batch
+            .column_by_name("is_anchor")
+            .filter(|col| !col.is_null(row))
+            .and_then(|col| col.as_any().downcast_ref::<BooleanArray>())
+            .map(|arr| arr.value(row)),
+         chunk_type:    string("chunk_type").map(|s| Self::parse_chunk_type(&s)),
+         context_prev:  string("context_prev"),
+         context_next:  string("context_next"),
+         fts:           None,
+         bridges:       string("bridges"),
+         language:      string("language"),
+         score:         0.0,
+      })
+   }
+
+   /// Checks whether `table` has an index covering `column`.
+   async fn has_index(table: &Table, column: &str) -> bool {
+      table.list_indices().await.is_ok_and(|indices| {
+         indices
+            .iter()
+            .any(|i| i.columns.iter().any(|c| c == column))
+      })
+   }
+
+   /// Computes query similarity against the anchor chunk of each distinct
+   /// file in `results`.
+   async fn anchor_similarities(
+      table: &Table,
+      results: &[SearchResult],
+      query_vector: &[f32],
+      quantized: bool,
+   ) -> Result<HashMap<PathBuf, f32>> {
+      let escaped: HashSet<String> = results
+         .iter()
+         .map(|r| format!("'{}'", store::escape_path_literal(&r.path)))
+         .collect();
+      let escaped: Vec<String> = escaped.into_iter().collect();
+      let predicate = format!("is_anchor = true AND path IN ({})", escaped.join(","));
+
+      let batches: Vec<RecordBatch> = table
+         .query()
+         .only_if(predicate)
+         .select(Select::columns(&Self::with_vector_scale(&["path", "vector"], quantized)))
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteQuery)?
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectResults)?;
+
+      let mut sims = HashMap::with_capacity(escaped.len());
+      for batch in &batches {
+         let path_col = batch
+            .column_by_name("path")
+            .ok_or(StoreError::MissingPathColumn)?
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or(StoreError::PathColumnTypeMismatch)?;
+         let vectors = DenseVectors::from_batch(batch)?;
+
+         for i in 0..batch.num_rows() {
+            if path_col.is_null(i) {
+               continue;
+            }
+            let sim = Self::cosine_similarity(query_vector, &vectors.get(i));
+            sims.insert(PathBuf::from(path_col.value(i)), sim);
+         }
+      }
+
+      Ok(sims)
+   }
+
+   /// Error for tables without a Lance dataset behind them, which quantized
+   /// vectors need for what the `LanceDB` API does not cover on `i8` columns.
+   fn not_local() -> lancedb::Error {
+      lancedb::Error::NotSupported {
+         message: "quantized vectors require a local table".to_string(),
+      }
+   }
+
+   /// Trains an `IVF_PQ` index by cosine distance on the `i8` vectors of a
+   /// quantized table, whatever `vector_index` is set to. `LanceDB` only
+   /// builds vector indexes on float columns, so it is created through Lance,
+   /// which trains on the vectors widened to `f32`.
+   async fn create_quantized_index(table: &Table, num_partitions: u32) -> lancedb::Result<()> {
+      let dim = config::get().dense_vector_dim();
+      // Same sub-vector count LanceDB picks for float vectors
+      let num_sub_vectors = if dim.is_multiple_of(16) {
+         dim / 16
+      } else if dim.is_multiple_of(8) {
+         dim / 8
+      } else {
+         1
+      };
+      let params = VectorIndexParams::ivf_pq(
+         num_partitions as usize,
+         8,
+         num_sub_vectors,
+         DistanceType::Cosine.into(),
+         50,
+      );
+      table
+         .dataset()
+         .ok_or_else(Self::not_local)?
+         .get_mut()
+         .await?
+         .create_index(&["vector"], IndexType::Vector, None, &params, true)
+         .await?;
+      Ok(())
+   }
+
+   /// Nearest rows to a quantized `query` among those matching `filter`,
+   /// through the ANN index when the table has one and `exact` is unset. The
+   /// query builder only passes float queries, which Lance refuses for `i8`
+   /// columns, so the dataset is searched directly. Cosine distance ignores
+   /// the scale of each row and ranks the unit vectors as their `f32`
+   /// originals would.
+   async fn quantized_search(
+      table: &Table,
+      query: &Int8Array,
+      filter: &str,
+      limit: usize,
+      nprobes: usize,
+      exact: bool,
+   ) -> lancedb::Result<Vec<RecordBatch>> {
+      let dataset = table.dataset().ok_or_else(Self::not_local)?;
+      let mut scanner = dataset.get().await?.scan();
+      scanner
+         .prefilter(true)
+         .filter(filter)?
+         .nearest("vector", query, limit)?
+         .distance_metric(DistanceType::Cosine.into())
+         .nprobs(nprobes)
+         .use_index(!exact);
+      let batches = scanner.try_into_stream().await?.try_collect().await?;
+      Ok(batches)
+   }
+
+   /// Two-stage search over tables without an ANN index. The rows nearest
+   /// by hamming distance between binary codes, `binary_prefilter_oversample`
+   /// times as many as needed, are ranked by their full vectors and the best
+   /// `limit` kept.
+   async fn binary_search(
+      table: &Table,
+      query: &[f32],
+      filter: &str,
+      limit: usize,
+   ) -> Result<Vec<RecordBatch>, StoreError> {
+      let oversample = config::get().binary_prefilter_oversample.max(1);
+      let code: ArrayRef = Arc::new(UInt8Array::from(binarize(query)));
+      let batches: Vec<RecordBatch> = table
+         .query()
+         .nearest_to(code)
+         .map_err(StoreError::CreateVectorQuery)?
+         .column("vector_bits")
+         .distance_type(DistanceType::Hamming)
+         .limit(limit * oversample)
+         .only_if(filter)
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteQuery)?
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectResults)?;
+
+      let mut scored = Vec::new();
+      for (batch_idx, batch) in batches.iter().enumerate() {
+         let vectors = DenseVectors::from_batch(batch)?;
+         for row in 0..batch.num_rows() {
+            let score = Self::cosine_similarity(query, &vectors.get(row));
+            scored.push((score, batch_idx, row as u32));
+         }
+      }
+      scored.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+      scored.truncate(limit);
+
+      let mut rows = vec![Vec::new(); batches.len()];
+      for (_, batch_idx, row) in scored {
+         rows[batch_idx].push(row);
+      }
+      batches
+         .iter()
+         .zip(rows)
+         .filter(|(_, rows)| !rows.is_empty())
+         .map(|(batch, rows)| {
+            take_record_batch(batch, &UInt32Array::from(rows))
+               .map_err(StoreError::CreateRecordBatch)
+         })
+         .collect()
+   }
+
    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
       debug_assert_eq!(a.len(), b.len(), "cosine_similarity requires equal-length vectors");
       let len = a.len().min(b.len());
@@ -724,6 +1434,84 @@ impl LanceStore {
       }
       dot
    }
+
+   /// Reads the [`store::chunk_fingerprint`] of every chunk stored for
+   /// `file_paths`, keyed by chunk id.
+   async fn chunk_fingerprints(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<String, FileHash>> {
+      const BATCH_SIZE: usize = 900;
+
+      let mut fingerprints = HashMap::new();
+      if file_paths.is_empty() || !self.has_table(store_id) {
+         return Ok(fingerprints);
+      }
+      let table = self.get_table(store_id).await?;
+      let columns = Self::with_vector_scale(
+         &["chunk_hash", "vector", "colbert", "colbert_scale"],
+         Self::is_quantized_table(&table).await?,
+      );
+
+      for chunk in file_paths.chunks(BATCH_SIZE) {
+         let escaped: Vec<String> = chunk
+            .iter()
+            .map(|p| format!("'{}'", store::escape_path_literal(p)))
+            .collect();
+
+         let batches: Vec<RecordBatch> = table
+            .query()
+            .only_if(format!("path IN ({})", escaped.join(",")))
+            .select(Select::columns(&[
+               "id",
+               "chunk_hash",
+               "start_line",
+               "end_line",
+               "context_prev",
+               "context_next",
+            ]))
+            .execute()
+            .await
+            .map_err(StoreError::ExecuteQuery)?
+            .try_collect()
+            .await
+            .map_err(StoreError::CollectResults)?;
+
+         for batch in batches {
+            let column = |name: &str| batch.column_by_name(name).map(|c| c.as_any());
+            let (Some(id), Some(hash), Some(start), Some(end), Some(prev), Some(next)) = (
+               column("id").and_then(|c| c.downcast_ref::<StringArray>()),
+               column("chunk_hash").and_then(|c| c.downcast_ref::<BinaryArray>()),
+               column("start_line").and_then(|c| c.downcast_ref::<UInt32Array>()),
+               column("end_line").and_then(|c| c.downcast_ref::<UInt32Array>()),
+               column("context_prev").and_then(|c| c.downcast_ref::<StringArray>()),
+               column("context_next").and_then(|c| c.downcast_ref::<StringArray>()),
+            ) else {
+               return Err(StoreError::ChunkColumnTypeMismatch.into());
+            };
+
+            let text = |array: &StringArray, row: usize| {
+               (!array.is_null(row)).then(|| array.value(row).to_string())
+            };
+            for row in 0..batch.num_rows() {
+               let Some(chunk_hash) = FileHash::from_slice(hash.value(row)) else {
+                  continue;
+               };
+               let fingerprint = store::chunk_fingerprint(
+                  &chunk_hash,
+                  start.value(row),
+                  end.value(row),
+                  text(prev, row).as_deref(),
+                  text(next, row).as_deref(),
+               );
+               fingerprints.insert(id.value(row).to_string(), fingerprint);
+            }
+         }
+      }
+
+      Ok(fingerprints)
+   }
 }
 
 impl Default for LanceStore {
@@ -757,33 +1545,91 @@ impl super::Store for LanceStore {
             results:  vec![],
             status:   SearchStatus::Ready,
             progress: None,
+            mode:     VectorSearchMode::Exact,
+            route:    QueryRoute::Semantic,
+            stages:   Vec::new(),
          });
       };
 
-      let anchor_filter = "(is_anchor IS NULL OR is_anchor = false)";
-      let doc_clause =
-         "(path LIKE '%.md' OR path LIKE '%.mdx' OR path LIKE '%.txt' OR path LIKE '%.json')";
+      let doc_clause = "(path LIKE '%.md' OR path LIKE '%.mdx' OR path LIKE '%.txt' OR path LIKE \
+                        '%.json' OR COALESCE(chunk_type, '') = 'doc')";
       let code_clause = format!("NOT {doc_clause}");
 
-      let mut code_filter = format!("{code_clause} AND {anchor_filter}");
-      let mut doc_filter = format!("{doc_clause} AND {anchor_filter}");
-      let base_filter = if let Some(filter) = params.path_filter {
-         let filter_str = store::escape_path_for_like(filter);
-         let path_clause = format!("path LIKE '{filter_str}%'");
-         code_filter = format!("{path_clause} AND {code_clause} AND {anchor_filter}");
-         doc_filter = format!("{path_clause} AND {doc_clause} AND {anchor_filter}");
-         Some(format!("{path_clause} AND {anchor_filter}"))
+      let base_filter = Self::search_filter(
+         params.path_filter,
+         params.path_globs,
+         params.indexed_after,
+         params.crate_name,
+      );
+      let code_filter = format!("{code_clause} AND {base_filter}");
+      let doc_filter = format!("{doc_clause} AND {base_filter}");
+
+      let cfg = config::get();
+      let metric = distance_type(cfg.vector_metric());
+      let nprobes = params.nprobes.unwrap_or(cfg.vector_nprobes).max(1);
+      let quantized = Self::is_quantized_table(&table).await?;
+      if quantized {
+         check_quantized_metric(cfg.vector_metric())?;
+      }
+      let quantized_query =
+         quantized.then(|| Int8Array::from(quantize_vector(params.query_vector).0));
+
+      // Tables below the indexing threshold have no ANN index and are
+      // scanned in full, by their binary codes first once they are large.
+      let mode = if params.exact {
+         VectorSearchMode::Exact
+      } else if Self::has_index(&table, "vector").await {
+         VectorSearchMode::Approximate
+      } else if cfg.binary_prefilter_min_chunks > 0
+         && table
+            .count_rows(None)
+            .await
+            .map_err(StoreError::CountRows)?
+            >= cfg.binary_prefilter_min_chunks
+      {
+         VectorSearchMode::Binary
       } else {
-         Some(anchor_filter.to_owned())
+         VectorSearchMode::Exact
+      };
+      let vector_query = || -> Result<VectorQuery, StoreError> {
+         let query = table
+            .query()
+            .nearest_to(params.query_vector)
+            .map_err(StoreError::CreateVectorQuery)?
+            .distance_type(metric)
+            .nprobes(nprobes);
+         Ok(if params.exact {
+            query.bypass_vector_index()
+         } else {
+            query
+         })
       };
 
       let (code_batches, doc_batches): (Vec<RecordBatch>, Vec<RecordBatch>) = tokio::try_join!(
          async {
-            let stream = table
-               .query()
-               .nearest_to(params.query_vector)
-               .map_err(StoreError::CreateVectorQuery)?
-               .limit(300)
+            if mode == VectorSearchMode::Binary {
+               return Self::binary_search(
+                  &table,
+                  params.query_vector,
+                  &code_filter,
+                  params.dense_candidates(),
+               )
+               .await;
+            }
+            if let Some(query) = &quantized_query {
+               return Self::quantized_search(
+                  &table,
+                  query,
+                  &code_filter,
+                  params.dense_candidates(),
+                  nprobes,
+                  params.exact,
+               )
+               .await
+               .map_err(StoreError::ExecuteCodeSearch);
+            }
+            let stream = vector_query()?
+               .limit(params.dense_candidates())
                .only_if(&code_filter)
                .execute()
                .await
@@ -794,10 +1640,22 @@ impl super::Store for LanceStore {
                .map_err(StoreError::CollectCodeResults)
          },
          async {
-            let stream = table
-               .query()
-               .nearest_to(params.query_vector)
-               .map_err(StoreError::CreateVectorQuery)?
+            if mode == VectorSearchMode::Binary {
+               return Self::binary_search(&table, params.query_vector, &doc_filter, 50).await;
+            }
+            if let Some(query) = &quantized_query {
+               return Self::quantized_search(
+                  &table,
+                  query,
+                  &doc_filter,
+                  50,
+                  nprobes,
+                  params.exact,
+               )
+               .await
+               .map_err(StoreError::ExecuteDocSearch);
+            }
+            let stream = vector_query()?
                .only_if(&doc_filter)
                .limit(50)
                .execute()
@@ -811,11 +1669,10 @@ impl super::Store for LanceStore {
       )?;
 
       let fts_query = FullTextSearchQuery::new(params.query_text.to_owned());
-      let mut fts_query_builder = table.query().full_text_search(fts_query);
-
-      if let Some(ref filter) = base_filter {
-         fts_query_builder = fts_query_builder.only_if(filter);
-      }
+      let fts_query_builder = table
+         .query()
+         .full_text_search(fts_query)
+         .only_if(&base_filter);
 
       let fts_batches: Vec<RecordBatch> = match fts_query_builder.limit(50).execute().await {
          Ok(stream) => stream.try_collect().await.unwrap_or_default(),
@@ -830,7 +1687,11 @@ impl super::Store for LanceStore {
 
       let estimated_capacity = all_batches.iter().map(|b| b.num_rows()).sum();
       let mut candidates: Vec<(usize, usize)> = Vec::with_capacity(estimated_capacity);
+      let mut candidate_keys: Vec<(&str, u32)> = Vec::with_capacity(estimated_capacity);
       let mut seen_keys: HashSet<(&str, u32)> = HashSet::with_capacity(estimated_capacity);
+      // Full-text hits come last and in relevance order.
+      let fts_start = code_batches.len() + doc_batches.len();
+      let mut fts_ranks: HashMap<(&str, u32), usize> = HashMap::new();
 
       for (batch_idx, batch) in all_batches.iter().enumerate() {
          let path_col = batch
@@ -855,11 +1716,17 @@ impl super::Store for LanceStore {
             let path = path_col.value(i);
             let start_line = start_line_col.value(i);
 
+            if batch_idx >= fts_start {
+               let rank = fts_ranks.len();
+               fts_ranks.entry((path, start_line)).or_insert(rank);
+            }
+
             if !seen_keys.insert((path, start_line)) {
                continue;
             }
 
             candidates.push((batch_idx, i));
+            candidate_keys.push((path, start_line));
          }
       }
 
@@ -923,61 +1790,39 @@ impl super::Store for LanceStore {
             }
          });
 
-         let vector_list = batch
-            .column_by_name("vector")
-            .unwrap()
-            .as_any()
-            .downcast_ref::<FixedSizeListArray>()
-            .ok_or(StoreError::VectorColumnTypeMismatch)?;
-         let vector_values = vector_list.value(*row_idx);
-         let vector_floats = vector_values
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .ok_or(StoreError::VectorValuesTypeMismatch)?;
-
-         let offset = vector_floats.offset();
-         let len = vector_floats.len();
-         let values = vector_floats.values();
-         let doc_vector = &values[offset..offset + len];
-
-         let score = Self::cosine_similarity(params.query_vector, doc_vector);
+         let doc_vector = DenseVectors::from_batch(batch)?.get(*row_idx);
+         let score = Self::cosine_similarity(params.query_vector, &doc_vector);
 
-         let mut full_content = String::new();
-         let mut context_prev_lines = 0u32;
-
-         if let Some(prev_col) = batch.column_by_name("context_prev")
-            && !prev_col.is_null(*row_idx)
-            && let Some(prev_str) = prev_col.as_any().downcast_ref::<StringArray>()
-         {
-            let prev_content = prev_str.value(*row_idx);
-            context_prev_lines = prev_content.lines().count() as u32;
-            full_content.push_str(prev_content);
-            if !prev_content.ends_with('\n') {
-               full_content.push('\n');
-            }
-         }
-         full_content.push_str(&content);
-         if let Some(next_col) = batch.column_by_name("context_next")
-            && !next_col.is_null(*row_idx)
-            && let Some(next_str) = next_col.as_any().downcast_ref::<StringArray>()
-         {
-            let next_content = next_str.value(*row_idx);
-            if !full_content.ends_with('\n') && !next_content.is_empty() {
-               full_content.push('\n');
-            }
-            full_content.push_str(next_content);
-         }
-
-         let adjusted_start_line = start_line.saturating_sub(context_prev_lines);
+         let optional_str = |name: &str| {
+            batch
+               .column_by_name(name)
+               .filter(|col| !col.is_null(*row_idx))
+               .and_then(|col| col.as_any().downcast_ref::<StringArray>())
+               .map(|arr| Str::copy_from_str(arr.value(*row_idx)))
+         };
+         let fts = fts_ranks
+            .get(&candidate_keys[cand_idx])
+            .map(|&rank| ScoreBreakdown::fts_rank_score(rank));
 
          scored_results.push((cand_idx, SearchResult {
             path,
-            content: full_content.into(),
+            content: content.into(),
             score,
-            start_line: adjusted_start_line,
+            start_line,
             num_lines: end_line.saturating_sub(start_line).max(1),
             chunk_type,
             is_anchor,
+            breakdown: Some(ScoreBreakdown {
+               dense: Some(score),
+               fts,
+               fused: score,
+               ..Default::default()
+            }),
+            bridges: optional_str("bridges"),
+            language: optional_str("language"),
+            context_prev: optional_str("context_prev"),
+            context_next: optional_str("context_next"),
+            linked: Vec::new(),
          }));
       }
 
@@ -987,11 +1832,13 @@ impl super::Store for LanceStore {
             .unwrap_or(std::cmp::Ordering::Equal)
       });
 
+      let rerank_started = Instant::now();
       if params.rerank && !params.query_colbert.is_empty() {
-         const RERANK_CAP: usize = 50;
-         let rerank_count = scored_results.len().min(RERANK_CAP);
+         let rerank_count = scored_results.len().min(params.rerank_count());
 
-         for (cand_idx, result) in scored_results.iter_mut().take(rerank_count) {
+         let mut reranked = Vec::with_capacity(rerank_count);
+         let mut docs = Vec::with_capacity(rerank_count);
+         for (result_idx, (cand_idx, _)) in scored_results.iter().take(rerank_count).enumerate() {
             let (batch_idx, row_idx) = candidates[*cand_idx];
             let batch = all_batches[batch_idx];
 
@@ -1020,28 +1867,203 @@ impl super::Store for LanceStore {
                      1.0
                   };
 
-                  result.score = max_sim_quantized(
-                     params.query_colbert,
-                     colbert_binary,
-                     scale,
-                     config::get().colbert_dim,
-                  );
+                  reranked.push(result_idx);
+                  docs.push((colbert_binary, scale));
                }
             }
          }
 
-         scored_results.sort_by(|a, b| {
-            b.1.score
-               .partial_cmp(&a.1.score)
-               .unwrap_or(std::cmp::Ordering::Equal)
-         });
+         let scores =
+            max_sim_batch(params.query_colbert, &docs, config::get().colbert_dim, params.cancel);
+         for (result_idx, score) in reranked.into_iter().zip(scores) {
+            if let Some(breakdown) = &mut scored_results[result_idx].1.breakdown {
+               breakdown.colbert = Some(score);
+            }
+         }
       }
 
       let mut scored_results: Vec<SearchResult> =
          scored_results.into_iter().map(|(_, r)| r).collect();
+      fusion::fuse(&mut scored_results, &Fusion::from_config());
+      scored_results.sort_by(|a, b| {
+         b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+      });
+      let rerank = StageTiming::since(SearchStage::Rerank, rerank_started);
+
+      let anchor_weight = config::get().anchor_weight;
+      if anchor_weight > 0.0 && !scored_results.is_empty() {
+         const ANCHOR_CAP: usize = 100;
+         let head = scored_results.len().min(ANCHOR_CAP);
+
+         match Self::anchor_similarities(
+            &table,
+            &scored_results[..head],
+            params.query_vector,
+            quantized,
+         )
+         .await
+         {
+            Ok(sims) => {
+               ranking::apply_anchor_boost(&mut scored_results[..head], &sims, anchor_weight);
+               scored_results.sort_by(|a, b| {
+                  b.score
+                     .partial_cmp(&a.score)
+                     .unwrap_or(std::cmp::Ordering::Equal)
+               });
+            },
+            Err(e) => tracing::debug!("skipping anchor boost: {e}"),
+         }
+      }
+
       scored_results.truncate(params.limit);
 
-      Ok(SearchResponse { results: scored_results, status: SearchStatus::Ready, progress: None })
+      Ok(SearchResponse {
+         results: scored_results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode,
+         route: QueryRoute::Semantic,
+         stages: vec![rerank],
+      })
+   }
+
+   async fn keyword_search(&self, params: store::KeywordParams<'_>) -> Result<SearchResponse> {
+      let Ok(table) = self.get_table(params.store_id).await else {
+         return Ok(SearchResponse {
+            results:  vec![],
+            status:   SearchStatus::Ready,
+            progress: None,
+            mode:     VectorSearchMode::Keyword,
+            route:    QueryRoute::Semantic,
+            stages:   Vec::new(),
+         });
+      };
+
+      let filter = Self::search_filter(
+         params.path_filter,
+         params.path_globs,
+         params.indexed_after,
+         params.crate_name,
+      );
+      let stream = table
+         .query()
+         .full_text_search(FullTextSearchQuery::new(params.query_text.to_owned()))
+         .only_if(&filter)
+         .limit(params.limit)
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteKeywordSearch)?;
+      let batches: Vec<RecordBatch> = stream
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectKeywordResults)?;
+
+      let mut candidates = Vec::with_capacity(batches.iter().map(RecordBatch::num_rows).sum());
+      for batch in &batches {
+         for row in 0..batch.num_rows() {
+            candidates.push(Self::candidate_from_row(batch, row)?);
+         }
+      }
+
+      Ok(SearchResponse {
+         results:  candidate::rank_by_position(candidates),
+         status:   SearchStatus::Ready,
+         progress: None,
+         mode:     VectorSearchMode::Keyword,
+         route:    QueryRoute::Semantic,
+         stages:   Vec::new(),
+      })
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let table = self.get_table(store_id).await?;
+      let pattern = bridge::like_pattern(key).replace('\'', "''");
+      let stream = table
+         .query()
+         .only_if(format!("bridges LIKE '{pattern}' AND (is_anchor IS NULL OR is_anchor = false)"))
+         .limit(limit)
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteBridgeQuery)?;
+      let batches: Vec<RecordBatch> = stream
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectBridgeResults)?;
+
+      let mut candidates = Vec::with_capacity(batches.iter().map(RecordBatch::num_rows).sum());
+      for batch in &batches {
+         for row in 0..batch.num_rows() {
+            candidates.push(Self::candidate_from_row(batch, row)?);
+         }
+      }
+      Ok(candidate::bridged(candidates))
+   }
+
+   async fn search_files(&self, params: store::FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let table = self.get_table(params.store_id).await?;
+      let mut predicate = "is_anchor = true".to_owned();
+      if let Some(path) = params.path_filter {
+         let prefix = store::escape_path_for_like(path);
+         predicate = format!("path LIKE '{prefix}%' AND {predicate}");
+      }
+
+      // A file has a single anchor, so scanning them all stays cheap next to
+      // a chunk search.
+      let batches: Vec<RecordBatch> = table
+         .query()
+         .only_if(predicate)
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteQuery)?
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectResults)?;
+
+      let mut candidates = Vec::with_capacity(batches.iter().map(RecordBatch::num_rows).sum());
+      for batch in &batches {
+         let vectors = DenseVectors::from_batch(batch)?;
+         for row in 0..batch.num_rows() {
+            let mut cand = Self::candidate_from_row(batch, row)?;
+            cand.vector = vectors.get(row).into_owned();
+            candidates.push(cand);
+         }
+      }
+      Ok(candidate::rank_anchors(candidates, params.query_vector, params.limit))
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<store::ChunkVector>> {
+      let table = self.get_table(store_id).await?;
+      let columns = Self::with_vector_scale(
+         &["path", "content", "start_line", "end_line", "is_anchor", "chunk_type", "vector"],
+         Self::is_quantized_table(&table).await?,
+      );
+      let batches: Vec<RecordBatch> = table
+         .query()
+         .select(Select::columns(&columns))
+         .execute()
+         .await
+         .map_err(StoreError::ExecuteQuery)?
+         .try_collect()
+         .await
+         .map_err(StoreError::CollectResults)?;
+
+      let mut candidates = Vec::with_capacity(batches.iter().map(RecordBatch::num_rows).sum());
+      for batch in &batches {
+         let vectors = DenseVectors::from_batch(batch)?;
+         for row in 0..batch.num_rows() {
+            let mut cand = Self::candidate_from_row(batch, row)?;
+            cand.vector = vectors.get(row).into_owned();
+            candidates.push(cand);
+         }
+      }
+      Ok(candidate::chunk_vectors(candidates))
    }
 
    async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
@@ -1084,6 +2106,67 @@ impl super::Store for LanceStore {
       Ok(())
    }
 
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      let (records, outdated) = match self.chunk_fingerprints(store_id, file_paths).await {
+         Ok(stored) => {
+            let diff = store::diff_chunks(stored, records);
+            tracing::debug!("rewriting {} chunks, keeping {}", diff.changed.len(), diff.kept);
+            if diff.stale.is_empty() && diff.changed.is_empty() {
+               return Ok(0);
+            }
+            let escaped: Vec<String> = diff
+               .stale
+               .iter()
+               .map(|id| format!("'{}'", id.replace('\'', "''")))
+               .collect();
+            let outdated = (!escaped.is_empty()).then(|| format!("id IN ({})", escaped.join(",")));
+            (diff.changed, outdated)
+         },
+         Err(e) => {
+            tracing::debug!("rewriting whole files in {store_id}: {e}");
+            let escaped: Vec<String> = file_paths
+               .iter()
+               .collect::<HashSet<_>>()
+               .into_iter()
+               .map(|p| format!("'{}'", store::escape_path_literal(p)))
+               .collect();
+            (records, Some(format!("path IN ({})", escaped.join(","))))
+         },
+      };
+
+      let table = self.get_table(store_id).await?;
+      let written = records.len();
+      if records.is_empty() {
+         if let Some(outdated) = outdated {
+            table
+               .delete(&outdated)
+               .await
+               .map_err(StoreError::DeleteChunks)?;
+         }
+         return Ok(0);
+      }
+
+      // One merge commits the deletes and the inserts together, so readers
+      // never see a file with its old chunks gone and the new ones missing.
+      let mut merge = table.merge_insert(&["id"]);
+      merge
+         .when_matched_update_all(None)
+         .when_not_matched_insert_all();
+      if let Some(outdated) = outdated {
+         merge.when_not_matched_by_source_delete(Some(outdated));
+      }
+      merge
+         .execute(Box::new(RecordBatchOnce::new(Self::records_to_batch(records)?)))
+         .await
+         .map_err(StoreError::ReplaceChunks)?;
+      Ok(written)
+   }
+
    async fn delete_store(&self, store_id: &str) -> Result<()> {
       let conn = self.get_connection(store_id).await?;
 
@@ -1093,6 +2176,7 @@ impl super::Store for LanceStore {
          .map_err(StoreError::DropTable)?;
 
       self.connections.write().remove(store_id);
+      self.resident.lock().remove(store_id);
 
       Ok(())
    }
@@ -1105,13 +2189,15 @@ impl super::Store for LanceStore {
          .map_err(StoreError::CountRows)?;
 
       Ok(StoreInfo {
-         store_id:  store_id.to_string(),
-         row_count: row_count as u64,
-         path:      self.data_dir.join(store_id),
+         store_id:        store_id.to_string(),
+         row_count:       row_count as u64,
+         path:            self.data_dir.join(store_id),
+         dense_dim:       config::get().dense_vector_dim(),
+         dense_quantized: Self::is_quantized_table(&table).await?,
       })
    }
 
-   async fn list_files(&self, store_id: &str) -> Result<Vec<PathBuf>> {
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
       let Ok(table) = self.get_table(store_id).await else {
          return Ok(vec![]);
       };
@@ -1119,7 +2205,7 @@ impl super::Store for LanceStore {
       let stream_result = table
          .query()
          .only_if("is_anchor = true")
-         .select(Select::columns(&["path"]))
+         .select(Select::columns(&["path", "indexed_at"]))
          .execute()
          .await;
 
@@ -1127,7 +2213,7 @@ impl super::Store for LanceStore {
          Ok(s) => s,
          Err(_) => table
             .query()
-            .select(Select::columns(&["path"]))
+            .select(Select::columns(&["path", "indexed_at"]))
             .execute()
             .await
             .map_err(StoreError::ExecuteQuery)?,
@@ -1138,25 +2224,41 @@ impl super::Store for LanceStore {
          .await
          .map_err(StoreError::CollectResults)?;
 
-      let mut paths = Vec::new();
-      let mut seen = HashSet::new();
+      let mut files: Vec<IndexedFile> = Vec::new();
+      let mut positions: HashMap<PathBuf, usize> = HashMap::new();
 
       for batch in batches {
+         let indexed_at_array = batch
+            .column_by_name("indexed_at")
+            .and_then(|col| col.as_any().downcast_ref::<UInt64Array>());
+
          if let Some(path_col) = batch.column_by_name("path")
             && let Some(path_array) = path_col.as_any().downcast_ref::<StringArray>()
          {
             for i in 0..path_array.len() {
-               if !path_array.is_null(i) {
-                  let path: PathBuf = path_array.value(i).into();
-                  if seen.insert(path.clone()) {
-                     paths.push(path);
-                  }
+               if path_array.is_null(i) {
+                  continue;
+               }
+               let path: PathBuf = path_array.value(i).into();
+               let indexed_at = indexed_at_array
+                  .filter(|arr| !arr.is_null(i))
+                  .map(|arr| arr.value(i));
+
+               match positions.entry(path) {
+                  Entry::Occupied(e) => {
+                     let file = &mut files[*e.get()];
+                     file.indexed_at = file.indexed_at.max(indexed_at);
+                  },
+                  Entry::Vacant(e) => {
+                     files.push(IndexedFile { path: e.key().clone(), indexed_at });
+                     e.insert(files.len() - 1);
+                  },
                }
             }
          }
       }
 
-      Ok(paths)
+      Ok(files)
    }
 
    async fn is_empty(&self, store_id: &str) -> Result<bool> {
@@ -1201,12 +2303,37 @@ impl super::Store for LanceStore {
          return Ok(());
       }
 
-      let mut num_partitions = (vector_rows / 100).clamp(8, 64) as u32;
-      num_partitions = num_partitions.min(vector_rows as u32).max(1);
+      let cfg = config::get();
+      let num_partitions = cfg
+         .vector_partitions
+         .unwrap_or_else(|| (vector_rows / 100).clamp(8, 64) as u32)
+         .min(vector_rows as u32)
+         .max(1);
+      if Self::is_quantized_table(&table).await? {
+         if let Err(e) = Self::create_quantized_index(&table, num_partitions).await {
+            tracing::warn!("skipping vector index for {store_id} (rows={vector_rows}): {e}");
+         }
+         return Ok(());
+      }
+      let metric = distance_type(cfg.vector_metric());
 
-      let index = Index::IvfPq(
-         lancedb::index::vector::IvfPqIndexBuilder::default().num_partitions(num_partitions),
-      );
+      let index = match cfg.vector_index {
+         VectorIndexType::IvfPq => Index::IvfPq(
+            IvfPqIndexBuilder::default()
+               .num_partitions(num_partitions)
+               .distance_type(metric),
+         ),
+         VectorIndexType::IvfHnswPq => Index::IvfHnswPq(
+            IvfHnswPqIndexBuilder::default()
+               .num_partitions(num_partitions)
+               .distance_type(metric),
+         ),
+         VectorIndexType::IvfHnswSq => Index::IvfHnswSq(
+            IvfHnswSqIndexBuilder::default()
+               .num_partitions(num_partitions)
+               .distance_type(metric),
+         ),
+      };
 
       if let Err(e) = table.create_index(&["vector"], index).execute().await {
          tracing::warn!("skipping vector index for {store_id} (rows={vector_rows}): {e}");
@@ -1216,6 +2343,136 @@ impl super::Store for LanceStore {
       Ok(())
    }
 
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      const BATCH_SIZE: usize = 900;
+
+      let mut embeddings = HashMap::new();
+      if file_paths.is_empty() {
+         return Ok(embeddings);
+      }
+      let table = self.get_table(store_id).await?;
+
+      for chunk in file_paths.chunks(BATCH_SIZE) {
+         let escaped: Vec<String> = chunk
+            .iter()
+            .map(|p| format!("'{}'", store::escape_path_literal(p)))
+            .collect();
+
+         let batches: Vec<RecordBatch> = table
+            .query()
+            .only_if(format!("path IN ({})", escaped.join(",")))
+            .select(Select::columns(&columns))
+            .execute()
+            .await
+            .map_err(StoreError::ExecuteQuery)?
+            .try_collect()
+            .await
+            .map_err(StoreError::CollectResults)?;
+
+         for batch in batches {
+            let vectors = DenseVectors::from_batch(&batch)?;
+            let (Some(hash_col), Some(colbert_col), Some(scale_col)) = (
+               batch
+                  .column_by_name("chunk_hash")
+                  .and_then(|c| c.as_any().downcast_ref::<BinaryArray>()),
+               batch
+                  .column_by_name("colbert")
+                  .and_then(|c| c.as_any().downcast_ref::<LargeBinaryArray>()),
+               batch
+                  .column_by_name("colbert_scale")
+                  .and_then(|c| c.as_any().downcast_ref::<Float64Array>()),
+            ) else {
+               return Err(StoreError::VectorColumnTypeMismatch.into());
+            };
+
+            for row in 0..batch.num_rows() {
+               let Some(chunk_hash) = FileHash::from_slice(hash_col.value(row)) else {
+                  continue;
+               };
+               embeddings.insert(chunk_hash, HybridEmbedding {
+                  dense:         vectors.get(row).into_owned(),
+                  colbert:       if colbert_col.is_null(row) {
+                     Vec::new()
+                  } else {
+                     colbert_col.value(row).to_vec()
+                  },
+                  colbert_scale: if scale_col.is_null(row) {
+                     1.0
+                  } else {
+                     scale_col.value(row)
+                  },
+               });
+            }
+         }
+      }
+
+      Ok(embeddings)
+   }
+
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      let table = self.get_table(store_id).await?;
+
+      let indices = table.list_indices().await.map_err(StoreError::WarmUp)?;
+      for index in indices {
+         if let Err(e) = table.prewarm_index(&index.name).await {
+            tracing::debug!("cannot prewarm index {}: {e}", index.name);
+         }
+      }
+
+      // Scanning the columns every search reads pulls them into the page
+      // cache.
+      let mut stream = table
+         .query()
+         .select(Select::columns(&Self::with_vector_scale(
+            WARM_COLUMNS,
+            Self::is_quantized_table(&table).await?,
+         )))
+         .execute()
+         .await
+         .map_err(StoreError::WarmUp)?;
+      while stream
+         .try_next()
+         .await
+         .map_err(StoreError::WarmUp)?
+         .is_some()
+      {}
+
+      let limit = config::get().lock_index_max_mb * 1024 * 1024;
+      if limit > 0 {
+         self.lock_resident(store_id, limit)?;
+      }
+      Ok(())
+   }
+
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      let table = self.get_table(store_id).await?;
+
+      // Merges the small fragments left by incremental inserts, prunes old
+      // versions and folds unindexed rows into existing indexes.
+      table
+         .optimize(OptimizeAction::All)
+         .await
+         .map_err(StoreError::Optimize)?;
+
+      let indices = table.list_indices().await.map_err(StoreError::Optimize)?;
+      let has_index = |column: &str| {
+         indices
+            .iter()
+            .any(|i| i.columns.iter().any(|c| c == column))
+      };
+      if !has_index("content") {
+         self.create_fts_index(store_id).await?;
+      }
+      if !has_index("vector") {
+         self.create_vector_index(store_id).await?;
+      }
+      Ok(())
+   }
+
    async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
       let Ok(table) = self.get_table(store_id).await else {
          return Ok(HashMap::new());
@@ -1268,4 +2525,143 @@ impl super::Store for LanceStore {
 
       Ok(hashes)
    }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      let Some((prefix, len)) = store::root_prefix(root) else {
+         return Ok(false);
+      };
+      let Ok(table) = self.get_table(store_id).await else {
+         return Ok(true);
+      };
+
+      let prefix = store::escape_path_literal(Path::new(&prefix));
+      table
+         .update()
+         .only_if(format!("substr(path, 1, {len}) = '{prefix}'"))
+         .column("path", format!("substr(path, {})", len + 1))
+         .column("id", format!("substr(id, {})", len + 1))
+         .execute()
+         .await
+         .map_err(StoreError::Relativize)?;
+      Ok(true)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn schema_with(version: Option<u32>, dim: i32, with_hash: bool) -> Schema {
+      let mut fields = vec![
+         Field::new("id", DataType::Utf8, false),
+         Field::new("path", DataType::Utf8, false),
+         Field::new("content", DataType::Utf8, false),
+         Field::new("start_line", DataType::UInt32, false),
+         Field::new(
+            "vector",
+            DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), dim),
+            false,
+         ),
+      ];
+      if with_hash {
+         fields.push(Field::new("hash", DataType::Binary, false));
+      }
+      let metadata = version
+         .map(|v| HashMap::from([(SCHEMA_VERSION_KEY.to_string(), v.to_string())]))
+         .unwrap_or_default();
+      Schema::new_with_metadata(fields, metadata)
+   }
+
+   #[test]
+   fn plan_migration_detects_layouts() {
+      let current = schema_with(Some(SCHEMA_VERSION), 384, true);
+      assert_eq!(LanceStore::plan_migration(&current, 384, false).unwrap(), Migration::None);
+      assert_eq!(LanceStore::plan_migration(&current, 768, false).unwrap(), Migration::Rewrite);
+
+      assert_eq!(LanceStore::plan_migration(&current, 384, true).unwrap(), Migration::Rewrite);
+
+      let legacy = schema_with(None, 384, true);
+      assert_eq!(LanceStore::plan_migration(&legacy, 384, false).unwrap(), Migration::Rewrite);
+
+      let broken = schema_with(Some(SCHEMA_VERSION), 384, false);
+      assert!(matches!(
+         LanceStore::plan_migration(&broken, 384, false).unwrap(),
+         Migration::Rebuild(_)
+      ));
+
+      let future = schema_with(Some(SCHEMA_VERSION + 1), 384, true);
+      assert!(matches!(
+         LanceStore::plan_migration(&future, 384, false),
+         Err(StoreError::SchemaTooNew { found, .. }) if found == SCHEMA_VERSION + 1
+      ));
+   }
+
+   #[test]
+   fn quantized_vectors_round_trip() {
+      let vector = [0.5, -0.25, 0.0, 0.125];
+      let (values, scale) = quantize_vector(&vector);
+      assert_eq!(values, [127, -64, 0, 32]);
+
+      let batch = RecordBatch::try_from_iter([
+         (
+            "vector",
+            Arc::new(FixedSizeListArray::new(
+               LanceStore::vector_item(true),
+               4,
+               Arc::new(Int8Array::from(values)),
+               None,
+            )) as ArrayRef,
+         ),
+         ("vector_scale", Arc::new(Float32Array::from(vec![scale])) as ArrayRef),
+      ])
+      .unwrap();
+      let restored = DenseVectors::from_batch(&batch).unwrap().get(0);
+      for (x, y) in vector.iter().zip(restored.iter()) {
+         assert!((x - y).abs() <= scale / 2.0, "{x} became {y}");
+      }
+
+      let (zeros, scale) = quantize_vector(&[0.0; 3]);
+      assert_eq!(zeros, [0; 3]);
+      assert!(scale > 0.0);
+   }
+
+   #[test]
+   fn quantized_vectors_require_cosine() {
+      assert!(check_quantized_metric(VectorMetric::Cosine).is_ok());
+      for metric in [VectorMetric::L2, VectorMetric::Dot] {
+         let err = check_quantized_metric(metric).unwrap_err();
+         assert!(err.to_string().contains(metric.as_str()), "{err}");
+      }
+
+      let cfg = config::Config { quantize_dense: true, ..Default::default() };
+      assert!(check_quantized_metric(cfg.vector_metric()).is_ok());
+   }
+
+   #[test]
+   fn binarizes_by_sign() {
+      assert_eq!(binarize(&[0.5, -0.1, 0.0, 0.2, 1.0, -1.0, 0.3, 0.1, -0.4, 0.7]), [
+         0b1001_1011,
+         0b0100_0000
+      ]);
+   }
+
+   #[tokio::test]
+   async fn created_tables_record_schema_version() {
+      let dir = tempfile::TempDir::new().unwrap();
+      let conn = connect(dir.path().to_str().unwrap())
+         .execute()
+         .await
+         .unwrap();
+
+      let schema = LanceStore::create_schema();
+      conn
+         .create_table("t", RecordBatchOnce::new(LanceStore::create_empty_batch(&schema).unwrap()))
+         .execute()
+         .await
+         .unwrap();
+
+      let table = conn.open_table("t").execute().await.unwrap();
+      let stored = table.schema().await.unwrap();
+      assert_eq!(LanceStore::schema_version(&stored), SCHEMA_VERSION);
+   }
 }
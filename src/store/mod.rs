@@ -1,21 +1,41 @@
-//! Vector storage abstraction with `LanceDB` implementation.
+//! Vector storage abstraction with `LanceDB`, flat-file, `SQLite`, Qdrant and
+//! Postgres implementations.
 
+pub mod auto;
+mod candidate;
+pub mod flat;
 pub mod lance;
+pub mod overlay;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod qdrant;
+pub mod rooted;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 use std::{
-   collections::HashMap,
+   collections::{HashMap, HashSet},
    path::{Path, PathBuf},
    sync::Arc,
 };
 
 use ndarray::Array2;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-   error::Result,
+   config::{self, StoreBackend},
+   embed::HybridEmbedding,
+   error::{Error, Result},
    meta::FileHash,
-   types::{SearchResponse, StoreInfo, VectorRecord},
+   search::glob::PathGlobs,
+   types::{IndexedFile, SearchResponse, SearchResult, StoreInfo, VectorRecord},
 };
 
+/// Rows ranked by dense similarity that go on to fusion and reranking, unless
+/// the rerank depth asks for more.
+pub const DENSE_CANDIDATES: usize = 300;
+
 /// Converts a path to the exact string stored in the table.
 pub fn path_to_store_value(path: &Path) -> String {
    match path.to_str() {
@@ -43,6 +63,105 @@ pub fn escape_path_for_like(path: &Path) -> String {
       .replace('\'', "''")
 }
 
+/// Prefix of the stored paths under `root`, with its length in characters as
+/// SQL `substr` counts them; `None` for a root whose paths are stored
+/// hex-encoded.
+pub fn root_prefix(root: &Path) -> Option<(String, usize)> {
+   let root = root.to_str()?.trim_end_matches(std::path::is_separator);
+   let prefix = format!("{root}{}", std::path::MAIN_SEPARATOR);
+   let len = prefix.chars().count();
+   Some((prefix, len))
+}
+
+/// Fingerprint of a stored chunk: its content hash plus the fields that can
+/// change while the content stays the same, when code around it moves.
+pub fn chunk_fingerprint(
+   chunk_hash: &FileHash,
+   start_line: u32,
+   end_line: u32,
+   context_prev: Option<&str>,
+   context_next: Option<&str>,
+) -> FileHash {
+   let mut hasher = Sha256::new();
+   hasher.update(chunk_hash);
+   hasher.update(start_line.to_le_bytes());
+   hasher.update(end_line.to_le_bytes());
+   for context in [context_prev, context_next] {
+      // Length-prefixed, so that text cannot move between the two fields.
+      let context = context.unwrap_or_default();
+      hasher.update((context.len() as u64).to_le_bytes());
+      hasher.update(context);
+   }
+   FileHash::new(hasher.finalize().into())
+}
+
+/// [`chunk_fingerprint`] of a record.
+pub fn record_fingerprint(record: &VectorRecord) -> FileHash {
+   chunk_fingerprint(
+      &record.chunk_hash,
+      record.start_line,
+      record.end_line,
+      record.context_prev.as_deref(),
+      record.context_next.as_deref(),
+   )
+}
+
+/// New records of reindexed files, split by whether they need writing.
+#[derive(Debug, Default)]
+pub struct ChunkDiff {
+   /// Ids of stored chunks that were removed or changed.
+   pub stale:   Vec<String>,
+   /// Records to insert.
+   pub changed: Vec<VectorRecord>,
+   /// Stored chunks left as they are.
+   pub kept:    usize,
+}
+
+/// Compares `records`, every chunk of some reindexed files, against the
+/// [`chunk_fingerprint`]s stored for those files, keyed by chunk id.
+///
+/// Stored chunks are matched by fingerprint, not id: ids number the chunks
+/// of a file, so splitting one shifts those of every chunk after it. Changed
+/// records are given ids derived from their fingerprint instead, which no
+/// chunk left in place can have. Anchors are always rewritten, since they
+/// carry the file's hash and index time.
+pub fn diff_chunks(stored: HashMap<String, FileHash>, records: Vec<VectorRecord>) -> ChunkDiff {
+   let anchors: HashSet<&str> = records
+      .iter()
+      .filter(|record| record.is_anchor.unwrap_or(false))
+      .map(|record| record.id.as_str())
+      .collect();
+   let mut diff = ChunkDiff::default();
+   let mut by_fingerprint: HashMap<FileHash, Vec<String>> = HashMap::new();
+   for (id, fingerprint) in stored {
+      if anchors.contains(id.as_str()) {
+         diff.stale.push(id);
+      } else {
+         by_fingerprint.entry(fingerprint).or_default().push(id);
+      }
+   }
+
+   for mut record in records {
+      if record.is_anchor.unwrap_or(false) {
+         diff.changed.push(record);
+         continue;
+      }
+      let fingerprint = record_fingerprint(&record);
+      if by_fingerprint
+         .get_mut(&fingerprint)
+         .and_then(Vec::pop)
+         .is_some()
+      {
+         diff.kept += 1;
+         continue;
+      }
+      record.id = format!("{}:{}", record.path.display(), &fingerprint.to_string()[..16]);
+      diff.changed.push(record);
+   }
+   diff.stale.extend(by_fingerprint.into_values().flatten());
+   diff
+}
+
 /// Parameters for vector search queries.
 pub struct SearchParams<'a> {
    pub store_id:      &'a str,
@@ -51,7 +170,74 @@ pub struct SearchParams<'a> {
    pub query_colbert: &'a Array2<f32>,
    pub limit:         usize,
    pub path_filter:   Option<&'a Path>,
+   /// Only match paths accepted by these globs. Backends may narrow rows with
+   /// them but need not apply them exactly; the search engine does that.
+   pub path_globs:    Option<&'a PathGlobs>,
+   /// Only match files indexed at or after this Unix timestamp (seconds).
+   pub indexed_after: Option<u64>,
+   /// Only match chunks owned by this Cargo package.
+   pub crate_name:    Option<&'a str>,
    pub rerank:        bool,
+   /// Candidates to rescore with `ColBERT`; `None` uses `rerank_depth`.
+   pub rerank_depth:  Option<usize>,
+   /// IVF partitions to probe for dense search; `None` uses `vector_nprobes`.
+   /// Ignored by backends without an IVF index.
+   pub nprobes:       Option<usize>,
+   /// Scan every vector instead of using the approximate index.
+   pub exact:         bool,
+   /// Stops reranking early once fired; `None` when the search cannot be
+   /// cancelled.
+   pub cancel:        Option<&'a CancellationToken>,
+}
+
+impl SearchParams<'_> {
+   /// Candidates to rescore with `ColBERT`: the rerank depth, or zero when
+   /// reranking is off.
+   pub fn rerank_count(&self) -> usize {
+      if self.rerank && !self.query_colbert.is_empty() {
+         self
+            .rerank_depth
+            .unwrap_or_else(|| config::get().rerank_depth)
+      } else {
+         0
+      }
+   }
+
+   /// Dense candidates to fetch, enough to cover the rerank depth.
+   pub fn dense_candidates(&self) -> usize {
+      DENSE_CANDIDATES.max(self.rerank_count())
+   }
+}
+
+/// Parameters for full-text queries that skip embeddings entirely.
+pub struct KeywordParams<'a> {
+   pub store_id:      &'a str,
+   pub query_text:    &'a str,
+   pub limit:         usize,
+   pub path_filter:   Option<&'a Path>,
+   /// See [`SearchParams::path_globs`].
+   pub path_globs:    Option<&'a PathGlobs>,
+   /// Only match files indexed at or after this Unix timestamp (seconds).
+   pub indexed_after: Option<u64>,
+   /// Only match chunks owned by this Cargo package.
+   pub crate_name:    Option<&'a str>,
+}
+
+/// Parameters for ranking files by their anchor chunks.
+pub struct FileSearchParams<'a> {
+   pub store_id:     &'a str,
+   pub query_vector: &'a [f32],
+   pub limit:        usize,
+   pub path_filter:  Option<&'a Path>,
+}
+
+/// Location and dense embedding of a stored chunk of code.
+#[derive(Debug, Clone)]
+pub struct ChunkVector {
+   pub path:       PathBuf,
+   pub start_line: u32,
+   pub end_line:   u32,
+   pub vector:     Vec<f32>,
 }
 
 /// Storage backend for vector embeddings, supporting search, indexing, and file
@@ -65,20 +251,66 @@ pub trait Store: Send + Sync {
    /// full-text search.
    async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse>;
 
+   /// Searches the full-text index alone, ranking by keyword relevance. By
+   /// default this fails for backends without a full-text index.
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let _ = params;
+      Err(Error::KeywordSearchUnavailable)
+   }
+
+   /// Returns up to `limit` non-anchor chunks whose bridge keys may include
+   /// `key`, without context lines. Matches can be loose; callers confirm
+   /// them against [`SearchResult::bridges`].
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>>;
+
+   /// Ranks files by the similarity of their anchor chunks to the query
+   /// vector, returning the best `limit` anchors.
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>>;
+
+   /// Returns every chunk of code with its dense embedding, leaving out
+   /// anchors and module summaries, for comparing chunks with each other.
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>>;
+
    /// Deletes all records associated with a single file.
    async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()>;
 
    /// Deletes all records associated with multiple files.
    async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()>;
 
+   /// Replaces every record of `file_paths` with `records`, which hold all
+   /// chunks of those files, and returns how many records were written.
+   ///
+   /// By default the files' records are deleted and `records` inserted.
+   /// Backends that can look up stored chunks write only what
+   /// [`diff_chunks`] finds changed; chunks left in place keep their earlier
+   /// `indexed_at`. The deletes and inserts land in one commit, so a failed
+   /// replace leaves the files' earlier records intact.
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      self.delete_files(store_id, file_paths).await?;
+      let written = records.len();
+      self.insert_batch(store_id, records).await?;
+      Ok(written)
+   }
+
    /// Deletes an entire store.
    async fn delete_store(&self, store_id: &str) -> Result<()>;
 
    /// Retrieves metadata about a store.
    async fn get_info(&self, store_id: &str) -> Result<StoreInfo>;
 
-   /// Lists all files currently indexed in the store.
-   async fn list_files(&self, store_id: &str) -> Result<Vec<PathBuf>>;
+   /// Lists all files currently indexed in the store along with the time each
+   /// was indexed.
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>>;
 
    /// Checks whether a store contains any records.
    async fn is_empty(&self, store_id: &str) -> Result<bool>;
@@ -91,6 +323,44 @@ pub trait Store: Send + Sync {
 
    /// Retrieves file hashes for all indexed files.
    async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>>;
+
+   /// Retrieves the stored embeddings of every chunk in the given files, keyed
+   /// by chunk content hash.
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>>;
+
+   /// Compacts storage and brings indexes up to date after incremental
+   /// writes. By default this refreshes the full-text and vector indexes.
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      self.create_fts_index(store_id).await?;
+      self.create_vector_index(store_id).await
+   }
+
+   /// Loads indexes and frequently read data into memory ahead of the first
+   /// search. By default this does nothing.
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      let _ = store_id;
+      Ok(())
+   }
+
+   /// Records `root` as the directory the rows of `store_id` give paths
+   /// relative to, as its metadata now does. By default this does nothing;
+   /// see [`RootedStore`].
+   fn set_root(&self, store_id: &str, root: Option<&Path>) {
+      let _ = (store_id, root);
+   }
+
+   /// Rewrites the paths and ids of the rows under `root` relative to it,
+   /// moving a store indexed before roots were recorded over to them. Rows
+   /// elsewhere keep their absolute paths. Returns whether the rows were
+   /// rewritten; by default they cannot be, and the store stays absolute.
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      let _ = (store_id, root);
+      Ok(false)
+   }
 }
 
 #[async_trait::async_trait]
@@ -103,6 +373,27 @@ impl<T: Store + ?Sized> Store for Arc<T> {
       (**self).search(params).await
    }
 
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      (**self).keyword_search(params).await
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      (**self).find_bridged(store_id, key, limit).await
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      (**self).search_files(params).await
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      (**self).chunk_vectors(store_id).await
+   }
+
    async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
       (**self).delete_file(store_id, file_path).await
    }
@@ -111,6 +402,15 @@ impl<T: Store + ?Sized> Store for Arc<T> {
       (**self).delete_files(store_id, file_paths).await
    }
 
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      (**self).replace_files(store_id, file_paths, records).await
+   }
+
    async fn delete_store(&self, store_id: &str) -> Result<()> {
       (**self).delete_store(store_id).await
    }
@@ -119,7 +419,7 @@ impl<T: Store + ?Sized> Store for Arc<T> {
       (**self).get_info(store_id).await
    }
 
-   async fn list_files(&self, store_id: &str) -> Result<Vec<PathBuf>> {
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
       (**self).list_files(store_id).await
    }
 
@@ -138,9 +438,61 @@ impl<T: Store + ?Sized> Store for Arc<T> {
    async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
       (**self).get_file_hashes(store_id).await
    }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      (**self).get_chunk_embeddings(store_id, file_paths).await
+   }
+
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      (**self).optimize(store_id).await
+   }
+
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      (**self).warm_up(store_id).await
+   }
+
+   fn set_root(&self, store_id: &str, root: Option<&Path>) {
+      (**self).set_root(store_id, root);
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      (**self).relativize(store_id, root).await
+   }
 }
 
+pub use auto::AutoStore;
+pub use flat::FlatStore;
 pub use lance::LanceStore;
+pub use overlay::OverlayStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+pub use qdrant::QdrantStore;
+pub use rooted::RootedStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Opens the store backend selected by the `store_backend` setting, wrapped
+/// to resolve the relative paths of rooted stores and to layer branch stores
+/// over their base.
+pub fn open() -> Result<Arc<dyn Store>> {
+   let backend: Box<dyn Store> = match config::get().store_backend {
+      StoreBackend::Lance if config::get().flat_store_max_chunks > 0 => Box::new(AutoStore::new()?),
+      StoreBackend::Lance => Box::new(LanceStore::new()?),
+      StoreBackend::Flat => Box::new(FlatStore::new()?),
+      StoreBackend::Qdrant => Box::new(QdrantStore::new()?),
+      #[cfg(feature = "sqlite")]
+      StoreBackend::Sqlite => Box::new(SqliteStore::new()?),
+      #[cfg(feature = "postgres")]
+      StoreBackend::Postgres => Box::new(PostgresStore::new()?),
+      #[cfg(not(all(feature = "sqlite", feature = "postgres")))]
+      backend => return Err(crate::Error::BackendUnavailable(backend.as_str())),
+   };
+   Ok(Arc::new(OverlayStore::new(Box::new(RootedStore::new(backend)))))
+}
 
 #[cfg(test)]
 mod tests {
@@ -163,4 +515,72 @@ mod tests {
       let path = Path::new("foo_bar%baz'qux");
       assert_eq!(escape_path_for_like(path), "foo\\_bar\\%baz''qux");
    }
+
+   fn record(index: u32, content: &'static str, start_line: u32) -> VectorRecord {
+      VectorRecord {
+         id: format!("/repo/a.rs:{index}"),
+         path: Arc::new(PathBuf::from("/repo/a.rs")),
+         hash: FileHash::sum("file"),
+         chunk_hash: FileHash::sum(content),
+         content: content.into(),
+         start_line,
+         end_line: start_line + 2,
+         chunk_index: Some(index),
+         is_anchor: Some(false),
+         chunk_type: None,
+         context_prev: None,
+         context_next: None,
+         vector: Vec::new(),
+         colbert: Vec::new(),
+         colbert_scale: 1.0,
+         indexed_at: None,
+         crate_name: None,
+         bridges: None,
+         language: None,
+      }
+   }
+
+   #[test]
+   fn diff_chunks_keeps_unchanged_records() {
+      let stored: HashMap<String, FileHash> =
+         [record(0, "a", 0), record(1, "b", 3), record(2, "c", 6)]
+            .iter()
+            .map(|r| (r.id.clone(), record_fingerprint(r)))
+            .collect();
+
+      // Chunk 1 is edited and chunk 2 is gone; chunk 0 is untouched.
+      let diff = diff_chunks(stored, vec![record(0, "a", 0), record(1, "b2", 3)]);
+
+      assert_eq!(diff.kept, 1);
+      let changed: Vec<&str> = diff.changed.iter().map(|r| r.content.as_str()).collect();
+      assert_eq!(changed, ["b2"]);
+      let mut stale = diff.stale;
+      stale.sort();
+      assert_eq!(stale, ["/repo/a.rs:1", "/repo/a.rs:2"]);
+   }
+
+   #[test]
+   fn diff_chunks_keeps_chunks_after_a_split() {
+      let stored: HashMap<String, FileHash> = [record(0, "ab", 0), record(1, "c", 6)]
+         .iter()
+         .map(|r| (r.id.clone(), record_fingerprint(r)))
+         .collect();
+
+      // Chunk 0 is split in two, so "c" moves from index 1 to 2.
+      let diff = diff_chunks(stored, vec![record(0, "a", 0), record(1, "b", 3), record(2, "c", 6)]);
+
+      assert_eq!(diff.kept, 1);
+      assert_eq!(diff.stale, ["/repo/a.rs:0"]);
+      let changed: Vec<&str> = diff.changed.iter().map(|r| r.content.as_str()).collect();
+      assert_eq!(changed, ["a", "b"]);
+      for record in &diff.changed {
+         assert!(record.id.starts_with("/repo/a.rs:"), "{}", record.id);
+         assert!(!["/repo/a.rs:0", "/repo/a.rs:1"].contains(&record.id.as_str()), "{}", record.id);
+      }
+   }
+
+   #[test]
+   fn fingerprint_covers_moved_lines() {
+      assert_ne!(record_fingerprint(&record(0, "a", 0)), record_fingerprint(&record(0, "a", 1)));
+   }
 }
@@ -0,0 +1,465 @@
+//! Branch stores that share the rows of their base store.
+//!
+//! A branch or worktree store, `<store>@<branch>`, would otherwise hold a full
+//! copy of the default branch's index. Once its metadata records the base it
+//! was started from, it holds rows only for the files that differ from the
+//! base, and [`OverlayStore`] reads the base's rows for the rest: moved from
+//! the base's root to the branch's, and leaving out the files the branch
+//! replaced or deleted. Writes only ever go to the branch store.
+
+use std::{
+   collections::{HashMap, HashSet},
+   path::{Path, PathBuf},
+   sync::Arc,
+   time::SystemTime,
+};
+
+use parking_lot::RwLock;
+
+use crate::{
+   embed::HybridEmbedding,
+   error::Result,
+   git,
+   meta::{self, FileHash},
+   store::{ChunkVector, FileSearchParams, KeywordParams, SearchParams, Store},
+   types::{IndexedFile, SearchResponse, SearchResult, StoreInfo, VectorRecord},
+};
+
+/// [`Store`] that layers branch stores over the rows of their base store.
+pub struct OverlayStore {
+   inner:  Box<dyn Store>,
+   /// Layer of each branch store looked up so far.
+   layers: RwLock<HashMap<String, Lookup>>,
+}
+
+struct Lookup {
+   /// When the store's metadata was saved, as of the lookup.
+   modified: Option<SystemTime>,
+   /// `None` for stores on their own.
+   layer:    Option<Arc<Layer>>,
+}
+
+/// How a branch store shares the rows of its base.
+struct Layer {
+   base:      String,
+   base_root: PathBuf,
+   root:      PathBuf,
+   /// Files, under `root`, whose base rows the branch replaced or deleted,
+   /// including those written since its metadata was last saved.
+   shadowed:  RwLock<HashSet<PathBuf>>,
+}
+
+impl Layer {
+   /// `path` under the base's root, if it lies under the branch's.
+   fn base_path(&self, path: &Path) -> Option<PathBuf> {
+      Some(self.base_root.join(path.strip_prefix(&self.root).ok()?))
+   }
+
+   /// Base `path` under the branch's root, if it lies under the base's.
+   fn branch_path(&self, path: &Path) -> Option<PathBuf> {
+      Some(self.root.join(path.strip_prefix(&self.base_root).ok()?))
+   }
+
+   /// Base `path` under the branch's root, or as is when it lies outside the
+   /// base's, unless the branch shadows it.
+   fn shared_path(&self, path: &Path) -> Option<PathBuf> {
+      let path = self.branch_path(path).unwrap_or_else(|| path.to_path_buf());
+      (!self.shadowed.read().contains(&path)).then_some(path)
+   }
+
+   /// Base `result` as the branch sees it; `None` if the branch shadows it.
+   fn shared_result(&self, mut result: SearchResult) -> Option<SearchResult> {
+      result.path = self.shared_path(&result.path)?;
+      for linked in &mut result.linked {
+         if let Some(path) = self.branch_path(&linked.path) {
+            linked.path = path;
+         }
+      }
+      Some(result)
+   }
+
+   /// Whether the base may hold rows under the path filter `path`.
+   fn shares(&self, path: Option<&Path>) -> bool {
+      path.is_none_or(|path| path.starts_with(&self.root))
+   }
+
+   fn shadow<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) {
+      self
+         .shadowed
+         .write()
+         .extend(paths.into_iter().map(Path::to_path_buf));
+   }
+}
+
+impl OverlayStore {
+   pub fn new(inner: Box<dyn Store>) -> Self {
+      Self { inner, layers: RwLock::new(HashMap::new()) }
+   }
+
+   /// Layer of `store_id`, reread whenever its metadata was saved since.
+   fn layer(&self, store_id: &str) -> Option<Arc<Layer>> {
+      git::base_store_id(store_id)?;
+      let modified = meta::modified(store_id);
+      if let Some(lookup) = self.layers.read().get(store_id)
+         && lookup.modified == modified
+      {
+         return lookup.layer.clone();
+      }
+      let layer = meta::overlay(store_id).map(|overlay| {
+         Arc::new(Layer {
+            base:      overlay.base,
+            base_root: overlay.base_root,
+            root:      overlay.root,
+            shadowed:  RwLock::new(overlay.shadowed),
+         })
+      });
+      self
+         .layers
+         .write()
+         .insert(store_id.to_string(), Lookup { modified, layer: layer.clone() });
+      layer
+   }
+
+   fn forget(&self, store_id: &str) {
+      self.layers.write().remove(store_id);
+   }
+}
+
+/// Fetches the best `limit` base results the branch does not shadow, asking
+/// `fetch` for more rows while shadowed ones crowd them out.
+async fn shared_results<F, Fut>(layer: &Layer, limit: usize, fetch: F) -> Result<Vec<SearchResult>>
+where
+   F: Fn(usize) -> Fut,
+   Fut: Future<Output = Result<Vec<SearchResult>>>,
+{
+   let mut fetch_limit = limit;
+   loop {
+      let results = fetch(fetch_limit).await?;
+      let exhausted = results.len() < fetch_limit;
+      let shared: Vec<SearchResult> = results
+         .into_iter()
+         .filter_map(|result| layer.shared_result(result))
+         .collect();
+      if shared.len() >= limit || exhausted {
+         return Ok(shared);
+      }
+      fetch_limit = fetch_limit.saturating_mul(2);
+   }
+}
+
+/// `own` and `shared` results merged by score, keeping the best `limit`.
+fn merge(mut own: Vec<SearchResult>, shared: Vec<SearchResult>, limit: usize) -> Vec<SearchResult> {
+   own.extend(shared);
+   own.sort_by(|a, b| b.score.total_cmp(&a.score));
+   own.truncate(limit);
+   own
+}
+
+#[async_trait::async_trait]
+impl Store for OverlayStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if let Some(layer) = self.layer(store_id) {
+         layer.shadow(records.iter().map(|record| record.path.as_path()));
+      }
+      self.inner.insert_batch(store_id, records).await
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let Some(layer) = self.layer(params.store_id) else {
+         return self.inner.search(params).await;
+      };
+      let limit = params.limit;
+      let shared = if layer.shares(params.path_filter) {
+         let path_filter = params.path_filter.and_then(|path| layer.base_path(path));
+         let path_globs = params
+            .path_globs
+            .map(|globs| globs.relative_to(&layer.root))
+            .transpose()?;
+         shared_results(&layer, limit, |limit| {
+            let search = self.inner.search(SearchParams {
+               store_id: &layer.base,
+               limit,
+               path_filter: path_filter.as_deref(),
+               path_globs: path_globs.as_ref(),
+               ..params
+            });
+            async move { Ok(search.await?.results) }
+         })
+         .await?
+      } else {
+         Vec::new()
+      };
+      let mut response = self.inner.search(params).await?;
+      response.results = merge(response.results, shared, limit);
+      Ok(response)
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let Some(layer) = self.layer(params.store_id) else {
+         return self.inner.keyword_search(params).await;
+      };
+      let limit = params.limit;
+      let shared = if layer.shares(params.path_filter) {
+         let path_filter = params.path_filter.and_then(|path| layer.base_path(path));
+         let path_globs = params
+            .path_globs
+            .map(|globs| globs.relative_to(&layer.root))
+            .transpose()?;
+         shared_results(&layer, limit, |limit| {
+            let search = self.inner.keyword_search(KeywordParams {
+               store_id: &layer.base,
+               limit,
+               path_filter: path_filter.as_deref(),
+               path_globs: path_globs.as_ref(),
+               ..params
+            });
+            async move { Ok(search.await?.results) }
+         })
+         .await?
+      } else {
+         Vec::new()
+      };
+      let mut response = self.inner.keyword_search(params).await?;
+      response.results = merge(response.results, shared, limit);
+      Ok(response)
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let own = self.inner.find_bridged(store_id, key, limit).await?;
+      let Some(layer) = self.layer(store_id) else {
+         return Ok(own);
+      };
+      let shared =
+         shared_results(&layer, limit, |limit| self.inner.find_bridged(&layer.base, key, limit))
+            .await?;
+      // Bridged chunks are confirmed rather than ranked, so own ones go first
+      let mut results = own;
+      results.extend(shared);
+      results.truncate(limit);
+      Ok(results)
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let Some(layer) = self.layer(params.store_id) else {
+         return self.inner.search_files(params).await;
+      };
+      let limit = params.limit;
+      let shared = if layer.shares(params.path_filter) {
+         let path_filter = params.path_filter.and_then(|path| layer.base_path(path));
+         shared_results(&layer, limit, |limit| {
+            self.inner.search_files(FileSearchParams {
+               store_id: &layer.base,
+               limit,
+               path_filter: path_filter.as_deref(),
+               ..params
+            })
+         })
+         .await?
+      } else {
+         Vec::new()
+      };
+      let own = self.inner.search_files(params).await?;
+      Ok(merge(own, shared, limit))
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      let mut chunks = self.inner.chunk_vectors(store_id).await?;
+      if let Some(layer) = self.layer(store_id) {
+         chunks.extend(
+            self
+               .inner
+               .chunk_vectors(&layer.base)
+               .await?
+               .into_iter()
+               .filter_map(|mut chunk| {
+                  chunk.path = layer.shared_path(&chunk.path)?;
+                  Some(chunk)
+               }),
+         );
+      }
+      Ok(chunks)
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      if let Some(layer) = self.layer(store_id) {
+         layer.shadow([file_path]);
+      }
+      self.inner.delete_file(store_id, file_path).await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      if let Some(layer) = self.layer(store_id) {
+         layer.shadow(file_paths.iter().map(PathBuf::as_path));
+      }
+      self.inner.delete_files(store_id, file_paths).await
+   }
+
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      if let Some(layer) = self.layer(store_id) {
+         layer.shadow(file_paths.iter().map(PathBuf::as_path));
+         layer.shadow(records.iter().map(|record| record.path.as_path()));
+      }
+      self
+         .inner
+         .replace_files(store_id, file_paths, records)
+         .await
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      self.forget(store_id);
+      self.inner.delete_store(store_id).await
+   }
+
+   /// Counts the base store's rows along with the branch's own.
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      let mut info = self.inner.get_info(store_id).await?;
+      if let Some(layer) = self.layer(store_id) {
+         info.row_count += self.inner.get_info(&layer.base).await?.row_count;
+      }
+      Ok(info)
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      let mut files = self.inner.list_files(store_id).await?;
+      if let Some(layer) = self.layer(store_id) {
+         files.extend(
+            self
+               .inner
+               .list_files(&layer.base)
+               .await?
+               .into_iter()
+               .filter_map(|mut file| {
+                  file.path = layer.shared_path(&file.path)?;
+                  Some(file)
+               }),
+         );
+      }
+      Ok(files)
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      if !self.inner.is_empty(store_id).await? {
+         return Ok(false);
+      }
+      match self.layer(store_id) {
+         Some(layer) => self.inner.is_empty(&layer.base).await,
+         None => Ok(true),
+      }
+   }
+
+   async fn create_fts_index(&self, store_id: &str) -> Result<()> {
+      self.inner.create_fts_index(store_id).await
+   }
+
+   async fn create_vector_index(&self, store_id: &str) -> Result<()> {
+      self.inner.create_vector_index(store_id).await
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      let own = self.inner.get_file_hashes(store_id).await?;
+      let Some(layer) = self.layer(store_id) else {
+         return Ok(own);
+      };
+      let mut hashes: HashMap<PathBuf, FileHash> = self
+         .inner
+         .get_file_hashes(&layer.base)
+         .await?
+         .into_iter()
+         .filter_map(|(path, hash)| Some((layer.shared_path(&path)?, hash)))
+         .collect();
+      hashes.extend(own);
+      Ok(hashes)
+   }
+
+   /// Chunks are keyed by content, so the base's embeddings of a file serve
+   /// the branch's copy of it even where the branch shadows it.
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      let own = self
+         .inner
+         .get_chunk_embeddings(store_id, file_paths)
+         .await?;
+      let Some(layer) = self.layer(store_id) else {
+         return Ok(own);
+      };
+      let base_paths: Vec<PathBuf> = file_paths
+         .iter()
+         .filter_map(|path| layer.base_path(path))
+         .collect();
+      let mut embeddings = self
+         .inner
+         .get_chunk_embeddings(&layer.base, &base_paths)
+         .await?;
+      embeddings.extend(own);
+      Ok(embeddings)
+   }
+
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      self.inner.optimize(store_id).await
+   }
+
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      if let Some(layer) = self.layer(store_id) {
+         self.inner.warm_up(&layer.base).await?;
+      }
+      self.inner.warm_up(store_id).await
+   }
+
+   fn set_root(&self, store_id: &str, root: Option<&Path>) {
+      self.forget(store_id);
+      self.inner.set_root(store_id, root);
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      self.inner.relativize(store_id, root).await
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn layer() -> Layer {
+      Layer {
+         base:      "repo".to_string(),
+         base_root: PathBuf::from("/main"),
+         root:      PathBuf::from("/work"),
+         shadowed:  RwLock::new([PathBuf::from("/work/old.rs")].into()),
+      }
+   }
+
+   #[test]
+   fn shared_paths_move_to_the_branch_root() {
+      let layer = layer();
+      assert_eq!(
+         layer.shared_path(Path::new("/main/src/lib.rs")),
+         Some(PathBuf::from("/work/src/lib.rs"))
+      );
+      assert_eq!(layer.shared_path(Path::new("/main/old.rs")), None);
+      assert_eq!(
+         layer.shared_path(Path::new("https://docs.rs/x")),
+         Some(PathBuf::from("https://docs.rs/x"))
+      );
+      assert_eq!(layer.base_path(Path::new("/work/src")), Some(PathBuf::from("/main/src")));
+   }
+
+   #[test]
+   fn written_files_shadow_the_base() {
+      let layer = layer();
+      layer.shadow([Path::new("/work/src/lib.rs")]);
+      assert_eq!(layer.shared_path(Path::new("/main/src/lib.rs")), None);
+   }
+}
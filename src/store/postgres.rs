@@ -0,0 +1,862 @@
+//! Postgres storage backend.
+//!
+//! Stores each index in its own table (`smgrep_<store_id>`) in a database
+//! with the pgvector extension. Dense vectors are ranked with the `<=>` cosine
+//! operator and keyword matches come from a generated `tsvector` column.
+//! Table layout and dimensions are tracked in `smgrep_stores` so upgrades and
+//! model changes can be detected on open. Inserts use binary `COPY`.
+
+use std::{
+   collections::{HashMap, HashSet},
+   fs,
+   path::{Path, PathBuf},
+   pin::pin,
+};
+
+use parking_lot::RwLock;
+use pgvector::Vector;
+use postgres_native_tls::MakeTlsConnector;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tokio_postgres::{Client, Row, binary_copy::BinaryCopyInWriter, types::Type};
+
+use crate::{
+   chunker::bridge,
+   config,
+   embed::HybridEmbedding,
+   error::Result,
+   meta::FileHash,
+   search::ranking,
+   store::{
+      self, ChunkVector, FileSearchParams, KeywordParams, SearchParams,
+      candidate::{self, Candidate, dot, sort_by_score},
+   },
+   types::{
+      ChunkType, IndexedFile, QueryRoute, SearchResponse, SearchResult, SearchStatus, StageTiming,
+      StoreInfo, VectorRecord, VectorSearchMode,
+   },
+};
+
+/// Current table layout version, recorded per store in `smgrep_stores`.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Longest identifier Postgres keeps without truncating.
+const MAX_IDENT_LEN: usize = 63;
+
+const STORES_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS smgrep_stores (
+   store_id       TEXT PRIMARY KEY,
+   table_name     TEXT NOT NULL,
+   schema_version INTEGER NOT NULL,
+   dense_dim      INTEGER NOT NULL
+)";
+
+const COPY_COLUMNS: &str = "id, path, hash, content, start_line, end_line, vector, colbert, \
+                            colbert_scale, chunk_index, is_anchor, chunk_type, context_prev, \
+                            context_next, indexed_at, crate_name, chunk_hash, bridges, language";
+
+const SELECT_COLUMNS: &str = "path, content, start_line, end_line, vector, colbert, \
+                              colbert_scale, is_anchor, chunk_type, context_prev, context_next, \
+                              bridges, language";
+
+const ANCHOR_FILTER: &str = "is_anchor IS NOT TRUE";
+const DOC_CLAUSE: &str = "(path LIKE '%.md' OR path LIKE '%.mdx' OR path LIKE '%.txt' OR path \
+                          LIKE '%.json' OR COALESCE(chunk_type, '') = 'doc')";
+const FILTER_CLAUSE: &str = "($2::text IS NULL OR starts_with(path, $2)) AND ($3::int8 IS NULL OR \
+                             indexed_at >= $3) AND ($4::text IS NULL OR crate_name = $4)";
+
+/// Errors that can occur during Postgres store operations.
+#[derive(Debug, thiserror::Error)]
+pub enum PostgresStoreError {
+   #[error("failed to set up TLS: {0}")]
+   Tls(#[from] native_tls::Error),
+
+   #[error("failed to read CA certificate {path}: {source}", path = path.display())]
+   ReadCaCert {
+      path:   PathBuf,
+      #[source]
+      source: std::io::Error,
+   },
+
+   #[error("failed to connect to postgres: {0}")]
+   Connect(#[source] tokio_postgres::Error),
+
+   #[error("failed to initialize schema: {0}")]
+   InitSchema(#[source] tokio_postgres::Error),
+
+   #[error(
+      "index uses schema v{found}, but this smgrep supports up to v{supported}; upgrade smgrep or \
+       run `smgrep clean`"
+   )]
+   SchemaTooNew { found: u32, supported: u32 },
+
+   #[error("failed to insert records: {0}")]
+   Insert(#[source] tokio_postgres::Error),
+
+   #[error("failed to execute query: {0}")]
+   Query(#[source] tokio_postgres::Error),
+
+   #[error("failed to delete records: {0}")]
+   Delete(#[source] tokio_postgres::Error),
+
+   #[error("failed to update records: {0}")]
+   Update(#[source] tokio_postgres::Error),
+}
+
+/// Postgres-backed vector store.
+pub struct PostgresStore {
+   url:    String,
+   tls:    MakeTlsConnector,
+   client: OnceCell<Client>,
+   tables: RwLock<HashSet<String>>,
+}
+
+impl PostgresStore {
+   /// Prepares a store for the server configured by `postgres_url`; the
+   /// connection is opened on first use.
+   pub fn new() -> Result<Self> {
+      let cfg = config::get();
+      Self::with_url(&cfg.postgres_url, cfg.postgres_ca_cert.as_deref())
+   }
+
+   pub fn with_url(url: &str, ca_cert: Option<&Path>) -> Result<Self> {
+      let mut builder = native_tls::TlsConnector::builder();
+      if let Some(path) = ca_cert {
+         let pem = fs::read(path).map_err(|source| PostgresStoreError::ReadCaCert {
+            path: path.to_path_buf(),
+            source,
+         })?;
+         builder.add_root_certificate(
+            native_tls::Certificate::from_pem(&pem).map_err(PostgresStoreError::Tls)?,
+         );
+      }
+      let tls = MakeTlsConnector::new(builder.build().map_err(PostgresStoreError::Tls)?);
+
+      Ok(Self {
+         url: url.to_string(),
+         tls,
+         client: OnceCell::new(),
+         tables: RwLock::new(HashSet::new()),
+      })
+   }
+
+   async fn client(&self) -> Result<&Client, PostgresStoreError> {
+      self
+         .client
+         .get_or_try_init(|| async {
+            let (client, connection) = tokio_postgres::connect(&self.url, self.tls.clone())
+               .await
+               .map_err(PostgresStoreError::Connect)?;
+            tokio::spawn(async move {
+               if let Err(e) = connection.await {
+                  tracing::warn!("postgres connection closed: {e}");
+               }
+            });
+            client
+               .batch_execute("CREATE EXTENSION IF NOT EXISTS vector")
+               .await
+               .map_err(PostgresStoreError::InitSchema)?;
+            client
+               .batch_execute(STORES_TABLE)
+               .await
+               .map_err(PostgresStoreError::InitSchema)?;
+            Ok(client)
+         })
+         .await
+   }
+
+   /// Returns the quoted table name for a store.
+   ///
+   /// IDs that would exceed the identifier limit are replaced by a hash so
+   /// distinct stores never collide after truncation.
+   fn table_name(store_id: &str) -> String {
+      let mut name = format!("smgrep_{store_id}");
+      if name.len() > MAX_IDENT_LEN {
+         let digest = Sha256::digest(store_id.as_bytes());
+         name = format!("smgrep_{}", &hex::encode(digest)[..32]);
+      }
+      format!("\"{}\"", name.replace('"', "\"\""))
+   }
+
+   /// Creates the store's table on first use, rebuilding it if it was created
+   /// for a different vector dimension.
+   async fn ensure_table(&self, store_id: &str) -> Result<(&Client, String), PostgresStoreError> {
+      let client = self.client().await?;
+      let table = Self::table_name(store_id);
+      if self.tables.read().contains(&table) {
+         return Ok((client, table));
+      }
+
+      let dim = config::get().dense_vector_dim();
+      let row = client
+         .query_opt("SELECT schema_version, dense_dim FROM smgrep_stores WHERE store_id = $1", &[
+            &store_id,
+         ])
+         .await
+         .map_err(PostgresStoreError::InitSchema)?;
+
+      if let Some(row) = &row {
+         let version = row.get::<_, i32>(0) as u32;
+         if version > SCHEMA_VERSION {
+            return Err(PostgresStoreError::SchemaTooNew {
+               found:     version,
+               supported: SCHEMA_VERSION,
+            });
+         }
+         let stored_dim = row.get::<_, i32>(1) as usize;
+         if stored_dim != dim {
+            tracing::warn!("index {store_id} has {stored_dim}-dim vectors; clearing for re-index");
+            client
+               .batch_execute(&format!("DROP TABLE IF EXISTS {table}"))
+               .await
+               .map_err(PostgresStoreError::Delete)?;
+         }
+      }
+
+      client
+         .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+               id            TEXT NOT NULL,
+               path          TEXT NOT NULL,
+               hash          BYTEA NOT NULL,
+               content       TEXT NOT NULL,
+               start_line    INTEGER NOT NULL,
+               end_line      INTEGER NOT NULL,
+               vector        vector({dim}) NOT NULL,
+               colbert       BYTEA,
+               colbert_scale DOUBLE PRECISION,
+               chunk_index   INTEGER,
+               is_anchor     BOOLEAN,
+               chunk_type    TEXT,
+               context_prev  TEXT,
+               context_next  TEXT,
+               indexed_at    BIGINT,
+               crate_name    TEXT,
+               chunk_hash    BYTEA,
+               bridges       TEXT,
+               language      TEXT,
+               content_tsv   tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED
+            );
+            CREATE INDEX IF NOT EXISTS {path_index} ON {table} (path);",
+            path_index = Self::index_name(&table, "path"),
+         ))
+         .await
+         .map_err(PostgresStoreError::InitSchema)?;
+      if row.as_ref().is_some_and(|r| r.get::<_, i32>(0) < 2) {
+         client
+            .batch_execute(&format!(
+               "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS chunk_hash BYTEA"
+            ))
+            .await
+            .map_err(PostgresStoreError::InitSchema)?;
+      }
+      if row.as_ref().is_some_and(|r| r.get::<_, i32>(0) < 3) {
+         client
+            .batch_execute(&format!("ALTER TABLE {table} ADD COLUMN IF NOT EXISTS bridges TEXT"))
+            .await
+            .map_err(PostgresStoreError::InitSchema)?;
+      }
+      if row.as_ref().is_some_and(|r| r.get::<_, i32>(0) < 4) {
+         client
+            .batch_execute(&format!("ALTER TABLE {table} ADD COLUMN IF NOT EXISTS language TEXT"))
+            .await
+            .map_err(PostgresStoreError::InitSchema)?;
+      }
+
+      client
+         .execute(
+            "INSERT INTO smgrep_stores (store_id, table_name, schema_version, dense_dim) VALUES \
+             ($1, $2, $3, $4) ON CONFLICT (store_id) DO UPDATE SET table_name = $2, \
+             schema_version = $3, dense_dim = $4",
+            &[&store_id, &table, &(SCHEMA_VERSION as i32), &(dim as i32)],
+         )
+         .await
+         .map_err(PostgresStoreError::InitSchema)?;
+
+      self.tables.write().insert(table.clone());
+      Ok((client, table))
+   }
+
+   /// Builds a quoted index name derived from a quoted table name.
+   fn index_name(table: &str, suffix: &str) -> String {
+      let base = table.trim_matches('"');
+      let max = MAX_IDENT_LEN - suffix.len() - 1;
+      let base = &base[..base.floor_char_boundary(max.min(base.len()))];
+      format!("\"{base}_{suffix}\"")
+   }
+
+   async fn query_candidates(
+      client: &Client,
+      sql: &str,
+      params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+   ) -> Result<Vec<Candidate>, PostgresStoreError> {
+      let rows = client
+         .query(sql, params)
+         .await
+         .map_err(PostgresStoreError::Query)?;
+      Ok(rows.iter().map(candidate_from_row).collect())
+   }
+
+   /// Computes query similarity against the anchor chunk of each distinct
+   /// file in `results`.
+   async fn anchor_similarities(
+      client: &Client,
+      table: &str,
+      results: &[SearchResult],
+      query_vector: &[f32],
+   ) -> Result<HashMap<PathBuf, f32>, PostgresStoreError> {
+      let paths: Vec<String> = results
+         .iter()
+         .map(|r| store::path_to_store_value(&r.path))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+
+      let rows = client
+         .query(&format!("SELECT path, vector FROM {table} WHERE is_anchor AND path = ANY($1)"), &[
+            &paths,
+         ])
+         .await
+         .map_err(PostgresStoreError::Query)?;
+
+      Ok(rows
+         .iter()
+         .map(|row| {
+            let vector: Vector = row.get(1);
+            (PathBuf::from(row.get::<_, String>(0)), dot(query_vector, vector.as_slice()))
+         })
+         .collect())
+   }
+
+   async fn search_inner(
+      &self,
+      params: &SearchParams<'_>,
+   ) -> Result<(Vec<SearchResult>, StageTiming), PostgresStoreError> {
+      let (client, table) = self.ensure_table(params.store_id).await?;
+
+      let query_vector = Vector::from(params.query_vector.to_vec());
+      let prefix = params.path_filter.map(store::path_to_store_value);
+      let indexed_after = params.indexed_after.map(|t| t as i64);
+      let crate_name = params.crate_name;
+      // The HNSW index only serves a bare `vector <=> $1` ordering; any other
+      // expression makes the planner fall back to an exact sequential scan.
+      let distance = if params.exact {
+         "(vector <=> $1) + 0"
+      } else {
+         "vector <=> $1"
+      };
+
+      let knn = |doc: bool, k: usize| {
+         let kind = if doc {
+            DOC_CLAUSE.to_string()
+         } else {
+            format!("NOT {DOC_CLAUSE}")
+         };
+         format!(
+            "SELECT {SELECT_COLUMNS} FROM {table} WHERE {FILTER_CLAUSE} AND {kind} AND \
+             {ANCHOR_FILTER} ORDER BY {distance} LIMIT {k}"
+         )
+      };
+
+      let mut candidates =
+         Self::query_candidates(client, &knn(false, params.dense_candidates()), &[
+            &query_vector,
+            &prefix,
+            &indexed_after,
+            &crate_name,
+         ])
+         .await?;
+      candidates.extend(
+         Self::query_candidates(client, &knn(true, 50), &[
+            &query_vector,
+            &prefix,
+            &indexed_after,
+            &crate_name,
+         ])
+         .await?,
+      );
+
+      let fts_sql = format!(
+         "SELECT {SELECT_COLUMNS} FROM {table}, websearch_to_tsquery('simple', $1) q WHERE \
+          content_tsv @@ q AND {FILTER_CLAUSE} AND {ANCHOR_FILTER} ORDER BY ts_rank(content_tsv, \
+          q) DESC LIMIT 50"
+      );
+      match Self::query_candidates(client, &fts_sql, &[
+         &params.query_text,
+         &prefix,
+         &indexed_after,
+         &crate_name,
+      ])
+      .await
+      {
+         Ok(mut rows) => {
+            candidate::mark_fts(&mut rows);
+            candidates.extend(rows);
+         },
+         Err(e) => tracing::debug!("skipping full-text search: {e}"),
+      }
+
+      let query_colbert =
+         (params.rerank && !params.query_colbert.is_empty()).then_some(params.query_colbert);
+      let (mut results, rerank) = candidate::rank(
+         candidates,
+         params.query_vector,
+         query_colbert,
+         params.rerank_count(),
+         params.cancel,
+      );
+
+      let anchor_weight = config::get().anchor_weight;
+      if anchor_weight > 0.0 && !results.is_empty() {
+         const ANCHOR_CAP: usize = 100;
+         let head = results.len().min(ANCHOR_CAP);
+
+         match Self::anchor_similarities(client, &table, &results[..head], params.query_vector)
+            .await
+         {
+            Ok(sims) => {
+               ranking::apply_anchor_boost(&mut results[..head], &sims, anchor_weight);
+               sort_by_score(&mut results, |r| r.score);
+            },
+            Err(e) => tracing::debug!("skipping anchor boost: {e}"),
+         }
+      }
+
+      results.truncate(params.limit);
+      Ok((results, rerank))
+   }
+
+   async fn keyword_inner(
+      &self,
+      params: &KeywordParams<'_>,
+   ) -> Result<Vec<SearchResult>, PostgresStoreError> {
+      let (client, table) = self.ensure_table(params.store_id).await?;
+
+      let prefix = params.path_filter.map(store::path_to_store_value);
+      let indexed_after = params.indexed_after.map(|t| t as i64);
+      let sql = format!(
+         "SELECT {SELECT_COLUMNS} FROM {table}, websearch_to_tsquery('simple', $1) q WHERE \
+          content_tsv @@ q AND {FILTER_CLAUSE} AND {ANCHOR_FILTER} ORDER BY ts_rank(content_tsv, \
+          q) DESC LIMIT {}",
+         params.limit
+      );
+      let candidates = Self::query_candidates(client, &sql, &[
+         &params.query_text,
+         &prefix,
+         &indexed_after,
+         &params.crate_name,
+      ])
+      .await?;
+      Ok(candidate::rank_by_position(candidates))
+   }
+
+   async fn bridged_inner(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>, PostgresStoreError> {
+      let (client, table) = self.ensure_table(store_id).await?;
+
+      let sql = format!(
+         "SELECT {SELECT_COLUMNS} FROM {table} WHERE bridges LIKE $1 AND {ANCHOR_FILTER} LIMIT \
+          {limit}"
+      );
+      let pattern = bridge::like_pattern(key);
+      let candidates = Self::query_candidates(client, &sql, &[&pattern]).await?;
+      Ok(candidate::bridged(candidates))
+   }
+
+   async fn files_inner(
+      &self,
+      params: &FileSearchParams<'_>,
+   ) -> Result<Vec<SearchResult>, PostgresStoreError> {
+      let (client, table) = self.ensure_table(params.store_id).await?;
+
+      let sql = format!(
+         "SELECT {SELECT_COLUMNS} FROM {table} WHERE is_anchor IS TRUE AND ($2::text IS NULL OR \
+          starts_with(path, $2)) ORDER BY vector <=> $1 LIMIT {}",
+         params.limit
+      );
+      let query_vector = Vector::from(params.query_vector.to_vec());
+      let prefix = params.path_filter.map(store::path_to_store_value);
+      let candidates = Self::query_candidates(client, &sql, &[&query_vector, &prefix]).await?;
+      Ok(candidate::rank_anchors(candidates, params.query_vector, params.limit))
+   }
+
+   async fn insert_records(
+      &self,
+      store_id: &str,
+      records: &[VectorRecord],
+   ) -> Result<(), PostgresStoreError> {
+      let (client, table) = self.ensure_table(store_id).await?;
+
+      // pgvector is an extension, so its type OID has to be looked up.
+      let vector_type = client
+         .prepare("SELECT $1::vector")
+         .await
+         .map_err(PostgresStoreError::Insert)?
+         .params()[0]
+         .clone();
+      let types = [
+         Type::TEXT,
+         Type::TEXT,
+         Type::BYTEA,
+         Type::TEXT,
+         Type::INT4,
+         Type::INT4,
+         vector_type,
+         Type::BYTEA,
+         Type::FLOAT8,
+         Type::INT4,
+         Type::BOOL,
+         Type::TEXT,
+         Type::TEXT,
+         Type::TEXT,
+         Type::INT8,
+         Type::TEXT,
+         Type::BYTEA,
+         Type::TEXT,
+         Type::TEXT,
+      ];
+
+      let sink = client
+         .copy_in(&format!("COPY {table} ({COPY_COLUMNS}) FROM STDIN BINARY"))
+         .await
+         .map_err(PostgresStoreError::Insert)?;
+      let mut writer = pin!(BinaryCopyInWriter::new(sink, &types));
+
+      for record in records {
+         let path = store::path_to_store_value(&record.path);
+         let vector = Vector::from(record.vector.clone());
+         let colbert = (!record.colbert.is_empty()).then_some(record.colbert.as_slice());
+         let chunk_index = record.chunk_index.map(|i| i as i32);
+         let chunk_type = record.chunk_type.map(ChunkType::as_lowercase_str);
+         let indexed_at = record.indexed_at.map(|t| t as i64);
+
+         writer
+            .as_mut()
+            .write(&[
+               &record.id,
+               &path,
+               &record.hash.as_ref(),
+               &record.content.as_str(),
+               &(record.start_line as i32),
+               &(record.end_line as i32),
+               &vector,
+               &colbert,
+               &record.colbert_scale,
+               &chunk_index,
+               &record.is_anchor,
+               &chunk_type,
+               &record.context_prev.as_deref(),
+               &record.context_next.as_deref(),
+               &indexed_at,
+               &record.crate_name.as_deref(),
+               &record.chunk_hash.as_ref(),
+               &record.bridges.as_deref(),
+               &record.language.as_deref(),
+            ])
+            .await
+            .map_err(PostgresStoreError::Insert)?;
+      }
+
+      writer.finish().await.map_err(PostgresStoreError::Insert)?;
+      Ok(())
+   }
+}
+
+/// Reads a [`Candidate`] from a row selected with [`SELECT_COLUMNS`].
+fn candidate_from_row(row: &Row) -> Candidate {
+   Candidate {
+      path:          PathBuf::from(row.get::<_, String>(0)),
+      content:       row.get(1),
+      start_line:    row.get::<_, i32>(2) as u32,
+      end_line:      row.get::<_, i32>(3) as u32,
+      vector:        row.get::<_, Vector>(4).to_vec(),
+      colbert:       row.get(5),
+      colbert_scale: row.get::<_, Option<f64>>(6).unwrap_or(1.0),
+      is_anchor:     row.get(7),
+      chunk_type:    row
+         .get::<_, Option<String>>(8)
+         .map(|s| ChunkType::from_lowercase_str(&s)),
+      context_prev:  row.get(9),
+      context_next:  row.get(10),
+      fts:           None,
+      bridges:       row.get(11),
+      language:      row.get(12),
+      score:         0.0,
+   }
+}
+
+#[async_trait::async_trait]
+impl super::Store for PostgresStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if records.is_empty() {
+         return Ok(());
+      }
+      Ok(self.insert_records(store_id, &records).await?)
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let (results, rerank) = self.search_inner(&params).await?;
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: if params.exact {
+            VectorSearchMode::Exact
+         } else {
+            VectorSearchMode::Approximate
+         },
+         route: QueryRoute::Semantic,
+         stages: vec![rerank],
+      })
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let results = self.keyword_inner(&params).await?;
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: VectorSearchMode::Keyword,
+         route: QueryRoute::Semantic,
+         stages: Vec::new(),
+      })
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      Ok(self.bridged_inner(store_id, key, limit).await?)
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      Ok(self.files_inner(&params).await?)
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      let sql = format!("SELECT {SELECT_COLUMNS} FROM {table} WHERE is_anchor IS NOT TRUE");
+      let candidates = Self::query_candidates(client, &sql, &[]).await?;
+      Ok(candidate::chunk_vectors(candidates))
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      self
+         .delete_files(store_id, &[file_path.to_path_buf()])
+         .await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      if file_paths.is_empty() {
+         return Ok(());
+      }
+
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+
+      let (client, table) = self.ensure_table(store_id).await?;
+      client
+         .execute(&format!("DELETE FROM {table} WHERE path = ANY($1)"), &[&paths])
+         .await
+         .map_err(PostgresStoreError::Delete)?;
+      Ok(())
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      if file_paths.is_empty() {
+         return Ok(HashMap::new());
+      }
+
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect();
+
+      let (client, table) = self.ensure_table(store_id).await?;
+      let rows = client
+         .query(
+            &format!(
+               "SELECT chunk_hash, content, vector, colbert, colbert_scale FROM {table} WHERE \
+                path = ANY($1)"
+            ),
+            &[&paths],
+         )
+         .await
+         .map_err(PostgresStoreError::Query)?;
+
+      Ok(rows
+         .iter()
+         .map(|row| {
+            // Rows written before chunk hashes were stored fall back to
+            // hashing their content.
+            let chunk_hash = row
+               .get::<_, Option<&[u8]>>(0)
+               .and_then(FileHash::from_slice)
+               .unwrap_or_else(|| FileHash::sum(row.get::<_, &str>(1)));
+            (chunk_hash, HybridEmbedding {
+               dense:         row.get::<_, Vector>(2).to_vec(),
+               colbert:       row.get::<_, Option<Vec<u8>>>(3).unwrap_or_default(),
+               colbert_scale: row.get::<_, Option<f64>>(4).unwrap_or(1.0),
+            })
+         })
+         .collect())
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      let client = self.client().await?;
+      let table = Self::table_name(store_id);
+      self.tables.write().remove(&table);
+
+      client
+         .batch_execute(&format!("DROP TABLE IF EXISTS {table}"))
+         .await
+         .map_err(PostgresStoreError::Delete)?;
+      client
+         .execute("DELETE FROM smgrep_stores WHERE store_id = $1", &[&store_id])
+         .await
+         .map_err(PostgresStoreError::Delete)?;
+      Ok(())
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      let row_count: i64 = client
+         .query_one(&format!("SELECT COUNT(*) FROM {table}"), &[])
+         .await
+         .map_err(PostgresStoreError::Query)?
+         .get(0);
+
+      Ok(StoreInfo {
+         store_id:        store_id.to_string(),
+         row_count:       row_count as u64,
+         path:            PathBuf::from(table.trim_matches('"')),
+         dense_dim:       config::get().dense_vector_dim(),
+         dense_quantized: false,
+      })
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      let rows = client
+         .query(&format!("SELECT path, MAX(indexed_at) FROM {table} GROUP BY path"), &[])
+         .await
+         .map_err(PostgresStoreError::Query)?;
+
+      Ok(rows
+         .iter()
+         .map(|row| IndexedFile {
+            path:       PathBuf::from(row.get::<_, String>(0)),
+            indexed_at: row.get::<_, Option<i64>>(1).map(|t| t as u64),
+         })
+         .collect())
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      let exists: bool = client
+         .query_one(&format!("SELECT EXISTS(SELECT 1 FROM {table})"), &[])
+         .await
+         .map_err(PostgresStoreError::Query)?
+         .get(0);
+      Ok(!exists)
+   }
+
+   async fn create_fts_index(&self, store_id: &str) -> Result<()> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      client
+         .batch_execute(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {table} USING gin (content_tsv)",
+            Self::index_name(&table, "fts"),
+         ))
+         .await
+         .map_err(PostgresStoreError::Query)?;
+      Ok(())
+   }
+
+   async fn create_vector_index(&self, store_id: &str) -> Result<()> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      client
+         .batch_execute(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {table} USING hnsw (vector vector_cosine_ops)",
+            Self::index_name(&table, "vec"),
+         ))
+         .await
+         .map_err(PostgresStoreError::Query)?;
+      Ok(())
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      let (client, table) = self.ensure_table(store_id).await?;
+      let rows = client
+         .query(&format!("SELECT DISTINCT ON (path) path, hash FROM {table}"), &[])
+         .await
+         .map_err(PostgresStoreError::Query)?;
+
+      let mut hashes = HashMap::new();
+      for row in rows {
+         let path: String = row.get(0);
+         let Some(hash) = FileHash::from_slice(row.get(1)) else {
+            tracing::warn!("skipping corrupted hash for path: {path:?}");
+            continue;
+         };
+         hashes.insert(PathBuf::from(path), hash);
+      }
+      Ok(hashes)
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      let Some((prefix, len)) = store::root_prefix(root) else {
+         return Ok(false);
+      };
+      let (client, table) = self.ensure_table(store_id).await?;
+      client
+         .execute(
+            &format!(
+               "UPDATE {table} SET path = substr(path, $2), id = substr(id, $2) WHERE \
+                substr(path, 1, $3) = $1"
+            ),
+            &[&prefix, &(len as i32 + 1), &(len as i32)],
+         )
+         .await
+         .map_err(PostgresStoreError::Update)?;
+      Ok(true)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn table_names_are_quoted_and_bounded() {
+      assert_eq!(PostgresStore::table_name("owner-repo"), "\"smgrep_owner-repo\"");
+      assert_eq!(PostgresStore::table_name("a\"b"), "\"smgrep_a\"\"b\"");
+
+      let long = "x".repeat(100);
+      let name = PostgresStore::table_name(&long);
+      assert!(name.trim_matches('"').len() <= MAX_IDENT_LEN);
+      assert_ne!(name, PostgresStore::table_name(&"x".repeat(101)));
+   }
+
+   #[test]
+   fn index_names_fit_identifier_limit() {
+      let table = PostgresStore::table_name(&"y".repeat(56));
+      let index = PostgresStore::index_name(&table, "path");
+      assert!(index.trim_matches('"').len() <= MAX_IDENT_LEN);
+      assert!(index.ends_with("_path\""));
+   }
+}
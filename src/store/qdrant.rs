@@ -0,0 +1,846 @@
+//! Qdrant storage backend.
+//!
+//! Talks to a remote Qdrant server over its REST API so several machines can
+//! share one index. Each store maps to a collection named
+//! `smgrep_<store_id>`; chunk metadata lives in the point payload, with
+//! keyword indexes on the fields used for filtering. Qdrant has no full-text
+//! ranking, so search is dense retrieval followed by `ColBERT` reranking.
+
+use std::{
+   collections::{HashMap, HashSet},
+   path::{Path, PathBuf},
+};
+
+use parking_lot::RwLock;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::{
+   chunker::bridge,
+   config,
+   embed::HybridEmbedding,
+   error::Result,
+   meta::FileHash,
+   search::ranking,
+   store::{
+      self, ChunkVector, FileSearchParams, SearchParams,
+      candidate::{self, Candidate, dot, sort_by_score},
+   },
+   types::{
+      ChunkType, IndexedFile, QueryRoute, SearchResponse, SearchResult, SearchStatus, StageTiming,
+      StoreInfo, VectorRecord, VectorSearchMode,
+   },
+};
+
+/// Prefix applied to store IDs to form collection names.
+const COLLECTION_PREFIX: &str = "smgrep_";
+
+/// Page size for scroll requests.
+const SCROLL_PAGE: usize = 1024;
+
+const DOC_EXTENSIONS: [&str; 4] = ["md", "mdx", "txt", "json"];
+
+/// Payload fields that get a keyword/integer index when a collection is
+/// created.
+const INDEXED_FIELDS: [(&str, &str); 7] = [
+   ("path", "keyword"),
+   ("dirs", "keyword"),
+   ("is_anchor", "bool"),
+   ("is_doc", "bool"),
+   ("indexed_at", "integer"),
+   ("crate_name", "keyword"),
+   ("bridges", "keyword"),
+];
+
+/// Errors that can occur during Qdrant store operations.
+#[derive(Debug, thiserror::Error)]
+pub enum QdrantStoreError {
+   #[error("request to qdrant failed: {0}")]
+   Request(#[from] reqwest::Error),
+
+   #[error("qdrant returned {status}: {body}")]
+   Status { status: StatusCode, body: String },
+
+   #[error("unexpected qdrant response: {0}")]
+   InvalidResponse(#[source] serde_json::Error),
+
+   #[error(
+      "collection {collection} has {found}-dimensional vectors, expected {expected}; run `smgrep \
+       clean` to recreate it"
+   )]
+   DimensionMismatch { collection: String, found: usize, expected: usize },
+}
+
+/// Chunk metadata stored alongside each point.
+#[derive(Serialize, Deserialize)]
+struct Payload {
+   path:          String,
+   #[serde(default, skip_deserializing)]
+   dirs:          Vec<String>,
+   hash:          String,
+   #[serde(default)]
+   content:       String,
+   #[serde(default)]
+   start_line:    u32,
+   #[serde(default)]
+   end_line:      u32,
+   #[serde(default)]
+   colbert:       Option<String>,
+   #[serde(default)]
+   colbert_scale: Option<f64>,
+   #[serde(default)]
+   chunk_index:   Option<u32>,
+   #[serde(default)]
+   is_anchor:     Option<bool>,
+   #[serde(default)]
+   is_doc:        bool,
+   #[serde(default)]
+   chunk_type:    Option<String>,
+   #[serde(default)]
+   context_prev:  Option<String>,
+   #[serde(default)]
+   context_next:  Option<String>,
+   #[serde(default)]
+   indexed_at:    Option<u64>,
+   #[serde(default)]
+   crate_name:    Option<String>,
+   #[serde(default)]
+   chunk_hash:    Option<String>,
+   #[serde(default)]
+   bridges:       Vec<String>,
+   #[serde(default)]
+   language:      Option<String>,
+}
+
+impl Payload {
+   fn from_record(record: &VectorRecord) -> Self {
+      let path = store::path_to_store_value(&record.path);
+      Self {
+         dirs: path_prefixes(&path),
+         is_doc: is_doc_path(&record.path) || record.chunk_type == Some(ChunkType::Doc),
+         path,
+         hash: hex::encode(record.hash),
+         content: record.content.to_string(),
+         start_line: record.start_line,
+         end_line: record.end_line,
+         colbert: (!record.colbert.is_empty()).then(|| hex::encode(&record.colbert)),
+         colbert_scale: Some(record.colbert_scale),
+         chunk_index: record.chunk_index,
+         is_anchor: record.is_anchor,
+         chunk_type: record.chunk_type.map(|t| t.as_lowercase_str().to_string()),
+         context_prev: record.context_prev.as_ref().map(ToString::to_string),
+         context_next: record.context_next.as_ref().map(ToString::to_string),
+         indexed_at: record.indexed_at,
+         crate_name: record.crate_name.as_ref().map(ToString::to_string),
+         chunk_hash: Some(hex::encode(record.chunk_hash)),
+         bridges: record
+            .bridges
+            .as_deref()
+            .map(|b| bridge::keys(b).map(str::to_string).collect())
+            .unwrap_or_default(),
+         language: record.language.as_ref().map(ToString::to_string),
+      }
+   }
+
+   fn into_candidate(self, vector: Vec<f32>) -> Candidate {
+      Candidate {
+         path: PathBuf::from(self.path),
+         content: self.content,
+         start_line: self.start_line,
+         end_line: self.end_line,
+         vector,
+         colbert: self.colbert.and_then(|c| hex::decode(c).ok()),
+         colbert_scale: self.colbert_scale.unwrap_or(1.0),
+         is_anchor: self.is_anchor,
+         chunk_type: self.chunk_type.map(|s| ChunkType::from_lowercase_str(&s)),
+         context_prev: self.context_prev,
+         context_next: self.context_next,
+         fts: None,
+         bridges: bridge::encode(&self.bridges).map(String::from),
+         language: self.language,
+         score: 0.0,
+      }
+   }
+}
+
+#[derive(Deserialize)]
+struct Point<P> {
+   payload: P,
+   #[serde(default)]
+   vector:  Option<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+struct ScrollPage<P> {
+   points:           Vec<Point<P>>,
+   next_page_offset: Option<Value>,
+}
+
+/// Subset of the payload read when listing files.
+#[derive(Deserialize)]
+struct FileEntry {
+   path:       String,
+   #[serde(default)]
+   hash:       String,
+   #[serde(default)]
+   indexed_at: Option<u64>,
+}
+
+/// Subset of the payload read when reusing chunk embeddings.
+#[derive(Deserialize)]
+struct ChunkEntry {
+   #[serde(default)]
+   chunk_hash:    Option<String>,
+   #[serde(default)]
+   content:       String,
+   #[serde(default)]
+   colbert:       Option<String>,
+   #[serde(default)]
+   colbert_scale: Option<f64>,
+}
+
+impl ChunkEntry {
+   /// Hash of the chunk content; points written before chunk hashes were
+   /// stored fall back to hashing their content.
+   fn chunk_hash(&self) -> FileHash {
+      self
+         .chunk_hash
+         .as_deref()
+         .and_then(|h| hex::decode(h).ok())
+         .and_then(|h| FileHash::from_slice(&h))
+         .unwrap_or_else(|| FileHash::sum(&self.content))
+   }
+}
+
+/// Derives a stable numeric point ID from a record ID.
+fn point_id(record_id: &str) -> u64 {
+   let digest = Sha256::digest(record_id.as_bytes());
+   u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+/// Returns every leading path segment of `path`, including the path itself,
+/// so a prefix filter becomes an exact keyword match.
+fn path_prefixes(path: &str) -> Vec<String> {
+   let mut prefixes: Vec<String> = path
+      .match_indices(['/', '\\'])
+      .filter(|&(i, _)| i > 0)
+      .map(|(i, _)| path[..i].to_string())
+      .collect();
+   prefixes.push(path.to_string());
+   prefixes
+}
+
+fn is_doc_path(path: &Path) -> bool {
+   path
+      .extension()
+      .and_then(|e| e.to_str())
+      .is_some_and(|e| DOC_EXTENSIONS.contains(&e))
+}
+
+fn match_condition(key: &str, value: impl Serialize) -> Value {
+   json!({ "key": key, "match": { "value": value } })
+}
+
+fn paths_filter(paths: &[String]) -> Value {
+   json!({ "must": [{ "key": "path", "match": { "any": paths } }] })
+}
+
+/// Builds the filter applied to non-anchor search candidates.
+fn search_filter(
+   path_filter: Option<&str>,
+   indexed_after: Option<u64>,
+   crate_name: Option<&str>,
+   doc: bool,
+) -> Value {
+   let mut must = vec![match_condition("is_doc", doc)];
+   if let Some(prefix) = path_filter {
+      must.push(match_condition("dirs", prefix.trim_end_matches(['/', '\\'])));
+   }
+   if let Some(ts) = indexed_after {
+      must.push(json!({ "key": "indexed_at", "range": { "gte": ts } }));
+   }
+   if let Some(name) = crate_name {
+      must.push(match_condition("crate_name", name));
+   }
+   json!({
+      "must": must,
+      "must_not": [match_condition("is_anchor", true)],
+   })
+}
+
+/// Qdrant-backed vector store.
+pub struct QdrantStore {
+   client:      reqwest::Client,
+   base_url:    String,
+   api_key:     Option<String>,
+   collections: RwLock<HashSet<String>>,
+}
+
+impl QdrantStore {
+   /// Connects to the server configured by `qdrant_url` / `qdrant_api_key`.
+   pub fn new() -> Result<Self> {
+      let cfg = config::get();
+      Self::with_url(&cfg.qdrant_url, cfg.qdrant_api_key.clone())
+   }
+
+   pub fn with_url(url: &str, api_key: Option<String>) -> Result<Self> {
+      let client = reqwest::Client::builder()
+         .build()
+         .map_err(QdrantStoreError::Request)?;
+      Ok(Self {
+         client,
+         base_url: url.trim_end_matches('/').to_string(),
+         api_key,
+         collections: RwLock::new(HashSet::new()),
+      })
+   }
+
+   fn collection(store_id: &str) -> String {
+      format!("{COLLECTION_PREFIX}{store_id}")
+   }
+
+   /// Sends a request and returns the `result` field of the response, or
+   /// `None` if the collection does not exist.
+   async fn call(
+      &self,
+      method: Method,
+      path: &str,
+      body: Option<Value>,
+   ) -> Result<Option<Value>, QdrantStoreError> {
+      let mut req = self
+         .client
+         .request(method, format!("{}{path}", self.base_url));
+      if let Some(key) = &self.api_key {
+         req = req.header("api-key", key);
+      }
+      if let Some(body) = body {
+         req = req.json(&body);
+      }
+
+      let resp = req.send().await?;
+      let status = resp.status();
+      if status == StatusCode::NOT_FOUND {
+         return Ok(None);
+      }
+      if !status.is_success() {
+         let body = resp.text().await.unwrap_or_default();
+         return Err(QdrantStoreError::Status { status, body });
+      }
+
+      let mut value: Value = resp.json().await?;
+      Ok(Some(value.get_mut("result").map(Value::take).unwrap_or_default()))
+   }
+
+   async fn call_as<T: serde::de::DeserializeOwned>(
+      &self,
+      method: Method,
+      path: &str,
+      body: Option<Value>,
+   ) -> Result<Option<T>, QdrantStoreError> {
+      self
+         .call(method, path, body)
+         .await?
+         .map(serde_json::from_value)
+         .transpose()
+         .map_err(QdrantStoreError::InvalidResponse)
+   }
+
+   /// Creates the collection and its payload indexes on first use, or checks
+   /// that an existing collection matches the configured dimension.
+   async fn ensure_collection(&self, store_id: &str) -> Result<String, QdrantStoreError> {
+      let name = Self::collection(store_id);
+      if self.collections.read().contains(&name) {
+         return Ok(name);
+      }
+
+      let dim = config::get().dense_vector_dim();
+      let path = format!("/collections/{name}");
+      if let Some(info) = self.call(Method::GET, &path, None).await? {
+         let found = info
+            .pointer("/config/params/vectors/size")
+            .and_then(Value::as_u64)
+            .unwrap_or(dim as u64) as usize;
+         if found != dim {
+            return Err(QdrantStoreError::DimensionMismatch {
+               collection: name,
+               found,
+               expected: dim,
+            });
+         }
+      } else {
+         let body = json!({ "vectors": { "size": dim, "distance": "Cosine" } });
+         self.call(Method::PUT, &path, Some(body)).await?;
+         for (field, schema) in INDEXED_FIELDS {
+            let body = json!({ "field_name": field, "field_schema": schema });
+            self
+               .call(Method::PUT, &format!("{path}/index?wait=true"), Some(body))
+               .await?;
+         }
+      }
+
+      self.collections.write().insert(name.clone());
+      Ok(name)
+   }
+
+   /// Reads every point matching `filter`, following scroll pagination.
+   async fn scroll<P: serde::de::DeserializeOwned>(
+      &self,
+      store_id: &str,
+      filter: Option<Value>,
+      with_payload: Value,
+      with_vector: bool,
+   ) -> Result<Vec<Point<P>>, QdrantStoreError> {
+      let path = format!("/collections/{}/points/scroll", Self::collection(store_id));
+      let mut points = Vec::new();
+      let mut offset = Value::Null;
+
+      loop {
+         let body = json!({
+            "filter": filter,
+            "limit": SCROLL_PAGE,
+            "offset": offset,
+            "with_payload": with_payload,
+            "with_vector": with_vector,
+         });
+         let Some(page) = self
+            .call_as::<ScrollPage<P>>(Method::POST, &path, Some(body))
+            .await?
+         else {
+            break;
+         };
+         points.extend(page.points);
+         match page.next_page_offset {
+            Some(next) if !next.is_null() => offset = next,
+            _ => break,
+         }
+      }
+
+      Ok(points)
+   }
+
+   async fn knn(
+      &self,
+      collection: &str,
+      vector: &[f32],
+      filter: Value,
+      limit: usize,
+      exact: bool,
+   ) -> Result<Vec<Candidate>, QdrantStoreError> {
+      let body = json!({
+         "vector": vector,
+         "filter": filter,
+         "limit": limit,
+         "params": { "exact": exact },
+         "with_payload": true,
+         "with_vector": true,
+      });
+      let points: Vec<Point<Payload>> = self
+         .call_as(Method::POST, &format!("/collections/{collection}/points/search"), Some(body))
+         .await?
+         .unwrap_or_default();
+
+      Ok(points
+         .into_iter()
+         .map(|p| p.payload.into_candidate(p.vector.unwrap_or_default()))
+         .collect())
+   }
+
+   /// Computes query similarity against the anchor chunk of each distinct
+   /// file in `results`.
+   async fn anchor_similarities(
+      &self,
+      store_id: &str,
+      results: &[SearchResult],
+      query_vector: &[f32],
+   ) -> Result<HashMap<PathBuf, f32>, QdrantStoreError> {
+      let paths: Vec<String> = results
+         .iter()
+         .map(|r| store::path_to_store_value(&r.path))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+
+      let mut filter = paths_filter(&paths);
+      filter["must"]
+         .as_array_mut()
+         .expect("filter has must clause")
+         .push(match_condition("is_anchor", true));
+
+      let anchors: Vec<Point<FileEntry>> = self
+         .scroll(store_id, Some(filter), json!(["path"]), true)
+         .await?;
+
+      Ok(anchors
+         .into_iter()
+         .map(|p| {
+            let sim = dot(query_vector, p.vector.as_deref().unwrap_or_default());
+            (PathBuf::from(p.payload.path), sim)
+         })
+         .collect())
+   }
+
+   async fn search_inner(
+      &self,
+      params: &SearchParams<'_>,
+   ) -> Result<(Vec<SearchResult>, StageTiming), QdrantStoreError> {
+      let collection = Self::collection(params.store_id);
+      let prefix = params.path_filter.map(store::path_to_store_value);
+
+      let mut candidates = self
+         .knn(
+            &collection,
+            params.query_vector,
+            search_filter(prefix.as_deref(), params.indexed_after, params.crate_name, false),
+            params.dense_candidates(),
+            params.exact,
+         )
+         .await?;
+      candidates.extend(
+         self
+            .knn(
+               &collection,
+               params.query_vector,
+               search_filter(prefix.as_deref(), params.indexed_after, params.crate_name, true),
+               50,
+               params.exact,
+            )
+            .await?,
+      );
+
+      let query_colbert =
+         (params.rerank && !params.query_colbert.is_empty()).then_some(params.query_colbert);
+      let (mut results, rerank) = candidate::rank(
+         candidates,
+         params.query_vector,
+         query_colbert,
+         params.rerank_count(),
+         params.cancel,
+      );
+
+      let anchor_weight = config::get().anchor_weight;
+      if anchor_weight > 0.0 && !results.is_empty() {
+         const ANCHOR_CAP: usize = 100;
+         let head = results.len().min(ANCHOR_CAP);
+
+         match self
+            .anchor_similarities(params.store_id, &results[..head], params.query_vector)
+            .await
+         {
+            Ok(sims) => {
+               ranking::apply_anchor_boost(&mut results[..head], &sims, anchor_weight);
+               sort_by_score(&mut results, |r| r.score);
+            },
+            Err(e) => tracing::debug!("skipping anchor boost: {e}"),
+         }
+      }
+
+      results.truncate(params.limit);
+      Ok((results, rerank))
+   }
+}
+
+#[async_trait::async_trait]
+impl super::Store for QdrantStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if records.is_empty() {
+         return Ok(());
+      }
+
+      let collection = self.ensure_collection(store_id).await?;
+      let points: Vec<Value> = records
+         .iter()
+         .map(|record| {
+            json!({
+               "id": point_id(&record.id),
+               "vector": record.vector,
+               "payload": Payload::from_record(record),
+            })
+         })
+         .collect();
+
+      self
+         .call(
+            Method::PUT,
+            &format!("/collections/{collection}/points?wait=true"),
+            Some(json!({ "points": points })),
+         )
+         .await?;
+      Ok(())
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let (results, rerank) = self.search_inner(&params).await?;
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: if params.exact {
+            VectorSearchMode::Exact
+         } else {
+            VectorSearchMode::Approximate
+         },
+         route: QueryRoute::Semantic,
+         stages: vec![rerank],
+      })
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let path = format!("/collections/{}/points/scroll", Self::collection(store_id));
+      let body = json!({
+         "filter": {
+            "must": [match_condition("bridges", key)],
+            "must_not": [match_condition("is_anchor", true)],
+         },
+         "limit": limit,
+         "with_payload": true,
+         "with_vector": false,
+      });
+      let candidates: Vec<Candidate> = self
+         .call_as::<ScrollPage<Payload>>(Method::POST, &path, Some(body))
+         .await?
+         .map(|page| {
+            page
+               .points
+               .into_iter()
+               .map(|p| p.payload.into_candidate(Vec::new()))
+               .collect()
+         })
+         .unwrap_or_default();
+      Ok(candidate::bridged(candidates))
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let mut must = vec![match_condition("is_anchor", true)];
+      if let Some(prefix) = params.path_filter.map(store::path_to_store_value) {
+         must.push(match_condition("dirs", prefix.trim_end_matches(['/', '\\'])));
+      }
+      let candidates = self
+         .knn(
+            &Self::collection(params.store_id),
+            params.query_vector,
+            json!({ "must": must }),
+            params.limit,
+            false,
+         )
+         .await?;
+      Ok(candidate::rank_anchors(candidates, params.query_vector, params.limit))
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      let points: Vec<Point<Payload>> = self
+         .scroll(
+            store_id,
+            Some(json!({ "must_not": [match_condition("is_anchor", true)] })),
+            json!(["path", "hash", "content", "start_line", "end_line", "is_anchor", "chunk_type"]),
+            true,
+         )
+         .await?;
+      let candidates = points
+         .into_iter()
+         .filter_map(|point| Some(point.payload.into_candidate(point.vector?)))
+         .collect();
+      Ok(candidate::chunk_vectors(candidates))
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      self
+         .delete_files(store_id, &[file_path.to_path_buf()])
+         .await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      if file_paths.is_empty() {
+         return Ok(());
+      }
+
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+
+      let path = format!("/collections/{}/points/delete?wait=true", Self::collection(store_id));
+      for chunk in paths.chunks(SCROLL_PAGE) {
+         self
+            .call(Method::POST, &path, Some(json!({ "filter": paths_filter(chunk) })))
+            .await?;
+      }
+      Ok(())
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect();
+
+      let mut embeddings = HashMap::new();
+      for chunk in paths.chunks(SCROLL_PAGE) {
+         let points: Vec<Point<ChunkEntry>> = self
+            .scroll(
+               store_id,
+               Some(paths_filter(chunk)),
+               json!(["chunk_hash", "content", "colbert", "colbert_scale"]),
+               true,
+            )
+            .await?;
+         for point in points {
+            let Some(dense) = point.vector else {
+               continue;
+            };
+            embeddings.insert(point.payload.chunk_hash(), HybridEmbedding {
+               dense,
+               colbert: point
+                  .payload
+                  .colbert
+                  .and_then(|c| hex::decode(c).ok())
+                  .unwrap_or_default(),
+               colbert_scale: point.payload.colbert_scale.unwrap_or(1.0),
+            });
+         }
+      }
+      Ok(embeddings)
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      let name = Self::collection(store_id);
+      self.collections.write().remove(&name);
+      self
+         .call(Method::DELETE, &format!("/collections/{name}"), None)
+         .await?;
+      Ok(())
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      let name = Self::collection(store_id);
+      let info = self
+         .call(Method::GET, &format!("/collections/{name}"), None)
+         .await?;
+      let row_count = info
+         .as_ref()
+         .and_then(|i| i.get("points_count"))
+         .and_then(Value::as_u64)
+         .unwrap_or(0);
+
+      Ok(StoreInfo {
+         store_id: store_id.to_string(),
+         row_count,
+         path: PathBuf::from(format!("{}/collections/{name}", self.base_url)),
+         dense_dim: config::get().dense_vector_dim(),
+         dense_quantized: false,
+      })
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      let points: Vec<Point<FileEntry>> = self
+         .scroll(store_id, None, json!(["path", "indexed_at"]), false)
+         .await?;
+
+      let mut files: HashMap<String, Option<u64>> = HashMap::new();
+      for point in points {
+         let entry = files.entry(point.payload.path).or_default();
+         *entry = (*entry).max(point.payload.indexed_at);
+      }
+
+      Ok(files
+         .into_iter()
+         .map(|(path, indexed_at)| IndexedFile { path: PathBuf::from(path), indexed_at })
+         .collect())
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      let count = self
+         .call(
+            Method::POST,
+            &format!("/collections/{}/points/count", Self::collection(store_id)),
+            Some(json!({ "exact": false })),
+         )
+         .await?;
+      Ok(count
+         .as_ref()
+         .and_then(|c| c.get("count"))
+         .and_then(Value::as_u64)
+         .unwrap_or(0)
+         == 0)
+   }
+
+   async fn create_fts_index(&self, _store_id: &str) -> Result<()> {
+      // Qdrant does not rank by full text; search relies on dense retrieval.
+      Ok(())
+   }
+
+   async fn create_vector_index(&self, _store_id: &str) -> Result<()> {
+      // Qdrant maintains its HNSW index as points are upserted.
+      Ok(())
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      let points: Vec<Point<FileEntry>> = self
+         .scroll(store_id, None, json!(["path", "hash"]), false)
+         .await?;
+
+      let mut hashes = HashMap::new();
+      for point in points {
+         let FileEntry { path, hash, .. } = point.payload;
+         let Some(hash) = hex::decode(&hash)
+            .ok()
+            .and_then(|h| FileHash::from_slice(&h))
+         else {
+            tracing::warn!("skipping corrupted hash for path: {path:?}");
+            continue;
+         };
+         hashes.insert(PathBuf::from(path), hash);
+      }
+      Ok(hashes)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn path_prefixes_cover_every_ancestor() {
+      assert_eq!(path_prefixes("/repo/src/main.rs"), vec![
+         "/repo",
+         "/repo/src",
+         "/repo/src/main.rs"
+      ]);
+      assert_eq!(path_prefixes("main.rs"), vec!["main.rs"]);
+   }
+
+   #[test]
+   fn point_ids_are_stable() {
+      assert_eq!(point_id("/repo/a.rs:0"), point_id("/repo/a.rs:0"));
+      assert_ne!(point_id("/repo/a.rs:0"), point_id("/repo/a.rs:1"));
+   }
+
+   #[test]
+   fn search_filter_excludes_anchors_and_applies_scope() {
+      let filter = search_filter(Some("/repo/src/"), Some(42), Some("app-core"), true);
+      assert_eq!(
+         filter,
+         json!({
+            "must": [
+               { "key": "is_doc", "match": { "value": true } },
+               { "key": "dirs", "match": { "value": "/repo/src" } },
+               { "key": "indexed_at", "range": { "gte": 42 } },
+               { "key": "crate_name", "match": { "value": "app-core" } },
+            ],
+            "must_not": [{ "key": "is_anchor", "match": { "value": true } }],
+         })
+      );
+   }
+}
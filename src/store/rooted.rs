@@ -0,0 +1,371 @@
+//! Relative paths for stores with a recorded root.
+//!
+//! A store whose metadata records the directory it indexes keeps its rows'
+//! paths and ids relative to that directory, so that it stays valid when
+//! the repository is moved or the store is imported on another machine.
+//! [`RootedStore`] wraps a backend to translate between the absolute paths
+//! the rest of smgrep works with and the relative ones in the rows. Stores
+//! indexed before roots were recorded are moved over by
+//! [`Store::relativize`] on their next sync where the backend can rewrite
+//! rows in place; the rest keep absolute paths and are passed through
+//! untouched.
+
+use std::{
+   collections::HashMap,
+   path::{self, Path, PathBuf},
+   sync::Arc,
+};
+
+use parking_lot::RwLock;
+
+use crate::{
+   embed::HybridEmbedding,
+   error::Result,
+   meta::{self, FileHash},
+   store::{self, ChunkVector, FileSearchParams, KeywordParams, SearchParams, Store},
+   types::{IndexedFile, SearchResponse, SearchResult, StoreInfo, VectorRecord},
+};
+
+/// [`Store`] that resolves the rows of rooted stores against their root.
+pub struct RootedStore {
+   inner: Box<dyn Store>,
+   /// Root of each store looked up so far, `None` for absolute ones.
+   roots: RwLock<HashMap<String, Option<Arc<Path>>>>,
+}
+
+impl RootedStore {
+   pub fn new(inner: Box<dyn Store>) -> Self {
+      Self { inner, roots: RwLock::new(HashMap::new()) }
+   }
+
+   fn root(&self, store_id: &str) -> Option<Arc<Path>> {
+      if let Some(root) = self.roots.read().get(store_id) {
+         return root.clone();
+      }
+      let root = meta::stored_root(store_id).map(Arc::from);
+      self
+         .roots
+         .write()
+         .insert(store_id.to_string(), root.clone());
+      root
+   }
+}
+
+/// `path` as stored under `root`: relative to it when inside it, and as is
+/// otherwise, as for documents fetched over HTTP.
+fn stored_path(root: &Path, path: &Path) -> PathBuf {
+   path
+      .strip_prefix(root)
+      .map_or_else(|_| path.to_path_buf(), Path::to_path_buf)
+}
+
+fn stored_paths(root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+   paths.iter().map(|path| stored_path(root, path)).collect()
+}
+
+/// Record id `id`, `{path}:{suffix}`, with its path relative to `root`.
+fn stored_id(root: &Path, id: &str) -> String {
+   let root = store::path_to_store_value(root);
+   match id.strip_prefix(root.trim_end_matches(path::is_separator)) {
+      Some(rest) if rest.starts_with(':') => rest.to_string(),
+      Some(rest) if rest.starts_with(path::is_separator) => rest[1..].to_string(),
+      _ => id.to_string(),
+   }
+}
+
+/// Stored `path` resolved against `root`, unless it was stored absolute.
+fn absolute_path(root: &Path, path: PathBuf) -> PathBuf {
+   if path.as_os_str().is_empty() {
+      root.to_path_buf()
+   } else if path.is_absolute() || path.to_string_lossy().contains("://") {
+      path
+   } else {
+      root.join(path)
+   }
+}
+
+/// `records` with their paths and ids relative to `root` where they lie
+/// under it.
+pub(crate) fn stored_records(root: &Path, records: Vec<VectorRecord>) -> Vec<VectorRecord> {
+   // Records of a file share its path, and go on sharing the rebased one
+   let mut last: Option<(Arc<PathBuf>, Arc<PathBuf>)> = None;
+   records
+      .into_iter()
+      .map(|mut record| {
+         let path = match &last {
+            Some((from, to)) if Arc::ptr_eq(from, &record.path) => Arc::clone(to),
+            _ => {
+               let to = Arc::new(stored_path(root, &record.path));
+               last = Some((Arc::clone(&record.path), Arc::clone(&to)));
+               to
+            },
+         };
+         record.id = stored_id(root, &record.id);
+         record.path = path;
+         record
+      })
+      .collect()
+}
+
+fn absolute_result(root: &Path, mut result: SearchResult) -> SearchResult {
+   result.path = absolute_path(root, result.path);
+   for linked in &mut result.linked {
+      linked.path = absolute_path(root, std::mem::take(&mut linked.path));
+   }
+   result
+}
+
+fn absolute_response(root: &Path, mut response: SearchResponse) -> SearchResponse {
+   response.results = response
+      .results
+      .into_iter()
+      .map(|result| absolute_result(root, result))
+      .collect();
+   response
+}
+
+/// Stored path filter for `path`; `None` when it covers the whole root.
+fn stored_filter(root: &Path, path: Option<&Path>) -> Option<PathBuf> {
+   path
+      .map(|path| stored_path(root, path))
+      .filter(|path| !path.as_os_str().is_empty())
+}
+
+#[async_trait::async_trait]
+impl Store for RootedStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      match self.root(store_id) {
+         Some(root) => {
+            let records = stored_records(&root, records);
+            self.inner.insert_batch(store_id, records).await
+         },
+         None => self.inner.insert_batch(store_id, records).await,
+      }
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let Some(root) = self.root(params.store_id) else {
+         return self.inner.search(params).await;
+      };
+      let path_filter = stored_filter(&root, params.path_filter);
+      let path_globs = params
+         .path_globs
+         .map(|globs| globs.relative_to(&root))
+         .transpose()?;
+      let params = SearchParams {
+         path_filter: path_filter.as_deref(),
+         path_globs: path_globs.as_ref(),
+         ..params
+      };
+      Ok(absolute_response(&root, self.inner.search(params).await?))
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let Some(root) = self.root(params.store_id) else {
+         return self.inner.keyword_search(params).await;
+      };
+      let path_filter = stored_filter(&root, params.path_filter);
+      let path_globs = params
+         .path_globs
+         .map(|globs| globs.relative_to(&root))
+         .transpose()?;
+      let params = KeywordParams {
+         path_filter: path_filter.as_deref(),
+         path_globs: path_globs.as_ref(),
+         ..params
+      };
+      Ok(absolute_response(&root, self.inner.keyword_search(params).await?))
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let results = self.inner.find_bridged(store_id, key, limit).await?;
+      Ok(match self.root(store_id) {
+         Some(root) => results
+            .into_iter()
+            .map(|result| absolute_result(&root, result))
+            .collect(),
+         None => results,
+      })
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let Some(root) = self.root(params.store_id) else {
+         return self.inner.search_files(params).await;
+      };
+      let path_filter = stored_filter(&root, params.path_filter);
+      let params = FileSearchParams { path_filter: path_filter.as_deref(), ..params };
+      Ok(self
+         .inner
+         .search_files(params)
+         .await?
+         .into_iter()
+         .map(|result| absolute_result(&root, result))
+         .collect())
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      let mut chunks = self.inner.chunk_vectors(store_id).await?;
+      if let Some(root) = self.root(store_id) {
+         for chunk in &mut chunks {
+            chunk.path = absolute_path(&root, std::mem::take(&mut chunk.path));
+         }
+      }
+      Ok(chunks)
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      match self.root(store_id) {
+         Some(root) => {
+            let file_path = stored_path(&root, file_path);
+            self.inner.delete_file(store_id, &file_path).await
+         },
+         None => self.inner.delete_file(store_id, file_path).await,
+      }
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      match self.root(store_id) {
+         Some(root) => {
+            let file_paths = stored_paths(&root, file_paths);
+            self.inner.delete_files(store_id, &file_paths).await
+         },
+         None => self.inner.delete_files(store_id, file_paths).await,
+      }
+   }
+
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      match self.root(store_id) {
+         Some(root) => {
+            let file_paths = stored_paths(&root, file_paths);
+            let records = stored_records(&root, records);
+            self
+               .inner
+               .replace_files(store_id, &file_paths, records)
+               .await
+         },
+         None => {
+            self
+               .inner
+               .replace_files(store_id, file_paths, records)
+               .await
+         },
+      }
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      self.roots.write().remove(store_id);
+      self.inner.delete_store(store_id).await
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      self.inner.get_info(store_id).await
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      let mut files = self.inner.list_files(store_id).await?;
+      if let Some(root) = self.root(store_id) {
+         for file in &mut files {
+            file.path = absolute_path(&root, std::mem::take(&mut file.path));
+         }
+      }
+      Ok(files)
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      self.inner.is_empty(store_id).await
+   }
+
+   async fn create_fts_index(&self, store_id: &str) -> Result<()> {
+      self.inner.create_fts_index(store_id).await
+   }
+
+   async fn create_vector_index(&self, store_id: &str) -> Result<()> {
+      self.inner.create_vector_index(store_id).await
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      let hashes = self.inner.get_file_hashes(store_id).await?;
+      Ok(match self.root(store_id) {
+         Some(root) => hashes
+            .into_iter()
+            .map(|(path, hash)| (absolute_path(&root, path), hash))
+            .collect(),
+         None => hashes,
+      })
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      match self.root(store_id) {
+         Some(root) => {
+            let file_paths = stored_paths(&root, file_paths);
+            self.inner.get_chunk_embeddings(store_id, &file_paths).await
+         },
+         None => self.inner.get_chunk_embeddings(store_id, file_paths).await,
+      }
+   }
+
+   async fn optimize(&self, store_id: &str) -> Result<()> {
+      self.inner.optimize(store_id).await
+   }
+
+   async fn warm_up(&self, store_id: &str) -> Result<()> {
+      self.inner.warm_up(store_id).await
+   }
+
+   fn set_root(&self, store_id: &str, root: Option<&Path>) {
+      self
+         .roots
+         .write()
+         .insert(store_id.to_string(), root.map(Arc::from));
+   }
+
+   /// Only stores without a root hold absolute paths to rewrite.
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      if self.root(store_id).is_some() {
+         return Ok(false);
+      }
+      self.inner.relativize(store_id, root).await
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn stores_paths_under_root_relative() {
+      let root = Path::new("/repo");
+      assert_eq!(stored_path(root, Path::new("/repo/src/lib.rs")), Path::new("src/lib.rs"));
+      assert_eq!(stored_path(root, Path::new("/other/lib.rs")), Path::new("/other/lib.rs"));
+      assert_eq!(stored_path(root, Path::new("https://x.dev/a")), Path::new("https://x.dev/a"));
+      assert_eq!(stored_filter(root, Some(root)), None);
+
+      assert_eq!(stored_id(root, "/repo/src/lib.rs:3"), "src/lib.rs:3");
+      assert_eq!(stored_id(root, "/repo:module"), ":module");
+      assert_eq!(stored_id(root, "/repository/lib.rs:0"), "/repository/lib.rs:0");
+   }
+
+   #[test]
+   fn resolves_stored_paths_against_root() {
+      let root = Path::new("/moved/repo");
+      let absolute = |path: &str| absolute_path(root, PathBuf::from(path));
+      assert_eq!(absolute("src/lib.rs"), Path::new("/moved/repo/src/lib.rs"));
+      assert_eq!(absolute(""), root);
+      assert_eq!(absolute("/repo/src/lib.rs"), Path::new("/repo/src/lib.rs"));
+      assert_eq!(absolute("https://x.dev/a"), Path::new("https://x.dev/a"));
+   }
+}
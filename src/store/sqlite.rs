@@ -0,0 +1,1234 @@
+//! `SQLite` storage backend.
+//!
+//! Keeps each store in a single database file at
+//! `<data_dir>/<store_id>/index.sqlite`. Dense vectors are stored as float32
+//! blobs and ranked with the sqlite-vec distance functions, full-text search
+//! goes through an external-content FTS5 table kept in sync by triggers.
+
+use std::{
+   collections::{HashMap, HashSet},
+   fs,
+   path::{Path, PathBuf},
+   sync::{Arc, Once},
+};
+
+use parking_lot::{Mutex, RwLock};
+use rusqlite::{Connection, OptionalExtension, Row, Transaction, params, params_from_iter};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+   chunker::bridge,
+   config,
+   embed::HybridEmbedding,
+   error::Result,
+   meta::FileHash,
+   search::ranking,
+   store::{
+      self, ChunkVector, FileSearchParams, KeywordParams, SearchParams,
+      candidate::{self, Candidate, dot, sort_by_score},
+   },
+   types::{
+      ChunkType, IndexedFile, QueryRoute, SearchResponse, SearchResult, SearchStatus, StageTiming,
+      StoreInfo, VectorRecord, VectorSearchMode,
+   },
+};
+
+/// Current database layout version, stored in `PRAGMA user_version`.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// File name of the database inside a store's data directory.
+const DB_FILE: &str = "index.sqlite";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS settings (
+   key   TEXT PRIMARY KEY,
+   value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chunks (
+   rowid         INTEGER PRIMARY KEY,
+   id            TEXT NOT NULL,
+   path          TEXT NOT NULL,
+   hash          BLOB NOT NULL,
+   content       TEXT NOT NULL,
+   start_line    INTEGER NOT NULL,
+   end_line      INTEGER NOT NULL,
+   vector        BLOB NOT NULL,
+   colbert       BLOB,
+   colbert_scale REAL,
+   chunk_index   INTEGER,
+   is_anchor     INTEGER,
+   chunk_type    TEXT,
+   context_prev  TEXT,
+   context_next  TEXT,
+   indexed_at    INTEGER,
+   crate_name    TEXT,
+   chunk_hash    BLOB,
+   bridges       TEXT,
+   language      TEXT
+);
+CREATE INDEX IF NOT EXISTS chunks_path ON chunks(path);
+CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts
+   USING fts5(content, content='chunks', content_rowid='rowid');
+CREATE TRIGGER IF NOT EXISTS chunks_ai AFTER INSERT ON chunks BEGIN
+   INSERT INTO chunks_fts(rowid, content) VALUES (new.rowid, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS chunks_ad AFTER DELETE ON chunks BEGIN
+   INSERT INTO chunks_fts(chunks_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+END;
+";
+
+const SELECT_COLUMNS: &str = "c.path, c.content, c.start_line, c.end_line, c.vector, c.colbert, \
+                              c.colbert_scale, c.is_anchor, c.chunk_type, c.context_prev, \
+                              c.context_next, c.bridges, c.language";
+
+/// Row restrictions bound to parameters `?2`..`?5`, as produced by
+/// [`RowFilter`].
+const FILTER_CLAUSE: &str = "(?2 = 0 OR substr(c.path, 1, ?2) = ?3) AND (?4 IS NULL OR \
+                             c.indexed_at >= ?4) AND (?5 IS NULL OR c.crate_name = ?5)";
+const ANCHOR_FILTER: &str = "(c.is_anchor IS NULL OR c.is_anchor = 0)";
+const DOC_CLAUSE: &str = "(c.path LIKE '%.md' OR c.path LIKE '%.mdx' OR c.path LIKE '%.txt' OR \
+                          c.path LIKE '%.json' OR COALESCE(c.chunk_type, '') = 'doc')";
+
+/// Errors that can occur during `SQLite` store operations.
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteStoreError {
+   #[error("failed to open database: {0}")]
+   Open(#[source] rusqlite::Error),
+
+   #[error("failed to initialize schema: {0}")]
+   InitSchema(#[source] rusqlite::Error),
+
+   #[error(
+      "index uses schema v{found}, but this smgrep supports up to v{supported}; upgrade smgrep or \
+       run `smgrep clean`"
+   )]
+   SchemaTooNew { found: u32, supported: u32 },
+
+   #[error("failed to insert records: {0}")]
+   Insert(#[source] rusqlite::Error),
+
+   #[error("failed to execute query: {0}")]
+   Query(#[source] rusqlite::Error),
+
+   #[error("failed to delete records: {0}")]
+   Delete(#[source] rusqlite::Error),
+
+   #[error("failed to update records: {0}")]
+   Update(#[source] rusqlite::Error),
+
+   #[error("database task failed: {0}")]
+   Task(#[from] tokio::task::JoinError),
+}
+
+/// Registers sqlite-vec with every connection opened by this process.
+fn register_vec_extension() {
+   static REGISTER: Once = Once::new();
+   REGISTER.call_once(|| {
+      type EntryPoint = unsafe extern "C" fn(
+         *mut rusqlite::ffi::sqlite3,
+         *mut *mut std::ffi::c_char,
+         *const rusqlite::ffi::sqlite3_api_routines,
+      ) -> std::ffi::c_int;
+
+      // SAFETY: `sqlite3_vec_init` is a standard SQLite extension entry point,
+      // so it matches the signature `sqlite3_auto_extension` expects.
+      unsafe {
+         let entry =
+            std::mem::transmute::<*const (), EntryPoint>(sqlite_vec::sqlite3_vec_init as *const ());
+         rusqlite::ffi::sqlite3_auto_extension(Some(entry));
+      }
+   });
+}
+
+/// Single `SQLite` file implementation of [`Store`](super::Store).
+pub struct SqliteStore {
+   connections: RwLock<HashMap<String, Arc<Mutex<Connection>>>>,
+   data_dir:    PathBuf,
+}
+
+impl SqliteStore {
+   /// Creates a new store using the data directory from configuration.
+   pub fn new() -> Result<Self> {
+      Self::with_data_dir(config::data_dir().clone())
+   }
+
+   /// Creates a new store rooted at `data_dir`.
+   pub fn with_data_dir(data_dir: PathBuf) -> Result<Self> {
+      fs::create_dir_all(&data_dir)?;
+      register_vec_extension();
+      Ok(Self { connections: RwLock::new(HashMap::new()), data_dir })
+   }
+
+   fn db_path(&self, store_id: &str) -> PathBuf {
+      self.data_dir.join(store_id).join(DB_FILE)
+   }
+
+   fn get_connection(&self, store_id: &str) -> Result<Arc<Mutex<Connection>>> {
+      if let Some(conn) = self.connections.read().get(store_id) {
+         return Ok(Arc::clone(conn));
+      }
+
+      let db_path = self.db_path(store_id);
+      if let Some(parent) = db_path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+
+      let conn = Connection::open(&db_path).map_err(SqliteStoreError::Open)?;
+      Self::init_schema(&conn, store_id)?;
+
+      let conn = Arc::new(Mutex::new(conn));
+      Ok(Arc::clone(
+         self
+            .connections
+            .write()
+            .entry(store_id.to_string())
+            .or_insert(conn),
+      ))
+   }
+
+   /// Creates tables on first open and clears vectors whose dimension no
+   /// longer matches the configured model.
+   fn init_schema(conn: &Connection, store_id: &str) -> Result<(), SqliteStoreError> {
+      conn
+         .pragma_update(None, "journal_mode", "WAL")
+         .map_err(SqliteStoreError::InitSchema)?;
+      conn
+         .busy_timeout(std::time::Duration::from_secs(30))
+         .map_err(SqliteStoreError::InitSchema)?;
+
+      let version: u32 = conn
+         .pragma_query_value(None, "user_version", |row| row.get(0))
+         .map_err(SqliteStoreError::InitSchema)?;
+      if version > SCHEMA_VERSION {
+         return Err(SqliteStoreError::SchemaTooNew {
+            found:     version,
+            supported: SCHEMA_VERSION,
+         });
+      }
+
+      conn
+         .execute_batch(SCHEMA)
+         .map_err(SqliteStoreError::InitSchema)?;
+      if version == 1 {
+         conn
+            .execute_batch("ALTER TABLE chunks ADD COLUMN indexed_at INTEGER")
+            .map_err(SqliteStoreError::InitSchema)?;
+      }
+      if (1..3).contains(&version) {
+         conn
+            .execute_batch("ALTER TABLE chunks ADD COLUMN crate_name TEXT")
+            .map_err(SqliteStoreError::InitSchema)?;
+      }
+      if (1..4).contains(&version) {
+         conn
+            .execute_batch("ALTER TABLE chunks ADD COLUMN chunk_hash BLOB")
+            .map_err(SqliteStoreError::InitSchema)?;
+      }
+      if (1..5).contains(&version) {
+         conn
+            .execute_batch("ALTER TABLE chunks ADD COLUMN bridges TEXT")
+            .map_err(SqliteStoreError::InitSchema)?;
+      }
+      if (1..6).contains(&version) {
+         conn
+            .execute_batch("ALTER TABLE chunks ADD COLUMN language TEXT")
+            .map_err(SqliteStoreError::InitSchema)?;
+      }
+      conn
+         .pragma_update(None, "user_version", SCHEMA_VERSION)
+         .map_err(SqliteStoreError::InitSchema)?;
+
+      let dim = config::get().dense_vector_dim().to_string();
+      let stored_dim: Option<String> = conn
+         .query_row("SELECT value FROM settings WHERE key = 'dense_dim'", [], |row| row.get(0))
+         .optional()
+         .map_err(SqliteStoreError::InitSchema)?;
+      if let Some(stored_dim) = stored_dim
+         && stored_dim != dim
+      {
+         tracing::warn!("index {store_id} has {stored_dim}-dim vectors; clearing for re-index");
+         conn
+            .execute("DELETE FROM chunks", [])
+            .map_err(SqliteStoreError::Delete)?;
+      }
+      conn
+         .execute("INSERT OR REPLACE INTO settings (key, value) VALUES ('dense_dim', ?1)", [dim])
+         .map_err(SqliteStoreError::InitSchema)?;
+
+      Ok(())
+   }
+
+   /// Runs `f` against the store's connection on the blocking thread pool.
+   async fn with_conn<T, F>(&self, store_id: &str, f: F) -> Result<T>
+   where
+      T: Send + 'static,
+      F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+   {
+      let conn = self.get_connection(store_id)?;
+      tokio::task::spawn_blocking(move || f(&mut conn.lock()))
+         .await
+         .map_err(SqliteStoreError::Task)?
+   }
+
+   fn insert_records(
+      tx: &Transaction<'_>,
+      records: &[VectorRecord],
+   ) -> Result<(), SqliteStoreError> {
+      let mut stmt = tx
+         .prepare_cached(
+            "INSERT INTO chunks (id, path, hash, content, start_line, end_line, vector, colbert, \
+             colbert_scale, chunk_index, is_anchor, chunk_type, context_prev, context_next, \
+             indexed_at, crate_name, chunk_hash, bridges, language) VALUES (?1, ?2, ?3, ?4, ?5, \
+             ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+         )
+         .map_err(SqliteStoreError::Insert)?;
+
+      for record in records {
+         stmt
+            .execute(params![
+               record.id,
+               store::path_to_store_value(&record.path),
+               record.hash.as_ref(),
+               record.content.as_str(),
+               record.start_line,
+               record.end_line,
+               Self::vector_to_blob(&record.vector),
+               record.colbert,
+               record.colbert_scale,
+               record.chunk_index,
+               record.is_anchor,
+               record.chunk_type.map(ChunkType::as_lowercase_str),
+               record.context_prev.as_deref(),
+               record.context_next.as_deref(),
+               record.indexed_at.map(|t| t as i64),
+               record.crate_name.as_deref(),
+               record.chunk_hash.as_ref(),
+               record.bridges.as_deref(),
+               record.language.as_deref(),
+            ])
+            .map_err(SqliteStoreError::Insert)?;
+      }
+      Ok(())
+   }
+
+   /// Reads the [`store::chunk_fingerprint`] and path of every chunk stored
+   /// for `paths`, keyed by chunk id.
+   fn chunk_fingerprints(
+      tx: &Transaction<'_>,
+      paths: &[String],
+   ) -> Result<HashMap<String, (FileHash, String)>, SqliteStoreError> {
+      let mut stmt = tx
+         .prepare_cached(
+            "SELECT id, chunk_hash, content, start_line, end_line, context_prev, context_next \
+             FROM chunks WHERE path = ?1",
+         )
+         .map_err(SqliteStoreError::Query)?;
+
+      let mut fingerprints = HashMap::new();
+      for path in paths {
+         let rows = stmt
+            .query_map([path], |row| {
+               let chunk_hash = row
+                  .get::<_, Option<Vec<u8>>>(1)?
+                  .and_then(|h| FileHash::from_slice(&h))
+                  .map_or_else(|| row.get::<_, String>(2).map(FileHash::sum), Ok)?;
+               let fingerprint = store::chunk_fingerprint(
+                  &chunk_hash,
+                  row.get(3)?,
+                  row.get(4)?,
+                  row.get::<_, Option<String>>(5)?.as_deref(),
+                  row.get::<_, Option<String>>(6)?.as_deref(),
+               );
+               Ok((row.get::<_, String>(0)?, fingerprint))
+            })
+            .map_err(SqliteStoreError::Query)?;
+         for row in rows {
+            let (id, fingerprint) = row.map_err(SqliteStoreError::Query)?;
+            fingerprints.insert(id, (fingerprint, path.clone()));
+         }
+      }
+      Ok(fingerprints)
+   }
+
+   fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+      vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+   }
+
+   fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
+      blob
+         .chunks_exact(4)
+         .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+         .collect()
+   }
+
+   /// Builds an FTS5 query matching any of the words in `text`, quoting each
+   /// so user input cannot inject FTS syntax.
+   fn fts_query(text: &str) -> Option<String> {
+      let terms: Vec<String> = text
+         .split(|c: char| !c.is_alphanumeric() && c != '_')
+         .filter(|t| !t.is_empty())
+         .map(|t| format!("\"{t}\""))
+         .collect();
+      (!terms.is_empty()).then(|| terms.join(" OR "))
+   }
+
+   fn query_candidates(
+      conn: &Connection,
+      sql: &str,
+      params: &[&dyn rusqlite::ToSql],
+   ) -> Result<Vec<Candidate>, SqliteStoreError> {
+      let mut stmt = conn.prepare_cached(sql).map_err(SqliteStoreError::Query)?;
+      let rows = stmt
+         .query_map(params, candidate_from_row)
+         .map_err(SqliteStoreError::Query)?;
+      rows
+         .collect::<Result<_, _>>()
+         .map_err(SqliteStoreError::Query)
+   }
+
+   /// Computes query similarity against the anchor chunk of each distinct
+   /// file in `results`.
+   fn anchor_similarities(
+      conn: &Connection,
+      results: &[SearchResult],
+      query_vector: &[f32],
+   ) -> Result<HashMap<PathBuf, f32>, SqliteStoreError> {
+      let paths: Vec<String> = results
+         .iter()
+         .map(|r| store::path_to_store_value(&r.path))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+      let placeholders = vec!["?"; paths.len()].join(",");
+      let sql = format!(
+         "SELECT path, vector FROM chunks WHERE is_anchor = 1 AND path IN ({placeholders})"
+      );
+
+      let mut stmt = conn.prepare(&sql).map_err(SqliteStoreError::Query)?;
+      let rows = stmt
+         .query_map(params_from_iter(&paths), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+         })
+         .map_err(SqliteStoreError::Query)?;
+
+      let mut sims = HashMap::with_capacity(paths.len());
+      for row in rows {
+         let (path, blob) = row.map_err(SqliteStoreError::Query)?;
+         let sim = dot(query_vector, &Self::blob_to_vector(&blob));
+         sims.insert(PathBuf::from(path), sim);
+      }
+      Ok(sims)
+   }
+
+   #[allow(clippy::too_many_arguments, reason = "one argument per search parameter")]
+   fn search_blocking(
+      conn: &Connection,
+      query_text: &str,
+      query_vector: &[f32],
+      query_colbert: Option<&ndarray::Array2<f32>>,
+      rerank_depth: usize,
+      limit: usize,
+      filter: RowFilter,
+      cancel: Option<&CancellationToken>,
+   ) -> Result<(Vec<SearchResult>, StageTiming), SqliteStoreError> {
+      let query_blob = Self::vector_to_blob(query_vector);
+      let RowFilter { path, indexed_after, crate_name } = filter;
+      let prefix = path.unwrap_or_default();
+      let prefix_len = prefix.chars().count() as i64;
+      let indexed_after = indexed_after.map(|t| t as i64);
+
+      let knn = |doc: bool, k: usize| {
+         let kind = if doc {
+            DOC_CLAUSE.to_string()
+         } else {
+            format!("NOT {DOC_CLAUSE}")
+         };
+         let sql = format!(
+            "SELECT {SELECT_COLUMNS} FROM chunks c WHERE {FILTER_CLAUSE} AND {kind} AND \
+             {ANCHOR_FILTER} ORDER BY vec_distance_cosine(c.vector, ?1) LIMIT {k}"
+         );
+         Self::query_candidates(conn, &sql, &[
+            &query_blob,
+            &prefix_len,
+            &prefix,
+            &indexed_after,
+            &crate_name,
+         ])
+      };
+
+      let mut candidates = knn(false, store::DENSE_CANDIDATES.max(rerank_depth))?;
+      candidates.extend(knn(true, 50)?);
+
+      if let Some(fts) = Self::fts_query(query_text) {
+         let sql = format!(
+            "SELECT {SELECT_COLUMNS} FROM chunks_fts f JOIN chunks c ON c.rowid = f.rowid WHERE \
+             chunks_fts MATCH ?1 AND {FILTER_CLAUSE} AND {ANCHOR_FILTER} ORDER BY f.rank LIMIT 50"
+         );
+         match Self::query_candidates(conn, &sql, &[
+            &fts,
+            &prefix_len,
+            &prefix,
+            &indexed_after,
+            &crate_name,
+         ]) {
+            Ok(mut rows) => {
+               candidate::mark_fts(&mut rows);
+               candidates.extend(rows);
+            },
+            Err(e) => tracing::debug!("skipping full-text search: {e}"),
+         }
+      }
+
+      let (mut results, rerank) =
+         candidate::rank(candidates, query_vector, query_colbert, rerank_depth, cancel);
+
+      let anchor_weight = config::get().anchor_weight;
+      if anchor_weight > 0.0 && !results.is_empty() {
+         const ANCHOR_CAP: usize = 100;
+         let head = results.len().min(ANCHOR_CAP);
+
+         match Self::anchor_similarities(conn, &results[..head], query_vector) {
+            Ok(sims) => {
+               ranking::apply_anchor_boost(&mut results[..head], &sims, anchor_weight);
+               sort_by_score(&mut results, |r| r.score);
+            },
+            Err(e) => tracing::debug!("skipping anchor boost: {e}"),
+         }
+      }
+
+      results.truncate(limit);
+      Ok((results, rerank))
+   }
+
+   fn keyword_blocking(
+      conn: &Connection,
+      query_text: &str,
+      limit: usize,
+      filter: RowFilter,
+   ) -> Result<Vec<SearchResult>, SqliteStoreError> {
+      let Some(fts) = Self::fts_query(query_text) else {
+         return Ok(vec![]);
+      };
+      let RowFilter { path, indexed_after, crate_name } = filter;
+      let prefix = path.unwrap_or_default();
+      let prefix_len = prefix.chars().count() as i64;
+      let indexed_after = indexed_after.map(|t| t as i64);
+
+      let sql = format!(
+         "SELECT {SELECT_COLUMNS} FROM chunks_fts f JOIN chunks c ON c.rowid = f.rowid WHERE \
+          chunks_fts MATCH ?1 AND {FILTER_CLAUSE} AND {ANCHOR_FILTER} ORDER BY f.rank LIMIT \
+          {limit}"
+      );
+      let candidates = Self::query_candidates(conn, &sql, &[
+         &fts,
+         &prefix_len,
+         &prefix,
+         &indexed_after,
+         &crate_name,
+      ])?;
+      Ok(candidate::rank_by_position(candidates))
+   }
+}
+
+impl Default for SqliteStore {
+   fn default() -> Self {
+      Self::new().expect("failed to create SqliteStore")
+   }
+}
+
+/// Search restrictions moved onto the blocking pool.
+struct RowFilter {
+   path:          Option<String>,
+   indexed_after: Option<u64>,
+   crate_name:    Option<String>,
+}
+
+/// Reads a [`Candidate`] from a row selected with [`SELECT_COLUMNS`].
+fn candidate_from_row(row: &Row<'_>) -> rusqlite::Result<Candidate> {
+   Ok(Candidate {
+      path:          PathBuf::from(row.get::<_, String>(0)?),
+      content:       row.get(1)?,
+      start_line:    row.get(2)?,
+      end_line:      row.get(3)?,
+      vector:        SqliteStore::blob_to_vector(&row.get::<_, Vec<u8>>(4)?),
+      colbert:       row.get(5)?,
+      colbert_scale: row.get::<_, Option<f64>>(6)?.unwrap_or(1.0),
+      is_anchor:     row.get(7)?,
+      chunk_type:    row
+         .get::<_, Option<String>>(8)?
+         .map(|s| ChunkType::from_lowercase_str(&s)),
+      context_prev:  row.get(9)?,
+      context_next:  row.get(10)?,
+      fts:           None,
+      bridges:       row.get(11)?,
+      language:      row.get(12)?,
+      score:         0.0,
+   })
+}
+
+#[async_trait::async_trait]
+impl super::Store for SqliteStore {
+   async fn insert_batch(&self, store_id: &str, records: Vec<VectorRecord>) -> Result<()> {
+      if records.is_empty() {
+         return Ok(());
+      }
+
+      self
+         .with_conn(store_id, move |conn| {
+            let tx = conn.transaction().map_err(SqliteStoreError::Insert)?;
+            Self::insert_records(&tx, &records)?;
+            tx.commit().map_err(SqliteStoreError::Insert)?;
+            Ok(())
+         })
+         .await
+   }
+
+   async fn search(&self, params: SearchParams<'_>) -> Result<SearchResponse> {
+      let query_text = params.query_text.to_owned();
+      let query_vector = params.query_vector.to_vec();
+      let query_colbert =
+         (params.rerank && !params.query_colbert.is_empty()).then(|| params.query_colbert.clone());
+      let filter = RowFilter {
+         path:          params.path_filter.map(store::path_to_store_value),
+         indexed_after: params.indexed_after,
+         crate_name:    params.crate_name.map(str::to_owned),
+      };
+      let rerank_depth = params.rerank_count();
+      let limit = params.limit;
+      let cancel = params.cancel.cloned();
+
+      let (results, rerank) = self
+         .with_conn(params.store_id, move |conn| {
+            Ok(Self::search_blocking(
+               conn,
+               &query_text,
+               &query_vector,
+               query_colbert.as_ref(),
+               rerank_depth,
+               limit,
+               filter,
+               cancel.as_ref(),
+            )?)
+         })
+         .await?;
+
+      // Distances are computed for every row, so search is always exact.
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: VectorSearchMode::Exact,
+         route: QueryRoute::Semantic,
+         stages: vec![rerank],
+      })
+   }
+
+   async fn keyword_search(&self, params: KeywordParams<'_>) -> Result<SearchResponse> {
+      let query_text = params.query_text.to_owned();
+      let filter = RowFilter {
+         path:          params.path_filter.map(store::path_to_store_value),
+         indexed_after: params.indexed_after,
+         crate_name:    params.crate_name.map(str::to_owned),
+      };
+      let limit = params.limit;
+
+      let results = self
+         .with_conn(params.store_id, move |conn| {
+            Ok(Self::keyword_blocking(conn, &query_text, limit, filter)?)
+         })
+         .await?;
+
+      Ok(SearchResponse {
+         results,
+         status: SearchStatus::Ready,
+         progress: None,
+         mode: VectorSearchMode::Keyword,
+         route: QueryRoute::Semantic,
+         stages: Vec::new(),
+      })
+   }
+
+   async fn find_bridged(
+      &self,
+      store_id: &str,
+      key: &str,
+      limit: usize,
+   ) -> Result<Vec<SearchResult>> {
+      let pattern = bridge::like_pattern(key);
+      self
+         .with_conn(store_id, move |conn| {
+            let sql = format!(
+               "SELECT {SELECT_COLUMNS} FROM chunks c WHERE c.bridges LIKE ?1 AND {ANCHOR_FILTER} \
+                LIMIT {limit}"
+            );
+            Ok(candidate::bridged(Self::query_candidates(conn, &sql, &[&pattern])?))
+         })
+         .await
+   }
+
+   async fn search_files(&self, params: FileSearchParams<'_>) -> Result<Vec<SearchResult>> {
+      let prefix = params
+         .path_filter
+         .map(store::path_to_store_value)
+         .unwrap_or_default();
+      let query_vector = params.query_vector.to_vec();
+      let limit = params.limit;
+      self
+         .with_conn(params.store_id, move |conn| {
+            let sql = format!(
+               "SELECT {SELECT_COLUMNS} FROM chunks c WHERE c.is_anchor = 1 AND (?1 = 0 OR \
+                substr(c.path, 1, ?1) = ?2)"
+            );
+            let prefix_len = prefix.chars().count() as i64;
+            let candidates = Self::query_candidates(conn, &sql, &[&prefix_len, &prefix])?;
+            Ok(candidate::rank_anchors(candidates, &query_vector, limit))
+         })
+         .await
+   }
+
+   async fn chunk_vectors(&self, store_id: &str) -> Result<Vec<ChunkVector>> {
+      self
+         .with_conn(store_id, |conn| {
+            let sql = format!("SELECT {SELECT_COLUMNS} FROM chunks c WHERE c.is_anchor IS NOT 1");
+            let candidates = Self::query_candidates(conn, &sql, &[])?;
+            Ok(candidate::chunk_vectors(candidates))
+         })
+         .await
+   }
+
+   async fn delete_file(&self, store_id: &str, file_path: &Path) -> Result<()> {
+      self
+         .delete_files(store_id, &[file_path.to_path_buf()])
+         .await
+   }
+
+   async fn delete_files(&self, store_id: &str, file_paths: &[PathBuf]) -> Result<()> {
+      if file_paths.is_empty() {
+         return Ok(());
+      }
+
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+
+      self
+         .with_conn(store_id, move |conn| {
+            let tx = conn.transaction().map_err(SqliteStoreError::Delete)?;
+            {
+               let mut stmt = tx
+                  .prepare_cached("DELETE FROM chunks WHERE path = ?1")
+                  .map_err(SqliteStoreError::Delete)?;
+               for path in &paths {
+                  stmt.execute([path]).map_err(SqliteStoreError::Delete)?;
+               }
+            }
+            tx.commit().map_err(SqliteStoreError::Delete)?;
+            Ok(())
+         })
+         .await
+   }
+
+   async fn replace_files(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+      records: Vec<VectorRecord>,
+   ) -> Result<usize> {
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect::<HashSet<_>>()
+         .into_iter()
+         .collect();
+      let file_hashes: HashMap<String, FileHash> = records
+         .iter()
+         .map(|r| (store::path_to_store_value(&r.path), r.hash))
+         .collect();
+
+      self
+         .with_conn(store_id, move |conn| {
+            let tx = conn.transaction().map_err(SqliteStoreError::Insert)?;
+            let stored = Self::chunk_fingerprints(&tx, &paths)?;
+            let stored_paths: HashMap<&str, &str> = stored
+               .iter()
+               .map(|(id, (_, path))| (id.as_str(), path.as_str()))
+               .collect();
+            let diff = store::diff_chunks(
+               stored
+                  .iter()
+                  .map(|(id, (fingerprint, _))| (id.clone(), *fingerprint))
+                  .collect(),
+               records,
+            );
+
+            {
+               let mut stmt = tx
+                  .prepare_cached("DELETE FROM chunks WHERE path = ?1 AND id = ?2")
+                  .map_err(SqliteStoreError::Delete)?;
+               for id in &diff.stale {
+                  stmt
+                     .execute(params![stored_paths[id.as_str()], id])
+                     .map_err(SqliteStoreError::Delete)?;
+               }
+            }
+            {
+               // Chunks left in place take the file's new hash.
+               let mut stmt = tx
+                  .prepare_cached("UPDATE chunks SET hash = ?1 WHERE path = ?2")
+                  .map_err(SqliteStoreError::Insert)?;
+               for (path, hash) in &file_hashes {
+                  stmt
+                     .execute(params![hash.as_ref(), path])
+                     .map_err(SqliteStoreError::Insert)?;
+               }
+            }
+            Self::insert_records(&tx, &diff.changed)?;
+            tx.commit().map_err(SqliteStoreError::Insert)?;
+
+            tracing::debug!("rewrote {} chunks, kept {}", diff.changed.len(), diff.kept);
+            Ok(diff.changed.len())
+         })
+         .await
+   }
+
+   async fn get_chunk_embeddings(
+      &self,
+      store_id: &str,
+      file_paths: &[PathBuf],
+   ) -> Result<HashMap<FileHash, HybridEmbedding>> {
+      if file_paths.is_empty() || !self.db_path(store_id).exists() {
+         return Ok(HashMap::new());
+      }
+
+      let paths: Vec<String> = file_paths
+         .iter()
+         .map(|p| store::path_to_store_value(p))
+         .collect();
+
+      self
+         .with_conn(store_id, move |conn| {
+            let mut stmt = conn
+               .prepare_cached(
+                  "SELECT chunk_hash, content, vector, colbert, colbert_scale FROM chunks WHERE \
+                   path = ?1",
+               )
+               .map_err(SqliteStoreError::Query)?;
+
+            let mut embeddings = HashMap::new();
+            for path in &paths {
+               let rows = stmt
+                  .query_map([path], |row| {
+                     // Rows written before chunk hashes were stored fall back
+                     // to hashing their content.
+                     let chunk_hash = row
+                        .get::<_, Option<Vec<u8>>>(0)?
+                        .and_then(|h| FileHash::from_slice(&h))
+                        .map_or_else(|| row.get::<_, String>(1).map(FileHash::sum), Ok)?;
+                     Ok((chunk_hash, HybridEmbedding {
+                        dense:         Self::blob_to_vector(&row.get::<_, Vec<u8>>(2)?),
+                        colbert:       row.get::<_, Option<Vec<u8>>>(3)?.unwrap_or_default(),
+                        colbert_scale: row.get::<_, Option<f64>>(4)?.unwrap_or(1.0),
+                     }))
+                  })
+                  .map_err(SqliteStoreError::Query)?;
+               for row in rows {
+                  let (chunk_hash, embedding) = row.map_err(SqliteStoreError::Query)?;
+                  embeddings.insert(chunk_hash, embedding);
+               }
+            }
+            Ok(embeddings)
+         })
+         .await
+   }
+
+   async fn delete_store(&self, store_id: &str) -> Result<()> {
+      self.connections.write().remove(store_id);
+
+      let db_path = self.db_path(store_id);
+      for suffix in ["", "-wal", "-shm"] {
+         let mut path = db_path.clone().into_os_string();
+         path.push(suffix);
+         match fs::remove_file(&path) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into()),
+         }
+      }
+
+      Ok(())
+   }
+
+   async fn get_info(&self, store_id: &str) -> Result<StoreInfo> {
+      let row_count = self
+         .with_conn(store_id, |conn| {
+            Ok(conn
+               .query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get::<_, i64>(0))
+               .map_err(SqliteStoreError::Query)?)
+         })
+         .await?;
+
+      Ok(StoreInfo {
+         store_id:        store_id.to_string(),
+         row_count:       row_count as u64,
+         path:            self.db_path(store_id),
+         dense_dim:       config::get().dense_vector_dim(),
+         dense_quantized: false,
+      })
+   }
+
+   async fn list_files(&self, store_id: &str) -> Result<Vec<IndexedFile>> {
+      self
+         .with_conn(store_id, |conn| {
+            let mut stmt = conn
+               .prepare("SELECT path, MAX(indexed_at) FROM chunks GROUP BY path")
+               .map_err(SqliteStoreError::Query)?;
+            let files = stmt
+               .query_map([], |row| {
+                  Ok(IndexedFile {
+                     path:       PathBuf::from(row.get::<_, String>(0)?),
+                     indexed_at: row.get::<_, Option<i64>>(1)?.map(|t| t as u64),
+                  })
+               })
+               .map_err(SqliteStoreError::Query)?
+               .collect::<Result<_, _>>()
+               .map_err(SqliteStoreError::Query)?;
+            Ok(files)
+         })
+         .await
+   }
+
+   async fn is_empty(&self, store_id: &str) -> Result<bool> {
+      if !self.db_path(store_id).exists() {
+         return Ok(true);
+      }
+
+      self
+         .with_conn(store_id, |conn| {
+            let exists: bool = conn
+               .query_row("SELECT EXISTS(SELECT 1 FROM chunks)", [], |row| row.get(0))
+               .map_err(SqliteStoreError::Query)?;
+            Ok(!exists)
+         })
+         .await
+   }
+
+   async fn create_fts_index(&self, store_id: &str) -> Result<()> {
+      // The FTS table is maintained by triggers; merging segments keeps
+      // queries fast after large syncs.
+      self
+         .with_conn(store_id, |conn| {
+            conn
+               .execute("INSERT INTO chunks_fts(chunks_fts) VALUES ('optimize')", [])
+               .map_err(SqliteStoreError::Query)?;
+            Ok(())
+         })
+         .await
+   }
+
+   async fn create_vector_index(&self, _store_id: &str) -> Result<()> {
+      // sqlite-vec scans vectors exhaustively; there is no index to build.
+      Ok(())
+   }
+
+   async fn get_file_hashes(&self, store_id: &str) -> Result<HashMap<PathBuf, FileHash>> {
+      if !self.db_path(store_id).exists() {
+         return Ok(HashMap::new());
+      }
+
+      self
+         .with_conn(store_id, |conn| {
+            let mut stmt = conn
+               .prepare("SELECT path, hash FROM chunks GROUP BY path")
+               .map_err(SqliteStoreError::Query)?;
+            let rows = stmt
+               .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))
+               .map_err(SqliteStoreError::Query)?;
+
+            let mut hashes = HashMap::new();
+            for row in rows {
+               let (path, hash) = row.map_err(SqliteStoreError::Query)?;
+               let Some(hash) = FileHash::from_slice(&hash) else {
+                  tracing::warn!("skipping corrupted hash for path: {path:?}");
+                  continue;
+               };
+               hashes.insert(PathBuf::from(path), hash);
+            }
+            Ok(hashes)
+         })
+         .await
+   }
+
+   async fn relativize(&self, store_id: &str, root: &Path) -> Result<bool> {
+      let Some((prefix, len)) = store::root_prefix(root) else {
+         return Ok(false);
+      };
+      if !self.db_path(store_id).exists() {
+         return Ok(true);
+      }
+
+      self
+         .with_conn(store_id, move |conn| {
+            conn
+               .execute(
+                  "UPDATE chunks SET path = substr(path, ?2), id = substr(id, ?2) WHERE \
+                   substr(path, 1, ?3) = ?1",
+                  params![prefix, len + 1, len],
+               )
+               .map_err(SqliteStoreError::Update)?;
+            Ok(true)
+         })
+         .await
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use ndarray::Array2;
+   use tempfile::TempDir;
+
+   use super::*;
+   use crate::{Str, grammar::GrammarManager, store::Store};
+
+   fn record(path: &str, content: &'static str, axis: usize) -> VectorRecord {
+      let mut vector = vec![0.0; config::get().dense_vector_dim()];
+      vector[axis] = 1.0;
+      VectorRecord {
+         id: format!("{path}:0"),
+         path: Arc::new(PathBuf::from(path)),
+         hash: FileHash::sum(content),
+         chunk_hash: FileHash::sum(content),
+         content: content.into(),
+         start_line: 0,
+         end_line: 3,
+         chunk_index: Some(0),
+         is_anchor: Some(false),
+         chunk_type: Some(ChunkType::Function),
+         context_prev: None,
+         context_next: None,
+         vector,
+         colbert: vec![],
+         colbert_scale: 1.0,
+         indexed_at: Some(100),
+         crate_name: Some(if axis == 0 { "app-core" } else { "app" }.into()),
+         bridges: bridge::collect(Path::new(path), content),
+         language: GrammarManager::language_for(Path::new(path), content).map(Str::from),
+      }
+   }
+
+   #[tokio::test]
+   async fn insert_search_and_delete() {
+      let dir = TempDir::new().unwrap();
+      let store = SqliteStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      assert!(store.is_empty("s").await.unwrap());
+
+      store
+         .insert_batch("s", vec![
+            record("/repo/a.rs", "fn connect_database() {}", 0),
+            record("/repo/b.rs", "fn parse_config() {}", 1),
+         ])
+         .await
+         .unwrap();
+      assert!(!store.is_empty("s").await.unwrap());
+
+      let mut query = vec![0.0; config::get().dense_vector_dim()];
+      query[1] = 1.0;
+      let colbert = Array2::zeros((0, 0));
+      let response = store
+         .search(SearchParams {
+            store_id:      "s",
+            query_text:    "config",
+            query_vector:  &query,
+            query_colbert: &colbert,
+            limit:         10,
+            path_filter:   None,
+            path_globs:    None,
+            indexed_after: None,
+            crate_name:    None,
+            rerank:        false,
+            rerank_depth:  None,
+            nprobes:       None,
+            exact:         false,
+            cancel:        None,
+         })
+         .await
+         .unwrap();
+      assert_eq!(response.results[0].path, Path::new("/repo/b.rs"));
+      assert_eq!(response.results.len(), 2);
+      assert_eq!(response.results[0].language.as_deref(), Some("rust"));
+
+      let scoped = store
+         .search(SearchParams {
+            store_id:      "s",
+            query_text:    "config",
+            query_vector:  &query,
+            query_colbert: &colbert,
+            limit:         10,
+            path_filter:   None,
+            path_globs:    None,
+            indexed_after: None,
+            crate_name:    Some("app-core"),
+            rerank:        false,
+            rerank_depth:  None,
+            nprobes:       None,
+            exact:         false,
+            cancel:        None,
+         })
+         .await
+         .unwrap();
+      assert_eq!(scoped.results.len(), 1);
+      assert_eq!(scoped.results[0].path, Path::new("/repo/a.rs"));
+
+      store
+         .delete_file("s", Path::new("/repo/b.rs"))
+         .await
+         .unwrap();
+      assert_eq!(store.list_files("s").await.unwrap(), vec![IndexedFile {
+         path:       PathBuf::from("/repo/a.rs"),
+         indexed_at: Some(100),
+      }]);
+      assert_eq!(store.get_file_hashes("s").await.unwrap().len(), 1);
+
+      let embeddings = store
+         .get_chunk_embeddings("s", &[PathBuf::from("/repo/a.rs")])
+         .await
+         .unwrap();
+      assert_eq!(embeddings.len(), 1);
+      assert_eq!(embeddings[&FileHash::sum("fn connect_database() {}")].dense[0], 1.0);
+
+      store.delete_store("s").await.unwrap();
+      assert!(store.is_empty("s").await.unwrap());
+   }
+
+   #[tokio::test]
+   async fn replace_files_rewrites_changed_chunks_only() {
+      let dir = TempDir::new().unwrap();
+      let store = SqliteStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      let chunk = |index: u32, content: &'static str| VectorRecord {
+         id: format!("/repo/a.rs:{index}"),
+         chunk_index: Some(index),
+         start_line: index * 3,
+         end_line: index * 3 + 2,
+         ..record("/repo/a.rs", content, 0)
+      };
+      store
+         .insert_batch("s", vec![
+            chunk(0, "fn a() {}"),
+            chunk(1, "fn b() {}"),
+            chunk(2, "fn c() {}"),
+         ])
+         .await
+         .unwrap();
+
+      let written = store
+         .replace_files("s", &[PathBuf::from("/repo/a.rs")], vec![
+            chunk(0, "fn a() {}"),
+            chunk(1, "fn b2() {}"),
+         ])
+         .await
+         .unwrap();
+      assert_eq!(written, 1);
+
+      let embeddings = store
+         .get_chunk_embeddings("s", &[PathBuf::from("/repo/a.rs")])
+         .await
+         .unwrap();
+      assert_eq!(embeddings.len(), 2);
+      assert!(embeddings.contains_key(&FileHash::sum("fn a() {}")));
+      assert!(embeddings.contains_key(&FileHash::sum("fn b2() {}")));
+      assert_eq!(store.list_files("s").await.unwrap().len(), 1);
+   }
+
+   #[tokio::test]
+   async fn keyword_search_matches_terms_only() {
+      let dir = TempDir::new().unwrap();
+      let store = SqliteStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      store
+         .insert_batch("s", vec![
+            record("/repo/a.rs", "fn connect_database() {}", 0),
+            record("/repo/b.rs", "fn parse_config() {}", 1),
+         ])
+         .await
+         .unwrap();
+
+      let response = store
+         .keyword_search(KeywordParams {
+            store_id:      "s",
+            query_text:    "config",
+            limit:         10,
+            path_filter:   None,
+            path_globs:    None,
+            indexed_after: None,
+            crate_name:    None,
+         })
+         .await
+         .unwrap();
+      assert_eq!(response.mode, VectorSearchMode::Keyword);
+      assert_eq!(response.results.len(), 1);
+      assert_eq!(response.results[0].path, Path::new("/repo/b.rs"));
+   }
+
+   #[tokio::test]
+   async fn find_bridged_matches_whole_keys() {
+      let dir = TempDir::new().unwrap();
+      let store = SqliteStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      store
+         .insert_batch("s", vec![
+            record("/repo/ffi.rs", "#[no_mangle]\npub extern \"C\" fn zs_init() {}", 0),
+            record("/repo/init.c", "int zs_init(void) {\n   return 0;\n}", 1),
+            record("/repo/other.c", "int zs_init_all(void);", 1),
+         ])
+         .await
+         .unwrap();
+
+      let found = store.find_bridged("s", "c:zs_init", 10).await.unwrap();
+      let mut paths: Vec<_> = found.iter().map(|r| r.path.as_path()).collect();
+      paths.sort();
+      assert_eq!(paths, [Path::new("/repo/ffi.rs"), Path::new("/repo/init.c")]);
+      assert!(
+         found
+            .iter()
+            .all(|r| r.bridges.as_deref() == Some(" c:zs_init "))
+      );
+   }
+
+   #[tokio::test]
+   async fn relativize_rewrites_rows_under_root() {
+      let dir = TempDir::new().unwrap();
+      let store = SqliteStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+      store
+         .insert_batch("s", vec![
+            record("/repo/src/a.rs", "fn connect_database() {}", 0),
+            record("/elsewhere/b.rs", "fn parse_config() {}", 1),
+         ])
+         .await
+         .unwrap();
+
+      assert!(store.relativize("s", Path::new("/repo")).await.unwrap());
+      let mut paths: Vec<_> = store
+         .get_file_hashes("s")
+         .await
+         .unwrap()
+         .into_keys()
+         .collect();
+      paths.sort();
+      assert_eq!(paths, [PathBuf::from("/elsewhere/b.rs"), PathBuf::from("src/a.rs")]);
+
+      store
+         .delete_files("s", &[PathBuf::from("src/a.rs")])
+         .await
+         .unwrap();
+      assert_eq!(store.list_files("s").await.unwrap().len(), 1);
+   }
+
+   #[test]
+   fn fts_query_quotes_terms() {
+      assert_eq!(
+         SqliteStore::fts_query("where's \"auth\" OR*").as_deref(),
+         Some("\"where\" OR \"s\" OR \"auth\" OR \"OR\"")
+      );
+      assert_eq!(SqliteStore::fts_query("?!"), None);
+   }
+}
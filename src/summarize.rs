@@ -0,0 +1,306 @@
+//! Natural-language summaries of definition chunks.
+//!
+//! A [`Summarizer`] describes each function, class or type in one sentence
+//! before it is embedded, so that questions asked in prose match the code
+//! they describe. Summaries are cached per store by chunk content, and only
+//! new or edited definitions are sent to the model.
+
+use std::{
+   collections::HashMap,
+   fs,
+   path::{Path, PathBuf},
+   sync::Arc,
+   time::Duration,
+};
+
+use futures::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+   Result, Str, config,
+   error::HttpError,
+   meta::FileHash,
+   types::{ChunkType, PreparedChunk},
+};
+
+/// Summaries requested from the model at once.
+const CONCURRENCY: usize = 4;
+/// Time allowed for one summary before the chunk is embedded without it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Instructions sent along with each chunk.
+const PROMPT: &str = "Summarize what the following code does in one plain English sentence. Name \
+                      its purpose, not its syntax. Reply with the sentence only.";
+
+/// Writes a one-sentence summary of a chunk of code.
+#[async_trait::async_trait]
+pub trait Summarizer: Send + Sync {
+   /// Summarizes `code`, a definition from the file at `path`.
+   async fn summarize(&self, path: &Path, code: &str) -> Result<String>;
+}
+
+/// Summarizer backed by an OpenAI-compatible chat completions endpoint, such
+/// as a local Ollama or llama.cpp server or a hosted API.
+pub struct HttpSummarizer {
+   client:  reqwest::Client,
+   url:     String,
+   model:   String,
+   api_key: Option<String>,
+}
+
+impl HttpSummarizer {
+   pub fn new(url: String, model: String, api_key: Option<String>) -> Self {
+      let client = reqwest::Client::builder()
+         .timeout(REQUEST_TIMEOUT)
+         .build()
+         .unwrap_or_default();
+      Self { client, url, model, api_key }
+   }
+}
+
+#[derive(Deserialize)]
+struct Completion {
+   choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+   message: Message,
+}
+
+#[derive(Deserialize)]
+struct Message {
+   content: String,
+}
+
+#[async_trait::async_trait]
+impl Summarizer for HttpSummarizer {
+   async fn summarize(&self, path: &Path, code: &str) -> Result<String> {
+      let body = json!({
+         "model": self.model,
+         "temperature": 0,
+         "max_tokens": 80,
+         "messages": [
+            { "role": "system", "content": PROMPT },
+            { "role": "user", "content": format!("File: {}\n\n{code}", path.display()) },
+         ],
+      });
+      let mut request = self.client.post(&self.url).json(&body);
+      if let Some(key) = &self.api_key {
+         request = request.bearer_auth(key);
+      }
+      let response = request.send().await.map_err(HttpError::from)?;
+      if !response.status().is_success() {
+         return Err(HttpError::StatusCode(response.status().as_u16()).into());
+      }
+      let completion: Completion = response.json().await.map_err(HttpError::from)?;
+      Ok(completion
+         .choices
+         .into_iter()
+         .next()
+         .map(|choice| choice.message.content.trim().to_string())
+         .unwrap_or_default())
+   }
+}
+
+/// The summarizer set up by `summarizer_url`, if any.
+pub fn from_config() -> Option<Arc<dyn Summarizer>> {
+   let cfg = config::get();
+   let url = cfg.summarizer_url.clone()?;
+   Some(Arc::new(HttpSummarizer::new(
+      url,
+      cfg.summarizer_model.clone(),
+      cfg.summarizer_api_key.clone(),
+   )))
+}
+
+/// Summaries of a store's chunks, keyed by the hash of the chunk content.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SummaryCache {
+   summaries: HashMap<String, String>,
+   #[serde(skip)]
+   path:      PathBuf,
+   #[serde(skip)]
+   dirty:     bool,
+}
+
+impl SummaryCache {
+   /// Loads the cache of `store_id`, starting empty when there is none or it
+   /// cannot be read.
+   pub fn load(store_id: &str) -> Self {
+      let path = config::meta_dir().join(format!("{store_id}.summaries.json"));
+      let mut cache: Self = fs::read_to_string(&path)
+         .ok()
+         .and_then(|content| serde_json::from_str(&content).ok())
+         .unwrap_or_default();
+      cache.path = path;
+      cache
+   }
+
+   pub fn save(&mut self) -> Result<()> {
+      if !self.dirty {
+         return Ok(());
+      }
+      if let Some(parent) = self.path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+      fs::write(&self.path, serde_json::to_string(&self.summaries)?)?;
+      self.dirty = false;
+      Ok(())
+   }
+
+   fn get(&self, key: &str) -> Option<&str> {
+      self.summaries.get(key).map(String::as_str)
+   }
+
+   fn insert(&mut self, key: String, summary: String) {
+      self.summaries.insert(key, summary);
+      self.dirty = true;
+   }
+}
+
+/// Whether chunks of `chunk_type` get a summary.
+const fn is_definition(chunk_type: Option<ChunkType>) -> bool {
+   matches!(
+      chunk_type,
+      Some(
+         ChunkType::Function
+            | ChunkType::Class
+            | ChunkType::Interface
+            | ChunkType::Method
+            | ChunkType::TypeAlias
+      )
+   )
+}
+
+/// Puts a summary before the embedded text of each definition in `chunks`,
+/// asking `summarizer` for those not in `cache`.
+///
+/// A chunk the model fails on is embedded without a summary, so that an
+/// unreachable model slows a sync down rather than stopping it.
+pub async fn summarize_chunks(
+   summarizer: &dyn Summarizer,
+   cache: &Mutex<SummaryCache>,
+   chunks: &mut [PreparedChunk],
+) {
+   let keys: Vec<Option<String>> = chunks
+      .iter()
+      .map(|chunk| {
+         is_definition(chunk.chunk_type)
+            .then(|| FileHash::sum(chunk.content.as_bytes()).to_string())
+      })
+      .collect();
+
+   let missing: Vec<usize> = {
+      let cache = cache.lock();
+      keys
+         .iter()
+         .enumerate()
+         .filter(|(_, key)| key.as_deref().is_some_and(|key| cache.get(key).is_none()))
+         .map(|(i, _)| i)
+         .collect()
+   };
+   let computed: Vec<(usize, Result<String>)> = stream::iter(missing)
+      .map(|i| {
+         let chunk = &chunks[i];
+         async move {
+            (
+               i,
+               summarizer
+                  .summarize(&chunk.path, chunk.content.as_str())
+                  .await,
+            )
+         }
+      })
+      .buffer_unordered(CONCURRENCY)
+      .collect()
+      .await;
+
+   let mut cache = cache.lock();
+   for (i, summary) in computed {
+      match summary {
+         Ok(summary) if !summary.is_empty() => {
+            if let Some(key) = keys[i].clone() {
+               cache.insert(key, summary);
+            }
+         },
+         Ok(_) => {},
+         Err(e) => {
+            tracing::warn!("failed to summarize a chunk of {}: {e}", chunks[i].path.display());
+         },
+      }
+   }
+   for (chunk, key) in chunks.iter_mut().zip(&keys) {
+      let Some(summary) = key.as_deref().and_then(|key| cache.get(key)) else {
+         continue;
+      };
+      chunk.embed_text = Some(format!("{summary}\n{}", chunk.text_to_embed().as_str()).into());
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::sync::atomic::{AtomicUsize, Ordering};
+
+   use super::*;
+   use crate::Error;
+
+   /// Summarizes every chunk as its first line, failing on empty ones.
+   #[derive(Default)]
+   struct FirstLine {
+      calls: AtomicUsize,
+   }
+
+   #[async_trait::async_trait]
+   impl Summarizer for FirstLine {
+      async fn summarize(&self, _path: &Path, code: &str) -> Result<String> {
+         self.calls.fetch_add(1, Ordering::Relaxed);
+         match code.lines().next() {
+            Some(line) => Ok(format!("Summary of {line}")),
+            None => Err(Error::Cancelled),
+         }
+      }
+   }
+
+   fn chunk(content: &'static str, chunk_type: ChunkType) -> PreparedChunk {
+      PreparedChunk {
+         id:           String::new(),
+         path:         Arc::new(PathBuf::from("src/lib.rs")),
+         hash:         FileHash::default(),
+         content:      Str::from_static(content),
+         start_line:   0,
+         end_line:     0,
+         chunk_index:  None,
+         is_anchor:    Some(false),
+         chunk_type:   Some(chunk_type),
+         context_prev: None,
+         context_next: None,
+         bridges:      None,
+         language:     None,
+         embed_text:   None,
+      }
+   }
+
+   #[tokio::test]
+   async fn summaries_precede_definitions_and_are_cached() {
+      let summarizer = FirstLine::default();
+      let cache = Mutex::new(SummaryCache::default());
+      let mut chunks = [
+         chunk("fn parse() {}", ChunkType::Function),
+         chunk("let x = 1;", ChunkType::Block),
+         chunk("", ChunkType::Method),
+      ];
+
+      summarize_chunks(&summarizer, &cache, &mut chunks).await;
+      assert_eq!(chunks[0].text_to_embed().as_str(), "Summary of fn parse() {}\nfn parse() {}");
+      assert_eq!(chunks[1].embed_text, None);
+      assert_eq!(chunks[2].embed_text, None);
+      assert_eq!(summarizer.calls.load(Ordering::Relaxed), 2);
+
+      let mut again = [chunk("fn parse() {}", ChunkType::Function)];
+      summarize_chunks(&summarizer, &cache, &mut again).await;
+      assert_eq!(again[0].text_to_embed(), chunks[0].text_to_embed());
+      assert_eq!(summarizer.calls.load(Ordering::Relaxed), 2);
+   }
+}
@@ -1,54 +1,372 @@
 //! File synchronization and indexing engine
 
 use std::{
-   collections::HashSet,
+   collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+   io::Read,
+   ops::Range,
    path::{Path, PathBuf},
    sync::Arc,
+   time::SystemTime,
 };
 
+use encoding_rs::Encoding;
 use futures::stream::{self, StreamExt};
 use indicatif::ProgressBar;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 pub use crate::types::SyncProgress;
 use crate::{
-   Result, Str,
-   chunker::{Chunker, anchor::create_anchor_chunk},
-   config,
-   embed::Embedder,
-   file::FileSystem,
+   Error, Result, Str,
+   chunker::{
+      Chunker, anchor::create_anchor_chunk, bridge, embed_text, module::create_module_chunk,
+      stream::chunk_file,
+   },
+   config::{self, FileHashAlgorithm},
+   disk, docs,
+   embed::{self, Embedder, HybridEmbedding},
+   file::{self, FileSystem, Unindexable},
+   git,
+   grammar::GrammarManager,
+   hooks::{self, Hook},
    index_lock::IndexLock,
-   meta::{FileHash, MetaStore},
+   meta::{self, FileHash, FileStat, MetaStore, SyncActor, SyncRun},
    store::Store,
-   types::{PreparedChunk, VectorRecord},
+   summarize::{self, Summarizer, SummaryCache},
+   types::{ChunkType, PreparedChunk, VectorRecord},
+   util,
+   workspace::CargoWorkspace,
 };
 
-/// Gets file modification time as Unix seconds
-async fn get_mtime(path: &Path) -> u64 {
+/// Gets file modification time as Unix seconds, and size
+async fn get_stat(path: &Path) -> FileStat {
    let Ok(metadata) = tokio::fs::metadata(path).await else {
-      return 0;
+      return FileStat::default();
    };
-   metadata
+   let mtime = metadata
       .modified()
       .ok()
       .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-      .map_or(0, |d| d.as_secs())
+      .map_or(0, |d| d.as_secs());
+   FileStat { mtime, size: metadata.len() }
+}
+
+/// Batches in flight between two stages of the indexing pipeline.
+const PIPELINE_DEPTH: usize = 2;
+
+/// Files a directory needs for a module summary; one file's anchor already
+/// says as much.
+pub const MIN_MODULE_FILES: usize = 2;
+
+/// Bytes from the start of a streamed file that are checked for binary or
+/// minified content.
+const STREAMED_SNIFF_BYTES: u64 = 64 * 1024;
+
+/// Content of a file to index.
+enum Content {
+   /// Read into memory and decoded.
+   Loaded { text: Str, encoding: &'static Encoding },
+   /// Over `stream_chunk_threshold_kb`, so left on disk until it is chunked a
+   /// segment at a time.
+   Streamed { len: u64, encoding: &'static Encoding },
+}
+
+impl Content {
+   const fn len(&self) -> u64 {
+      match self {
+         Self::Loaded { text, .. } => text.len() as u64,
+         Self::Streamed { len, .. } => *len,
+      }
+   }
+
+   const fn encoding(&self) -> &'static Encoding {
+      match self {
+         Self::Loaded { encoding, .. } | Self::Streamed { encoding, .. } => encoding,
+      }
+   }
+}
+
+/// Hashes a file without loading it, returning the hash and the file's
+/// first bytes for content checks.
+fn hash_large_file(
+   path: &Path,
+   algorithm: FileHashAlgorithm,
+) -> std::io::Result<(FileHash, Vec<u8>)> {
+   let mut file = std::fs::File::open(path)?;
+   let mut head = Vec::new();
+   (&mut file)
+      .take(STREAMED_SNIFF_BYTES)
+      .read_to_end(&mut head)?;
+   let hash = FileHash::digest_reader(algorithm, head.as_slice().chain(file))?;
+   Ok((hash, head))
+}
+
+/// Files with their hash, stat, encoding label and prepared chunks.
+type FileBatch = Vec<(PathBuf, FileHash, FileStat, Option<&'static str>, Vec<PreparedChunk>)>;
+
+/// Batch of files whose chunks have been embedded, ready to be written.
+struct EmbeddedBatch {
+   /// Path, file hash, stat and encoding label of each file.
+   files:    Vec<(PathBuf, FileHash, FileStat, Option<&'static str>)>,
+   /// Files in the batch that already have rows in the store.
+   replaced: Vec<PathBuf>,
+   records:  Vec<VectorRecord>,
+   /// Files left out because their chunks could not be embedded, with the
+   /// error.
+   failed:   Vec<(PathBuf, String)>,
+}
+
+/// Store of the default branch or main checkout that a branch or worktree
+/// store reuses embeddings from.
+pub struct BaseStore {
+   store_id: String,
+   /// Root of the linked worktree being indexed and of the main checkout,
+   /// whose files the base store holds under the same relative paths.
+   worktree: Option<(PathBuf, PathBuf)>,
+}
+
+impl BaseStore {
+   /// Base of the branch or worktree store `store_id` of `root`, if there is
+   /// one built with the current models.
+   pub fn resolve(store_id: &str, root: &Path) -> Option<Self> {
+      let base = git::base_store_id(store_id)?;
+      if !meta::exists(base) {
+         return None;
+      }
+      let meta_store = MetaStore::load(base).ok()?;
+      if meta_store.model_mismatch() {
+         return None;
+      }
+      let worktree = git::main_worktree(root).map(|main| (root.to_path_buf(), main));
+      Some(Self { store_id: base.to_string(), worktree })
+   }
+
+   /// Embeddings of the chunks of `file_paths` in the base store, keyed by
+   /// chunk content hash; none if they cannot be read.
+   pub async fn embeddings<S: Store + ?Sized>(
+      &self,
+      store: &S,
+      file_paths: &[PathBuf],
+   ) -> HashMap<FileHash, HybridEmbedding> {
+      let mapped: Vec<PathBuf>;
+      let file_paths = match &self.worktree {
+         Some((root, main)) => {
+            mapped = file_paths
+               .iter()
+               .filter_map(|path| Some(main.join(path.strip_prefix(root).ok()?)))
+               .collect();
+            &mapped
+         },
+         None => file_paths,
+      };
+      store
+         .get_chunk_embeddings(&self.store_id, file_paths)
+         .await
+         .unwrap_or_else(|e| {
+            tracing::debug!("failed to read embeddings of {}: {e}", self.store_id);
+            HashMap::new()
+         })
+   }
+}
+
+/// Adds a finished run to the store's sync history.
+fn record_sync(store_id: &str, run: SyncRun) -> Result<()> {
+   let _lock = IndexLock::acquire(store_id)?;
+   let mut meta_store = MetaStore::load(store_id)?;
+   meta_store.record_sync(run);
+   meta_store.save()
+}
+
+/// Keeps the rows of `store_id` relative to `root`, the directory being
+/// indexed: a store that already is, moved along with the repository or
+/// imported from elsewhere, is moved over to it, and so is a store without
+/// rows yet. A store from before roots were recorded has its rows rewritten
+/// relative to `root` where the backend can, and otherwise keeps absolute
+/// paths.
+pub async fn adopt_root<S: Store + ?Sized>(
+   store: &S,
+   store_id: &str,
+   meta_store: &mut MetaStore,
+   root: &Path,
+) -> Result<()> {
+   let adopt = root.is_absolute()
+      && (meta_store.root().is_some()
+         || store.is_empty(store_id).await?
+         || store.relativize(store_id, root).await?);
+   if adopt && meta_store.set_root(root) {
+      meta_store.save()?;
+   }
+   store.set_root(store_id, meta_store.root());
+   Ok(())
+}
+
+/// Starts a branch or worktree store that has no files yet out sharing the
+/// rows of its base store, so that only the files that differ from the base
+/// are indexed into it. Stores without a root, and those that already hold
+/// files of their own, are left as they are.
+pub fn share_base(store_id: &str, meta_store: &mut MetaStore) -> Result<()> {
+   let Some(base) = git::base_store_id(store_id) else {
+      return Ok(());
+   };
+   if meta_store.base().is_some()
+      || meta_store.root().is_none()
+      || meta_store.all_paths().next().is_some()
+   {
+      return Ok(());
+   }
+   if meta_store.set_base(base) {
+      tracing::info!("{store_id} shares the rows of {base}");
+      meta_store.save()?;
+   }
+   Ok(())
+}
+
+/// Directories holding the files of `paths` that lie under `root`, whose
+/// module summaries change with them.
+pub fn module_dirs<'a>(
+   root: &Path,
+   paths: impl IntoIterator<Item = &'a PathBuf>,
+) -> BTreeSet<PathBuf> {
+   paths
+      .into_iter()
+      .filter_map(|path| path.parent())
+      .filter(|dir| dir.starts_with(root))
+      .map(Path::to_path_buf)
+      .collect()
+}
+
+/// Rewrites the module summaries of `dirs` from the `indexed` files directly
+/// inside each, and removes those of directories left with fewer than
+/// [`MIN_MODULE_FILES`]. Summaries whose text is unchanged keep their stored
+/// embeddings. Returns the number of summaries written.
+pub async fn refresh_module_chunks<E, S>(
+   embedder: &E,
+   store: &S,
+   store_id: &str,
+   dirs: &BTreeSet<PathBuf>,
+   indexed: &[PathBuf],
+   workspace: &CargoWorkspace,
+) -> Result<usize>
+where
+   E: Embedder + ?Sized,
+   S: Store + ?Sized,
+{
+   let mut children: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+   for path in indexed {
+      if let Some(dir) = path.parent()
+         && dirs.contains(dir)
+      {
+         children.entry(dir).or_default().push(path);
+      }
+   }
+   children.retain(|_, files| files.len() >= MIN_MODULE_FILES);
+
+   let emptied: Vec<PathBuf> = dirs
+      .iter()
+      .filter(|dir| !children.contains_key(dir.as_path()))
+      .cloned()
+      .collect();
+   if !emptied.is_empty() {
+      store.delete_files(store_id, &emptied).await?;
+   }
+   if children.is_empty() {
+      return Ok(0);
+   }
+
+   let mut chunks = Vec::with_capacity(children.len());
+   for (dir, files) in children {
+      let contents: Vec<(&Path, Str)> = files
+         .into_iter()
+         .filter_map(|path| {
+            let bytes = std::fs::read(path).ok()?;
+            Some((path, file::encoding::decode(&bytes, None).0))
+         })
+         .collect();
+      let files: Vec<(&Path, &str)> = contents
+         .iter()
+         .map(|(path, text)| (*path, text.as_str()))
+         .collect();
+      let chunk = create_module_chunk(dir, &files);
+      let embed = embed_text(dir, &chunk).unwrap_or_else(|| chunk.content.clone());
+      chunks.push((dir.to_path_buf(), chunk.content, embed));
+   }
+
+   let written: Vec<PathBuf> = chunks.iter().map(|(dir, ..)| dir.clone()).collect();
+   let reuse = store.get_chunk_embeddings(store_id, &written).await?;
+   let texts: Vec<Str> = chunks.iter().map(|(.., embed)| embed.clone()).collect();
+   let (embeddings, _) = embed::compute_hybrid_reusing(embedder, &texts, &reuse).await?;
+
+   let indexed_at = util::unix_now();
+   let records: Vec<VectorRecord> = chunks
+      .into_iter()
+      .zip(embeddings)
+      .map(|((dir, content, embed), embedding)| VectorRecord {
+         id: format!("{}:module", dir.display()),
+         crate_name: workspace.crate_for(&dir),
+         path: Arc::new(dir),
+         hash: FileHash::sum(content.as_bytes()),
+         chunk_hash: FileHash::sum(embed.as_bytes()),
+         content,
+         start_line: 0,
+         end_line: 0,
+         chunk_index: None,
+         is_anchor: Some(false),
+         chunk_type: Some(ChunkType::Module),
+         context_prev: None,
+         context_next: None,
+         vector: embedding.dense,
+         colbert: embedding.colbert,
+         colbert_scale: embedding.colbert_scale,
+         indexed_at: Some(indexed_at),
+         bridges: None,
+         language: None,
+      })
+      .collect();
+   let count = records.len();
+   store.replace_files(store_id, &written, records).await?;
+   Ok(count)
 }
 
 /// Engine for synchronizing files to the index
-pub struct SyncEngine<F: FileSystem, E: Embedder, S: Store> {
+pub struct SyncEngine<F: FileSystem, C: Chunker, E: Embedder, S: Store> {
    file_system: F,
-   chunker:     Chunker,
+   chunker:     C,
    embedder:    E,
    store:       S,
+   summarizer:  Option<Arc<dyn Summarizer>>,
+   paranoid:    bool,
 }
 
 /// Result summary from a sync operation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SyncResult {
    pub processed: usize,
    pub indexed:   usize,
    pub skipped:   usize,
    pub deleted:   usize,
+
+   /// Files left out of the index by content; see [`crate::file::sniff`].
+   pub binary:    usize,
+   pub minified:  usize,
+   pub too_large: usize,
+
+   /// Files whose chunks could not be embedded; the next sync retries them.
+   pub failed:     usize,
+   #[serde(skip)]
+   pub last_error: Option<String>,
+}
+
+/// JSON passed on stdin to the index hooks; `post_index` also receives the
+/// sync counts.
+#[derive(Serialize)]
+struct IndexHookPayload<'a> {
+   store_id: &'a str,
+   root:     &'a Path,
+   #[serde(flatten)]
+   result:   Option<&'a SyncResult>,
 }
 
 /// Trait for receiving sync progress updates
@@ -79,27 +397,91 @@ impl SyncProgressCallback for ProgressBar {
    }
 }
 
-impl<F, E, S> SyncEngine<F, E, S>
+impl<F, C, E, S> SyncEngine<F, C, E, S>
 where
    F: FileSystem + Sync,
+   C: Chunker,
    E: Embedder + Send + Sync,
    S: Store + Send + Sync,
 {
-   pub const fn new(file_system: F, chunker: Chunker, embedder: E, store: S) -> Self {
-      Self { file_system, chunker, embedder, store }
+   pub const fn new(file_system: F, chunker: C, embedder: E, store: S) -> Self {
+      Self { file_system, chunker, embedder, store, summarizer: None, paranoid: false }
+   }
+
+   /// Summarizes definition chunks with `summarizer`, when there is one,
+   /// and embeds each with its summary.
+   pub fn with_summarizer(mut self, summarizer: Option<Arc<dyn Summarizer>>) -> Self {
+      self.summarizer = summarizer;
+      self
+   }
+
+   /// Hashes every file, rather than taking those whose modification time
+   /// and size match the metadata to be unchanged.
+   pub const fn with_paranoid(mut self, paranoid: bool) -> Self {
+      self.paranoid = paranoid;
+      self
    }
 
    /// Performs an initial sync of files to the index
+   ///
+   /// Once `cancel` fires the sync stops at the next batch boundary, or
+   /// abandons the embedding in flight, and fails with
+   /// [`Error::Cancelled`](crate::Error::Cancelled). Batches already written
+   /// are recorded, so a rerun resumes after them.
+   ///
+   /// Unless `dry_run` is set, the run is added to the store's sync history
+   /// under `actor`, whether it succeeds or not.
+   #[tracing::instrument(name = "sync", skip_all, fields(store_id = %store_id))]
    pub async fn initial_sync(
+      &self,
+      store_id: &str,
+      root: &Path,
+      dry_run: bool,
+      actor: SyncActor,
+      callback: &mut dyn SyncProgressCallback,
+      cancel: &CancellationToken,
+   ) -> Result<SyncResult> {
+      let started = SystemTime::now();
+      let result = self.sync(store_id, root, dry_run, callback, cancel).await;
+      if !dry_run {
+         let mut run = SyncRun::new(actor, started);
+         match &result {
+            Ok(r) => {
+               run.processed = r.processed;
+               run.indexed = r.indexed;
+               run.deleted = r.deleted;
+               run.errors = r.failed;
+               run.last_error.clone_from(&r.last_error);
+            },
+            Err(e) => {
+               run.errors = 1;
+               run.last_error = Some(e.to_string());
+            },
+         }
+         if let Err(e) = record_sync(store_id, run) {
+            tracing::warn!("Failed to record sync history for {store_id}: {e}");
+         }
+      }
+      result
+   }
+
+   async fn sync(
       &self,
       store_id: &str,
       root: &Path,
       dry_run: bool,
       callback: &mut dyn SyncProgressCallback,
+      cancel: &CancellationToken,
    ) -> Result<SyncResult> {
       const SAVE_INTERVAL: usize = 25;
 
-      let _lock = IndexLock::acquire(store_id)?;
+      // Hooks run outside the lock so that they may invoke smgrep themselves.
+      if !dry_run {
+         let payload = IndexHookPayload { store_id, root, result: None };
+         hooks::run(Hook::PreIndex, root, store_id, &payload).await?;
+      }
+
+      let lock = IndexLock::acquire(store_id)?;
 
       let mut meta_store = MetaStore::load(store_id)?;
       let model_changed = meta_store.model_mismatch();
@@ -110,13 +492,28 @@ where
          meta_store.reset_for_model_change();
       }
 
+      if meta_store.needs_rebuild() {
+         meta_store.rebuild_from(self.store.get_file_hashes(store_id).await?);
+      }
+      if !dry_run {
+         adopt_root(&self.store, store_id, &mut meta_store, root).await?;
+         share_base(store_id, &mut meta_store)?;
+      }
+
       // If lance store is empty but meta_store has entries for this root,
       // clear the stale metadata (data was deleted externally)
       if !dry_run && self.store.is_empty(store_id).await? {
          meta_store.delete_by_prefix(root);
       }
 
-      let files = self.file_system.get_files(root)?.collect::<HashSet<_>>();
+      let mut files = self.file_system.get_files(root)?.collect::<HashSet<_>>();
+      let workspace = CargoWorkspace::discover(root);
+
+      // External docs take precedence over the regular scan when a source lies
+      // inside the repository, and keep their rows while a source is down.
+      let external_docs = docs::collect(&self.file_system, root, &config::get().docs_sources).await;
+      let doc_paths: HashSet<PathBuf> = external_docs.iter().map(|d| d.path.clone()).collect();
+      files.retain(|p| !doc_paths.contains(p));
 
       let mut processed = 0;
       let mut indexed = 0;
@@ -124,7 +521,7 @@ where
 
       let deleted_paths: Vec<PathBuf> = meta_store
          .all_paths()
-         .filter(|p| !files.contains(*p))
+         .filter(|p| !files.contains(*p) && !doc_paths.contains(*p))
          .cloned()
          .collect();
 
@@ -135,159 +532,355 @@ where
          }
       }
 
-      let deleted_count = deleted_paths.len();
+      let mut deleted_count = deleted_paths.len();
 
-      let scanned = stream::iter(files.into_iter().map(|file_path| async {
-         let current_mtime = get_mtime(&file_path).await;
+      let stream_threshold = config::get().stream_chunk_threshold_kb * 1024;
+      let algorithm = config::get().file_hash;
+      // Hashes from another algorithm never match, so every file is hashed
+      // and reindexed, reusing its embeddings
+      let rehash = meta_store.hash_mismatch();
+      if rehash && !dry_run {
+         tracing::info!("re-hashing every file of {store_id} with {}", algorithm.as_str());
+      }
+      let hash_all = self.paranoid || rehash;
+      let scans = stream::iter(files.into_iter().map(|file_path| async {
+         let stat = get_stat(&file_path).await;
 
-         if let Some(stored_mtime) = meta_store.get_mtime(&file_path)
-            && stored_mtime == current_mtime
-         {
+         if !hash_all && meta_store.get_stat(&file_path) == Some(stat) {
             return None;
          }
 
+         if let Some(reason) = file::check_size(&file_path) {
+            return Some(Err((file_path, reason)));
+         }
+         let len = stat.size;
+         let hint = meta_store.get_encoding(&file_path);
          // TODO: blocking I/O in filter_map - could be improved with async iteration
-         let content = std::fs::read(&file_path).ok()?;
-         let hash = FileHash::sum(&content);
+         let streamed = if len > stream_threshold {
+            let (hash, head) = hash_large_file(&file_path, algorithm).ok()?;
+            if let Some(reason) = file::check_content(&head) {
+               return Some(Err((file_path, reason)));
+            }
+            // Lines can only be split before decoding when a newline is one
+            // byte, which rules out UTF-16.
+            let encoding = file::encoding::detect(&head, hint);
+            encoding
+               .is_ascii_compatible()
+               .then_some((Content::Streamed { len, encoding }, hash))
+         } else {
+            None
+         };
+         let (content, hash) = match streamed {
+            Some(streamed) => streamed,
+            None => {
+               let bytes = std::fs::read(&file_path).ok()?;
+               if let Some(reason) = file::check_content(&bytes) {
+                  return Some(Err((file_path, reason)));
+               }
+               let (text, encoding) = file::encoding::decode(&bytes, hint);
+               (Content::Loaded { text, encoding }, FileHash::digest(algorithm, &bytes))
+            },
+         };
 
          let existing_hash = meta_store.get_hash(file_path.as_path());
          let needs_indexing = existing_hash != Some(hash);
          let has_existing_hash = existing_hash.is_some();
 
-         Some((file_path, hash, content, current_mtime, needs_indexing, has_existing_hash))
+         Some(Ok((file_path, hash, content, stat, needs_indexing, has_existing_hash)))
       }))
       .buffer_unordered(64)
       .filter_map(|x| async move { x })
       .collect::<Vec<_>>()
       .await;
 
-      if !dry_run {
-         let changed_files = scanned
-            .iter()
-            .filter_map(|(file_path, _, _, _, needs_indexing, has_existing_hash)| {
-               if *needs_indexing && *has_existing_hash {
-                  Some(file_path.clone())
-               } else {
-                  None
-               }
-            })
-            .collect::<Vec<_>>();
-         if !changed_files.is_empty() {
-            self.store.delete_files(store_id, &changed_files).await?;
+      let mut scanned = Vec::with_capacity(scans.len());
+      let mut unindexable = Vec::new();
+      for scan in scans {
+         match scan {
+            Ok(found) => scanned.push(found),
+            Err(left_out) => unindexable.push(left_out),
          }
       }
 
+      let count = |kind| unindexable.iter().filter(|(_, r)| *r == kind).count();
+      let (binary, minified, too_large) =
+         (count(Unindexable::Binary), count(Unindexable::Minified), count(Unindexable::TooLarge));
+      if !unindexable.is_empty() {
+         tracing::info!(
+            "skipped {binary} binary, {minified} minified and {too_large} oversized files"
+         );
+      }
+
+      // Files that turned binary, minified or too large lose the rows of their
+      // earlier versions.
+      let dropped: Vec<PathBuf> = unindexable
+         .into_iter()
+         .map(|(path, _)| path)
+         .filter(|path| meta_store.get_hash(path).is_some())
+         .collect();
+      if !dry_run && !dropped.is_empty() {
+         self.store.delete_files(store_id, &dropped).await?;
+         for path in &dropped {
+            meta_store.remove(path);
+         }
+      }
+      deleted_count += dropped.len();
+      let mut touched_dirs = module_dirs(root, deleted_paths.iter().chain(&dropped));
+
+      for doc in external_docs {
+         let Some(content) = doc.content else {
+            continue;
+         };
+         let hash = FileHash::digest(algorithm, &content);
+         let (text, encoding) = file::encoding::decode(&content, None);
+         let existing_hash = meta_store.get_hash(&doc.path);
+         let stat = get_stat(&doc.path).await;
+         scanned.push((
+            doc.path,
+            hash,
+            Content::Loaded { text, encoding },
+            stat,
+            existing_hash != Some(hash),
+            existing_hash.is_some(),
+         ));
+      }
+
+      // Changed files keep their old rows until re-embedded, so unchanged
+      // chunks can reuse the stored embeddings.
+      let changed_files: HashSet<PathBuf> = scanned
+         .iter()
+         .filter(|(_, _, _, _, needs_indexing, has_existing_hash)| {
+            *needs_indexing && *has_existing_hash
+         })
+         .map(|(file_path, ..)| file_path.clone())
+         .collect();
+
       let files_to_index: Vec<_> = scanned
          .into_iter()
-         .filter_map(|(path_str, hash, content, mtime, needs_indexing, _)| {
+         .filter_map(|(path_str, hash, content, stat, needs_indexing, _)| {
             processed += 1;
             if !needs_indexing {
+               if !dry_run {
+                  meta_store.set_stat(&path_str, stat);
+               }
                skipped += 1;
                None
             } else if dry_run {
                indexed += 1;
                None
             } else {
-               Some((path_str, hash, content, mtime))
+               Some((path_str, hash, content, stat))
             }
          })
          .collect();
 
-      let chunked_files: Vec<_> = stream::iter(files_to_index.into_iter())
-         .map(|(path, hash, content, mtime)| {
-            let chunker = self.chunker.clone();
-            async move {
-               let content_str = Str::from_utf8_lossy(&content);
-               let path_arc = Arc::new(path.clone());
+      touched_dirs.extend(module_dirs(root, files_to_index.iter().map(|(path, ..)| path)));
+      util::check_cancelled(Some(cancel))?;
 
-               let chunks = match chunker.chunk(&content_str, &path).await {
-                  Ok(c) => c,
-                  Err(e) => {
-                     tracing::warn!("Failed to chunk {}: {}", path.display(), e);
-                     return None;
-                  },
-               };
-               let anchor_chunk = create_anchor_chunk(&content_str, &path);
-
-               let mut prepared_chunks = Vec::with_capacity(chunks.len() + 1);
-
-               let anchor_prepared = PreparedChunk {
-                  id: format!("{}:anchor", path.display()),
-                  path: Arc::clone(&path_arc),
-                  hash,
-                  content: anchor_chunk.content,
-                  start_line: anchor_chunk.start_line as u32,
-                  end_line: anchor_chunk.end_line as u32,
-                  chunk_index: Some(0),
-                  is_anchor: Some(true),
-                  chunk_type: anchor_chunk.chunk_type,
-                  context_prev: None,
-                  context_next: None,
-               };
-               prepared_chunks.push(anchor_prepared);
-
-               for (idx, chunk) in chunks.iter().enumerate() {
-                  let context_prev: Option<Str> = if idx > 0 {
-                     Some(chunks[idx - 1].content.clone())
-                  } else {
-                     None
-                  };
+      let index_dir = disk::index_dir();
+      if let Some(dir) = index_dir
+         && !files_to_index.is_empty()
+      {
+         let source_bytes = files_to_index
+            .iter()
+            .map(|(_, _, content, _)| content.len())
+            .sum();
+         disk::ensure_space(dir, disk::estimate_index_size(source_bytes, files_to_index.len()))?;
+      }
 
-                  let context_next: Option<Str> = if idx < chunks.len() - 1 {
-                     Some(chunks[idx + 1].content.clone())
-                  } else {
-                     None
+      let total_to_embed = files_to_index.len();
+      let summaries = Mutex::new(if self.summarizer.is_some() && total_to_embed > 0 {
+         SummaryCache::load(store_id)
+      } else {
+         SummaryCache::default()
+      });
+      let (chunked_tx, mut chunked_rx) = mpsc::channel::<FileBatch>(PIPELINE_DEPTH);
+      let (embedded_tx, mut embedded_rx) = mpsc::channel::<EmbeddedBatch>(PIPELINE_DEPTH);
+
+      // Chunking, embedding and writing run side by side on successive
+      // batches, so the embedder is not left idle while files are chunked or
+      // rows written. Each stage stops once the next one hangs up.
+      let doc_paths = &doc_paths;
+      let max_chunks = config::get().max_chunks_per_file;
+      let chunking = async move {
+         let mut batches = stream::iter(files_to_index)
+            .map(|(path, hash, content, stat)| {
+               let chunker = &self.chunker;
+               async move {
+                  let is_doc = doc_paths.contains(&path);
+                  let path_arc = Arc::new(path.clone());
+
+                  let encoding = content.encoding();
+                  let (content_str, mut chunks, mut truncated) = match content {
+                     Content::Loaded { text, .. } => match chunker
+                        .chunk(&text, &path)
+                        .instrument(tracing::info_span!("chunk", path = %path.display()))
+                        .await
+                     {
+                        Ok(chunks) => (text, chunks, false),
+                        Err(e) => {
+                           tracing::warn!("Failed to chunk {}: {}", path.display(), e);
+                           return None;
+                        },
+                     },
+                     Content::Streamed { encoding, .. } => {
+                        let file = path.clone();
+                        let task = tokio::task::spawn_blocking(move || {
+                           chunk_file(&file, encoding, max_chunks)
+                        });
+                        match task.await.map_err(std::io::Error::other) {
+                           Ok(Ok(streamed)) => (
+                              streamed.head,
+                              chunker.limit(streamed.chunks).await,
+                              streamed.truncated,
+                           ),
+                           Ok(Err(e)) | Err(e) => {
+                              tracing::warn!("Failed to chunk {}: {}", path.display(), e);
+                              return None;
+                           },
+                        }
+                     },
                   };
+                  if max_chunks > 0 && chunks.len() > max_chunks {
+                     chunks.truncate(max_chunks);
+                     truncated = true;
+                  }
+                  if truncated {
+                     tracing::warn!(
+                        "{} has more than {max_chunks} chunks (max_chunks_per_file); indexing the \
+                         first {max_chunks}",
+                        path.display()
+                     );
+                  }
+                  let anchor_chunk = create_anchor_chunk(&content_str, &path);
+                  let language = GrammarManager::language_for(&path, &content_str).map(Str::from);
 
-                  let prepared = PreparedChunk {
-                     id: format!("{}:{}", path.display(), idx),
+                  let mut prepared_chunks = Vec::with_capacity(chunks.len() + 1);
+
+                  let anchor_prepared = PreparedChunk {
+                     id: format!("{}:anchor", path.display()),
                      path: Arc::clone(&path_arc),
                      hash,
-                     content: chunk.content.clone(),
-                     start_line: chunk.start_line as u32,
-                     end_line: chunk.end_line as u32,
-                     chunk_index: Some(idx as u32 + 1),
-                     is_anchor: Some(false),
-                     chunk_type: chunk.chunk_type,
-                     context_prev,
-                     context_next,
+                     content: anchor_chunk.content,
+                     start_line: anchor_chunk.start_line as u32,
+                     end_line: anchor_chunk.end_line as u32,
+                     chunk_index: Some(0),
+                     is_anchor: Some(true),
+                     chunk_type: anchor_chunk.chunk_type,
+                     context_prev: None,
+                     context_next: None,
+                     bridges: None,
+                     language: language.clone(),
+                     embed_text: None,
                   };
-                  prepared_chunks.push(prepared);
-               }
+                  prepared_chunks.push(anchor_prepared);
 
-               Some((path, hash, mtime, prepared_chunks))
-            }
-         })
-         .buffer_unordered(64)
-         .filter_map(|x| async move { x })
-         .collect()
-         .await;
+                  for (idx, chunk) in chunks.iter().enumerate() {
+                     let context_prev: Option<Str> = if idx > 0 {
+                        Some(chunks[idx - 1].content.clone())
+                     } else {
+                        None
+                     };
 
-      let mut embed_queue: Vec<(PathBuf, FileHash, u64, Vec<PreparedChunk>)> =
-         Vec::with_capacity(batch_size);
-      let mut since_save = 0;
-      let total_to_embed = chunked_files.len();
-      let mut embedded = 0;
+                     let context_next: Option<Str> = if idx < chunks.len() - 1 {
+                        Some(chunks[idx + 1].content.clone())
+                     } else {
+                        None
+                     };
 
-      for (path, hash, mtime, prepared_chunks) in chunked_files {
-         embed_queue.push((path, hash, mtime, prepared_chunks));
+                     let prepared = PreparedChunk {
+                        id: format!("{}:{}", path.display(), idx),
+                        path: Arc::clone(&path_arc),
+                        hash,
+                        content: chunk.content.clone(),
+                        start_line: chunk.start_line as u32,
+                        end_line: chunk.end_line as u32,
+                        chunk_index: Some(idx as u32 + 1),
+                        is_anchor: Some(false),
+                        chunk_type: if is_doc {
+                           Some(ChunkType::Doc)
+                        } else {
+                           chunk.chunk_type
+                        },
+                        context_prev,
+                        context_next,
+                        bridges: bridge::collect(&path, &chunk.content),
+                        language: language.clone(),
+                        embed_text: embed_text(&path, chunk),
+                     };
+                     prepared_chunks.push(prepared);
+                  }
 
-         if embed_queue.len() >= batch_size {
-            callback.progress(SyncProgress {
-               processed: embedded,
-               indexed,
-               total: total_to_embed,
-               current_file: Some(
-                  format!("Embedding batch ({} files)...", embed_queue.len()).into(),
-               ),
-            });
+                  Some((path, hash, stat, file::encoding::label(encoding), prepared_chunks))
+               }
+            })
+            .buffer_unordered(64)
+            .filter_map(|x| async move { x })
+            .chunks(batch_size.max(1));
+         while let Some(batch) = batches.next().await {
+            if chunked_tx.send(batch).await.is_err() {
+               break;
+            }
+         }
+      };
 
-            let batch = std::mem::take(&mut embed_queue);
-            let batch_count = batch.len();
-            let batch_indexed = self
-               .process_embed_batch(store_id, batch, &mut meta_store)
+      // A branch or worktree store starts out from its base store's
+      // embeddings, which a store sharing its rows reads already
+      let base_store = if meta_store.base().is_none() {
+         BaseStore::resolve(store_id, root)
+      } else {
+         None
+      };
+      let base_store = base_store.as_ref();
+      let changed_files = &changed_files;
+      let workspace = &workspace;
+      let summaries = &summaries;
+      let embedding = async move {
+         while let Some(batch) = chunked_rx.recv().await {
+            util::check_cancelled(Some(cancel))?;
+            let embedded = self
+               .embed_batch(
+                  store_id,
+                  base_store,
+                  batch,
+                  changed_files,
+                  workspace,
+                  summaries,
+                  cancel,
+               )
+               .await?;
+            if embedded_tx.send(embedded).await.is_err() {
+               break;
+            }
+         }
+         Result::<()>::Ok(())
+      };
+
+      let writing = async {
+         let mut indexed = 0;
+         let mut embedded = 0;
+         let mut failed = 0;
+         let mut last_error = None;
+         let mut since_save = 0;
+         callback.progress(SyncProgress {
+            processed:    0,
+            indexed:      0,
+            total:        total_to_embed,
+            current_file: (total_to_embed > 0).then(|| {
+               format!("Embedding batch ({} files)...", total_to_embed.min(batch_size)).into()
+            }),
+         });
+
+         while let Some(mut batch) = embedded_rx.recv().await {
+            let batch_count = batch.files.len() + batch.failed.len();
+            for (path, e) in batch.failed.drain(..) {
+               tracing::warn!("Failed to embed {}: {e}", path.display());
+               failed += 1;
+               last_error = Some(format!("{}: {e}", path.display()));
+            }
+            indexed += self
+               .write_batch(store_id, batch, &mut meta_store, index_dir)
                .await?;
-            indexed += batch_indexed;
             embedded += batch_count;
             since_save += batch_count;
 
@@ -296,33 +889,35 @@ where
                since_save = 0;
             }
 
+            let remaining = total_to_embed.saturating_sub(embedded);
             callback.progress(SyncProgress {
                processed: embedded,
                indexed,
                total: total_to_embed,
-               current_file: None,
+               current_file: (remaining > 0).then(|| {
+                  format!("Embedding batch ({} files)...", remaining.min(batch_size)).into()
+               }),
             });
          }
-      }
+         Result::<_>::Ok((indexed, embedded, failed, last_error))
+      };
 
-      if !dry_run && !embed_queue.is_empty() {
-         callback.progress(SyncProgress {
-            processed: embedded,
-            indexed,
-            total: total_to_embed,
-            current_file: Some(
-               format!("Embedding final batch ({} files)...", embed_queue.len()).into(),
-            ),
-         });
-
-         let batch = std::mem::take(&mut embed_queue);
-         let batch_count = batch.len();
-         let batch_indexed = self
-            .process_embed_batch(store_id, batch, &mut meta_store)
-            .await?;
-         indexed += batch_indexed;
-         embedded += batch_count;
+      let ((), embedding, writing) = tokio::join!(chunking, embedding, writing);
+      if let Err(e) = summaries.lock().save() {
+         tracing::warn!("failed to save chunk summaries: {e}");
       }
+      let (batch_indexed, embedded, failed, last_error) = match (writing, embedding) {
+         (Ok(written), Ok(())) => written,
+         (Err(e), _) | (Ok(_), Err(e)) => {
+            // Batches written before the failure are kept, so a rerun resumes
+            // after them.
+            if let Err(e) = meta_store.save() {
+               tracing::warn!("failed to save index progress: {e}");
+            }
+            return Err(e);
+         },
+      };
+      indexed += batch_indexed;
 
       if !dry_run {
          callback.progress(SyncProgress {
@@ -332,11 +927,37 @@ where
             current_file: Some("Creating indexes...".into()),
          });
 
+         meta_store.record_file_hash();
+         meta_store.set_head(git::head_commit(root));
          meta_store.save()?;
 
-         if indexed > 0 {
-            self.store.create_fts_index(store_id).await?;
-            self.store.create_vector_index(store_id).await?;
+         let mut summaries = 0;
+         if config::get().module_summaries && !touched_dirs.is_empty() {
+            callback.progress(SyncProgress {
+               processed: embedded,
+               indexed,
+               total: total_to_embed,
+               current_file: Some("Summarizing directories...".into()),
+            });
+            let indexed_paths: Vec<PathBuf> = meta_store.all_paths().cloned().collect();
+            summaries = refresh_module_chunks(
+               &self.embedder,
+               &self.store,
+               store_id,
+               &touched_dirs,
+               &indexed_paths,
+               workspace,
+            )
+            .await?;
+         }
+
+         if indexed > 0 || summaries > 0 {
+            async {
+               self.store.create_fts_index(store_id).await?;
+               self.store.create_vector_index(store_id).await
+            }
+            .instrument(tracing::info_span!("create_indexes"))
+            .await?;
          }
       }
 
@@ -347,36 +968,151 @@ where
          current_file: None,
       });
 
-      Ok(SyncResult { processed, indexed, skipped, deleted: deleted_count })
+      let result = SyncResult {
+         processed,
+         indexed,
+         skipped,
+         deleted: deleted_count,
+         binary,
+         minified,
+         too_large,
+         failed,
+         last_error,
+      };
+      drop(lock);
+      if !dry_run {
+         let payload = IndexHookPayload { store_id, root, result: Some(&result) };
+         hooks::run_logged(Hook::PostIndex, root, store_id, &payload).await;
+      }
+      Ok(result)
    }
 
-   async fn process_embed_batch(
+   /// Embeds the chunks of a batch of files, reusing the stored embeddings of
+   /// unchanged chunks in files being reindexed, and in `base_store`.
+   ///
+   /// A batch the embedder fails on is embedded again file by file, and the
+   /// files that still fail are left out and reported, so that one file does
+   /// not abort the sync. When every file fails the error is not about the
+   /// files, and is returned instead.
+   #[tracing::instrument(skip_all, fields(files = batch.len()))]
+   #[allow(clippy::too_many_arguments, reason = "state shared by every batch of a sync")]
+   async fn embed_batch(
       &self,
       store_id: &str,
-      batch: Vec<(PathBuf, FileHash, u64, Vec<PreparedChunk>)>,
-      meta_store: &mut MetaStore,
-   ) -> Result<usize> {
-      let file_count = batch.len();
-      let all_chunks: Vec<PreparedChunk> = batch
+      base_store: Option<&BaseStore>,
+      batch: FileBatch,
+      changed_files: &HashSet<PathBuf>,
+      workspace: &CargoWorkspace,
+      summaries: &Mutex<SummaryCache>,
+      cancel: &CancellationToken,
+   ) -> Result<EmbeddedBatch> {
+      let mut all_chunks: Vec<PreparedChunk> = batch
          .iter()
-         .flat_map(|(_, _, _, chunks)| chunks.iter().cloned())
+         .flat_map(|(.., chunks)| chunks.iter().cloned())
+         .collect();
+      let mut spans = Vec::with_capacity(batch.len());
+      for (.., chunks) in &batch {
+         let start = spans.last().map_or(0, |span: &Range<usize>| span.end);
+         spans.push(start..start + chunks.len());
+      }
+      let mut files: Vec<_> = batch
+         .into_iter()
+         .map(|(path, hash, stat, encoding, _)| (path, hash, stat, encoding))
          .collect();
 
       if all_chunks.is_empty() {
-         return Ok(0);
+         return Ok(EmbeddedBatch {
+            files,
+            replaced: Vec::new(),
+            records: Vec::new(),
+            failed: Vec::new(),
+         });
+      }
+
+      let mut replaced: Vec<PathBuf> = files
+         .iter()
+         .map(|(path, ..)| path)
+         .filter(|path| changed_files.contains(*path))
+         .cloned()
+         .collect();
+      let mut reuse = if replaced.is_empty() {
+         HashMap::new()
+      } else {
+         self.store.get_chunk_embeddings(store_id, &replaced).await?
+      };
+      if let Some(base) = base_store {
+         let paths: Vec<PathBuf> = files.iter().map(|(path, ..)| path.clone()).collect();
+         reuse.extend(base.embeddings(&self.store, &paths).await);
+      }
+
+      if let Some(summarizer) = &self.summarizer {
+         util::cancellable(cancel, async {
+            summarize::summarize_chunks(&**summarizer, summaries, &mut all_chunks).await;
+            Ok(())
+         })
+         .await?;
       }
 
-      let texts: Vec<Str> = all_chunks.iter().map(|c| c.content.clone()).collect();
+      let texts: Vec<Str> = all_chunks
+         .iter()
+         .map(|c| c.text_to_embed().clone())
+         .collect();
 
-      let embeddings = self.embedder.compute_hybrid(&texts).await?;
+      let mut embeddings: Vec<Option<HybridEmbedding>> = Vec::with_capacity(texts.len());
+      let mut failed = Vec::new();
+      match self.embed_texts(&texts, &reuse, cancel).await {
+         Ok(embedded) => embeddings.extend(embedded.into_iter().map(Some)),
+         Err(Error::Cancelled) => return Err(Error::Cancelled),
+         Err(e) if files.len() == 1 => {
+            failed.push((files[0].0.clone(), e.to_string()));
+            embeddings.resize(texts.len(), None);
+         },
+         Err(batch_err) => {
+            tracing::warn!(
+               "failed to embed a batch of {} files, retrying them one by one: {batch_err}",
+               files.len()
+            );
+            for ((path, ..), span) in files.iter().zip(&spans) {
+               match self.embed_texts(&texts[span.clone()], &reuse, cancel).await {
+                  Ok(embedded) => embeddings.extend(embedded.into_iter().map(Some)),
+                  Err(Error::Cancelled) => return Err(Error::Cancelled),
+                  Err(e) => {
+                     failed.push((path.clone(), e.to_string()));
+                     embeddings.extend(span.clone().map(|_| None));
+                  },
+               }
+            }
+            if failed.len() == files.len() {
+               return Err(batch_err);
+            }
+         },
+      }
+      if !failed.is_empty() {
+         let failed_paths: HashSet<&PathBuf> = failed.iter().map(|(path, _)| path).collect();
+         files.retain(|(path, ..)| !failed_paths.contains(path));
+         // Failed files keep their old rows until a later sync embeds them
+         replaced.retain(|path| !failed_paths.contains(path));
+      }
 
+      let indexed_at = util::unix_now();
       let records: Vec<VectorRecord> = all_chunks
          .into_iter()
-         .zip(embeddings.into_iter())
+         .zip(embeddings)
+         .filter_map(|(chunk, embedding)| Some((chunk, embedding?)))
          .map(|(chunk, embedding)| VectorRecord {
+            crate_name:    workspace.crate_for(&chunk.path),
             id:            chunk.id,
             path:          chunk.path,
             hash:          chunk.hash,
+            // Hashes what was embedded, so reuse skips chunks whose context
+            // changed
+            chunk_hash:    FileHash::sum(
+               chunk
+                  .embed_text
+                  .as_ref()
+                  .unwrap_or(&chunk.content)
+                  .as_bytes(),
+            ),
             content:       chunk.content,
             start_line:    chunk.start_line,
             end_line:      chunk.end_line,
@@ -388,13 +1124,59 @@ where
             vector:        embedding.dense,
             colbert:       embedding.colbert,
             colbert_scale: embedding.colbert_scale,
+            indexed_at:    Some(indexed_at),
+            bridges:       chunk.bridges,
+            language:      chunk.language,
          })
          .collect();
 
-      self.store.insert_batch(store_id, records).await?;
+      Ok(EmbeddedBatch { files, replaced, records, failed })
+   }
+
+   /// Embeds `texts`, reusing stored embeddings. Embedding is the slow step
+   /// and writes nothing, so it is abandoned on cancellation; store writes
+   /// always run to completion.
+   #[tracing::instrument(name = "embed", skip_all, fields(chunks = texts.len()))]
+   async fn embed_texts(
+      &self,
+      texts: &[Str],
+      reuse: &HashMap<FileHash, HybridEmbedding>,
+      cancel: &CancellationToken,
+   ) -> Result<Vec<HybridEmbedding>> {
+      let (embeddings, computed) =
+         util::cancellable(cancel, embed::compute_hybrid_reusing(&self.embedder, texts, reuse))
+            .await?;
+      if computed < texts.len() {
+         tracing::debug!("reused {} of {} chunk embeddings", texts.len() - computed, texts.len());
+      }
+      Ok(embeddings)
+   }
+
+   /// Checks the disk reserve, then stores an embedded batch and records its
+   /// files in the metadata. Returns the number of files written.
+   #[tracing::instrument(skip_all, fields(rows = batch.records.len()))]
+   async fn write_batch(
+      &self,
+      store_id: &str,
+      batch: EmbeddedBatch,
+      meta_store: &mut MetaStore,
+      index_dir: Option<&Path>,
+   ) -> Result<usize> {
+      if batch.records.is_empty() {
+         return Ok(0);
+      }
+      if let Some(dir) = index_dir {
+         disk::ensure_reserve(dir)?;
+      }
+
+      let file_count = batch.files.len();
+      self
+         .store
+         .replace_files(store_id, &batch.replaced, batch.records)
+         .await?;
 
-      for (path, hash, mtime, _) in batch {
-         meta_store.set_meta(path, hash, mtime);
+      for (path, hash, stat, encoding) in batch.files {
+         meta_store.set_meta(path, hash, stat, encoding);
       }
 
       Ok(file_count)
@@ -1,4 +1,4 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{fmt, path::PathBuf, sync::Arc, time::Instant};
 
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
@@ -16,6 +16,10 @@ pub enum ChunkType {
    TypeAlias,
    Block,
    Other,
+   /// Documentation pulled in from an external docs source.
+   Doc,
+   /// Synthetic summary of the files in a directory.
+   Module,
 }
 
 impl ChunkType {
@@ -28,6 +32,23 @@ impl ChunkType {
          Self::TypeAlias => "typealias",
          Self::Block => "block",
          Self::Other => "other",
+         Self::Doc => "doc",
+         Self::Module => "module",
+      }
+   }
+
+   /// Inverse of [`Self::as_lowercase_str`]; unknown names map to `Other`.
+   pub fn from_lowercase_str(s: &str) -> Self {
+      match s {
+         "function" => Self::Function,
+         "class" => Self::Class,
+         "interface" => Self::Interface,
+         "method" => Self::Method,
+         "typealias" => Self::TypeAlias,
+         "block" => Self::Block,
+         "doc" => Self::Doc,
+         "module" => Self::Module,
+         _ => Self::Other,
       }
    }
 }
@@ -82,6 +103,7 @@ pub struct PreparedChunk {
    #[serde(deserialize_with = "crate::serde_arc_pathbuf::deserialize")]
    pub path:         Arc<PathBuf>,
    pub hash:         FileHash,
+   /// Chunk as written in the file; this is what is stored and displayed.
    pub content:      Str,
    pub start_line:   u32,
    pub end_line:     u32,
@@ -90,14 +112,36 @@ pub struct PreparedChunk {
    pub chunk_type:   Option<ChunkType>,
    pub context_prev: Option<Str>,
    pub context_next: Option<Str>,
+   /// FFI bridge keys, as encoded by [`crate::chunker::bridge::collect`].
+   pub bridges:      Option<Str>,
+   /// Language of the file, from its extension, a modeline or a shebang; see
+   /// [`crate::grammar::GrammarManager::language_for`].
+   pub language:     Option<Str>,
+   /// Text embedded for the chunk, when `embed_template` makes it differ
+   /// from `content`.
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub embed_text:   Option<Str>,
+}
+
+impl PreparedChunk {
+   /// Text the chunk is embedded from.
+   pub fn text_to_embed(&self) -> &Str {
+      self.embed_text.as_ref().unwrap_or(&self.content)
+   }
 }
 
 /// Chunk with embedding vectors ready for storage in vector database
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VectorRecord {
    pub id:            String,
+   #[serde(serialize_with = "crate::serde_arc_pathbuf::serialize")]
+   #[serde(deserialize_with = "crate::serde_arc_pathbuf::deserialize")]
    pub path:          Arc<PathBuf>,
    pub hash:          FileHash,
+   /// SHA-256 of the text embedded for the chunk (`content` unless
+   /// `embed_template` adds to it), used to reuse embeddings of unchanged
+   /// chunks.
+   pub chunk_hash:    FileHash,
    pub content:       Str,
    pub start_line:    u32,
    pub end_line:      u32,
@@ -109,18 +153,82 @@ pub struct VectorRecord {
    pub vector:        Vec<f32>,
    pub colbert:       Vec<u8>,
    pub colbert_scale: f64,
+   /// Unix timestamp (seconds) at which the file was embedded.
+   pub indexed_at:    Option<u64>,
+   /// Cargo package owning the file, for Rust workspaces.
+   pub crate_name:    Option<Str>,
+   /// FFI bridge keys, as encoded by [`crate::chunker::bridge::collect`].
+   pub bridges:       Option<Str>,
+   /// Language of the file, as for [`PreparedChunk::language`].
+   pub language:      Option<Str>,
+}
+
+/// Per-signal scores of a result, kept for `--explain`
+///
+/// The final score is `fused` times each multiplier that was applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScoreBreakdown {
+   /// Similarity between the query and chunk embeddings.
+   pub dense:     Option<f32>,
+   /// Reciprocal rank among full-text matches; `None` when the chunk did not
+   /// match.
+   pub fts:       Option<f32>,
+   /// `ColBERT` `MaxSim`; `None` when the chunk was not reranked.
+   pub colbert:   Option<f32>,
+   /// Combined score before any boosts.
+   pub fused:     f32,
+   /// Multiplier from the file-level anchor similarity.
+   pub anchor:    Option<f32>,
+   /// Multiplier for definitions such as functions and classes.
+   pub structure: Option<f32>,
+   /// Multiplier penalizing test, documentation and config files.
+   pub file_type: Option<f32>,
+   /// Multiplier for mentioning the symbol a symbol query names.
+   pub symbol:    Option<f32>,
+   /// Cross-encoder relevance; `None` unless reranked with `--rerank cross`.
+   pub cross:     Option<f32>,
+}
+
+impl ScoreBreakdown {
+   /// Scores a full-text match found at `rank` (0-based).
+   pub fn fts_rank_score(rank: usize) -> f32 {
+      1.0 / (rank + 1) as f32
+   }
 }
 
 /// Individual search result with location and relevance score
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
+   pub path:         PathBuf,
+   pub content:      Str,
+   pub score:        f32,
+   pub start_line:   u32,
+   pub num_lines:    u32,
+   pub chunk_type:   Option<ChunkType>,
+   pub is_anchor:    Option<bool>,
+   /// How `score` was derived; `None` when the backend does not report it.
+   pub breakdown:    Option<ScoreBreakdown>,
+   /// FFI bridge keys recorded for the chunk.
+   pub bridges:      Option<Str>,
+   /// Language the chunk's file was indexed as, when known.
+   pub language:     Option<Str>,
+   /// Chunk before this one in its file.
+   pub context_prev: Option<Str>,
+   /// Chunk after this one in its file.
+   pub context_next: Option<Str>,
+   /// Code on the other side of an FFI boundary sharing a bridge key.
+   pub linked:       Vec<LinkedResult>,
+}
+
+/// Chunk linked to a search result through a shared FFI bridge key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkedResult {
+   /// Shared key, e.g. `c:deflate` or `jni:Inflater.inflate`.
+   pub symbol:     Str,
    pub path:       PathBuf,
    pub content:    Str,
-   pub score:      f32,
    pub start_line: u32,
    pub num_lines:  u32,
-   pub chunk_type: Option<ChunkType>,
-   pub is_anchor:  Option<bool>,
 }
 
 /// Current indexing status of the search system
@@ -131,20 +239,163 @@ pub enum SearchStatus {
    Indexing,
 }
 
+/// Progress of the index a daemon was still building when it answered a
+/// search, whose results are then partial
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexProgress {
+   pub percent:     u8,
+   pub files_done:  usize,
+   pub files_total: usize,
+   /// Estimated seconds until indexing finishes, from the rate so far;
+   /// `None` until a file has been indexed.
+   pub eta_secs:    Option<u64>,
+}
+
+impl fmt::Display for IndexProgress {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "index {}% complete", self.percent)?;
+      match self.eta_secs {
+         Some(secs) if secs >= 60 => write!(f, ", about {}m {}s left", secs / 60, secs % 60),
+         Some(secs) => write!(f, ", about {secs}s left"),
+         None => Ok(()),
+      }
+   }
+}
+
+/// Stage of a search, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchStage {
+   /// Connecting to the daemon and waiting for it to take the request, as
+   /// seen by the client.
+   Queued,
+   /// Embedding the query.
+   Embedding,
+   /// Fetching candidates from the store's vector and full-text indexes.
+   Retrieval,
+   /// Rescoring candidates, with `ColBERT` when enabled, and fusing scores.
+   Rerank,
+   /// Rescoring the top results with the cross-encoder.
+   Cross,
+}
+
+impl SearchStage {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Queued => "queued",
+         Self::Embedding => "embedding",
+         Self::Retrieval => "retrieval",
+         Self::Rerank => "rerank",
+         Self::Cross => "cross",
+      }
+   }
+}
+
+/// Time a search spent in one stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTiming {
+   pub stage: SearchStage,
+   pub ms:    u64,
+}
+
+impl StageTiming {
+   /// Timing of a stage that started at `start` and ends now.
+   pub fn since(stage: SearchStage, start: Instant) -> Self {
+      Self { stage, ms: start.elapsed().as_millis() as u64 }
+   }
+}
+
+/// How dense candidates were retrieved for a search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorSearchMode {
+   /// Approximate nearest-neighbor index.
+   #[default]
+   Approximate,
+   /// Brute-force scan over every vector.
+   Exact,
+   /// Scan over binary codes of every vector, with the nearest rescored by
+   /// their full vectors.
+   Binary,
+   /// No dense retrieval; only the full-text index was queried.
+   Keyword,
+}
+
+/// How a query was routed, based on whether it looks like a path or a symbol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryRoute {
+   /// Searched as given.
+   #[default]
+   Semantic,
+   /// Restricted to files whose path ends with the query.
+   Path,
+   /// Chunks mentioning the symbol's name were boosted.
+   Symbol,
+}
+
+impl QueryRoute {
+   pub const fn as_str(self) -> &'static str {
+      match self {
+         Self::Semantic => "semantic",
+         Self::Path => "path",
+         Self::Symbol => "symbol",
+      }
+   }
+}
+
 /// Response from a semantic search query
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResponse {
    pub results:  Vec<SearchResult>,
    pub status:   SearchStatus,
-   pub progress: Option<u8>,
+   /// Set while the daemon is still indexing.
+   pub progress: Option<IndexProgress>,
+   #[serde(default)]
+   pub mode:     VectorSearchMode,
+   #[serde(default)]
+   pub route:    QueryRoute,
+   /// Time spent in each stage that ran, in order.
+   #[serde(default)]
+   pub stages:   Vec<StageTiming>,
+}
+
+/// File present in a store, with the time it was last indexed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedFile {
+   pub path:       PathBuf,
+   /// Unix timestamp (seconds); `None` for rows written before timestamps
+   /// were recorded.
+   pub indexed_at: Option<u64>,
 }
 
 /// Metadata about a vector store instance
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoreInfo {
-   pub store_id:  String,
-   pub row_count: u64,
-   pub path:      PathBuf,
+   pub store_id:        String,
+   pub row_count:       u64,
+   pub path:            PathBuf,
+   pub dense_dim:       usize,
+   /// Whether dense vectors are stored as `i8` with a scale instead of `f32`.
+   pub dense_quantized: bool,
+}
+
+impl StoreInfo {
+   /// Bytes taken by the dense vectors, including the scale of each
+   /// quantized one.
+   pub const fn dense_bytes(&self) -> u64 {
+      let per_vector = if self.dense_quantized {
+         self.dense_dim + size_of::<f32>()
+      } else {
+         self.dense_dim * size_of::<f32>()
+      };
+      self.row_count * per_vector as u64
+   }
+
+   /// Bytes the dense vectors would take as `f32`.
+   pub const fn dense_f32_bytes(&self) -> u64 {
+      self.row_count * (self.dense_dim * size_of::<f32>()) as u64
+   }
 }
 
 /// Progress tracking for indexing operations
@@ -0,0 +1,189 @@
+//! Local usage statistics.
+//!
+//! When `usage_stats` is enabled, every CLI search is tallied into a JSON file
+//! under the smgrep base directory: queries per day, frequently queried terms,
+//! how often a warm daemon answered, and search latency. The file never leaves
+//! the machine; `smgrep stats --usage` is its only reader.
+
+use std::{
+   collections::{BTreeMap, HashMap},
+   fs,
+   time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, config};
+
+/// Days of per-day query counts kept before the oldest are dropped.
+const MAX_DAYS: usize = 90;
+
+/// Distinct query terms tracked before the least frequent are dropped.
+const MAX_TERMS: usize = 1000;
+
+/// Words too common in natural-language queries to say anything about usage.
+const STOP_WORDS: &[&str] = &[
+   "and", "are", "does", "for", "from", "how", "into", "the", "this", "that", "what", "when",
+   "where", "which", "with",
+];
+
+/// Aggregated search usage, persisted as `usage.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageStats {
+   /// Search count keyed by local date (`YYYY-MM-DD`).
+   pub queries_per_day:  BTreeMap<String, u64>,
+   /// Occurrences of each lowercased query term.
+   pub terms:            HashMap<String, u64>,
+   /// Searches answered by a running daemon with warm models and index.
+   pub cache_hits:       u64,
+   /// Searches that had to load models and open the store in-process.
+   pub cache_misses:     u64,
+   /// Sum of end-to-end search latencies.
+   pub total_latency_ms: u64,
+}
+
+impl UsageStats {
+   /// Loads the stats file, starting fresh if it is missing or unreadable.
+   pub fn load() -> Self {
+      fs::read(config::usage_stats_path())
+         .ok()
+         .and_then(|data| {
+            serde_json::from_slice(&data)
+               .inspect_err(|e| tracing::debug!("ignoring unreadable usage stats: {e}"))
+               .ok()
+         })
+         .unwrap_or_default()
+   }
+
+   /// Writes the stats file, replacing it atomically.
+   pub fn save(&self) -> Result<()> {
+      let path = config::usage_stats_path();
+      if let Some(parent) = path.parent() {
+         fs::create_dir_all(parent)?;
+      }
+      let tmp = path.with_extension("json.tmp");
+      fs::write(&tmp, serde_json::to_vec(self)?)?;
+      fs::rename(&tmp, path)?;
+      Ok(())
+   }
+
+   /// Removes the stats file.
+   pub fn reset() -> Result<()> {
+      match fs::remove_file(config::usage_stats_path()) {
+         Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+         _ => Ok(()),
+      }
+   }
+
+   /// Tallies one search made on `day`.
+   pub fn record(&mut self, day: String, query: &str, latency: Duration, cache_hit: bool) {
+      *self.queries_per_day.entry(day).or_default() += 1;
+      while self.queries_per_day.len() > MAX_DAYS {
+         self.queries_per_day.pop_first();
+      }
+
+      for term in query_terms(query) {
+         *self.terms.entry(term).or_default() += 1;
+      }
+      if self.terms.len() > MAX_TERMS {
+         let mut terms: Vec<_> = self.terms.drain().collect();
+         terms.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+         terms.truncate(MAX_TERMS);
+         self.terms = terms.into_iter().collect();
+      }
+
+      if cache_hit {
+         self.cache_hits += 1;
+      } else {
+         self.cache_misses += 1;
+      }
+      self.total_latency_ms = self
+         .total_latency_ms
+         .saturating_add(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX));
+   }
+
+   pub const fn searches(&self) -> u64 {
+      self.cache_hits + self.cache_misses
+   }
+
+   /// Fraction of searches answered by a warm daemon.
+   #[allow(clippy::cast_precision_loss, reason = "ratio of counters for display")]
+   pub fn cache_hit_rate(&self) -> Option<f64> {
+      let searches = self.searches();
+      (searches > 0).then(|| self.cache_hits as f64 / searches as f64)
+   }
+
+   pub fn average_latency(&self) -> Option<Duration> {
+      let searches = self.searches();
+      (searches > 0).then(|| Duration::from_millis(self.total_latency_ms / searches))
+   }
+
+   /// The `n` most frequent query terms, most frequent first.
+   pub fn top_terms(&self, n: usize) -> Vec<(&str, u64)> {
+      let mut terms: Vec<_> = self.terms.iter().map(|(t, &c)| (t.as_str(), c)).collect();
+      terms.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+      terms.truncate(n);
+      terms
+   }
+}
+
+/// Records a finished search if usage statistics are enabled.
+///
+/// Failures are logged and otherwise ignored so stats never break a search.
+pub fn record_search(query: &str, latency: Duration, cache_hit: bool) {
+   if !config::get().usage_stats {
+      return;
+   }
+   let day = chrono::Local::now().format("%Y-%m-%d").to_string();
+   let mut stats = UsageStats::load();
+   stats.record(day, query, latency, cache_hit);
+   if let Err(e) = stats.save() {
+      tracing::debug!("failed to save usage stats: {e}");
+   }
+}
+
+/// Splits a query into lowercased identifier-like terms, skipping short words
+/// and stop words.
+fn query_terms(query: &str) -> impl Iterator<Item = String> {
+   query
+      .split(|c: char| !c.is_alphanumeric() && c != '_')
+      .filter(|t| t.chars().count() > 2)
+      .map(str::to_lowercase)
+      .filter(|t| !STOP_WORDS.contains(&t.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn extracts_query_terms() {
+      let terms: Vec<_> = query_terms("Where is the retry_policy for HTTP::Client?").collect();
+      assert_eq!(terms, vec!["retry_policy", "http", "client"]);
+   }
+
+   #[test]
+   fn records_searches() {
+      let mut stats = UsageStats::default();
+      stats.record("2026-01-01".into(), "retry policy", Duration::from_millis(30), true);
+      stats.record("2026-01-01".into(), "retry backoff", Duration::from_millis(10), false);
+      stats.record("2026-01-02".into(), "backoff", Duration::from_millis(20), true);
+
+      assert_eq!(stats.searches(), 3);
+      assert_eq!(stats.queries_per_day["2026-01-01"], 2);
+      assert_eq!(stats.top_terms(2), vec![("backoff", 2), ("retry", 2)]);
+      assert_eq!(stats.average_latency(), Some(Duration::from_millis(20)));
+      assert!((stats.cache_hit_rate().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+   }
+
+   #[test]
+   fn bounds_history() {
+      let mut stats = UsageStats::default();
+      for day in 0..=MAX_DAYS {
+         stats.record(format!("2026-{day:04}"), &format!("term{day}"), Duration::ZERO, false);
+      }
+      assert_eq!(stats.queries_per_day.len(), MAX_DAYS);
+      assert!(!stats.queries_per_day.contains_key("2026-0000"));
+   }
+}
@@ -1,8 +1,14 @@
-//! Utility functions for filesystem operations
+//! Utility functions for filesystem operations, sizes and times
 
-use std::{fs, path::Path};
+use std::{
+   fs,
+   path::Path,
+   time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::Result;
+use tokio_util::sync::CancellationToken;
+
+use crate::{Error, Result};
 
 /// Recursively calculates the total size of a directory in bytes
 pub fn get_dir_size(path: &Path) -> Result<u64> {
@@ -40,3 +46,134 @@ pub fn format_size(bytes: u64) -> String {
       format!("{:.1} GB", bytes as f64 / GB as f64)
    }
 }
+
+/// Fails with [`Error::Cancelled`] once `cancel` has fired.
+pub fn check_cancelled(cancel: Option<&CancellationToken>) -> Result<()> {
+   if cancel.is_some_and(CancellationToken::is_cancelled) {
+      Err(Error::Cancelled)
+   } else {
+      Ok(())
+   }
+}
+
+/// Runs `fut` to completion unless `cancel` fires first, in which case `fut`
+/// is dropped at its next await point.
+pub async fn cancellable<T>(
+   cancel: &CancellationToken,
+   fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+   tokio::select! {
+      biased;
+      () = cancel.cancelled() => Err(Error::Cancelled),
+      result = fut => result,
+   }
+}
+
+/// Token cancelled when the process receives Ctrl-C. Once installed, Ctrl-C
+/// no longer terminates the process, so callers must check the token.
+pub fn cancel_on_ctrl_c() -> CancellationToken {
+   let cancel = CancellationToken::new();
+   let token = cancel.clone();
+   tokio::spawn(async move {
+      if tokio::signal::ctrl_c().await.is_ok() {
+         token.cancel();
+      }
+   });
+   cancel
+}
+
+/// Current time as a Unix timestamp in seconds
+pub fn unix_now() -> u64 {
+   SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |d| d.as_secs())
+}
+
+/// Resident memory of this process in bytes, where the platform reports it
+pub fn resident_memory() -> Option<u64> {
+   #[cfg(target_os = "linux")]
+   {
+      let status = fs::read_to_string("/proc/self/status").ok()?;
+      status.lines().find_map(|line| {
+         let kb = line.strip_prefix("VmRSS:")?.trim().strip_suffix("kB")?;
+         kb.trim().parse::<u64>().ok().map(|kb| kb * 1024)
+      })
+   }
+   #[cfg(not(target_os = "linux"))]
+   {
+      None
+   }
+}
+
+/// Memory available to new allocations in bytes, where the platform reports
+/// it
+pub fn available_memory() -> Option<u64> {
+   #[cfg(target_os = "linux")]
+   {
+      let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+      meminfo.lines().find_map(|line| {
+         let kb = line
+            .strip_prefix("MemAvailable:")?
+            .trim()
+            .strip_suffix("kB")?;
+         kb.trim().parse::<u64>().ok().map(|kb| kb * 1024)
+      })
+   }
+   #[cfg(not(target_os = "linux"))]
+   {
+      None
+   }
+}
+
+/// Parses a relative age such as `90s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_age(s: &str) -> std::result::Result<Duration, String> {
+   let s = s.trim();
+   let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+   let (num, unit) = s.split_at(split);
+   let num: u64 = num
+      .parse()
+      .map_err(|_| format!("invalid age `{s}`: expected a number followed by s, m, h, d or w"))?;
+
+   let secs = match unit {
+      "s" => 1,
+      "m" => 60,
+      "h" => 60 * 60,
+      "d" => 24 * 60 * 60,
+      "w" => 7 * 24 * 60 * 60,
+      _ => return Err(format!("invalid age unit in `{s}`: expected s, m, h, d or w")),
+   };
+   Ok(Duration::from_secs(num.saturating_mul(secs)))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parse_age_units() {
+      assert_eq!(parse_age("90s"), Ok(Duration::from_secs(90)));
+      assert_eq!(parse_age("7d"), Ok(Duration::from_secs(7 * 86400)));
+      assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 86400)));
+      assert!(parse_age("7").is_err());
+      assert!(parse_age("d").is_err());
+      assert!(parse_age("3y").is_err());
+   }
+
+   #[tokio::test]
+   async fn cancellable_stops_pending_work() {
+      let cancel = CancellationToken::new();
+      assert_eq!(cancellable(&cancel, async { Ok(1) }).await.unwrap(), 1);
+
+      cancel.cancel();
+      let pending = cancellable(&cancel, std::future::pending::<Result<()>>()).await;
+      assert!(matches!(pending, Err(Error::Cancelled)));
+      assert!(check_cancelled(Some(&cancel)).is_err());
+      assert!(check_cancelled(None).is_ok());
+   }
+
+   #[cfg(target_os = "linux")]
+   #[test]
+   fn reads_resident_memory() {
+      assert!(resident_memory().is_some_and(|bytes| bytes > 0));
+   }
+}
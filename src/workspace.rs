@@ -0,0 +1,250 @@
+//! Cargo workspace discovery.
+//!
+//! Maps files to the workspace member crate that owns them, so chunks can be
+//! tagged with their crate at index time and searches scoped with `--crate`
+//! instead of hand-written path filters.
+
+use std::{
+   fs,
+   path::{Path, PathBuf},
+};
+
+use crate::Str;
+
+const MANIFEST: &str = "Cargo.toml";
+
+/// Member crates of the Cargo workspace enclosing a directory.
+#[derive(Debug, Clone, Default)]
+pub struct CargoWorkspace {
+   /// Member directories and package names, deepest directories first so
+   /// nested crates win over their parents.
+   members: Vec<(PathBuf, Str)>,
+}
+
+impl CargoWorkspace {
+   /// Finds the workspace that `dir` belongs to.
+   ///
+   /// Walks up from `dir` to the nearest manifest with a `[workspace]` table,
+   /// falling back to the closest single-package manifest. Returns an empty
+   /// workspace outside of Cargo projects.
+   pub fn discover(dir: &Path) -> Self {
+      let mut package_root = None;
+
+      for ancestor in dir.ancestors() {
+         let Some(manifest) = read_manifest(ancestor) else {
+            continue;
+         };
+         if let Some(workspace) = manifest.get("workspace").and_then(|w| w.as_table()) {
+            return Self::from_workspace(ancestor, &manifest, workspace);
+         }
+         if package_root.is_none() && package_name(&manifest).is_some() {
+            package_root = Some((ancestor.to_path_buf(), manifest));
+         }
+      }
+
+      let mut this = Self::default();
+      if let Some((root, manifest)) = package_root
+         && let Some(name) = package_name(&manifest)
+      {
+         this.members.push((root, name));
+      }
+      this
+   }
+
+   fn from_workspace(root: &Path, manifest: &toml::Table, workspace: &toml::Table) -> Self {
+      let patterns = |key: &str| -> Vec<&str> {
+         workspace
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str()).collect())
+            .unwrap_or_default()
+      };
+      let excluded: Vec<PathBuf> = patterns("exclude")
+         .into_iter()
+         .flat_map(|p| expand_member_glob(root, p))
+         .collect();
+
+      let mut members = Vec::new();
+      if let Some(name) = package_name(manifest) {
+         members.push((root.to_path_buf(), name));
+      }
+      for dir in patterns("members")
+         .into_iter()
+         .flat_map(|p| expand_member_glob(root, p))
+      {
+         if excluded.contains(&dir) || members.iter().any(|(d, _)| *d == dir) {
+            continue;
+         }
+         match read_manifest(&dir).as_ref().and_then(package_name) {
+            Some(name) => members.push((dir, name)),
+            None => {
+               tracing::debug!("skipping workspace member without a package: {}", dir.display());
+            },
+         }
+      }
+
+      members.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+      Self { members }
+   }
+
+   /// Returns the name of the crate that owns `path`, if any.
+   pub fn crate_for(&self, path: &Path) -> Option<Str> {
+      self
+         .members
+         .iter()
+         .find(|(dir, _)| path.starts_with(dir))
+         .map(|(_, name)| name.clone())
+   }
+
+   /// Checks whether `name` is a member of this workspace.
+   pub fn contains(&self, name: &str) -> bool {
+      self.members.iter().any(|(_, n)| n.as_str() == name)
+   }
+
+   /// Member crate names, sorted.
+   pub fn names(&self) -> Vec<&str> {
+      let mut names: Vec<&str> = self.members.iter().map(|(_, n)| n.as_str()).collect();
+      names.sort_unstable();
+      names
+   }
+
+   pub const fn is_empty(&self) -> bool {
+      self.members.is_empty()
+   }
+}
+
+fn read_manifest(dir: &Path) -> Option<toml::Table> {
+   let path = dir.join(MANIFEST);
+   let text = fs::read_to_string(&path).ok()?;
+   text
+      .parse()
+      .inspect_err(|e| tracing::debug!("failed to parse {}: {e}", path.display()))
+      .ok()
+}
+
+fn package_name(manifest: &toml::Table) -> Option<Str> {
+   manifest
+      .get("package")?
+      .get("name")?
+      .as_str()
+      .map(Str::copy_from_str)
+}
+
+/// Expands a workspace `members` / `exclude` entry, which may use `*` and `?`
+/// wildcards in any path component.
+fn expand_member_glob(root: &Path, pattern: &str) -> Vec<PathBuf> {
+   let mut dirs = vec![root.to_path_buf()];
+   for component in pattern
+      .split(['/', '\\'])
+      .filter(|c| !c.is_empty() && *c != ".")
+   {
+      if !component.contains(['*', '?']) {
+         dirs = dirs.into_iter().map(|d| d.join(component)).collect();
+         continue;
+      }
+      dirs = dirs
+         .iter()
+         .filter_map(|d| fs::read_dir(d).ok())
+         .flatten()
+         .flatten()
+         .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+         .filter(|e| {
+            e.file_name()
+               .to_str()
+               .is_some_and(|n| wildcard_match(component, n))
+         })
+         .map(|e| e.path())
+         .collect();
+   }
+   dirs.retain(|d| d.is_dir());
+   dirs.sort();
+   dirs
+}
+
+/// Matches `name` against a pattern where `*` matches any run of characters
+/// and `?` matches exactly one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+   let pattern: Vec<char> = pattern.chars().collect();
+   let name: Vec<char> = name.chars().collect();
+   let (mut p, mut n) = (0, 0);
+   let mut backtrack = None;
+
+   while n < name.len() {
+      match pattern.get(p) {
+         Some('*') => {
+            backtrack = Some((p, n));
+            p += 1;
+         },
+         Some(&c) if c == '?' || c == name[n] => {
+            p += 1;
+            n += 1;
+         },
+         _ => match backtrack {
+            Some((bp, bn)) => {
+               p = bp + 1;
+               n = bn + 1;
+               backtrack = Some((bp, bn + 1));
+            },
+            None => return false,
+         },
+      }
+   }
+   pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+   use tempfile::TempDir;
+
+   use super::*;
+
+   fn write(root: &Path, rel: &str, contents: &str) {
+      let path = root.join(rel);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, contents).unwrap();
+   }
+
+   #[test]
+   fn wildcard_matching() {
+      assert!(wildcard_match("*", "core"));
+      assert!(wildcard_match("smgrep-*", "smgrep-core"));
+      assert!(wildcard_match("c?re", "core"));
+      assert!(!wildcard_match("smgrep-*", "other"));
+      assert!(!wildcard_match("c?re", "coore"));
+   }
+
+   #[test]
+   fn discovers_members_and_owning_crates() {
+      let dir = TempDir::new().unwrap();
+      let root = dir.path();
+      write(
+         root,
+         "Cargo.toml",
+         "[package]\nname = \"app\"\n\n[workspace]\nmembers = [\"crates/*\", \
+          \"tools/cli\"]\nexclude = [\"crates/scratch\"]\n",
+      );
+      write(root, "crates/core/Cargo.toml", "[package]\nname = \"app-core\"\n");
+      write(root, "crates/scratch/Cargo.toml", "[package]\nname = \"scratch\"\n");
+      write(root, "tools/cli/Cargo.toml", "[package]\nname = \"app-cli\"\n");
+      fs::create_dir_all(root.join("crates/core/src")).unwrap();
+
+      let ws = CargoWorkspace::discover(&root.join("crates/core/src"));
+      assert_eq!(ws.names(), vec!["app", "app-cli", "app-core"]);
+      assert_eq!(
+         ws.crate_for(&root.join("crates/core/src/lib.rs"))
+            .as_deref(),
+         Some("app-core")
+      );
+      assert_eq!(ws.crate_for(&root.join("tools/cli/main.rs")).as_deref(), Some("app-cli"));
+      assert_eq!(ws.crate_for(&root.join("src/main.rs")).as_deref(), Some("app"));
+      assert!(!ws.contains("scratch"));
+   }
+
+   #[test]
+   fn non_cargo_directory_is_empty() {
+      let dir = TempDir::new().unwrap();
+      let ws = CargoWorkspace::discover(dir.path());
+      assert!(ws.is_empty());
+      assert_eq!(ws.crate_for(&dir.path().join("a.rs")), None);
+   }
+}
@@ -2,7 +2,11 @@ use std::path::Path;
 
 use smgrep::{
    Str,
-   chunker::{Chunker, anchor::create_anchor_chunk},
+   chunker::{
+      Chunker, TreeSitterChunker,
+      anchor::{AnchorSummary, create_anchor_chunk},
+      module::create_module_chunk,
+   },
    types::ChunkType,
 };
 
@@ -28,9 +32,104 @@ function test() {
    assert!(chunk.content.as_str().contains("Exports:"));
 }
 
+#[test]
+fn test_anchor_summary_round_trip() {
+   let content = Str::from_static(
+      r"
+import { foo } from 'bar';
+import qux from 'quux';
+export const baz = 42;
+
+// Exports: not a summary line
+function test() {}
+",
+   );
+   let chunk = create_anchor_chunk(&content, Path::new("test.ts"));
+   let summary = AnchorSummary::parse(chunk.content.as_str());
+
+   assert_eq!(summary.imports, ["bar", "quux"]);
+   assert_eq!(summary.exports, ["baz"]);
+
+   let bare = create_anchor_chunk(&Str::from_static("// Exports: nothing\n"), Path::new("a.rs"));
+   assert_eq!(AnchorSummary::parse(bare.content.as_str()), AnchorSummary::default());
+}
+
+#[test]
+fn test_rust_anchor_lists_public_items_and_modules() {
+   let content = Str::from_static(
+      r"#![allow(dead_code)]
+//! Crate docs.
+
+use std::{fs, path::Path};
+use crate::store::Store;
+mod private;
+pub mod api;
+
+pub fn run() {}
+pub(crate) fn hidden() {}
+impl Runner {
+    pub fn method() {}
+}
+",
+   );
+   let chunk = create_anchor_chunk(&content, Path::new("lib.rs"));
+   let summary = AnchorSummary::parse(chunk.content.as_str());
+
+   assert_eq!(summary.imports, ["crate::store::Store", "std"]);
+   assert_eq!(summary.exports, ["run"]);
+   assert_eq!(summary.modules, ["api", "private"]);
+   assert!(
+      chunk
+         .content
+         .as_str()
+         .contains("Top comments:\n//! Crate docs.")
+   );
+}
+
+#[test]
+fn test_python_and_go_anchor_exports() {
+   let python = Str::from_static(
+      "\"\"\"Module doc.\"\"\"\nfrom os import path\nimport sys\n\ndef run():\n    pass\n\ndef \
+       _private():\n    pass\n",
+   );
+   let chunk = create_anchor_chunk(&python, Path::new("m.py"));
+   let summary = AnchorSummary::parse(chunk.content.as_str());
+   assert_eq!(summary.imports, ["os", "sys"]);
+   assert_eq!(summary.exports, ["run"]);
+   assert!(chunk.content.as_str().contains("Module doc."));
+
+   let go = Str::from_static(
+      "package foo\n\nimport (\n\t\"fmt\"\n)\n\nfunc New() {}\nfunc helper() {}\ntype Server \
+       struct{}\n",
+   );
+   let chunk = create_anchor_chunk(&go, Path::new("f.go"));
+   let summary = AnchorSummary::parse(chunk.content.as_str());
+   assert_eq!(summary.imports, ["fmt"]);
+   assert_eq!(summary.exports, ["New", "Server"]);
+}
+
+#[test]
+fn test_module_chunk_summarizes_directory() {
+   let files = [
+      (
+         Path::new("src/auth/session.rs"),
+         "//! Login sessions and their tokens.\n\npub fn login() {}\n",
+      ),
+      (Path::new("src/auth/mod.rs"), "pub mod session;\npub struct Auth;\n"),
+   ];
+   let chunk = create_module_chunk(Path::new("src/auth"), &files);
+   let text = chunk.content.as_str();
+
+   assert_eq!(chunk.chunk_type, Some(ChunkType::Module));
+   assert!(!chunk.is_anchor.unwrap_or(false));
+   assert!(text.starts_with("Directory: src/auth\n\nFiles: mod.rs, session.rs"));
+   assert!(text.contains("Exports: Auth, login, session"));
+   assert!(text.contains("session.rs: Login sessions and their tokens."));
+}
+
 #[tokio::test]
 async fn test_treesitter_chunker_typescript() {
-   let chunker = Chunker::default();
+   let chunker = TreeSitterChunker::default();
    let content = Str::from_static(
       r"
 export function greet(name: string): string {
@@ -0,0 +1,129 @@
+//! Indexing with an `embed_template`.
+//!
+//! Syncs a small repository with a template that prefixes each chunk with
+//! its path, and checks that only the embedder sees the rendered text: the
+//! search results show every chunk as written in the file. Runs in its own
+//! process so that the configuration, read once, can come from a temporary
+//! home directory. Needs the `mock-embedder` feature.
+#![cfg(feature = "mock-embedder")]
+
+use std::{
+   fs,
+   sync::{Arc, Mutex},
+};
+
+use smgrep::{
+   Str,
+   chunker::ChainChunker,
+   embed::{Embedder, EmbedderStatus, HybridEmbedding, QueryEmbedding, mock::MockEmbedder},
+   file::LocalFileSystem,
+   meta::SyncActor,
+   search::{Retrieval, SearchEngine, SearchFilter},
+   store::{FlatStore, Store},
+   sync::SyncEngine,
+};
+use tokio_util::sync::CancellationToken;
+
+const SOURCE: &str = "\
+pub fn parse_config(path: &Path) -> Config {
+   let text = fs::read_to_string(path).unwrap();
+   toml::from_str(&text).unwrap()
+}
+
+pub fn save_config(path: &Path, config: &Config) {
+   fs::write(path, toml::to_string(config).unwrap()).unwrap();
+}
+";
+
+/// [`MockEmbedder`] that keeps the texts it embeds.
+struct RecordingEmbedder {
+   inner: MockEmbedder,
+   texts: Mutex<Vec<Str>>,
+}
+
+#[async_trait::async_trait]
+impl Embedder for RecordingEmbedder {
+   async fn compute_hybrid(&self, texts: &[Str]) -> smgrep::Result<Vec<HybridEmbedding>> {
+      self.texts.lock().unwrap().extend_from_slice(texts);
+      self.inner.compute_hybrid(texts).await
+   }
+
+   async fn encode_query(&self, text: &str) -> smgrep::Result<QueryEmbedding> {
+      self.inner.encode_query(text).await
+   }
+
+   fn is_ready(&self) -> bool {
+      self.inner.is_ready()
+   }
+
+   fn unload(&self) -> bool {
+      self.inner.unload()
+   }
+
+   fn status(&self) -> EmbedderStatus {
+      self.inner.status()
+   }
+}
+
+#[tokio::test]
+async fn results_show_chunks_without_template() {
+   let home = tempfile::TempDir::new().unwrap();
+   let config_dir = home.path().join(".smgrep");
+   fs::create_dir_all(&config_dir).unwrap();
+   fs::write(
+      config_dir.join("config.toml"),
+      "embed_template = \"path: {path}\\n{content}\"\nmodule_summaries = false\n",
+   )
+   .unwrap();
+   // SAFETY: the only test of this process sets HOME before anything reads it
+   unsafe {
+      std::env::set_var("HOME", home.path());
+   }
+
+   let repo = tempfile::TempDir::new().unwrap();
+   let file = repo.path().join("config.rs");
+   fs::write(&file, SOURCE).unwrap();
+
+   let store: Arc<dyn Store> =
+      Arc::new(FlatStore::with_data_dir(home.path().join("data")).unwrap());
+   let embedder =
+      Arc::new(RecordingEmbedder { inner: MockEmbedder::default(), texts: Mutex::new(Vec::new()) });
+   let cancel = CancellationToken::new();
+   SyncEngine::new(
+      LocalFileSystem::new(),
+      ChainChunker::default(),
+      embedder.clone(),
+      store.clone(),
+   )
+   .initial_sync("template", repo.path(), false, SyncActor::Index, &mut (), &cancel)
+   .await
+   .unwrap();
+
+   let prefix = format!("path: {}\n", file.display());
+   let embedded = embedder.texts.lock().unwrap().clone();
+   assert!(embedded.iter().any(|text| text.starts_with(&prefix)), "{embedded:?}");
+
+   let engine = SearchEngine::new(store, embedder);
+   let response = engine
+      .search(
+         "template",
+         "parse config",
+         10,
+         10,
+         SearchFilter { path: Some(repo.path()), ..SearchFilter::default() },
+         Retrieval::default(),
+         &cancel,
+      )
+      .await
+      .unwrap();
+   let chunks: Vec<_> = response
+      .results
+      .iter()
+      .filter(|result| result.is_anchor != Some(true))
+      .collect();
+   assert!(!chunks.is_empty(), "{response:?}");
+   for result in chunks {
+      assert!(!result.content.starts_with("path: "), "{:?}", result.content);
+      assert!(SOURCE.contains(&*result.content), "{:?}", result.content);
+   }
+}
@@ -0,0 +1,23 @@
+use argon2::{
+   Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+   password_hash::{SaltString, rand_core::OsRng},
+};
+
+/// Hashes a password with a random salt before it is stored.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+   let salt = SaltString::generate(&mut OsRng);
+   Ok(Argon2::default()
+      .hash_password(password.as_bytes(), &salt)?
+      .to_string())
+}
+
+/// Checks a login attempt against the stored password hash.
+pub fn verify_password(password: &str, stored_hash: &str) -> bool {
+   PasswordHash::new(stored_hash)
+      .map(|hash| {
+         Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+      })
+      .unwrap_or(false)
+}
@@ -0,0 +1,31 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Cache that evicts the least recently used entry once it is full.
+pub struct LruCache<V> {
+   capacity: usize,
+   entries:  HashMap<String, V>,
+   order:    VecDeque<String>,
+}
+
+impl<V> LruCache<V> {
+   pub fn get(&mut self, key: &str) -> Option<&V> {
+      self.touch(key);
+      self.entries.get(key)
+   }
+
+   pub fn insert(&mut self, key: String, value: V) {
+      if self.entries.len() == self.capacity {
+         // Evict the least recently used entry
+         if let Some(oldest) = self.order.pop_front() {
+            self.entries.remove(&oldest);
+         }
+      }
+      self.touch(&key);
+      self.entries.insert(key, value);
+   }
+
+   fn touch(&mut self, key: &str) {
+      self.order.retain(|k| k != key);
+      self.order.push_back(key.to_string());
+   }
+}
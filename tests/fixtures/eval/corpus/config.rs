@@ -0,0 +1,22 @@
+use std::{env, fs, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct Settings {
+   pub port:         u16,
+   pub database_url: String,
+}
+
+/// Loads settings from a toml file, letting environment variables override it.
+pub fn load_settings(path: &Path) -> anyhow::Result<Settings> {
+   let text = fs::read_to_string(path)?;
+   let mut settings: Settings = toml::from_str(&text)?;
+   if let Ok(port) = env::var("APP_PORT") {
+      settings.port = port.parse()?;
+   }
+   if let Ok(url) = env::var("APP_DATABASE_URL") {
+      settings.database_url = url;
+   }
+   Ok(settings)
+}
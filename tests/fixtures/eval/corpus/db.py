@@ -0,0 +1,22 @@
+import psycopg2.pool
+
+_pool = None
+
+
+def connection_pool(dsn, min_connections=1, max_connections=10):
+    """Opens the shared pool of postgres database connections."""
+    global _pool
+    if _pool is None:
+        _pool = psycopg2.pool.SimpleConnectionPool(min_connections, max_connections, dsn)
+    return _pool
+
+
+def fetch_user(dsn, user_id):
+    """Fetches one user row from the database."""
+    conn = connection_pool(dsn).getconn()
+    try:
+        with conn.cursor() as cursor:
+            cursor.execute("SELECT id, name, email FROM users WHERE id = %s", (user_id,))
+            return cursor.fetchone()
+    finally:
+        connection_pool(dsn).putconn(conn)
@@ -0,0 +1,31 @@
+use std::{
+   fs::{self, File, OpenOptions},
+   io::{self, Write},
+   path::PathBuf,
+};
+
+/// Log file writer that rotates the file once it grows past a size limit.
+pub struct RotatingLog {
+   path:      PathBuf,
+   max_bytes: u64,
+   file:      File,
+}
+
+impl RotatingLog {
+   pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+      if self.file.metadata()?.len() >= self.max_bytes {
+         self.rotate()?;
+      }
+      writeln!(self.file, "{line}")
+   }
+
+   /// Renames the current log file to `.1` and starts a new one.
+   fn rotate(&mut self) -> io::Result<()> {
+      fs::rename(&self.path, self.path.with_extension("log.1"))?;
+      self.file = OpenOptions::new()
+         .create(true)
+         .append(true)
+         .open(&self.path)?;
+      Ok(())
+   }
+}
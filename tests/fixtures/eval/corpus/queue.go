@@ -0,0 +1,25 @@
+package jobs
+
+import "context"
+
+// Job is a unit of background work.
+type Job func(ctx context.Context) error
+
+// RunWorkers starts a pool of workers that dequeue jobs from the queue until
+// the context is cancelled.
+func RunWorkers(ctx context.Context, queue <-chan Job, workers int, failed chan<- error) {
+	for i := 0; i < workers; i++ {
+		go func() {
+			for {
+				select {
+				case <-ctx.Done():
+					return
+				case job := <-queue:
+					if err := job(ctx); err != nil {
+						failed <- err
+					}
+				}
+			}
+		}()
+	}
+}
@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+/// Sends an http request, retrying failures with exponential backoff.
+pub async fn send_with_retry(
+   client: &reqwest::Client,
+   url: &str,
+   max_attempts: u32,
+) -> reqwest::Result<reqwest::Response> {
+   let mut delay = Duration::from_millis(100);
+   let mut attempt = 1;
+   loop {
+      match client.get(url).send().await {
+         Ok(response) => return Ok(response),
+         Err(_) if attempt < max_attempts => {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+         },
+         Err(e) => return Err(e),
+      }
+   }
+}
@@ -0,0 +1,28 @@
+export type Token = { kind: "number" | "operator" | "paren"; text: string };
+
+// Splits an arithmetic expression into number, operator and paren tokens.
+export function tokenize(expression: string): Token[] {
+  const tokens: Token[] = [];
+  let i = 0;
+  while (i < expression.length) {
+    const c = expression[i];
+    if (/\s/.test(c)) {
+      i++;
+    } else if (/[0-9.]/.test(c)) {
+      let number = "";
+      while (i < expression.length && /[0-9.]/.test(expression[i])) {
+        number += expression[i++];
+      }
+      tokens.push({ kind: "number", text: number });
+    } else if ("+-*/".includes(c)) {
+      tokens.push({ kind: "operator", text: c });
+      i++;
+    } else if ("()".includes(c)) {
+      tokens.push({ kind: "paren", text: c });
+      i++;
+    } else {
+      throw new Error(`unexpected character ${c} in expression`);
+    }
+  }
+  return tokens;
+}
@@ -1,4 +1,4 @@
-use smgrep::format::format_chunk_text;
+use smgrep::format::{format_chunk_text, render_embed_template};
 
 #[test]
 fn test_format_chunk_text_with_context() {
@@ -65,3 +65,20 @@ fn test_format_chunk_text_separator() {
    assert!(result.contains("\n---\n"));
    assert!(result.ends_with("code"));
 }
+
+#[test]
+fn test_render_embed_template() {
+   let context = vec!["File: src/auth.rs".to_string(), "Method: login".to_string()];
+   let content = "fn login() { \"{path}\" }";
+
+   let result =
+      render_embed_template("{context}\n---\n{content}", &context, "src/auth.rs", content);
+   assert_eq!(result, format_chunk_text(&context, "src/auth.rs", content));
+
+   let result =
+      render_embed_template("{path}: {unknown} {content", &context, "src/auth.rs", content);
+   assert_eq!(result, "src/auth.rs: {unknown} {content");
+
+   let result = render_embed_template("{content}", &context, "src/auth.rs", content);
+   assert_eq!(result, content);
+}
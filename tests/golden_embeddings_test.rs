@@ -0,0 +1,125 @@
+//! Golden vectors for the configured embedding models.
+//!
+//! Embeds fixed texts with `CandleEmbedder` and compares the results with
+//! `tests/golden/embeddings.json`, so that changes to tokenization, pooling or
+//! precision that move embeddings are caught. The test is skipped when the
+//! models are not in the Hugging Face cache, so it never downloads them. When
+//! the file is missing, or `SMGREP_BLESS_GOLDEN=1` is set after an intended
+//! change, it is written from the current output instead.
+
+use std::{fs, path::PathBuf};
+
+use hf_hub::Cache;
+use serde::{Deserialize, Serialize};
+use smgrep::{
+   Str, config,
+   embed::{CandleEmbedder, Embedder},
+};
+
+/// Leading dense dimensions kept in the file.
+const DENSE_PREFIX: usize = 16;
+/// Largest difference allowed between a dense value and its golden value,
+/// leaving room for differences in floating-point accumulation order.
+const TOLERANCE: f32 = 1e-3;
+
+const TEXTS: [&str; 4] = [
+   "fn parse_config(path: &Path) -> Result<Config> {\n   toml::from_str(&read(path)?)\n}",
+   "def fibonacci(n):\n    return n if n < 2 else fibonacci(n - 1) + fibonacci(n - 2)",
+   "export const greet = (name: string): string => `Hello, ${name}`;",
+   "where is the configuration file parsed",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Golden {
+   dense_model:   String,
+   colbert_model: String,
+   vectors:       Vec<GoldenVector>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GoldenVector {
+   text:           String,
+   dense_len:      usize,
+   dense:          Vec<f32>,
+   colbert_tokens: usize,
+}
+
+fn golden_path() -> PathBuf {
+   PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/embeddings.json")
+}
+
+fn cached(model_id: &str) -> bool {
+   Cache::default()
+      .model(model_id.to_string())
+      .get("model.safetensors")
+      .is_some()
+}
+
+#[tokio::test]
+async fn embeddings_match_golden_vectors() {
+   let cfg = config::get();
+   if !cached(&cfg.dense_model) || !cached(&cfg.colbert_model) {
+      eprintln!("skipping: {} or {} is not cached", cfg.dense_model, cfg.colbert_model);
+      return;
+   }
+
+   let embedder = CandleEmbedder::new().unwrap();
+   let texts: Vec<Str> = TEXTS.iter().map(|&t| Str::from(t)).collect();
+   let embeddings = embedder.compute_hybrid(&texts).await.unwrap();
+   let actual = Golden {
+      dense_model:   cfg.dense_model.clone(),
+      colbert_model: cfg.colbert_model.clone(),
+      vectors:       TEXTS
+         .iter()
+         .zip(&embeddings)
+         .map(|(text, embedding)| GoldenVector {
+            text:           (*text).to_string(),
+            dense_len:      embedding.dense.len(),
+            dense:          embedding.dense.iter().take(DENSE_PREFIX).copied().collect(),
+            colbert_tokens: embedding.colbert.len() / cfg.colbert_dim,
+         })
+         .collect(),
+   };
+
+   let path = golden_path();
+   let bless = std::env::var_os("SMGREP_BLESS_GOLDEN").is_some();
+   let expected = match fs::read_to_string(&path) {
+      Ok(content) if !bless => serde_json::from_str::<Golden>(&content).unwrap(),
+      _ => {
+         fs::create_dir_all(path.parent().unwrap()).unwrap();
+         fs::write(&path, serde_json::to_string_pretty(&actual).unwrap()).unwrap();
+         eprintln!("wrote golden vectors to {}", path.display());
+         return;
+      },
+   };
+   if (&expected.dense_model, &expected.colbert_model)
+      != (&actual.dense_model, &actual.colbert_model)
+   {
+      eprintln!(
+         "skipping: golden vectors are for {} and {}",
+         expected.dense_model, expected.colbert_model
+      );
+      return;
+   }
+
+   assert_eq!(expected.vectors.len(), actual.vectors.len());
+   for (expected, actual) in expected.vectors.iter().zip(&actual.vectors) {
+      assert_eq!(
+         expected.text, actual.text,
+         "golden texts changed; rerun with SMGREP_BLESS_GOLDEN=1"
+      );
+      assert_eq!(expected.dense_len, actual.dense_len, "dense size of {:?}", actual.text);
+      assert_eq!(
+         expected.colbert_tokens, actual.colbert_tokens,
+         "ColBERT tokens of {:?}",
+         actual.text
+      );
+      for (i, (e, a)) in expected.dense.iter().zip(&actual.dense).enumerate() {
+         assert!(
+            (e - a).abs() <= TOLERANCE,
+            "dense[{i}] of {:?} moved from {e} to {a}",
+            actual.text
+         );
+      }
+   }
+}